@@ -0,0 +1,101 @@
+// Pre-populated stores shared by the benches. Each bench file includes this with `mod fixture;`,
+// so not every helper is used by every bench.
+#![allow(dead_code)]
+
+use snapchain::proto;
+use snapchain::storage::store::engine::{MempoolMessage, ShardEngine};
+use snapchain::storage::store::stores::StoreLimits;
+use snapchain::storage::store::test_helper::{self, limits, EngineOptions};
+use snapchain::utils::factory::messages_factory;
+use tempfile::TempDir;
+use tokio::runtime::Runtime;
+
+// Total number of messages in the store, spread evenly across the fids
+pub const STORE_SIZES: [usize; 3] = [1_000, 10_000, 50_000];
+pub const NUM_FIDS: u64 = 10;
+
+// Messages are committed in blocks of this size, close to what a validator proposes
+const MESSAGES_PER_CHUNK: usize = 250;
+const FIRST_FID: u64 = 1_000;
+
+pub struct StoreFixture {
+    pub engine: ShardEngine,
+    pub fids: Vec<u64>,
+    pub messages_per_fid: usize,
+    // Removed with the fixture
+    _dir: TempDir,
+}
+
+pub fn runtime() -> Runtime {
+    Runtime::new().unwrap()
+}
+
+// Casts for the fid, with distinct text so each one is kept. Timestamps only advance every few
+// casts, so long running benches don't create casts too far in the future to be accepted.
+pub fn casts(fid: u64, start: usize, count: usize) -> Vec<proto::Message> {
+    let first_timestamp = messages_factory::farcaster_time() - 10 * 24 * 60 * 60;
+    (start..start + count)
+        .map(|i| {
+            messages_factory::casts::create_cast_add(
+                fid,
+                &format!("cast {} from {}", i, fid),
+                Some(first_timestamp + (i / 10) as u32),
+                None,
+            )
+        })
+        .collect()
+}
+
+pub fn commit_messages(engine: &mut ShardEngine, messages: Vec<proto::Message>) {
+    let state_change = engine.propose_state_change(
+        engine.shard_id(),
+        messages
+            .into_iter()
+            .map(MempoolMessage::UserMessage)
+            .collect(),
+    );
+    assert!(!state_change.transactions.is_empty());
+    test_helper::validate_and_commit_state_change(engine, &state_change);
+}
+
+// A shard whose fids have num_messages casts between them. Storage limits are lifted so nothing is
+// pruned while it's populated.
+pub fn populated_store(runtime: &Runtime, num_messages: usize) -> StoreFixture {
+    let (mut engine, dir) = test_helper::new_engine_with_options(EngineOptions {
+        limits: Some(StoreLimits {
+            limits: limits::unlimited(),
+            legacy_limits: limits::zero(),
+            byte_limits: None,
+        }),
+        db: None,
+        messages_request_tx: None,
+    });
+    let fids: Vec<u64> = (FIRST_FID..FIRST_FID + NUM_FIDS).collect();
+    let messages_per_fid = num_messages / fids.len();
+
+    runtime.block_on(async {
+        for fid in &fids {
+            test_helper::register_user(
+                *fid,
+                test_helper::default_signer(),
+                test_helper::default_custody_address(),
+                &mut engine,
+            )
+            .await;
+        }
+    });
+
+    for fid in &fids {
+        for start in (0..messages_per_fid).step_by(MESSAGES_PER_CHUNK) {
+            let count = MESSAGES_PER_CHUNK.min(messages_per_fid - start);
+            commit_messages(&mut engine, casts(*fid, start, count));
+        }
+    }
+
+    StoreFixture {
+        engine,
+        fids,
+        messages_per_fid,
+        _dir: dir,
+    }
+}
@@ -0,0 +1,263 @@
+mod fixture;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use fixture::{StoreFixture, STORE_SIZES};
+use prost::Message;
+use snapchain::storage::db::{
+    Compression, CompressionType, PageOptions, RocksDB, RocksDbTransactionBatch, WriteDurability,
+};
+use std::time::{Duration, Instant};
+
+const INSERT_BATCH_SIZE: usize = 100;
+const READ_PAGE_SIZE: usize = 1_000;
+// Blocks are small so the cost of the writes themselves dominates
+const BLOCK_SIZE: usize = 10;
+const BLOCKS_PER_ITER: usize = 20;
+// Commits per flush, 1 being the per-block default
+const COMMIT_BATCH_SIZES: [u32; 3] = [1, 5, 20];
+// Roughly the keys a block of a few messages writes, with their indexes and trie nodes
+const KEYS_PER_WRITE: usize = 50;
+const VALUE_SIZE: usize = 256;
+// Casts written per iteration of the compression bench
+const CASTS_PER_WRITE: usize = 100;
+
+fn bench_message_insert(c: &mut Criterion) {
+    let runtime = fixture::runtime();
+    let mut group = c.benchmark_group("message_insert");
+    group.throughput(Throughput::Elements(INSERT_BATCH_SIZE as u64));
+    for size in STORE_SIZES {
+        let StoreFixture {
+            mut engine,
+            fids,
+            messages_per_fid,
+            ..
+        } = fixture::populated_store(&runtime, size);
+        // Continue each fid's casts where the fixture left off
+        let mut next_index = messages_per_fid;
+
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, _| {
+            b.iter_custom(|iters| {
+                let mut elapsed = Duration::ZERO;
+                for i in 0..iters {
+                    let fid = fids[i as usize % fids.len()];
+                    let messages = fixture::casts(fid, next_index, INSERT_BATCH_SIZE);
+                    next_index += INSERT_BATCH_SIZE;
+
+                    // Signing the messages isn't part of the insert
+                    let start = Instant::now();
+                    fixture::commit_messages(&mut engine, messages);
+                    elapsed += start.elapsed();
+                }
+                elapsed
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_read_by_fid(c: &mut Criterion) {
+    let runtime = fixture::runtime();
+    let mut group = c.benchmark_group("read_all_messages_by_fid");
+    for size in STORE_SIZES {
+        let fixture = fixture::populated_store(&runtime, size);
+        let stores = fixture.engine.get_stores();
+        let fid = fixture.fids[0];
+        group.throughput(Throughput::Elements(fixture.messages_per_fid as u64));
+
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, _| {
+            b.iter(|| {
+                // Every page of the fid's casts, the way a bulk rpc client reads them
+                let mut page_options = PageOptions {
+                    page_size: Some(READ_PAGE_SIZE),
+                    ..PageOptions::default()
+                };
+                let mut count = 0;
+                loop {
+                    let page = stores
+                        .cast_store
+                        .get_all_messages_by_fid(fid, None, None, &page_options)
+                        .unwrap();
+                    count += page.messages.len();
+                    match page.next_page_token {
+                        Some(page_token) => page_options.page_token = Some(page_token),
+                        None => break,
+                    }
+                }
+                assert_eq!(count, fixture.messages_per_fid);
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_pruning(c: &mut Criterion) {
+    let runtime = fixture::runtime();
+    let mut group = c.benchmark_group("prune_messages");
+    for size in STORE_SIZES {
+        let fixture = fixture::populated_store(&runtime, size);
+        let stores = fixture.engine.get_stores();
+        let fid = fixture.fids[0];
+        let current_count = fixture.messages_per_fid as u32;
+        // Prune half of the fid's casts, as if its storage had halved
+        let max_count = current_count / 2;
+        group.throughput(Throughput::Elements((current_count - max_count) as u64));
+
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, _| {
+            b.iter(|| {
+                // The transaction isn't committed, so every iteration prunes the same messages
+                let mut txn = RocksDbTransactionBatch::new();
+                let events = stores
+                    .cast_store
+                    .prune_messages(fid, current_count, max_count, &mut txn)
+                    .unwrap();
+                assert_eq!(events.len() as u32, current_count - max_count);
+                txn
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_commit_batching(c: &mut Criterion) {
+    let runtime = fixture::runtime();
+    let mut group = c.benchmark_group("commit_batching");
+    group.throughput(Throughput::Elements((BLOCK_SIZE * BLOCKS_PER_ITER) as u64));
+    for batch_size in COMMIT_BATCH_SIZES {
+        let StoreFixture {
+            engine,
+            fids,
+            messages_per_fid,
+            _dir,
+        } = fixture::populated_store(&runtime, STORE_SIZES[0]);
+        // Only the batch size triggers a flush
+        let mut engine = engine.with_commit_batching(batch_size, Duration::from_secs(3600));
+        let mut next_index = messages_per_fid;
+
+        group.bench_with_input(
+            BenchmarkId::from_parameter(batch_size),
+            &batch_size,
+            |b, _| {
+                b.iter_custom(|iters| {
+                    let mut elapsed = Duration::ZERO;
+                    for i in 0..iters {
+                        let fid = fids[i as usize % fids.len()];
+                        let blocks: Vec<_> = (0..BLOCKS_PER_ITER)
+                            .map(|block| {
+                                fixture::casts(fid, next_index + block * BLOCK_SIZE, BLOCK_SIZE)
+                            })
+                            .collect();
+                        next_index += BLOCK_SIZE * BLOCKS_PER_ITER;
+
+                        let start = Instant::now();
+                        for messages in blocks {
+                            fixture::commit_messages(&mut engine, messages);
+                        }
+                        // Writes aren't done until they're flushed
+                        engine.flush_commits();
+                        elapsed += start.elapsed();
+                    }
+                    elapsed
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+fn bench_write_durability(c: &mut Criterion) {
+    let mut group = c.benchmark_group("write_durability");
+    group.throughput(Throughput::Elements(1));
+    let dir = tempfile::tempdir().unwrap();
+    for durability in [
+        WriteDurability::Sync,
+        WriteDurability::Wal,
+        WriteDurability::UnsafeNoWal,
+    ] {
+        let path = dir.path().join(format!("{:?}", durability));
+        let db = RocksDB::new(path.to_str().unwrap()).with_write_durability(durability);
+        db.open().unwrap();
+        let mut next_key = 0u64;
+
+        // Each iteration is one committed write, like a block
+        group.bench_function(
+            BenchmarkId::from_parameter(format!("{:?}", durability)),
+            |b| {
+                b.iter(|| {
+                    let mut txn = db.txn();
+                    for _ in 0..KEYS_PER_WRITE {
+                        txn.put(next_key.to_be_bytes().to_vec(), vec![0; VALUE_SIZE]);
+                        next_key += 1;
+                    }
+                    db.commit(txn).unwrap();
+                })
+            },
+        );
+        db.close();
+    }
+    group.finish();
+}
+
+fn bench_compression(c: &mut Criterion) {
+    let mut group = c.benchmark_group("compression");
+    group.throughput(Throughput::Elements(CASTS_PER_WRITE as u64));
+    let dir = tempfile::tempdir().unwrap();
+    let zstd = Compression {
+        algorithm: CompressionType::Zstd,
+        ..Compression::default()
+    };
+    for (name, compression) in [
+        (
+            "none",
+            Compression {
+                algorithm: CompressionType::None,
+                ..Compression::default()
+            },
+        ),
+        ("lz4", Compression::default()),
+        ("zstd", zstd),
+        (
+            "zstd_dictionary",
+            Compression {
+                zstd_dictionary_bytes: 16 * 1024,
+                ..zstd
+            },
+        ),
+    ] {
+        let path = dir.path().join(name);
+        let db = RocksDB::new(path.to_str().unwrap()).with_compression(compression);
+        db.open().unwrap();
+        let mut next_index = 0;
+
+        // Each iteration writes casts and compacts them, which is where they're compressed
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| {
+                let mut txn = db.txn();
+                for cast in fixture::casts(1_000, next_index, CASTS_PER_WRITE) {
+                    txn.put(cast.hash.clone(), cast.encode_to_vec());
+                }
+                next_index += CASTS_PER_WRITE;
+                db.commit(txn).unwrap();
+                db.compact_range(&[], &[0xff; 32]).unwrap();
+            })
+        });
+        // Criterion only reports the time, the space is compared from the output
+        println!(
+            "compression/{}: {} bytes on disk per cast",
+            name,
+            db.approximate_size() / next_index.max(1) as u64
+        );
+        db.close();
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_message_insert,
+    bench_read_by_fid,
+    bench_pruning,
+    bench_commit_batching,
+    bench_write_durability,
+    bench_compression
+);
+criterion_main!(benches);
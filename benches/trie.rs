@@ -0,0 +1,77 @@
+mod fixture;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use fixture::STORE_SIZES;
+use snapchain::storage::db::RocksDbTransactionBatch;
+use snapchain::storage::trie::merkle_trie::{Context, MerkleTrie, TrieKey};
+use std::time::{Duration, Instant};
+
+const INSERT_BATCH_SIZE: usize = 100;
+
+fn bench_subtree_hash(c: &mut Criterion) {
+    let runtime = fixture::runtime();
+    let mut group = c.benchmark_group("trie_subtree_hash");
+    for size in STORE_SIZES {
+        let fixture = fixture::populated_store(&runtime, size);
+        let db = fixture.engine.db.clone();
+        let mut trie = MerkleTrie::new(16).unwrap();
+        trie.initialize(&db).unwrap();
+        group.throughput(Throughput::Elements(fixture.fids.len() as u64));
+
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, _| {
+            b.iter(|| {
+                // The account roots the engine computes for every transaction
+                let mut txn = RocksDbTransactionBatch::new();
+                for fid in &fixture.fids {
+                    let hash = trie.get_hash(&db, &mut txn, &TrieKey::for_fid(*fid));
+                    assert!(!hash.is_empty());
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_insert_and_rehash(c: &mut Criterion) {
+    let runtime = fixture::runtime();
+    let mut group = c.benchmark_group("trie_insert");
+    group.throughput(Throughput::Elements(INSERT_BATCH_SIZE as u64));
+    for size in STORE_SIZES {
+        let fixture = fixture::populated_store(&runtime, size);
+        let db = fixture.engine.db.clone();
+        let mut trie = MerkleTrie::new(16).unwrap();
+        trie.initialize(&db).unwrap();
+        let fid = fixture.fids[0];
+        let keys: Vec<Vec<u8>> = fixture::casts(fid, fixture.messages_per_fid, INSERT_BATCH_SIZE)
+            .iter()
+            .map(TrieKey::for_message)
+            .collect();
+
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, _| {
+            b.iter_custom(|iters| {
+                let mut elapsed = Duration::ZERO;
+                for _ in 0..iters {
+                    let mut txn = RocksDbTransactionBatch::new();
+                    let start = Instant::now();
+                    trie.insert(
+                        &Context::new(),
+                        &db,
+                        &mut txn,
+                        keys.iter().map(|key| key.as_slice()).collect(),
+                    )
+                    .unwrap();
+                    trie.root_hash().unwrap();
+                    elapsed += start.elapsed();
+
+                    // Nothing was committed, so this undoes the inserts for the next iteration
+                    trie.reload(&db).unwrap();
+                }
+                elapsed
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_subtree_hash, bench_insert_and_rehash);
+criterion_main!(benches);
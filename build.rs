@@ -1,5 +1,7 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut builder = tonic_build::configure();
+    let out_dir = std::path::PathBuf::from(std::env::var("OUT_DIR")?);
+    let mut builder =
+        tonic_build::configure().file_descriptor_set_path(out_dir.join("snapchain_descriptor.bin"));
 
     // Custom type attributes required for malachite
     builder = builder
@@ -13,6 +15,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         &[
             "src/proto/admin_rpc.proto",
             "src/proto/blocks.proto",
+            "src/proto/debug_rpc.proto",
             "src/proto/rpc.proto",
             "src/proto/message.proto",
             "src/proto/onchain_event.proto",
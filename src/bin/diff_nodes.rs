@@ -0,0 +1,43 @@
+use std::error::Error;
+
+use clap::Parser;
+use snapchain::proto::hub_service_client::HubServiceClient;
+use snapchain::utils::trie_diff::{describe_trie_key, diff_tries};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Find the messages two nodes disagree on, by comparing a shard's trie on both through their rpcs", long_about = None)]
+struct Args {
+    /// The rpc address of the first node, e.g. http://127.0.0.1:3383
+    #[arg(long)]
+    endpoint_a: String,
+
+    #[arg(long)]
+    endpoint_b: String,
+
+    #[arg(long)]
+    shard: u32,
+
+    /// Both nodes have to be at this height for the whole comparison, since the trie is only
+    /// served at the latest height
+    #[arg(long)]
+    height: u64,
+}
+
+fn print_keys(side: &str, keys: &[Vec<u8>]) {
+    println!("Only on {} ({}):", side, keys.len());
+    for key in keys {
+        println!("- {}", describe_trie_key(key));
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+    let mut client_a = HubServiceClient::connect(args.endpoint_a).await?;
+    let mut client_b = HubServiceClient::connect(args.endpoint_b).await?;
+
+    let diff = diff_tries(&mut client_a, &mut client_b, args.shard, args.height).await?;
+    print_keys("A", &diff.only_in_a);
+    print_keys("B", &diff.only_in_b);
+    Ok(())
+}
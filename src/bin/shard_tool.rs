@@ -0,0 +1,90 @@
+use std::error::Error;
+
+use clap::{Parser, Subcommand};
+use snapchain::proto::hub_service_client::HubServiceClient;
+use snapchain::proto::GetShardStatsRequest;
+use snapchain::utils::reshard_advice::recommend;
+use tonic::transport::Channel;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Inspect the load on a node's shards, to plan resharding", long_about = None)]
+struct Args {
+    /// The rpc address of the node, e.g. http://127.0.0.1:3383
+    #[arg(long)]
+    endpoint: String,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print the request and write rates of each shard
+    Stats,
+    /// Print a plan, as json, for splitting the load observed by the node across a number of
+    /// shards. Nothing is changed on the node.
+    ReshardAdvice {
+        #[arg(long)]
+        target_shards: u32,
+    },
+}
+
+async fn stats(client: &mut HubServiceClient<Channel>) -> Result<(), Box<dyn Error>> {
+    let response = client
+        .get_shard_stats(GetShardStatsRequest {
+            include_fid_activity: false,
+        })
+        .await?
+        .into_inner();
+    for stats in response.shard_stats {
+        println!(
+            "- shard {}: {:.2} requests/s, {:.2} writes/s, {} messages from {} fids since the node started",
+            stats.shard_id,
+            stats.requests_per_second,
+            stats.writes_per_second,
+            stats.messages_written,
+            stats.active_fids
+        );
+    }
+    Ok(())
+}
+
+async fn reshard_advice(
+    client: &mut HubServiceClient<Channel>,
+    target_shards: u32,
+) -> Result<(), Box<dyn Error>> {
+    if target_shards == 0 {
+        return Err("target_shards must be at least 1".into());
+    }
+    let response = client
+        .get_shard_stats(GetShardStatsRequest {
+            include_fid_activity: true,
+        })
+        .await?
+        .into_inner();
+    let plan = recommend(&response.shard_stats, target_shards);
+    eprintln!(
+        "Variance of the shard loads: {:.2} hashed, {:.2} planned, with {} fids overridden",
+        plan.hashed_variance,
+        plan.planned_variance,
+        plan.overrides().count()
+    );
+    println!("{}", serde_json::to_string_pretty(&plan)?);
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+    // The advice needs every fid that wrote, more than fits in the default message size
+    let mut client = HubServiceClient::connect(args.endpoint)
+        .await?
+        .max_decoding_message_size(usize::MAX);
+
+    match args.command {
+        Command::Stats => stats(&mut client).await,
+        Command::ReshardAdvice { target_shards } => {
+            reshard_advice(&mut client, target_shards).await
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use std::error::Error;
+
+use clap::{Parser, Subcommand};
+use snapchain::storage::db::checkpoint::restore_checkpoint;
+use snapchain::storage::db::snapshot::{
+    list_snapshots, restore_relayout, validate_snapshot, SnapshotMetadata,
+};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Inspect and validate the snapshots available for restoring a node, or restore a shard from a checkpoint", long_about = None)]
+struct Args {
+    /// Path to the node's config file, used for the network, storage and snapshot settings
+    #[arg(long)]
+    config_path: String,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print the snapshots available for download, with the shards each one includes
+    ListSnapshots,
+    /// Replace a shard's db with a local checkpoint made by the CreateCheckpoint admin rpc. The
+    /// node must be stopped.
+    RestoreCheckpoint {
+        #[arg(long)]
+        shard_id: u32,
+
+        /// The path the checkpoint was created at
+        #[arg(long)]
+        checkpoint_path: String,
+    },
+    /// Restore the latest snapshots of a network with a different number of shards, moving each
+    /// fid to the shard it routes to among the node's shards. The node must be stopped and its
+    /// shards empty.
+    RestoreRelayout {
+        /// The number of shards the snapshots were taken with
+        #[arg(long)]
+        source_shards: u32,
+    },
+    /// Download a snapshot into a temporary directory and check each of its shards, without
+    /// touching the node's dbs. Prints a JSON report and exits with an error if any shard fails.
+    ValidateSnapshot {
+        /// The location of one of the snapshot's shards, as printed by list-snapshots
+        #[arg(long)]
+        uri: String,
+
+        /// Where to create the temporary directory, which needs room for the largest shard.
+        /// Defaults to the system's.
+        #[arg(long)]
+        work_dir: Option<String>,
+    },
+}
+
+fn format_timestamp(timestamp_ms: i64) -> String {
+    match chrono::DateTime::from_timestamp_millis(timestamp_ms) {
+        Some(date) => date.to_rfc3339(),
+        None => timestamp_ms.to_string(),
+    }
+}
+
+fn format_optional(value: Option<u64>, unit: &str) -> String {
+    match value {
+        Some(value) => format!("{}{}", value, unit),
+        None => "unknown".to_string(),
+    }
+}
+
+fn print_shard(shard_id: u32, metadata: &SnapshotMetadata) {
+    println!(
+        "- shard {}: height {}, size {}, format v{}, {} chunks at {}",
+        shard_id,
+        format_optional(metadata.block_height, ""),
+        format_optional(metadata.size_bytes, " bytes"),
+        metadata.format_version,
+        metadata.chunks.len(),
+        metadata.key_base
+    );
+}
+
+async fn list(config: &snapchain::cfg::Config) -> Result<(), Box<dyn Error>> {
+    let network = config.fc_network;
+    // The block shard is 0
+    let shard_ids: Vec<u32> = (0..=config.consensus.num_shards).collect();
+    let manifest = list_snapshots(network, &config.snapshot, &shard_ids).await?;
+
+    println!("Network: {}", network.as_str_name());
+    println!("Source:  {}", config.snapshot.snapshot_download_url);
+    if manifest.snapshots.is_empty() {
+        println!("No snapshots found");
+        return Ok(());
+    }
+
+    for entry in manifest.snapshots.iter().rev() {
+        println!();
+        println!(
+            "Snapshot {} (shards {})",
+            format_timestamp(entry.timestamp),
+            entry
+                .shards
+                .keys()
+                .map(|shard_id| shard_id.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        for (shard_id, metadata) in &entry.shards {
+            print_shard(*shard_id, metadata);
+        }
+    }
+
+    Ok(())
+}
+
+fn restore(
+    config: &snapchain::cfg::Config,
+    shard_id: u32,
+    checkpoint_path: &str,
+) -> Result<(), Box<dyn Error>> {
+    let db_path = format!(
+        "{}/shard-{}",
+        config.storage.shard_base_dir(&config.rocksdb_dir, shard_id),
+        shard_id
+    );
+    let restored = restore_checkpoint(config.fc_network, shard_id, checkpoint_path, &db_path)?;
+
+    println!(
+        "Restored shard {} at {} to height {}",
+        shard_id, db_path, restored.block_height
+    );
+    if let Some(fallback_path) = restored.fallback_path {
+        println!("The previous db was moved to {}", fallback_path);
+    }
+    Ok(())
+}
+
+async fn relayout(
+    config: &snapchain::cfg::Config,
+    source_shards: u32,
+) -> Result<(), Box<dyn Error>> {
+    let summary = restore_relayout(
+        config.fc_network,
+        &config.snapshot,
+        &config.storage,
+        &config.rocksdb_dir,
+        source_shards,
+        &config.consensus.shard_ids,
+        config.trie_branching_factor,
+    )
+    .await?;
+
+    println!(
+        "Moved {} fids from {} shards to {}: {} onchain events, {} messages and {} fnames",
+        summary.fids,
+        source_shards,
+        config.consensus.shard_ids.len(),
+        summary.onchain_events,
+        summary.messages,
+        summary.fnames
+    );
+    Ok(())
+}
+
+async fn validate(
+    config: &snapchain::cfg::Config,
+    uri: &str,
+    work_dir: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let shard_ids: Vec<u32> = (0..=config.consensus.num_shards).collect();
+    let shards = validate_snapshot(
+        config.fc_network,
+        &config.snapshot,
+        &config.storage,
+        uri,
+        &shard_ids,
+        config.trie_branching_factor,
+        work_dir,
+    )
+    .await?;
+
+    let passed = shards.iter().all(|shard| shard.passed);
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&serde_json::json!({
+            "uri": uri,
+            "passed": passed,
+            "shards": shards,
+        }))?
+    );
+    if !passed {
+        return Err("snapshot failed validation".into());
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+    let config = snapchain::cfg::load_config(&args.config_path)?;
+
+    match args.command {
+        Command::ListSnapshots => list(&config).await,
+        Command::RestoreCheckpoint {
+            shard_id,
+            checkpoint_path,
+        } => restore(&config, shard_id, &checkpoint_path),
+        Command::RestoreRelayout { source_shards } => relayout(&config, source_shards).await,
+        Command::ValidateSnapshot { uri, work_dir } => {
+            validate(&config, &uri, work_dir.as_deref()).await
+        }
+    }
+}
@@ -0,0 +1,7 @@
+use snapchain::perf::sync_apply_perftest;
+use std::error::Error;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    sync_apply_perftest::run().await
+}
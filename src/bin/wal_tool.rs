@@ -0,0 +1,143 @@
+use std::error::Error;
+use std::path::Path;
+
+use clap::{Parser, Subcommand};
+use informalsystems_malachitebft_engine::wal::WalEntry;
+use informalsystems_malachitebft_wal as wal;
+use snapchain::consensus::malachite::snapchain_codec::SnapchainCodec;
+use snapchain::consensus::malachite::wal::{consensus_home_dir, log_entries, wal_file};
+use snapchain::storage::db::RocksDB;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Inspect or reset a shard's consensus WAL", long_about = None)]
+struct Args {
+    /// The node's rocksdb_dir
+    #[arg(long, default_value = ".rocks")]
+    rocksdb_dir: String,
+
+    /// Base dir of the shard db, if it's overridden in the node's storage config
+    #[arg(long)]
+    shard_db_dir: Option<String>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print the height and round of each WAL entry
+    WalInfo {
+        #[arg(long)]
+        shard: u32,
+    },
+    /// Move the WAL aside so consensus starts the height from scratch on the next startup
+    WalReset {
+        #[arg(long)]
+        shard: u32,
+
+        /// Confirm the reset. Without it, only prints what would be done.
+        #[arg(long)]
+        yes: bool,
+    },
+}
+
+fn wal_info(wal_path: &Path) -> Result<(), Box<dyn Error>> {
+    // Opening a missing log would create it
+    if !wal_path.exists() {
+        return Err(format!("No WAL found at {}", wal_path.display()).into());
+    }
+
+    let mut log = wal::Log::open(wal_path)?;
+    let len = log.len();
+
+    println!("WAL:     {}", wal_path.display());
+    println!("Height:  {}", log.sequence());
+    println!("Entries: {len}");
+    println!("Size:    {} bytes", log.size_bytes().unwrap_or(0));
+
+    for (idx, entry) in log_entries(&mut log, &SnapchainCodec)?.enumerate() {
+        match entry {
+            Ok(WalEntry::ConsensusMsg(msg)) => {
+                println!(
+                    "- #{idx}: height {} round {}: {msg:?}",
+                    msg.height(),
+                    msg.round()
+                );
+            }
+            Ok(entry) => {
+                println!("- #{idx}: {entry:?}");
+            }
+            Err(e) => {
+                println!("- #{idx}: Error decoding WAL entry: {e}");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// The node keeps the shard db open while it's running, so failing to take the rocksdb lock means
+// the WAL may be in use
+fn ensure_node_stopped(shard_db_path: &str) -> Result<(), Box<dyn Error>> {
+    if !Path::new(shard_db_path).exists() {
+        return Ok(());
+    }
+    let db = RocksDB::new(shard_db_path);
+    if let Err(err) = db.open() {
+        return Err(format!(
+            "Unable to lock shard db at {} ({}), is the node still running?",
+            shard_db_path, err
+        )
+        .into());
+    }
+    db.close();
+    Ok(())
+}
+
+fn wal_reset(wal_path: &Path, shard_db_path: &str, yes: bool) -> Result<(), Box<dyn Error>> {
+    if !wal_path.exists() {
+        println!("No WAL found at {}", wal_path.display());
+        return Ok(());
+    }
+
+    ensure_node_stopped(shard_db_path)?;
+
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs();
+    let backup_path = wal_path.with_extension(format!("wal.{}.bak", timestamp));
+    if !yes {
+        println!(
+            "Would move {} to {}. Re-run with --yes to reset the WAL.",
+            wal_path.display(),
+            backup_path.display()
+        );
+        return Ok(());
+    }
+
+    // Keep the old WAL around instead of deleting it, in case it's needed for debugging
+    std::fs::rename(wal_path, &backup_path)?;
+    println!(
+        "Moved {} to {}. A new WAL will be created when the node starts.",
+        wal_path.display(),
+        backup_path.display()
+    );
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+
+    match args.command {
+        Command::WalInfo { shard } => {
+            let wal_path = wal_file(&consensus_home_dir(&args.rocksdb_dir, shard));
+            wal_info(&wal_path)
+        }
+        Command::WalReset { shard, yes } => {
+            let wal_path = wal_file(&consensus_home_dir(&args.rocksdb_dir, shard));
+            let shard_db_dir = args.shard_db_dir.unwrap_or(args.rocksdb_dir.clone());
+            let shard_db_path = format!("{}/shard-{}", shard_db_dir, shard);
+            wal_reset(&wal_path, &shard_db_path, yes)
+        }
+    }
+}
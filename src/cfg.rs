@@ -1,8 +1,9 @@
 use crate::{
-    connectors, consensus, mempool,
+    connectors, consensus, core, mempool,
     network::{self, http_server},
+    node,
     proto::FarcasterNetwork,
-    storage,
+    storage, utils,
 };
 use clap::Parser;
 use figment::{
@@ -41,6 +42,9 @@ pub struct PruningConfig {
     pub block_retention: Option<Duration>,
     #[serde(with = "humantime_serde")]
     pub event_retention: Duration,
+    // Also prunes the oldest events beyond this many per shard, whatever their age
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub event_retention_max_events: Option<u64>,
 }
 
 impl Default for PruningConfig {
@@ -48,12 +52,15 @@ impl Default for PruningConfig {
         Self {
             block_retention: None,
             event_retention: Duration::from_secs(60 * 60 * 24 * 3), // 3 days
+            event_retention_max_events: None,
         }
     }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Config {
+    // "pretty" for human readable logs, or "json" for newline delimited JSON with span fields.
+    // "text" is accepted as an alias for "pretty".
     pub log_format: String,
     pub fnames: connectors::fname::Config,
     pub onchain_events: connectors::onchain_events::Config,
@@ -63,23 +70,44 @@ pub struct Config {
     pub snapshot: storage::db::snapshot::Config,
     pub rpc_auth: String,
     pub admin_rpc_auth: String,
+    pub admin_audit: network::admin_audit::Config,
     pub rpc_address: String,
     pub http_address: String,
     pub rocksdb_dir: String,
+    pub storage: storage::db::Config,
     pub clear_db: bool,
+    // Restore each shard without data from its latest snapshot, or sync it from scratch if there's
+    // none, then sync live from there
+    pub bootstrap: bool,
+    // Start without the shards whose db can't be opened instead of refusing to start. Requests
+    // for those shards are rejected as unavailable until they're resynced.
+    pub tolerate_shard_failures: bool,
     pub statsd: StatsdConfig,
+    pub latency_histograms: utils::latency_histograms::Config,
     pub trie_branching_factor: u32,
     pub l1_rpc_url: String,
     pub fc_network: FarcasterNetwork,
+    pub custom_network: core::custom_network::Config,
     pub read_node: bool,
+    // Serves reads from another node's dbs on the same machine instead of syncing its own
+    pub read_replica: node::read_replica::Config,
     pub pruning: PruningConfig,
     pub http_server: http_server::Config,
+    pub rpc_timeouts: network::rpc_timeout::Config,
+    // Streams served at once by the Subscribe and GetBlocks rpcs, together. Further calls are
+    // rejected as resource exhausted until one of them ends.
+    pub max_streaming_subscribers: usize,
+    pub grpc_reflection: network::reflection::Config,
+    pub debug_service: network::debug_server::Config,
+    pub rpc_compression: network::rpc_compression::Config,
+    pub submission_sequence: network::submission_sequence::Config,
+    pub shutdown: node::shutdown::Config,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Config {
-            log_format: "text".to_string(),
+            log_format: "pretty".to_string(),
             fnames: connectors::fname::Config::default(),
             onchain_events: connectors::onchain_events::Config::default(),
             consensus: consensus::consensus::Config::default(),
@@ -87,25 +115,39 @@ impl Default for Config {
             mempool: mempool::mempool::Config::default(),
             rpc_auth: "".to_string(),
             admin_rpc_auth: "".to_string(),
+            admin_audit: network::admin_audit::Config::default(),
             rpc_address: "0.0.0.0:3383".to_string(),
             http_address: "0.0.0.0:3381".to_string(),
             rocksdb_dir: ".rocks".to_string(),
+            storage: storage::db::Config::default(),
             clear_db: false,
+            bootstrap: false,
+            tolerate_shard_failures: false,
             statsd: StatsdConfig::default(),
+            latency_histograms: utils::latency_histograms::Config::default(),
             trie_branching_factor: 16,
             l1_rpc_url: "".to_string(),
             fc_network: FarcasterNetwork::Devnet,
+            custom_network: core::custom_network::Config::default(),
             snapshot: storage::db::snapshot::Config::default(),
             read_node: false,
+            read_replica: node::read_replica::Config::default(),
             pruning: PruningConfig::default(),
             http_server: http_server::Config::default(),
+            rpc_timeouts: network::rpc_timeout::Config::default(),
+            max_streaming_subscribers: network::server::DEFAULT_MAX_STREAMING_SUBSCRIBERS,
+            grpc_reflection: network::reflection::Config::default(),
+            debug_service: network::debug_server::Config::default(),
+            rpc_compression: network::rpc_compression::Config::default(),
+            submission_sequence: network::submission_sequence::Config::default(),
+            shutdown: node::shutdown::Config::default(),
         }
     }
 }
 
 #[derive(Parser)]
 pub struct CliArgs {
-    #[arg(long, help = "Log format (text or json)")]
+    #[arg(long, help = "Log format (pretty or json)")]
     log_format: Option<String>,
 
     #[arg(long, help = "Path to the config file")]
@@ -113,31 +155,56 @@ pub struct CliArgs {
 
     #[arg(long, action, help = "Start the node with a clean database")]
     clear_db: bool,
+
+    #[arg(
+        long,
+        action,
+        help = "Restore shards without data from the latest snapshots, then sync live"
+    )]
+    bootstrap: bool,
+
+    #[arg(
+        long,
+        action,
+        help = "Run the startup self-check over every height and the whole trie of each shard"
+    )]
+    deep: bool,
     // All new arguments that are to override values from config files or environment variables
     // should be probably be optional (`Option<T>`) and without a default. Setting a default
     // in this case will have the effect of automatically overriding all previous configuration
     // layers. Remember to add the override code below and a test case.
 }
 
-pub fn load_and_merge_config(args: Vec<String>) -> Result<Config, Box<dyn Error>> {
-    let cli_args = CliArgs::try_parse_from(args)?;
-
+/// Loads the config file merged with the environment, without any command line overrides.
+pub fn load_config(config_path: &str) -> Result<Config, Box<dyn Error>> {
     let mut figment = Figment::from(Serialized::defaults(Config::default()));
 
-    if Path::new(&cli_args.config_path).exists() {
-        figment = figment.merge(Toml::file(&cli_args.config_path));
+    if Path::new(config_path).exists() {
+        figment = figment.merge(Toml::file(config_path));
     } else {
-        return Err(format!("config file not found: {}", &cli_args.config_path).into());
+        return Err(format!("config file not found: {}", config_path).into());
     }
 
     figment = figment.merge(Env::prefixed("SNAPCHAIN_").split("__"));
 
-    let mut config: Config = figment.extract()?;
+    Ok(figment.extract()?)
+}
+
+pub fn load_and_merge_config(args: Vec<String>) -> Result<Config, Box<dyn Error>> {
+    let cli_args = CliArgs::try_parse_from(args)?;
+
+    let mut config = load_config(&cli_args.config_path)?;
 
     if let Some(log_format) = cli_args.log_format {
         config.log_format = log_format;
     }
     config.clear_db = cli_args.clear_db;
+    config.bootstrap = cli_args.bootstrap;
+    // Only turns the deep check on, leaving the config's setting otherwise
+    if cli_args.deep {
+        config.storage.self_check.enabled = true;
+        config.storage.self_check.deep = true;
+    }
 
     Ok(config)
 }
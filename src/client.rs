@@ -0,0 +1,428 @@
+use crate::proto;
+use crate::proto::admin_service_client::AdminServiceClient;
+use crate::proto::hub_service_client::HubServiceClient;
+use base64::Engine;
+use std::future::Future;
+use std::time::Duration;
+use thiserror::Error;
+use tonic::codegen::InterceptedService;
+use tonic::metadata::AsciiMetadataValue;
+use tonic::service::Interceptor;
+use tonic::transport::{Channel, Endpoint};
+use tonic::{Code, Request, Response, Status, Streaming};
+
+pub use tonic::transport::{Certificate, ClientTlsConfig};
+
+pub type HubClient = HubServiceClient<InterceptedService<Channel, BasicAuth>>;
+pub type AdminClient = AdminServiceClient<InterceptedService<Channel, BasicAuth>>;
+
+#[derive(Error, Debug)]
+pub enum ClientError {
+    #[error("invalid endpoint: {0}")]
+    InvalidEndpoint(String),
+
+    #[error("invalid credentials: {0}")]
+    InvalidCredentials(String),
+
+    #[error(transparent)]
+    TransportError(#[from] tonic::transport::Error),
+}
+
+#[derive(Clone, Debug)]
+pub struct RetryConfig {
+    // Attempts after the first one, 0 disables retries
+    pub max_retries: u32,
+    // Doubled after every retry, up to max_backoff
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+impl RetryConfig {
+    pub fn none() -> Self {
+        RetryConfig {
+            max_retries: 0,
+            ..Self::default()
+        }
+    }
+
+    fn backoff(&self, retry: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff)
+    }
+}
+
+// Only failures that mean the node couldn't handle the request right now are retried, e.g. it's
+// unreachable or its mempool is full. Submitting a message again is safe, duplicates are rejected.
+fn is_retryable(status: &Status) -> bool {
+    status.code() == Code::Unavailable
+}
+
+/// Adds the basic auth header the node's rpc servers check, if credentials were configured.
+#[derive(Clone, Default)]
+pub struct BasicAuth {
+    header: Option<AsciiMetadataValue>,
+}
+
+impl BasicAuth {
+    pub fn new(username: &str, password: &str) -> Result<Self, ClientError> {
+        let encoded =
+            base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", username, password));
+        let header = format!("Basic {}", encoded)
+            .parse()
+            .map_err(|_| ClientError::InvalidCredentials(username.to_string()))?;
+        Ok(BasicAuth {
+            header: Some(header),
+        })
+    }
+}
+
+impl Interceptor for BasicAuth {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(header) = &self.header {
+            request
+                .metadata_mut()
+                .insert("authorization", header.clone());
+        }
+        Ok(request)
+    }
+}
+
+pub struct SnapchainClientBuilder {
+    endpoint: String,
+    tls: Option<ClientTlsConfig>,
+    auth: Option<(String, String)>,
+    admin_auth: Option<(String, String)>,
+    retry: RetryConfig,
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+}
+
+impl SnapchainClientBuilder {
+    pub fn new(endpoint: impl Into<String>) -> Self {
+        SnapchainClientBuilder {
+            endpoint: endpoint.into(),
+            tls: None,
+            auth: None,
+            admin_auth: None,
+            retry: RetryConfig::default(),
+            connect_timeout: None,
+            timeout: None,
+        }
+    }
+
+    pub fn tls(mut self, tls: ClientTlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Credentials for the public rpcs, matching the node's `rpc_auth`
+    pub fn auth(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.auth = Some((username.into(), password.into()));
+        self
+    }
+
+    /// Credentials for the admin rpcs, matching the node's `admin_rpc_auth`
+    pub fn admin_auth(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.admin_auth = Some((username.into(), password.into()));
+        self
+    }
+
+    pub fn retry(mut self, retry: RetryConfig) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Deadline of each attempt, a retried request gets a new one
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    fn make_endpoint(&self) -> Result<Endpoint, ClientError> {
+        let mut endpoint = Endpoint::from_shared(self.endpoint.clone())
+            .map_err(|_| ClientError::InvalidEndpoint(self.endpoint.clone()))?;
+        if let Some(tls) = &self.tls {
+            endpoint = endpoint.tls_config(tls.clone())?;
+        }
+        if let Some(timeout) = self.connect_timeout {
+            endpoint = endpoint.connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.timeout {
+            endpoint = endpoint.timeout(timeout);
+        }
+        Ok(endpoint)
+    }
+
+    fn build(self, channel: Channel) -> Result<SnapchainClient, ClientError> {
+        let auth = match &self.auth {
+            Some((username, password)) => BasicAuth::new(username, password)?,
+            None => BasicAuth::default(),
+        };
+        let admin_auth = match &self.admin_auth {
+            Some((username, password)) => BasicAuth::new(username, password)?,
+            None => BasicAuth::default(),
+        };
+        Ok(SnapchainClient {
+            hub: HubServiceClient::with_interceptor(channel.clone(), auth),
+            admin: AdminServiceClient::with_interceptor(channel, admin_auth),
+            retry: self.retry,
+        })
+    }
+
+    /// Connects to the node, failing if it can't be reached
+    pub async fn connect(self) -> Result<SnapchainClient, ClientError> {
+        let channel = self.make_endpoint()?.connect().await?;
+        self.build(channel)
+    }
+
+    /// Connects on the first request instead, and reconnects whenever the connection drops
+    pub fn connect_lazy(self) -> Result<SnapchainClient, ClientError> {
+        let channel = self.make_endpoint()?.connect_lazy();
+        self.build(channel)
+    }
+}
+
+// A method per rpc, taking the request message and returning the response message. Streaming rpcs
+// are only retried until the stream is opened.
+macro_rules! rpcs {
+    ($client:ident, $($method:ident($request:ty) -> $response:ty;)*) => {
+        $(
+            pub async fn $method(&self, request: $request) -> Result<$response, Status> {
+                self.call(request, |request| {
+                    let mut client = self.$client.clone();
+                    async move { client.$method(request).await }
+                })
+                .await
+            }
+        )*
+    };
+}
+
+/// Client for the hub and admin rpcs of a node, sharing a single connection. Cheap to clone.
+#[derive(Clone)]
+pub struct SnapchainClient {
+    hub: HubClient,
+    admin: AdminClient,
+    retry: RetryConfig,
+}
+
+impl SnapchainClient {
+    pub fn builder(endpoint: impl Into<String>) -> SnapchainClientBuilder {
+        SnapchainClientBuilder::new(endpoint)
+    }
+
+    /// The generated clients, for what the typed methods don't cover, e.g. request metadata
+    pub fn hub_client(&self) -> HubClient {
+        self.hub.clone()
+    }
+
+    pub fn admin_client(&self) -> AdminClient {
+        self.admin.clone()
+    }
+
+    async fn call<Req, Res, F, Fut>(&self, request: Req, mut send: F) -> Result<Res, Status>
+    where
+        Req: Clone,
+        F: FnMut(Req) -> Fut,
+        Fut: Future<Output = Result<Response<Res>, Status>>,
+    {
+        let mut retry = 0;
+        loop {
+            match send(request.clone()).await {
+                Ok(response) => return Ok(response.into_inner()),
+                Err(status) if retry < self.retry.max_retries && is_retryable(&status) => {
+                    tokio::time::sleep(self.retry.backoff(retry)).await;
+                    retry += 1;
+                }
+                Err(status) => return Err(status),
+            }
+        }
+    }
+
+    rpcs! { hub,
+        submit_message(proto::Message) -> proto::Message;
+        validate_message(proto::Message) -> proto::ValidationResponse;
+        get_blocks(proto::BlocksRequest) -> Streaming<proto::Block>;
+        get_block_by_hash(proto::BlockByHashRequest) -> proto::Block;
+        get_shard_chunks(proto::ShardChunksRequest) -> proto::ShardChunksResponse;
+        get_info(proto::GetInfoRequest) -> proto::GetInfoResponse;
+        get_sync_status(proto::GetSyncStatusRequest) -> proto::GetSyncStatusResponse;
+        get_shard_stats(proto::GetShardStatsRequest) -> proto::GetShardStatsResponse;
+        get_network_stats(proto::GetNetworkStatsRequest) -> proto::GetNetworkStatsResponse;
+        get_fids(proto::FidsRequest) -> proto::FidsResponse;
+        get_fid_timeline(proto::GetFidTimelineRequest) -> proto::GetFidTimelineResponse;
+        subscribe(proto::SubscribeRequest) -> Streaming<proto::HubEvent>;
+        get_event(proto::EventRequest) -> proto::HubEvent;
+        get_events(proto::EventsRequest) -> proto::EventsResponse;
+        get_message_by_hash(proto::MessageByHashRequest) -> proto::Message;
+        has_messages(proto::HasMessagesRequest) -> proto::HasMessagesResponse;
+        get_message_with_proof(proto::MessageByHashRequest) -> proto::MessageWithProof;
+        get_multi_proof(proto::GetMultiProofRequest) -> proto::MultiProof;
+        get_cast(proto::CastId) -> proto::Message;
+        get_casts_by_fid(proto::FidRequest) -> proto::MessagesResponse;
+        get_casts_by_parent(proto::CastsByParentRequest) -> proto::MessagesResponse;
+        get_casts_by_mention(proto::FidRequest) -> proto::MessagesResponse;
+        get_recent_casts(proto::RecentCastsRequest) -> proto::MessagesResponse;
+        get_reaction(proto::ReactionRequest) -> proto::Message;
+        get_reactions_by_fid(proto::ReactionsByFidRequest) -> proto::MessagesResponse;
+        get_reactions_by_cast(proto::ReactionsByTargetRequest) -> proto::MessagesResponse;
+        get_reactions_by_target(proto::ReactionsByTargetRequest) -> proto::MessagesResponse;
+        get_user_data(proto::UserDataRequest) -> proto::Message;
+        get_user_data_by_fid(proto::FidRequest) -> proto::MessagesResponse;
+        get_username_proof(proto::UsernameProofRequest) -> proto::UserNameProof;
+        get_user_name_proofs_by_fid(proto::FidRequest) -> proto::UsernameProofsResponse;
+        get_user_name_proofs_by_name(proto::UsernameProofRequest) -> proto::UsernameProofsResponse;
+        get_verification(proto::VerificationRequest) -> proto::Message;
+        get_verifications_by_fid(proto::VerificationsByFidRequest) -> proto::MessagesResponse;
+        get_on_chain_signer(proto::SignerRequest) -> proto::OnChainEvent;
+        get_on_chain_signers_by_fid(proto::FidRequest) -> proto::OnChainEventResponse;
+        get_signers(proto::SignersRequest) -> proto::SignersResponse;
+        get_on_chain_events(proto::OnChainEventRequest) -> proto::OnChainEventResponse;
+        get_on_chain_events_by_fid(proto::OnChainEventsByFidRequest) -> proto::OnChainEventResponse;
+        get_on_chain_events_by_block_range(proto::OnChainEventsByBlockRangeRequest) -> proto::OnChainEventResponse;
+        get_id_registry_on_chain_event(proto::FidRequest) -> proto::OnChainEvent;
+        get_id_registry_on_chain_event_by_address(proto::IdRegistryEventByAddressRequest) -> proto::OnChainEvent;
+        get_current_storage_limits_by_fid(proto::FidRequest) -> proto::StorageLimitsResponse;
+        get_storage_bytes_by_fid(proto::FidRequest) -> proto::StorageBytesResponse;
+        get_storage_usage(proto::GetStorageUsageRequest) -> proto::GetStorageUsageResponse;
+        get_fid_activity_counts(proto::GetFidActivityCountsRequest) -> proto::GetFidActivityCountsResponse;
+        get_link(proto::LinkRequest) -> proto::Message;
+        get_links_by_fid(proto::LinksByFidRequest) -> proto::MessagesResponse;
+        get_links_by_target(proto::LinksByTargetRequest) -> proto::MessagesResponse;
+        get_link_compact_state_message_by_fid(proto::FidRequest) -> proto::MessagesResponse;
+        get_all_cast_messages_by_fid(proto::FidTimestampRequest) -> proto::MessagesResponse;
+        get_all_reaction_messages_by_fid(proto::FidTimestampRequest) -> proto::MessagesResponse;
+        get_all_verification_messages_by_fid(proto::FidTimestampRequest) -> proto::MessagesResponse;
+        get_all_user_data_messages_by_fid(proto::FidTimestampRequest) -> proto::MessagesResponse;
+        get_all_link_messages_by_fid(proto::FidTimestampRequest) -> proto::MessagesResponse;
+        stream_all_cast_messages_by_fid(proto::FidTimestampRequest) -> Streaming<proto::MessagesResponse>;
+        stream_all_reaction_messages_by_fid(proto::FidTimestampRequest) -> Streaming<proto::MessagesResponse>;
+        stream_all_verification_messages_by_fid(proto::FidTimestampRequest) -> Streaming<proto::MessagesResponse>;
+        stream_all_user_data_messages_by_fid(proto::FidTimestampRequest) -> Streaming<proto::MessagesResponse>;
+        stream_all_link_messages_by_fid(proto::FidTimestampRequest) -> Streaming<proto::MessagesResponse>;
+        get_cast_compact_state_by_fid(proto::FidRequest) -> proto::MessagesResponse;
+        get_reaction_compact_state_by_fid(proto::FidRequest) -> proto::MessagesResponse;
+        get_verification_compact_state_by_fid(proto::FidRequest) -> proto::MessagesResponse;
+        get_user_data_compact_state_by_fid(proto::FidRequest) -> proto::MessagesResponse;
+        get_link_compact_state_by_fid(proto::FidRequest) -> proto::MessagesResponse;
+        get_trie_metadata_by_prefix(proto::TrieNodeMetadataRequest) -> proto::TrieNodeMetadataResponse;
+        get_proof(proto::GetProofRequest) -> proto::MessageProof;
+        get_validator_set(proto::ValidatorSetRequest) -> proto::ValidatorSetResponse;
+        get_epoch_info(proto::GetEpochInfoRequest) -> proto::GetEpochInfoResponse;
+        get_shard_root(proto::ShardRootRequest) -> proto::ShardRootResponse;
+        get_trie_roots_at_heights(proto::GetTrieRootsAtHeightsRequest) -> proto::GetTrieRootsAtHeightsResponse;
+        get_shard_manifest(proto::GetShardManifestRequest) -> proto::GetShardManifestResponse;
+        get_votes(proto::GetVotesRequest) -> proto::GetVotesResponse;
+        get_proposer_stats(proto::GetProposerStatsRequest) -> proto::GetProposerStatsResponse;
+        get_network_config(proto::GetNetworkConfigRequest) -> proto::GetNetworkConfigResponse;
+        get_recent_blocks_summary(proto::GetRecentBlocksSummaryRequest) -> proto::GetRecentBlocksSummaryResponse;
+        get_submission_policy(proto::GetSubmissionPolicyRequest) -> proto::GetSubmissionPolicyResponse;
+    }
+
+    rpcs! { admin,
+        upload_snapshot(proto::Empty) -> proto::Empty;
+        retry_onchain_events(proto::RetryOnchainEventsRequest) -> proto::Empty;
+        pause_onchain_ingestion(proto::Empty) -> proto::Empty;
+        resume_onchain_ingestion(proto::Empty) -> proto::Empty;
+        freeze_shard(proto::FreezeShardRequest) -> proto::Empty;
+        unfreeze_shard(proto::FreezeShardRequest) -> proto::Empty;
+        create_checkpoint(proto::CreateCheckpointRequest) -> proto::CreateCheckpointResponse;
+        check_shard_consistency(proto::CheckShardConsistencyRequest) -> proto::CheckShardConsistencyResponse;
+        submit_on_chain_events(proto::SubmitOnChainEventsRequest) -> proto::SubmitOnChainEventsResponse;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn fast_retries(max_retries: u32) -> RetryConfig {
+        RetryConfig {
+            max_retries,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(2),
+        }
+    }
+
+    #[test]
+    fn test_backoff() {
+        let retry = RetryConfig::default();
+        assert_eq!(retry.backoff(0), Duration::from_millis(100));
+        assert_eq!(retry.backoff(3), Duration::from_millis(800));
+        assert_eq!(retry.backoff(10), Duration::from_secs(5));
+        assert_eq!(retry.backoff(u32::MAX), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_basic_auth() {
+        let mut auth = BasicAuth::new("user", "pass").unwrap();
+        let request = auth.call(Request::new(())).unwrap();
+        assert_eq!(
+            request.metadata().get("authorization").unwrap(),
+            "Basic dXNlcjpwYXNz"
+        );
+
+        let request = BasicAuth::default().call(Request::new(())).unwrap();
+        assert!(request.metadata().get("authorization").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_retries() {
+        let client = SnapchainClient::builder("http://127.0.0.1:1")
+            .retry(fast_retries(2))
+            .connect_lazy()
+            .unwrap();
+
+        // Unavailable nodes are retried until they answer
+        let attempts = AtomicU32::new(0);
+        let result = client
+            .call((), |_| async {
+                if attempts.fetch_add(1, Ordering::SeqCst) < 2 {
+                    Err(Status::unavailable("mempool is full"))
+                } else {
+                    Ok(Response::new(42))
+                }
+            })
+            .await;
+        assert_eq!(result.unwrap(), 42);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        // Other errors are returned straight away
+        let attempts = AtomicU32::new(0);
+        let result: Result<(), Status> = client
+            .call((), |_| async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(Status::invalid_argument("bad message"))
+            })
+            .await;
+        assert_eq!(result.unwrap_err().code(), Code::InvalidArgument);
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+        // Nothing is listening, so the real rpcs give up once the retries are used up
+        let status = client.get_info(proto::GetInfoRequest {}).await.unwrap_err();
+        assert_eq!(status.code(), Code::Unavailable);
+    }
+
+    #[test]
+    fn test_invalid_endpoint() {
+        let result = SnapchainClient::builder("not a uri").connect_lazy();
+        assert!(matches!(result, Err(ClientError::InvalidEndpoint(_))));
+    }
+}
@@ -8,9 +8,10 @@ use async_trait::async_trait;
 use foundry_common::ens::EnsError;
 use futures_util::stream::StreamExt;
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 use thiserror::Error;
 use tokio::sync::mpsc;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 use crate::mempool::mempool::{MempoolRequest, MempoolSource};
 use crate::{
@@ -21,11 +22,26 @@ use crate::{
     proto::{
         on_chain_event, IdRegisterEventBody, IdRegisterEventType, OnChainEvent, OnChainEventType,
         SignerEventBody, SignerEventType, SignerMigratedEventBody, StorageRentEventBody,
-        ValidatorMessage, VerificationAddAddressBody,
+        ValidatorMessage, ValidatorStakeEventBody, VerificationAddAddressBody,
     },
-    storage::store::{engine::MempoolMessage, node_local_state::LocalStateStore},
-    utils::statsd_wrapper::StatsdClientWrapper,
+    storage::store::{
+        engine::MempoolMessage,
+        node_local_state::{LocalStateError, LocalStateStore},
+        validator_stakes::MAX_VALIDATOR_STAKE,
+    },
+    utils::{latency_histograms::Latency, statsd_wrapper::StatsdClientWrapper},
 };
+use pause::PauseState;
+use pending::PendingEvents;
+use reorg::{HaltState, ReorgCheck, ReorgDetector, ReorgHalt};
+use retry::RetryQueue;
+
+pub mod pause;
+pub mod pending;
+pub mod reorg;
+pub mod retry;
+pub mod submitted;
+pub mod supervisor;
 
 sol!(
     #[allow(missing_docs)]
@@ -48,24 +64,65 @@ sol!(
     "src/connectors/onchain_events/key_registry_abi.json"
 );
 
-static STORAGE_REGISTRY: Address = address!("00000000fcce7f938e7ae6d3c335bd6a1a7c593d");
+sol!(
+    #[allow(missing_docs)]
+    contract StakeRegistryAbi {
+        event StakeChanged(bytes32 indexed validatorKey, uint256 stake);
+    }
+);
+
+pub static STORAGE_REGISTRY: Address = address!("00000000fcce7f938e7ae6d3c335bd6a1a7c593d");
 
-static KEY_REGISTRY: Address = address!("00000000Fc1237824fb747aBDE0FF18990E59b7e");
+pub static KEY_REGISTRY: Address = address!("00000000Fc1237824fb747aBDE0FF18990E59b7e");
 
-static ID_REGISTRY: Address = address!("00000000Fc6c5F01Fc30151999387Bb99A9f489b");
+pub static ID_REGISTRY: Address = address!("00000000Fc6c5F01Fc30151999387Bb99A9f489b");
 
 // For reference, in case it needs to be specified manually
 const FIRST_BLOCK: u64 = 108864739;
-static CHAIN_ID: u32 = 10; // OP mainnet
+pub const OP_MAINNET_CHAIN_ID: u32 = 10;
 const RENT_EXPIRY_IN_SECONDS: u64 = 365 * 24 * 60 * 60; // One year
 
 const RETRY_TIMEOUT_SECONDS: u64 = 10;
+// How often live sync polls the chain head to report the ingestion lag
+const CHAIN_HEAD_POLL_INTERVAL_SECONDS: u64 = 30;
+// How often the chain head is polled while the pending events wait for confirmations to free up room
+const PENDING_EVENTS_POLL_INTERVAL_SECONDS: u64 = 2;
+// Stakes are emitted in wei
+const WEI_PER_TOKEN: u64 = 1_000_000_000_000_000_000;
+
+// The whole tokens of a stake, capped like every recorded stake
+fn stake_in_tokens(stake: U256) -> u64 {
+    let tokens = stake / U256::from(WEI_PER_TOKEN);
+    tokens.min(U256::from(MAX_VALIDATOR_STAKE)).to::<u64>()
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
     pub rpc_url: String,
     pub start_block_number: Option<u64>,
     pub stop_block_number: Option<u64>,
+    // Live sync halts instead of following a reorg deeper than this many blocks
+    pub max_reorg_depth: u64,
+    // Blocks on top of an onchain event's before it's submitted, 0 submits events as soon as
+    // they're seen
+    pub confirmation_depth: u64,
+    // Events waiting for confirmations that are buffered before polling the rpc pauses until some
+    // are submitted. Events are never dropped, so a batch can take the buffer past it.
+    pub max_pending_events: u64,
+    // The rpc must be for this chain, it's also recorded on the onchain events
+    pub chain_id: u32,
+    // Accept onchain events from a trusted indexer through the SubmitOnChainEvents admin rpc. They
+    // are only checked for structure, so only enable this for a feed you'd trust as the rpc.
+    pub accept_submitted_events: bool,
+    // The contract emitting the stakes validators' votes can be weighed by, its StakeChanged
+    // events are only ingested when it's set
+    pub stake_registry_address: Option<String>,
+    // Restarts of the connector after it fails or panics, in a row without ingesting a block,
+    // before ingestion is given up on
+    pub max_connector_restarts: u32,
+    // Backoff before the first restart, doubled on each one after it
+    #[serde(with = "humantime_serde")]
+    pub connector_restart_backoff: Duration,
 }
 
 impl Default for Config {
@@ -74,10 +131,19 @@ impl Default for Config {
             rpc_url: String::new(),
             start_block_number: None,
             stop_block_number: None,
+            max_reorg_depth: 64,
+            confirmation_depth: 0,
+            max_pending_events: 10_000,
+            chain_id: OP_MAINNET_CHAIN_ID,
+            accept_submitted_events: false,
+            stake_registry_address: None,
+            max_connector_restarts: 5,
+            connector_restart_backoff: Duration::from_secs(1),
         };
     }
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub enum OnchainEventsRequest {
     RetryFid(u64),
     RetryBlockRange {
@@ -123,6 +189,21 @@ pub enum SubscribeError {
 
     #[error("Unable to find block by hash")]
     UnableToFindBlockByHash,
+
+    #[error("Reorg of {depth} blocks at block {block_number} exceeds the maximum reorg depth")]
+    ReorgTooDeep { block_number: u64, depth: u64 },
+
+    #[error("The rpc url is for chain {actual}, but chain_id is configured as {expected}")]
+    ChainIdMismatch { expected: u32, actual: u64 },
+
+    #[error("The onchain events in the db are from chain {stored}, but chain_id is configured as {expected}")]
+    StoredChainIdMismatch { expected: u32, stored: u32 },
+
+    #[error("Invalid stake registry address: {0}")]
+    InvalidStakeRegistryAddress(String),
+
+    #[error("Unable to read or record the chain id: {0}")]
+    UnableToAccessChainId(#[from] LocalStateError),
 }
 
 #[async_trait]
@@ -175,9 +256,22 @@ pub struct Subscriber {
     statsd_client: StatsdClientWrapper,
     local_state_store: LocalStateStore,
     onchain_events_request_rx: mpsc::Receiver<OnchainEventsRequest>,
+    max_reorg_depth: u64,
+    reorg_detector: ReorgDetector,
+    halt_state: HaltState,
+    pause_state: PauseState,
+    retry_queue: RetryQueue,
+    chain_id: u32,
+    stake_registry: Option<Address>,
+    // The latest block the rpc reported, 0 until it's first polled
+    chain_head_block: u64,
+    confirmation_depth: u64,
+    max_pending_events: u64,
+    pending_events: PendingEvents,
+    // The latest block processed, the one recorded is held back while its events are pending
+    processed_block: u64,
 }
 
-// TODO(aditi): Wait for 1 confirmation before "committing" an onchain event.
 impl Subscriber {
     pub fn new(
         config: &Config,
@@ -185,12 +279,24 @@ impl Subscriber {
         statsd_client: StatsdClientWrapper,
         local_state_store: LocalStateStore,
         onchain_events_request_rx: mpsc::Receiver<OnchainEventsRequest>,
+        halt_state: HaltState,
+        pause_state: PauseState,
+        retry_queue: RetryQueue,
+        pending_events: PendingEvents,
     ) -> Result<Subscriber, SubscribeError> {
         if config.rpc_url.is_empty() {
             return Err(SubscribeError::EmptyRpcUrl);
         }
         let url = config.rpc_url.parse()?;
         let provider = ProviderBuilder::new().on_http(url);
+        let stake_registry = match &config.stake_registry_address {
+            None => None,
+            Some(address) => Some(
+                address
+                    .parse()
+                    .map_err(|_| SubscribeError::InvalidStakeRegistryAddress(address.clone()))?,
+            ),
+        };
         Ok(Subscriber {
             local_state_store,
             provider,
@@ -201,9 +307,28 @@ impl Subscriber {
             stop_block_number: config.stop_block_number,
             statsd_client,
             onchain_events_request_rx,
+            max_reorg_depth: config.max_reorg_depth,
+            reorg_detector: ReorgDetector::new(config.max_reorg_depth),
+            halt_state,
+            pause_state,
+            retry_queue,
+            chain_id: config.chain_id,
+            stake_registry,
+            chain_head_block: 0,
+            confirmation_depth: config.confirmation_depth,
+            max_pending_events: config.max_pending_events,
+            pending_events,
+            processed_block: 0,
         })
     }
 
+    // The contracts live sync and block range retries follow
+    fn contract_addresses(&self) -> Vec<Address> {
+        let mut addresses = vec![STORAGE_REGISTRY, KEY_REGISTRY, ID_REGISTRY];
+        addresses.extend(self.stake_registry);
+        addresses
+    }
+
     fn count(&self, key: &str, value: i64) {
         self.statsd_client
             .count(format!("onchain_events.{}", key).as_str(), value);
@@ -214,6 +339,49 @@ impl Subscriber {
             .gauge(format!("onchain_events.{}", key).as_str(), value);
     }
 
+    // How many blocks ingestion is behind the chain head, unknown until the head is polled
+    fn lag_blocks(&self) -> Option<u64> {
+        if self.chain_head_block == 0 {
+            return None;
+        }
+        Some(
+            self.chain_head_block
+                .saturating_sub(self.latest_block_in_db()),
+        )
+    }
+
+    fn report_lag(&self) {
+        if let Some(lag) = self.lag_blocks() {
+            self.gauge("lag_blocks", lag);
+        }
+    }
+
+    fn set_chain_head(&mut self, block_number: u64) {
+        self.chain_head_block = block_number;
+        self.gauge("chain_head_block", block_number);
+        self.report_lag();
+    }
+
+    fn pending_events_full(&self) -> bool {
+        self.pending_events.len() as u64 >= self.max_pending_events
+    }
+
+    fn report_pending_events(&self) {
+        self.gauge("pending_events", self.pending_events.len() as u64);
+    }
+
+    async fn poll_chain_head(&mut self) {
+        match self.provider.get_block_number().await {
+            Ok(block_number) => self.set_chain_head(block_number),
+            Err(err) => {
+                warn!(
+                    err = err.to_string(),
+                    "Unable to get the chain head block for the ingestion lag"
+                );
+            }
+        }
+    }
+
     async fn add_onchain_event(
         &mut self,
         fid: u64,
@@ -234,7 +402,7 @@ impl Subscriber {
             log_index,
             tx_index,
             r#type: event_type as i32,
-            chain_id: CHAIN_ID,
+            chain_id: self.chain_id,
             version: 0,
             body: Some(event_body),
             transaction_hash: transaction_hash.to_vec(),
@@ -262,6 +430,9 @@ impl Subscriber {
             OnChainEventType::EventTypeStorageRent => {
                 self.count("num_storage_events", 1);
             }
+            OnChainEventType::EventTypeValidatorStake => {
+                self.count("num_validator_stake_events", 1);
+            }
         };
         match &event.body {
             Some(on_chain_event::Body::IdRegisterEventBody(id_register_event_body)) => {
@@ -272,6 +443,15 @@ impl Subscriber {
             _ => {}
         }
         self.gauge("latest_block_number", block_number as u64);
+        if self.confirmation_depth == 0 {
+            self.submit_onchain_event(event).await;
+        } else {
+            self.pending_events.push(event);
+            self.report_pending_events();
+        }
+    }
+
+    async fn submit_onchain_event(&self, event: OnChainEvent) {
         if let Err(err) = self
             .mempool_tx
             .send(MempoolRequest::AddMessage(
@@ -290,11 +470,83 @@ impl Subscriber {
                 log_index = event.log_index,
                 err = err.to_string(),
                 "Unable to send onchain event to mempool"
-            )
+            );
+            self.count("apply_errors", 1);
+        }
+    }
+
+    // Makes sure the rpc is for the configured chain, and that the events already in the db came
+    // from it too. Otherwise the events of two chains would be mixed.
+    pub async fn verify_chain_id(&self) -> Result<(), SubscribeError> {
+        let rpc_chain_id = self.provider.get_chain_id().await?;
+        self.check_chain_id(rpc_chain_id)
+    }
+
+    fn check_chain_id(&self, rpc_chain_id: u64) -> Result<(), SubscribeError> {
+        if rpc_chain_id != self.chain_id as u64 {
+            return Err(SubscribeError::ChainIdMismatch {
+                expected: self.chain_id,
+                actual: rpc_chain_id,
+            });
+        }
+        match self.local_state_store.get_onchain_events_chain_id()? {
+            Some(stored) if stored != self.chain_id => Err(SubscribeError::StoredChainIdMismatch {
+                expected: self.chain_id,
+                stored,
+            }),
+            Some(_) => Ok(()),
+            // First start, or a db from before the chain id was recorded
+            None => Ok(self
+                .local_state_store
+                .set_onchain_events_chain_id(self.chain_id)?),
         }
     }
 
-    fn record_block_number(&self, block_number: u64) {
+    // Submits the pending events with confirmation_depth blocks on top of theirs. The chain is at
+    // least at the last block processed, in case the head wasn't polled since.
+    async fn submit_confirmed_events(&mut self) {
+        if self.pending_events.is_empty() {
+            return;
+        }
+        let chain_head = self.chain_head_block.max(self.processed_block);
+        let confirmed_block = chain_head.saturating_sub(self.confirmation_depth);
+        for event in self.pending_events.release(confirmed_block) {
+            self.submit_onchain_event(event).await;
+        }
+        self.report_pending_events();
+        // Moves the recorded block up past the events submitted
+        self.record_block_number(self.processed_block);
+    }
+
+    // Waits for enough of the pending events to be confirmed and submitted for the buffer to have
+    // room again
+    async fn wait_for_pending_events(&mut self) {
+        info!(
+            pending_events = self.pending_events.len(),
+            "Onchain events ingestion waiting for confirmations"
+        );
+        loop {
+            self.poll_chain_head().await;
+            self.submit_confirmed_events().await;
+            if !self.pending_events_full() {
+                break;
+            }
+            tokio::time::sleep(tokio::time::Duration::from_secs(
+                PENDING_EVENTS_POLL_INTERVAL_SECONDS,
+            ))
+            .await;
+        }
+        info!("Onchain events ingestion resumed");
+    }
+
+    fn record_block_number(&mut self, block_number: u64) {
+        self.processed_block = self.processed_block.max(block_number);
+        // Pending events are only kept in memory, so the recorded block stays before them for a
+        // restart to read them again
+        let block_number = match self.pending_events.oldest_block() {
+            Some(oldest_block) => block_number.min(oldest_block.saturating_sub(1)),
+            None => block_number,
+        };
         if block_number as u64 > self.latest_block_in_db() {
             match self.local_state_store.set_latest_block_number(block_number) {
                 Err(err) => {
@@ -306,6 +558,8 @@ impl Subscriber {
                 }
                 _ => {}
             }
+            self.gauge("processed_block", block_number);
+            self.report_lag();
         };
     }
 
@@ -362,6 +616,7 @@ impl Subscriber {
         // TODO(aditi): Cache these queries for timestamp to optimize rpc calls.
         // [block_timestamp] exists on [Log], however it's never populated in practice.
         let block_timestamp = self.get_block_timestamp(block_hash).await?;
+        let stake_registry = self.stake_registry;
         let add_event = |fid, event_type, event_body| async move {
             self.add_onchain_event(
                 fid,
@@ -520,6 +775,25 @@ impl Subscriber {
                 .await;
                 Ok(())
             }
+            // Anyone could emit an event with the same signature
+            Some(&StakeRegistryAbi::StakeChanged::SIGNATURE_HASH)
+                if stake_registry == Some(event.inner.address) =>
+            {
+                let StakeRegistryAbi::StakeChanged {
+                    validatorKey,
+                    stake,
+                } = event.log_decode()?.inner.data;
+                add_event(
+                    0,
+                    OnChainEventType::EventTypeValidatorStake,
+                    on_chain_event::Body::ValidatorStakeEventBody(ValidatorStakeEventBody {
+                        validator_public_key: validatorKey.to_vec(),
+                        stake: stake_in_tokens(stake),
+                    }),
+                )
+                .await;
+                Ok(())
+            }
             _ => Ok(()),
         }
     }
@@ -533,7 +807,8 @@ impl Subscriber {
                     error!(
                         event_kind,
                         "Error processing onchain event. Error: {:#?}. Event: {:#?}", err, event,
-                    )
+                    );
+                    self.count("apply_errors", 1);
                 }
                 Ok(()) => {}
             }
@@ -577,7 +852,15 @@ impl Subscriber {
         let batch_size = 1000;
         let mut start_block = initial_start_block;
         loop {
+            if self.pause_state.paused() {
+                // Every batch before this one was recorded, so this is where the cursor is
+                self.wait_until_resumed().await;
+            }
+            if self.pending_events_full() {
+                self.wait_for_pending_events().await;
+            }
             let stop_block = final_stop_block.min(start_block + batch_size);
+            let batch_start = std::time::Instant::now();
 
             let storage_filter = Filter::new()
                 .address(STORAGE_REGISTRY)
@@ -594,8 +877,22 @@ impl Subscriber {
                 .from_block(start_block)
                 .to_block(stop_block);
             self.get_logs_with_retry(key_filter, "key").await?;
+            if let Some(stake_registry) = self.stake_registry {
+                let stake_filter = Filter::new()
+                    .address(stake_registry)
+                    .from_block(start_block)
+                    .to_block(stop_block);
+                self.get_logs_with_retry(stake_filter, "stake").await?;
+            }
 
             self.record_block_number(stop_block);
+            self.submit_confirmed_events().await;
+            self.statsd_client.time_latency(
+                Latency::OnchainEventsBatch,
+                None,
+                None,
+                batch_start.elapsed().as_millis() as u64,
+            );
             start_block += batch_size;
 
             if start_block > final_stop_block {
@@ -657,9 +954,81 @@ impl Subscriber {
         }
     }
 
+    // Returns whether the log should be processed. Logs removed by a reorg are skipped, the
+    // events from the new chain are processed as they arrive.
+    fn check_for_reorg(&mut self, event: &Log) -> Result<bool, SubscribeError> {
+        let (block_number, block_hash) = match (event.block_number, event.block_hash) {
+            (Some(block_number), Some(block_hash)) => (block_number, block_hash),
+            // process_log reports these
+            _ => return Ok(true),
+        };
+
+        match self
+            .reorg_detector
+            .check_log(block_number, block_hash, event.removed)
+        {
+            ReorgCheck::Continue => {}
+            ReorgCheck::Reorg { depth } => {
+                warn!(block_number, depth, "Reorg detected in onchain events");
+                self.count("reorgs", 1);
+            }
+            ReorgCheck::TooDeep { depth } => {
+                error!(
+                    block_number,
+                    depth,
+                    max_reorg_depth = self.max_reorg_depth,
+                    "CRITICAL: reorg exceeds the maximum depth, halting onchain events ingestion. \
+                    Check the l2 rpc, the node must be restarted to resume."
+                );
+                self.count("reorg_too_deep", 1);
+                self.gauge("halted", 1);
+                self.halt_state.halt(ReorgHalt {
+                    block_number,
+                    depth,
+                });
+                return Err(SubscribeError::ReorgTooDeep {
+                    block_number,
+                    depth,
+                });
+            }
+        }
+
+        if event.removed {
+            // Events from the removed block that are still pending are never submitted
+            self.pending_events.remove_block(block_hash.as_slice());
+            self.report_pending_events();
+        }
+        Ok(!event.removed)
+    }
+
+    async fn wait_until_resumed(&self) {
+        info!("Onchain events ingestion paused");
+        self.gauge("paused", 1);
+        self.pause_state.wait_until_resumed().await;
+        self.gauge("paused", 0);
+        info!("Onchain events ingestion resumed");
+    }
+
+    // Waits out a pause, then returns the block live sync should continue from. Nothing is
+    // buffered while paused, so that's the last block recorded before the pause.
+    async fn resume_live_sync(&self, live_sync_block: u64) -> u64 {
+        self.wait_until_resumed().await;
+        live_sync_block.max(self.latest_block_in_db())
+    }
+
+    // Returns Ok when ingestion is paused or the pending events buffer is full, as well as when the
+    // subscription ends
     async fn sync_live_events(&mut self, start_block_number: u64) -> Result<(), SubscribeError> {
+        let mut paused = self.pause_state.subscribe();
+        if *paused.borrow_and_update() || self.pending_events_full() {
+            return Ok(());
+        }
+
+        // A new subscription may deliver logs from before the previous one ended
+        self.reorg_detector = ReorgDetector::new(self.max_reorg_depth);
+
         let filter = Filter::new()
-            .address(vec![STORAGE_REGISTRY, KEY_REGISTRY, ID_REGISTRY])
+            .address(self.contract_addresses())
             .from_block(start_block_number);
 
         let filter = match self.stop_block_number {
@@ -669,32 +1038,43 @@ impl Subscriber {
 
         let subscription = self.provider.watch_logs(&filter).await?;
         let mut stream = subscription.into_stream();
+        let mut chain_head_poll = tokio::time::interval(tokio::time::Duration::from_secs(
+            CHAIN_HEAD_POLL_INTERVAL_SECONDS,
+        ));
         loop {
             tokio::select! {
                  biased;
 
+                 Ok(()) = paused.changed() => {
+                    if *paused.borrow_and_update() {
+                        // Dropping the subscription stops polling the rpc
+                        return Ok(());
+                    }
+                 }
                  request = self.onchain_events_request_rx.recv() => {
                     match request {
                         None => {
                             // Ignore, this can happen if we don't run an admin server
                         }, Some(request) => {
-                            match request {
-                                OnchainEventsRequest::RetryFid(retry_fid) =>  {
-                                    if let Err(err) = self.retry_fid(retry_fid).await {
-                                        error!(fid = retry_fid, "Unable to retry fid: {}", err.to_string())
-                                    }
-                                },
-                                OnchainEventsRequest::RetryBlockRange{start_block_number, stop_block_number} => {
-                                    if let Err(err) = self.retry_block_range(start_block_number, stop_block_number).await {
-                                        error!(start_block_number, stop_block_number, "Unable to retry block range: {}", err.to_string())
-                                    }
-
-
-                                }
+                            // Queue everything that's waiting, so it's coalesced before any of it runs
+                            let latest_block = self.latest_block_in_db();
+                            self.retry_queue.push(request, latest_block);
+                            while let Ok(request) = self.onchain_events_request_rx.try_recv() {
+                                self.retry_queue.push(request, latest_block);
                             }
                         }
                     }
                  }
+                 // One retry at a time, so requests that arrive meanwhile are merged into the queue
+                 _ = std::future::ready(()), if !self.retry_queue.is_empty() => {
+                    if let Some(request) = self.retry_queue.pop() {
+                        self.run_retry(request).await;
+                    }
+                 }
+                 _ = chain_head_poll.tick() => {
+                    self.poll_chain_head().await;
+                    self.submit_confirmed_events().await;
+                 }
                  events = stream.next() => {
                      match events {
                          None => {
@@ -702,14 +1082,19 @@ impl Subscriber {
                              break;
                          },
                          Some(events) => {
+                             let batch_start = std::time::Instant::now();
                              for event in events {
+                                 if !self.check_for_reorg(&event)? {
+                                     continue;
+                                 }
                                  let result = self.process_log(&event).await;
                                  match result {
                                      Err(err) => {
                                          error!(
                                              "Error processing onchain event. Error: {:#?}. Event: {:#?}",
                                              err, event,
-                                         )
+                                         );
+                                         self.count("apply_errors", 1);
                                      }
                                      Ok(()) => match event.block_number {
                                          None => {}
@@ -719,6 +1104,22 @@ impl Subscriber {
                                      },
                                  }
                              }
+                             self.submit_confirmed_events().await;
+                             self.statsd_client.time_latency(
+                                 Latency::OnchainEventsBatch,
+                                 None,
+                                 None,
+                                 batch_start.elapsed().as_millis() as u64,
+                             );
+                             if self.pending_events_full() {
+                                 warn!(
+                                     pending_events = self.pending_events.len(),
+                                     "Onchain events waiting for confirmations are at the maximum, pausing polling"
+                                 );
+                                 self.count("pending_events_full", 1);
+                                 // Dropping the subscription stops polling the rpc
+                                 return Ok(());
+                             }
                          }
                      }
                  }
@@ -727,6 +1128,32 @@ impl Subscriber {
         Ok(())
     }
 
+    async fn run_retry(&mut self, request: OnchainEventsRequest) {
+        match request {
+            OnchainEventsRequest::RetryFid(retry_fid) => {
+                if let Err(err) = self.retry_fid(retry_fid).await {
+                    error!(fid = retry_fid, "Unable to retry fid: {}", err.to_string())
+                }
+            }
+            OnchainEventsRequest::RetryBlockRange {
+                start_block_number,
+                stop_block_number,
+            } => {
+                if let Err(err) = self
+                    .retry_block_range(start_block_number, stop_block_number)
+                    .await
+                {
+                    error!(
+                        start_block_number,
+                        stop_block_number,
+                        "Unable to retry block range: {}",
+                        err.to_string()
+                    )
+                }
+            }
+        }
+    }
+
     pub async fn retry_fid(&mut self, fid: u64) -> Result<(), SubscribeError> {
         info!(fid, "Retrying onchain events for fid");
         let filter = Filter::new()
@@ -773,7 +1200,7 @@ impl Subscriber {
             stop_block_number, "Retrying onchain events in range"
         );
         let filter = Filter::new()
-            .address(vec![STORAGE_REGISTRY, KEY_REGISTRY, ID_REGISTRY])
+            .address(self.contract_addresses())
             .from_block(start_block_number)
             .to_block(stop_block_number);
         self.get_logs_with_retry(filter, "all").await?;
@@ -782,6 +1209,7 @@ impl Subscriber {
 
     pub async fn run(&mut self) -> Result<(), SubscribeError> {
         let latest_block_on_chain = self.latest_block_on_chain().await?;
+        self.set_chain_head(latest_block_on_chain);
         let latest_block_in_db = self.latest_block_in_db();
         info!(
             start_block_number = self.start_block_number,
@@ -824,16 +1252,30 @@ impl Subscriber {
             }
         }
 
-        if live_sync_block.is_none() {
-            info!("Historical sync complete. Not subscribing to live events");
-            return Ok(());
-        }
+        let mut live_sync_block = match live_sync_block {
+            None => {
+                info!("Historical sync complete. Not subscribing to live events");
+                return Ok(());
+            }
+            Some(live_sync_block) => live_sync_block,
+        };
 
         loop {
-            match self.sync_live_events(live_sync_block.unwrap()).await {
+            match self.sync_live_events(live_sync_block).await {
+                Err(e @ SubscribeError::ReorgTooDeep { .. }) => return Err(e),
                 Err(e) => {
                     error!("Live sync ended with error: {e}. Retrying in 10 seconds",);
                 }
+                Ok(()) if self.pause_state.paused() => {
+                    live_sync_block = self.resume_live_sync(live_sync_block).await;
+                    continue;
+                }
+                Ok(()) if self.pending_events_full() => {
+                    self.wait_for_pending_events().await;
+                    // The events still pending are kept, so it continues from the last block seen
+                    live_sync_block = live_sync_block.max(self.processed_block);
+                    continue;
+                }
                 _ => {
                     error!("Live sync ended unexpectedly. Retrying in 10 seconds",);
                 }
@@ -842,3 +1284,296 @@ impl Subscriber {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::db::RocksDB;
+    use crate::storage::store::test_helper;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_stake_in_tokens() {
+        let wei = U256::from(WEI_PER_TOKEN);
+        assert_eq!(stake_in_tokens(U256::ZERO), 0);
+        assert_eq!(stake_in_tokens(wei - U256::from(1)), 0);
+        assert_eq!(stake_in_tokens(wei * U256::from(1_500)), 1_500);
+        assert_eq!(stake_in_tokens(U256::MAX), MAX_VALIDATOR_STAKE);
+    }
+
+    fn make_subscriber(
+        max_reorg_depth: u64,
+    ) -> (
+        Subscriber,
+        mpsc::Receiver<MempoolRequest>,
+        HaltState,
+        tempfile::TempDir,
+    ) {
+        let (subscriber, mempool_rx, halt_state, _, dir) =
+            make_subscriber_with_pause(max_reorg_depth);
+        (subscriber, mempool_rx, halt_state, dir)
+    }
+
+    fn make_subscriber_with_pause(
+        max_reorg_depth: u64,
+    ) -> (
+        Subscriber,
+        mpsc::Receiver<MempoolRequest>,
+        HaltState,
+        PauseState,
+        tempfile::TempDir,
+    ) {
+        make_subscriber_with_config(Config {
+            max_reorg_depth,
+            ..Config::default()
+        })
+    }
+
+    fn make_subscriber_with_config(
+        config: Config,
+    ) -> (
+        Subscriber,
+        mpsc::Receiver<MempoolRequest>,
+        HaltState,
+        PauseState,
+        tempfile::TempDir,
+    ) {
+        let dir = tempfile::TempDir::new().unwrap();
+        let db = RocksDB::new(dir.path().join("a.db").to_str().unwrap());
+        db.open().unwrap();
+
+        let (mempool_tx, mempool_rx) = mpsc::channel(10);
+        let (_, onchain_events_request_rx) = mpsc::channel(10);
+        let halt_state = HaltState::default();
+        let pause_state = PauseState::default();
+        let config = Config {
+            // Never contacted, reorg checks don't make rpc calls
+            rpc_url: "http://127.0.0.1:8545".to_string(),
+            ..config
+        };
+        let subscriber = Subscriber::new(
+            &config,
+            mempool_tx,
+            test_helper::statsd_client(),
+            LocalStateStore::new(Arc::new(db)),
+            onchain_events_request_rx,
+            halt_state.clone(),
+            pause_state.clone(),
+            RetryQueue::default(),
+            PendingEvents::default(),
+        )
+        .unwrap();
+        (subscriber, mempool_rx, halt_state, pause_state, dir)
+    }
+
+    #[tokio::test]
+    async fn test_pause_and_resume_ingestion() {
+        let (mut subscriber, mut mempool_rx, _halt_state, pause_state, _dir) =
+            make_subscriber_with_pause(10);
+        subscriber.record_block_number(150);
+        assert!(pause_state.pause());
+        assert!(!pause_state.pause());
+
+        // The rpc isn't running, so live sync would fail if it tried to poll it
+        subscriber.sync_live_events(100).await.unwrap();
+        assert!(mempool_rx.try_recv().is_err());
+
+        // Stays paused until resumed
+        let still_paused = tokio::time::timeout(
+            std::time::Duration::from_millis(50),
+            subscriber.resume_live_sync(100),
+        )
+        .await;
+        assert!(still_paused.is_err());
+
+        assert!(pause_state.resume());
+        assert!(!pause_state.paused());
+        // Continues from the recorded block rather than where live sync first started
+        assert_eq!(subscriber.resume_live_sync(100).await, 150);
+    }
+
+    async fn add_rent_event(subscriber: &mut Subscriber, block_number: u32) {
+        subscriber
+            .add_onchain_event(
+                1234,
+                block_number,
+                FixedBytes::from([block_number as u8; 32]),
+                0,
+                0,
+                0,
+                FixedBytes::default(),
+                OnChainEventType::EventTypeStorageRent,
+                on_chain_event::Body::StorageRentEventBody(StorageRentEventBody::default()),
+            )
+            .await;
+        subscriber.record_block_number(block_number as u64);
+    }
+
+    #[tokio::test]
+    async fn test_full_pending_events_buffer_pauses_polling() {
+        let (mut subscriber, mut mempool_rx, _halt_state, _pause_state, _dir) =
+            make_subscriber_with_config(Config {
+                confirmation_depth: 5,
+                max_pending_events: 2,
+                ..Config::default()
+            });
+        subscriber.record_block_number(99);
+        add_rent_event(&mut subscriber, 100).await;
+        add_rent_event(&mut subscriber, 101).await;
+        assert_eq!(subscriber.pending_events.len(), 2);
+        assert!(mempool_rx.try_recv().is_err());
+        // Held back for a restart to read the pending events again
+        assert_eq!(subscriber.latest_block_in_db(), 99);
+
+        // The rpc isn't running, so live sync would fail if it tried to poll it
+        subscriber.sync_live_events(101).await.unwrap();
+
+        // Nothing is confirmed until the chain is 5 blocks past an event
+        subscriber.set_chain_head(104);
+        let still_full = tokio::time::timeout(
+            std::time::Duration::from_millis(50),
+            subscriber.wait_for_pending_events(),
+        )
+        .await;
+        assert!(still_full.is_err());
+        assert_eq!(subscriber.pending_events.len(), 2);
+
+        subscriber.set_chain_head(105);
+        subscriber.wait_for_pending_events().await;
+        match mempool_rx.try_recv().unwrap() {
+            MempoolRequest::AddMessage(MempoolMessage::ValidatorMessage(message), _, _) => {
+                assert_eq!(message.on_chain_event.unwrap().block_number, 100);
+            }
+            _ => panic!("Expected the onchain event"),
+        }
+        assert!(mempool_rx.try_recv().is_err());
+        assert_eq!(subscriber.pending_events.len(), 1);
+        assert_eq!(subscriber.latest_block_in_db(), 100);
+
+        // Polls the rpc again now that there's room
+        assert!(subscriber.sync_live_events(101).await.is_err());
+    }
+
+    #[test]
+    fn test_lag_blocks() {
+        let (mut subscriber, _mempool_rx, _halt_state, _dir) = make_subscriber(10);
+        // Unknown until the chain head is polled
+        subscriber.record_block_number(150);
+        assert_eq!(subscriber.lag_blocks(), None);
+
+        subscriber.set_chain_head(200);
+        assert_eq!(subscriber.lag_blocks(), Some(50));
+        subscriber.record_block_number(190);
+        assert_eq!(subscriber.lag_blocks(), Some(10));
+        // A head that hasn't caught up with the processed block yet isn't a negative lag
+        subscriber.set_chain_head(180);
+        assert_eq!(subscriber.lag_blocks(), Some(0));
+    }
+
+    #[test]
+    fn test_chain_id_mismatch_is_rejected() {
+        let (subscriber, _mempool_rx, _halt_state, _dir) = make_subscriber(10);
+
+        // An rpc for another chain, e.g. base instead of optimism
+        let result = subscriber.check_chain_id(8453);
+        assert!(matches!(
+            result,
+            Err(SubscribeError::ChainIdMismatch {
+                expected: 10,
+                actual: 8453
+            })
+        ));
+        assert_eq!(
+            subscriber
+                .local_state_store
+                .get_onchain_events_chain_id()
+                .unwrap(),
+            None
+        );
+
+        // The first successful check records the chain
+        subscriber.check_chain_id(10).unwrap();
+        assert_eq!(
+            subscriber
+                .local_state_store
+                .get_onchain_events_chain_id()
+                .unwrap(),
+            Some(10)
+        );
+        subscriber.check_chain_id(10).unwrap();
+    }
+
+    #[test]
+    fn test_chain_id_change_is_detected() {
+        let (mut subscriber, _mempool_rx, _halt_state, _dir) = make_subscriber(10);
+        subscriber.check_chain_id(10).unwrap();
+        subscriber.record_block_number(100);
+
+        // The config was pointed at another chain after events were stored
+        subscriber.chain_id = 8453;
+        let result = subscriber.check_chain_id(8453);
+        assert!(matches!(
+            result,
+            Err(SubscribeError::StoredChainIdMismatch {
+                expected: 8453,
+                stored: 10
+            })
+        ));
+
+        // Recording blocks keeps the chain id
+        assert_eq!(subscriber.latest_block_in_db(), 100);
+        assert_eq!(
+            subscriber
+                .local_state_store
+                .get_onchain_events_chain_id()
+                .unwrap(),
+            Some(10)
+        );
+    }
+
+    fn make_log(block_number: u64, block_hash: u8, removed: bool) -> Log {
+        Log {
+            block_number: Some(block_number),
+            block_hash: Some(FixedBytes::from([block_hash; 32])),
+            removed,
+            ..Log::default()
+        }
+    }
+
+    #[test]
+    fn test_too_deep_reorg_halts_ingestion() {
+        let (mut subscriber, mut mempool_rx, halt_state, _dir) = make_subscriber(10);
+
+        assert!(subscriber
+            .check_for_reorg(&make_log(100, 1, false))
+            .unwrap());
+        subscriber.record_block_number(100);
+
+        // Shallow reorgs are followed, skipping the removed logs
+        assert!(!subscriber.check_for_reorg(&make_log(100, 1, true)).unwrap());
+        assert!(subscriber
+            .check_for_reorg(&make_log(100, 2, false))
+            .unwrap());
+        assert!(halt_state.halted().is_none());
+
+        let result = subscriber.check_for_reorg(&make_log(80, 3, false));
+        assert!(matches!(
+            result,
+            Err(SubscribeError::ReorgTooDeep {
+                block_number: 80,
+                depth: 21
+            })
+        ));
+        assert_eq!(
+            halt_state.halted(),
+            Some(ReorgHalt {
+                block_number: 80,
+                depth: 21
+            })
+        );
+
+        // Nothing was rolled back or submitted
+        assert_eq!(subscriber.latest_block_in_db(), 100);
+        assert!(mempool_rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,42 @@
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// Whether an operator paused onchain events ingestion, e.g. for maintenance on the l2 rpc.
+/// Shared by the admin rpc that toggles it, the connector and the rpc server that reports it.
+#[derive(Clone)]
+pub struct PauseState {
+    paused: Arc<watch::Sender<bool>>,
+}
+
+impl Default for PauseState {
+    fn default() -> Self {
+        PauseState {
+            paused: Arc::new(watch::Sender::new(false)),
+        }
+    }
+}
+
+impl PauseState {
+    // Both return whether the state changed
+    pub fn pause(&self) -> bool {
+        !self.paused.send_replace(true)
+    }
+
+    pub fn resume(&self) -> bool {
+        self.paused.send_replace(false)
+    }
+
+    pub fn paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<bool> {
+        self.paused.subscribe()
+    }
+
+    pub async fn wait_until_resumed(&self) {
+        let mut paused = self.subscribe();
+        // The sender is owned by self, so it can't be dropped while waiting
+        let _ = paused.wait_for(|paused| !*paused).await;
+    }
+}
@@ -0,0 +1,56 @@
+use crate::proto::OnChainEvent;
+use std::sync::{Arc, Mutex};
+
+/// Onchain events waiting for enough blocks on top of theirs before they're submitted, so the
+/// ones from blocks a reorg removes are dropped rather than merged. Shared with the rpc server so
+/// operators can see how many are waiting.
+#[derive(Clone, Default)]
+pub struct PendingEvents {
+    // In the order they were seen
+    events: Arc<Mutex<Vec<OnChainEvent>>>,
+}
+
+impl PendingEvents {
+    /// Buffers the event, unless it already is, e.g. when a new subscription delivers the logs of
+    /// the block the previous one stopped at again
+    pub fn push(&self, event: OnChainEvent) {
+        let mut events = self.events.lock().unwrap();
+        let buffered = events.iter().any(|pending| {
+            pending.block_hash == event.block_hash && pending.log_index == event.log_index
+        });
+        if !buffered {
+            events.push(event);
+        }
+    }
+
+    /// Drops the events from a block a reorg removed, returning how many there were
+    pub fn remove_block(&self, block_hash: &[u8]) -> usize {
+        let mut events = self.events.lock().unwrap();
+        let len = events.len();
+        events.retain(|event| event.block_hash != block_hash);
+        len - events.len()
+    }
+
+    /// Takes the events from confirmed_block and before, in the order they were seen
+    pub fn release(&self, confirmed_block: u64) -> Vec<OnChainEvent> {
+        let mut events = self.events.lock().unwrap();
+        let (released, pending): (Vec<_>, Vec<_>) = events
+            .drain(..)
+            .partition(|event| event.block_number as u64 <= confirmed_block);
+        *events = pending;
+        released
+    }
+
+    pub fn oldest_block(&self) -> Option<u64> {
+        let events = self.events.lock().unwrap();
+        events.iter().map(|event| event.block_number as u64).min()
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.lock().unwrap().is_empty()
+    }
+}
@@ -0,0 +1,196 @@
+use alloy_primitives::FixedBytes;
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+/// A reorg deeper than the configured maximum. Ingestion stops when one is observed, since
+/// rolling back that much state is more likely to be caused by a misconfigured rpc or an attack
+/// than by the chain.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReorgHalt {
+    pub block_number: u64,
+    pub depth: u64,
+}
+
+/// Whether the onchain events connector has halted, or kept failing until it was given up on,
+/// shared with the rpc server so operators can see it.
+#[derive(Clone, Default)]
+pub struct HaltState {
+    halt: Arc<RwLock<Option<ReorgHalt>>>,
+    failure: Arc<RwLock<Option<String>>>,
+}
+
+impl HaltState {
+    pub fn halt(&self, halt: ReorgHalt) {
+        *self.halt.write().unwrap() = Some(halt);
+    }
+
+    pub fn halted(&self) -> Option<ReorgHalt> {
+        self.halt.read().unwrap().clone()
+    }
+
+    pub fn fail(&self, reason: String) {
+        *self.failure.write().unwrap() = Some(reason);
+    }
+
+    // Why the connector was given up on, the error of its last run
+    pub fn failure(&self) -> Option<String> {
+        self.failure.read().unwrap().clone()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ReorgCheck {
+    Continue,
+    Reorg { depth: u64 },
+    TooDeep { depth: u64 },
+}
+
+/// Detects reorgs in the live log stream from the hashes of the blocks logs were seen in. Logs
+/// arrive in block order, so a log for a block we've gone past, with a hash we haven't seen for
+/// it, or one flagged as removed, means the blocks from there on were replaced.
+pub struct ReorgDetector {
+    max_reorg_depth: u64,
+    block_hashes: BTreeMap<u64, FixedBytes<32>>,
+}
+
+impl ReorgDetector {
+    pub fn new(max_reorg_depth: u64) -> Self {
+        ReorgDetector {
+            max_reorg_depth,
+            block_hashes: BTreeMap::new(),
+        }
+    }
+
+    pub fn check_log(
+        &mut self,
+        block_number: u64,
+        block_hash: FixedBytes<32>,
+        removed: bool,
+    ) -> ReorgCheck {
+        let latest_block = match self.block_hashes.last_key_value() {
+            Some((latest_block, _)) => *latest_block,
+            None => {
+                if !removed {
+                    self.block_hashes.insert(block_number, block_hash);
+                }
+                return ReorgCheck::Continue;
+            }
+        };
+
+        let depth = if block_number > latest_block {
+            0
+        } else if !removed && self.block_hashes.get(&block_number) == Some(&block_hash) {
+            0
+        } else {
+            latest_block - block_number + 1
+        };
+
+        if depth > self.max_reorg_depth {
+            return ReorgCheck::TooDeep { depth };
+        }
+
+        if depth > 0 {
+            // These blocks were replaced
+            self.block_hashes.split_off(&block_number);
+        }
+        if !removed {
+            self.block_hashes.insert(block_number, block_hash);
+        }
+
+        // Anything older is past the maximum depth, so there's no need to compare against it
+        let latest_block = latest_block.max(block_number);
+        self.block_hashes = self
+            .block_hashes
+            .split_off(&latest_block.saturating_sub(self.max_reorg_depth));
+
+        if depth > 0 {
+            ReorgCheck::Reorg { depth }
+        } else {
+            ReorgCheck::Continue
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash(byte: u8) -> FixedBytes<32> {
+        FixedBytes::from([byte; 32])
+    }
+
+    #[test]
+    fn test_detects_reorgs() {
+        let mut detector = ReorgDetector::new(10);
+        assert_eq!(
+            detector.check_log(100, hash(1), false),
+            ReorgCheck::Continue
+        );
+        // More logs in the same block, and later blocks
+        assert_eq!(
+            detector.check_log(100, hash(1), false),
+            ReorgCheck::Continue
+        );
+        assert_eq!(
+            detector.check_log(102, hash(2), false),
+            ReorgCheck::Continue
+        );
+        assert_eq!(
+            detector.check_log(105, hash(3), false),
+            ReorgCheck::Continue
+        );
+
+        // A different block at a height we've seen
+        assert_eq!(
+            detector.check_log(102, hash(4), false),
+            ReorgCheck::Reorg { depth: 4 }
+        );
+        // The replacement chain continues normally
+        assert_eq!(
+            detector.check_log(103, hash(5), false),
+            ReorgCheck::Continue
+        );
+        assert_eq!(
+            detector.check_log(102, hash(4), false),
+            ReorgCheck::Continue
+        );
+
+        // Logs the node flags as removed
+        assert_eq!(
+            detector.check_log(103, hash(5), true),
+            ReorgCheck::Reorg { depth: 1 }
+        );
+        assert_eq!(
+            detector.check_log(103, hash(6), false),
+            ReorgCheck::Continue
+        );
+    }
+
+    #[test]
+    fn test_reorg_too_deep() {
+        let mut detector = ReorgDetector::new(10);
+        assert_eq!(
+            detector.check_log(100, hash(1), false),
+            ReorgCheck::Continue
+        );
+        assert_eq!(
+            detector.check_log(120, hash(2), false),
+            ReorgCheck::Continue
+        );
+
+        assert_eq!(
+            detector.check_log(110, hash(3), false),
+            ReorgCheck::TooDeep { depth: 11 }
+        );
+        // Blocks older than the maximum depth are forgotten, a log for one is a deep reorg too
+        assert_eq!(
+            detector.check_log(100, hash(1), false),
+            ReorgCheck::TooDeep { depth: 21 }
+        );
+        // Exactly the maximum depth is still allowed
+        assert_eq!(
+            detector.check_log(111, hash(4), true),
+            ReorgCheck::Reorg { depth: 10 }
+        );
+    }
+}
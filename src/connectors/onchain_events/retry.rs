@@ -0,0 +1,161 @@
+use super::{OnchainEventsRequest, FIRST_BLOCK};
+use std::sync::{Arc, Mutex};
+
+#[derive(Default)]
+struct PendingRetries {
+    // Inclusive, sorted by start block, and neither overlapping nor adjacent
+    block_ranges: Vec<(u64, u64)>,
+    // In the order they were requested
+    fids: Vec<u64>,
+}
+
+impl PendingRetries {
+    // A fid retry scans every block from the first one, so a range from there to the latest block
+    // already finds its events
+    fn covers_fid(&self, latest_block: u64) -> bool {
+        self.block_ranges
+            .iter()
+            .any(|(start, stop)| *start <= FIRST_BLOCK && *stop >= latest_block)
+    }
+
+    fn add_block_range(&mut self, start_block_number: u64, stop_block_number: u64) {
+        let (mut start, mut stop) = (start_block_number, stop_block_number);
+        self.block_ranges.retain(|(pending_start, pending_stop)| {
+            let touches =
+                *pending_start <= stop.saturating_add(1) && start <= pending_stop.saturating_add(1);
+            if touches {
+                start = start.min(*pending_start);
+                stop = stop.max(*pending_stop);
+            }
+            !touches
+        });
+        let index = self
+            .block_ranges
+            .partition_point(|(pending_start, _)| *pending_start < start);
+        self.block_ranges.insert(index, (start, stop));
+    }
+}
+
+/// Retries requested through the admin rpc that the connector hasn't started yet. Requests are
+/// coalesced as they're queued, so repeated or overlapping requests don't turn into repeated scans
+/// of the same blocks. Shared with the rpc server so operators can see what's queued.
+#[derive(Clone, Default)]
+pub struct RetryQueue {
+    pending: Arc<Mutex<PendingRetries>>,
+}
+
+impl RetryQueue {
+    /// Queues the request, merging block ranges that overlap or are adjacent and dropping fids that
+    /// are already queued or covered by a queued range. latest_block is the last block the
+    /// connector has seen, live sync picks up anything after it.
+    pub fn push(&self, request: OnchainEventsRequest, latest_block: u64) {
+        let mut pending = self.pending.lock().unwrap();
+        match request {
+            OnchainEventsRequest::RetryFid(fid) => {
+                if !pending.fids.contains(&fid) && !pending.covers_fid(latest_block) {
+                    pending.fids.push(fid);
+                }
+            }
+            OnchainEventsRequest::RetryBlockRange {
+                start_block_number,
+                stop_block_number,
+            } => {
+                pending.add_block_range(start_block_number, stop_block_number);
+                if pending.covers_fid(latest_block) {
+                    pending.fids.clear();
+                }
+            }
+        }
+    }
+
+    /// The next retry to run, block ranges first
+    pub fn pop(&self) -> Option<OnchainEventsRequest> {
+        let mut pending = self.pending.lock().unwrap();
+        if !pending.block_ranges.is_empty() {
+            let (start_block_number, stop_block_number) = pending.block_ranges.remove(0);
+            return Some(OnchainEventsRequest::RetryBlockRange {
+                start_block_number,
+                stop_block_number,
+            });
+        }
+        if !pending.fids.is_empty() {
+            return Some(OnchainEventsRequest::RetryFid(pending.fids.remove(0)));
+        }
+        None
+    }
+
+    pub fn is_empty(&self) -> bool {
+        let pending = self.pending.lock().unwrap();
+        pending.block_ranges.is_empty() && pending.fids.is_empty()
+    }
+
+    pub fn pending_block_ranges(&self) -> Vec<(u64, u64)> {
+        self.pending.lock().unwrap().block_ranges.clone()
+    }
+
+    pub fn pending_fids(&self) -> Vec<u64> {
+        self.pending.lock().unwrap().fids.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start_block_number: u64, stop_block_number: u64) -> OnchainEventsRequest {
+        OnchainEventsRequest::RetryBlockRange {
+            start_block_number,
+            stop_block_number,
+        }
+    }
+
+    #[test]
+    fn test_merges_block_ranges() {
+        let queue = RetryQueue::default();
+        queue.push(range(200, 300), 0);
+        queue.push(range(100, 150), 0);
+        queue.push(range(250, 400), 0);
+        assert_eq!(queue.pending_block_ranges(), vec![(100, 150), (200, 400)]);
+
+        // Adjacent ranges are merged, and a range can join the ones on both sides of it
+        queue.push(range(151, 199), 0);
+        assert_eq!(queue.pending_block_ranges(), vec![(100, 400)]);
+        queue.push(range(120, 130), 0);
+        assert_eq!(queue.pending_block_ranges(), vec![(100, 400)]);
+
+        queue.push(range(500, 600), 0);
+        assert_eq!(queue.pop(), Some(range(100, 400)));
+        assert_eq!(queue.pop(), Some(range(500, 600)));
+        assert_eq!(queue.pop(), None);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_drops_covered_fids() {
+        let latest_block = FIRST_BLOCK + 1_000;
+        let queue = RetryQueue::default();
+        queue.push(OnchainEventsRequest::RetryFid(1), latest_block);
+        queue.push(OnchainEventsRequest::RetryFid(2), latest_block);
+        queue.push(OnchainEventsRequest::RetryFid(1), latest_block);
+        assert_eq!(queue.pending_fids(), vec![1, 2]);
+
+        // A range that doesn't reach back to the first block doesn't find all of a fid's events
+        queue.push(range(FIRST_BLOCK + 1, latest_block), latest_block);
+        assert_eq!(queue.pending_fids(), vec![1, 2]);
+
+        queue.push(range(FIRST_BLOCK, FIRST_BLOCK), latest_block);
+        assert_eq!(
+            queue.pending_block_ranges(),
+            vec![(FIRST_BLOCK, latest_block)]
+        );
+        assert_eq!(queue.pending_fids(), Vec::<u64>::new());
+        queue.push(OnchainEventsRequest::RetryFid(3), latest_block);
+        assert_eq!(queue.pending_fids(), Vec::<u64>::new());
+
+        // Once the range has started, fids are queued again
+        assert_eq!(queue.pop(), Some(range(FIRST_BLOCK, latest_block)));
+        queue.push(OnchainEventsRequest::RetryFid(3), latest_block);
+        assert_eq!(queue.pop(), Some(OnchainEventsRequest::RetryFid(3)));
+        assert!(queue.is_empty());
+    }
+}
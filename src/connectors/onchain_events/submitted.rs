@@ -0,0 +1,140 @@
+use crate::proto::{on_chain_event, OnChainEvent, OnChainEventType};
+
+/// Checks a batch of onchain events submitted by a trusted indexer before they're handed to the
+/// mempool, the way the connector would have built them from the l2 logs. The feed is trusted for
+/// the contents of the events, so this only catches malformed or misordered batches.
+pub fn validate_submitted_events(events: &[OnChainEvent], chain_id: u32) -> Result<(), String> {
+    let mut previous: Option<&OnChainEvent> = None;
+    for (i, event) in events.iter().enumerate() {
+        validate_event(event, chain_id).map_err(|err| format!("event {}: {}", i, err))?;
+
+        // Events must be in the order they were emitted, like the connector reads them
+        if let Some(previous) = previous {
+            if (event.block_number, event.log_index) <= (previous.block_number, previous.log_index)
+            {
+                return Err(format!(
+                    "event {}: not after the previous event (block {}, log {})",
+                    i, previous.block_number, previous.log_index
+                ));
+            }
+            if event.block_number == previous.block_number
+                && event.block_hash != previous.block_hash
+            {
+                return Err(format!(
+                    "event {}: block hash differs from the previous event in block {}",
+                    i, event.block_number
+                ));
+            }
+        }
+        previous = Some(event);
+    }
+    Ok(())
+}
+
+fn validate_event(event: &OnChainEvent, chain_id: u32) -> Result<(), String> {
+    // Validator stakes aren't tied to an fid
+    if event.fid == 0 && event.r#type() != OnChainEventType::EventTypeValidatorStake {
+        return Err("fid is required".to_string());
+    }
+    if event.chain_id != chain_id {
+        return Err(format!(
+            "chain id {} doesn't match the configured chain id {}",
+            event.chain_id, chain_id
+        ));
+    }
+    if event.block_number == 0 {
+        return Err("block number is required".to_string());
+    }
+    if event.block_hash.len() != 32 {
+        return Err("block hash must be 32 bytes".to_string());
+    }
+    if event.transaction_hash.len() != 32 {
+        return Err("transaction hash must be 32 bytes".to_string());
+    }
+
+    let body_matches = match (event.r#type(), &event.body) {
+        (OnChainEventType::EventTypeSigner, Some(on_chain_event::Body::SignerEventBody(_))) => true,
+        (
+            OnChainEventType::EventTypeSignerMigrated,
+            Some(on_chain_event::Body::SignerMigratedEventBody(_)),
+        ) => true,
+        (
+            OnChainEventType::EventTypeIdRegister,
+            Some(on_chain_event::Body::IdRegisterEventBody(_)),
+        ) => true,
+        (
+            OnChainEventType::EventTypeStorageRent,
+            Some(on_chain_event::Body::StorageRentEventBody(_)),
+        ) => true,
+        (
+            OnChainEventType::EventTypeValidatorStake,
+            Some(on_chain_event::Body::ValidatorStakeEventBody(_)),
+        ) => true,
+        _ => false,
+    };
+    if !body_matches {
+        return Err(format!(
+            "body doesn't match the event type {}",
+            event.r#type().as_str_name()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::factory::events_factory;
+
+    fn event(block_number: u32, log_index: u32) -> OnChainEvent {
+        let mut event = events_factory::create_rent_event(1234, None, Some(10), false);
+        event.block_number = block_number;
+        event.block_hash = vec![block_number as u8; 32];
+        event.log_index = log_index;
+        event
+    }
+
+    #[test]
+    fn test_valid_batch() {
+        let events = vec![event(100, 0), event(100, 3), event(101, 0)];
+        assert_eq!(validate_submitted_events(&events, 10), Ok(()));
+        assert_eq!(validate_submitted_events(&[], 10), Ok(()));
+    }
+
+    #[test]
+    fn test_misordered_batch() {
+        let events = vec![event(101, 0), event(100, 0)];
+        assert!(validate_submitted_events(&events, 10).is_err());
+
+        // The same event twice in a batch is misordered too
+        let events = vec![event(100, 1), event(100, 1)];
+        assert!(validate_submitted_events(&events, 10).is_err());
+
+        let mut events = vec![event(100, 0), event(100, 1)];
+        events[1].block_hash = vec![0; 32];
+        assert!(validate_submitted_events(&events, 10).is_err());
+    }
+
+    #[test]
+    fn test_malformed_events() {
+        assert!(validate_submitted_events(&[event(100, 0)], 8453).is_err());
+
+        let mut no_fid = event(100, 0);
+        no_fid.fid = 0;
+        assert!(validate_submitted_events(&[no_fid], 10).is_err());
+        // Except for validator stakes
+        let stake = events_factory::create_validator_stake_event(vec![1; 32], 10, 100);
+        assert_eq!(validate_submitted_events(&[stake], 10), Ok(()));
+
+        let mut short_hash = event(100, 0);
+        short_hash.transaction_hash = vec![1; 4];
+        assert!(validate_submitted_events(&[short_hash], 10).is_err());
+
+        let mut wrong_type = event(100, 0);
+        wrong_type.r#type = OnChainEventType::EventTypeSigner as i32;
+        assert_eq!(
+            validate_submitted_events(&[wrong_type], 10),
+            Err("event 0: body doesn't match the event type EVENT_TYPE_SIGNER".to_string())
+        );
+    }
+}
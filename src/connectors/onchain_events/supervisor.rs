@@ -0,0 +1,199 @@
+use super::reorg::HaltState;
+use super::{SubscribeError, Subscriber};
+use crate::utils::statsd_wrapper::StatsdClientWrapper;
+use async_trait::async_trait;
+use futures_util::FutureExt;
+use std::any::Any;
+use std::panic::AssertUnwindSafe;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(60);
+
+#[async_trait]
+pub trait Connector: Send {
+    async fn run(&mut self) -> Result<(), SubscribeError>;
+
+    // The last block recorded as ingested, a restarted run resumes from it
+    fn cursor(&self) -> u64;
+}
+
+#[async_trait]
+impl Connector for Subscriber {
+    async fn run(&mut self) -> Result<(), SubscribeError> {
+        Subscriber::run(self).await
+    }
+
+    fn cursor(&self) -> u64 {
+        self.latest_block_in_db()
+    }
+}
+
+/// Keeps the onchain events connector running. When it returns an error or panics, it's
+/// restarted after a backoff that doubles up to a minute, and resumes from the last block it
+/// recorded. Once it has been restarted max_restarts times in a row without recording a new
+/// block, it's given up on and ingestion is marked failed, so it shows in GetInfo. A too deep
+/// reorg is never restarted from, the connector halted on purpose.
+pub struct ConnectorSupervisor {
+    max_restarts: u32,
+    restart_backoff: Duration,
+    halt_state: HaltState,
+    statsd_client: StatsdClientWrapper,
+}
+
+impl ConnectorSupervisor {
+    pub fn new(
+        max_restarts: u32,
+        restart_backoff: Duration,
+        halt_state: HaltState,
+        statsd_client: StatsdClientWrapper,
+    ) -> Self {
+        ConnectorSupervisor {
+            max_restarts,
+            restart_backoff,
+            halt_state,
+            statsd_client,
+        }
+    }
+
+    pub async fn run(&self, connector: &mut impl Connector) {
+        let mut restarts = 0;
+        let mut backoff = self.restart_backoff;
+        loop {
+            let cursor = connector.cursor();
+            let reason = match AssertUnwindSafe(connector.run()).catch_unwind().await {
+                Ok(Ok(())) => {
+                    info!("Onchain events connector finished");
+                    return;
+                }
+                Ok(Err(err @ SubscribeError::ReorgTooDeep { .. })) => {
+                    error!("Onchain events connector halted: {}", err);
+                    return;
+                }
+                Ok(Err(err)) => err.to_string(),
+                Err(panic) => format!("panicked: {}", panic_message(&*panic)),
+            };
+
+            if connector.cursor() > cursor {
+                restarts = 0;
+                backoff = self.restart_backoff;
+            }
+            if restarts >= self.max_restarts {
+                error!(
+                    restarts,
+                    reason,
+                    "CRITICAL: onchain events connector keeps failing, giving up on ingestion. \
+                    The node must be restarted to resume."
+                );
+                self.statsd_client.gauge("onchain_events.failed", 1);
+                self.halt_state.fail(reason);
+                return;
+            }
+            restarts += 1;
+            warn!(
+                restarts,
+                reason,
+                backoff_ms = backoff.as_millis() as u64,
+                "Onchain events connector exited unexpectedly, restarting"
+            );
+            self.statsd_client.count("onchain.connector_restart", 1);
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_RESTART_BACKOFF);
+        }
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::store::test_helper;
+
+    // Ingests ten blocks per run, and panics or fails on the runs it's told to
+    struct FakeConnector {
+        cursor: u64,
+        runs: Vec<u64>,
+        panics_on: Vec<usize>,
+        fails_on: Vec<usize>,
+        stops_after: usize,
+    }
+
+    impl FakeConnector {
+        fn new(panics_on: Vec<usize>, fails_on: Vec<usize>, stops_after: usize) -> Self {
+            FakeConnector {
+                cursor: 100,
+                runs: vec![],
+                panics_on,
+                fails_on,
+                stops_after,
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Connector for FakeConnector {
+        async fn run(&mut self) -> Result<(), SubscribeError> {
+            let run = self.runs.len();
+            self.runs.push(self.cursor);
+            if self.fails_on.contains(&run) {
+                return Err(SubscribeError::EmptyRpcUrl);
+            }
+            self.cursor += 10;
+            if self.panics_on.contains(&run) {
+                panic!("simulated connector panic");
+            }
+            if run + 1 >= self.stops_after {
+                return Ok(());
+            }
+            Err(SubscribeError::LogMissingBlockNumber)
+        }
+
+        fn cursor(&self) -> u64 {
+            self.cursor
+        }
+    }
+
+    fn supervisor(max_restarts: u32, halt_state: HaltState) -> ConnectorSupervisor {
+        ConnectorSupervisor::new(
+            max_restarts,
+            Duration::from_millis(1),
+            halt_state,
+            test_helper::statsd_client(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_panic_restarts_the_connector() {
+        let halt_state = HaltState::default();
+        let mut connector = FakeConnector::new(vec![0], vec![], 2);
+        supervisor(3, halt_state.clone()).run(&mut connector).await;
+
+        // Restarted once, from where the panicked run got to
+        assert_eq!(connector.runs, vec![100, 110]);
+        assert_eq!(connector.cursor, 120);
+        assert_eq!(halt_state.failure(), None);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_consecutive_restarts() {
+        let halt_state = HaltState::default();
+        // Progress resets the count, so only the failures in a row count
+        let mut connector = FakeConnector::new(vec![], vec![1, 3, 4], 10);
+        supervisor(2, halt_state.clone()).run(&mut connector).await;
+
+        assert_eq!(connector.runs, vec![100, 110, 110, 120, 120]);
+        assert_eq!(
+            halt_state.failure(),
+            Some(SubscribeError::EmptyRpcUrl.to_string())
+        );
+    }
+}
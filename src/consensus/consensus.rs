@@ -1,12 +1,18 @@
 use crate::consensus::malachite::network_connector::MalachiteNetworkEvent;
+use crate::core::types::{FixedProposer, ProposerSelector, RoundRobinProposer};
 use crate::mempool::mempool::MempoolRequest;
-use crate::proto;
+use crate::proto::{self, FarcasterNetwork, MessageType};
+use crate::storage::store::engine::BlockLimits;
 pub use informalsystems_malachitebft_core_consensus::Params as ConsensusParams;
 pub use informalsystems_malachitebft_core_consensus::State as ConsensusState;
 use libp2p::identity::ed25519::{Keypair, SecretKey};
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 use std::time::Duration;
 
+// Room for a link compact state message, the largest a message can be
+const MIN_BLOCK_BYTES: u64 = 128 * 1024;
+
 #[derive(Clone, Debug)]
 pub enum MalachiteEventShard {
     None,
@@ -30,6 +36,80 @@ pub struct ValidatorSetConfig {
     pub shard_ids: Vec<u32>,
 }
 
+/// How the validators on a shard pick the proposer for each round
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "strategy", rename_all = "snake_case")]
+pub enum ProposerSelection {
+    #[default]
+    RoundRobin,
+    // Devnet only, the validator at this index in the set always proposes
+    Fixed {
+        validator_index: usize,
+    },
+}
+
+impl ProposerSelection {
+    pub fn selector(&self) -> Arc<dyn ProposerSelector> {
+        match self {
+            ProposerSelection::RoundRobin => Arc::new(RoundRobinProposer),
+            ProposerSelection::Fixed { validator_index } => Arc::new(FixedProposer {
+                validator_index: *validator_index,
+            }),
+        }
+    }
+}
+
+/// What each validator's vote weighs when counting a quorum. Every validator on the network needs
+/// the same source, the weights decide which blocks commit.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub enum VotingPowerSource {
+    #[default]
+    Equal,
+    // The validators' stakes from the stake registry's onchain events, as of the end of the
+    // previous epoch of epoch_length heights
+    Stake {
+        epoch_length: u64,
+    },
+}
+
+impl VotingPowerSource {
+    pub fn stake_epoch_length(&self) -> Option<u64> {
+        match self {
+            VotingPowerSource::Equal => None,
+            VotingPowerSource::Stake { epoch_length } => Some(*epoch_length),
+        }
+    }
+}
+
+/// Devnet only, messages of the type are removed once they're older than the ttl, whatever the
+/// fid's storage. Every validator on the network needs the same ttls, they change the shard root.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MessageTtl {
+    // Named as in the protobuf, e.g. MESSAGE_TYPE_REACTION_ADD
+    pub message_type: String,
+    #[serde(with = "humantime_serde")]
+    pub ttl: Duration,
+}
+
+impl MessageTtl {
+    // Only the add and remove types of the stores that are pruned oldest first can expire
+    pub fn parsed_type(&self) -> Option<MessageType> {
+        match MessageType::from_str_name(&self.message_type)? {
+            message_type @ (MessageType::CastAdd
+            | MessageType::CastRemove
+            | MessageType::ReactionAdd
+            | MessageType::ReactionRemove
+            | MessageType::LinkAdd
+            | MessageType::LinkRemove
+            | MessageType::VerificationAddEthAddress
+            | MessageType::VerificationRemove
+            | MessageType::UserDataAdd) => Some(message_type),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub private_key: String,
@@ -46,14 +126,51 @@ pub struct Config {
     pub precommit_time: Duration, // Timeout for each propose/prevote/precommit step
     #[serde(with = "humantime_serde")]
     pub block_time: Duration,
+    // Heights after an empty shard chunk start idle_block_time apart instead of block_time, so a
+    // quiet network commits far fewer empty blocks. The block shard waits for the shards' chunks.
+    // 0 keeps the usual block time, not allowed on mainnet.
+    #[serde(default, with = "humantime_serde")]
+    pub idle_block_time: Duration,
 
     pub max_messages_per_block: u32,
+    // Further caps on the chunks a proposer builds, 0 leaves them at the network's limits. The
+    // bytes are the encoded size of the chunk's messages. Validators check proposals against the
+    // network's limits, not these.
+    #[serde(default)]
+    pub max_transactions_per_block: u32,
+    #[serde(default)]
+    pub max_block_bytes: u64,
     validator_sets: Option<Vec<ValidatorSetConfig>>,
     validator_addresses: Option<Vec<String>>, // Deprecated
 
     // Number of seconds to wait before kicking off start height
     pub consensus_start_delay: u32,
     pub sync_request_timeout: Duration,
+
+    #[serde(default)]
+    pub proposer_selection: ProposerSelection,
+    #[serde(default)]
+    pub message_ttls: Vec<MessageTtl>,
+    // Messages timestamped more than this many block times before the block they'd be merged in
+    // are rejected. 0 accepts messages of any age, not allowed on mainnet. Every validator on the
+    // network needs the same value, it changes the shard root. The age is measured from the
+    // chunk's timestamp, which validators keep within MAX_TIMESTAMP_DRIFT of their clocks.
+    #[serde(default)]
+    pub max_message_age_blocks: u32,
+    #[serde(default)]
+    pub voting_power: VotingPowerSource,
+    // How many shards a read node applies synced blocks for at the same time, 0 for all of them.
+    // A shard's own blocks are always applied one after the other.
+    #[serde(default)]
+    pub sync_apply_parallelism: u32,
+    // A validator doesn't propose or vote until this many peers are connected, so one that starts
+    // without a view of the network can't fork it. 0 starts right away.
+    #[serde(default)]
+    pub min_peers_for_consensus: u32,
+    // Shards of shard_ids the node syncs and serves like a read node would, without proposing or
+    // voting on them. It validates the other ones, and always the block shard.
+    #[serde(default)]
+    pub follower_shard_ids: Vec<u32>,
 }
 
 impl Config {
@@ -69,15 +186,134 @@ impl Config {
             num_shards: shard_ids.len() as u32,
             shard_ids,
             block_time: self.block_time,
+            idle_block_time: self.idle_block_time,
             propose_time: self.propose_time,
             prevote_time: self.prevote_time,
             precommit_time: self.precommit_time,
             step_delta: self.step_delta,
             max_messages_per_block: self.max_messages_per_block,
+            max_transactions_per_block: self.max_transactions_per_block,
+            max_block_bytes: self.max_block_bytes,
             validator_addresses: None,
             validator_sets: Some(validator_sets.clone()),
             consensus_start_delay: self.consensus_start_delay,
             sync_request_timeout: self.sync_request_timeout,
+            proposer_selection: self.proposer_selection.clone(),
+            message_ttls: self.message_ttls.clone(),
+            max_message_age_blocks: self.max_message_age_blocks,
+            voting_power: self.voting_power.clone(),
+            sync_apply_parallelism: self.sync_apply_parallelism,
+            min_peers_for_consensus: self.min_peers_for_consensus,
+            follower_shard_ids: self.follower_shard_ids.clone(),
+        }
+    }
+
+    pub fn validate(&self, network: FarcasterNetwork) -> Result<(), String> {
+        if let ProposerSelection::Fixed { .. } = self.proposer_selection {
+            if network != FarcasterNetwork::Devnet {
+                return Err(format!(
+                    "a fixed proposer is only allowed on devnet, not {}",
+                    network.as_str_name()
+                ));
+            }
+        }
+        if self.max_block_bytes != 0 && self.max_block_bytes < MIN_BLOCK_BYTES {
+            return Err(format!(
+                "max_block_bytes must be at least {}, so any message fits in a block",
+                MIN_BLOCK_BYTES
+            ));
+        }
+        if self.max_block_bytes > BlockLimits::NETWORK.max_bytes {
+            return Err(format!(
+                "max_block_bytes can be at most the network's {}",
+                BlockLimits::NETWORK.max_bytes
+            ));
+        }
+        if self.max_transactions_per_block > BlockLimits::NETWORK.max_transactions {
+            return Err(format!(
+                "max_transactions_per_block can be at most the network's {}",
+                BlockLimits::NETWORK.max_transactions
+            ));
+        }
+        if !self.idle_block_time.is_zero() {
+            if network == FarcasterNetwork::Mainnet {
+                return Err("idle_block_time is not allowed on mainnet".to_string());
+            }
+            if self.idle_block_time < self.block_time {
+                return Err("idle_block_time must be at least the block_time".to_string());
+            }
+        }
+        if !self.message_ttls.is_empty() && network != FarcasterNetwork::Devnet {
+            return Err(format!(
+                "message ttls are only allowed on devnet, not {}",
+                network.as_str_name()
+            ));
+        }
+        for (i, message_ttl) in self.message_ttls.iter().enumerate() {
+            if message_ttl.parsed_type().is_none() {
+                return Err(format!(
+                    "message type {} can't have a ttl",
+                    message_ttl.message_type
+                ));
+            }
+            if message_ttl.ttl.as_secs() == 0 {
+                return Err(format!(
+                    "ttl for {} must be at least a second",
+                    message_ttl.message_type
+                ));
+            }
+            if self.message_ttls[..i]
+                .iter()
+                .any(|other| other.message_type == message_ttl.message_type)
+            {
+                return Err(format!("duplicate ttl for {}", message_ttl.message_type));
+            }
+        }
+        if self.max_message_age_blocks != 0 && network == FarcasterNetwork::Mainnet {
+            return Err("max_message_age_blocks is not allowed on mainnet".to_string());
+        }
+        if self.voting_power.stake_epoch_length() == Some(0) {
+            return Err("voting_power epoch_length must be at least 1".to_string());
+        }
+        for shard_id in &self.follower_shard_ids {
+            if !self.shard_ids.contains(shard_id) {
+                return Err(format!(
+                    "follower shard {} isn't one of the node's shard_ids",
+                    shard_id
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn block_limits(&self) -> BlockLimits {
+        BlockLimits {
+            max_transactions: self.max_transactions_per_block,
+            max_bytes: self.max_block_bytes,
+        }
+    }
+
+    // The ttls by message type, only meaningful once the config is validated
+    pub fn message_ttls(&self) -> Vec<(MessageType, Duration)> {
+        self.message_ttls
+            .iter()
+            .filter_map(|message_ttl| Some((message_ttl.parsed_type()?, message_ttl.ttl)))
+            .collect()
+    }
+
+    // How old a message can be as of the block it's merged in, if there's a limit
+    pub fn max_message_age(&self) -> Option<Duration> {
+        if self.max_message_age_blocks == 0 {
+            return None;
+        }
+        Some(self.block_time.saturating_mul(self.max_message_age_blocks))
+    }
+
+    pub fn shard_role(&self, shard_id: u32) -> proto::ShardRole {
+        if self.follower_shard_ids.contains(&shard_id) {
+            proto::ShardRole::Follower
+        } else {
+            proto::ShardRole::Validator
         }
     }
 
@@ -111,11 +347,189 @@ impl Default for Config {
             precommit_time: Duration::from_millis(500),
             step_delta: Duration::from_millis(500),
             block_time: Duration::from_millis(1000),
+            idle_block_time: Duration::ZERO,
             max_messages_per_block: 1000,
+            max_transactions_per_block: 0,
+            max_block_bytes: 0,
             validator_addresses: None,
             validator_sets: None,
             consensus_start_delay: 2,
             sync_request_timeout: Duration::from_secs(2),
+            proposer_selection: ProposerSelection::default(),
+            message_ttls: vec![],
+            max_message_age_blocks: 0,
+            voting_power: VotingPowerSource::default(),
+            sync_apply_parallelism: 0,
+            min_peers_for_consensus: 0,
+            follower_shard_ids: vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::types::{SnapchainShard, SnapchainValidator, SnapchainValidatorSet};
+
+    #[test]
+    fn test_fixed_proposer() {
+        let validator_set = SnapchainValidatorSet::new(
+            (0..3)
+                .map(|_| {
+                    SnapchainValidator::new(
+                        SnapchainShard::new(1),
+                        Keypair::generate().public(),
+                        None,
+                        0,
+                    )
+                })
+                .collect(),
+        );
+
+        let selector = ProposerSelection::Fixed { validator_index: 1 }.selector();
+        for (height, round) in [(1, 0), (2, 0), (2, 1), (10, 4)] {
+            assert_eq!(
+                selector.select(&validator_set, height, round),
+                &validator_set.validators[1]
+            );
         }
+
+        // Round robin moves on to the next validator every height and round
+        let selector = ProposerSelection::RoundRobin.selector();
+        assert_eq!(
+            selector.select(&validator_set, 1, 0),
+            &validator_set.validators[0]
+        );
+        assert_eq!(
+            selector.select(&validator_set, 1, 1),
+            &validator_set.validators[1]
+        );
+        assert_eq!(
+            selector.select(&validator_set, 3, 0),
+            &validator_set.validators[2]
+        );
+    }
+
+    #[test]
+    fn test_fixed_proposer_is_devnet_only() {
+        let config = Config {
+            proposer_selection: ProposerSelection::Fixed { validator_index: 0 },
+            ..Default::default()
+        };
+        assert!(config.validate(FarcasterNetwork::Devnet).is_ok());
+        assert!(config.validate(FarcasterNetwork::Mainnet).is_err());
+        assert!(config.validate(FarcasterNetwork::Testnet).is_err());
+        assert!(Config::default()
+            .validate(FarcasterNetwork::Mainnet)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_message_ttls_are_devnet_only() {
+        let message_ttl = |message_type: &str, secs| MessageTtl {
+            message_type: message_type.to_string(),
+            ttl: Duration::from_secs(secs),
+        };
+        let config = Config {
+            message_ttls: vec![message_ttl("MESSAGE_TYPE_REACTION_ADD", 60)],
+            ..Default::default()
+        };
+        assert!(config.validate(FarcasterNetwork::Devnet).is_ok());
+        assert!(config.validate(FarcasterNetwork::Mainnet).is_err());
+        assert_eq!(
+            config.message_ttls(),
+            vec![(MessageType::ReactionAdd, Duration::from_secs(60))]
+        );
+
+        for message_ttls in [
+            vec![message_ttl("MESSAGE_TYPE_LINK_COMPACT_STATE", 60)],
+            vec![message_ttl("REACTION_ADD", 60)],
+            vec![message_ttl("MESSAGE_TYPE_CAST_ADD", 0)],
+            vec![
+                message_ttl("MESSAGE_TYPE_CAST_ADD", 60),
+                message_ttl("MESSAGE_TYPE_CAST_ADD", 120),
+            ],
+        ] {
+            let config = Config {
+                message_ttls,
+                ..Default::default()
+            };
+            assert!(config.validate(FarcasterNetwork::Devnet).is_err());
+        }
+    }
+
+    #[test]
+    fn test_max_message_age_blocks() {
+        let config = Config {
+            block_time: Duration::from_millis(500),
+            max_message_age_blocks: 20,
+            ..Default::default()
+        };
+        assert_eq!(config.max_message_age(), Some(Duration::from_secs(10)));
+        assert!(config.validate(FarcasterNetwork::Testnet).is_ok());
+        assert!(config.validate(FarcasterNetwork::Mainnet).is_err());
+        assert_eq!(Config::default().max_message_age(), None);
+    }
+
+    #[test]
+    fn test_max_block_bytes_fits_any_message() {
+        let config = |max_block_bytes| Config {
+            max_block_bytes,
+            ..Default::default()
+        };
+        assert!(config(0).validate(FarcasterNetwork::Mainnet).is_ok());
+        assert!(config(MIN_BLOCK_BYTES)
+            .validate(FarcasterNetwork::Mainnet)
+            .is_ok());
+        assert!(config(4096).validate(FarcasterNetwork::Mainnet).is_err());
+        assert!(config(BlockLimits::NETWORK.max_bytes + 1)
+            .validate(FarcasterNetwork::Mainnet)
+            .is_err());
+
+        let config = Config {
+            max_transactions_per_block: BlockLimits::NETWORK.max_transactions + 1,
+            ..Default::default()
+        };
+        assert!(config.validate(FarcasterNetwork::Mainnet).is_err());
+    }
+
+    #[test]
+    fn test_idle_block_time() {
+        let config = |idle_block_time| Config {
+            idle_block_time,
+            ..Default::default()
+        };
+        assert!(config(Duration::from_secs(10))
+            .validate(FarcasterNetwork::Testnet)
+            .is_ok());
+        assert!(config(Duration::from_secs(10))
+            .validate(FarcasterNetwork::Mainnet)
+            .is_err());
+        // Shorter than the block time
+        assert!(config(Duration::from_millis(500))
+            .validate(FarcasterNetwork::Testnet)
+            .is_err());
+        assert!(config(Duration::ZERO)
+            .validate(FarcasterNetwork::Mainnet)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_follower_shard_ids() {
+        let config = |follower_shard_ids| Config {
+            shard_ids: vec![1, 2],
+            num_shards: 2,
+            follower_shard_ids,
+            ..Default::default()
+        };
+        let mixed = config(vec![2]);
+        assert!(mixed.validate(FarcasterNetwork::Mainnet).is_ok());
+        assert_eq!(mixed.shard_role(0), proto::ShardRole::Validator);
+        assert_eq!(mixed.shard_role(1), proto::ShardRole::Validator);
+        assert_eq!(mixed.shard_role(2), proto::ShardRole::Follower);
+
+        // Only the node's own shards can be followed, which leaves out the block shard
+        assert!(config(vec![3]).validate(FarcasterNetwork::Mainnet).is_err());
+        assert!(config(vec![0]).validate(FarcasterNetwork::Mainnet).is_err());
     }
 }
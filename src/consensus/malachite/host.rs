@@ -1,8 +1,9 @@
 //! Implementation of a host actor for bridiging consensus and the application via a set of channels.
 
 use crate::consensus::validator::{ProposalSource, ShardValidator};
-use crate::core::types::SnapchainValidatorContext;
+use crate::core::types::{Height, SnapchainValidatorContext, SnapchainValidatorSet};
 use crate::network::gossip::GossipEvent;
+use crate::network::sync_progress::SyncProgress;
 use crate::proto::{self, decided_value, full_proposal, Block, Commits, FullProposal, ShardChunk};
 use crate::utils::statsd_wrapper::StatsdClientWrapper;
 use bytes::Bytes;
@@ -15,9 +16,14 @@ use informalsystems_malachitebft_engine::util::streaming::{
 use informalsystems_malachitebft_sync::RawDecidedValue;
 use prost::Message;
 use ractor::{async_trait, Actor, ActorProcessingErr, ActorRef, SpawnErr};
+use std::time::Duration;
 use tokio::sync::mpsc;
 use tracing::{error, info, warn};
 
+const FROZEN_SHARD_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const SHARD_CHUNKS_POLL_INTERVAL: Duration = Duration::from_millis(50);
+const CONNECTED_PEERS_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
 /// Actor for bridging consensus and the application via a set of channels.
 ///
 /// This actor is responsible for forwarding messages from the
@@ -31,6 +37,10 @@ pub struct HostState {
     pub gossip_tx: mpsc::Sender<GossipEvent<SnapchainValidatorContext>>,
     pub statsd: StatsdClientWrapper,
     pub consensus_block_time: u64, // in ms
+    // Zero unless heights are paced on a quiet network, see the consensus config
+    pub idle_block_time: Duration,
+    pub min_peers_for_consensus: u32,
+    pub sync_progress: SyncProgress,
 }
 
 impl Host {
@@ -44,6 +54,24 @@ impl Host {
         let (actor_ref, _) = Actor::spawn(None, Self::new(), state).await?;
         Ok(actor_ref)
     }
+
+    // The validator set of the height, None when its stakes can't be read. The height isn't
+    // started then, rather than weighing the votes differently from the other validators.
+    fn validator_set(state: &HostState, height: Height) -> Option<SnapchainValidatorSet> {
+        match state.shard_validator.get_validator_set(height.as_u64()) {
+            Ok(validator_set) => Some(validator_set),
+            Err(err) => {
+                error!(
+                    height = height.to_string(),
+                    "Unable to get the validator set, stopping the height: {}", err
+                );
+                state
+                    .statsd
+                    .count_with_shard(height.shard_index, "host.validator_set_errors", 1);
+                None
+            }
+        }
+    }
 }
 
 impl Host {
@@ -58,12 +86,33 @@ impl Host {
                 // Start height
                 state.shard_validator.start(); // Call each time?
                 let height = state.shard_validator.get_current_height().increment();
-                let validator_set = state.shard_validator.get_validator_set(height.as_u64());
+                let Some(validator_set) = Self::validator_set(state, height) else {
+                    return Ok(());
+                };
                 // Wait a few seconds before starting
                 tokio::time::sleep(tokio::time::Duration::from_secs(
                     state.consensus_start_delay as u64,
                 ))
                 .await;
+                // Nothing is proposed or voted on until the node sees enough of the network
+                let min_peers = state.min_peers_for_consensus as usize;
+                if state.sync_progress.connected_peers() < min_peers {
+                    info!(
+                        height = height.to_string(),
+                        connected_peers = state.sync_progress.connected_peers(),
+                        min_peers,
+                        "Waiting for peers before joining consensus"
+                    );
+                    state
+                        .sync_progress
+                        .set_waiting_for_peers(height.shard_index, true);
+                    while state.sync_progress.connected_peers() < min_peers {
+                        tokio::time::sleep(CONNECTED_PEERS_POLL_INTERVAL).await;
+                    }
+                    state
+                        .sync_progress
+                        .set_waiting_for_peers(height.shard_index, false);
+                }
                 info!(
                     height = height.to_string(),
                     validators = validator_set.validators.len(),
@@ -205,7 +254,10 @@ impl Host {
             }
 
             HostMsg::GetValidatorSet { height, reply_to } => {
-                reply_to.send(state.shard_validator.get_validator_set(height.as_u64()))?;
+                // Left unanswered when the set can't be read, so the height doesn't go on
+                if let Some(validator_set) = Self::validator_set(state, height) {
+                    reply_to.send(validator_set)?;
+                }
             }
 
             HostMsg::Decided {
@@ -224,9 +276,9 @@ impl Host {
                         hex::encode(certificate.value_id.hash),
                         certificate.height
                     );
-                    let validator_set = state
-                        .shard_validator
-                        .get_validator_set(certificate.height.as_u64());
+                    let Some(validator_set) = Self::validator_set(state, certificate.height) else {
+                        return Ok(());
+                    };
                     consensus_ref
                         .cast(ConsensusMsg::StartHeight(certificate.height, validator_set))?;
                     return Ok(());
@@ -245,6 +297,15 @@ impl Host {
                     None
                 };
 
+                // Every validator of the shard decides the same chunk, so they all pace the next
+                // height the same way
+                let idle = !state.idle_block_time.is_zero()
+                    && matches!(
+                        &decided_value,
+                        Some(decided_value::Value::Shard(shard_chunk))
+                            if shard_chunk.transactions.is_empty()
+                    );
+
                 // Only publish decided values if you're the proposer to reduce network traffic
                 if proposed_value.proposer_address() == state.shard_validator.get_address() {
                     state
@@ -273,15 +334,59 @@ impl Host {
                     elapsed.as_millis() as u64,
                 );
                 // Start next height, while trying to maintain the block time
-                let delay = state
-                    .shard_validator
-                    .next_height_delay(state.consensus_block_time);
+                let target_block_time = if idle {
+                    state.statsd.count_with_shard(
+                        certificate.height.shard_index,
+                        "host.idle_heights",
+                        1,
+                    );
+                    state.idle_block_time.as_millis() as u64
+                } else {
+                    state.consensus_block_time
+                };
+                let delay = state.shard_validator.next_height_delay(target_block_time);
                 let next_height = certificate.height.increment();
-                let validator_set = state
-                    .shard_validator
-                    .get_validator_set(next_height.as_u64());
+                let Some(validator_set) = Self::validator_set(state, next_height) else {
+                    return Ok(());
+                };
+                let shard_freeze = state.shard_validator.shard_freeze();
+                // Shards pace themselves on a quiet network, so blocks wait for their chunks
+                // rather than being proposed without them. Never longer than the idle block time,
+                // so a stalled shard can't stall the blocks any more than it otherwise would.
+                let shard_chunks =
+                    if state.idle_block_time.is_zero() || state.shard_validator.is_syncing() {
+                        None
+                    } else {
+                        state.shard_validator.committed_shard_chunks()
+                    };
+                let idle_block_time = state.idle_block_time;
                 tokio::spawn(async move {
                     tokio::time::sleep(delay).await;
+                    if let Some(shard_chunks) = shard_chunks {
+                        let deadline = tokio::time::Instant::now() + idle_block_time;
+                        while !shard_chunks.all_committed(next_height.block_number)
+                            && tokio::time::Instant::now() < deadline
+                        {
+                            tokio::time::sleep(SHARD_CHUNKS_POLL_INTERVAL).await;
+                        }
+                    }
+                    // Stop participating in consensus while the shard is frozen, and pick up at
+                    // the next height once it's unfrozen
+                    if let Some(shard_freeze) = shard_freeze {
+                        if shard_freeze.is_frozen() {
+                            info!(
+                                next_height = next_height.to_string(),
+                                "Shard frozen, pausing consensus"
+                            );
+                            while shard_freeze.is_frozen() {
+                                tokio::time::sleep(FROZEN_SHARD_POLL_INTERVAL).await;
+                            }
+                            info!(
+                                next_height = next_height.to_string(),
+                                "Shard unfrozen, resuming consensus"
+                            );
+                        }
+                    }
                     if let Err(err) =
                         consensus_ref.cast(ConsensusMsg::StartHeight(next_height, validator_set))
                     {
@@ -5,6 +5,7 @@ pub mod read_sync;
 pub mod snapchain_codec;
 pub mod spawn;
 pub mod spawn_read_node;
+pub mod wal;
 
 #[cfg(test)]
 mod read_node_actors_test;
@@ -4,6 +4,7 @@ use tokio::sync::mpsc;
 
 use crate::consensus::consensus::SystemMessage;
 use crate::consensus::read_validator::ReadValidator;
+use crate::consensus::sync_apply::SyncApplyLimiter;
 use crate::core::types::SnapchainValidatorContext;
 use crate::proto::{self, Height};
 use informalsystems_malachitebft_sync::RawDecidedValue;
@@ -45,6 +46,7 @@ pub struct ReadHost {}
 pub struct ReadHostState {
     pub validator: ReadValidator,
     pub system_tx: mpsc::Sender<SystemMessage>,
+    pub apply_limiter: SyncApplyLimiter,
 }
 
 impl ReadHost {
@@ -77,7 +79,11 @@ impl ReadHost {
             }
 
             ReadHostMsg::ProcessDecidedValue { value, sync } => {
-                let num_values_processed = state.validator.process_decided_value(value);
+                let validator = &mut state.validator;
+                let num_values_processed = state
+                    .apply_limiter
+                    .run(|| validator.process_decided_value(value))
+                    .await;
                 if num_values_processed > 0 {
                     sync.cast(read_sync::Msg::Decided(state.validator.last_height))?
                 }
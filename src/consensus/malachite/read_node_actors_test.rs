@@ -7,6 +7,7 @@ mod tests {
     use crate::consensus::malachite::network_connector::MalachiteNetworkEvent;
     use crate::consensus::malachite::spawn_read_node::MalachiteReadNodeActors;
     use crate::consensus::read_validator::Engine;
+    use crate::consensus::sync_apply::SyncApplyLimiter;
     use crate::core::types::{Address, SnapchainValidatorContext};
     use crate::network::gossip::GossipEvent;
     use crate::proto::{self, Height, ShardChunk, StatusMessage};
@@ -70,6 +71,8 @@ mod tests {
             shard_id,
             test_helper::statsd_client(),
             config,
+            SyncApplyLimiter::unlimited(),
+            None,
         )
         .await
         .unwrap();
@@ -16,9 +16,11 @@ use crate::consensus::malachite::network_connector::{
     NetworkConnectorArgs,
 };
 use crate::consensus::malachite::snapchain_codec::SnapchainCodec;
+use crate::consensus::malachite::wal;
 use crate::consensus::validator::ShardValidator;
 use crate::core::types::{ShardId, SnapchainValidatorContext};
 use crate::network::gossip::GossipEvent;
+use crate::network::sync_progress::SyncProgress;
 use crate::utils::statsd_wrapper::StatsdClientWrapper;
 use informalsystems_malachitebft_engine::sync::{Params as SyncParams, Sync, SyncRef};
 use informalsystems_malachitebft_engine::util::events::TxEvent;
@@ -47,10 +49,9 @@ pub async fn spawn_wal_actor(
     registry: &SharedRegistry,
     span: Span,
 ) -> Result<WalRef<SnapchainValidatorContext>, ractor::SpawnErr> {
-    let wal_dir = home_dir.join("wal");
-    std::fs::create_dir_all(&wal_dir).unwrap();
+    std::fs::create_dir_all(wal::wal_dir(home_dir)).unwrap();
 
-    let wal_file = wal_dir.join("consensus.wal");
+    let wal_file = wal::wal_file(home_dir);
     let codec = SnapchainCodec;
 
     Wal::spawn(&ctx, codec, wal_file, registry.clone(), span)
@@ -64,6 +65,9 @@ pub async fn spawn_host(
     gossip_tx: mpsc::Sender<GossipEvent<SnapchainValidatorContext>>,
     consensus_start_delay: u32,
     consensus_block_time: u64,
+    idle_block_time: Duration,
+    min_peers_for_consensus: u32,
+    sync_progress: SyncProgress,
     statsd: StatsdClientWrapper,
 ) -> Result<HostRef<SnapchainValidatorContext>, ractor::SpawnErr> {
     let state = HostState {
@@ -71,6 +75,9 @@ pub async fn spawn_host(
         shard_validator,
         consensus_start_delay,
         consensus_block_time,
+        idle_block_time,
+        min_peers_for_consensus,
+        sync_progress,
         gossip_tx,
         statsd,
     };
@@ -168,12 +175,15 @@ impl MalachiteConsensusActors {
         local_peer_id: PeerId,
         db_dir: String,
         gossip_tx: mpsc::Sender<GossipEvent<SnapchainValidatorContext>>,
+        sync_progress: SyncProgress,
         registry: &SharedRegistry,
         config: Config,
         statsd: StatsdClientWrapper,
     ) -> Result<Self, ractor::SpawnErr> {
         let current_height = shard_validator.get_current_height();
-        let validator_set = shard_validator.get_validator_set(current_height.as_u64());
+        let validator_set = shard_validator
+            .get_validator_set(current_height.as_u64())
+            .map_err(|err| ractor::SpawnErr::StartupFailed(err.to_string().into()))?;
         let address = shard_validator.get_address();
         let shard_id = shard_validator.shard_id.shard_id();
         let name = if shard_id == 0 {
@@ -185,7 +195,7 @@ impl MalachiteConsensusActors {
 
         let network_actor = spawn_network_actor(gossip_tx.clone(), local_peer_id).await?;
         let wal_actor = spawn_wal_actor(
-            Path::new(format!("{}/shard-{}/wal", db_dir, shard_id).as_str()),
+            &wal::consensus_home_dir(&db_dir, shard_id),
             ctx.clone(),
             registry,
             span.clone(),
@@ -197,6 +207,9 @@ impl MalachiteConsensusActors {
             gossip_tx.clone(),
             config.consensus_start_delay,
             config.block_time.as_millis() as u64,
+            config.idle_block_time,
+            config.min_peers_for_consensus,
+            sync_progress,
             statsd,
         )
         .await?;
@@ -255,4 +268,14 @@ impl MalachiteConsensusActors {
         self.wal_actor.stop(None);
         self.sync_actor.stop(None);
     }
+
+    // Stops consensus first so nothing new is decided, then waits for the host to finish
+    // committing the height it's on
+    pub async fn stop_and_wait(&self) {
+        let _ = self.consensus_actor.stop_and_wait(None, None).await;
+        let _ = self.host_actor.stop_and_wait(None, None).await;
+        self.network_actor.stop(None);
+        self.wal_actor.stop(None);
+        self.sync_actor.stop(None);
+    }
 }
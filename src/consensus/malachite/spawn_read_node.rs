@@ -9,14 +9,15 @@ use crate::consensus::malachite::network_connector::{
     MalachiteNetworkActorMsg, MalachiteNetworkEvent,
 };
 use crate::consensus::read_validator::{self, Engine};
-use crate::consensus::validator::{StoredValidatorSet, StoredValidatorSets};
+use crate::consensus::sync_apply::SyncApplyLimiter;
+use crate::consensus::validator::StoredValidatorSets;
 use crate::core::types::{ShardId, SnapchainValidatorContext};
 use crate::network::gossip::GossipEvent;
-use crate::proto::{self, Height};
+use crate::proto::{self, Height, ShardChunk};
 use crate::utils::statsd_wrapper::StatsdClientWrapper;
 use informalsystems_malachitebft_metrics::SharedRegistry;
 use libp2p::PeerId;
-use tokio::sync::mpsc;
+use tokio::sync::{broadcast, mpsc};
 
 use super::read_host::{ReadHost, ReadHostMsg, ReadHostRef, ReadHostState};
 use super::read_sync::{ReadParams, ReadSync, ReadSyncRef};
@@ -28,12 +29,18 @@ pub async fn spawn_read_host(
     engine: Engine,
     system_tx: mpsc::Sender<SystemMessage>,
     config: Config,
+    apply_limiter: SyncApplyLimiter,
+    shard_decision_tx: Option<broadcast::Sender<ShardChunk>>,
 ) -> Result<ReadHostRef, ractor::SpawnErr> {
-    let validator_set_config = config.get_validator_set_config(shard_id);
-    let validator_sets = validator_set_config
-        .iter()
-        .map(|config| StoredValidatorSet::new(ShardId::new(shard_id), &config))
-        .collect();
+    let stakes = match &engine {
+        Engine::ShardEngine(engine) => engine.validator_stakes(),
+        Engine::BlockEngine(_) => None,
+    };
+    let validator_sets = StoredValidatorSets::from_config(
+        ShardId::new(shard_id),
+        &config.get_validator_set_config(shard_id),
+    )
+    .with_stakes(stakes);
     let state = ReadHostState {
         validator: read_validator::ReadValidator {
             shard_id,
@@ -44,10 +51,12 @@ pub async fn spawn_read_host(
             },
             max_num_buffered_blocks: 100,
             buffered_blocks: BTreeMap::new(),
-            validator_sets: StoredValidatorSets::new(shard_id, validator_sets),
+            validator_sets,
             statsd_client,
+            shard_decision_tx,
         },
         system_tx,
+        apply_limiter,
     };
     let actor_ref = ReadHost::spawn(state).await?;
     Ok(actor_ref)
@@ -91,6 +100,8 @@ impl MalachiteReadNodeActors {
         shard_id: u32,
         statsd_client: StatsdClientWrapper,
         config: Config,
+        apply_limiter: SyncApplyLimiter,
+        shard_decision_tx: Option<broadcast::Sender<ShardChunk>>,
     ) -> Result<Self, ractor::SpawnErr> {
         let name = if shard_id == 0 {
             format!("Block")
@@ -104,8 +115,16 @@ impl MalachiteReadNodeActors {
             ..ValueSyncConfig::default()
         };
         let network_actor = spawn_network_actor(gossip_tx.clone(), local_peer_id).await?;
-        let host_actor =
-            spawn_read_host(shard_id, statsd_client, engine, system_tx, config).await?;
+        let host_actor = spawn_read_host(
+            shard_id,
+            statsd_client,
+            engine,
+            system_tx,
+            config,
+            apply_limiter,
+            shard_decision_tx,
+        )
+        .await?;
         let sync_actor = spawn_read_sync_actor(
             ctx.clone(),
             network_actor.clone(),
@@ -152,4 +171,11 @@ impl MalachiteReadNodeActors {
         self.network_actor.stop(None);
         self.sync_actor.stop(None);
     }
+
+    // Waits for the host to finish committing the decided value it's on
+    pub async fn stop_and_wait(&self) {
+        self.sync_actor.stop(None);
+        let _ = self.host_actor.stop_and_wait(None, None).await;
+        self.network_actor.stop(None);
+    }
 }
@@ -0,0 +1,62 @@
+//! Helpers for locating and reading the consensus write-ahead log outside of the consensus actors.
+
+use std::error::Error;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+use informalsystems_malachitebft_core_types::Context;
+use informalsystems_malachitebft_engine::wal::{WalCodec, WalEntry};
+use informalsystems_malachitebft_wal as wal;
+
+pub fn consensus_home_dir(db_dir: &str, shard_id: u32) -> PathBuf {
+    PathBuf::from(format!("{}/shard-{}/wal", db_dir, shard_id))
+}
+
+pub fn wal_dir(home_dir: &Path) -> PathBuf {
+    home_dir.join("wal")
+}
+
+pub fn wal_file(home_dir: &Path) -> PathBuf {
+    wal_dir(home_dir).join("consensus.wal")
+}
+
+pub fn log_entries<'a, Ctx, Codec>(
+    log: &'a mut wal::Log,
+    codec: &'a Codec,
+) -> Result<WalIter<'a, Ctx, Codec>, Box<dyn Error>>
+where
+    Ctx: Context,
+    Codec: WalCodec<Ctx>,
+{
+    Ok(WalIter {
+        iter: log.iter()?,
+        codec,
+        _marker: PhantomData,
+    })
+}
+
+pub struct WalIter<'a, Ctx, Codec> {
+    iter: wal::LogIter<'a>,
+    codec: &'a Codec,
+    _marker: PhantomData<Ctx>,
+}
+
+impl<Ctx, Codec> Iterator for WalIter<'_, Ctx, Codec>
+where
+    Ctx: Context,
+    Codec: WalCodec<Ctx>,
+{
+    type Item = Result<WalEntry<Ctx>, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.iter.next()?;
+        match entry {
+            Ok(bytes) => {
+                let buf = std::io::Cursor::new(bytes);
+                let entry = WalEntry::decode(self.codec, buf);
+                Some(entry.map_err(Into::into))
+            }
+            Err(e) => Some(Err(e.into())),
+        }
+    }
+}
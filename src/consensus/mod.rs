@@ -2,9 +2,12 @@ pub mod consensus;
 pub mod malachite;
 pub mod proposer;
 pub mod read_validator;
+pub mod sync_apply;
 pub mod validator;
 
 #[cfg(test)]
 mod proposed_values_test;
 #[cfg(test)]
+mod proposer_test;
+#[cfg(test)]
 mod read_validator_test;
@@ -1,12 +1,11 @@
-use crate::core::types::{
-    proto, Address, Height, ShardHash, ShardId, SnapchainShard, FARCASTER_EPOCH,
-};
+use crate::core::custom_network::farcaster_epoch;
+use crate::core::types::{proto, Address, Height, ShardHash, ShardId, SnapchainShard};
 use crate::proto::{
     full_proposal, Block, BlockHeader, Commits, FullProposal, ShardChunk, ShardChunkWitness,
     ShardHeader, ShardWitness,
 };
 use crate::storage::store::engine::{BlockEngine, ShardEngine, ShardStateChange};
-use crate::storage::store::stores::Stores;
+use crate::storage::store::stores::{ShardFreeze, Stores};
 use crate::storage::store::BlockStorageError;
 use crate::utils::statsd_wrapper::StatsdClientWrapper;
 use informalsystems_malachitebft_core_types::{Round, Validity};
@@ -23,12 +22,16 @@ pub const PROTOCOL_VERSION: u32 = 1;
 pub const GENESIS_MESSAGE: &str =
     "It occurs to me that our survival may depend upon our talking to one another.";
 
+// How far ahead of a validator's clock a proposed chunk's timestamp can be, in seconds. Replay
+// expires and rejects state by the chunk's timestamp, so it's bounded by the validators' clocks.
+pub const MAX_TIMESTAMP_DRIFT: u64 = 30;
+
 pub fn current_time() -> u64 {
     std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
         .unwrap()
         .as_secs()
-        - (FARCASTER_EPOCH / 1000)
+        - (farcaster_epoch() / 1000)
 }
 
 #[allow(async_fn_in_trait)] // TODO
@@ -147,6 +150,17 @@ impl ShardProposer {
     pub fn start_round(&mut self, height: Height, round: Round) {
         self.engine.start_round(height, round);
     }
+
+    pub fn shard_freeze(&self) -> ShardFreeze {
+        self.engine.get_shard_freeze()
+    }
+
+    fn chunk_timestamp(chunk: &Option<ShardChunk>) -> u64 {
+        chunk
+            .as_ref()
+            .and_then(|chunk| chunk.header.as_ref())
+            .map_or(0, |header| header.timestamp)
+    }
 }
 
 impl Proposer for ShardProposer {
@@ -162,17 +176,21 @@ impl Proposer for ShardProposer {
         let messages = self.engine.pull_messages(mempool_timeout).await.unwrap(); // TODO: don't unwrap
 
         let previous_chunk = self.engine.get_last_shard_chunk();
-        let parent_hash = match previous_chunk {
+        let parent_hash = match &previous_chunk {
             Some(chunk) => chunk.hash.clone(),
             None => vec![0, 32],
         };
 
-        let state_change = self
-            .engine
-            .propose_state_change(self.shard_id.shard_id(), messages);
+        // The chunk is built with the same timestamp it's proposed with, so validators replaying it
+        // don't depend on their own clocks. It never goes back past the parent's, which validators
+        // would reject.
+        let timestamp = current_time().max(Self::chunk_timestamp(&previous_chunk));
+        let state_change =
+            self.engine
+                .propose_state_change_at(self.shard_id.shard_id(), messages, timestamp);
         let shard_header = ShardHeader {
             parent_hash,
-            timestamp: current_time(),
+            timestamp,
             height: Some(height.clone()),
             shard_root: state_change.new_state_root.clone(),
         };
@@ -223,11 +241,26 @@ impl Proposer for ShardProposer {
                 return Validity::Invalid;
             }
 
+            let parent_timestamp = Self::chunk_timestamp(&self.engine.get_last_shard_chunk());
+            if header.timestamp < parent_timestamp
+                || header.timestamp > current_time() + MAX_TIMESTAMP_DRIFT
+            {
+                warn!(
+                    shard = height.shard_index,
+                    height = height.block_number,
+                    timestamp = header.timestamp,
+                    parent_timestamp,
+                    "Invalid chunk timestamp"
+                );
+                return Validity::Invalid;
+            }
+
             let state = ShardStateChange {
                 shard_id: height.shard_index,
                 new_state_root: header.shard_root.clone(),
                 transactions: chunk.transactions.clone(),
                 events: vec![],
+                timestamp: header.timestamp,
             };
             return if self.engine.validate_state_change(&state) {
                 Validity::Valid
@@ -316,6 +349,24 @@ pub enum BlockProposerError {
     BlockStorageError(#[from] BlockStorageError),
 }
 
+/// The shard chunks a block witnesses, as committed by the shards on this node
+#[derive(Clone)]
+pub struct CommittedShardChunks {
+    shard_stores: HashMap<u32, Stores>,
+}
+
+impl CommittedShardChunks {
+    // Whether every shard has committed its chunk at the height, so a block can witness them
+    pub fn all_committed(&self, block_number: u64) -> bool {
+        self.shard_stores.values().all(|stores| {
+            matches!(
+                stores.shard_store.get_chunk_by_height(block_number),
+                Ok(Some(_))
+            )
+        })
+    }
+}
+
 pub struct BlockProposer {
     #[allow(dead_code)] // TODO
     shard_id: SnapchainShard,
@@ -353,6 +404,12 @@ impl BlockProposer {
         }
     }
 
+    pub fn committed_shard_chunks(&self) -> CommittedShardChunks {
+        CommittedShardChunks {
+            shard_stores: self.shard_stores.clone(),
+        }
+    }
+
     async fn collect_confirmed_shard_witnesses(
         &mut self,
         height: Height,
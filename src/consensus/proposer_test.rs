@@ -0,0 +1,84 @@
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use informalsystems_malachitebft_core_types::{Round, Validity};
+    use tokio::sync::broadcast;
+
+    use crate::{
+        consensus::proposer::{current_time, Proposer, ShardProposer, MAX_TIMESTAMP_DRIFT},
+        core::types::{Address, ShardId, SnapchainShard},
+        proto::{self, FullProposal},
+        storage::store::test_helper::{self, commit_event, default_storage_event, FID_FOR_TEST},
+    };
+
+    // The proposer, and the timestamp of the parent chunk its proposals follow
+    async fn setup() -> (ShardProposer, u64, tempfile::TempDir) {
+        let (mut engine, tmpdir) = test_helper::new_engine();
+        let parent = commit_event(&mut engine, &default_storage_event(FID_FOR_TEST)).await;
+        let (tx_decision, _) = broadcast::channel(1);
+        let proposer = ShardProposer::new(
+            Address([0; 32]),
+            SnapchainShard::new(1),
+            engine,
+            test_helper::statsd_client(),
+            tx_decision,
+        );
+        (proposer, parent.header.unwrap().timestamp, tmpdir)
+    }
+
+    async fn propose(proposer: &mut ShardProposer) -> FullProposal {
+        let height = proposer.get_confirmed_height().increment();
+        proposer
+            .propose_value(height, Round::new(0), Duration::from_millis(100))
+            .await
+    }
+
+    fn with_timestamp(proposal: &FullProposal, timestamp: u64) -> FullProposal {
+        let mut proposal = proposal.clone();
+        if let Some(proto::full_proposal::ProposedValue::Shard(chunk)) =
+            proposal.proposed_value.as_mut()
+        {
+            chunk.header.as_mut().unwrap().timestamp = timestamp;
+        }
+        proposal
+    }
+
+    fn timestamp(proposal: &FullProposal) -> u64 {
+        match &proposal.proposed_value {
+            Some(proto::full_proposal::ProposedValue::Shard(chunk)) => {
+                chunk.header.as_ref().unwrap().timestamp
+            }
+            _ => panic!("Expected a shard chunk"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rejects_far_future_timestamp() {
+        let (mut proposer, _, _tmpdir) = setup().await;
+        let proposal = propose(&mut proposer).await;
+        assert!(matches!(
+            proposer.add_proposed_value(&proposal),
+            Validity::Valid
+        ));
+
+        let far_future = with_timestamp(&proposal, current_time() + MAX_TIMESTAMP_DRIFT + 3600);
+        assert!(matches!(
+            proposer.add_proposed_value(&far_future),
+            Validity::Invalid
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rejects_timestamp_before_parent() {
+        let (mut proposer, parent_timestamp, _tmpdir) = setup().await;
+        let proposal = propose(&mut proposer).await;
+        assert!(timestamp(&proposal) >= parent_timestamp);
+
+        let backwards = with_timestamp(&proposal, parent_timestamp - 1);
+        assert!(matches!(
+            proposer.add_proposed_value(&backwards),
+            Validity::Invalid
+        ));
+    }
+}
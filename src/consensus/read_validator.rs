@@ -1,7 +1,7 @@
 use std::collections::BTreeMap;
 
 use crate::core::types::{SnapchainValidatorContext, Vote};
-use crate::proto::{self, DecidedValue, Height};
+use crate::proto::{self, DecidedValue, Height, ShardChunk};
 use crate::storage::store::engine::{BlockEngine, ShardEngine};
 use crate::utils::statsd_wrapper::StatsdClientWrapper;
 use bytes::Bytes;
@@ -10,6 +10,7 @@ use informalsystems_malachitebft_sync::RawDecidedValue;
 use itertools::Itertools;
 use libp2p::identity::ed25519::PublicKey;
 use prost::Message;
+use tokio::sync::broadcast;
 use tracing::{debug, error, info, warn};
 
 use super::validator::StoredValidatorSets;
@@ -26,6 +27,9 @@ pub struct ReadValidator {
     pub buffered_blocks: BTreeMap<Height, proto::DecidedValue>,
     pub statsd_client: StatsdClientWrapper,
     pub validator_sets: StoredValidatorSets,
+    // Told about the committed chunks of a shard a validator node follows, so its mempool drops the
+    // messages they merged like it does for the shards it proposes on
+    pub shard_decision_tx: Option<broadcast::Sender<ShardChunk>>,
 }
 
 impl ReadValidator {
@@ -49,6 +53,9 @@ impl ReadValidator {
             Engine::ShardEngine(shard_engine) => match &value.value {
                 Some(proto::decided_value::Value::Shard(shard_chunk)) => {
                     shard_engine.commit_shard_chunk(&shard_chunk);
+                    if let Some(shard_decision_tx) = &self.shard_decision_tx {
+                        let _ = shard_decision_tx.send(shard_chunk.clone());
+                    }
                     info!(
                         %height,
                         hash = hex::encode(&shard_chunk.hash),
@@ -117,9 +124,16 @@ impl ReadValidator {
             }
         };
 
-        let validator_set = self
+        let validator_set = match self
             .validator_sets
-            .get_validator_set(certificate.height.as_u64());
+            .get_validator_set(certificate.height.as_u64())
+        {
+            Ok(validator_set) => validator_set,
+            Err(err) => {
+                error!(%certificate.height, last_height = %self.last_height, "Unable to get the validator set: {}", err);
+                return false;
+            }
+        };
 
         let mut expected_pubkeys = validator_set
             .validators
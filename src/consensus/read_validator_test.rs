@@ -7,6 +7,7 @@ mod tests {
 
     use crate::consensus::consensus::ValidatorSetConfig;
     use crate::consensus::read_validator::{Engine, ReadValidator};
+    use crate::consensus::sync_apply::SyncApplyLimiter;
     use crate::consensus::validator::{StoredValidatorSet, StoredValidatorSets};
     use crate::core::types::{Address, ShardId};
     use crate::proto::{self, CommitSignature, Commits, Height, ShardChunk, ShardHash};
@@ -19,7 +20,13 @@ mod tests {
     async fn setup(
         num_already_decided_blocks: u64,
     ) -> (ShardEngine, ShardEngine, ReadValidator, Keypair) {
-        let proposer_keypair = Keypair::generate();
+        setup_with_keypair(num_already_decided_blocks, Keypair::generate()).await
+    }
+
+    async fn setup_with_keypair(
+        num_already_decided_blocks: u64,
+        proposer_keypair: Keypair,
+    ) -> (ShardEngine, ShardEngine, ReadValidator, Keypair) {
         let (mut proposer_engine, _) = test_helper::new_engine();
         let (mut read_node_engine, _) = test_helper::new_engine();
         for _ in 0..num_already_decided_blocks {
@@ -57,6 +64,7 @@ mod tests {
             buffered_blocks: BTreeMap::new(),
             statsd_client: test_helper::statsd_client(),
             validator_sets: StoredValidatorSets::new(read_node_engine.shard_id(), validator_sets),
+            shard_decision_tx: None,
         };
 
         (
@@ -216,4 +224,60 @@ mod tests {
         let num_processed = process_decided_value(&mut read_validator, &shard_chunk).await;
         assert_eq!(num_processed, 0);
     }
+
+    // Syncs every shard's chunks, in order within a shard, with the shards in parallel as far as
+    // the limiter lets them, and returns each shard's last chunk
+    async fn sync_shards(
+        shard_chunks: &[Vec<ShardChunk>],
+        keypairs: &[Keypair],
+        limiter: SyncApplyLimiter,
+    ) -> Vec<Option<ShardChunk>> {
+        let mut handles = vec![];
+        for (chunks, keypair) in shard_chunks.iter().zip(keypairs) {
+            let (_, read_node_engine, mut read_validator, _) =
+                setup_with_keypair(0, keypair.clone()).await;
+            let (chunks, limiter) = (chunks.clone(), limiter.clone());
+            handles.push(tokio::spawn(async move {
+                for chunk in chunks {
+                    let decided_value = proto::DecidedValue {
+                        value: Some(proto::decided_value::Value::Shard(chunk)),
+                    };
+                    limiter
+                        .run(|| read_validator.process_decided_value(decided_value))
+                        .await;
+                }
+                read_node_engine.get_last_shard_chunk()
+            }));
+        }
+        let mut last_chunks = vec![];
+        for handle in handles {
+            last_chunks.push(handle.await.unwrap());
+        }
+        last_chunks
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_parallel_sync_matches_sequential() {
+        let mut shard_chunks = vec![];
+        let mut keypairs = vec![];
+        let mut tips = vec![];
+        for _ in 0..3 {
+            let keypair = Keypair::generate();
+            let (mut proposer_engine, _) = test_helper::new_engine();
+            let mut chunks = vec![];
+            for _ in 0..5 {
+                chunks.push(commit_shard_chunk(&mut proposer_engine, &keypair).await);
+            }
+            tips.push(chunks.last().cloned());
+            shard_chunks.push(chunks);
+            keypairs.push(keypair);
+        }
+
+        let sequential = sync_shards(&shard_chunks, &keypairs, SyncApplyLimiter::new(1)).await;
+        let parallel = sync_shards(&shard_chunks, &keypairs, SyncApplyLimiter::new(3)).await;
+        let unlimited = sync_shards(&shard_chunks, &keypairs, SyncApplyLimiter::unlimited()).await;
+        assert_eq!(sequential, tips);
+        assert_eq!(parallel, sequential);
+        assert_eq!(unlimited, sequential);
+    }
 }
@@ -0,0 +1,92 @@
+use std::sync::Arc;
+use tokio::runtime::{Handle, RuntimeFlavor};
+use tokio::sync::Semaphore;
+
+/// Caps how many shards apply synced blocks at the same time. Each shard applies its own blocks
+/// in order from its own host actor, into its own db and trie, so shards can go in parallel
+/// without sharing any state. The blocks of a shard never do, since its host handles one at a
+/// time. Applying a block is blocking work, so it runs with block_in_place to keep the runtime's
+/// other tasks moving. It's shared by all the shards of a node.
+#[derive(Clone)]
+pub struct SyncApplyLimiter {
+    // None applies every shard at once
+    permits: Option<Arc<Semaphore>>,
+}
+
+impl SyncApplyLimiter {
+    // 0 doesn't limit it, every shard applies its blocks as they come
+    pub fn new(parallelism: u32) -> Self {
+        SyncApplyLimiter {
+            permits: (parallelism > 0).then(|| Arc::new(Semaphore::new(parallelism as usize))),
+        }
+    }
+
+    pub fn unlimited() -> Self {
+        Self::new(0)
+    }
+
+    /// Runs f once a slot is free. The slot is given back when f returns or panics, so a shard
+    /// that fails to apply a block doesn't hold up the others.
+    pub async fn run<T>(&self, f: impl FnOnce() -> T) -> T {
+        let _permit = match &self.permits {
+            Some(permits) => Some(permits.clone().acquire_owned().await.unwrap()),
+            None => None,
+        };
+        match Handle::current().runtime_flavor() {
+            RuntimeFlavor::CurrentThread => f(),
+            _ => tokio::task::block_in_place(f),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    async fn max_concurrency(limiter: SyncApplyLimiter, tasks: usize) -> usize {
+        let running = Arc::new(AtomicUsize::new(0));
+        let max = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..tasks)
+            .map(|_| {
+                let (limiter, running, max) = (limiter.clone(), running.clone(), max.clone());
+                tokio::spawn(async move {
+                    limiter
+                        .run(|| {
+                            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                            max.fetch_max(now, Ordering::SeqCst);
+                            std::thread::sleep(Duration::from_millis(20));
+                            running.fetch_sub(1, Ordering::SeqCst);
+                        })
+                        .await
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.await.unwrap();
+        }
+        max.load(Ordering::SeqCst)
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_parallelism_is_capped() {
+        assert_eq!(max_concurrency(SyncApplyLimiter::new(1), 4).await, 1);
+        assert!(max_concurrency(SyncApplyLimiter::new(2), 4).await <= 2);
+        assert!(max_concurrency(SyncApplyLimiter::unlimited(), 4).await > 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_panic_gives_the_slot_back() {
+        let limiter = SyncApplyLimiter::new(1);
+        let failing = limiter.clone();
+        let result = tokio::spawn(async move { failing.run(|| panic!("bad block")).await }).await;
+        assert!(result.is_err());
+        assert_eq!(
+            tokio::time::timeout(Duration::from_secs(1), limiter.run(|| 1))
+                .await
+                .unwrap(),
+            1
+        );
+    }
+}
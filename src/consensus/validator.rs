@@ -1,11 +1,13 @@
 use super::consensus::ValidatorSetConfig;
-use crate::consensus::proposer::{BlockProposer, Proposer, ShardProposer};
+use crate::consensus::proposer::{BlockProposer, CommittedShardChunks, Proposer, ShardProposer};
 use crate::core::types::{
     Address, Height, ShardId, SnapchainShard, SnapchainValidator, SnapchainValidatorContext,
     SnapchainValidatorSet,
 };
 use crate::proto::{full_proposal, Commits, FullProposal, ShardHash};
 use crate::storage::store::node_local_state::LocalStateStore;
+use crate::storage::store::stores::ShardFreeze;
+use crate::storage::store::validator_stakes::{ValidatorStakes, ValidatorStakesError};
 use crate::utils::statsd_wrapper::StatsdClientWrapper;
 use informalsystems_malachitebft_core_consensus::ProposedValue;
 use informalsystems_malachitebft_core_types::{Round, ValidatorSet};
@@ -23,14 +25,41 @@ pub enum ProposalSource {
 pub struct StoredValidatorSets {
     shard_id: u32,
     sets: Vec<StoredValidatorSet>,
+    // Weighs the validators by stake instead of equally when set
+    stakes: Option<ValidatorStakes>,
 }
 
 impl StoredValidatorSets {
     pub fn new(shard_id: u32, sets: Vec<StoredValidatorSet>) -> Self {
-        Self { shard_id, sets }
+        Self {
+            shard_id,
+            sets,
+            stakes: None,
+        }
+    }
+
+    pub fn with_stakes(mut self, stakes: Option<ValidatorStakes>) -> Self {
+        self.stakes = stakes;
+        self
+    }
+
+    // Unset when the validators are weighed equally
+    pub fn stakes(&self) -> Option<&ValidatorStakes> {
+        self.stakes.as_ref()
     }
 
-    pub fn get_validator_set(&self, height: u64) -> SnapchainValidatorSet {
+    pub fn from_config(shard: SnapchainShard, configs: &Vec<ValidatorSetConfig>) -> Self {
+        Self::new(
+            shard.shard_id(),
+            configs
+                .iter()
+                .map(|config| StoredValidatorSet::new(shard, config))
+                .collect(),
+        )
+    }
+
+    // The set in effect at the given height
+    pub fn get_stored_validator_set(&self, height: u64) -> &StoredValidatorSet {
         let mut result = &self.sets[0];
         for config in &self.sets {
             if config.shard_ids.contains(&self.shard_id)
@@ -40,7 +69,20 @@ impl StoredValidatorSets {
                 result = config;
             }
         }
-        result.validators.clone()
+        result
+    }
+
+    // The set in effect at the given height, with the voting power of its validators at it. Every
+    // validator has to weigh the votes the same, so there's no set when the stakes can't be read.
+    pub fn get_validator_set(
+        &self,
+        height: u64,
+    ) -> Result<SnapchainValidatorSet, ValidatorStakesError> {
+        let mut validators = self.get_stored_validator_set(height).validators.clone();
+        if let Some(stakes) = &self.stakes {
+            stakes.apply(height, &mut validators)?;
+        }
+        Ok(validators)
     }
 }
 
@@ -105,13 +147,7 @@ impl ShardValidator {
         ShardValidator {
             shard_id: shard.clone(),
             address: address.clone(),
-            validator_sets: StoredValidatorSets::new(
-                shard.shard_id(),
-                validator_set
-                    .iter()
-                    .map(|config| StoredValidatorSet::new(shard, &config))
-                    .collect(),
-            ),
+            validator_sets: StoredValidatorSets::from_config(shard, &validator_set),
             current_height: None,
             proposal_source: ProposalSource::Consensus,
             current_round: Round::new(0),
@@ -126,12 +162,23 @@ impl ShardValidator {
         }
     }
 
-    pub fn get_validator_set(&self, height: u64) -> SnapchainValidatorSet {
+    pub fn with_validator_stakes(mut self, stakes: Option<ValidatorStakes>) -> Self {
+        self.validator_sets = self.validator_sets.with_stakes(stakes);
+        self
+    }
+
+    pub fn get_validator_set(
+        &self,
+        height: u64,
+    ) -> Result<SnapchainValidatorSet, ValidatorStakesError> {
         self.validator_sets.get_validator_set(height)
     }
 
     pub fn validator_count(&self, height: u64) -> usize {
-        self.get_validator_set(height).count()
+        self.validator_sets
+            .get_stored_validator_set(height)
+            .validators
+            .count()
     }
 
     pub fn get_address(&self) -> Address {
@@ -156,6 +203,24 @@ impl ShardValidator {
         panic!("No proposer set on validator");
     }
 
+    // The block shard can't be frozen, only user data shards
+    pub fn shard_freeze(&self) -> Option<ShardFreeze> {
+        self.shard_proposer
+            .as_ref()
+            .map(|shard_proposer| shard_proposer.shard_freeze())
+    }
+
+    pub fn is_syncing(&self) -> bool {
+        self.proposal_source == ProposalSource::Sync
+    }
+
+    // Only on the block shard, whose blocks witness every shard's chunks
+    pub fn committed_shard_chunks(&self) -> Option<CommittedShardChunks> {
+        self.block_proposer
+            .as_ref()
+            .map(|block_proposer| block_proposer.committed_shard_chunks())
+    }
+
     pub fn start(&mut self) {
         self.started = true;
     }
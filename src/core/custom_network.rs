@@ -0,0 +1,254 @@
+use crate::core::protocol_features::ActivationHeights;
+use crate::core::types::FARCASTER_EPOCH;
+use crate::proto::FarcasterNetwork;
+use crate::storage::store::stores::{Limits, StoreLimits};
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static CONFIGURED_FARCASTER_EPOCH: OnceLock<u64> = OnceLock::new();
+static CONFIGURED_ACTIVATION_HEIGHTS: OnceLock<ActivationHeights> = OnceLock::new();
+
+/// Constants of a private network. Only used when `fc_network` is Custom, the built-in networks
+/// always use the baked-in values whatever is configured here.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Config {
+    // Unix time in milliseconds that farcaster timestamps count from
+    pub farcaster_epoch: u64,
+    // Messages each storage unit allows per store, for units rented before and after the legacy
+    // cutoff
+    pub limits: Limits,
+    pub legacy_limits: Limits,
+    // Serialized message bytes each storage unit allows per store. Unset, stores are only
+    // limited by message count.
+    #[serde(default)]
+    pub byte_limits: Option<Limits>,
+    // Heights the protocol features apply from, see ActivationHeights. None are scheduled unless
+    // they're set.
+    #[serde(default)]
+    pub activation_heights: ActivationHeights,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            farcaster_epoch: FARCASTER_EPOCH,
+            limits: Limits::default(),
+            legacy_limits: Limits::legacy(),
+            byte_limits: None,
+            activation_heights: ActivationHeights::default(),
+        }
+    }
+}
+
+impl Config {
+    pub fn validate(&self, network: FarcasterNetwork) -> Result<(), String> {
+        if network != FarcasterNetwork::Custom {
+            return Ok(());
+        }
+
+        // Farcaster timestamps are in seconds
+        if self.farcaster_epoch % 1000 != 0 {
+            return Err(format!(
+                "farcaster_epoch must be a whole number of seconds: {}",
+                self.farcaster_epoch
+            ));
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| format!("failed to get time: {}", e))?
+            .as_millis() as u64;
+        if self.farcaster_epoch > now {
+            return Err(format!(
+                "farcaster_epoch is in the future: {}",
+                self.farcaster_epoch
+            ));
+        }
+
+        // Legacy units may grant nothing, a new network has none of them
+        validate_limits("limits", &self.limits)?;
+        if let Some(byte_limits) = &self.byte_limits {
+            validate_limits("byte_limits", byte_limits)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn farcaster_epoch(&self, network: FarcasterNetwork) -> u64 {
+        match network {
+            FarcasterNetwork::Custom => self.farcaster_epoch,
+            _ => FARCASTER_EPOCH,
+        }
+    }
+
+    pub fn activation_heights(&self, network: FarcasterNetwork) -> ActivationHeights {
+        match network {
+            FarcasterNetwork::Custom => self.activation_heights.clone(),
+            _ => built_in_activation_heights(network),
+        }
+    }
+
+    pub fn store_limits(&self, network: FarcasterNetwork) -> StoreLimits {
+        match network {
+            FarcasterNetwork::Custom => StoreLimits {
+                limits: self.limits.clone(),
+                legacy_limits: self.legacy_limits.clone(),
+                byte_limits: self.byte_limits.clone(),
+            },
+            _ => StoreLimits::default(),
+        }
+    }
+}
+
+fn validate_limits(name: &str, limits: &Limits) -> Result<(), String> {
+    for (store, limit) in [
+        ("casts", limits.casts),
+        ("links", limits.links),
+        ("reactions", limits.reactions),
+        ("user_data", limits.user_data),
+        ("user_name_proofs", limits.user_name_proofs),
+        ("verifications", limits.verifications),
+    ] {
+        if limit == 0 {
+            return Err(format!("{}.{} must be greater than 0", name, store));
+        }
+    }
+    Ok(())
+}
+
+/// Sets the epoch used by all farcaster time conversions. Must be called at startup, before any
+/// time is converted, and only once.
+pub fn set_farcaster_epoch(epoch: u64) -> Result<(), String> {
+    CONFIGURED_FARCASTER_EPOCH
+        .set(epoch)
+        .map_err(|existing| format!("farcaster epoch is already set to {}", existing))
+}
+
+// None of the features are scheduled on the built-in networks yet
+fn built_in_activation_heights(_network: FarcasterNetwork) -> ActivationHeights {
+    ActivationHeights::default()
+}
+
+/// Sets the activation heights the engines start with. Must be called at startup, like
+/// [set_farcaster_epoch].
+pub fn set_activation_heights(activation_heights: ActivationHeights) -> Result<(), String> {
+    CONFIGURED_ACTIVATION_HEIGHTS
+        .set(activation_heights)
+        .map_err(|_| "activation heights are already set".to_string())
+}
+
+pub fn activation_heights(network: FarcasterNetwork) -> ActivationHeights {
+    CONFIGURED_ACTIVATION_HEIGHTS
+        .get()
+        .cloned()
+        .unwrap_or_else(|| built_in_activation_heights(network))
+}
+
+pub fn farcaster_epoch() -> u64 {
+    CONFIGURED_FARCASTER_EPOCH
+        .get()
+        .copied()
+        .unwrap_or(FARCASTER_EPOCH)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn custom_config() -> Config {
+        Config {
+            farcaster_epoch: 1704067200000, // January 1, 2024 UTC
+            limits: Limits {
+                casts: 10,
+                links: 20,
+                reactions: 30,
+                user_data: 5,
+                user_name_proofs: 2,
+                verifications: 3,
+            },
+            legacy_limits: Limits {
+                casts: 0,
+                links: 0,
+                reactions: 0,
+                user_data: 0,
+                user_name_proofs: 0,
+                verifications: 0,
+            },
+            byte_limits: Some(Limits {
+                casts: 10_000,
+                links: 5_000,
+                reactions: 5_000,
+                user_data: 1_000,
+                user_name_proofs: 1_000,
+                verifications: 1_000,
+            }),
+            activation_heights: ActivationHeights::all(),
+        }
+    }
+
+    #[test]
+    fn test_custom_network_uses_configured_constants() {
+        let config = custom_config();
+        assert_eq!(config.validate(FarcasterNetwork::Custom), Ok(()));
+        assert_eq!(
+            config.farcaster_epoch(FarcasterNetwork::Custom),
+            1704067200000
+        );
+
+        let store_limits = config.store_limits(FarcasterNetwork::Custom);
+        assert_eq!(store_limits.limits, config.limits);
+        assert_eq!(store_limits.legacy_limits, config.legacy_limits);
+        assert_eq!(store_limits.byte_limits, config.byte_limits);
+        assert_eq!(
+            config.activation_heights(FarcasterNetwork::Custom),
+            ActivationHeights::all()
+        );
+    }
+
+    #[test]
+    fn test_built_in_networks_ignore_configured_constants() {
+        let config = custom_config();
+        for network in [
+            FarcasterNetwork::Mainnet,
+            FarcasterNetwork::Testnet,
+            FarcasterNetwork::Devnet,
+        ] {
+            assert_eq!(config.farcaster_epoch(network), FARCASTER_EPOCH);
+            let store_limits = config.store_limits(network);
+            assert_eq!(store_limits.limits, Limits::default());
+            assert_eq!(store_limits.legacy_limits, Limits::legacy());
+            assert_eq!(store_limits.byte_limits, None);
+            assert_eq!(
+                config.activation_heights(network),
+                ActivationHeights::default()
+            );
+        }
+    }
+
+    #[test]
+    fn test_validation() {
+        let mut config = custom_config();
+        config.farcaster_epoch += 500;
+        assert!(config.validate(FarcasterNetwork::Custom).is_err());
+
+        let mut config = custom_config();
+        config.farcaster_epoch = u64::MAX - u64::MAX % 1000;
+        assert!(config.validate(FarcasterNetwork::Custom).is_err());
+
+        let mut config = custom_config();
+        config.limits.user_data = 0;
+        assert_eq!(
+            config.validate(FarcasterNetwork::Custom),
+            Err("limits.user_data must be greater than 0".to_string())
+        );
+
+        let mut config = custom_config();
+        config.byte_limits.as_mut().unwrap().casts = 0;
+        assert_eq!(
+            config.validate(FarcasterNetwork::Custom),
+            Err("byte_limits.casts must be greater than 0".to_string())
+        );
+        // Built-in networks don't use these, so they aren't checked
+        assert_eq!(config.validate(FarcasterNetwork::Mainnet), Ok(()));
+    }
+}
@@ -58,12 +58,40 @@ impl HubError {
         }
     }
 
+    pub fn duplicate_nonce(error_message: &str) -> HubError {
+        HubError {
+            code: "bad_request.duplicate_nonce".to_string(),
+            message: error_message.to_string(),
+        }
+    }
+
     pub fn rate_limited(error_message: &str) -> HubError {
         HubError {
             code: "bad_request.rate_limited".to_string(),
             message: error_message.to_string(),
         }
     }
+
+    pub fn failed_precondition(error_message: &str) -> HubError {
+        HubError {
+            code: "failed_precondition".to_string(),
+            message: error_message.to_string(),
+        }
+    }
+
+    pub fn deadline_exceeded(error_message: &str) -> HubError {
+        HubError {
+            code: "deadline_exceeded".to_string(),
+            message: error_message.to_string(),
+        }
+    }
+
+    pub fn resource_exhausted(error_message: &str) -> HubError {
+        HubError {
+            code: "resource_exhausted".to_string(),
+            message: error_message.to_string(),
+        }
+    }
 }
 
 impl Error for HubError {}
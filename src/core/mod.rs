@@ -1,5 +1,7 @@
+pub mod custom_network;
 pub mod error;
 mod message;
+pub mod protocol_features;
 pub mod types;
 pub mod util;
 pub mod validations;
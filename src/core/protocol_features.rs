@@ -0,0 +1,68 @@
+use serde::{Deserialize, Serialize};
+
+/// Changes to the rules chunks are built and replayed with. Each one applies from its activation
+/// height on, so chunks committed before it still replay to the state root they recorded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProtocolFeature {
+    // Removing a signer also revokes its link compact state and username proof messages
+    RevokeAllSignerMessages,
+    // Validators reject chunks over the network-wide block limits
+    NetworkBlockLimits,
+    // Link compact states with the same timestamp are ordered by hash, and links at a compact
+    // state's timestamp are rejected after it like it deletes them before it, so the merged state
+    // doesn't depend on the order
+    CommutativeCompactState,
+}
+
+/// The first height each feature applies at. Features without one don't apply on the network
+/// yet. Every validator of a network needs the same heights.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ActivationHeights {
+    #[serde(default)]
+    pub revoke_all_signer_messages: Option<u64>,
+    #[serde(default)]
+    pub network_block_limits: Option<u64>,
+    #[serde(default)]
+    pub commutative_compact_state: Option<u64>,
+}
+
+impl ActivationHeights {
+    /// Every feature from the first height, for new networks
+    pub fn all() -> ActivationHeights {
+        ActivationHeights {
+            revoke_all_signer_messages: Some(0),
+            network_block_limits: Some(0),
+            commutative_compact_state: Some(0),
+        }
+    }
+
+    pub fn is_active(&self, feature: ProtocolFeature, height: u64) -> bool {
+        let activation_height = match feature {
+            ProtocolFeature::RevokeAllSignerMessages => self.revoke_all_signer_messages,
+            ProtocolFeature::NetworkBlockLimits => self.network_block_limits,
+            ProtocolFeature::CommutativeCompactState => self.commutative_compact_state,
+        };
+        activation_height.map_or(false, |activation_height| height >= activation_height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_features_apply_from_their_activation_height() {
+        let heights = ActivationHeights {
+            revoke_all_signer_messages: Some(10),
+            network_block_limits: None,
+            commutative_compact_state: None,
+        };
+        let feature = ProtocolFeature::RevokeAllSignerMessages;
+        assert!(!heights.is_active(feature, 9));
+        assert!(heights.is_active(feature, 10));
+        assert!(heights.is_active(feature, 11));
+        // Unscheduled features never apply
+        assert!(!heights.is_active(ProtocolFeature::NetworkBlockLimits, u64::MAX));
+        assert!(ActivationHeights::all().is_active(ProtocolFeature::NetworkBlockLimits, 0));
+    }
+}
@@ -10,8 +10,8 @@ use libp2p::identity::ed25519::Keypair;
 use prost::Message;
 use serde::{Deserialize, Serialize};
 use std::fmt::{Debug, Display};
-use std::sync::Arc;
-use tracing::{error, warn};
+use std::sync::{Arc, Mutex};
+use tracing::{error, info, warn};
 
 pub use crate::proto; // TODO: reconsider how this is imported
 
@@ -426,6 +426,8 @@ pub struct SnapchainValidator {
     pub public_key: PublicKey,
     pub rpc_address: Option<String>,
     pub current_height: u64,
+    // 1 unless the shard weighs votes by stake
+    pub voting_power: u64,
 }
 
 impl SnapchainValidator {
@@ -441,6 +443,7 @@ impl SnapchainValidator {
             public_key,
             rpc_address,
             current_height,
+            voting_power: 1,
         }
     }
 }
@@ -479,6 +482,15 @@ impl SnapchainValidatorSet {
         self.validators.iter().any(|v| v.address == *address)
     }
 
+    // Validators take turns proposing, starting over from the next one on every round
+    pub fn proposer(&self, height: u64, round: u64) -> &SnapchainValidator {
+        assert!(self.validators.len() > 0);
+        let proposer_index = (height as usize - 1 + round as usize) % self.validators.len();
+        self.validators
+            .get(proposer_index)
+            .expect("proposer_index is valid")
+    }
+
     pub fn shard_id(&self) -> u32 {
         if self.validators.is_empty() {
             0
@@ -605,10 +617,59 @@ impl Proposal {
     }
 }
 
+/// Decides which validator proposes at a height and round. Every validator on a shard has to use
+/// the same strategy, or they won't agree on whose proposal to vote for.
+pub trait ProposerSelector: Debug + Send + Sync {
+    fn select<'a>(
+        &self,
+        validator_set: &'a SnapchainValidatorSet,
+        height: u64,
+        round: u64,
+    ) -> &'a SnapchainValidator;
+}
+
+/// The default, validators take turns proposing
+#[derive(Debug)]
+pub struct RoundRobinProposer;
+
+impl ProposerSelector for RoundRobinProposer {
+    fn select<'a>(
+        &self,
+        validator_set: &'a SnapchainValidatorSet,
+        height: u64,
+        round: u64,
+    ) -> &'a SnapchainValidator {
+        validator_set.proposer(height, round)
+    }
+}
+
+/// The validator at validator_index in the (sorted) set proposes at every height and round, so
+/// tests can reproduce what happens with a given proposer. An index past the end of the set wraps
+/// around. Only allowed on devnet.
+#[derive(Debug)]
+pub struct FixedProposer {
+    pub validator_index: usize,
+}
+
+impl ProposerSelector for FixedProposer {
+    fn select<'a>(
+        &self,
+        validator_set: &'a SnapchainValidatorSet,
+        _height: u64,
+        _round: u64,
+    ) -> &'a SnapchainValidator {
+        assert!(validator_set.validators.len() > 0);
+        &validator_set.validators[self.validator_index % validator_set.validators.len()]
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct SnapchainValidatorContext {
     keypair: Arc<Keypair>,
     signing_provider: Ed25519Provider,
+    proposer_selector: Arc<dyn ProposerSelector>,
+    // The last height and round a proposer was logged for, so each round is only logged once
+    last_logged_proposer: Arc<Mutex<Option<(Height, Round)>>>,
 }
 
 impl SnapchainValidatorContext {
@@ -617,9 +678,16 @@ impl SnapchainValidatorContext {
         Self {
             keypair: keypair.clone(),
             signing_provider: Ed25519Provider::new(keypair),
+            proposer_selector: Arc::new(RoundRobinProposer),
+            last_logged_proposer: Arc::new(Mutex::new(None)),
         }
     }
 
+    pub fn with_proposer_selector(mut self, proposer_selector: Arc<dyn ProposerSelector>) -> Self {
+        self.proposer_selector = proposer_selector;
+        self
+    }
+
     pub fn public_key(&self) -> PublicKey {
         self.keypair.public()
     }
@@ -651,20 +719,22 @@ impl informalsystems_malachitebft_core_types::Context for SnapchainValidatorCont
         height: Self::Height,
         round: Round,
     ) -> &'a Self::Validator {
-        assert!(validator_set.validators.len() > 0);
         assert!(round != Round::Nil && round.as_i64() >= 0);
-
-        let proposer_index = {
-            let height = height.as_u64() as usize;
-            let round = round.as_i64() as usize;
-
-            (height - 1 + round) % validator_set.validators.len()
-        };
-
-        validator_set
-            .validators
-            .get(proposer_index)
-            .expect("proposer_index is valid")
+        let proposer =
+            self.proposer_selector
+                .select(validator_set, height.as_u64(), round.as_i64() as u64);
+
+        let mut last_logged = self.last_logged_proposer.lock().unwrap();
+        if *last_logged != Some((height, round)) {
+            info!(
+                height = height.to_string(),
+                round = round.as_i64(),
+                proposer = proposer.address.to_string(),
+                "Selected proposer"
+            );
+            *last_logged = Some((height, round));
+        }
+        proposer
     }
 
     fn new_proposal(
@@ -820,7 +890,7 @@ impl informalsystems_malachitebft_core_types::Validator<SnapchainValidatorContex
     }
 
     fn voting_power(&self) -> VotingPower {
-        1
+        self.voting_power
     }
 }
 
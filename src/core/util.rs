@@ -1,16 +1,19 @@
+use crate::core::custom_network::farcaster_epoch;
 use crate::core::error::HubError;
-use crate::core::types::FARCASTER_EPOCH;
+use crate::proto::HashScheme;
+use crate::storage::util::blake3_20;
 
 #[allow(dead_code)]
 pub fn to_farcaster_time(time_ms: u64) -> Result<u64, HubError> {
-    if time_ms < FARCASTER_EPOCH {
+    let epoch = farcaster_epoch();
+    if time_ms < epoch {
         return Err(HubError {
             code: "bad_request.invalid_param".to_string(),
             message: format!("time_ms is before the farcaster epoch: {}", time_ms),
         });
     }
 
-    let seconds_since_epoch = (time_ms - FARCASTER_EPOCH) / 1000;
+    let seconds_since_epoch = (time_ms - epoch) / 1000;
     if seconds_since_epoch > u32::MAX as u64 {
         return Err(HubError {
             code: "bad_request.invalid_param".to_string(),
@@ -23,11 +26,11 @@ pub fn to_farcaster_time(time_ms: u64) -> Result<u64, HubError> {
 
 #[allow(dead_code)]
 pub fn from_farcaster_time(time: u64) -> u64 {
-    time * 1000 + FARCASTER_EPOCH
+    time * 1000 + farcaster_epoch()
 }
 
 pub fn farcaster_time_to_unix_seconds(time: u64) -> u64 {
-    time + (FARCASTER_EPOCH / 1000)
+    time + (farcaster_epoch() / 1000)
 }
 
 #[allow(dead_code)]
@@ -41,13 +44,24 @@ pub fn get_farcaster_time() -> Result<u64, HubError> {
     Ok(to_farcaster_time(now.as_millis() as u64)?)
 }
 
+/// The hash of a message's data bytes under a hash scheme. It's what a message's declared hash is
+/// checked against, so anything that hashes message data goes through here. None for schemes that
+/// don't hash anything.
+pub fn hash_message_data(hash_scheme: HashScheme, data_bytes: &[u8]) -> Option<Vec<u8>> {
+    match hash_scheme {
+        HashScheme::Blake3 => Some(blake3_20(data_bytes)),
+        HashScheme::None => None,
+    }
+}
+
 pub fn calculate_message_hash(data_bytes: &[u8]) -> Vec<u8> {
-    blake3::hash(data_bytes).as_bytes()[0..20].to_vec()
+    hash_message_data(HashScheme::Blake3, data_bytes).unwrap()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::core::types::FARCASTER_EPOCH;
 
     #[test]
     fn test_get_farcaster_time() {
@@ -0,0 +1,52 @@
+use crate::core::validations::error::ValidationError;
+use crate::proto::{self, FarcasterNetwork};
+use std::sync::Arc;
+
+/// An extra check on user messages, for forks running a Custom network with rules of their own.
+/// It runs after the built-in validation wherever the engine validates a message, when blocks are
+/// proposed, validated and replayed and when messages are submitted, so every validator on the
+/// network needs the same ones. It sees messages that passed the built-in checks.
+pub trait MessageValidator: Send + Sync {
+    // Named in the error when a message is rejected
+    fn name(&self) -> &str;
+
+    fn validate(&self, message: &proto::Message) -> Result<(), String>;
+}
+
+/// The extra validators a node runs, none unless registered at node construction
+#[derive(Clone, Default)]
+pub struct MessageValidators {
+    validators: Vec<Arc<dyn MessageValidator>>,
+}
+
+impl MessageValidators {
+    // Built-in networks only use the standard validation
+    pub fn new(
+        network: FarcasterNetwork,
+        validators: Vec<Arc<dyn MessageValidator>>,
+    ) -> Result<Self, String> {
+        if !validators.is_empty() && network != FarcasterNetwork::Custom {
+            return Err(format!(
+                "message validators can only be registered on a Custom network, not {}",
+                network.as_str_name()
+            ));
+        }
+        Ok(MessageValidators { validators })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.validators.is_empty()
+    }
+
+    pub fn validate(&self, message: &proto::Message) -> Result<(), ValidationError> {
+        for validator in &self.validators {
+            validator
+                .validate(message)
+                .map_err(|reason| ValidationError::RejectedByValidator {
+                    validator: validator.name().to_string(),
+                    reason,
+                })?;
+        }
+        Ok(())
+    }
+}
@@ -1,6 +1,7 @@
 use thiserror::Error;
 
-#[derive(Error, Debug, Clone, PartialEq)]
+#[derive(Error, Debug, Clone, PartialEq, strum_macros::IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
 pub enum ValidationError {
     #[error("Message data is missing")]
     MissingData,
@@ -24,4 +25,6 @@ pub enum ValidationError {
     InvalidNetwork,
     #[error("Invalid button index")]
     InvalidButtonIndex,
+    #[error("Rejected by {validator}: {reason}")]
+    RejectedByValidator { validator: String, reason: String },
 }
@@ -1,19 +1,31 @@
+use crate::core::util::hash_message_data;
 use crate::core::validations::error::ValidationError;
 use crate::core::validations::validate_cast_id;
 use crate::proto::{
     self, FarcasterNetwork, FrameActionBody, MessageData, MessageType, UserDataBody, UserDataType,
 };
-use crate::storage::util::{blake3_20, bytes_compare};
+use crate::storage::util::bytes_compare;
 
-use ed25519_dalek::{Signature, VerifyingKey};
 use fancy_regex::Regex;
 use prost::Message;
 
-use super::{cast, link, reaction, verification};
+use super::{cast, link, reaction, signature_scheme, verification};
 
-const MAX_DATA_BYTES: usize = 2048;
-const MAX_DATA_BYTES_FOR_LINK_COMPACT: usize = 65536;
+pub const MAX_DATA_BYTES: usize = 2048;
+pub const MAX_DATA_BYTES_FOR_LINK_COMPACT: usize = 65536;
 const EMBEDS_V1_CUTOFF: u32 = 73612800;
+// Messages are signed by app keys, which are always ed25519. EIP-712 is only used for claims.
+const MESSAGE_SIGNATURE_SCHEMES: [proto::SignatureScheme; 1] = [proto::SignatureScheme::Ed25519];
+
+/// The schemes messages can be hashed with on a network. Every network only takes blake3 for now.
+pub fn message_hash_schemes(network: FarcasterNetwork) -> &'static [proto::HashScheme] {
+    match network {
+        FarcasterNetwork::Mainnet
+        | FarcasterNetwork::Testnet
+        | FarcasterNetwork::Devnet
+        | FarcasterNetwork::None => &[proto::HashScheme::Blake3],
+    }
+}
 
 pub fn validate_message_type(message_type: i32) -> Result<(), ValidationError> {
     MessageType::try_from(message_type)
@@ -105,7 +117,12 @@ pub fn validate_message(
         }
     }
 
-    validate_message_hash(message.hash_scheme, &data_bytes, &message.hash)?;
+    validate_message_hash(
+        current_network,
+        message.hash_scheme,
+        &data_bytes,
+        &message.hash,
+    )?;
     validate_signature(
         message.signature_scheme,
         &message.hash,
@@ -153,45 +170,62 @@ pub fn validate_message(
     Ok(())
 }
 
+/// The hash and signature checks of validate_message on their own, for when the rest of the message
+/// is checked later
+pub fn validate_message_signature(
+    message: &proto::Message,
+    network: FarcasterNetwork,
+) -> Result<(), ValidationError> {
+    let data_bytes = match &message.data_bytes {
+        Some(data_bytes) => data_bytes.clone(),
+        None => message
+            .data
+            .as_ref()
+            .ok_or(ValidationError::MissingData)?
+            .encode_to_vec(),
+    };
+    validate_message_hash(network, message.hash_scheme, &data_bytes, &message.hash)?;
+    validate_signature(
+        message.signature_scheme,
+        &message.hash,
+        &message.signature,
+        &message.signer,
+    )
+}
+
 fn validate_signature(
     signature_scheme: i32,
     data_bytes: &Vec<u8>,
     signature: &Vec<u8>,
     signer: &Vec<u8>,
 ) -> Result<(), ValidationError> {
-    if signature_scheme != proto::SignatureScheme::Ed25519 as i32 {
-        return Err(ValidationError::InvalidSignatureScheme);
-    }
-
-    if signature.len() == 0 {
-        return Err(ValidationError::MissingSignature);
-    }
-
-    let sig = Signature::from_slice(signature).map_err(|_| ValidationError::InvalidSignature)?;
-    let public_key = VerifyingKey::try_from(signer.as_slice())
-        .map_err(|_| ValidationError::MissingOrInvalidSigner)?;
-
-    public_key
-        .verify_strict(data_bytes.as_slice(), &sig)
-        .map_err(|_| ValidationError::InvalidSignature)?;
+    let scheme = proto::SignatureScheme::try_from(signature_scheme)
+        .ok()
+        .filter(|scheme| MESSAGE_SIGNATURE_SCHEMES.contains(scheme))
+        .and_then(signature_scheme::for_signature_scheme)
+        .ok_or(ValidationError::InvalidSignatureScheme)?;
 
-    Ok(())
+    signature_scheme::verify_signature(scheme, data_bytes, signature, signer)
 }
 
+// The declared hash has to be the one recomputed from the data, with a scheme the network takes
 fn validate_message_hash(
+    network: FarcasterNetwork,
     hash_scheme: i32,
     data_bytes: &Vec<u8>,
     hash: &Vec<u8>,
 ) -> Result<(), ValidationError> {
-    if hash_scheme != proto::HashScheme::Blake3 as i32 {
-        return Err(ValidationError::InvalidHashScheme);
-    }
+    let hash_scheme = proto::HashScheme::try_from(hash_scheme)
+        .ok()
+        .filter(|scheme| message_hash_schemes(network).contains(scheme))
+        .ok_or(ValidationError::InvalidHashScheme)?;
 
     if data_bytes.len() == 0 {
         return Err(ValidationError::MissingData);
     }
 
-    let result = blake3_20(data_bytes);
+    let result =
+        hash_message_data(hash_scheme, data_bytes).ok_or(ValidationError::InvalidHashScheme)?;
     if bytes_compare(&result, hash) != 0 {
         return Err(ValidationError::InvalidHash);
     }
@@ -1,7 +1,7 @@
 mod tests {
     use crate::core::util::calculate_message_hash;
     use crate::core::validations::error::ValidationError;
-    use crate::core::validations::message::validate_message;
+    use crate::core::validations::message::{validate_message, validate_message_signature};
     use crate::proto::{self};
     use crate::proto::{CastId, FarcasterNetwork};
     use crate::storage::store::test_helper;
@@ -147,6 +147,33 @@ mod tests {
         assert_validation_error(&msg, ValidationError::InvalidHash);
     }
 
+    #[test]
+    fn test_validates_declared_hash_against_recomputed_hash() {
+        for network in [
+            FarcasterNetwork::Mainnet,
+            FarcasterNetwork::Testnet,
+            FarcasterNetwork::Devnet,
+        ] {
+            let msg = valid_message();
+            assert_eq!(validate_message(&msg, network), Ok(()));
+            assert_eq!(validate_message_signature(&msg, network), Ok(()));
+
+            // Signed over the tampered hash, so only the hash is wrong
+            let mut tampered = msg.clone();
+            tampered.hash[0] ^= 0xff;
+            let signer = test_helper::default_signer();
+            tampered.signature = signer.sign(&tampered.hash).to_bytes().to_vec();
+            assert_eq!(
+                validate_message(&tampered, network),
+                Err(ValidationError::InvalidHash)
+            );
+            assert_eq!(
+                validate_message_signature(&tampered, network),
+                Err(ValidationError::InvalidHash)
+            );
+        }
+    }
+
     #[test]
     fn validates_signature_scheme() {
         let mut msg = valid_message();
@@ -157,6 +184,11 @@ mod tests {
 
         msg.signature_scheme = 2;
         assert_validation_error(&msg, ValidationError::InvalidSignatureScheme);
+
+        // Declared as ed25519 but signed by an eth address
+        let mut msg = valid_message();
+        msg.signer = vec![1; 20];
+        assert_validation_error(&msg, ValidationError::InvalidSignatureScheme);
     }
 
     #[test]
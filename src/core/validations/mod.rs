@@ -3,10 +3,12 @@ use error::ValidationError;
 use crate::proto::CastId;
 
 pub mod cast;
+pub mod custom;
 pub mod error;
 pub mod link;
 pub mod message;
 pub mod reaction;
+pub mod signature_scheme;
 pub mod verification;
 
 #[cfg(test)]
@@ -24,6 +26,9 @@ mod reaction_test;
 #[cfg(test)]
 mod link_test;
 
+#[cfg(test)]
+mod signature_scheme_test;
+
 pub fn validate_fid(fid: u64) -> Result<(), ValidationError> {
     match fid {
         0 => Err(ValidationError::InvalidData),
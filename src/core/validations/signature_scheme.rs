@@ -0,0 +1,120 @@
+use crate::core::validations::error::ValidationError;
+use crate::proto;
+
+/// A curve that messages and verification claims can be signed with. What gets signed and what
+/// identifies the signer differs per curve: ed25519 signs the raw bytes and is identified by its
+/// public key, while secp256k1 signs a 32 byte prehash and is identified by the eth address the
+/// signature recovers to.
+pub trait SignatureScheme: Sync {
+    fn name(&self) -> &'static str;
+
+    // Length of the public key or address that identifies the signer
+    fn key_len(&self) -> usize;
+
+    fn verify(&self, message: &[u8], signature: &[u8], key: &[u8]) -> Result<(), ValidationError>;
+}
+
+pub struct Ed25519;
+
+impl SignatureScheme for Ed25519 {
+    fn name(&self) -> &'static str {
+        "ed25519"
+    }
+
+    fn key_len(&self) -> usize {
+        ed25519_dalek::PUBLIC_KEY_LENGTH
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8], key: &[u8]) -> Result<(), ValidationError> {
+        let sig = ed25519_dalek::Signature::from_slice(signature)
+            .map_err(|_| ValidationError::InvalidSignature)?;
+        let public_key = ed25519_dalek::VerifyingKey::try_from(key)
+            .map_err(|_| ValidationError::MissingOrInvalidSigner)?;
+
+        public_key
+            .verify_strict(message, &sig)
+            .map_err(|_| ValidationError::InvalidSignature)
+    }
+}
+
+pub struct Secp256k1;
+
+impl SignatureScheme for Secp256k1 {
+    fn name(&self) -> &'static str {
+        "secp256k1"
+    }
+
+    fn key_len(&self) -> usize {
+        20
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8], key: &[u8]) -> Result<(), ValidationError> {
+        if message.len() != 32 {
+            return Err(ValidationError::InvalidHash);
+        }
+
+        if signature.len() != 65 {
+            return Err(ValidationError::InvalidSignature);
+        }
+
+        // Wallets use either 0/1 or 27/28 for the recovery id
+        let signature = alloy_primitives::PrimitiveSignature::from_bytes_and_parity(
+            &signature[0..64],
+            signature[64] != 0x1b && signature[64] != 0x00,
+        );
+
+        let recovered = signature
+            .recover_address_from_prehash(&alloy_primitives::B256::from_slice(message))
+            .map_err(|_| ValidationError::InvalidSignature)?;
+
+        if recovered.as_slice() != key {
+            return Err(ValidationError::InvalidSignature);
+        }
+
+        Ok(())
+    }
+}
+
+const SCHEMES: [&dyn SignatureScheme; 2] = [&Ed25519, &Secp256k1];
+
+pub fn for_signature_scheme(
+    signature_scheme: proto::SignatureScheme,
+) -> Option<&'static dyn SignatureScheme> {
+    match signature_scheme {
+        proto::SignatureScheme::Ed25519 => Some(&Ed25519),
+        proto::SignatureScheme::Eip712 => Some(&Secp256k1),
+        proto::SignatureScheme::None => None,
+    }
+}
+
+// Only EOA claims are covered for ethereum, contract wallet claims are verified against the
+// chain outside of consensus
+pub fn for_protocol(protocol: proto::Protocol) -> &'static dyn SignatureScheme {
+    match protocol {
+        proto::Protocol::Ethereum => &Secp256k1,
+        proto::Protocol::Solana => &Ed25519,
+    }
+}
+
+/// Verifies the signature with the given scheme, after checking that the key is of the type the
+/// scheme expects. A key that belongs to a different scheme (e.g. an eth address on a message
+/// declared as ed25519) is rejected as a scheme mismatch rather than a bad signature.
+pub fn verify_signature(
+    scheme: &dyn SignatureScheme,
+    message: &[u8],
+    signature: &[u8],
+    key: &[u8],
+) -> Result<(), ValidationError> {
+    if signature.is_empty() {
+        return Err(ValidationError::MissingSignature);
+    }
+
+    if key.len() != scheme.key_len() {
+        if SCHEMES.iter().any(|other| other.key_len() == key.len()) {
+            return Err(ValidationError::InvalidSignatureScheme);
+        }
+        return Err(ValidationError::MissingOrInvalidSigner);
+    }
+
+    scheme.verify(message, signature, key)
+}
@@ -0,0 +1,126 @@
+mod tests {
+    use crate::core::validations::error::ValidationError;
+    use crate::core::validations::signature_scheme::{
+        for_protocol, for_signature_scheme, verify_signature, Ed25519, Secp256k1,
+    };
+    use crate::proto;
+
+    // RFC 8032 section 7.1, tests 1 and 2
+    const ED25519_VECTORS: [(&str, &str, &str); 2] = [
+        (
+            "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+            "",
+            "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+        ),
+        (
+            "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+            "72",
+            "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00",
+        ),
+    ];
+
+    // Address, keccak256 prehash and 65 byte signature with a 27/28 recovery id
+    const SECP256K1_VECTOR: (&str, &str, &str) = (
+        "5d75763bf37916938ffd4caa7b67662a1a96d411",
+        "7f47cfea450917b9d0b0ed5ab1428b8e4882ec23618ff7897874d9437e0d122f",
+        "d409c6a9120beb9de200b328c24571fe12fb5c4a7d4a9cac38b79c489fe76b7f7358965e76cab5c4bfe50636414b672fbe9c268554756372810cd4f3aaf09ac11c",
+    );
+
+    fn decode(vector: (&str, &str, &str)) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+        (
+            hex::decode(vector.0).unwrap(),
+            hex::decode(vector.1).unwrap(),
+            hex::decode(vector.2).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_ed25519_vectors() {
+        for vector in ED25519_VECTORS {
+            let (key, message, signature) = decode(vector);
+            assert_eq!(
+                verify_signature(&Ed25519, &message, &signature, &key),
+                Ok(())
+            );
+
+            let mut tampered = signature.clone();
+            tampered[0] ^= 1;
+            assert_eq!(
+                verify_signature(&Ed25519, &message, &tampered, &key),
+                Err(ValidationError::InvalidSignature)
+            );
+        }
+    }
+
+    #[test]
+    fn test_secp256k1_vector() {
+        let (address, hash, signature) = decode(SECP256K1_VECTOR);
+        assert_eq!(
+            verify_signature(&Secp256k1, &hash, &signature, &address),
+            Ok(())
+        );
+
+        // 0/1 recovery ids are accepted as well as 27/28
+        let mut signature_with_parity = signature.clone();
+        signature_with_parity[64] -= 27;
+        assert_eq!(
+            verify_signature(&Secp256k1, &hash, &signature_with_parity, &address),
+            Ok(())
+        );
+
+        let mut other_address = address.clone();
+        other_address[0] ^= 1;
+        assert_eq!(
+            verify_signature(&Secp256k1, &hash, &signature, &other_address),
+            Err(ValidationError::InvalidSignature)
+        );
+
+        assert_eq!(
+            verify_signature(&Secp256k1, &hash[..31], &signature, &address),
+            Err(ValidationError::InvalidHash)
+        );
+    }
+
+    #[test]
+    fn test_rejects_key_of_another_scheme() {
+        let (ed25519_key, message, ed25519_signature) = decode(ED25519_VECTORS[1]);
+        let (address, hash, secp256k1_signature) = decode(SECP256K1_VECTOR);
+
+        assert_eq!(
+            verify_signature(&Ed25519, &hash, &secp256k1_signature, &address),
+            Err(ValidationError::InvalidSignatureScheme)
+        );
+        assert_eq!(
+            verify_signature(&Secp256k1, &message, &ed25519_signature, &ed25519_key),
+            Err(ValidationError::InvalidSignatureScheme)
+        );
+        assert_eq!(
+            verify_signature(&Ed25519, &message, &ed25519_signature, &[1; 16]),
+            Err(ValidationError::MissingOrInvalidSigner)
+        );
+        assert_eq!(
+            verify_signature(&Ed25519, &message, &[], &ed25519_key),
+            Err(ValidationError::MissingSignature)
+        );
+    }
+
+    #[test]
+    fn test_scheme_dispatch() {
+        assert_eq!(
+            for_signature_scheme(proto::SignatureScheme::Ed25519)
+                .unwrap()
+                .name(),
+            "ed25519"
+        );
+        assert_eq!(
+            for_signature_scheme(proto::SignatureScheme::Eip712)
+                .unwrap()
+                .name(),
+            "secp256k1"
+        );
+        assert!(for_signature_scheme(proto::SignatureScheme::None).is_none());
+
+        assert_eq!(for_protocol(proto::Protocol::Ethereum).name(), "secp256k1");
+        assert_eq!(for_protocol(proto::Protocol::Solana).name(), "ed25519");
+    }
+}
@@ -1,4 +1,5 @@
 use crate::core::validations::error::ValidationError;
+use crate::core::validations::signature_scheme::{self, verify_signature};
 use crate::proto::{self, VerificationAddAddressBody};
 use alloy_dyn_abi::TypedData;
 use alloy_provider::Provider;
@@ -141,22 +142,12 @@ pub fn validate_fname_transfer(transfer: &proto::FnameTransfer) -> Result<(), Va
 
     let hash = prehash.unwrap();
     let fname_signer = alloy_primitives::address!("Bc5274eFc266311015793d89E9B591fa46294741");
-    let signature = alloy_primitives::PrimitiveSignature::from_bytes_and_parity(
-        &proof.signature[0..64],
-        proof.signature[64] != 0x1b && proof.signature[64] != 0x00,
-    );
-
-    let recovered_address = signature.recover_address_from_prehash(&hash);
-    if recovered_address.is_err() {
-        return Err(ValidationError::InvalidSignature);
-    }
-
-    let recovered = recovered_address.unwrap();
-    if recovered != fname_signer {
-        return Err(ValidationError::InvalidSignature);
-    }
-
-    Ok(())
+    verify_signature(
+        &signature_scheme::Secp256k1,
+        hash.as_slice(),
+        &proof.signature,
+        fname_signer.as_slice(),
+    )
 }
 
 fn validate_eth_address(address: &Vec<u8>) -> Result<&Vec<u8>, ValidationError> {
@@ -240,22 +231,12 @@ fn validate_verification_eoa_signature(
     }
 
     let hash = prehash.unwrap();
-    let signature = alloy_primitives::PrimitiveSignature::from_bytes_and_parity(
-        &body.claim_signature[0..64],
-        body.claim_signature[64] != 0x1b && body.claim_signature[64] != 0x00,
-    );
-
-    let recovered_address = signature.recover_address_from_prehash(&hash);
-    if recovered_address.is_err() {
-        return Err(ValidationError::InvalidSignature);
-    }
-
-    let recovered = recovered_address.unwrap().to_vec();
-    if recovered != body.address {
-        return Err(ValidationError::InvalidSignature);
-    }
-
-    Ok(())
+    verify_signature(
+        signature_scheme::for_protocol(proto::Protocol::Ethereum),
+        hash.as_slice(),
+        &body.claim_signature,
+        &body.address,
+    )
 }
 
 pub async fn validate_verification_contract_signature<P, T>(
@@ -433,20 +414,17 @@ fn validate_verification_add_sol_address_signature(
 
     let full_message = recreate_solana_claim_message(reconstructed_claim.unwrap());
 
-    let public_key =
-        ed25519_dalek::VerifyingKey::from_bytes(body.address.as_slice().try_into().unwrap());
-
-    if public_key.is_err() {
-        return Err(ValidationError::InvalidData);
-    }
-
-    let signature =
-        ed25519_dalek::Signature::from_bytes(body.claim_signature.as_slice().try_into().unwrap());
-
-    match public_key.unwrap().verify_strict(&full_message, &signature) {
-        Ok(_) => Ok(()),
-        Err(_) => Err(ValidationError::InvalidSignature),
-    }
+    verify_signature(
+        signature_scheme::for_protocol(proto::Protocol::Solana),
+        &full_message,
+        &body.claim_signature,
+        &body.address,
+    )
+    .map_err(|err| match err {
+        // The address is a well formed length but not a valid ed25519 public key
+        ValidationError::MissingOrInvalidSigner => ValidationError::InvalidData,
+        err => err,
+    })
 }
 
 fn validate_add_eth_address(
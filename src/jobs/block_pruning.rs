@@ -1,4 +1,5 @@
 use crate::core::util;
+use crate::jobs::registry::JobRegistry;
 use crate::storage::constants::PAGE_SIZE_MAX;
 use crate::storage::db::PageOptions;
 use crate::storage::store::stores::Stores;
@@ -17,12 +18,14 @@ pub fn block_pruning_job(
     block_store: BlockStore,
     shard_stores: HashMap<u32, Stores>,
     sync_complete_rx: watch::Receiver<bool>,
+    job_registry: JobRegistry,
 ) -> Result<Job, JobSchedulerError> {
     Job::new_async(schedule, move |_, _| {
         // TODO: Can these clones be avoided?
         let sync_complete_rx = sync_complete_rx.clone();
         let block_store = block_store.clone();
         let shard_stores = shard_stores.clone();
+        let job_registry = job_registry.clone();
         Box::pin(async move {
             // Wait for sync to complete before pruning
             let sync_complete = *sync_complete_rx.borrow();
@@ -40,6 +43,10 @@ pub fn block_pruning_job(
                     None
                 });
 
+            // Progress is the number of blocks and chunks pruned, cancelling skips the shards
+            // that haven't started
+            let job = job_registry.start("block_pruning", Some(0), true);
+            let mut pruned = 0;
             let page_options = PageOptions {
                 page_size: Some(PAGE_SIZE_MAX),
                 ..PageOptions::default()
@@ -57,16 +64,24 @@ pub fn block_pruning_job(
                         0
                     });
                 info!("Pruned {} blocks", count);
+                pruned += count as u64;
+                job.set_progress(pruned);
             }
 
             for (shard_id, stores) in shard_stores.iter() {
-                stores
+                if job.is_cancelled() {
+                    info!(pruned, "Block pruning cancelled");
+                    return;
+                }
+                job.set_shard(*shard_id);
+                pruned += stores
                     .prune_shard_chunks_until(cutoff_timestamp, THROTTLE, None)
                     .await
                     .unwrap_or_else(|e| {
                         error!("Error pruning shard {} chunks: {}", shard_id, e);
                         0
-                    });
+                    }) as u64;
+                job.set_progress(pruned);
             }
         })
     })
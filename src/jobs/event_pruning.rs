@@ -1,30 +1,56 @@
 use crate::core::util::get_farcaster_time;
+use crate::jobs::registry::JobRegistry;
 use crate::storage::store::stores::Stores;
 use std::collections::HashMap;
 use std::time::Duration;
 use tokio_cron_scheduler::{Job, JobSchedulerError};
-use tracing::error;
+use tracing::{error, info};
 
 const THROTTLE: Duration = Duration::from_millis(100);
 
 pub fn event_pruning_job(
     schedule: &str,
     event_retention: Duration,
+    event_retention_max_events: Option<u64>,
     shard_stores: HashMap<u32, Stores>,
+    job_registry: JobRegistry,
 ) -> Result<Job, JobSchedulerError> {
     Job::new_async(schedule, move |_, _| {
         let shard_stores = shard_stores.clone();
+        let job_registry = job_registry.clone();
         Box::pin(async move {
-            for (_shard_id, stores) in shard_stores.iter() {
+            // Progress is the number of events pruned, cancelling skips the shards that haven't
+            // started
+            let job = job_registry.start("event_pruning", None, true);
+            let mut pruned = 0;
+            for (shard_id, stores) in shard_stores.iter() {
+                if job.is_cancelled() {
+                    info!(pruned, "Event pruning cancelled");
+                    return;
+                }
+                job.set_shard(*shard_id);
                 let cutoff_timestamp =
                     get_farcaster_time().unwrap() - (event_retention.as_secs() as u64);
-                stores
+                pruned += stores
                     .prune_events_until(cutoff_timestamp, THROTTLE, None)
                     .await
                     .unwrap_or_else(|e| {
                         error!("Error pruning events: {}", e);
                         0
-                    });
+                    }) as u64;
+                if let Some(max_events) = event_retention_max_events {
+                    pruned += stores
+                        .prune_events_over(max_events, THROTTLE, None)
+                        .await
+                        .unwrap_or_else(|e| {
+                            error!("Error pruning events: {}", e);
+                            0
+                        }) as u64;
+                }
+                job.set_progress(pruned);
+                if let Err(e) = stores.report_event_log_metrics() {
+                    error!("Error reporting event log metrics: {}", e);
+                }
             }
         })
     })
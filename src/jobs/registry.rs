@@ -0,0 +1,220 @@
+use crate::proto;
+use crate::utils::statsd_wrapper::StatsdClientWrapper;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+use tracing::info;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum CancelJobError {
+    #[error("no running job {0}")]
+    NotFound(u64),
+
+    #[error("job {0} can't be cancelled")]
+    NotCancellable(u64),
+}
+
+struct RunningJob {
+    job_type: &'static str,
+    shard_id: Option<u32>,
+    progress: u64,
+    started_at: u64,
+    // Unset for jobs that can't be stopped part way
+    cancelled: Option<Arc<AtomicBool>>,
+}
+
+#[derive(Default)]
+struct Jobs {
+    next_id: u64,
+    running: BTreeMap<u64, RunningJob>,
+}
+
+/// The background jobs running on the node, like snapshot uploads, pruning and index rebuilds.
+/// Each job registers when it starts and is removed when its handle is dropped. Cancelling a job
+/// only asks it to stop, it's up to the job to check its handle between batches.
+#[derive(Clone)]
+pub struct JobRegistry {
+    jobs: Arc<Mutex<Jobs>>,
+    statsd_client: StatsdClientWrapper,
+}
+
+impl JobRegistry {
+    pub fn new(statsd_client: StatsdClientWrapper) -> Self {
+        JobRegistry {
+            jobs: Arc::new(Mutex::new(Jobs::default())),
+            statsd_client,
+        }
+    }
+
+    /// Registers a job, which runs until the handle is dropped. The shard is left unset for jobs
+    /// covering several shards until they move on to one.
+    pub fn start(
+        &self,
+        job_type: &'static str,
+        shard_id: Option<u32>,
+        cancellable: bool,
+    ) -> JobHandle {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let started_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.next_id += 1;
+        let id = jobs.next_id;
+        jobs.running.insert(
+            id,
+            RunningJob {
+                job_type,
+                shard_id,
+                progress: 0,
+                started_at,
+                cancelled: cancellable.then(|| cancelled.clone()),
+            },
+        );
+        self.statsd_client
+            .gauge("jobs.active", jobs.running.len() as u64);
+        JobHandle {
+            id,
+            cancelled,
+            registry: self.clone(),
+        }
+    }
+
+    pub fn jobs(&self) -> Vec<proto::BackgroundJob> {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.running
+            .iter()
+            .map(|(id, job)| proto::BackgroundJob {
+                job_id: *id,
+                job_type: job.job_type.to_string(),
+                shard_id: job.shard_id,
+                progress: job.progress,
+                started_at: job.started_at,
+                cancellable: job.cancelled.is_some(),
+                cancel_requested: job
+                    .cancelled
+                    .as_ref()
+                    .is_some_and(|cancelled| cancelled.load(Ordering::Relaxed)),
+            })
+            .collect()
+    }
+
+    pub fn cancel(&self, job_id: u64) -> Result<(), CancelJobError> {
+        let jobs = self.jobs.lock().unwrap();
+        let job = jobs
+            .running
+            .get(&job_id)
+            .ok_or(CancelJobError::NotFound(job_id))?;
+        let cancelled = job
+            .cancelled
+            .as_ref()
+            .ok_or(CancelJobError::NotCancellable(job_id))?;
+        cancelled.store(true, Ordering::Relaxed);
+        info!(job_id, job_type = job.job_type, "Cancelling background job");
+        Ok(())
+    }
+
+    fn update(&self, job_id: u64, update: impl FnOnce(&mut RunningJob)) {
+        if let Some(job) = self.jobs.lock().unwrap().running.get_mut(&job_id) {
+            update(job);
+        }
+    }
+
+    fn finish(&self, job_id: u64) {
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.running.remove(&job_id);
+        self.statsd_client
+            .gauge("jobs.active", jobs.running.len() as u64);
+    }
+}
+
+pub struct JobHandle {
+    id: u64,
+    cancelled: Arc<AtomicBool>,
+    registry: JobRegistry,
+}
+
+impl JobHandle {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// How far along the job is, in units of its own, e.g. the messages indexed so far
+    pub fn set_progress(&self, progress: u64) {
+        self.registry.update(self.id, |job| job.progress = progress);
+    }
+
+    pub fn set_shard(&self, shard_id: u32) {
+        self.registry
+            .update(self.id, |job| job.shard_id = Some(shard_id));
+    }
+}
+
+impl Drop for JobHandle {
+    fn drop(&mut self) {
+        self.registry.finish(self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::store::test_helper;
+
+    #[test]
+    fn test_jobs_are_listed_until_they_finish() {
+        let registry = JobRegistry::new(test_helper::statsd_client());
+        let rebuild = registry.start("rebuild_index", Some(1), true);
+        let compaction = registry.start("compact_trie", Some(2), false);
+        rebuild.set_progress(1_000);
+
+        let jobs = registry.jobs();
+        assert_eq!(
+            jobs.iter()
+                .map(|job| (
+                    job.job_id,
+                    job.job_type.as_str(),
+                    job.shard_id,
+                    job.progress
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (rebuild.id(), "rebuild_index", Some(1), 1_000),
+                (compaction.id(), "compact_trie", Some(2), 0),
+            ]
+        );
+        assert!(jobs[0].started_at > 0);
+
+        assert_eq!(
+            registry.cancel(compaction.id()),
+            Err(CancelJobError::NotCancellable(compaction.id()))
+        );
+        assert!(!rebuild.is_cancelled());
+        registry.cancel(rebuild.id()).unwrap();
+        assert!(rebuild.is_cancelled());
+        // Still listed until the job notices and stops
+        assert!(registry.jobs()[0].cancel_requested);
+
+        let rebuild_id = rebuild.id();
+        drop(rebuild);
+        assert_eq!(
+            registry
+                .jobs()
+                .iter()
+                .map(|job| job.job_id)
+                .collect::<Vec<_>>(),
+            vec![compaction.id()]
+        );
+        assert_eq!(
+            registry.cancel(rebuild_id),
+            Err(CancelJobError::NotFound(rebuild_id))
+        );
+    }
+}
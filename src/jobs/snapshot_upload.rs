@@ -1,77 +1,130 @@
+use crate::jobs::registry::JobRegistry;
 use crate::proto::FarcasterNetwork;
 use crate::storage;
-use crate::storage::db::snapshot::{clear_old_snapshots, SnapshotError};
+use crate::storage::db::snapshot::{
+    clear_old_snapshots, record_in_manifest, stream_db_to_s3, SnapshotError,
+};
 use crate::storage::db::RocksDB;
 use crate::storage::store::stores::Stores;
 use crate::storage::store::BlockStore;
 use crate::utils::statsd_wrapper::StatsdClientWrapper;
-use std::collections::HashMap;
+use itertools::Itertools;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
 use tokio_cron_scheduler::{Job, JobSchedulerError};
-use tracing::{error, info};
+use tracing::{error, info, warn};
+
+// How often the block shard's height is checked for milestones
+const MILESTONE_POLL_INTERVAL: Duration = Duration::from_secs(1);
 
 async fn backup_and_upload(
     fc_network: FarcasterNetwork,
     snapshot_config: storage::db::snapshot::Config,
     shard_id: u32,
     db: Arc<RocksDB>,
+    block_height: Option<u64>,
+    milestone_height: Option<u64>,
     now: i64,
     statsd_client: StatsdClientWrapper,
 ) -> Result<(), SnapshotError> {
-    let backup_dir = snapshot_config.backup_dir.clone();
-    let tar_gz_path = RocksDB::backup_db(db, &backup_dir, shard_id, now)?;
-    storage::db::snapshot::upload_to_s3(
-        fc_network,
-        tar_gz_path,
-        &snapshot_config,
-        shard_id,
-        &statsd_client,
-    )
-    .await?;
+    let metadata = if snapshot_config.stream_upload {
+        stream_db_to_s3(
+            fc_network,
+            db,
+            &snapshot_config,
+            shard_id,
+            block_height,
+            milestone_height,
+            &statsd_client,
+        )
+        .await?
+    } else {
+        let backup_dir = snapshot_config.backup_dir.clone();
+        let tar_gz_path = RocksDB::backup_db(db, &backup_dir, shard_id, now)?;
+        storage::db::snapshot::upload_to_s3(
+            fc_network,
+            tar_gz_path,
+            &snapshot_config,
+            shard_id,
+            block_height,
+            milestone_height,
+            &statsd_client,
+        )
+        .await?
+    };
+    // Record the new snapshot before the old one is deleted, so the manifest never points to
+    // a snapshot that's gone
+    record_in_manifest(fc_network, &snapshot_config, now, shard_id, metadata).await?;
     clear_old_snapshots(fc_network, &snapshot_config, shard_id).await?;
     Ok(())
 }
 
+// Uploads snapshots for the given shards, 0 being the block shard. Shards without stores are
+// ignored. Cancelling the job skips the shards that haven't started uploading. The milestone is
+// recorded in the metadata of uploads triggered by snapshot_every_n_blocks.
 pub async fn upload_snapshot(
     snapshot_config: storage::db::snapshot::Config,
     fc_network: FarcasterNetwork,
     block_store: BlockStore,
     shard_stores: HashMap<u32, Stores>,
+    shard_ids: Vec<u32>,
+    milestone_height: Option<u64>,
     statsd_client: StatsdClientWrapper,
+    job_registry: JobRegistry,
 ) -> Result<(), SnapshotError> {
     if std::fs::exists(snapshot_config.backup_dir.clone())? {
         return Err(SnapshotError::UploadAlreadyInProgress);
     }
+    // Progress is the number of shards done
+    let job = job_registry.start("snapshot_upload", None, true);
 
     let now = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
         .as_millis();
 
-    if let Err(err) = backup_and_upload(
-        fc_network,
-        snapshot_config.clone(),
-        0,
-        block_store.db.clone(),
-        now as i64,
-        statsd_client.clone(),
-    )
-    .await
-    {
-        error!(
-            shard = 0,
-            "Unable to upload snapshot for shard {}",
-            err.to_string()
+    if shard_ids.contains(&0) {
+        job.set_shard(0);
+        if let Err(err) = backup_and_upload(
+            fc_network,
+            snapshot_config.clone(),
+            0,
+            block_store.db.clone(),
+            block_store.max_block_number().ok(),
+            milestone_height,
+            now as i64,
+            statsd_client.clone(),
         )
+        .await
+        {
+            error!(
+                shard = 0,
+                "Unable to upload snapshot for shard {}",
+                err.to_string()
+            )
+        }
+        job.set_progress(1);
     }
 
-    for (shard, stores) in shard_stores.iter() {
+    let mut shards_done = shard_ids.contains(&0) as u64;
+    for (shard, stores) in shard_stores
+        .iter()
+        .filter(|(shard, _)| shard_ids.contains(shard))
+    {
+        if job.is_cancelled() {
+            info!(shards_done, "Snapshot upload cancelled");
+            break;
+        }
+        job.set_shard(*shard);
         if let Err(err) = backup_and_upload(
             fc_network,
             snapshot_config.clone(),
             *shard,
             stores.db.clone(),
+            stores.shard_store.max_block_number().ok(),
+            milestone_height,
             now as i64,
             statsd_client.clone(),
         )
@@ -83,6 +136,8 @@ pub async fn upload_snapshot(
                 err.to_string()
             );
         }
+        shards_done += 1;
+        job.set_progress(shards_done);
     }
 
     if let Err(err) = std::fs::remove_dir_all(snapshot_config.backup_dir.clone()) {
@@ -92,26 +147,61 @@ pub async fn upload_snapshot(
     Ok(())
 }
 
-pub fn snapshot_upload_job(
+pub fn all_shard_ids(shard_stores: &HashMap<u32, Stores>) -> Vec<u32> {
+    let mut shard_ids = vec![0];
+    shard_ids.extend(shard_stores.keys().sorted());
+    shard_ids
+}
+
+// Groups shards by their configured schedule, leaving out excluded shards
+pub fn shards_by_schedule(
+    snapshot_config: &storage::db::snapshot::Config,
+    shard_ids: &[u32],
+) -> BTreeMap<String, Vec<u32>> {
+    let mut schedules: BTreeMap<String, Vec<u32>> = BTreeMap::new();
+    for shard_id in shard_ids {
+        if let Some(schedule) = snapshot_config.schedule_for_shard(*shard_id) {
+            schedules
+                .entry(schedule.to_string())
+                .or_default()
+                .push(*shard_id);
+        }
+    }
+    schedules
+}
+
+fn snapshot_upload_job(
     schedule: &str,
+    shard_ids: Vec<u32>,
+    upload_lock: Arc<Mutex<()>>,
     snapshot_config: storage::db::snapshot::Config,
     fc_network: FarcasterNetwork,
     block_store: BlockStore,
     shard_stores: HashMap<u32, Stores>,
     statsd_client: StatsdClientWrapper,
+    job_registry: JobRegistry,
 ) -> Result<Job, JobSchedulerError> {
     Job::new_async(schedule, move |_, _| {
+        let shard_ids = shard_ids.clone();
+        let upload_lock = upload_lock.clone();
         let snapshot_config = snapshot_config.clone();
         let block_store = block_store.clone();
         let shard_stores = shard_stores.clone();
         let statsd_client = statsd_client.clone();
+        let job_registry = job_registry.clone();
         Box::pin(async move {
+            // Schedules can overlap, wait for the other upload instead of failing
+            let _guard = upload_lock.lock().await;
+            info!(?shard_ids, "Starting scheduled snapshot upload");
             if let Err(err) = upload_snapshot(
                 snapshot_config,
                 fc_network,
                 block_store,
                 shard_stores,
+                shard_ids,
+                None,
                 statsd_client,
+                job_registry,
             )
             .await
             {
@@ -120,3 +210,151 @@ pub fn snapshot_upload_job(
         })
     })
 }
+
+/// One upload job per distinct schedule, so shards that change a lot can be snapshotted more
+/// often than quiet ones. Uploads holding upload_lock wait for each other.
+pub fn snapshot_upload_jobs(
+    snapshot_config: storage::db::snapshot::Config,
+    fc_network: FarcasterNetwork,
+    block_store: BlockStore,
+    shard_stores: HashMap<u32, Stores>,
+    upload_lock: Arc<Mutex<()>>,
+    statsd_client: StatsdClientWrapper,
+    job_registry: JobRegistry,
+) -> Result<Vec<Job>, JobSchedulerError> {
+    let schedules = shards_by_schedule(&snapshot_config, &all_shard_ids(&shard_stores));
+    schedules
+        .into_iter()
+        .map(|(schedule, shard_ids)| {
+            snapshot_upload_job(
+                &schedule,
+                shard_ids,
+                upload_lock.clone(),
+                snapshot_config.clone(),
+                fc_network,
+                block_store.clone(),
+                shard_stores.clone(),
+                statsd_client.clone(),
+                job_registry.clone(),
+            )
+        })
+        .collect()
+}
+
+/// Tracks the block heights that are a multiple of every_n_blocks. The first height seen is only
+/// a baseline, so a restarted node doesn't upload for a milestone it crossed before stopping.
+pub struct HeightMilestones {
+    every_n_blocks: u64,
+    last_milestone: Option<u64>,
+}
+
+impl HeightMilestones {
+    pub fn new(every_n_blocks: u64) -> Self {
+        HeightMilestones {
+            every_n_blocks,
+            last_milestone: None,
+        }
+    }
+
+    // The latest milestone at or below height, if it's past the last one. Milestones skipped
+    // between two calls are only reported once, as the latest.
+    pub fn crossed(&mut self, height: u64) -> Option<u64> {
+        if self.every_n_blocks == 0 {
+            return None;
+        }
+        let milestone = height / self.every_n_blocks * self.every_n_blocks;
+        match self.last_milestone {
+            Some(last) if milestone > last => {
+                self.last_milestone = Some(milestone);
+                Some(milestone)
+            }
+            Some(_) => None,
+            None => {
+                self.last_milestone = Some(milestone);
+                None
+            }
+        }
+    }
+}
+
+/// Uploads a snapshot of every scheduled shard each time the block shard crosses a multiple of
+/// snapshot_every_n_blocks. The block shard's height is the one consensus decides on, so each
+/// snapshot covers the shards as of about the same point. A milestone crossed while another
+/// upload holds upload_lock is skipped rather than queued, since the next one isn't far.
+pub async fn snapshot_milestone_uploads(
+    snapshot_config: storage::db::snapshot::Config,
+    fc_network: FarcasterNetwork,
+    block_store: BlockStore,
+    shard_stores: HashMap<u32, Stores>,
+    upload_lock: Arc<Mutex<()>>,
+    statsd_client: StatsdClientWrapper,
+    job_registry: JobRegistry,
+) {
+    let shard_ids: Vec<u32> = all_shard_ids(&shard_stores)
+        .into_iter()
+        .filter(|shard_id| snapshot_config.schedule_for_shard(*shard_id).is_some())
+        .collect();
+    let mut milestones = HeightMilestones::new(snapshot_config.snapshot_every_n_blocks);
+    let mut interval = tokio::time::interval(MILESTONE_POLL_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let Ok(height) = block_store.max_block_number() else {
+            continue;
+        };
+        let Some(milestone) = milestones.crossed(height) else {
+            continue;
+        };
+        let Ok(_guard) = upload_lock.try_lock() else {
+            warn!(
+                milestone,
+                "Skipping snapshot for height milestone, an upload is in progress"
+            );
+            statsd_client.count("snapshots.milestone_skipped", 1);
+            continue;
+        };
+        info!(
+            milestone,
+            ?shard_ids,
+            "Starting snapshot upload for height milestone"
+        );
+        if let Err(err) = upload_snapshot(
+            snapshot_config.clone(),
+            fc_network,
+            block_store.clone(),
+            shard_stores.clone(),
+            shard_ids.clone(),
+            Some(milestone),
+            statsd_client.clone(),
+            job_registry.clone(),
+        )
+        .await
+        {
+            error!(milestone, "Error uploading snapshots {}", err.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crossing_the_interval_triggers_a_snapshot() {
+        let mut milestones = HeightMilestones::new(100);
+        // The height the node starts at is the baseline, even right on a multiple
+        assert_eq!(milestones.crossed(200), None);
+        assert_eq!(milestones.crossed(250), None);
+        assert_eq!(milestones.crossed(299), None);
+        assert_eq!(milestones.crossed(300), Some(300));
+        assert_eq!(milestones.crossed(301), None);
+        assert_eq!(milestones.crossed(300), None);
+        // Several milestones passed between polls upload once, for the latest
+        assert_eq!(milestones.crossed(612), Some(600));
+        assert_eq!(milestones.crossed(650), None);
+
+        let mut disabled = HeightMilestones::new(0);
+        assert_eq!(disabled.crossed(0), None);
+        assert_eq!(disabled.crossed(1_000), None);
+    }
+}
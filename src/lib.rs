@@ -1,4 +1,5 @@
 pub mod cfg;
+pub mod client;
 pub mod connectors;
 pub mod consensus;
 pub mod core;
@@ -14,4 +15,8 @@ mod tests;
 
 pub mod proto {
     tonic::include_proto!("_");
+
+    // Served by grpc reflection
+    pub const FILE_DESCRIPTOR_SET: &[u8] =
+        tonic::include_file_descriptor_set!("snapchain_descriptor");
 }
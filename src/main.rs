@@ -2,34 +2,60 @@ use hyper::server::conn::http1;
 use hyper::service::service_fn;
 use hyper_util::rt::TokioIo;
 use informalsystems_malachitebft_metrics::{Metrics, SharedRegistry};
+use snapchain::connectors::onchain_events::pause::PauseState;
+use snapchain::connectors::onchain_events::pending::PendingEvents;
+use snapchain::connectors::onchain_events::reorg::HaltState;
+use snapchain::connectors::onchain_events::retry::RetryQueue;
+use snapchain::connectors::onchain_events::supervisor::ConnectorSupervisor;
 use snapchain::connectors::onchain_events::{L1Client, OnchainEventsRequest, RealL1Client};
 use snapchain::consensus::consensus::SystemMessage;
+use snapchain::consensus::validator::StoredValidatorSets;
+use snapchain::core::custom_network;
+use snapchain::core::types::SnapchainShard;
+use snapchain::core::validations::custom::MessageValidators;
+use snapchain::jobs::registry::JobRegistry;
+use snapchain::mempool::admission::{FidAllowlist, MessageTypeAdmission, StateAdmissionRules};
 use snapchain::mempool::mempool::{Mempool, MempoolRequest, ReadNodeMempool};
 use snapchain::mempool::routing;
+use snapchain::network::admin_audit::AdminAuditLayer;
 use snapchain::network::admin_server::MyAdminService;
+use snapchain::network::committed_messages::CommittedMessages;
+use snapchain::network::debug_server::MyDebugService;
 use snapchain::network::gossip::{GossipEvent, SnapchainGossip};
 use snapchain::network::http_server::HubHttpServiceImpl;
+use snapchain::network::reflection;
+use snapchain::network::rpc_compression;
+use snapchain::network::rpc_timeout::RpcTimeoutLayer;
 use snapchain::network::server::MyHubService;
+use snapchain::network::sync_progress::SyncProgress;
+use snapchain::network::vote_history::VoteHistory;
+use snapchain::node::read_replica::{CatchUpStatus, ReadReplica};
+use snapchain::node::shutdown::{shutdown, ShutdownSignal};
 use snapchain::node::snapchain_node::SnapchainNode;
 use snapchain::node::snapchain_read_node::SnapchainReadNode;
+use snapchain::proto;
 use snapchain::proto::admin_service_server::AdminServiceServer;
-use snapchain::proto::hub_service_server::HubServiceServer;
-use snapchain::storage::db::snapshot::download_snapshots;
+use snapchain::proto::debug_service_server::DebugServiceServer;
+use snapchain::storage::db::disk_space::DiskSpaceGuard;
+use snapchain::storage::db::snapshot::{bootstrap_shard, download_snapshots, BootstrapOutcome};
 use snapchain::storage::db::RocksDB;
 use snapchain::storage::store::engine::Senders;
 use snapchain::storage::store::node_local_state::LocalStateStore;
-use snapchain::storage::store::stores::Stores;
+use snapchain::storage::store::stores::{StoreLimits, Stores};
+use snapchain::storage::store::validator_stakes::ValidatorStakes;
 use snapchain::storage::store::BlockStore;
-use snapchain::utils::statsd_wrapper::StatsdClientWrapper;
+use snapchain::utils::latency_histograms::LatencyHistograms;
+use snapchain::utils::statsd_wrapper::{udp_statsd_client, StatsdClientWrapper};
 use std::collections::{HashMap, HashSet};
 use std::error::Error;
+use std::fs;
 use std::net::SocketAddr;
 use std::process;
 use std::sync::Arc;
-use std::{fs, net};
 use tokio::net::TcpListener;
 use tokio::select;
 use tokio::signal::ctrl_c;
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::{broadcast, mpsc, watch};
 use tokio_cron_scheduler::JobScheduler;
 use tonic::transport::Server;
@@ -38,33 +64,92 @@ use tracing_subscriber::EnvFilter;
 
 const VERSION: Option<&str> = option_env!("CARGO_PKG_VERSION");
 
+// Read replicas have no gossip
 async fn start_servers(
     app_config: &snapchain::cfg::Config,
-    mut gossip: SnapchainGossip,
+    gossip: Option<SnapchainGossip>,
     mempool_tx: mpsc::Sender<MempoolRequest>,
     shutdown_tx: mpsc::Sender<()>,
+    shutdown_signal: ShutdownSignal,
     onchain_events_request_tx: mpsc::Sender<OnchainEventsRequest>,
     statsd_client: StatsdClientWrapper,
     shard_stores: HashMap<u32, Stores>,
     shard_senders: HashMap<u32, Senders>,
     block_store: BlockStore,
     l1_client: Option<Box<dyn L1Client>>,
+    onchain_events_halt: HaltState,
+    onchain_events_pause: PauseState,
+    onchain_events_retries: RetryQueue,
+    onchain_events_pending: PendingEvents,
+    message_type_admission: MessageTypeAdmission,
+    fid_allowlist: FidAllowlist,
+    admin_audit_layer: AdminAuditLayer,
+    job_registry: JobRegistry,
+    read_replica: Option<CatchUpStatus>,
 ) {
     let grpc_addr = app_config.rpc_address.clone();
     let grpc_socket_addr: SocketAddr = grpc_addr.parse().unwrap();
 
-    let admin_service = MyAdminService::new(
+    let mut admin_service = MyAdminService::new(
         app_config.admin_rpc_auth.clone(),
         mempool_tx.clone(),
         onchain_events_request_tx.clone(),
+        onchain_events_pause.clone(),
+        message_type_admission.clone(),
+        app_config.onchain_events.accept_submitted_events,
+        app_config.onchain_events.chain_id,
         shard_stores.clone(),
         block_store.clone(),
         app_config.snapshot.clone(),
         app_config.fc_network,
         statsd_client.clone(),
-    );
+    )
+    .with_job_registry(job_registry);
+    if let Some(gossip) = &gossip {
+        admin_service = admin_service.with_gossip_tx(gossip.tx.clone());
+    }
 
-    let service = Arc::new(MyHubService::new(
+    // Shard 0 is the block shard, it has no stakes and weighs its validators equally
+    let stake_epoch_length = app_config.consensus.voting_power.stake_epoch_length();
+    let validator_sets = std::iter::once(0)
+        .chain(shard_stores.keys().cloned())
+        .map(|shard_id| {
+            let stakes = shard_stores.get(&shard_id).zip(stake_epoch_length).map(
+                |(stores, epoch_length)| {
+                    ValidatorStakes::new(stores.shard_store.db.clone(), epoch_length)
+                },
+            );
+            (
+                shard_id,
+                StoredValidatorSets::from_config(
+                    SnapchainShard::new(shard_id),
+                    &app_config.consensus.get_validator_set_config(shard_id),
+                )
+                .with_stakes(stakes),
+            )
+        })
+        .collect();
+
+    // Every directory holding a db, the overrides can be on other disks
+    let disk_space_guard = DiskSpaceGuard::new(app_config.storage.disk_space.clone());
+    let db_dirs = std::iter::once(app_config.rocksdb_dir.clone())
+        .chain(app_config.storage.shard_dirs.iter().map(|o| o.dir.clone()))
+        .collect();
+    tokio::spawn(disk_space_guard.clone().run(db_dirs, statsd_client.clone()));
+
+    let (sync_progress, vote_history, peer_id) = match &gossip {
+        Some(gossip) => (
+            gossip.sync_progress.clone(),
+            gossip.vote_history.clone(),
+            gossip.swarm.local_peer_id().to_string(),
+        ),
+        None => (
+            SyncProgress::default(),
+            VoteHistory::default(),
+            "".to_string(),
+        ),
+    };
+    let mut service = MyHubService::new(
         app_config.rpc_auth.clone(),
         block_store.clone(),
         shard_stores.clone(),
@@ -75,21 +160,99 @@ async fn start_servers(
         Box::new(routing::ShardRouter {}),
         mempool_tx.clone(),
         l1_client,
+        onchain_events_halt,
+        onchain_events_pause,
+        onchain_events_retries,
+        validator_sets,
+        sync_progress,
+        vote_history,
         VERSION.unwrap_or("unknown").to_string(),
-        gossip.swarm.local_peer_id().to_string(),
+        peer_id,
+    )
+    .with_max_streaming_subscribers(app_config.max_streaming_subscribers)
+    .with_message_type_admission(message_type_admission)
+    .with_fid_allowlist(fid_allowlist)
+    .with_state_admission(StateAdmissionRules::from_config(&app_config.mempool))
+    .with_write_stall_config(app_config.storage.write_stall.clone())
+    .with_disk_space_guard(disk_space_guard)
+    .with_onchain_events_chain_id(app_config.onchain_events.chain_id)
+    .with_pending_onchain_events(onchain_events_pending)
+    .with_pending_dependencies(!app_config.mempool.pending_dependencies_ttl.is_zero())
+    .with_rate_limits(app_config.mempool.enable_rate_limits)
+    .with_submission_sequences(app_config.submission_sequence.clone())
+    .with_max_message_age(app_config.consensus.max_message_age())
+    .with_keypair(app_config.consensus.keypair())
+    .with_shard_roles(shard_roles(
+        app_config,
+        &shard_stores,
+        read_replica.is_some(),
     ));
+    if let Some(catch_up_status) = read_replica {
+        service = service.with_read_replica(catch_up_status);
+    }
+    if app_config.shutdown.drain_subscribers {
+        service = service.with_shutdown_signal(shutdown_signal.clone());
+    }
+    let service = Arc::new(service);
+    service.track_shard_load();
+    let debug_service = MyDebugService::new(
+        app_config.debug_service.clone(),
+        app_config.rpc_auth.clone(),
+        mempool_tx.clone(),
+        Box::new(routing::ShardRouter {}),
+        service.clone(),
+        app_config.consensus.num_shards,
+        app_config.fc_network,
+    )
+    .with_stores(block_store.clone(), &shard_stores);
     let grpc_service = service.clone();
     let grpc_shutdown_tx = shutdown_tx.clone();
+    let grpc_shutdown_signal = shutdown_signal.clone();
+    let grpc_reflection = app_config.grpc_reflection.clone();
+    let compression = app_config.rpc_compression.clone();
+    let rpc_timeout_layer =
+        RpcTimeoutLayer::new(app_config.rpc_timeouts.clone(), statsd_client.clone());
     tokio::spawn(async move {
         info!(grpc_addr = grpc_addr, "GrpcService listening",);
-        let mut server = Server::builder().add_service(HubServiceServer::from_arc(grpc_service));
+        let mut server = Server::builder()
+            .layer(rpc_timeout_layer)
+            .layer(admin_audit_layer)
+            .add_service(rpc_compression::hub_service_server(
+                grpc_service,
+                &compression,
+            ));
 
-        if admin_service.enabled() {
+        let admin_service_enabled = admin_service.enabled();
+        if admin_service_enabled {
             let admin_service = AdminServiceServer::new(admin_service);
             server = server.add_service(admin_service);
         }
 
-        let resp = server.serve(grpc_socket_addr).await;
+        let debug_service_enabled = debug_service.enabled();
+        if debug_service_enabled {
+            info!("Debug service enabled");
+            server = server.add_service(DebugServiceServer::new(debug_service));
+        }
+
+        if grpc_reflection.enabled {
+            match reflection::reflection_services(
+                &grpc_reflection,
+                debug_service_enabled,
+                admin_service_enabled,
+            ) {
+                Ok((reflection_v1, reflection_v1alpha)) => {
+                    info!("Grpc reflection enabled");
+                    server = server
+                        .add_service(reflection_v1)
+                        .add_service(reflection_v1alpha);
+                }
+                Err(e) => error!(error = ?e, "Unable to start grpc reflection"),
+            }
+        }
+
+        let resp = server
+            .serve_with_shutdown(grpc_socket_addr, grpc_shutdown_signal.wait())
+            .await;
 
         let msg = "grpc server stopped";
         match resp {
@@ -105,6 +268,7 @@ async fn start_servers(
 
     let http_shutdown_tx = shutdown_tx.clone();
     let http_server_config = app_config.http_server.clone();
+    let latency_histograms = statsd_client.latency_histograms();
     tokio::spawn(async move {
         let listener = TcpListener::bind(http_socket_addr).await.unwrap();
         info!(http_addr = http_addr, "HttpService listening",);
@@ -113,13 +277,22 @@ async fn start_servers(
             service: service.clone(),
         };
         loop {
-            match listener.accept().await {
+            let accepted = select! {
+                accepted = listener.accept() => accepted,
+                _ = shutdown_signal.clone().wait() => {
+                    info!("HttpService stopped");
+                    return;
+                }
+            };
+            match accepted {
                 Ok((stream, _)) => {
                     let io = TokioIo::new(stream);
                     let http_server_config = http_server_config.clone();
                     let service_clone = http_service.clone();
+                    let latency_histograms = latency_histograms.clone();
                     tokio::spawn(async move {
-                        let router = snapchain::network::http_server::Router::new(service_clone);
+                        let router = snapchain::network::http_server::Router::new(service_clone)
+                            .with_latency_histograms(latency_histograms);
                         if let Err(err) = http1::Builder::new()
                             .serve_connection(
                                 io,
@@ -142,6 +315,14 @@ async fn start_servers(
     });
 
     // Start gossip last
+    let Some(gossip) = gossip else {
+        return;
+    };
+    let mut gossip = gossip.with_committed_messages(CommittedMessages::new(
+        shard_stores,
+        app_config.consensus.num_shards,
+        Arc::new(routing::ShardRouter {}),
+    ));
     tokio::spawn(async move {
         info!("Starting gossip");
         gossip.start().await;
@@ -149,12 +330,50 @@ async fn start_servers(
     });
 }
 
+// Flushed on shutdown
+fn node_dbs(
+    block_store: &BlockStore,
+    shard_stores: &HashMap<u32, Stores>,
+    global_db: Option<Arc<RocksDB>>,
+) -> Vec<Arc<RocksDB>> {
+    std::iter::once(block_store.db.clone())
+        .chain(
+            shard_stores
+                .values()
+                .map(|stores| stores.shard_store.db.clone()),
+        )
+        .chain(global_db)
+        .collect()
+}
+
+// Read nodes and replicas only follow the shards, validators follow the ones they're configured to
+fn shard_roles(
+    app_config: &snapchain::cfg::Config,
+    shard_stores: &HashMap<u32, Stores>,
+    read_replica: bool,
+) -> HashMap<u32, proto::ShardRole> {
+    shard_stores
+        .keys()
+        .copied()
+        .chain([0])
+        .map(|shard_id| {
+            let role = if app_config.read_node || read_replica {
+                proto::ShardRole::Follower
+            } else {
+                app_config.consensus.shard_role(shard_id)
+            };
+            (shard_id, role)
+        })
+        .collect()
+}
+
 async fn schedule_background_jobs(
     app_config: &snapchain::cfg::Config,
     block_store: BlockStore,
     shard_stores: HashMap<u32, Stores>,
     sync_complete_rx: watch::Receiver<bool>,
     statsd_client: StatsdClientWrapper,
+    job_registry: JobRegistry,
 ) {
     let sched = JobScheduler::new().await.unwrap();
     let mut jobs = vec![];
@@ -167,6 +386,7 @@ async fn schedule_background_jobs(
                 block_store.clone(),
                 shard_stores.clone(),
                 sync_complete_rx,
+                job_registry.clone(),
             )
             .unwrap();
             jobs.push(job);
@@ -176,22 +396,40 @@ async fn schedule_background_jobs(
     let event_pruning_job = snapchain::jobs::event_pruning::event_pruning_job(
         "0 0 0 * * *", // midnight UTC every day
         app_config.pruning.event_retention,
+        app_config.pruning.event_retention_max_events,
         shard_stores.clone(),
+        job_registry.clone(),
     )
     .unwrap();
     jobs.push(event_pruning_job);
 
     if app_config.snapshot.snapshot_upload_enabled() {
-        let snapshot_upload_job = snapchain::jobs::snapshot_upload::snapshot_upload_job(
-            "0 0 5 * * *", // 5 AM UTC every day
+        // Shared by the scheduled and milestone uploads, so only one runs at a time
+        let upload_lock = Arc::new(tokio::sync::Mutex::new(()));
+        if app_config.snapshot.snapshot_every_n_blocks > 0 {
+            tokio::spawn(
+                snapchain::jobs::snapshot_upload::snapshot_milestone_uploads(
+                    app_config.snapshot.clone(),
+                    app_config.fc_network,
+                    block_store.clone(),
+                    shard_stores.clone(),
+                    upload_lock.clone(),
+                    statsd_client.clone(),
+                    job_registry.clone(),
+                ),
+            );
+        }
+        let snapshot_upload_jobs = snapchain::jobs::snapshot_upload::snapshot_upload_jobs(
             app_config.snapshot.clone(),
             app_config.fc_network,
             block_store,
             shard_stores,
+            upload_lock,
             statsd_client,
+            job_registry,
         )
         .unwrap();
-        jobs.push(snapshot_upload_job);
+        jobs.extend(snapshot_upload_jobs);
     }
 
     for job in jobs {
@@ -201,11 +439,105 @@ async fn schedule_background_jobs(
     sched.start().await.unwrap();
 }
 
+// Live sync starts from the restored db's height, which can be a few blocks past the height the
+// snapshot's metadata records
+fn record_snapshot_heights(
+    sync_progress: &SyncProgress,
+    restored_shard_ids: &[u32],
+    block_store: &BlockStore,
+    shard_stores: &HashMap<u32, Stores>,
+) {
+    for shard_id in restored_shard_ids {
+        let height = if *shard_id == 0 {
+            block_store.max_block_number().ok()
+        } else {
+            shard_stores
+                .get(shard_id)
+                .and_then(|stores| stores.shard_store.max_block_number().ok())
+        };
+        if let Some(height) = height {
+            sync_progress.record_snapshot_height(*shard_id, height);
+        }
+    }
+}
+
 fn is_dir_empty(path: &str) -> std::io::Result<bool> {
     let mut entries = fs::read_dir(path)?;
     Ok(entries.next().is_none())
 }
 
+async fn run_read_replica(
+    app_config: &snapchain::cfg::Config,
+    statsd_client: StatsdClientWrapper,
+    store_limits: StoreLimits,
+) -> Result<(), Box<dyn Error>> {
+    let replica = Arc::new(ReadReplica::open(
+        &app_config.read_replica,
+        &app_config.rocksdb_dir,
+        &app_config.storage,
+        &app_config.consensus.shard_ids,
+        app_config.trie_branching_factor,
+        store_limits,
+        statsd_client.clone(),
+    )?);
+    info!(
+        primary_rocksdb_dir = app_config.read_replica.primary_rocksdb_dir,
+        "Starting Snapchain read replica"
+    );
+
+    let catch_up_replica = replica.clone();
+    let catch_up_interval = app_config.read_replica.catch_up_interval;
+    tokio::spawn(async move { catch_up_replica.run(catch_up_interval).await });
+
+    // Nothing reads from these, submissions are rejected before they get to the mempool
+    let (mempool_tx, _) = mpsc::channel(1);
+    let (onchain_events_request_tx, _) = mpsc::channel(1);
+    let (shutdown_tx, mut shutdown_rx) = mpsc::channel::<()>(1);
+    let (shutdown_signal_tx, shutdown_signal) = ShutdownSignal::new();
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let admin_audit_layer = AdminAuditLayer::new(
+        &app_config.admin_audit,
+        &app_config.admin_rpc_auth,
+        statsd_client.clone(),
+    )
+    .map_err(|e| format!("Invalid admin audit config: {}", e))?;
+    let job_registry = JobRegistry::new(statsd_client.clone());
+
+    start_servers(
+        app_config,
+        None,
+        mempool_tx,
+        shutdown_tx,
+        shutdown_signal,
+        onchain_events_request_tx,
+        statsd_client,
+        replica.shard_stores.clone(),
+        HashMap::new(),
+        replica.block_store.clone(),
+        None,
+        HaltState::default(),
+        PauseState::default(),
+        RetryQueue::default(),
+        PendingEvents::default(),
+        MessageTypeAdmission::default(),
+        FidAllowlist::default(),
+        admin_audit_layer,
+        job_registry,
+        Some(replica.catch_up_status()),
+    )
+    .await;
+
+    select! {
+        _ = ctrl_c() => info!("Received Ctrl-C, shutting down"),
+        _ = sigterm.recv() => info!("Received SIGTERM, shutting down"),
+        _ = shutdown_rx.recv() => error!("Received shutdown signal, shutting down"),
+    }
+    // Nothing to flush, the replica doesn't write
+    shutdown(&app_config.shutdown, &shutdown_signal_tx, async {}, vec![]).await;
+    replica.close();
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     let args: Vec<String> = std::env::args().collect();
@@ -220,9 +552,14 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
     match app_config.log_format.as_str() {
-        "text" => tracing_subscriber::fmt().with_env_filter(env_filter).init(),
+        "pretty" | "text" => tracing_subscriber::fmt().with_env_filter(env_filter).init(),
+        // One object per line with timestamp, level, target, fields (including the message) and
+        // the current span plus its parents, so span fields like shard_id are searchable
         "json" => tracing_subscriber::fmt()
             .json()
+            .with_target(true)
+            .with_current_span(true)
+            .with_span_list(true)
             .with_env_filter(env_filter)
             .init(),
         _ => {
@@ -230,7 +567,75 @@ async fn main() -> Result<(), Box<dyn Error>> {
         }
     }
 
+    if let Err(e) = app_config.storage.validate(app_config.fc_network) {
+        return Err(format!("Invalid storage config: {}", e).into());
+    }
+
+    if let Err(e) = app_config.consensus.validate(app_config.fc_network) {
+        return Err(format!("Invalid consensus config: {}", e).into());
+    }
+
+    if let Err(e) = app_config.snapshot.validate() {
+        return Err(format!("Invalid snapshot config: {}", e).into());
+    }
+
+    if let Err(e) = app_config.gossip.validate() {
+        return Err(format!("Invalid gossip config: {}", e).into());
+    }
+
+    if let Err(e) = app_config.custom_network.validate(app_config.fc_network) {
+        return Err(format!("Invalid custom network config: {}", e).into());
+    }
+
+    if let Err(e) = app_config.latency_histograms.validate() {
+        return Err(format!("Invalid latency histograms config: {}", e).into());
+    }
+    custom_network::set_farcaster_epoch(
+        app_config
+            .custom_network
+            .farcaster_epoch(app_config.fc_network),
+    )?;
+    custom_network::set_activation_heights(
+        app_config
+            .custom_network
+            .activation_heights(app_config.fc_network),
+    )?;
+    let store_limits = app_config
+        .custom_network
+        .store_limits(app_config.fc_network);
+
+    if app_config.statsd.prefix == "" {
+        // TODO: consider removing this check
+        return Err("statsd prefix must be specified in config".into());
+    }
+
+    // TODO: parsing to SocketAddr only allows for IPs, DNS names won't work
+    let statsd_client = udp_statsd_client(
+        app_config.statsd.addr.as_str(),
+        app_config.statsd.prefix.as_str(),
+    )?;
+    let mut statsd_client = StatsdClientWrapper::new(statsd_client, app_config.statsd.use_tags);
+    if app_config.latency_histograms.enabled {
+        statsd_client = statsd_client.with_latency_histograms(Arc::new(LatencyHistograms::new(
+            &app_config.latency_histograms.buckets_ms,
+        )));
+    }
+
+    // Serves the primary's dbs as they are, so none of the setup of a node's own dbs applies
+    if app_config.read_replica.enabled() {
+        return run_read_replica(&app_config, statsd_client, store_limits).await;
+    }
+
     if app_config.clear_db {
+        for dir_override in &app_config.storage.shard_dirs {
+            let shard_dir = format!("{}/shard-{}", dir_override.dir, dir_override.shard_id);
+            if std::path::Path::new(&shard_dir).exists() {
+                if let Err(e) = std::fs::remove_dir_all(shard_dir.clone()) {
+                    error!("Failed to clear db at {:?}: {}", shard_dir, e);
+                }
+                warn!("Cleared db at {:?}", shard_dir);
+            }
+        }
         let db_dir = format!("{}", app_config.rocksdb_dir);
         if std::path::Path::new(&db_dir).exists() {
             let remove_result = std::fs::remove_dir_all(db_dir.clone());
@@ -247,48 +652,73 @@ async fn main() -> Result<(), Box<dyn Error>> {
         }
     }
 
-    // We only use snapshots if the db directory doesn't exist or is empty.
-    // If the user sets [force_load_db_from_snapshot], load the snapshot without checking directory contents.
-    if app_config.snapshot.force_load_db_from_snapshot
+    let mut all_shard_ids = app_config.consensus.shard_ids.clone();
+    all_shard_ids.push(0);
+    // Shards restored from a snapshot on this start, reported by GetSyncStatus
+    let mut restored_shard_ids = vec![];
+
+    if app_config.bootstrap {
+        info!("Bootstrapping from snapshots");
+        for shard_id in all_shard_ids.iter().cloned() {
+            let db_dir = app_config
+                .storage
+                .shard_base_dir(&app_config.rocksdb_dir, shard_id);
+            match bootstrap_shard(
+                app_config.fc_network,
+                &app_config.snapshot,
+                &db_dir,
+                shard_id,
+            )
+            .await?
+            {
+                BootstrapOutcome::Restored(metadata) => {
+                    info!(shard_id, "Restored snapshot {}", metadata.key_base);
+                    restored_shard_ids.push(shard_id);
+                }
+                BootstrapOutcome::NoSnapshot => {
+                    warn!(
+                        shard_id,
+                        "No snapshot to restore, syncing the shard from scratch"
+                    )
+                }
+                BootstrapOutcome::ExistingData => {
+                    info!(shard_id, "Shard already has data, resuming sync")
+                }
+            }
+        }
+    } else if app_config.snapshot.force_load_db_from_snapshot
         || (app_config.snapshot.load_db_from_snapshot
             && (!fs::exists(app_config.rocksdb_dir.clone()).unwrap()
                 || is_dir_empty(&app_config.rocksdb_dir).unwrap()))
     {
+        // Without bootstrapping, we only use snapshots if the db directory doesn't exist or is empty.
+        // If the user sets [force_load_db_from_snapshot], load the snapshot without checking directory contents.
         info!("Downloading snapshots");
-        let mut shard_ids = app_config.consensus.shard_ids.clone();
-        shard_ids.push(0);
-        for shard_id in shard_ids {
+        for shard_id in all_shard_ids.iter().cloned() {
             // Raise if the download fails. If there's a persistent issue, disable snapshot download.
             download_snapshots(
                 app_config.fc_network,
                 &app_config.snapshot,
-                app_config.rocksdb_dir.clone(),
+                app_config
+                    .storage
+                    .shard_base_dir(&app_config.rocksdb_dir, shard_id),
                 shard_id,
             )
             .await
             .unwrap();
+            restored_shard_ids.push(shard_id);
         }
     };
 
-    if app_config.statsd.prefix == "" {
-        // TODO: consider removing this check
-        return Err("statsd prefix must be specified in config".into());
-    }
-
-    // TODO: parsing to SocketAddr only allows for IPs, DNS names won't work
-    let (statsd_host, statsd_port) = match app_config.statsd.addr.parse::<SocketAddr>() {
-        Ok(addr) => Ok((addr.ip().to_string(), addr.port())),
-        Err(e) => Err(format!("invalid statsd address: {}", e)),
-    }?;
-
-    let host = (statsd_host, statsd_port);
-    let socket = net::UdpSocket::bind("0.0.0.0:0").unwrap();
-    let sink = cadence::UdpMetricSink::from(host, socket)?;
-    let statsd_client =
-        cadence::StatsdClient::builder(app_config.statsd.prefix.as_str(), sink).build();
-    let statsd_client = StatsdClientWrapper::new(statsd_client, app_config.statsd.use_tags);
-
-    let block_db = RocksDB::open_shard_db(app_config.rocksdb_dir.as_str(), 0);
+    let block_db = RocksDB::open_shard_db(
+        app_config
+            .storage
+            .shard_base_dir(&app_config.rocksdb_dir, 0)
+            .as_str(),
+        0,
+        &app_config.storage.network_namespace,
+        app_config.storage.shard_write_durability(0),
+    );
     let block_store = BlockStore::new(block_db);
     info!(
         "Block db height {}",
@@ -332,6 +762,9 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let gossip_tx = gossip.tx.clone();
 
     let (shutdown_tx, mut shutdown_rx) = mpsc::channel::<()>(1);
+    // Tells the components taking new work to stop, once the node starts shutting down
+    let (shutdown_signal_tx, shutdown_signal) = ShutdownSignal::new();
+    let mut sigterm = signal(SignalKind::terminate())?;
 
     let registry = SharedRegistry::global();
     // Use the new non-global metrics registry when we upgrade to newer version of malachite
@@ -347,6 +780,23 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let (sync_complete_tx, sync_complete_rx) = watch::channel(false);
 
     let (onchain_events_request_tx, onchain_events_request_rx) = mpsc::channel(100);
+    let onchain_events_halt = HaltState::default();
+    let onchain_events_pause = PauseState::default();
+    let onchain_events_retries = RetryQueue::default();
+    let onchain_events_pending = PendingEvents::default();
+    let message_type_admission =
+        MessageTypeAdmission::from_config(&app_config.mempool.disabled_message_types)
+            .map_err(|e| format!("Invalid mempool config: {}", e))?;
+    let fid_allowlist =
+        FidAllowlist::from_config(&app_config.mempool.allowed_fids, app_config.fc_network)
+            .map_err(|e| format!("Invalid mempool config: {}", e))?;
+    let admin_audit_layer = AdminAuditLayer::new(
+        &app_config.admin_audit,
+        &app_config.admin_rpc_auth,
+        statsd_client.clone(),
+    )
+    .map_err(|e| format!("Invalid admin audit config: {}", e))?;
+    let job_registry = JobRegistry::new(statsd_client.clone());
 
     if app_config.read_node {
         let node = SnapchainReadNode::create(
@@ -358,9 +808,13 @@ async fn main() -> Result<(), Box<dyn Error>> {
             messages_request_tx,
             block_store.clone(),
             app_config.rocksdb_dir.clone(),
+            app_config.storage.clone(),
             statsd_client.clone(),
             app_config.trie_branching_factor,
             app_config.fc_network,
+            store_limits,
+            MessageValidators::default(),
+            app_config.tolerate_shard_failures,
             registry,
         )
         .await;
@@ -371,9 +825,17 @@ async fn main() -> Result<(), Box<dyn Error>> {
             node.shard_stores.clone(),
             sync_complete_rx,
             statsd_client.clone(),
+            job_registry.clone(),
         )
         .await;
 
+        record_snapshot_heights(
+            &gossip.sync_progress,
+            &restored_shard_ids,
+            &block_store,
+            &node.shard_stores,
+        );
+
         let mut mempool = ReadNodeMempool::new(
             mempool_rx,
             app_config.consensus.num_shards,
@@ -385,29 +847,45 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
         start_servers(
             &app_config,
-            gossip,
+            Some(gossip),
             mempool_tx,
             shutdown_tx,
+            shutdown_signal.clone(),
             onchain_events_request_tx,
             statsd_client,
             node.shard_stores.clone(),
             node.shard_senders.clone(),
             block_store.clone(),
             l1_client,
+            onchain_events_halt.clone(),
+            onchain_events_pause.clone(),
+            onchain_events_retries.clone(),
+            onchain_events_pending.clone(),
+            message_type_admission.clone(),
+            fid_allowlist.clone(),
+            admin_audit_layer.clone(),
+            job_registry.clone(),
+            None,
         )
         .await;
 
+        let dbs = node_dbs(&block_store, &node.shard_stores, None);
         let mut shards_finished_syncing = HashSet::new();
         loop {
             select! {
                 _ = ctrl_c() => {
                     info!("Received Ctrl-C, shutting down");
-                    node.stop();
+                    shutdown(&app_config.shutdown, &shutdown_signal_tx, node.stop_and_wait(), dbs.clone()).await;
+                    return Ok(());
+                }
+                _ = sigterm.recv() => {
+                    info!("Received SIGTERM, shutting down");
+                    shutdown(&app_config.shutdown, &shutdown_signal_tx, node.stop_and_wait(), dbs.clone()).await;
                     return Ok(());
                 }
                 _ = shutdown_rx.recv() => {
                     error!("Received shutdown signal, shutting down");
-                    node.stop();
+                    shutdown(&app_config.shutdown, &shutdown_signal_tx, node.stop_and_wait(), dbs.clone()).await;
                     return Ok(());
                 }
                 Some(msg) = system_rx.recv() => {
@@ -415,8 +893,9 @@ async fn main() -> Result<(), Box<dyn Error>> {
                         SystemMessage::ReadNodeFinishedInitialSync {shard_id} => {
                             info!({shard_id}, "Initial sync completed for shard");
                             shards_finished_syncing.insert(shard_id);
-                            // [num_shards] doesn't account for the block shard, so account for it manually
-                            if shards_finished_syncing.len() as u32 == app_config.consensus.num_shards + 1 {
+                            // The block shard isn't in [shard_stores], so account for it manually. Unavailable
+                            // shards never sync, so only the shards the node serves are waited for.
+                            if shards_finished_syncing.len() == node.shard_stores.len() + 1 {
                                 info!("Initial sync completed for all shards");
 
                                 if let Err(err) = sync_complete_tx.send(true)
@@ -447,23 +926,33 @@ async fn main() -> Result<(), Box<dyn Error>> {
     } else {
         let (shard_decision_tx, shard_decision_rx) = broadcast::channel(100);
 
-        let global_db = RocksDB::open_global_db(&app_config.rocksdb_dir);
-        let local_state_store = LocalStateStore::new(global_db);
+        let global_db = RocksDB::open_global_db(
+            &app_config.rocksdb_dir,
+            &app_config.storage.network_namespace,
+            app_config.storage.write_durability,
+        );
+        let local_state_store = LocalStateStore::new(global_db.clone());
 
         let node = SnapchainNode::create(
             keypair.clone(),
             app_config.consensus.clone(),
             local_peer_id,
             gossip_tx.clone(),
+            system_tx.clone(),
+            gossip.sync_progress.clone(),
             shard_decision_tx,
             None,
             messages_request_tx,
             block_store.clone(),
             local_state_store.clone(),
             app_config.rocksdb_dir.clone(),
+            app_config.storage.clone(),
             statsd_client.clone(),
             app_config.trie_branching_factor,
             app_config.fc_network,
+            store_limits,
+            MessageValidators::default(),
+            app_config.tolerate_shard_failures,
             registry,
         )
         .await;
@@ -474,9 +963,17 @@ async fn main() -> Result<(), Box<dyn Error>> {
             node.shard_stores.clone(),
             sync_complete_rx,
             statsd_client.clone(),
+            job_registry.clone(),
         )
         .await;
 
+        record_snapshot_heights(
+            &gossip.sync_progress,
+            &restored_shard_ids,
+            &block_store,
+            &node.shard_stores,
+        );
+
         let mut mempool = Mempool::new(
             app_config.mempool.clone(),
             mempool_rx,
@@ -486,7 +983,9 @@ async fn main() -> Result<(), Box<dyn Error>> {
             gossip_tx.clone(),
             shard_decision_rx,
             statsd_client.clone(),
-        );
+        )
+        .with_message_type_admission(message_type_admission.clone())
+        .with_fid_allowlist(fid_allowlist.clone());
         tokio::spawn(async move { mempool.run().await });
 
         if !app_config.fnames.disable {
@@ -510,29 +1009,45 @@ async fn main() -> Result<(), Box<dyn Error>> {
                     statsd_client.clone(),
                     local_state_store,
                     onchain_events_request_rx,
+                    onchain_events_halt.clone(),
+                    onchain_events_pause.clone(),
+                    onchain_events_retries.clone(),
+                    onchain_events_pending.clone(),
                 )?;
+            // Refuse to start rather than ingest events from the wrong chain
+            onchain_events_subscriber.verify_chain_id().await?;
+            let supervisor = ConnectorSupervisor::new(
+                app_config.onchain_events.max_connector_restarts,
+                app_config.onchain_events.connector_restart_backoff,
+                onchain_events_halt.clone(),
+                statsd_client.clone(),
+            );
             tokio::spawn(async move {
-                let result = onchain_events_subscriber.run().await;
-                match result {
-                    Ok(()) => {}
-                    Err(e) => {
-                        error!("Error subscribing to on chain events {:#?}", e);
-                    }
-                }
+                supervisor.run(&mut onchain_events_subscriber).await;
             });
         }
 
         start_servers(
             &app_config,
-            gossip,
+            Some(gossip),
             mempool_tx.clone(),
             shutdown_tx.clone(),
+            shutdown_signal.clone(),
             onchain_events_request_tx,
             statsd_client,
             node.shard_stores.clone(),
             node.shard_senders.clone(),
             block_store.clone(),
             l1_client,
+            onchain_events_halt.clone(),
+            onchain_events_pause.clone(),
+            onchain_events_retries.clone(),
+            onchain_events_pending.clone(),
+            message_type_admission.clone(),
+            fid_allowlist.clone(),
+            admin_audit_layer.clone(),
+            job_registry.clone(),
+            None,
         )
         .await;
 
@@ -547,13 +1062,16 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 .for_each(|(shard_id, shard_store)| {
                     dbs.insert(*shard_id, shard_store.shard_store.db.clone());
                 });
+            let job_registry = job_registry.clone();
             tokio::spawn(async move {
                 info!(
                     "Backing up {:?} shard databases to {:?}",
                     shard_ids, app_config.snapshot.backup_dir
                 );
+                let job = job_registry.start("startup_backup", None, false);
                 let timestamp = chrono::Utc::now().timestamp_millis();
-                dbs.iter().for_each(|(shard_id, db)| {
+                for (i, (shard_id, db)) in dbs.iter().enumerate() {
+                    job.set_shard(*shard_id);
                     RocksDB::backup_db(
                         db.clone(),
                         &app_config.snapshot.backup_dir,
@@ -561,21 +1079,29 @@ async fn main() -> Result<(), Box<dyn Error>> {
                         timestamp,
                     )
                     .unwrap();
-                });
+                    job.set_progress(i as u64 + 1);
+                }
             });
         }
 
+        let dbs = node_dbs(&block_store, &node.shard_stores, Some(global_db));
+        let mut followers_finished_syncing = HashSet::new();
         // Kick it off
         loop {
             select! {
                 _ = ctrl_c() => {
                     info!("Received Ctrl-C, shutting down");
-                    node.stop();
+                    shutdown(&app_config.shutdown, &shutdown_signal_tx, node.stop_and_wait(), dbs.clone()).await;
+                    return Ok(());
+                }
+                _ = sigterm.recv() => {
+                    info!("Received SIGTERM, shutting down");
+                    shutdown(&app_config.shutdown, &shutdown_signal_tx, node.stop_and_wait(), dbs.clone()).await;
                     return Ok(());
                 }
                 _ = shutdown_rx.recv() => {
                     error!("Received shutdown signal, shutting down");
-                    node.stop();
+                    shutdown(&app_config.shutdown, &shutdown_signal_tx, node.stop_and_wait(), dbs.clone()).await;
                     return Ok(());
                 }
                 Some(msg) = system_rx.recv() => {
@@ -590,12 +1116,21 @@ async fn main() -> Result<(), Box<dyn Error>> {
                                 warn!("Failed to add to local mempool: {:?}", e);
                             }
                         },
-                        SystemMessage::DecidedValueForReadNode(_) => {
-                            // Ignore these for validator nodes
+                        SystemMessage::DecidedValueForReadNode(decided_value) => {
+                            // Only the shards the node follows use these
+                            node.dispatch_decided_value(decided_value);
                         }
-                        SystemMessage::ReadNodeFinishedInitialSync{shard_id: _} => {
-                            // Ignore these for validator nodes
-                            sync_complete_tx.send(true)?; // TODO: is this necessary?
+                        SystemMessage::ReadNodeFinishedInitialSync{shard_id} => {
+                            // Sent by the shards the node follows
+                            info!({shard_id}, "Initial sync completed for follower shard");
+                            followers_finished_syncing.insert(shard_id);
+                            // They then follow the decided values gossiped for read nodes
+                            if followers_finished_syncing.len() == node.follower_actors.len() {
+                                if let Err(err) =
+                                gossip_tx.send(GossipEvent::SubscribeToDecidedValuesTopic()).await {
+                                    panic!("Could not send sync complete message to gossip: {}", err.to_string());
+                                }
+                            }
                         }
                     }
                 }
@@ -0,0 +1,278 @@
+use crate::core::error::HubError;
+use crate::proto::{self, FarcasterNetwork, MessageType};
+use crate::storage::store::account::VerificationStore;
+use crate::storage::store::stores::Stores;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+/// Message types the node has stopped accepting, e.g. to shed one kind of traffic during an
+/// incident without stopping ingestion entirely. Set from the mempool config at startup, toggled
+/// through the admin rpc, and shared with the rpc server and the mempool, which reject submitted
+/// and gossiped messages of a disabled type. Messages already in the mempool are unaffected.
+#[derive(Clone, Default)]
+pub struct MessageTypeAdmission {
+    disabled: Arc<RwLock<HashSet<MessageType>>>,
+}
+
+impl MessageTypeAdmission {
+    /// Types are named as in the protobuf, e.g. MESSAGE_TYPE_LINK_ADD
+    pub fn from_config(disabled_message_types: &[String]) -> Result<Self, String> {
+        let admission = MessageTypeAdmission::default();
+        for name in disabled_message_types {
+            match MessageType::from_str_name(name) {
+                Some(message_type) if message_type != MessageType::None => {
+                    admission.disable(message_type);
+                }
+                _ => return Err(format!("unknown message type: {}", name)),
+            }
+        }
+        Ok(admission)
+    }
+
+    // Both return whether the state changed
+    pub fn disable(&self, message_type: MessageType) -> bool {
+        self.disabled.write().unwrap().insert(message_type)
+    }
+
+    pub fn enable(&self, message_type: MessageType) -> bool {
+        self.disabled.write().unwrap().remove(&message_type)
+    }
+
+    pub fn is_disabled(&self, message_type: MessageType) -> bool {
+        self.disabled.read().unwrap().contains(&message_type)
+    }
+
+    pub fn disabled_types(&self) -> Vec<MessageType> {
+        let mut disabled: Vec<MessageType> =
+            self.disabled.read().unwrap().iter().cloned().collect();
+        disabled.sort();
+        disabled
+    }
+
+    pub fn check(&self, message: &proto::Message) -> Result<(), HubError> {
+        let message_type = message.msg_type();
+        if self.is_disabled(message_type) {
+            return Err(HubError::unavailable(&format!(
+                "{} messages are temporarily not being accepted",
+                message_type.as_str_name()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// The fids allowed to send user messages, to keep a devnet or testnet limited to its own test
+/// accounts. Every fid is allowed when no list is configured. Mainnet can't have one, it would
+/// censor every other fid.
+#[derive(Clone, Default)]
+pub struct FidAllowlist {
+    allowed: Option<Arc<HashSet<u64>>>,
+}
+
+impl FidAllowlist {
+    pub fn from_config(allowed_fids: &[u64], network: FarcasterNetwork) -> Result<Self, String> {
+        if allowed_fids.is_empty() {
+            return Ok(FidAllowlist::default());
+        }
+        if network == FarcasterNetwork::Mainnet {
+            return Err("allowed_fids is only allowed on devnet and testnet".to_string());
+        }
+        Ok(FidAllowlist {
+            allowed: Some(Arc::new(allowed_fids.iter().cloned().collect())),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.allowed.is_some()
+    }
+
+    pub fn is_allowed(&self, fid: u64) -> bool {
+        match &self.allowed {
+            None => true,
+            Some(allowed) => allowed.contains(&fid),
+        }
+    }
+
+    pub fn check(&self, message: &proto::Message) -> Result<(), HubError> {
+        if !self.is_allowed(message.fid()) {
+            return Err(HubError::validation_failure(&format!(
+                "fid {} is not in the allowed fids of this network",
+                message.fid()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// What the mempool does with user messages from fids without a committed registration. They'd
+/// fail validation once proposed anyway, checking on the way in keeps them from being gossiped and
+/// held in the mempool until then.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnregisteredFids {
+    // Not checked on the way in
+    #[default]
+    Accept,
+    Reject,
+    // Held with the messages waiting on their onchain events, see pending_dependencies_ttl
+    Hold,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum StateAdmission {
+    Admit,
+    // Waits with the messages pending their onchain events
+    Hold,
+}
+
+pub struct StateRejection {
+    // For the metrics, e.g. fid_not_registered
+    pub reason: &'static str,
+    pub error: HubError,
+}
+
+/// The checks of user messages against the committed state of the node's shards, from the
+/// mempool config. Shared by the mempool and the rpc server, so ValidateMessage rejects the
+/// messages submission would.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StateAdmissionRules {
+    pub unregistered_fids: UnregisteredFids,
+    pub max_fids_per_verified_address: u32,
+    // Whether messages can wait for their onchain events, see pending_dependencies_ttl
+    pub hold_enabled: bool,
+}
+
+impl StateAdmissionRules {
+    pub fn from_config(config: &crate::mempool::mempool::Config) -> Self {
+        StateAdmissionRules {
+            unregistered_fids: config.unregistered_fids,
+            max_fids_per_verified_address: config.max_fids_per_verified_address,
+            hold_enabled: !config.pending_dependencies_ttl.is_zero(),
+        }
+    }
+
+    // Whether the fid is registered. Store errors are left for validation to report.
+    pub fn fid_registered(stores: &Stores, fid: u64) -> bool {
+        !matches!(
+            stores.onchain_event_store.get_id_register_event_by_fid(fid),
+            Ok(None)
+        )
+    }
+
+    // Whether the message verifies an address that max_fids_per_verified_address other fids have
+    // already verified. Store errors are left for validation to report.
+    fn verified_address_limit_reached(
+        &self,
+        shard_stores: &HashMap<u32, Stores>,
+        message: &proto::Message,
+    ) -> bool {
+        let limit = self.max_fids_per_verified_address;
+        let Some(proto::message_data::Body::VerificationAddAddressBody(body)) =
+            message.data.as_ref().and_then(|data| data.body.as_ref())
+        else {
+            return false;
+        };
+        if limit == 0 {
+            return false;
+        }
+        let mut fids = 0;
+        for stores in shard_stores.values() {
+            let store = &stores.verification_store;
+            // Verifying an address again doesn't take up another fid
+            if matches!(
+                VerificationStore::is_verified_address_fid(store, &body.address, message.fid()),
+                Ok(true)
+            ) {
+                return false;
+            }
+            fids += VerificationStore::get_verified_address_fid_count(store, &body.address)
+                .unwrap_or(0);
+        }
+        fids >= limit
+    }
+
+    pub fn check(
+        &self,
+        shard_stores: &HashMap<u32, Stores>,
+        shard_id: u32,
+        message: &proto::Message,
+    ) -> Result<StateAdmission, StateRejection> {
+        let Some(stores) = shard_stores.get(&shard_id) else {
+            return Ok(StateAdmission::Admit);
+        };
+        if self.unregistered_fids != UnregisteredFids::Accept
+            && !Self::fid_registered(stores, message.fid())
+        {
+            if self.unregistered_fids == UnregisteredFids::Hold && self.hold_enabled {
+                return Ok(StateAdmission::Hold);
+            }
+            return Err(StateRejection {
+                reason: "fid_not_registered",
+                error: HubError::validation_failure(&format!(
+                    "fid {} is not registered",
+                    message.fid()
+                )),
+            });
+        }
+
+        if self.verified_address_limit_reached(shard_stores, message) {
+            return Err(StateRejection {
+                reason: "verified_address_limit",
+                error: HubError::validation_failure(&format!(
+                    "address is already verified by the maximum of {} fids",
+                    self.max_fids_per_verified_address
+                )),
+            });
+        }
+        Ok(StateAdmission::Admit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::factory::messages_factory;
+
+    #[test]
+    fn test_message_type_admission() {
+        let admission =
+            MessageTypeAdmission::from_config(&["MESSAGE_TYPE_LINK_ADD".to_string()]).unwrap();
+        let link = messages_factory::links::create_link_add(1234, "follow", 5678, None, None);
+        let cast = messages_factory::casts::create_cast_add(1234, "test", None, None);
+
+        let err = admission.check(&link).unwrap_err();
+        assert_eq!(err.code, "unavailable");
+        assert!(admission.check(&cast).is_ok());
+
+        // A clone shares the state, as the rpc server and the mempool do
+        let shared = admission.clone();
+        assert!(shared.enable(MessageType::LinkAdd));
+        assert!(!shared.enable(MessageType::LinkAdd));
+        assert!(admission.check(&link).is_ok());
+
+        assert!(shared.disable(MessageType::CastAdd));
+        assert!(admission.check(&cast).is_err());
+        assert_eq!(admission.disabled_types(), vec![MessageType::CastAdd]);
+
+        assert!(MessageTypeAdmission::from_config(&["LINK_ADD".to_string()]).is_err());
+        assert!(MessageTypeAdmission::from_config(&["MESSAGE_TYPE_NONE".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_fid_allowlist() {
+        let allowlist = FidAllowlist::from_config(&[1234], FarcasterNetwork::Devnet).unwrap();
+        let allowed = messages_factory::casts::create_cast_add(1234, "test", None, None);
+        let other = messages_factory::casts::create_cast_add(5678, "test", None, None);
+        assert!(allowlist.check(&allowed).is_ok());
+        let err = allowlist.check(&other).unwrap_err();
+        assert_eq!(err.code, "bad_request.validation_failure");
+
+        // Without a list, any fid is allowed, on any network
+        let allowlist = FidAllowlist::from_config(&[], FarcasterNetwork::Mainnet).unwrap();
+        assert!(allowlist.check(&other).is_ok());
+
+        assert!(FidAllowlist::from_config(&[1234], FarcasterNetwork::Testnet).is_ok());
+        assert!(FidAllowlist::from_config(&[1234], FarcasterNetwork::Mainnet).is_err());
+    }
+}
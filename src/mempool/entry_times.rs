@@ -0,0 +1,89 @@
+use super::mempool::MempoolSource;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+struct Entry {
+    entered_at: Instant,
+    pulled_at: Option<Instant>,
+    source: Option<MempoolSource>,
+}
+
+/// Tracks when messages entered the mempool so we can report how long they waited before being
+/// included in a committed chunk. Entries are keyed by shard and mempool key identity and are
+/// removed when the message is committed or dropped. Messages pulled into a proposal that never
+/// gets committed are not reported back to the mempool, so pulled entries are pruned after a ttl.
+pub struct EntryTimes {
+    entries: HashMap<(u32, String), Entry>,
+}
+
+impl EntryTimes {
+    pub fn new() -> Self {
+        EntryTimes {
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    // Keeps the original entry time if the message was already tracked, e.g. when a message from
+    // an abandoned proposal is gossiped back to us
+    pub fn record(&mut self, shard_id: u32, identity: String, now: Instant) {
+        let entry = self.entries.entry((shard_id, identity)).or_insert(Entry {
+            entered_at: now,
+            pulled_at: None,
+            source: None,
+        });
+        entry.pulled_at = None;
+    }
+
+    pub fn mark_pulled(&mut self, shard_id: u32, identity: String, now: Instant) {
+        if let Some(entry) = self.entries.get_mut(&(shard_id, identity)) {
+            entry.pulled_at = Some(now);
+        }
+    }
+
+    // Remembers where the message came from, so a submitted message's progress can be logged
+    // against its rpc, and a requeued message is added back the way it first was
+    pub fn set_source(&mut self, shard_id: u32, identity: String, source: MempoolSource) {
+        if let Some(entry) = self.entries.get_mut(&(shard_id, identity)) {
+            entry.source = Some(source);
+        }
+    }
+
+    pub fn source(&self, shard_id: u32, identity: String) -> Option<MempoolSource> {
+        self.entries
+            .get(&(shard_id, identity))
+            .and_then(|entry| entry.source.clone())
+    }
+
+    pub fn request_id(&self, shard_id: u32, identity: String) -> Option<String> {
+        match self.source(shard_id, identity) {
+            Some(MempoolSource::RPC(request_id)) => request_id,
+            _ => None,
+        }
+    }
+
+    // Stops tracking the message and returns how long it was in the mempool
+    pub fn take(&mut self, shard_id: u32, identity: String, now: Instant) -> Option<Duration> {
+        self.entries
+            .remove(&(shard_id, identity))
+            .map(|entry| now.saturating_duration_since(entry.entered_at))
+    }
+
+    pub fn remove(&mut self, shard_id: u32, identity: String) {
+        self.entries.remove(&(shard_id, identity));
+    }
+
+    // Drops messages that were pulled more than [ttl] ago without being committed. Returns the
+    // number of entries dropped.
+    pub fn prune_pulled(&mut self, now: Instant, ttl: Duration) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|_, entry| match entry.pulled_at {
+            None => true,
+            Some(pulled_at) => now.saturating_duration_since(pulled_at) < ttl,
+        });
+        before - self.entries.len()
+    }
+}
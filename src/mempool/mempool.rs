@@ -5,8 +5,8 @@ use serde::{Deserialize, Serialize};
 use std::cmp::PartialEq;
 use std::sync::Arc;
 use std::{
-    collections::{BTreeMap, HashMap},
-    time::Duration,
+    collections::{BTreeMap, HashMap, HashSet},
+    time::{Duration, Instant},
 };
 use tokio::sync::{broadcast, mpsc, oneshot};
 
@@ -24,21 +24,39 @@ use crate::{
                 get_message_by_key, make_message_primary_key, make_ts_hash, type_to_set_postfix,
                 UserDataStore,
             },
-            engine::MempoolMessage,
+            engine::{BlockLimits, MempoolMessage},
             stores::Stores,
         },
     },
-    utils::statsd_wrapper::StatsdClientWrapper,
+    utils::{latency_histograms::Latency, statsd_wrapper::StatsdClientWrapper},
 };
 
+use super::admission::{
+    FidAllowlist, MessageTypeAdmission, StateAdmission, StateAdmissionRules, UnregisteredFids,
+};
+use super::entry_times::EntryTimes;
+use super::pending::PendingDependencies;
+use super::priority::MessagePriority;
 use super::routing::{MessageRouter, ShardRouter};
+use super::spill::{message_size, MempoolSpill};
 use governor::{Quota, RateLimiter};
 use moka::sync::{Cache, CacheBuilder};
 use std::num::NonZeroU32;
-use tracing::{error, warn};
+use tracing::{debug, error, info, warn};
 
 type DirectRateLimiter = RateLimiter<NotKeyed, InMemoryState, QuantaClock>;
 
+// Proposals are decided within a few seconds, so anything pulled longer ago than this was in a
+// proposal that didn't get committed
+const PULLED_ENTRY_TTL: Duration = Duration::from_secs(60 * 10);
+const ENTRY_TIMES_PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+// Subscribers further behind than this are dropped
+const EVENTS_CHANNEL_CAPACITY: usize = 1000;
+
+// With rate limits on, an fid can submit the larger of this and a tenth of the messages its
+// storage allows each hour
+pub const MIN_MESSAGES_PER_HOUR: u32 = 100;
+
 pub struct RateLimitsConfig {
     pub time_to_idle: Duration,
     pub max_capacity: u64,
@@ -98,7 +116,7 @@ impl RateLimits {
             } else {
                 // If we update the quota, we should update [time_to_idle] accordingly
                 Some(Arc::new(RateLimiter::direct(Quota::per_hour(
-                    NonZeroU32::new(100.max(storage_allowance / 10)).unwrap(),
+                    NonZeroU32::new(MIN_MESSAGES_PER_HOUR.max(storage_allowance / 10)).unwrap(),
                 ))))
             }
         })
@@ -124,6 +142,34 @@ pub struct Config {
     pub capacity_per_shard: u64,
     pub rx_poll_interval: Duration,
     pub enable_rate_limits: bool,
+    // When enabled, lower priority messages are moved to an on-disk overflow db once the
+    // in-memory mempool grows past [spill_threshold_bytes], and moved back as blocks drain the
+    // mempool. This bounds memory usage at the cost of disk reads on the block proposal path
+    // whenever spilled messages need to be reloaded.
+    pub spill_enabled: bool,
+    pub spill_threshold_bytes: u64,
+    pub spill_dir: String,
+    // The mempool stops taking messages once this many bytes are spilled, like it does once a
+    // shard holds [capacity_per_shard] messages in memory and on disk
+    pub spill_capacity_bytes: u64,
+    // Submitted and gossiped messages of these types are rejected, named as in the protobuf, e.g.
+    // MESSAGE_TYPE_LINK_ADD. Can be changed at runtime through the admin rpc.
+    pub disabled_message_types: Vec<String>,
+    // User messages from fids whose registration or signer hasn't been committed yet wait up to
+    // this long for it, instead of being rejected. Off when zero.
+    #[serde(with = "humantime_serde")]
+    pub pending_dependencies_ttl: Duration,
+    pub pending_dependencies_capacity: usize,
+    // Only user messages from these fids are accepted, all fids when empty. Devnet and testnet
+    // only.
+    pub allowed_fids: Vec<u64>,
+    // Whether user messages from fids that aren't registered yet are accepted, rejected or held.
+    // Holding needs pending_dependencies_ttl, they're rejected without it.
+    pub unregistered_fids: UnregisteredFids,
+    // Verifications of an address already verified by this many other fids are rejected. Only
+    // the fids on this node's shards are counted. Unlimited when zero, the default, since the
+    // count is only right once the node's index of verified addresses is rebuilt.
+    pub max_fids_per_verified_address: u32,
 }
 
 impl Default for Config {
@@ -134,6 +180,16 @@ impl Default for Config {
             capacity_per_shard: 1_000_000,
             rx_poll_interval: Duration::from_millis(1),
             enable_rate_limits: false,
+            spill_enabled: false,
+            spill_threshold_bytes: 512 * 1024 * 1024,
+            spill_dir: ".rocks.mempool".to_string(),
+            spill_capacity_bytes: 8 * 1024 * 1024 * 1024,
+            disabled_message_types: vec![],
+            pending_dependencies_ttl: Duration::ZERO,
+            pending_dependencies_capacity: 10_000,
+            allowed_fids: vec![],
+            unregistered_fids: UnregisteredFids::default(),
+            max_fids_per_verified_address: 0,
         }
     }
 }
@@ -144,9 +200,13 @@ pub enum MempoolMessageKind {
     UserMessage = 2,
 }
 
+// Messages are handed out for blocks in key order, which only depends on the messages themselves,
+// so mempools holding the same messages propose the same blocks however they arrived
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct MempoolKey {
     message_kind: MempoolMessageKind,
+    // u32::MAX minus the message's priority, so higher priorities sort first
+    priority_rank: u32,
     timestamp: u64, // in unix seconds
     identity: String,
 }
@@ -155,14 +215,33 @@ impl MempoolKey {
     pub fn new(message_kind: MempoolMessageKind, timestamp: u64, identity: String) -> Self {
         MempoolKey {
             message_kind,
+            priority_rank: u32::MAX,
             timestamp,
             identity,
         }
     }
 
+    pub fn with_priority(mut self, priority: u32) -> Self {
+        self.priority_rank = u32::MAX - priority;
+        self
+    }
+
     pub fn identity(self) -> String {
         self.identity
     }
+
+    // Byte encoding that sorts the same way as the key itself
+    pub fn encode(&self) -> Vec<u8> {
+        let message_kind = match self.message_kind {
+            MempoolMessageKind::ValidatorMessage => MempoolMessageKind::ValidatorMessage as u8,
+            MempoolMessageKind::UserMessage => MempoolMessageKind::UserMessage as u8,
+        };
+        let mut key = vec![message_kind];
+        key.extend_from_slice(&self.priority_rank.to_be_bytes());
+        key.extend_from_slice(&self.timestamp.to_be_bytes());
+        key.extend_from_slice(self.identity.as_bytes());
+        key
+    }
 }
 
 impl proto::Message {
@@ -204,7 +283,8 @@ impl proto::ValidatorMessage {
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum MempoolSource {
     Gossip,
-    RPC,
+    // With the id of the rpc that submitted the message, if it has one
+    RPC(Option<String>),
     Local,
 }
 
@@ -216,6 +296,41 @@ pub enum MempoolRequest {
         Option<oneshot::Sender<Result<(), HubError>>>,
     ),
     GetSize(oneshot::Sender<HashMap<u32, u64>>),
+    // A message the engine pulled for a proposal but left out, over the block limits
+    Requeue(MempoolMessage),
+    // Adds a message to the given shard regardless of fid routing. Only used by the debug
+    // service for testing cross shard behavior. These messages are not gossiped.
+    AddMessageToShard(
+        u32,
+        MempoolMessage,
+        Option<oneshot::Sender<Result<(), HubError>>>,
+    ),
+    // Watches the user messages going in and out of the mempool, for the debug service
+    Subscribe(oneshot::Sender<Result<MempoolSubscription, HubError>>),
+}
+
+/// The user messages in the mempool when the subscription started, and a receiver of the ones
+/// added and removed since. Messages spilled to disk aren't listed.
+pub struct MempoolSubscription {
+    pub pending: Vec<proto::MempoolEvent>,
+    pub events_rx: broadcast::Receiver<proto::MempoolEvent>,
+}
+
+fn mempool_event(
+    event_type: proto::MempoolEventType,
+    shard_id: u32,
+    message: &MempoolMessage,
+    removal_reason: proto::MempoolRemovalReason,
+) -> Option<proto::MempoolEvent> {
+    match message {
+        MempoolMessage::UserMessage(message) => Some(proto::MempoolEvent {
+            r#type: event_type as i32,
+            shard_id,
+            message: Some(message.clone()),
+            removal_reason: removal_reason as i32,
+        }),
+        MempoolMessage::ValidatorMessage(_) => None,
+    }
 }
 
 impl MempoolMessage {
@@ -231,6 +346,7 @@ pub struct MempoolMessagesRequest {
     pub shard_id: u32,
     pub message_tx: oneshot::Sender<Vec<MempoolMessage>>,
     pub max_messages_per_block: u32,
+    pub block_limits: BlockLimits,
 }
 
 pub struct ReadNodeMempool {
@@ -375,12 +491,28 @@ impl ReadNodeMempool {
                         }
                     }
                 }
+                MempoolRequest::AddMessageToShard(_, _, reply_to) => {
+                    if let Some(sender) = reply_to {
+                        if let Err(_) = sender.send(Err(HubError::invalid_parameter(
+                            "read nodes can't add messages to a specific shard",
+                        ))) {
+                            error!("Unable to reply to add message request from mempool");
+                        }
+                    }
+                }
                 MempoolRequest::GetSize(reply_to) => {
                     // Read nodes don't have a local mempool, so the size is always 0
                     if let Err(_) = reply_to.send(HashMap::new()) {
                         error!("Unable to reply to message size request from mempool");
                     }
                 }
+                MempoolRequest::Subscribe(reply_to) => {
+                    let _ = reply_to.send(Err(HubError::unavailable(
+                        "read nodes don't have a mempool to stream",
+                    )));
+                }
+                // Read nodes don't propose
+                MempoolRequest::Requeue(_) => {}
             }
         }
         panic!("Mempool has exited");
@@ -395,6 +527,15 @@ pub struct Mempool {
     statsd_client: StatsdClientWrapper,
     read_node_mempool: ReadNodeMempool,
     rate_limits: Option<RateLimits>,
+    spill: Option<MempoolSpill>,
+    in_memory_bytes: u64,
+    entry_times: EntryTimes,
+    entry_times_pruned_at: Instant,
+    message_type_admission: MessageTypeAdmission,
+    fid_allowlist: FidAllowlist,
+    pending_dependencies: PendingDependencies,
+    events_tx: broadcast::Sender<proto::MempoolEvent>,
+    priority: MessagePriority,
 }
 
 impl Mempool {
@@ -408,10 +549,30 @@ impl Mempool {
         shard_decision_rx: broadcast::Receiver<ShardChunk>,
         statsd_client: StatsdClientWrapper,
     ) -> Self {
+        let spill = if config.spill_enabled {
+            match MempoolSpill::open(&config.spill_dir) {
+                Ok(spill) => Some(spill),
+                Err(err) => panic!("Unable to open mempool spill db: {}", err),
+            }
+        } else {
+            None
+        };
         Mempool {
             messages: HashMap::new(),
+            spill,
+            in_memory_bytes: 0,
+            entry_times: EntryTimes::new(),
+            entry_times_pruned_at: Instant::now(),
+            message_type_admission: MessageTypeAdmission::default(),
+            fid_allowlist: FidAllowlist::default(),
+            pending_dependencies: PendingDependencies::new(
+                config.pending_dependencies_ttl,
+                config.pending_dependencies_capacity,
+            ),
             messages_request_rx,
             shard_decision_rx,
+            events_tx: broadcast::channel(EVENTS_CHANNEL_CAPACITY).0,
+            priority: MessagePriority::default(),
             rate_limits: if config.enable_rate_limits {
                 Some(RateLimits::new(
                     shard_stores.clone(),
@@ -433,6 +594,57 @@ impl Mempool {
         }
     }
 
+    pub fn with_message_type_admission(mut self, admission: MessageTypeAdmission) -> Self {
+        self.message_type_admission = admission;
+        self
+    }
+
+    pub fn with_fid_allowlist(mut self, allowlist: FidAllowlist) -> Self {
+        self.fid_allowlist = allowlist;
+        self
+    }
+
+    pub fn with_priority(mut self, priority: MessagePriority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    // Only built while someone is watching
+    fn publish(
+        &self,
+        event_type: proto::MempoolEventType,
+        shard_id: u32,
+        message: &MempoolMessage,
+        removal_reason: proto::MempoolRemovalReason,
+    ) {
+        if self.events_tx.receiver_count() == 0 {
+            return;
+        }
+        if let Some(event) = mempool_event(event_type, shard_id, message, removal_reason) {
+            let _ = self.events_tx.send(event);
+        }
+    }
+
+    fn subscribe(&self) -> MempoolSubscription {
+        // Subscribed before listing, nothing happens in between on the mempool's own loop
+        let events_rx = self.events_tx.subscribe();
+        let pending = self
+            .messages
+            .iter()
+            .flat_map(|(shard_id, shard_messages)| {
+                shard_messages.values().filter_map(|message| {
+                    mempool_event(
+                        proto::MempoolEventType::Pending,
+                        *shard_id,
+                        message,
+                        proto::MempoolRemovalReason::None,
+                    )
+                })
+            })
+            .collect();
+        MempoolSubscription { pending, events_rx }
+    }
+
     fn message_exceeds_rate_limits(&mut self, shard_id: u32, message: &MempoolMessage) -> bool {
         match message {
             MempoolMessage::UserMessage(message) => {
@@ -453,35 +665,445 @@ impl Mempool {
 
     async fn pull_messages(&mut self, request: MempoolMessagesRequest) {
         let mut messages = vec![];
+        // The chunk's transactions are grouped by fid
+        let mut fids = HashSet::new();
+        let mut bytes = 0;
         while messages.len() < request.max_messages_per_block as usize {
+            self.reload_spilled_if_first(request.shard_id);
             let shard_messages = self.messages.get_mut(&request.shard_id);
-            match shard_messages {
-                None => break,
+            let next_message = match shard_messages {
+                None => None,
                 Some(shard_messages) => {
-                    match shard_messages.pop_first() {
-                        None => break,
-                        Some((_, next_message)) => {
-                            let result = self.message_is_valid(&next_message);
-                            if result.is_ok() {
-                                messages.push(next_message);
+                    let fits = shard_messages
+                        .first_key_value()
+                        .map_or(true, |(_, message)| {
+                            request.block_limits.allows(
+                                fids.len() + !fids.contains(&message.fid()) as usize,
+                                bytes + message_size(message),
+                            )
+                        });
+                    // Messages are handed out in order, so this one and the rest wait for the
+                    // next chunk
+                    if !fits {
+                        break;
+                    }
+                    shard_messages.pop_first()
+                }
+            };
+            match next_message {
+                None => {
+                    // Memory is drained for this shard, fall back to spilled messages if any
+                    if self.reload_spilled(request.shard_id) == 0 {
+                        break;
+                    }
+                }
+                Some((key, next_message)) => {
+                    self.in_memory_bytes = self
+                        .in_memory_bytes
+                        .saturating_sub(message_size(&next_message));
+                    let result = self.message_is_valid_on_shard(request.shard_id, &next_message);
+                    let identity = key.identity();
+                    let request_id = self
+                        .entry_times
+                        .request_id(request.shard_id, identity.clone());
+                    self.publish(
+                        proto::MempoolEventType::Removed,
+                        request.shard_id,
+                        &next_message,
+                        match result {
+                            Ok(()) => proto::MempoolRemovalReason::Proposed,
+                            Err(_) => proto::MempoolRemovalReason::Invalid,
+                        },
+                    );
+                    match result {
+                        Ok(()) => {
+                            if let Some(request_id) = request_id {
+                                debug!(
+                                    request_id,
+                                    shard_id = request.shard_id,
+                                    "Pulled submitted message for a proposal"
+                                );
+                            }
+                            self.entry_times.mark_pulled(
+                                request.shard_id,
+                                identity,
+                                Instant::now(),
+                            );
+                            fids.insert(next_message.fid());
+                            bytes += message_size(&next_message);
+                            messages.push(next_message);
+                        }
+                        Err(err) => {
+                            if let Some(request_id) = request_id {
+                                info!(
+                                    request_id,
+                                    shard_id = request.shard_id,
+                                    "Dropped submitted message from the mempool: {}",
+                                    err
+                                );
                             }
+                            self.entry_times.remove(request.shard_id, identity);
                         }
-                    };
+                    }
                 }
             }
         }
+        self.reload_spilled(request.shard_id);
 
         if let Err(_) = request.message_tx.send(messages) {
             error!("Unable to send message from mempool");
         }
     }
 
+    // Messages held for the shard, in memory and spilled
+    fn shard_len(&self, shard_id: u32) -> u64 {
+        let in_memory = self
+            .messages
+            .get(&shard_id)
+            .map_or(0, |shard_messages| shard_messages.len() as u64);
+        let spilled = self.spill.as_ref().map_or(0, |spill| spill.len(shard_id));
+        in_memory + spilled
+    }
+
+    fn is_full(&self) -> bool {
+        if self.config.allow_unlimited_mempool_size {
+            return false;
+        }
+        if let Some(spill) = &self.spill {
+            if spill.bytes() >= self.config.spill_capacity_bytes
+                || spill
+                    .shard_ids()
+                    .any(|shard_id| self.shard_len(shard_id) >= self.config.capacity_per_shard)
+            {
+                return true;
+            }
+        }
+        self.messages
+            .keys()
+            .any(|shard_id| self.shard_len(*shard_id) >= self.config.capacity_per_shard)
+    }
+
+    // Moves the lowest priority messages of the largest shard to disk until the in-memory
+    // mempool is back under the threshold.
+    fn spill_if_needed(&mut self) {
+        let spill = match &mut self.spill {
+            None => return,
+            Some(spill) => spill,
+        };
+        while self.in_memory_bytes > self.config.spill_threshold_bytes {
+            let largest_shard = self
+                .messages
+                .iter_mut()
+                .max_by_key(|(_, shard_messages)| shard_messages.len());
+            let (shard_id, shard_messages) = match largest_shard {
+                None => break,
+                Some((shard_id, shard_messages)) => (*shard_id, shard_messages),
+            };
+            let (key, message) = match shard_messages.pop_last() {
+                None => break,
+                Some(entry) => entry,
+            };
+            if let Err(err) = spill.spill(shard_id, &key, &message) {
+                error!("Unable to spill mempool message: {}", err);
+                shard_messages.insert(key, message);
+                break;
+            }
+            self.in_memory_bytes = self.in_memory_bytes.saturating_sub(message_size(&message));
+            self.statsd_client
+                .count_with_shard(shard_id, "mempool.spill.spilled", 1);
+            self.statsd_client.gauge_with_shard(
+                shard_id,
+                "mempool.spill.size",
+                spill.len(shard_id),
+            );
+        }
+    }
+
+    // Moves spilled messages for the shard back into memory while there is room. Messages were
+    // validated before they were spilled, so they skip insert validation. Returns the number of
+    // messages reloaded.
+    fn reload_spilled(&mut self, shard_id: u32) -> usize {
+        let spill = match &mut self.spill {
+            None => return 0,
+            Some(spill) => spill,
+        };
+        let max_bytes = self
+            .config
+            .spill_threshold_bytes
+            .saturating_sub(self.in_memory_bytes);
+        let shard_is_empty = self
+            .messages
+            .get(&shard_id)
+            .map_or(true, |shard_messages| shard_messages.is_empty());
+        if max_bytes == 0 && !shard_is_empty {
+            return 0;
+        }
+
+        let reloaded = match spill.reload(shard_id, max_bytes) {
+            Ok(reloaded) => reloaded,
+            Err(err) => {
+                error!("Unable to reload spilled mempool messages: {}", err);
+                return 0;
+            }
+        };
+        let count = reloaded.len();
+        if count > 0 {
+            let shard_messages = self.messages.entry(shard_id).or_insert_with(BTreeMap::new);
+            for message in reloaded {
+                self.in_memory_bytes += message_size(&message);
+                shard_messages.insert(self.priority.key(&message), message);
+            }
+            self.statsd_client
+                .count_with_shard(shard_id, "mempool.spill.reloaded", count as u64);
+            self.statsd_client.gauge_with_shard(
+                shard_id,
+                "mempool.spill.size",
+                spill.len(shard_id),
+            );
+        }
+        count
+    }
+
+    // Messages inserted after others were spilled can be of lower priority than them, so the next
+    // message handed out may be on disk. Reloading it keeps the order the messages are handed out
+    // in down to their keys, whatever order they arrived in and whichever of them were spilled.
+    fn reload_spilled_if_first(&mut self, shard_id: u32) {
+        let spill = match &mut self.spill {
+            None => return,
+            Some(spill) => spill,
+        };
+        let first_spilled = match spill.first_key(shard_id) {
+            Ok(Some(first_spilled)) => first_spilled,
+            Ok(None) => return,
+            Err(err) => {
+                error!("Unable to read spilled mempool messages: {}", err);
+                return;
+            }
+        };
+        let spilled_is_first = self
+            .messages
+            .get(&shard_id)
+            .and_then(|shard_messages| shard_messages.first_key_value())
+            .map_or(true, |(key, _)| first_spilled < key.encode());
+        if !spilled_is_first {
+            return;
+        }
+        // Reloads at least the first spilled message, even without room for it
+        match spill.reload(shard_id, 0) {
+            Ok(reloaded) => {
+                let shard_messages = self.messages.entry(shard_id).or_insert_with(BTreeMap::new);
+                for message in reloaded {
+                    self.in_memory_bytes += message_size(&message);
+                    shard_messages.insert(self.priority.key(&message), message);
+                }
+                self.statsd_client
+                    .count_with_shard(shard_id, "mempool.spill.reloaded", 1);
+                self.statsd_client.gauge_with_shard(
+                    shard_id,
+                    "mempool.spill.size",
+                    spill.len(shard_id),
+                );
+            }
+            Err(err) => error!("Unable to reload spilled mempool messages: {}", err),
+        }
+    }
+
+    // Reports how long a committed message spent in the mempool. Messages we never saw, e.g.
+    // ones proposed by other validators before reaching us, are skipped.
+    fn record_inclusion_latency(&mut self, shard_id: u32, key: MempoolKey, message_type: &str) {
+        let identity = key.identity();
+        let request_id = self.entry_times.request_id(shard_id, identity.clone());
+        if let Some(latency) = self.entry_times.take(shard_id, identity, Instant::now()) {
+            let latency = latency.as_millis() as u64;
+            if let Some(request_id) = request_id {
+                info!(
+                    request_id,
+                    shard_id,
+                    latency_ms = latency,
+                    "Committed submitted message"
+                );
+            }
+            self.statsd_client.time_latency(
+                Latency::SubmitToCommit,
+                Some(shard_id),
+                Some(("message_type", message_type)),
+                latency,
+            );
+            self.statsd_client.histogram_with_shard_and_tag(
+                shard_id,
+                "mempool.inclusion_latency_distribution",
+                ("message_type", message_type),
+                latency,
+            );
+        }
+    }
+
+    fn prune_entry_times(&mut self) {
+        let now = Instant::now();
+        if now.duration_since(self.entry_times_pruned_at) < ENTRY_TIMES_PRUNE_INTERVAL {
+            return;
+        }
+        self.entry_times_pruned_at = now;
+        let pruned = self.entry_times.prune_pulled(now, PULLED_ENTRY_TTL);
+        if pruned > 0 {
+            self.statsd_client
+                .count("mempool.entry_times.pruned", pruned as i64);
+        }
+        self.statsd_client
+            .gauge("mempool.entry_times.size", self.entry_times.len() as u64);
+    }
+
+    // Whether the message was still in the mempool
+    fn remove_message(&mut self, shard_id: u32, key: &MempoolKey) -> bool {
+        if let Some(shard_messages) = self.messages.get_mut(&shard_id) {
+            if let Some(message) = shard_messages.remove(key) {
+                self.in_memory_bytes = self.in_memory_bytes.saturating_sub(message_size(&message));
+                return true;
+            }
+        }
+        if let Some(spill) = &mut self.spill {
+            match spill.remove(shard_id, key) {
+                Ok(removed) => return removed,
+                Err(err) => error!("Unable to remove spilled mempool message: {}", err),
+            }
+        }
+        false
+    }
+
+    // Whether the fid is registered and the message's signer is active, which it needs to be merged.
+    // Store errors are left for validation to report.
+    fn dependencies_satisfied(&self, shard_id: u32, message: &proto::Message) -> bool {
+        let Some(stores) = self.read_node_mempool.shard_stores.get(&shard_id) else {
+            return true;
+        };
+        let fid = message.fid();
+        StateAdmissionRules::fid_registered(stores, fid)
+            && !matches!(
+                stores
+                    .onchain_event_store
+                    .get_active_signer(fid, message.signer.clone()),
+                Ok(None)
+            )
+    }
+
+    fn hold_pending(
+        &mut self,
+        shard_id: u32,
+        message: MempoolMessage,
+        source: MempoolSource,
+    ) -> Result<(), HubError> {
+        let request_id = match &source {
+            MempoolSource::RPC(request_id) => request_id.clone(),
+            _ => None,
+        };
+        self.pending_dependencies
+            .hold(message, source, Instant::now())?;
+        if let Some(request_id) = request_id {
+            debug!(
+                request_id,
+                shard_id, "Holding submitted message until its onchain events are committed"
+            );
+        }
+        self.statsd_client
+            .count_with_shard(shard_id, "mempool.pending_dependencies.held", 1);
+        self.statsd_client.gauge(
+            "mempool.pending_dependencies.size",
+            self.pending_dependencies.len() as u64,
+        );
+        Ok(())
+    }
+
+    // Admits the held messages whose onchain events have been committed, and rejects the ones that
+    // waited too long
+    async fn admit_pending(&mut self) {
+        let (expired, ready) = self.pending_dependencies.take(Instant::now());
+        if ready.is_empty() && expired.is_empty() {
+            return;
+        }
+
+        for (fid, waiting) in ready {
+            let shard_id = self
+                .read_node_mempool
+                .message_router
+                .route_fid(fid, self.read_node_mempool.num_shards);
+            let mut still_waiting = vec![];
+            for pending in waiting {
+                let satisfied = match &pending.message {
+                    MempoolMessage::UserMessage(message) => {
+                        self.dependencies_satisfied(shard_id, message)
+                    }
+                    MempoolMessage::ValidatorMessage(_) => true,
+                };
+                if !satisfied {
+                    still_waiting.push(pending);
+                    continue;
+                }
+                let request_id = match &pending.source {
+                    MempoolSource::RPC(request_id) => request_id.clone(),
+                    _ => None,
+                };
+                match self.insert(pending.message, pending.source).await {
+                    Ok(()) => self.statsd_client.count_with_shard(
+                        shard_id,
+                        "mempool.pending_dependencies.admitted",
+                        1,
+                    ),
+                    Err(err) => {
+                        if let Some(request_id) = request_id {
+                            info!(
+                                request_id,
+                                shard_id,
+                                "Rejected submitted message once its onchain events landed: {}",
+                                err
+                            );
+                        }
+                    }
+                }
+            }
+            self.pending_dependencies.restore(fid, still_waiting);
+        }
+
+        for pending in expired {
+            let shard_id = self
+                .read_node_mempool
+                .message_router
+                .route_fid(pending.message.fid(), self.read_node_mempool.num_shards);
+            if let MempoolSource::RPC(Some(request_id)) = pending.source {
+                info!(
+                    request_id,
+                    shard_id,
+                    "Rejected submitted message, its onchain events weren't committed in time"
+                );
+            }
+            self.statsd_client.count_with_shard(
+                shard_id,
+                "mempool.pending_dependencies.expired",
+                1,
+            );
+        }
+        self.statsd_client.gauge(
+            "mempool.pending_dependencies.size",
+            self.pending_dependencies.len() as u64,
+        );
+    }
+
     pub fn message_is_valid(&mut self, message: &MempoolMessage) -> Result<(), HubError> {
         let shard = self
             .read_node_mempool
             .message_router
             .route_fid(message.fid(), self.read_node_mempool.num_shards);
+        self.message_is_valid_on_shard(shard, message)
+    }
 
+    fn message_is_valid_on_shard(
+        &mut self,
+        shard: u32,
+        message: &MempoolMessage,
+    ) -> Result<(), HubError> {
+        // The node started without the shard's stores, see tolerate_shard_failures
+        if !self.read_node_mempool.shard_stores.contains_key(&shard) {
+            return Err(HubError::unavailable("shard is unavailable on this node"));
+        }
         if self.message_already_exists(shard, message) {
             return Err(HubError::duplicate("message has already been merged"));
         }
@@ -497,6 +1119,15 @@ impl Mempool {
         &mut self,
         message: MempoolMessage,
         source: MempoolSource,
+    ) -> Result<(), HubError> {
+        self.admit(message, source, true).await
+    }
+
+    async fn admit(
+        &mut self,
+        message: MempoolMessage,
+        source: MempoolSource,
+        gossip: bool,
     ) -> Result<(), HubError> {
         let fid = message.fid();
         let shard_id = self
@@ -504,29 +1135,143 @@ impl Mempool {
             .message_router
             .route_fid(fid, self.read_node_mempool.num_shards);
 
+        // Only checked on the way in, so messages admitted before their type was disabled are
+        // still proposed
+        if let MempoolMessage::UserMessage(user_message) = &message {
+            if let Err(err) = self.message_type_admission.check(user_message) {
+                self.statsd_client.count_with_shard_and_tags(
+                    shard_id,
+                    "mempool.insert.message_type_disabled",
+                    &[("message_type", user_message.msg_type().as_str_name())],
+                    1,
+                );
+                return Err(err);
+            }
+
+            if let Err(err) = self.fid_allowlist.check(user_message) {
+                self.statsd_client
+                    .count_with_shard(shard_id, "mempool.insert.fid_not_allowed", 1);
+                return Err(err);
+            }
+
+            match StateAdmissionRules::from_config(&self.config).check(
+                &self.read_node_mempool.shard_stores,
+                shard_id,
+                user_message,
+            ) {
+                Ok(StateAdmission::Admit) => {}
+                Ok(StateAdmission::Hold) => return self.hold_pending(shard_id, message, source),
+                Err(rejection) => {
+                    self.statsd_client.count_with_shard(
+                        shard_id,
+                        &format!("mempool.insert.{}", rejection.reason),
+                        1,
+                    );
+                    return Err(rejection.error);
+                }
+            }
+
+            // Held back before validation, it needs the fid's onchain events
+            if self.pending_dependencies.is_enabled()
+                && !self.dependencies_satisfied(shard_id, user_message)
+            {
+                return self.hold_pending(shard_id, message, source);
+            }
+        }
+
+        if message.merged_on_every_shard() {
+            return self.insert_into_every_shard(message);
+        }
+
+        let result = self.insert_into_shard(shard_id, message.clone());
+        if result.is_ok() {
+            if let MempoolSource::RPC(Some(request_id)) = &source {
+                debug!(
+                    request_id,
+                    shard_id, "Added submitted message to the mempool"
+                );
+            }
+            self.entry_times
+                .set_source(shard_id, message.mempool_key().identity(), source.clone());
+            if gossip {
+                self.read_node_mempool.gossip_message(message, source).await;
+            }
+        }
+        result
+    }
+
+    // Takes back a message of a proposal it didn't fit in. It's added the way it first was, as
+    // far as the mempool still knows, but not gossiped again since other nodes got it then.
+    async fn requeue(&mut self, message: MempoolMessage) {
+        let shard_id = self
+            .read_node_mempool
+            .message_router
+            .route_fid(message.fid(), self.read_node_mempool.num_shards);
+        let source = self
+            .entry_times
+            .source(shard_id, message.mempool_key().identity())
+            .unwrap_or(MempoolSource::Gossip);
+        match self.admit(message, source, false).await {
+            Ok(()) => self
+                .statsd_client
+                .count_with_shard(shard_id, "mempool.requeued", 1),
+            Err(err) => debug!(shard_id, "Unable to requeue message: {}", err),
+        }
+    }
+
+    // Into the shards the node runs, succeeds if any of them took the message
+    fn insert_into_every_shard(&mut self, message: MempoolMessage) -> Result<(), HubError> {
+        let mut shard_ids: Vec<u32> = self
+            .read_node_mempool
+            .shard_stores
+            .keys()
+            .cloned()
+            .collect();
+        shard_ids.sort();
+        let mut result = Err(HubError::unavailable("no shards on this node"));
+        for shard_id in shard_ids {
+            let shard_result = self.insert_into_shard(shard_id, message.clone());
+            if result.is_err() {
+                result = shard_result;
+            }
+        }
+        result
+    }
+
+    fn insert_into_shard(
+        &mut self,
+        shard_id: u32,
+        message: MempoolMessage,
+    ) -> Result<(), HubError> {
+        let key = self.priority.key(&message);
         match self.messages.get_mut(&shard_id) {
             Some(shard_messages) => {
-                if shard_messages.contains_key(&message.mempool_key()) {
+                if shard_messages.contains_key(&key) {
                     // Exit early if the message already exists in the mempool
                     return Err(HubError::duplicate("message already in the mempool"));
                 }
             }
             None => {}
         }
+        if let Some(spill) = &self.spill {
+            if spill.contains(shard_id, &key) {
+                return Err(HubError::duplicate("message already in the mempool"));
+            }
+        }
 
         // TODO(aditi): Maybe we don't need to run validations here?
-        let result = self.message_is_valid(&message);
+        let result = self.message_is_valid_on_shard(shard_id, &message);
         if result.is_ok() {
             match self.messages.get_mut(&shard_id) {
                 None => {
                     let mut messages = BTreeMap::new();
-                    messages.insert(message.mempool_key(), message.clone());
+                    messages.insert(key, message.clone());
                     self.messages.insert(shard_id, messages);
                     self.statsd_client
                         .gauge_with_shard(shard_id, "mempool.size", 1);
                 }
                 Some(messages) => {
-                    messages.insert(message.mempool_key(), message.clone());
+                    messages.insert(key, message.clone());
                     self.statsd_client.gauge_with_shard(
                         shard_id,
                         "mempool.size",
@@ -535,10 +1280,19 @@ impl Mempool {
                 }
             }
 
+            self.in_memory_bytes += message_size(&message);
+            self.publish(
+                proto::MempoolEventType::Added,
+                shard_id,
+                &message,
+                proto::MempoolRemovalReason::None,
+            );
+            self.entry_times
+                .record(shard_id, message.mempool_key().identity(), Instant::now());
             self.statsd_client
                 .count_with_shard(shard_id, "mempool.insert.success", 1);
 
-            self.read_node_mempool.gossip_message(message, source).await;
+            self.spill_if_needed();
         } else {
             self.statsd_client.count("mempool.insert.failure", 1);
         }
@@ -562,14 +1316,30 @@ impl Mempool {
                         Ok(chunk) => {
                             let header = chunk.header.expect("Expects chunk to have a header");
                             let height = header.height.expect("Expects header to have a height");
-                            if let Some(mempool) = self.messages.get_mut(&height.shard_index) {
+                            // Held messages can be waiting for any of the fids' onchain events
+                            self.pending_dependencies.mark_committed();
+                            for transaction in &chunk.transactions {
+                                for system_message in &transaction.system_messages {
+                                    if let Some(onchain_event) = &system_message.on_chain_event {
+                                        self.pending_dependencies.mark_ready(onchain_event.fid);
+                                    }
+                                }
+                            }
+                            if self.messages.contains_key(&height.shard_index) {
                                 for transaction in chunk.transactions {
                                     for user_message in transaction.user_messages {
-                                        mempool.remove(&user_message.mempool_key());
+                                        let message = MempoolMessage::UserMessage(user_message.clone());
+                                        let key = self.priority.key(&message);
+                                        if self.remove_message(height.shard_index, &key) {
+                                            self.publish(proto::MempoolEventType::Removed, height.shard_index, &message, proto::MempoolRemovalReason::Committed);
+                                        }
+                                        self.record_inclusion_latency(height.shard_index, user_message.mempool_key(), user_message.msg_type().as_str_name());
                                         self.statsd_client.count_with_shard(height.shard_index, "mempool.remove.success", 1);
                                     }
                                     for system_message in transaction.system_messages {
-                                        mempool.remove(&system_message.mempool_key());
+                                        let key = self.priority.key(&MempoolMessage::ValidatorMessage(system_message.clone()));
+                                        self.remove_message(height.shard_index, &key);
+                                        self.record_inclusion_latency(height.shard_index, system_message.mempool_key(), "VALIDATOR_MESSAGE");
                                         if let Some(onchain_event) = system_message.on_chain_event
                                         {
                                             if onchain_event.r#type() == OnChainEventType::EventTypeStorageRent{
@@ -584,6 +1354,7 @@ impl Mempool {
                                     }
                                 }
                             }
+                            self.prune_entry_times();
                         },
                         Err(broadcast::error::RecvError::Closed) => {
                             panic!("Shard decision tx is closed.");
@@ -594,9 +1365,10 @@ impl Mempool {
                     }
                 }
                 _ = poll_interval.tick() => {
+                    self.admit_pending().await;
                     // We want to pull in multiple messages per poll so that throughput is not blocked on the polling frequency. The number of messages we pull should be fixed and relatively small so that the mempool isn't always stuck here.
                     for _ in 0..256 {
-                        if self.config.allow_unlimited_mempool_size || (self.messages.len() as u64) < self.config.capacity_per_shard {
+                        if !self.is_full() {
                             match self.read_node_mempool.mempool_rx.try_recv() {
                                 Ok(MempoolRequest::AddMessage(message, source, reply_to)) => {
                                     let result = self.insert(message, source).await;
@@ -606,10 +1378,26 @@ impl Mempool {
                                         }
                                     }
                                 }
+                                Ok(MempoolRequest::AddMessageToShard(shard_id, message, reply_to)) => {
+                                    let result = self.insert_into_shard(shard_id, message);
+                                    if let Some(sender) = reply_to {
+                                        if let Err(_) = sender.send(result) {
+                                            error!("Unable to reply to add message request from mempool");
+                                        }
+                                    }
+                                }
+                                Ok(MempoolRequest::Requeue(message)) => {
+                                    self.requeue(message).await;
+                                }
+                                Ok(MempoolRequest::Subscribe(reply_to)) => {
+                                    if let Err(_) = reply_to.send(Ok(self.subscribe())) {
+                                        error!("Unable to reply to subscribe request from mempool");
+                                    }
+                                }
                                 Ok(MempoolRequest::GetSize(reply_to)) => {
                                     let mut sizes = HashMap::new();
-                                    for (shard_id, messages) in &self.messages {
-                                        sizes.insert(*shard_id, messages.len() as u64);
+                                    for shard_id in self.messages.keys() {
+                                        sizes.insert(*shard_id, self.shard_len(*shard_id));
                                     }
                                     if let Err(_) = reply_to.send(sizes) {
                                         error!("Unable to reply to message size request from mempool");
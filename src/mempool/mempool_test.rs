@@ -1,20 +1,29 @@
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
+    use std::sync::Arc;
 
     use tokio::sync::{broadcast, mpsc, oneshot};
 
     use crate::{
         consensus::consensus::SystemMessage,
         core::util::to_farcaster_time,
-        mempool::mempool::{self, Mempool, MempoolMessagesRequest},
+        mempool::{
+            admission::UnregisteredFids,
+            entry_times::EntryTimes,
+            mempool::{self, Mempool, MempoolMessagesRequest},
+            pending::PendingDependencies,
+            priority::{MempoolPriority, MessagePriority},
+            spill,
+        },
         network::gossip::{Config, SnapchainGossip},
         proto::{
             self, FnameTransfer, Height, ShardChunk, ShardHeader, Transaction, UserNameProof,
             UserNameType, ValidatorMessage,
         },
+        storage::db::RocksDbTransactionBatch,
         storage::store::{
-            engine::{MempoolMessage, ShardEngine},
+            engine::{BlockLimits, MempoolMessage, ShardEngine},
             test_helper::{self, commit_event, default_storage_event, FID_FOR_TEST},
         },
         utils::{
@@ -45,6 +54,23 @@ mod tests {
         mpsc::Sender<MempoolMessagesRequest>,
         broadcast::Sender<ShardChunk>,
         mpsc::Receiver<SystemMessage>,
+    ) {
+        let mut mempool_config = mempool::Config::default();
+        mempool_config.enable_rate_limits = enable_rate_limits;
+        setup_with_mempool_config(config, mempool_config).await
+    }
+
+    async fn setup_with_mempool_config(
+        config: Option<Config>,
+        mempool_config: mempool::Config,
+    ) -> (
+        ShardEngine,
+        Option<SnapchainGossip>,
+        Mempool,
+        mpsc::Sender<MempoolRequest>,
+        mpsc::Sender<MempoolMessagesRequest>,
+        broadcast::Sender<ShardChunk>,
+        mpsc::Receiver<SystemMessage>,
     ) {
         let keypair = Keypair::generate();
         let statsd_client = StatsdClientWrapper::new(
@@ -82,8 +108,6 @@ mod tests {
             None => mpsc::channel(100).0,
         };
 
-        let mut mempool_config = mempool::Config::default();
-        mempool_config.enable_rate_limits = enable_rate_limits;
         let mempool = Mempool::new(
             mempool_config,
             mempool_rx,
@@ -298,6 +322,7 @@ mod tests {
                 .send(MempoolMessagesRequest {
                     shard_id: 1,
                     max_messages_per_block: 1,
+                    block_limits: BlockLimits::default(),
                     message_tx: mempool_retrieval_tx,
                 })
                 .await
@@ -400,6 +425,7 @@ mod tests {
             .send(MempoolMessagesRequest {
                 shard_id: 1,
                 max_messages_per_block: 2,
+                block_limits: BlockLimits::default(),
                 message_tx: mempool_retrieval_tx,
             })
             .await
@@ -411,6 +437,140 @@ mod tests {
         assert_eq!(result[0].fid(), fid);
     }
 
+    #[tokio::test]
+    async fn test_subscribe_to_mempool_events() {
+        let (mut engine, _, mut mempool, mempool_tx, messages_request_tx, shard_decision_tx, _) =
+            setup(None, false).await;
+        test_helper::register_user(
+            1234,
+            default_signer(),
+            default_custody_address(),
+            &mut engine,
+        )
+        .await;
+        tokio::spawn(async move {
+            mempool.run().await;
+        });
+
+        let add = |message: proto::Message| {
+            let mempool_tx = mempool_tx.clone();
+            async move {
+                let (req, res) = oneshot::channel();
+                mempool_tx
+                    .send(MempoolRequest::AddMessage(
+                        MempoolMessage::UserMessage(message),
+                        MempoolSource::Local,
+                        Some(req),
+                    ))
+                    .await
+                    .unwrap();
+                res.await.unwrap().unwrap();
+            }
+        };
+        let cast1 = create_cast_add(1234, "hello", None, None);
+        let cast2 = create_cast_add(1234, "world", None, None);
+        add(cast1.clone()).await;
+
+        let (req, res) = oneshot::channel();
+        mempool_tx
+            .send(MempoolRequest::Subscribe(req))
+            .await
+            .unwrap();
+        let mempool::MempoolSubscription {
+            pending,
+            mut events_rx,
+        } = res.await.unwrap().unwrap();
+        let summary = |event: &proto::MempoolEvent| {
+            (
+                event.r#type(),
+                event.shard_id,
+                event.message.as_ref().unwrap().hash.clone(),
+                event.removal_reason(),
+            )
+        };
+        assert_eq!(
+            pending.iter().map(summary).collect::<Vec<_>>(),
+            vec![(
+                proto::MempoolEventType::Pending,
+                1,
+                cast1.hash.clone(),
+                proto::MempoolRemovalReason::None
+            )]
+        );
+
+        async fn next_event(
+            events_rx: &mut broadcast::Receiver<proto::MempoolEvent>,
+        ) -> proto::MempoolEvent {
+            tokio::time::timeout(Duration::from_secs(1), events_rx.recv())
+                .await
+                .unwrap()
+                .unwrap()
+        }
+
+        add(cast2.clone()).await;
+        assert_eq!(
+            summary(&next_event(&mut events_rx).await),
+            (
+                proto::MempoolEventType::Added,
+                1,
+                cast2.hash.clone(),
+                proto::MempoolRemovalReason::None
+            )
+        );
+
+        // Committed while it was still waiting in the mempool
+        let _ = shard_decision_tx.send(ShardChunk {
+            header: Some(ShardHeader {
+                height: Some(Height {
+                    shard_index: 1,
+                    block_number: 1,
+                }),
+                timestamp: 0,
+                parent_hash: vec![],
+                shard_root: vec![],
+            }),
+            hash: vec![],
+            transactions: vec![Transaction {
+                fid: 1234,
+                user_messages: vec![cast1.clone()],
+                system_messages: vec![],
+                account_root: vec![],
+            }],
+            commits: None,
+        });
+        assert_eq!(
+            summary(&next_event(&mut events_rx).await),
+            (
+                proto::MempoolEventType::Removed,
+                1,
+                cast1.hash.clone(),
+                proto::MempoolRemovalReason::Committed
+            )
+        );
+
+        // Pulled into a proposal
+        let (message_tx, message_rx) = oneshot::channel();
+        messages_request_tx
+            .send(MempoolMessagesRequest {
+                shard_id: 1,
+                max_messages_per_block: 10,
+                block_limits: BlockLimits::default(),
+                message_tx,
+            })
+            .await
+            .unwrap();
+        assert_eq!(message_rx.await.unwrap().len(), 1);
+        assert_eq!(
+            summary(&next_event(&mut events_rx).await),
+            (
+                proto::MempoolEventType::Removed,
+                1,
+                cast2.hash.clone(),
+                proto::MempoolRemovalReason::Proposed
+            )
+        );
+    }
+
     #[tokio::test]
     async fn test_mempool_gossip() {
         // Create configs with different ports
@@ -510,6 +670,7 @@ mod tests {
             .send(MempoolMessagesRequest {
                 shard_id: 1,
                 max_messages_per_block: 1,
+                block_limits: BlockLimits::default(),
                 message_tx: mempool_retrieval_tx,
             })
             .await
@@ -557,4 +718,757 @@ mod tests {
         let error = result.unwrap_err();
         assert_eq!(error.code, "bad_request.duplicate");
     }
+
+    #[tokio::test]
+    async fn test_mempool_spill_and_reload() {
+        let spill_dir = tempfile::tempdir().unwrap();
+        let casts: Vec<proto::Message> = (0..3)
+            .map(|i| create_cast_add(123, "hello", Some(1_000 + i), None))
+            .collect();
+
+        let mut mempool_config = mempool::Config::default();
+        mempool_config.spill_enabled = true;
+        mempool_config.spill_dir = spill_dir.path().join("spill").to_str().unwrap().to_string();
+        // Only one cast fits in memory, the rest get spilled
+        mempool_config.spill_threshold_bytes =
+            spill::message_size(&MempoolMessage::UserMessage(casts[0].clone())) + 1;
+
+        let (_, _, mut mempool, mempool_tx, messages_request_tx, _decision_tx, _) =
+            setup_with_mempool_config(None, mempool_config).await;
+        tokio::spawn(async move {
+            mempool.run().await;
+        });
+
+        // Insert in reverse priority order so spilling has to pick the right messages
+        for cast in casts.iter().rev() {
+            let (req, res) = oneshot::channel();
+            mempool_tx
+                .send(MempoolRequest::AddMessage(
+                    MempoolMessage::UserMessage(cast.clone()),
+                    MempoolSource::Local,
+                    Some(req),
+                ))
+                .await
+                .unwrap();
+            res.await.unwrap().unwrap();
+        }
+
+        // Spilled messages are still deduplicated
+        let (req, res) = oneshot::channel();
+        mempool_tx
+            .send(MempoolRequest::AddMessage(
+                MempoolMessage::UserMessage(casts[2].clone()),
+                MempoolSource::Local,
+                Some(req),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(
+            res.await.unwrap().unwrap_err().code,
+            "bad_request.duplicate"
+        );
+
+        let (req, res) = oneshot::channel();
+        mempool_tx.send(MempoolRequest::GetSize(req)).await.unwrap();
+        assert_eq!(res.await.unwrap()[&1], 3);
+
+        // Messages are reloaded from disk and come out in priority order
+        let (mempool_retrieval_tx, mempool_retrieval_rx) = oneshot::channel();
+        messages_request_tx
+            .send(MempoolMessagesRequest {
+                shard_id: 1,
+                max_messages_per_block: 10,
+                block_limits: BlockLimits::default(),
+                message_tx: mempool_retrieval_tx,
+            })
+            .await
+            .unwrap();
+        let result = mempool_retrieval_rx.await.unwrap();
+        let hashes: Vec<Vec<u8>> = result
+            .iter()
+            .map(|message| match message {
+                MempoolMessage::UserMessage(message) => message.hash.clone(),
+                MempoolMessage::ValidatorMessage(_) => panic!("Expected user message"),
+            })
+            .collect();
+        let expected: Vec<Vec<u8>> = casts.iter().map(|cast| cast.hash.clone()).collect();
+        assert_eq!(hashes, expected);
+
+        let (req, res) = oneshot::channel();
+        mempool_tx.send(MempoolRequest::GetSize(req)).await.unwrap();
+        assert_eq!(res.await.unwrap()[&1], 0);
+    }
+
+    #[tokio::test]
+    async fn test_mempool_capacity_counts_spilled_messages() {
+        let spill_dir = tempfile::tempdir().unwrap();
+        let casts: Vec<proto::Message> = (0..3)
+            .map(|i| create_cast_add(123, "hello", Some(1_000 + i), None))
+            .collect();
+
+        let mut mempool_config = mempool::Config::default();
+        mempool_config.spill_enabled = true;
+        mempool_config.spill_dir = spill_dir.path().join("spill").to_str().unwrap().to_string();
+        mempool_config.spill_threshold_bytes =
+            spill::message_size(&MempoolMessage::UserMessage(casts[0].clone())) + 1;
+        mempool_config.capacity_per_shard = 2;
+
+        let (_, _, mut mempool, mempool_tx, messages_request_tx, _decision_tx, _) =
+            setup_with_mempool_config(None, mempool_config).await;
+        tokio::spawn(async move {
+            mempool.run().await;
+        });
+
+        let mut replies = vec![];
+        for cast in &casts {
+            let (req, res) = oneshot::channel();
+            mempool_tx
+                .send(MempoolRequest::AddMessage(
+                    MempoolMessage::UserMessage(cast.clone()),
+                    MempoolSource::Local,
+                    Some(req),
+                ))
+                .await
+                .unwrap();
+            replies.push(res);
+        }
+        let mut third_reply = replies.pop().unwrap();
+        for res in replies {
+            res.await.unwrap().unwrap();
+        }
+
+        // One cast in memory and one on disk fill the shard, so the third waits in the queue
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), &mut third_reply)
+                .await
+                .is_err()
+        );
+
+        let (mempool_retrieval_tx, mempool_retrieval_rx) = oneshot::channel();
+        messages_request_tx
+            .send(MempoolMessagesRequest {
+                shard_id: 1,
+                max_messages_per_block: 10,
+                block_limits: BlockLimits::default(),
+                message_tx: mempool_retrieval_tx,
+            })
+            .await
+            .unwrap();
+        assert_eq!(mempool_retrieval_rx.await.unwrap().len(), 2);
+
+        // Draining the shard makes room for it
+        third_reply.await.unwrap().unwrap();
+    }
+
+    async fn drain_after_inserting(
+        casts: &[proto::Message],
+        spill_threshold_bytes: u64,
+    ) -> Vec<Vec<u8>> {
+        let spill_dir = tempfile::tempdir().unwrap();
+        let mut mempool_config = mempool::Config::default();
+        mempool_config.spill_enabled = true;
+        mempool_config.spill_dir = spill_dir.path().join("spill").to_str().unwrap().to_string();
+        mempool_config.spill_threshold_bytes = spill_threshold_bytes;
+
+        let (_, _, mut mempool, mempool_tx, messages_request_tx, _decision_tx, _) =
+            setup_with_mempool_config(None, mempool_config).await;
+        tokio::spawn(async move {
+            mempool.run().await;
+        });
+        for cast in casts {
+            let (req, res) = oneshot::channel();
+            mempool_tx
+                .send(MempoolRequest::AddMessage(
+                    MempoolMessage::UserMessage(cast.clone()),
+                    MempoolSource::Local,
+                    Some(req),
+                ))
+                .await
+                .unwrap();
+            res.await.unwrap().unwrap();
+        }
+
+        let (mempool_retrieval_tx, mempool_retrieval_rx) = oneshot::channel();
+        messages_request_tx
+            .send(MempoolMessagesRequest {
+                shard_id: 1,
+                max_messages_per_block: 10,
+                block_limits: BlockLimits::default(),
+                message_tx: mempool_retrieval_tx,
+            })
+            .await
+            .unwrap();
+        mempool_retrieval_rx
+            .await
+            .unwrap()
+            .iter()
+            .map(|message| match message {
+                MempoolMessage::UserMessage(message) => message.hash.clone(),
+                MempoolMessage::ValidatorMessage(_) => panic!("Expected user message"),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_drained_order_is_independent_of_arrival() {
+        let long_text = "a".repeat(320);
+        let casts = vec![
+            create_cast_add(123, &long_text, Some(1_000), None),
+            create_cast_add(123, "b", Some(1_001), None),
+            create_cast_add(123, &long_text, Some(1_002), None),
+            create_cast_add(123, "d", Some(1_003), None),
+        ];
+        let size = |i: usize| spill::message_size(&MempoolMessage::UserMessage(casts[i].clone()));
+        // Fits the first, second and fourth casts, so which ones get spilled depends on the order
+        // they arrive in
+        let spill_threshold_bytes = size(0) + size(1) + size(3);
+
+        let expected: Vec<Vec<u8>> = casts.iter().map(|cast| cast.hash.clone()).collect();
+        let shuffled = [&casts[0], &casts[2], &casts[1], &casts[3]];
+        let reversed = [&casts[3], &casts[2], &casts[1], &casts[0]];
+        for order in [shuffled, reversed] {
+            let order: Vec<proto::Message> = order.into_iter().cloned().collect();
+            assert_eq!(
+                drain_after_inserting(&order, spill_threshold_bytes).await,
+                expected
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_proposal_respects_block_limits() {
+        let (engine, _, mut mempool, mempool_tx, messages_request_tx, _, _) =
+            setup(None, false).await;
+        let block_limits = BlockLimits {
+            max_transactions: 2,
+            max_bytes: 0,
+        };
+        let (requeue_tx, mut requeue_rx) = mpsc::channel(10);
+        let mut engine = engine
+            .with_block_limits(block_limits)
+            .with_mempool_requeue(requeue_tx);
+        let fids = [FID_FOR_TEST, FID_FOR_TEST + 1, FID_FOR_TEST + 2];
+        for fid in fids {
+            test_helper::register_user(
+                fid,
+                default_signer(),
+                default_custody_address(),
+                &mut engine,
+            )
+            .await;
+        }
+        tokio::spawn(async move {
+            mempool.run().await;
+        });
+
+        // Three fids, so more than fits in a chunk of two transactions. Each fid's casts come out
+        // of the mempool one after the other.
+        let timestamp = messages_factory::farcaster_time() - 100;
+        for (i, fid) in fids.iter().enumerate() {
+            for j in 0..2 {
+                let cast =
+                    create_cast_add(*fid, "test", Some(timestamp + (i * 2 + j) as u32), None);
+                let (req, res) = oneshot::channel();
+                mempool_tx
+                    .send(MempoolRequest::AddMessage(
+                        MempoolMessage::UserMessage(cast),
+                        MempoolSource::Local,
+                        Some(req),
+                    ))
+                    .await
+                    .unwrap();
+                res.await.unwrap().unwrap();
+            }
+        }
+
+        let pull_messages = || async {
+            let (message_tx, message_rx) = oneshot::channel();
+            messages_request_tx
+                .send(MempoolMessagesRequest {
+                    shard_id: 1,
+                    max_messages_per_block: 100,
+                    block_limits,
+                    message_tx,
+                })
+                .await
+                .unwrap();
+            message_rx.await.unwrap()
+        };
+
+        let messages = pull_messages().await;
+        assert_eq!(messages.len(), 4);
+        let state_change = engine.propose_state_change(1, messages.clone());
+        assert_eq!(state_change.transactions.len(), 2);
+        assert!(engine.validate_state_change(&state_change));
+
+        // The rest is left for the next chunk
+        let (req, res) = oneshot::channel();
+        mempool_tx.send(MempoolRequest::GetSize(req)).await.unwrap();
+        assert_eq!(res.await.unwrap()[&1], 2);
+        assert_eq!(pull_messages().await.len(), 2);
+
+        // Messages handed to the proposer that don't fit go back to the mempool
+        let mut engine = engine.with_block_limits(BlockLimits {
+            max_transactions: 1,
+            max_bytes: 0,
+        });
+        let over_limits = engine.propose_state_change(1, messages);
+        assert_eq!(over_limits.transactions.len(), 1);
+        for _ in 0..2 {
+            match requeue_rx.try_recv().unwrap() {
+                SystemMessage::Mempool(MempoolRequest::Requeue(message)) => {
+                    assert!(matches!(message, MempoolMessage::UserMessage(_)));
+                    assert_eq!(message.fid(), fids[1]);
+                    mempool_tx
+                        .send(MempoolRequest::Requeue(message))
+                        .await
+                        .unwrap();
+                }
+                _ => panic!("Expected a requeued user message"),
+            }
+        }
+        assert!(requeue_rx.try_recv().is_err());
+
+        // And are proposed again from there, once the mempool got to them
+        let mut requeued = vec![];
+        for _ in 0..100 {
+            requeued = pull_messages().await;
+            if !requeued.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(requeued.len(), 2);
+        assert!(requeued.iter().all(|message| message.fid() == fids[1]));
+
+        // Validators check proposals against the network's limits, not their own
+        assert!(engine.validate_state_change(&state_change));
+        let mut over_network_limits = state_change.clone();
+        over_network_limits.transactions = vec![
+            state_change.transactions[0].clone();
+            BlockLimits::NETWORK.max_transactions as usize + 1
+        ];
+        assert!(!engine.validate_state_change(&over_network_limits));
+    }
+
+    // Boosts the messages of one fid, like the system accounts of a fork
+    struct BoostFid(u64);
+
+    impl MempoolPriority for BoostFid {
+        fn priority(&self, message: &MempoolMessage) -> u32 {
+            (message.fid() == self.0) as u32
+        }
+    }
+
+    #[tokio::test]
+    async fn test_custom_priority_reorders_draining() {
+        assert!(MessagePriority::new(
+            proto::FarcasterNetwork::Mainnet,
+            Arc::new(BoostFid(FID_FOR_TEST))
+        )
+        .is_err());
+
+        let boosted_fid = FID_FOR_TEST + 1;
+        let timestamp = messages_factory::farcaster_time() - 100;
+        let earlier = create_cast_add(FID_FOR_TEST, "earlier", Some(timestamp), None);
+        let later = create_cast_add(boosted_fid, "later", Some(timestamp + 1), None);
+
+        let priorities = [
+            (MessagePriority::default(), vec![&earlier, &later]),
+            (
+                MessagePriority::new(
+                    proto::FarcasterNetwork::Custom,
+                    Arc::new(BoostFid(boosted_fid)),
+                )
+                .unwrap(),
+                vec![&later, &earlier],
+            ),
+        ];
+        for (priority, expected) in priorities {
+            let (mut engine, _, mempool, mempool_tx, messages_request_tx, _shard_decision_tx, _) =
+                setup(None, false).await;
+            for fid in [FID_FOR_TEST, boosted_fid] {
+                test_helper::register_user(
+                    fid,
+                    default_signer(),
+                    default_custody_address(),
+                    &mut engine,
+                )
+                .await;
+            }
+            let mut mempool = mempool.with_priority(priority);
+            tokio::spawn(async move {
+                mempool.run().await;
+            });
+
+            for cast in [&earlier, &later] {
+                let (req, res) = oneshot::channel();
+                mempool_tx
+                    .send(MempoolRequest::AddMessage(
+                        MempoolMessage::UserMessage(cast.clone()),
+                        MempoolSource::Local,
+                        Some(req),
+                    ))
+                    .await
+                    .unwrap();
+                res.await.unwrap().unwrap();
+            }
+
+            let (message_tx, message_rx) = oneshot::channel();
+            messages_request_tx
+                .send(MempoolMessagesRequest {
+                    shard_id: 1,
+                    max_messages_per_block: 10,
+                    block_limits: BlockLimits::default(),
+                    message_tx,
+                })
+                .await
+                .unwrap();
+            let drained: Vec<Vec<u8>> = message_rx
+                .await
+                .unwrap()
+                .iter()
+                .map(|message| match message {
+                    MempoolMessage::UserMessage(message) => message.hash.clone(),
+                    MempoolMessage::ValidatorMessage(_) => panic!("Expected user message"),
+                })
+                .collect();
+            assert_eq!(
+                drained,
+                expected
+                    .into_iter()
+                    .map(|cast| cast.hash.clone())
+                    .collect::<Vec<_>>()
+            );
+        }
+    }
+
+    fn chunk_with_event(onchain_event: proto::OnChainEvent) -> ShardChunk {
+        ShardChunk {
+            header: Some(ShardHeader {
+                height: Some(Height {
+                    shard_index: 1,
+                    block_number: 1,
+                }),
+                timestamp: 0,
+                parent_hash: vec![],
+                shard_root: vec![],
+            }),
+            hash: vec![],
+            transactions: vec![Transaction {
+                fid: onchain_event.fid,
+                user_messages: vec![],
+                system_messages: vec![ValidatorMessage {
+                    on_chain_event: Some(onchain_event),
+                    fname_transfer: None,
+                }],
+                account_root: vec![],
+            }],
+            commits: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_message_waits_for_fid_registration() {
+        let mut mempool_config = mempool::Config::default();
+        mempool_config.pending_dependencies_ttl = Duration::from_secs(2);
+        let (mut engine, _, mut mempool, mempool_tx, messages_request_tx, shard_decision_tx, _) =
+            setup_with_mempool_config(None, mempool_config).await;
+        tokio::spawn(async move {
+            mempool.run().await;
+        });
+
+        // Both arrive before their fid is registered
+        let cast = create_cast_add(1234, "hello", None, None);
+        let expiring_cast = create_cast_add(5678, "hello", None, None);
+        for message in [&cast, &expiring_cast] {
+            let (req, res) = oneshot::channel();
+            mempool_tx
+                .send(MempoolRequest::AddMessage(
+                    MempoolMessage::UserMessage(message.clone()),
+                    MempoolSource::Local,
+                    Some(req),
+                ))
+                .await
+                .unwrap();
+            res.await.unwrap().unwrap();
+        }
+
+        // They're held outside the mempool until then
+        let (req, res) = oneshot::channel();
+        mempool_tx.send(MempoolRequest::GetSize(req)).await.unwrap();
+        assert!(res.await.unwrap().is_empty());
+
+        let pull_messages = async || {
+            let (mempool_retrieval_tx, mempool_retrieval_rx) = oneshot::channel();
+            messages_request_tx
+                .send(MempoolMessagesRequest {
+                    shard_id: 1,
+                    max_messages_per_block: 10,
+                    block_limits: BlockLimits::default(),
+                    message_tx: mempool_retrieval_tx,
+                })
+                .await
+                .unwrap();
+            mempool_retrieval_rx.await.unwrap()
+        };
+
+        // Admitted once the registration lands
+        test_helper::register_user(
+            1234,
+            default_signer(),
+            default_custody_address(),
+            &mut engine,
+        )
+        .await;
+        let id_register_event = events_factory::create_id_register_event(
+            1234,
+            proto::IdRegisterEventType::Register,
+            default_custody_address(),
+            None,
+        );
+        shard_decision_tx
+            .send(chunk_with_event(id_register_event))
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        let result = pull_messages().await;
+        assert_eq!(result.len(), 1);
+        match &result[0] {
+            MempoolMessage::UserMessage(message) => assert_eq!(message.hash, cast.hash),
+            MempoolMessage::ValidatorMessage(_) => panic!("Expected user message"),
+        }
+
+        // Past the ttl the other one is rejected, registering its fid doesn't admit it anymore
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        test_helper::register_user(
+            5678,
+            default_signer(),
+            default_custody_address(),
+            &mut engine,
+        )
+        .await;
+        let id_register_event = events_factory::create_id_register_event(
+            5678,
+            proto::IdRegisterEventType::Register,
+            default_custody_address(),
+            None,
+        );
+        shard_decision_tx
+            .send(chunk_with_event(id_register_event))
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(pull_messages().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_unregistered_fids() {
+        async fn add_cast(
+            mempool_tx: &mpsc::Sender<MempoolRequest>,
+            fid: u64,
+        ) -> Result<(), crate::core::error::HubError> {
+            let (req, res) = oneshot::channel();
+            mempool_tx
+                .send(MempoolRequest::AddMessage(
+                    MempoolMessage::UserMessage(create_cast_add(fid, "hello", None, None)),
+                    MempoolSource::Local,
+                    Some(req),
+                ))
+                .await
+                .unwrap();
+            res.await.unwrap()
+        }
+        async fn mempool_size(mempool_tx: &mpsc::Sender<MempoolRequest>) -> u64 {
+            let (req, res) = oneshot::channel();
+            mempool_tx.send(MempoolRequest::GetSize(req)).await.unwrap();
+            res.await.unwrap().get(&1).copied().unwrap_or(0)
+        }
+
+        let mut mempool_config = mempool::Config::default();
+        mempool_config.unregistered_fids = UnregisteredFids::Reject;
+        let (mut engine, _, mut mempool, mempool_tx, _messages_request_tx, _shard_decision_tx, _) =
+            setup_with_mempool_config(None, mempool_config).await;
+        tokio::spawn(async move {
+            mempool.run().await;
+        });
+        test_helper::register_user(
+            FID_FOR_TEST,
+            default_signer(),
+            default_custody_address(),
+            &mut engine,
+        )
+        .await;
+
+        let err = add_cast(&mempool_tx, 5678).await.unwrap_err();
+        assert_eq!(err.code, "bad_request.validation_failure");
+        assert_eq!(err.message, "fid 5678 is not registered");
+        add_cast(&mempool_tx, FID_FOR_TEST).await.unwrap();
+        assert_eq!(mempool_size(&mempool_tx).await, 1);
+
+        // Held instead, like messages waiting on their signer
+        let mut mempool_config = mempool::Config::default();
+        mempool_config.unregistered_fids = UnregisteredFids::Hold;
+        mempool_config.pending_dependencies_ttl = Duration::from_secs(10);
+        let (_engine, _, mut mempool, mempool_tx, _messages_request_tx, _shard_decision_tx, _) =
+            setup_with_mempool_config(None, mempool_config).await;
+        tokio::spawn(async move {
+            mempool.run().await;
+        });
+        add_cast(&mempool_tx, 5678).await.unwrap();
+        assert_eq!(mempool_size(&mempool_tx).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_max_fids_per_verified_address() {
+        let address = hex::decode("91031dcfdea024b4d51e775486111d2b2a715871").unwrap();
+        let timestamp = messages_factory::farcaster_time();
+        let verification = |fid: u64, timestamp: u32| {
+            messages_factory::verifications::create_verification_add(
+                fid,
+                0,
+                address.clone(),
+                vec![],
+                vec![],
+                Some(timestamp),
+                None,
+            )
+        };
+        async fn add_message(
+            mempool_tx: &mpsc::Sender<MempoolRequest>,
+            message: proto::Message,
+        ) -> Result<(), crate::core::error::HubError> {
+            let (req, res) = oneshot::channel();
+            mempool_tx
+                .send(MempoolRequest::AddMessage(
+                    MempoolMessage::UserMessage(message),
+                    MempoolSource::Local,
+                    Some(req),
+                ))
+                .await
+                .unwrap();
+            res.await.unwrap()
+        }
+
+        // Off unless it's set
+        assert_eq!(mempool::Config::default().max_fids_per_verified_address, 0);
+        let mut mempool_config = mempool::Config::default();
+        mempool_config.max_fids_per_verified_address = 2;
+        let (engine, _, mut mempool, mempool_tx, _messages_request_tx, _shard_decision_tx, _) =
+            setup_with_mempool_config(None, mempool_config).await;
+        tokio::spawn(async move {
+            mempool.run().await;
+        });
+
+        // Merged straight into the store, the claim signatures aren't checked there
+        let stores = engine.get_stores();
+        let mut txn = RocksDbTransactionBatch::new();
+        for fid in [1001, 1002] {
+            stores
+                .verification_store
+                .merge(&verification(fid, timestamp), &mut txn)
+                .unwrap();
+        }
+        stores.db.commit(txn).unwrap();
+
+        let err = add_message(&mempool_tx, verification(1003, timestamp))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, "bad_request.validation_failure");
+        assert_eq!(
+            err.message,
+            "address is already verified by the maximum of 2 fids"
+        );
+
+        // The fids that verified it already can verify it again
+        add_message(&mempool_tx, verification(1001, timestamp + 1))
+            .await
+            .unwrap();
+        // Only the verifications are limited
+        add_message(&mempool_tx, create_cast_add(1003, "hello", None, None))
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn test_pending_expires_while_fid_is_ready() {
+        let ttl = Duration::from_secs(10);
+        let mut pending = PendingDependencies::new(ttl, 100);
+        let start = std::time::Instant::now();
+        let cast =
+            |text: &str| MempoolMessage::UserMessage(create_cast_add(1234, text, None, None));
+        pending
+            .hold(cast("first"), MempoolSource::Local, start)
+            .unwrap();
+
+        // Checked and still waiting, it's checked again once the next chunk is decided
+        pending.mark_ready(1234);
+        let (expired, ready) = pending.take(start);
+        assert!(expired.is_empty());
+        assert_eq!(ready.len(), 1);
+        for (fid, waiting) in ready {
+            pending.restore(fid, waiting);
+        }
+        assert_eq!(pending.len(), 1);
+        assert!(pending.take(start).1.is_empty());
+
+        // The next chunk is decided as the ttl runs out, it's expired rather than checked
+        pending
+            .hold(cast("second"), MempoolSource::Local, start + ttl / 2)
+            .unwrap();
+        pending.mark_committed();
+        let (expired, ready) = pending.take(start + ttl);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].1.len(), 1);
+        for (fid, waiting) in ready {
+            pending.restore(fid, waiting);
+        }
+
+        // The restored one still expires
+        let (expired, ready) = pending.take(start + ttl / 2 + ttl);
+        assert_eq!(expired.len(), 1);
+        assert!(ready.is_empty());
+        assert_eq!(pending.len(), 0);
+    }
+
+    #[test]
+    fn test_entry_times() {
+        let mut entry_times = EntryTimes::new();
+        let start = std::time::Instant::now();
+        let ttl = Duration::from_secs(60);
+
+        entry_times.record(1, "a".to_string(), start);
+        entry_times.record(1, "b".to_string(), start);
+        entry_times.record(2, "a".to_string(), start);
+        assert_eq!(entry_times.len(), 3);
+
+        // Latency is measured from the first time the message was recorded
+        entry_times.record(1, "a".to_string(), start + Duration::from_secs(5));
+        assert_eq!(
+            entry_times.take(1, "a".to_string(), start + Duration::from_secs(10)),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(entry_times.take(1, "a".to_string(), start), None);
+
+        // Only messages pulled into a proposal are pruned
+        entry_times.mark_pulled(1, "b".to_string(), start);
+        assert_eq!(entry_times.prune_pulled(start + ttl / 2, ttl), 0);
+        assert_eq!(entry_times.prune_pulled(start + ttl, ttl), 1);
+        assert_eq!(entry_times.len(), 1);
+
+        entry_times.remove(2, "a".to_string());
+        assert_eq!(entry_times.len(), 0);
+
+        // Request ids are kept with the entry
+        entry_times.set_request_id(1, "c".to_string(), "abc".to_string());
+        assert_eq!(entry_times.request_id(1, "c".to_string()), None);
+        entry_times.record(1, "c".to_string(), start);
+        entry_times.set_request_id(1, "c".to_string(), "abc".to_string());
+        assert_eq!(
+            entry_times.request_id(1, "c".to_string()),
+            Some("abc".to_string())
+        );
+        entry_times.take(1, "c".to_string(), start);
+        assert_eq!(entry_times.request_id(1, "c".to_string()), None);
+    }
 }
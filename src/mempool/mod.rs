@@ -1,5 +1,10 @@
+pub mod admission;
+pub mod entry_times;
 pub mod mempool;
+pub mod pending;
+pub mod priority;
 pub mod routing;
+pub mod spill;
 
 #[cfg(test)]
 mod mempool_test;
@@ -0,0 +1,151 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+use super::mempool::MempoolSource;
+use crate::core::error::HubError;
+use crate::storage::store::engine::MempoolMessage;
+
+pub struct PendingMessage {
+    pub message: MempoolMessage,
+    pub source: MempoolSource,
+    pub expires_at: Instant,
+}
+
+/// Holding area for user messages that arrived before the onchain events they need, the fid's
+/// registration or the signer they're signed with. Each one waits up to a ttl for an event for its
+/// fid to be committed, and is then handed back to the mempool to be admitted. Held messages don't
+/// count towards the mempool's size and aren't gossiped until they're admitted.
+pub struct PendingDependencies {
+    ttl: Duration,
+    capacity: usize,
+    messages: HashMap<u64, Vec<PendingMessage>>,
+    len: usize,
+    // In the order they expire, since they all have the same ttl. Messages that were admitted are
+    // skipped when their turn comes.
+    expiry_queue: VecDeque<(Instant, u64, String)>,
+    // Fids to check on the next admission pass
+    ready: HashSet<u64>,
+    // Fids that were checked and are still waiting. Decided chunks are published before they're
+    // committed, so the events they were waiting for may not have landed yet, they're checked again
+    // once the next chunk is decided.
+    rechecking: HashSet<u64>,
+}
+
+impl PendingDependencies {
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        PendingDependencies {
+            ttl,
+            capacity,
+            messages: HashMap::new(),
+            len: 0,
+            expiry_queue: VecDeque::new(),
+            ready: HashSet::new(),
+            rechecking: HashSet::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.ttl.is_zero()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn hold(
+        &mut self,
+        message: MempoolMessage,
+        source: MempoolSource,
+        now: Instant,
+    ) -> Result<(), HubError> {
+        let fid = message.fid();
+        let identity = message.mempool_key().identity();
+        let waiting = self.messages.entry(fid).or_default();
+        if waiting
+            .iter()
+            .any(|pending| pending.message.mempool_key().identity() == identity)
+        {
+            return Err(HubError::duplicate("message already in the mempool"));
+        }
+        if self.len >= self.capacity {
+            return Err(HubError::unavailable(
+                "too many messages waiting for onchain events",
+            ));
+        }
+
+        let expires_at = now + self.ttl;
+        waiting.push(PendingMessage {
+            message,
+            source,
+            expires_at,
+        });
+        self.len += 1;
+        self.expiry_queue.push_back((expires_at, fid, identity));
+        Ok(())
+    }
+
+    // Called for the fid of every onchain event in a decided chunk
+    pub fn mark_ready(&mut self, fid: u64) {
+        if self.messages.contains_key(&fid) {
+            self.ready.insert(fid);
+        }
+    }
+
+    // Called for every decided chunk, the ones before it have been committed by then
+    pub fn mark_committed(&mut self) {
+        self.ready.extend(self.rechecking.drain());
+    }
+
+    // Removes and returns the messages that have waited for longer than the ttl, and the messages
+    // of the fids to check again. The ones that still have to wait are put back with [restore].
+    // Expired first, the taken messages can't expire until they're restored.
+    pub fn take(&mut self, now: Instant) -> (Vec<PendingMessage>, Vec<(u64, Vec<PendingMessage>)>) {
+        let expired = self.expire(now);
+        (expired, self.take_ready())
+    }
+
+    fn take_ready(&mut self) -> Vec<(u64, Vec<PendingMessage>)> {
+        let mut ready = vec![];
+        for fid in self.ready.drain() {
+            if let Some(waiting) = self.messages.remove(&fid) {
+                self.len -= waiting.len();
+                ready.push((fid, waiting));
+            }
+        }
+        ready
+    }
+
+    pub fn restore(&mut self, fid: u64, waiting: Vec<PendingMessage>) {
+        if waiting.is_empty() {
+            return;
+        }
+        self.len += waiting.len();
+        self.messages.entry(fid).or_default().extend(waiting);
+        self.rechecking.insert(fid);
+    }
+
+    fn expire(&mut self, now: Instant) -> Vec<PendingMessage> {
+        let mut expired = vec![];
+        while let Some((expires_at, fid, identity)) = self.expiry_queue.front() {
+            if now < *expires_at {
+                break;
+            }
+            if let Some(waiting) = self.messages.get_mut(fid) {
+                if let Some(index) = waiting.iter().position(|pending| {
+                    pending.expires_at == *expires_at
+                        && pending.message.mempool_key().identity() == *identity
+                }) {
+                    expired.push(waiting.swap_remove(index));
+                    self.len -= 1;
+                    if waiting.is_empty() {
+                        self.messages.remove(fid);
+                        self.ready.remove(fid);
+                        self.rechecking.remove(fid);
+                    }
+                }
+            }
+            self.expiry_queue.pop_front();
+        }
+        expired
+    }
+}
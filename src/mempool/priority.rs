@@ -0,0 +1,60 @@
+use super::mempool::MempoolKey;
+use crate::proto::FarcasterNetwork;
+use crate::storage::store::engine::MempoolMessage;
+use std::sync::Arc;
+
+/// Scores mempool messages for forks running a Custom network that want some of them in blocks
+/// first, e.g. the ones from their system accounts. Messages with a higher priority are handed out
+/// for blocks before lower ones of the same kind, validator messages still come before user
+/// messages, and messages with the same priority keep their usual order. The score has to depend
+/// on the message alone for mempools holding the same messages to propose the same blocks.
+pub trait MempoolPriority: Send + Sync {
+    fn priority(&self, message: &MempoolMessage) -> u32;
+}
+
+/// Every message has the same priority, so they're handed out in key order
+pub struct DefaultPriority;
+
+impl MempoolPriority for DefaultPriority {
+    fn priority(&self, _message: &MempoolMessage) -> u32 {
+        0
+    }
+}
+
+/// The priority the mempool orders messages by, the default unless registered at node
+/// construction
+#[derive(Clone)]
+pub struct MessagePriority {
+    priority: Arc<dyn MempoolPriority>,
+}
+
+impl Default for MessagePriority {
+    fn default() -> Self {
+        MessagePriority {
+            priority: Arc::new(DefaultPriority),
+        }
+    }
+}
+
+impl MessagePriority {
+    // Built-in networks only use the default priority
+    pub fn new(
+        network: FarcasterNetwork,
+        priority: Arc<dyn MempoolPriority>,
+    ) -> Result<Self, String> {
+        if network != FarcasterNetwork::Custom {
+            return Err(format!(
+                "a mempool priority can only be registered on a Custom network, not {}",
+                network.as_str_name()
+            ));
+        }
+        Ok(MessagePriority { priority })
+    }
+
+    // The key the message is held under in the mempool
+    pub fn key(&self, message: &MempoolMessage) -> MempoolKey {
+        message
+            .mempool_key()
+            .with_priority(self.priority.priority(message))
+    }
+}
@@ -27,6 +27,7 @@ mod tests {
                     user_name_proofs: 0,
                     verifications: 0,
                 },
+                byte_limits: None,
             }),
             db: None,
             messages_request_tx: None,
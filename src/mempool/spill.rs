@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use prost::Message;
+
+use super::mempool::MempoolKey;
+use crate::core::error::HubError;
+use crate::proto;
+use crate::storage::db::{PageOptions, RocksDB, RocksdbError};
+use crate::storage::store::engine::MempoolMessage;
+use crate::storage::util::increment_vec_u8;
+
+const USER_MESSAGE_TAG: u8 = 1;
+const VALIDATOR_MESSAGE_TAG: u8 = 2;
+
+pub fn message_size(message: &MempoolMessage) -> u64 {
+    match message {
+        MempoolMessage::UserMessage(msg) => msg.encoded_len() as u64,
+        MempoolMessage::ValidatorMessage(msg) => msg.encoded_len() as u64,
+    }
+}
+
+fn encode_message(message: &MempoolMessage) -> Vec<u8> {
+    let (tag, mut bytes) = match message {
+        MempoolMessage::UserMessage(msg) => (USER_MESSAGE_TAG, msg.encode_to_vec()),
+        MempoolMessage::ValidatorMessage(msg) => (VALIDATOR_MESSAGE_TAG, msg.encode_to_vec()),
+    };
+    bytes.insert(0, tag);
+    bytes
+}
+
+fn decode_message(bytes: &[u8]) -> Result<MempoolMessage, HubError> {
+    match bytes.split_first() {
+        Some((&USER_MESSAGE_TAG, body)) => Ok(MempoolMessage::UserMessage(
+            proto::Message::decode(body).map_err(|e| HubError::from(e))?,
+        )),
+        Some((&VALIDATOR_MESSAGE_TAG, body)) => Ok(MempoolMessage::ValidatorMessage(
+            proto::ValidatorMessage::decode(body).map_err(|e| HubError::from(e))?,
+        )),
+        _ => Err(HubError::invalid_internal_state(
+            "unknown spilled mempool message",
+        )),
+    }
+}
+
+fn make_shard_prefix(shard_id: u32) -> Vec<u8> {
+    shard_id.to_be_bytes().to_vec()
+}
+
+fn make_spill_key(shard_id: u32, mempool_key: &MempoolKey) -> Vec<u8> {
+    let mut key = make_shard_prefix(shard_id);
+    key.extend(mempool_key.encode());
+    key
+}
+
+/// Overflow area for the mempool. Messages are keyed by shard and mempool key so iterating a
+/// shard returns them in the same priority order as the in-memory queue. Only messages that
+/// already passed mempool validation are spilled, so they are moved back into memory as-is.
+pub struct MempoolSpill {
+    db: Arc<RocksDB>,
+    counts: HashMap<u32, u64>,
+    bytes: u64,
+}
+
+impl MempoolSpill {
+    pub fn open(dir: &str) -> Result<MempoolSpill, RocksdbError> {
+        let db = RocksDB::new(dir);
+        // The mempool doesn't survive restarts, so neither do spilled messages
+        if Path::new(dir).exists() {
+            db.destroy()?;
+        }
+        db.open()?;
+        Ok(MempoolSpill {
+            db: Arc::new(db),
+            counts: HashMap::new(),
+            bytes: 0,
+        })
+    }
+
+    pub fn len(&self, shard_id: u32) -> u64 {
+        *self.counts.get(&shard_id).unwrap_or(&0)
+    }
+
+    pub fn shard_ids(&self) -> impl Iterator<Item = u32> + '_ {
+        self.counts.keys().copied()
+    }
+
+    // Size of the spilled messages across shards, as stored
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    pub fn contains(&self, shard_id: u32, key: &MempoolKey) -> bool {
+        matches!(self.db.get(&make_spill_key(shard_id, key)), Ok(Some(_)))
+    }
+
+    pub fn spill(
+        &mut self,
+        shard_id: u32,
+        key: &MempoolKey,
+        message: &MempoolMessage,
+    ) -> Result<(), RocksdbError> {
+        let value = encode_message(message);
+        self.db.put(&make_spill_key(shard_id, key), &value)?;
+        *self.counts.entry(shard_id).or_insert(0) += 1;
+        self.bytes += value.len() as u64;
+        Ok(())
+    }
+
+    pub fn remove(&mut self, shard_id: u32, key: &MempoolKey) -> Result<bool, RocksdbError> {
+        let spill_key = make_spill_key(shard_id, key);
+        let value = match self.db.get(&spill_key)? {
+            None => return Ok(false),
+            Some(value) => value,
+        };
+        self.db.del(&spill_key)?;
+        self.bytes = self.bytes.saturating_sub(value.len() as u64);
+        if let Some(count) = self.counts.get_mut(&shard_id) {
+            *count = count.saturating_sub(1);
+        }
+        Ok(true)
+    }
+
+    // The encoded mempool key of the highest priority spilled message for the shard
+    pub fn first_key(&self, shard_id: u32) -> Result<Option<Vec<u8>>, HubError> {
+        if self.len(shard_id) == 0 {
+            return Ok(None);
+        }
+        let start_prefix = make_shard_prefix(shard_id);
+        let stop_prefix = increment_vec_u8(&start_prefix);
+        let mut first_key = None;
+        self.db.for_each_iterator_by_prefix(
+            Some(start_prefix.clone()),
+            Some(stop_prefix),
+            &PageOptions::default(),
+            |key, _| {
+                first_key = Some(key[start_prefix.len()..].to_vec());
+                Ok(true) // Stop iterating
+            },
+        )?;
+        Ok(first_key)
+    }
+
+    // Removes and returns the highest priority spilled messages for the shard, up to
+    // [max_bytes]. At least one message is returned if any are spilled so callers always make
+    // progress.
+    pub fn reload(
+        &mut self,
+        shard_id: u32,
+        max_bytes: u64,
+    ) -> Result<Vec<MempoolMessage>, HubError> {
+        if self.len(shard_id) == 0 {
+            return Ok(vec![]);
+        }
+
+        let start_prefix = make_shard_prefix(shard_id);
+        let stop_prefix = increment_vec_u8(&start_prefix);
+
+        let mut messages = vec![];
+        let mut total_bytes = 0;
+        let mut stored_bytes = 0;
+        let mut txn = self.db.txn();
+        self.db.for_each_iterator_by_prefix(
+            Some(start_prefix),
+            Some(stop_prefix),
+            &PageOptions::default(),
+            |key, value| {
+                let message = decode_message(value)?;
+                let size = message_size(&message);
+                if !messages.is_empty() && total_bytes + size > max_bytes {
+                    return Ok(true); // Stop iterating
+                }
+                total_bytes += size;
+                stored_bytes += value.len() as u64;
+                txn.delete(key.to_vec());
+                messages.push(message);
+                Ok(false) // Continue iterating
+            },
+        )?;
+        self.db.commit(txn)?;
+
+        self.bytes = self.bytes.saturating_sub(stored_bytes);
+        if let Some(count) = self.counts.get_mut(&shard_id) {
+            *count = count.saturating_sub(messages.len() as u64);
+        }
+        Ok(messages)
+    }
+}
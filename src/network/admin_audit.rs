@@ -0,0 +1,344 @@
+use crate::network::rpc_extensions::{authenticated_user, parse_rpc_auth};
+use crate::network::rpc_timeout::RpcCategory;
+use crate::utils::statsd_wrapper::StatsdClientWrapper;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::future::Future;
+use std::io::Write;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tonic::body::BoxBody;
+use tonic::codegen::http;
+use tonic::metadata::MetadataMap;
+use tonic::transport::server::TcpConnectInfo;
+use tower::{Layer, Service};
+use tracing::{error, info};
+
+const ADMIN_SERVICE_PREFIX: &str = "/AdminService/";
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Config {
+    // Records who called which admin rpc, from where, and whether it succeeded
+    pub enabled: bool,
+    // Records are appended to this file as json lines when set, and logged otherwise
+    pub file: String,
+}
+
+/// One authenticated admin rpc call. Credentials are never recorded, only the user they belong to.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    // Unix time in milliseconds
+    pub timestamp: u64,
+    pub user: String,
+    pub rpc: String,
+    pub source_ip: Option<String>,
+    pub outcome: String,
+    // The grpc status code, Ok on success
+    pub code: String,
+}
+
+enum AuditSink {
+    Log,
+    File(Mutex<File>),
+}
+
+struct AuditLog {
+    allowed_users: HashMap<String, String>,
+    sink: AuditSink,
+    statsd_client: StatsdClientWrapper,
+}
+
+impl AuditLog {
+    fn record(&self, record: &AuditRecord) {
+        self.statsd_client.count_with_tags(
+            "admin.audit.calls",
+            &[
+                ("rpc", &record.rpc),
+                ("user", &record.user),
+                ("outcome", &record.outcome),
+            ],
+            1,
+        );
+        match &self.sink {
+            AuditSink::Log => info!(
+                user = record.user,
+                rpc = record.rpc,
+                source_ip = record.source_ip,
+                outcome = record.outcome,
+                code = record.code,
+                "admin rpc call"
+            ),
+            AuditSink::File(file) => {
+                // Serializing a struct of strings can't fail
+                let mut line = serde_json::to_string(record).unwrap();
+                line.push('\n');
+                if let Err(err) = file.lock().unwrap().write_all(line.as_bytes()) {
+                    error!(
+                        rpc = record.rpc,
+                        "Unable to write admin audit record: {}", err
+                    );
+                }
+            }
+        }
+    }
+}
+
+// Handler errors are returned as trailers-only responses, with the status in the headers. A
+// successful response only has it in the trailers.
+fn response_code(response: &http::Response<BoxBody>) -> tonic::Code {
+    match response.headers().get("grpc-status") {
+        Some(status) => tonic::Code::from_bytes(status.as_bytes()),
+        None => tonic::Code::Ok,
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Records every authenticated admin rpc call when enabled. Calls that fail authentication are
+/// left to the admin service to reject and aren't recorded. Streaming rpcs are recorded once the
+/// stream is returned.
+#[derive(Clone)]
+pub struct AdminAuditLayer {
+    audit_log: Option<Arc<AuditLog>>,
+}
+
+impl AdminAuditLayer {
+    pub fn new(
+        config: &Config,
+        admin_rpc_auth: &str,
+        statsd_client: StatsdClientWrapper,
+    ) -> Result<Self, String> {
+        if !config.enabled {
+            return Ok(Self { audit_log: None });
+        }
+        let sink = if config.file.is_empty() {
+            AuditSink::Log
+        } else {
+            // Only ever appended to, records from earlier runs are kept
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&config.file)
+                .map_err(|err| format!("unable to open {}: {}", config.file, err))?;
+            AuditSink::File(Mutex::new(file))
+        };
+        Ok(Self {
+            audit_log: Some(Arc::new(AuditLog {
+                allowed_users: parse_rpc_auth(admin_rpc_auth),
+                sink,
+                statsd_client,
+            })),
+        })
+    }
+}
+
+impl<S> Layer<S> for AdminAuditLayer {
+    type Service = AdminAudit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AdminAudit {
+            inner,
+            audit_log: self.audit_log.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AdminAudit<S> {
+    inner: S,
+    audit_log: Option<Arc<AuditLog>>,
+}
+
+impl<S, ReqBody> Service<http::Request<ReqBody>> for AdminAudit<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    ReqBody: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<ReqBody>) -> Self::Future {
+        // The readied service must be the one that's called, keep the clone for the next request
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let path = request.uri().path();
+        let user = match &self.audit_log {
+            Some(audit_log) if path.starts_with(ADMIN_SERVICE_PREFIX) => authenticated_user(
+                &MetadataMap::from_headers(request.headers().clone()),
+                &audit_log.allowed_users,
+            )
+            .ok()
+            .flatten(),
+            _ => None,
+        };
+        let Some(user) = user else {
+            return Box::pin(inner.call(request));
+        };
+
+        let audit_log = self.audit_log.clone().unwrap();
+        let timestamp = now_ms();
+        let rpc = RpcCategory::method_label(path);
+        let source_ip = request
+            .extensions()
+            .get::<TcpConnectInfo>()
+            .and_then(|info| info.remote_addr())
+            .map(|addr| addr.ip().to_string());
+        Box::pin(async move {
+            let result = inner.call(request).await;
+            let code = match &result {
+                Ok(response) => response_code(response),
+                Err(_) => tonic::Code::Internal,
+            };
+            audit_log.record(&AuditRecord {
+                timestamp,
+                user,
+                rpc,
+                source_ip,
+                outcome: if code == tonic::Code::Ok {
+                    "success".to_string()
+                } else {
+                    "failure".to_string()
+                },
+                code: format!("{:?}", code),
+            });
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::store::test_helper;
+    use base64::Engine;
+    use std::convert::Infallible;
+
+    // Stands in for the grpc server, failing every rpc on shard 2
+    #[derive(Clone)]
+    struct FakeAdminService;
+
+    impl Service<http::Request<()>> for FakeAdminService {
+        type Response = http::Response<BoxBody>;
+        type Error = Infallible;
+        type Future = std::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: http::Request<()>) -> Self::Future {
+            let response = if request.uri().query() == Some("shard=2") {
+                tonic::Status::invalid_argument("no shard store for 2").into_http()
+            } else {
+                http::Response::new(tonic::body::empty_body())
+            };
+            std::future::ready(Ok(response))
+        }
+    }
+
+    fn request(path: &str, credentials: &str) -> http::Request<()> {
+        let mut request = http::Request::builder()
+            .uri(path)
+            .header(
+                "authorization",
+                format!(
+                    "Basic {}",
+                    base64::engine::general_purpose::STANDARD.encode(credentials)
+                ),
+            )
+            .body(())
+            .unwrap();
+        request.extensions_mut().insert(TcpConnectInfo {
+            local_addr: None,
+            remote_addr: Some("10.0.0.1:5000".parse().unwrap()),
+        });
+        request
+    }
+
+    fn read_records(path: &std::path::Path) -> Vec<AuditRecord> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_records_admin_rpc_calls() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("audit.log");
+        let config = Config {
+            enabled: true,
+            file: file.to_str().unwrap().to_string(),
+        };
+        let layer =
+            AdminAuditLayer::new(&config, "admin:secret", test_helper::statsd_client()).unwrap();
+        let mut service = layer.layer(FakeAdminService);
+
+        for request in [
+            request("/AdminService/FreezeShard?shard=1", "admin:secret"),
+            request("/AdminService/FreezeShard?shard=2", "admin:secret"),
+            // Not authenticated, or not an admin rpc
+            request("/AdminService/FreezeShard?shard=1", "admin:wrong"),
+            request("/HubService/GetInfo", "admin:secret"),
+        ] {
+            service.call(request).await.unwrap();
+        }
+
+        let records = read_records(&file);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].user, "admin");
+        assert_eq!(records[0].rpc, "AdminService.FreezeShard");
+        assert_eq!(records[0].source_ip, Some("10.0.0.1".to_string()));
+        assert_eq!(records[0].outcome, "success");
+        assert_eq!(records[0].code, "Ok");
+        assert_eq!(records[1].outcome, "failure");
+        assert_eq!(records[1].code, "InvalidArgument");
+        assert!(!std::fs::read_to_string(&file).unwrap().contains("secret"));
+
+        // Reopening appends to the existing records
+        let layer =
+            AdminAuditLayer::new(&config, "admin:secret", test_helper::statsd_client()).unwrap();
+        let mut service = layer.layer(FakeAdminService);
+        service
+            .call(request("/AdminService/UnfreezeShard", "admin:secret"))
+            .await
+            .unwrap();
+        let records = read_records(&file);
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[2].rpc, "AdminService.UnfreezeShard");
+    }
+
+    #[tokio::test]
+    async fn test_disabled_by_default() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("audit.log");
+        let config = Config {
+            enabled: false,
+            file: file.to_str().unwrap().to_string(),
+        };
+        let layer =
+            AdminAuditLayer::new(&config, "admin:secret", test_helper::statsd_client()).unwrap();
+        let mut service = layer.layer(FakeAdminService);
+        service
+            .call(request("/AdminService/FreezeShard", "admin:secret"))
+            .await
+            .unwrap();
+        assert!(!file.exists());
+    }
+}
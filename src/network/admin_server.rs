@@ -1,30 +1,70 @@
+use crate::connectors::onchain_events::pause::PauseState;
+use crate::connectors::onchain_events::submitted::validate_submitted_events;
 use crate::connectors::onchain_events::OnchainEventsRequest;
-use crate::jobs::snapshot_upload::upload_snapshot;
-use crate::mempool::mempool::MempoolRequest;
-use crate::network::rpc_extensions::authenticate_request;
+use crate::core::error::HubError;
+use crate::core::types::SnapchainValidatorContext;
+use crate::jobs::registry::{CancelJobError, JobRegistry};
+use crate::jobs::snapshot_upload::{all_shard_ids, upload_snapshot};
+use crate::mempool::admission::MessageTypeAdmission;
+use crate::mempool::mempool::{MempoolRequest, MempoolSource};
+use crate::network::gossip::{DialPeerError, GossipEvent};
+use crate::network::rpc_extensions::{authenticate_request, parse_rpc_auth};
 use crate::proto::admin_service_server::AdminService;
-use crate::proto::{self, Empty, FarcasterNetwork, RetryOnchainEventsRequest};
+use crate::proto::{
+    self, CancelJobRequest, CheckShardConsistencyRequest, CheckShardConsistencyResponse,
+    CompactTrieRequest, CompactTrieResponse, CreateCheckpointRequest, CreateCheckpointResponse,
+    DialPeerRequest, DialPeerResponse, Empty, FarcasterNetwork, FreezeShardRequest, GcTrieRequest,
+    GcTrieResponse, GetPeersResponse, ListJobsResponse, MessageType, MessageTypeAdmissionResponse,
+    RebuildIndexProgress, RebuildIndexRequest, RecomputeTrieRootRequest, RecomputeTrieRootResponse,
+    RetryOnchainEventsRequest, SetMessageTypeAdmissionRequest, SetMetricsSinkRequest,
+    SubmitOnChainEventsRequest, SubmitOnChainEventsResponse, ValidatorMessage,
+};
 use crate::storage;
-use crate::storage::store::stores::Stores;
+use crate::storage::db::checkpoint::{self, CheckpointError};
+use crate::storage::db::{RocksDB, RocksdbError};
+use crate::storage::store::engine::MempoolMessage;
+use crate::storage::store::stores::{SecondaryIndex, Stores};
 use crate::storage::store::BlockStore;
-use crate::utils::statsd_wrapper::StatsdClientWrapper;
+use crate::utils::statsd_wrapper::{udp_statsd_client, StatsdClientWrapper};
 use rocksdb;
 use std::collections::HashMap;
 use std::io;
+use std::sync::Arc;
+use std::time::Duration;
 use thiserror::Error;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Request, Response, Status};
-use tracing::error;
+use tracing::{error, info, warn};
+
+// A block committed while the stores are counted makes the counts disagree without anything being
+// wrong, so the check is retried when the trie changed under it
+const CONSISTENCY_CHECK_ATTEMPTS: usize = 3;
+
+// Messages reindexed per write batch, and so per progress update
+const REBUILD_INDEX_BATCH_SIZE: usize = 1_000;
+
+// Orphaned trie nodes deleted per write batch. Engine commits wait while a batch is deleted.
+const TRIE_GC_BATCH_SIZE: usize = 1_000;
+
+// How long a DialPeer call waits for the connection before reporting the dial as failed
+const DIAL_PEER_TIMEOUT: Duration = Duration::from_secs(30);
 
 pub struct MyAdminService {
     allowed_users: HashMap<String, String>,
     pub mempool_tx: mpsc::Sender<MempoolRequest>,
     onchain_events_request_tx: mpsc::Sender<OnchainEventsRequest>,
+    onchain_events_pause: PauseState,
+    message_type_admission: MessageTypeAdmission,
+    accept_submitted_events: bool,
+    chain_id: u32,
     snapshot_config: storage::db::snapshot::Config,
     shard_stores: HashMap<u32, Stores>,
     block_store: BlockStore,
     fc_network: FarcasterNetwork,
     statsd_client: StatsdClientWrapper,
+    gossip_tx: Option<mpsc::Sender<GossipEvent<SnapchainValidatorContext>>>,
+    job_registry: JobRegistry,
 }
 
 #[derive(Debug, Error)]
@@ -41,35 +81,66 @@ impl MyAdminService {
         rpc_auth: String,
         mempool_tx: mpsc::Sender<MempoolRequest>,
         onchain_events_request_tx: mpsc::Sender<OnchainEventsRequest>,
+        onchain_events_pause: PauseState,
+        message_type_admission: MessageTypeAdmission,
+        accept_submitted_events: bool,
+        chain_id: u32,
         shard_stores: HashMap<u32, Stores>,
         block_store: BlockStore,
         snapshot_config: storage::db::snapshot::Config,
         fc_network: FarcasterNetwork,
         statsd_client: StatsdClientWrapper,
     ) -> Self {
-        let mut allowed_users = HashMap::new();
-        for auth in rpc_auth.split(",") {
-            let parts: Vec<&str> = auth.split(":").collect();
-            if parts.len() == 2 {
-                allowed_users.insert(parts[0].to_string(), parts[1].to_string());
-            }
-        }
-
         Self {
-            allowed_users,
+            allowed_users: parse_rpc_auth(&rpc_auth),
             mempool_tx,
             onchain_events_request_tx,
+            onchain_events_pause,
+            message_type_admission,
+            accept_submitted_events,
+            chain_id,
             shard_stores,
             block_store,
             snapshot_config,
             fc_network,
+            job_registry: JobRegistry::new(statsd_client.clone()),
             statsd_client,
+            gossip_tx: None,
         }
     }
 
+    /// Shared with the scheduled jobs, so ListJobs and CancelJob cover them too
+    pub fn with_job_registry(mut self, job_registry: JobRegistry) -> Self {
+        self.job_registry = job_registry;
+        self
+    }
+
+    /// For DialPeer and GetPeers, which are unavailable without it
+    pub fn with_gossip_tx(
+        mut self,
+        gossip_tx: mpsc::Sender<GossipEvent<SnapchainValidatorContext>>,
+    ) -> Self {
+        self.gossip_tx = Some(gossip_tx);
+        self
+    }
+
     pub fn enabled(&self) -> bool {
         !self.allowed_users.is_empty()
     }
+
+    fn get_stores_for_shard(&self, shard_id: u32) -> Result<&Stores, Status> {
+        self.shard_stores
+            .get(&shard_id)
+            .ok_or_else(|| Status::invalid_argument(format!("no shard store for {}", shard_id)))
+    }
+
+    fn get_db_for_shard(&self, shard_id: u32) -> Result<Arc<RocksDB>, Status> {
+        if shard_id == 0 {
+            Ok(self.block_store.db.clone())
+        } else {
+            Ok(self.get_stores_for_shard(shard_id)?.db.clone())
+        }
+    }
 }
 
 #[tonic::async_trait]
@@ -171,6 +242,622 @@ impl AdminService for MyAdminService {
         Ok(Response::new(Empty {}))
     }
 
+    async fn pause_onchain_ingestion(
+        &self,
+        request: Request<Empty>,
+    ) -> std::result::Result<Response<Empty>, Status> {
+        authenticate_request(&request, &self.allowed_users)?;
+
+        if self.onchain_events_pause.pause() {
+            info!("Paused onchain events ingestion");
+            self.statsd_client.count("admin.onchain_ingestion_pause", 1);
+        }
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn resume_onchain_ingestion(
+        &self,
+        request: Request<Empty>,
+    ) -> std::result::Result<Response<Empty>, Status> {
+        authenticate_request(&request, &self.allowed_users)?;
+
+        if self.onchain_events_pause.resume() {
+            info!("Resumed onchain events ingestion");
+            self.statsd_client
+                .count("admin.onchain_ingestion_resume", 1);
+        }
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn freeze_shard(
+        &self,
+        request: Request<FreezeShardRequest>,
+    ) -> std::result::Result<Response<Empty>, Status> {
+        authenticate_request(&request, &self.allowed_users)?;
+
+        let shard_id = request.into_inner().shard_id;
+        let stores = self.get_stores_for_shard(shard_id)?;
+        if stores.shard_freeze.freeze() {
+            info!(shard_id, "Froze shard");
+            self.statsd_client
+                .count_with_shard(shard_id, "admin.shard_freeze", 1);
+        }
+        self.statsd_client
+            .gauge_with_shard(shard_id, "admin.shard_frozen", 1);
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn unfreeze_shard(
+        &self,
+        request: Request<FreezeShardRequest>,
+    ) -> std::result::Result<Response<Empty>, Status> {
+        authenticate_request(&request, &self.allowed_users)?;
+
+        let shard_id = request.into_inner().shard_id;
+        let stores = self.get_stores_for_shard(shard_id)?;
+        if stores.shard_freeze.is_halted() {
+            return Err(Status::failed_precondition(
+                "shard halted after its commits couldn't be written, restart the node",
+            ));
+        }
+        if stores.shard_freeze.unfreeze() {
+            info!(shard_id, "Unfroze shard");
+            self.statsd_client
+                .count_with_shard(shard_id, "admin.shard_unfreeze", 1);
+        }
+        self.statsd_client
+            .gauge_with_shard(shard_id, "admin.shard_frozen", 0);
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn create_checkpoint(
+        &self,
+        request: Request<CreateCheckpointRequest>,
+    ) -> std::result::Result<Response<CreateCheckpointResponse>, Status> {
+        authenticate_request(&request, &self.allowed_users)?;
+
+        let CreateCheckpointRequest { shard_id, path } = request.into_inner();
+        if path.is_empty() {
+            return Err(Status::invalid_argument("path is required"));
+        }
+        let db = self.get_db_for_shard(shard_id)?;
+
+        let fc_network = self.fc_network;
+        let checkpoint_path = path.clone();
+        let metadata = tokio::task::spawn_blocking(move || {
+            checkpoint::create_checkpoint(&db, fc_network, shard_id, &checkpoint_path)
+        })
+        .await
+        .map_err(|err| Status::internal(err.to_string()))?
+        .map_err(|err| match err {
+            CheckpointError::RocksdbError(RocksdbError::PathExists(_)) => {
+                Status::already_exists(err.to_string())
+            }
+            _ => Status::internal(err.to_string()),
+        })?;
+
+        let block_height = metadata.block_height;
+        info!(shard_id, path, block_height, "Created checkpoint");
+        self.statsd_client
+            .count_with_shard(shard_id, "admin.checkpoint_created", 1);
+        Ok(Response::new(CreateCheckpointResponse { block_height }))
+    }
+
+    async fn check_shard_consistency(
+        &self,
+        request: Request<CheckShardConsistencyRequest>,
+    ) -> std::result::Result<Response<CheckShardConsistencyResponse>, Status> {
+        authenticate_request(&request, &self.allowed_users)?;
+
+        let shard_id = request.into_inner().shard_id;
+        let stores = self.get_stores_for_shard(shard_id)?.clone();
+        let counts = tokio::task::spawn_blocking(move || {
+            for _ in 0..CONSISTENCY_CHECK_ATTEMPTS {
+                let trie_leaf_count = stores.get_trie_leaf_count();
+                let store_count = stores.get_store_count()?;
+                if stores.get_trie_leaf_count() == trie_leaf_count {
+                    return Ok(Some((trie_leaf_count, store_count)));
+                }
+            }
+            Ok(None)
+        })
+        .await
+        .map_err(|err| Status::internal(err.to_string()))?
+        .map_err(|err: HubError| Status::internal(err.to_string()))?;
+        let Some((trie_leaf_count, store_count)) = counts else {
+            return Err(Status::unavailable(
+                "shard kept changing during the check, try again",
+            ));
+        };
+
+        let consistent = trie_leaf_count == store_count;
+        if !consistent {
+            warn!(
+                shard_id,
+                trie_leaf_count, store_count, "Trie leaf count doesn't match the stores"
+            );
+        }
+        self.statsd_client
+            .gauge_with_shard(shard_id, "admin.shard_consistent", consistent as u64);
+        Ok(Response::new(CheckShardConsistencyResponse {
+            trie_leaf_count,
+            store_count,
+            consistent,
+        }))
+    }
+
+    async fn recompute_trie_root(
+        &self,
+        request: Request<RecomputeTrieRootRequest>,
+    ) -> std::result::Result<Response<RecomputeTrieRootResponse>, Status> {
+        authenticate_request(&request, &self.allowed_users)?;
+
+        let shard_id = request.into_inner().shard_id;
+        let stores = self.get_stores_for_shard(shard_id)?.clone();
+        info!(shard_id, "Recomputing trie root from the stores");
+        let job = self
+            .job_registry
+            .start("recompute_trie_root", Some(shard_id), false);
+        let roots = tokio::task::spawn_blocking(move || {
+            let _job = job;
+            for _ in 0..CONSISTENCY_CHECK_ATTEMPTS {
+                let stored_root = stores.get_stored_trie_root()?;
+                let recomputed_root = stores.recompute_trie_root()?;
+                if stores.get_stored_trie_root()? == stored_root {
+                    return Ok(Some((stored_root, recomputed_root)));
+                }
+            }
+            Ok(None)
+        })
+        .await
+        .map_err(|err| Status::internal(err.to_string()))?
+        .map_err(|err: HubError| Status::internal(err.to_string()))?;
+        let Some((stored_root, recomputed_root)) = roots else {
+            return Err(Status::unavailable(
+                "shard kept changing during the check, try again",
+            ));
+        };
+
+        let matches = stored_root == recomputed_root;
+        if !matches {
+            warn!(
+                shard_id,
+                stored_root = hex::encode(&stored_root),
+                recomputed_root = hex::encode(&recomputed_root),
+                "Trie root doesn't match the stores"
+            );
+            self.statsd_client
+                .count_with_shard(shard_id, "admin.trie_root_mismatch", 1);
+        }
+        Ok(Response::new(RecomputeTrieRootResponse {
+            stored_root,
+            recomputed_root,
+            matches,
+        }))
+    }
+
+    async fn submit_on_chain_events(
+        &self,
+        request: Request<SubmitOnChainEventsRequest>,
+    ) -> std::result::Result<Response<SubmitOnChainEventsResponse>, Status> {
+        authenticate_request(&request, &self.allowed_users)?;
+
+        if !self.accept_submitted_events {
+            return Err(Status::failed_precondition(
+                "submitting onchain events is disabled, see onchain_events.accept_submitted_events",
+            ));
+        }
+        let events = request.into_inner().events;
+        validate_submitted_events(&events, self.chain_id).map_err(Status::invalid_argument)?;
+
+        // Queue the whole batch before waiting on the results, the mempool only picks up new
+        // messages every poll
+        let mut results = Vec::with_capacity(events.len());
+        for event in events {
+            let (result_tx, result_rx) = oneshot::channel();
+            self.mempool_tx
+                .send(MempoolRequest::AddMessage(
+                    MempoolMessage::ValidatorMessage(ValidatorMessage {
+                        on_chain_event: Some(event),
+                        fname_transfer: None,
+                    }),
+                    MempoolSource::Local,
+                    Some(result_tx),
+                ))
+                .await
+                .map_err(|err| Status::from_error(Box::new(err)))?;
+            results.push(result_rx);
+        }
+
+        let mut num_submitted = 0;
+        let mut num_duplicates = 0;
+        for result_rx in results {
+            match result_rx
+                .await
+                .map_err(|err| Status::internal(err.to_string()))?
+            {
+                Ok(()) => num_submitted += 1,
+                // Resubmitting a batch is harmless, the events already merged are left out
+                Err(err) if err.code == "bad_request.duplicate" => num_duplicates += 1,
+                Err(err) => return Err(Status::internal(err.to_string())),
+            }
+        }
+
+        info!(num_submitted, num_duplicates, "Submitted onchain events");
+        self.statsd_client
+            .count("admin.onchain_events_submitted", num_submitted as i64);
+        Ok(Response::new(SubmitOnChainEventsResponse {
+            num_submitted,
+            num_duplicates,
+        }))
+    }
+
+    type RebuildIndexStream = ReceiverStream<Result<RebuildIndexProgress, Status>>;
+
+    async fn rebuild_index(
+        &self,
+        request: Request<RebuildIndexRequest>,
+    ) -> std::result::Result<Response<Self::RebuildIndexStream>, Status> {
+        authenticate_request(&request, &self.allowed_users)?;
+
+        let RebuildIndexRequest {
+            shard_id,
+            index_name,
+        } = request.into_inner();
+        let Some(index) = SecondaryIndex::from_name(&index_name) else {
+            let names: Vec<&str> = SecondaryIndex::ALL.iter().map(|i| i.name()).collect();
+            return Err(Status::invalid_argument(format!(
+                "unknown index {}, expected one of {}",
+                index_name,
+                names.join(", ")
+            )));
+        };
+        let stores = self.get_stores_for_shard(shard_id)?.clone();
+        let statsd_client = self.statsd_client.clone();
+
+        info!(shard_id, index = index.name(), "Rebuilding index");
+        let job = self
+            .job_registry
+            .start("rebuild_index", Some(shard_id), true);
+        let (tx, rx) = mpsc::channel(100);
+        tokio::task::spawn_blocking(move || {
+            let mut page_token = None;
+            let mut messages_indexed = 0;
+            loop {
+                if job.is_cancelled() {
+                    warn!(
+                        shard_id,
+                        index = index.name(),
+                        messages_indexed,
+                        "Index rebuild cancelled"
+                    );
+                    let _ = tx.blocking_send(Err(Status::cancelled("index rebuild cancelled")));
+                    return;
+                }
+                let page = match stores.rebuild_index(index, page_token, REBUILD_INDEX_BATCH_SIZE) {
+                    Ok(page) => page,
+                    Err(err) => {
+                        error!(
+                            shard_id,
+                            index = index.name(),
+                            messages_indexed,
+                            "Error rebuilding index: {}",
+                            err
+                        );
+                        let _ = tx.blocking_send(Err(Status::internal(err.to_string())));
+                        return;
+                    }
+                };
+                messages_indexed += page.messages_indexed;
+                job.set_progress(messages_indexed);
+                statsd_client.gauge_with_shard(
+                    shard_id,
+                    &format!("admin.rebuild_index.{}.messages_indexed", index.name()),
+                    messages_indexed,
+                );
+
+                let done = page.next_page_token.is_none();
+                let progress = RebuildIndexProgress {
+                    messages_indexed,
+                    fid: page.fid,
+                    done,
+                };
+                // The batches written so far stay, the index is just partially rebuilt
+                if tx.blocking_send(Ok(progress)).is_err() {
+                    warn!(
+                        shard_id,
+                        index = index.name(),
+                        messages_indexed,
+                        "Client went away, index rebuild stopped"
+                    );
+                    return;
+                }
+                if done {
+                    break;
+                }
+                page_token = page.next_page_token;
+            }
+
+            info!(
+                shard_id,
+                index = index.name(),
+                messages_indexed,
+                "Rebuilt index"
+            );
+            statsd_client.count_with_shard(
+                shard_id,
+                &format!("admin.rebuild_index.{}.completed", index.name()),
+                1,
+            );
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn gc_trie(
+        &self,
+        request: Request<GcTrieRequest>,
+    ) -> std::result::Result<Response<GcTrieResponse>, Status> {
+        authenticate_request(&request, &self.allowed_users)?;
+
+        let shard_id = request.into_inner().shard_id;
+        let stores = self.get_stores_for_shard(shard_id)?.clone();
+        info!(shard_id, "Collecting orphaned trie nodes");
+        // Listed until the collection is done, even if the caller goes away
+        let job = self.job_registry.start("gc_trie", Some(shard_id), false);
+        let result = tokio::task::spawn_blocking(move || {
+            let _job = job;
+            stores.collect_trie_garbage(TRIE_GC_BATCH_SIZE)
+        })
+        .await
+        .map_err(|err| Status::internal(err.to_string()))?
+        .map_err(|err| Status::internal(err.to_string()))?;
+
+        info!(
+            shard_id,
+            nodes_scanned = result.nodes_scanned,
+            nodes_deleted = result.nodes_deleted,
+            bytes_reclaimed = result.bytes_reclaimed,
+            "Collected orphaned trie nodes"
+        );
+        self.statsd_client.count_with_shard(
+            shard_id,
+            "admin.trie_gc.nodes_deleted",
+            result.nodes_deleted,
+        );
+        self.statsd_client.count_with_shard(
+            shard_id,
+            "admin.trie_gc.bytes_reclaimed",
+            result.bytes_reclaimed,
+        );
+        Ok(Response::new(GcTrieResponse {
+            nodes_scanned: result.nodes_scanned,
+            nodes_deleted: result.nodes_deleted,
+            bytes_reclaimed: result.bytes_reclaimed,
+        }))
+    }
+
+    async fn compact_trie(
+        &self,
+        request: Request<CompactTrieRequest>,
+    ) -> std::result::Result<Response<CompactTrieResponse>, Status> {
+        authenticate_request(&request, &self.allowed_users)?;
+
+        let shard_id = request.into_inner().shard_id;
+        let stores = self.get_stores_for_shard(shard_id)?.clone();
+        info!(shard_id, "Compacting trie");
+        let job = self
+            .job_registry
+            .start("compact_trie", Some(shard_id), false);
+        let result = tokio::task::spawn_blocking(move || {
+            let _job = job;
+            stores.compact_trie()
+        })
+        .await
+        .map_err(|err| Status::internal(err.to_string()))?
+        .map_err(|err| match err.code.as_str() {
+            "failed_precondition" => Status::aborted(err.message),
+            _ => Status::internal(err.to_string()),
+        })?;
+
+        info!(
+            shard_id,
+            size_before = result.size_before,
+            size_after = result.size_after,
+            bytes_reclaimed = result.bytes_reclaimed,
+            "Compacted trie"
+        );
+        self.statsd_client.count_with_shard(
+            shard_id,
+            "admin.compact_trie.bytes_reclaimed",
+            result.bytes_reclaimed,
+        );
+        Ok(Response::new(CompactTrieResponse {
+            size_before: result.size_before,
+            size_after: result.size_after,
+            bytes_reclaimed: result.bytes_reclaimed,
+        }))
+    }
+
+    async fn set_message_type_admission(
+        &self,
+        request: Request<SetMessageTypeAdmissionRequest>,
+    ) -> std::result::Result<Response<MessageTypeAdmissionResponse>, Status> {
+        authenticate_request(&request, &self.allowed_users)?;
+
+        let SetMessageTypeAdmissionRequest {
+            message_type,
+            enabled,
+        } = request.into_inner();
+        let message_type = match MessageType::try_from(message_type) {
+            Ok(MessageType::None) | Err(_) => {
+                return Err(Status::invalid_argument(format!(
+                    "invalid message type: {}",
+                    message_type
+                )));
+            }
+            Ok(message_type) => message_type,
+        };
+
+        let type_name = message_type.as_str_name();
+        if enabled {
+            if self.message_type_admission.enable(message_type) {
+                info!(message_type = type_name, "Enabled message type admission");
+            }
+        } else if self.message_type_admission.disable(message_type) {
+            warn!(message_type = type_name, "Disabled message type admission");
+        }
+        self.statsd_client.gauge(
+            &format!("admin.message_type_disabled.{}", type_name),
+            (!enabled) as u64,
+        );
+
+        Ok(Response::new(MessageTypeAdmissionResponse {
+            disabled_message_types: self
+                .message_type_admission
+                .disabled_types()
+                .into_iter()
+                .map(|message_type| message_type as i32)
+                .collect(),
+        }))
+    }
+
+    async fn dial_peer(
+        &self,
+        request: Request<DialPeerRequest>,
+    ) -> std::result::Result<Response<DialPeerResponse>, Status> {
+        authenticate_request(&request, &self.allowed_users)?;
+
+        let multiaddr = request.into_inner().multiaddr;
+        let address: libp2p::Multiaddr = multiaddr.parse().map_err(|err| {
+            Status::invalid_argument(format!("invalid multiaddr {}: {}", multiaddr, err))
+        })?;
+        let Some(gossip_tx) = &self.gossip_tx else {
+            return Err(Status::unavailable("gossip isn't running"));
+        };
+
+        self.statsd_client.count("admin.peer_dials", 1);
+        let (reply_tx, reply_rx) = oneshot::channel();
+        gossip_tx
+            .send(GossipEvent::DialPeer(address, reply_tx))
+            .await
+            .map_err(|err| Status::internal(err.to_string()))?;
+        let result = match tokio::time::timeout(DIAL_PEER_TIMEOUT, reply_rx).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(DialPeerError::Failed("gossip stopped".to_string())),
+            Err(_) => Err(DialPeerError::Failed(format!(
+                "not connected after {:?}",
+                DIAL_PEER_TIMEOUT
+            ))),
+        };
+
+        match result {
+            Ok(peer_id) => {
+                info!(multiaddr, peer_id = peer_id.to_string(), "Dialed peer");
+                Ok(Response::new(DialPeerResponse {
+                    connected: true,
+                    peer_id: peer_id.to_string(),
+                    error: String::new(),
+                }))
+            }
+            Err(DialPeerError::InvalidAddress(err)) => Err(Status::invalid_argument(err)),
+            Err(DialPeerError::Failed(err)) => {
+                warn!(multiaddr, "Failed to dial peer: {}", err);
+                self.statsd_client.count("admin.peer_dials_failed", 1);
+                Ok(Response::new(DialPeerResponse {
+                    connected: false,
+                    peer_id: String::new(),
+                    error: err,
+                }))
+            }
+        }
+    }
+
+    async fn get_peers(
+        &self,
+        request: Request<Empty>,
+    ) -> std::result::Result<Response<GetPeersResponse>, Status> {
+        authenticate_request(&request, &self.allowed_users)?;
+
+        let Some(gossip_tx) = &self.gossip_tx else {
+            return Err(Status::unavailable("gossip isn't running"));
+        };
+        let (reply_tx, reply_rx) = oneshot::channel();
+        gossip_tx
+            .send(GossipEvent::GetPeers(reply_tx))
+            .await
+            .map_err(|err| Status::internal(err.to_string()))?;
+        let (peers, bans) = reply_rx
+            .await
+            .map_err(|_| Status::unavailable("gossip stopped"))?;
+
+        Ok(Response::new(GetPeersResponse {
+            connected_peer_ids: peers.iter().map(|peer_id| peer_id.to_string()).collect(),
+            bans: bans
+                .into_iter()
+                .map(|ban| proto::PeerBan {
+                    peer_id: ban.peer_id,
+                    banned_until: ban.banned_until,
+                    violations: ban.violations,
+                })
+                .collect(),
+        }))
+    }
+
+    async fn set_metrics_sink(
+        &self,
+        request: Request<SetMetricsSinkRequest>,
+    ) -> std::result::Result<Response<Empty>, Status> {
+        authenticate_request(&request, &self.allowed_users)?;
+
+        let SetMetricsSinkRequest {
+            address,
+            prefix,
+            sample_rate,
+        } = request.into_inner();
+        if prefix.is_empty() {
+            return Err(Status::invalid_argument("statsd prefix must be specified"));
+        }
+        let sample_rate = sample_rate.unwrap_or(1.0);
+        if !(sample_rate > 0.0 && sample_rate <= 1.0) {
+            return Err(Status::invalid_argument(format!(
+                "sample rate must be in (0, 1], got {}",
+                sample_rate
+            )));
+        }
+        let client = udp_statsd_client(&address, &prefix).map_err(Status::invalid_argument)?;
+
+        self.statsd_client.set_sink(client, sample_rate);
+        info!(address, prefix, sample_rate, "Switched the metrics sink");
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn list_jobs(
+        &self,
+        request: Request<Empty>,
+    ) -> std::result::Result<Response<ListJobsResponse>, Status> {
+        authenticate_request(&request, &self.allowed_users)?;
+
+        Ok(Response::new(ListJobsResponse {
+            jobs: self.job_registry.jobs(),
+        }))
+    }
+
+    async fn cancel_job(
+        &self,
+        request: Request<CancelJobRequest>,
+    ) -> std::result::Result<Response<Empty>, Status> {
+        authenticate_request(&request, &self.allowed_users)?;
+
+        let job_id = request.into_inner().job_id;
+        self.job_registry.cancel(job_id).map_err(|err| match err {
+            CancelJobError::NotFound(_) => Status::not_found(err.to_string()),
+            CancelJobError::NotCancellable(_) => Status::failed_precondition(err.to_string()),
+        })?;
+        self.statsd_client.count("admin.jobs_cancelled", 1);
+        Ok(Response::new(Empty {}))
+    }
+
     async fn upload_snapshot(
         &self,
         request: Request<Empty>,
@@ -186,13 +873,19 @@ impl AdminService for MyAdminService {
         let shard_stores = self.shard_stores.clone();
         let block_store = self.block_store.clone();
         let statsd_client = self.statsd_client.clone();
+        let job_registry = self.job_registry.clone();
+        // Manual uploads include every shard, regardless of their schedules
+        let shard_ids = all_shard_ids(&shard_stores);
         tokio::spawn(async move {
             if let Err(err) = upload_snapshot(
                 snapshot_config,
                 fc_network,
                 block_store,
                 shard_stores,
+                shard_ids,
+                None,
                 statsd_client,
+                job_registry,
             )
             .await
             {
@@ -203,3 +896,680 @@ impl AdminService for MyAdminService {
         Ok(Response::new(Empty {}))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mempool::mempool::{self, Mempool, MempoolMessagesRequest};
+    use crate::network::peer_bans::PeerBans;
+    use crate::proto::{link_body::Target, OnChainEventType};
+    use crate::storage::db::PageOptions;
+    use crate::storage::store::account::{
+        make_message_by_hash_key, make_ts_hash, LinkStore, StoreDef,
+    };
+    use crate::storage::store::engine::{BlockLimits, ShardEngine};
+    use crate::storage::store::test_helper;
+    use crate::storage::trie::merkle_trie::TrieKey;
+    use crate::utils::factory::{events_factory, messages_factory};
+    use base64::Engine;
+    use tokio::sync::broadcast;
+    use tokio_stream::StreamExt;
+
+    const FID: u64 = 1234;
+
+    struct TestSetup {
+        engine: ShardEngine,
+        service: MyAdminService,
+        messages_request_tx: mpsc::Sender<MempoolMessagesRequest>,
+        _dirs: Vec<tempfile::TempDir>,
+    }
+
+    fn setup(accept_submitted_events: bool) -> TestSetup {
+        let (engine, engine_dir) = test_helper::new_engine();
+        let (mempool_tx, mempool_rx) = mpsc::channel(100);
+        let (messages_request_tx, messages_request_rx) = mpsc::channel(100);
+        let (_shard_decision_tx, shard_decision_rx) = broadcast::channel(100);
+        let shard_stores = HashMap::from([(1, engine.get_stores())]);
+        let message_type_admission = MessageTypeAdmission::default();
+        let mut mempool = Mempool::new(
+            mempool::Config::default(),
+            mempool_rx,
+            messages_request_rx,
+            1,
+            shard_stores.clone(),
+            mpsc::channel(100).0,
+            shard_decision_rx,
+            test_helper::statsd_client(),
+        )
+        .with_message_type_admission(message_type_admission.clone());
+        tokio::spawn(async move { mempool.run().await });
+
+        let blocks_dir = tempfile::TempDir::new().unwrap();
+        let blocks_db = RocksDB::new(blocks_dir.path().join("blocks.db").to_str().unwrap());
+        blocks_db.open().unwrap();
+        let service = MyAdminService::new(
+            "admin:password".to_string(),
+            mempool_tx,
+            mpsc::channel(100).0,
+            PauseState::default(),
+            message_type_admission,
+            accept_submitted_events,
+            10,
+            shard_stores,
+            BlockStore::new(Arc::new(blocks_db)),
+            storage::db::snapshot::Config::default(),
+            FarcasterNetwork::Devnet,
+            test_helper::statsd_client(),
+        );
+        TestSetup {
+            engine,
+            service,
+            messages_request_tx,
+            _dirs: vec![engine_dir, blocks_dir],
+        }
+    }
+
+    fn submit_request(events: &[proto::OnChainEvent]) -> Request<SubmitOnChainEventsRequest> {
+        authorized_request(SubmitOnChainEventsRequest {
+            events: events.to_vec(),
+        })
+    }
+
+    fn authorized_request<T>(message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        let auth = format!(
+            "Basic {}",
+            base64::engine::general_purpose::STANDARD.encode("admin:password")
+        );
+        request
+            .metadata_mut()
+            .insert("authorization", auth.parse().unwrap());
+        request
+    }
+
+    fn rent_events(count: u32) -> Vec<proto::OnChainEvent> {
+        (0..count)
+            .map(|i| {
+                let mut event = events_factory::create_rent_event(FID, None, Some(1), false);
+                event.block_number = 100 + i;
+                event.block_hash = vec![i as u8; 32];
+                event
+            })
+            .collect()
+    }
+
+    async fn add_to_mempool(setup: &TestSetup, message: proto::Message) -> Result<(), HubError> {
+        let (tx, rx) = oneshot::channel();
+        setup
+            .service
+            .mempool_tx
+            .send(MempoolRequest::AddMessage(
+                MempoolMessage::UserMessage(message),
+                MempoolSource::Gossip,
+                Some(tx),
+            ))
+            .await
+            .unwrap();
+        rx.await.unwrap()
+    }
+
+    async fn commit_mempool(setup: &mut TestSetup) {
+        let (message_tx, message_rx) = oneshot::channel();
+        setup
+            .messages_request_tx
+            .send(MempoolMessagesRequest {
+                shard_id: 1,
+                message_tx,
+                max_messages_per_block: 100,
+                block_limits: BlockLimits::default(),
+            })
+            .await
+            .unwrap();
+        let messages = message_rx.await.unwrap();
+        let state_change = setup.engine.propose_state_change(1, messages);
+        test_helper::validate_and_commit_state_change(&mut setup.engine, &state_change);
+    }
+
+    #[tokio::test]
+    async fn test_submitted_events_are_applied_idempotently() {
+        let mut setup = setup(true);
+        let events = rent_events(3);
+
+        let response = setup
+            .service
+            .submit_on_chain_events(submit_request(&events))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.num_submitted, 3);
+        assert_eq!(response.num_duplicates, 0);
+
+        // Still waiting in the mempool
+        let response = setup
+            .service
+            .submit_on_chain_events(submit_request(&events))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.num_submitted, 0);
+        assert_eq!(response.num_duplicates, 3);
+
+        commit_mempool(&mut setup).await;
+        let stored = setup
+            .engine
+            .get_onchain_events(OnChainEventType::EventTypeStorageRent, FID)
+            .unwrap();
+        assert_eq!(stored.len(), 3);
+
+        // Already merged, resubmitting the batch changes nothing
+        let response = setup
+            .service
+            .submit_on_chain_events(submit_request(&events))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.num_submitted, 0);
+        assert_eq!(response.num_duplicates, 3);
+        commit_mempool(&mut setup).await;
+        let stored = setup
+            .engine
+            .get_onchain_events(OnChainEventType::EventTypeStorageRent, FID)
+            .unwrap();
+        assert_eq!(stored.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_submitted_events_are_rejected() {
+        let disabled = setup(false);
+        let response = disabled
+            .service
+            .submit_on_chain_events(submit_request(&rent_events(1)))
+            .await;
+        assert_eq!(
+            response.unwrap_err().code(),
+            tonic::Code::FailedPrecondition
+        );
+
+        let setup = setup(true);
+        let mut events = rent_events(2);
+        events.reverse();
+        let response = setup
+            .service
+            .submit_on_chain_events(submit_request(&events))
+            .await;
+        assert_eq!(response.unwrap_err().code(), tonic::Code::InvalidArgument);
+
+        let mut request = submit_request(&rent_events(1));
+        request.metadata_mut().remove("authorization");
+        let response = setup.service.submit_on_chain_events(request).await;
+        assert_eq!(response.unwrap_err().code(), tonic::Code::Unauthenticated);
+    }
+
+    #[tokio::test]
+    async fn test_rebuild_index() {
+        let mut setup = setup(true);
+        test_helper::register_user(
+            FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut setup.engine,
+        )
+        .await;
+        let links: Vec<proto::Message> = (0..3)
+            .map(|i| messages_factory::links::create_link_add(FID, "follow", 100 + i, None, None))
+            .collect();
+        test_helper::commit_messages(&mut setup.engine, links.clone()).await;
+
+        // Drop the index entries, as if the index was added after the links were merged
+        let link_store = setup.engine.get_stores().link_store;
+        let mut txn = link_store.db().txn();
+        for link in &links {
+            let ts_hash = make_ts_hash(link.data.as_ref().unwrap().timestamp, &link.hash).unwrap();
+            link_store
+                .store_def()
+                .delete_secondary_indices(&mut txn, &ts_hash, link)
+                .unwrap();
+        }
+        link_store.db().commit(txn).unwrap();
+        let links_by_target = |target_fid| {
+            LinkStore::get_links_by_target(
+                &link_store,
+                &Target::TargetFid(target_fid),
+                "".to_string(),
+                &PageOptions::default(),
+            )
+            .unwrap()
+            .messages
+        };
+        assert!(links_by_target(100).is_empty());
+
+        let progress: Vec<RebuildIndexProgress> = setup
+            .service
+            .rebuild_index(authorized_request(RebuildIndexRequest {
+                shard_id: 1,
+                index_name: "links_by_target".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .map(|progress| progress.unwrap())
+            .collect()
+            .await;
+        let last = progress.last().unwrap();
+        assert!(last.done);
+        assert_eq!(last.messages_indexed, 3);
+        for (i, link) in links.iter().enumerate() {
+            assert_eq!(links_by_target(100 + i as u64), vec![link.clone()]);
+        }
+
+        // The hash index is rebuilt from the messages of every store
+        let stores = setup.engine.get_stores();
+        let mut txn = link_store.db().txn();
+        for link in &links {
+            txn.delete(make_message_by_hash_key(&link.hash));
+        }
+        link_store.db().commit(txn).unwrap();
+        assert_eq!(stores.get_message_by_hash(&links[0].hash).unwrap(), None);
+        let progress: Vec<RebuildIndexProgress> = setup
+            .service
+            .rebuild_index(authorized_request(RebuildIndexRequest {
+                shard_id: 1,
+                index_name: "messages_by_hash".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .map(|progress| progress.unwrap())
+            .collect()
+            .await;
+        assert!(progress.last().unwrap().done);
+        for link in &links {
+            assert_eq!(
+                stores.get_message_by_hash(&link.hash).unwrap(),
+                Some(link.clone())
+            );
+        }
+
+        let response = setup
+            .service
+            .rebuild_index(authorized_request(RebuildIndexRequest {
+                shard_id: 1,
+                index_name: "links_by_nothing".to_string(),
+            }))
+            .await;
+        assert_eq!(response.unwrap_err().code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_set_message_type_admission() {
+        let setup = setup(false);
+        let set_admission = |message_type: MessageType, enabled| {
+            setup.service.set_message_type_admission(authorized_request(
+                SetMessageTypeAdmissionRequest {
+                    message_type: message_type as i32,
+                    enabled,
+                },
+            ))
+        };
+
+        let response = set_admission(MessageType::LinkAdd, false)
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(
+            response.disabled_message_types,
+            vec![MessageType::LinkAdd as i32]
+        );
+
+        // Gossiped messages of the type are rejected too
+        let link = messages_factory::links::create_link_add(FID, "follow", 100, None, None);
+        let err = add_to_mempool(&setup, link.clone()).await.unwrap_err();
+        assert_eq!(err.code, "unavailable");
+        let cast = messages_factory::casts::create_cast_add(FID, "test", None, None);
+        assert!(add_to_mempool(&setup, cast).await.is_ok());
+
+        let response = set_admission(MessageType::LinkAdd, true)
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(response.disabled_message_types.is_empty());
+        assert!(add_to_mempool(&setup, link).await.is_ok());
+
+        let response = set_admission(MessageType::None, false).await;
+        assert_eq!(response.unwrap_err().code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_compact_trie() {
+        let mut setup = setup(true);
+        let cast = messages_factory::casts::create_cast_add(FID, "test", None, None);
+        test_helper::register_user(
+            FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut setup.engine,
+        )
+        .await;
+        test_helper::commit_message(&mut setup.engine, &cast).await;
+
+        let response = setup
+            .service
+            .compact_trie(Request::new(CompactTrieRequest { shard_id: 1 }))
+            .await;
+        assert_eq!(response.unwrap_err().code(), tonic::Code::Unauthenticated);
+
+        let response = setup
+            .service
+            .compact_trie(authorized_request(CompactTrieRequest { shard_id: 2 }))
+            .await;
+        assert_eq!(response.unwrap_err().code(), tonic::Code::InvalidArgument);
+
+        let response = setup
+            .service
+            .compact_trie(authorized_request(CompactTrieRequest { shard_id: 1 }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(response.size_before > 0);
+        assert_eq!(
+            response.bytes_reclaimed,
+            response.size_before.saturating_sub(response.size_after)
+        );
+        // The trie is intact
+        assert!(test_helper::key_exists_in_trie(
+            &mut setup.engine,
+            &TrieKey::for_message(&cast)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_recompute_trie_root() {
+        let mut setup = setup(true);
+        test_helper::register_user(
+            FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut setup.engine,
+        )
+        .await;
+        let casts: Vec<proto::Message> = ["one", "two"]
+            .iter()
+            .map(|text| messages_factory::casts::create_cast_add(FID, text, None, None))
+            .collect();
+        test_helper::commit_messages(&mut setup.engine, casts.clone()).await;
+        let recompute = |shard_id| {
+            setup
+                .service
+                .recompute_trie_root(authorized_request(RecomputeTrieRootRequest { shard_id }))
+        };
+
+        let response = setup
+            .service
+            .recompute_trie_root(Request::new(RecomputeTrieRootRequest { shard_id: 1 }))
+            .await;
+        assert_eq!(response.unwrap_err().code(), tonic::Code::Unauthenticated);
+        assert_eq!(
+            recompute(2).await.unwrap_err().code(),
+            tonic::Code::InvalidArgument
+        );
+
+        let response = recompute(1).await.unwrap().into_inner();
+        assert!(response.matches);
+        assert_eq!(response.stored_root, setup.engine.trie_root_hash());
+        assert_eq!(response.recomputed_root, response.stored_root);
+
+        // Drop a cast from the store and leave it in the trie
+        let stores = setup.engine.get_stores();
+        let cast_key = stores
+            .db
+            .get(&make_message_by_hash_key(&casts[0].hash))
+            .unwrap()
+            .unwrap();
+        stores.db.del(&cast_key).unwrap();
+
+        let response = recompute(1).await.unwrap().into_inner();
+        assert!(!response.matches);
+        assert_eq!(response.stored_root, setup.engine.trie_root_hash());
+        assert_ne!(response.recomputed_root, response.stored_root);
+        // Still in the shard's trie
+        assert!(test_helper::key_exists_in_trie(
+            &mut setup.engine,
+            &TrieKey::for_message(&casts[0])
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_list_and_cancel_jobs() {
+        let setup = setup(false);
+        let pruning = setup
+            .service
+            .job_registry
+            .start("event_pruning", None, true);
+        pruning.set_shard(1);
+        let compaction = setup
+            .service
+            .job_registry
+            .start("compact_trie", Some(1), false);
+
+        let response = setup.service.list_jobs(Request::new(Empty {})).await;
+        assert_eq!(response.unwrap_err().code(), tonic::Code::Unauthenticated);
+        let list_jobs = || async {
+            setup
+                .service
+                .list_jobs(authorized_request(Empty {}))
+                .await
+                .unwrap()
+                .into_inner()
+                .jobs
+        };
+        let jobs = list_jobs().await;
+        assert_eq!(
+            jobs.iter()
+                .map(|job| (job.job_id, job.job_type.as_str(), job.shard_id))
+                .collect::<Vec<_>>(),
+            vec![
+                (pruning.id(), "event_pruning", Some(1)),
+                (compaction.id(), "compact_trie", Some(1)),
+            ]
+        );
+        assert!(jobs[0].cancellable && !jobs[1].cancellable);
+
+        let cancel_job = |job_id| {
+            setup
+                .service
+                .cancel_job(authorized_request(CancelJobRequest { job_id }))
+        };
+        let response = setup
+            .service
+            .cancel_job(Request::new(CancelJobRequest {
+                job_id: pruning.id(),
+            }))
+            .await;
+        assert_eq!(response.unwrap_err().code(), tonic::Code::Unauthenticated);
+        let response = cancel_job(compaction.id()).await;
+        assert_eq!(
+            response.unwrap_err().code(),
+            tonic::Code::FailedPrecondition
+        );
+        let response = cancel_job(1000).await;
+        assert_eq!(response.unwrap_err().code(), tonic::Code::NotFound);
+
+        cancel_job(pruning.id()).await.unwrap();
+        assert!(pruning.is_cancelled());
+        assert!(list_jobs().await[0].cancel_requested);
+
+        drop(pruning);
+        drop(compaction);
+        assert!(list_jobs().await.is_empty());
+    }
+
+    fn dial_request(multiaddr: &str) -> Request<DialPeerRequest> {
+        authorized_request(DialPeerRequest {
+            multiaddr: multiaddr.to_string(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_dial_peer() {
+        let TestSetup { service, _dirs, .. } = setup(false);
+        let address = "/ip4/127.0.0.1/udp/3382/quic-v1";
+
+        let response = service
+            .dial_peer(Request::new(DialPeerRequest {
+                multiaddr: address.to_string(),
+            }))
+            .await;
+        assert_eq!(response.unwrap_err().code(), tonic::Code::Unauthenticated);
+        let response = service.dial_peer(dial_request(address)).await;
+        assert_eq!(response.unwrap_err().code(), tonic::Code::Unavailable);
+
+        // Stands in for the swarm, only the local address connects
+        let (gossip_tx, mut gossip_rx) = mpsc::channel(10);
+        let service = service.with_gossip_tx(gossip_tx);
+        let peer_id = libp2p::PeerId::random();
+        tokio::spawn(async move {
+            while let Some(event) = gossip_rx.recv().await {
+                if let GossipEvent::DialPeer(address, reply_tx) = event {
+                    let result = if address.to_string().contains("127.0.0.1") {
+                        Ok(peer_id)
+                    } else {
+                        Err(DialPeerError::Failed("connection refused".to_string()))
+                    };
+                    let _ = reply_tx.send(result);
+                }
+            }
+        });
+
+        let response = service.dial_peer(dial_request("not a multiaddr")).await;
+        assert_eq!(response.unwrap_err().code(), tonic::Code::InvalidArgument);
+
+        let response = service
+            .dial_peer(dial_request(address))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(response.connected);
+        assert_eq!(response.peer_id, peer_id.to_string());
+
+        let response = service
+            .dial_peer(dial_request("/ip4/10.0.0.1/udp/3382/quic-v1"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!response.connected);
+        assert_eq!(response.error, "connection refused");
+    }
+
+    #[tokio::test]
+    async fn test_get_peers() {
+        let TestSetup { service, _dirs, .. } = setup(false);
+        let response = service.get_peers(authorized_request(Empty {})).await;
+        assert_eq!(response.unwrap_err().code(), tonic::Code::Unavailable);
+
+        // Stands in for the swarm, with a peer banned after 3 violations
+        let (gossip_tx, mut gossip_rx) = mpsc::channel(10);
+        let service = service.with_gossip_tx(gossip_tx);
+        let connected = libp2p::PeerId::random();
+        let mut peer_bans =
+            PeerBans::new(3, Duration::from_secs(60), Duration::from_secs(3600), None);
+        let banned = libp2p::PeerId::random();
+        for now in 1..=3 {
+            peer_bans.record_violation(banned, now);
+        }
+        tokio::spawn(async move {
+            while let Some(event) = gossip_rx.recv().await {
+                if let GossipEvent::GetPeers(reply_tx) = event {
+                    let _ = reply_tx.send((vec![connected], peer_bans.bans(3)));
+                }
+            }
+        });
+
+        let response = service.get_peers(Request::new(Empty {})).await;
+        assert_eq!(response.unwrap_err().code(), tonic::Code::Unauthenticated);
+        let response = service
+            .get_peers(authorized_request(Empty {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.connected_peer_ids, vec![connected.to_string()]);
+        assert_eq!(
+            response.bans,
+            vec![proto::PeerBan {
+                peer_id: banned.to_string(),
+                banned_until: 3 + 3_600_000,
+                violations: 3,
+            }]
+        );
+    }
+
+    fn receive_metrics(socket: &std::net::UdpSocket) -> Option<String> {
+        let mut buf = [0; 1024];
+        let len = socket.recv(&mut buf).ok()?;
+        Some(String::from_utf8_lossy(&buf[..len]).to_string())
+    }
+
+    #[tokio::test]
+    async fn test_set_metrics_sink() {
+        let setup = setup(false);
+        // Made before the switch, and repointed by it all the same
+        let statsd_client = setup.service.statsd_client.clone();
+        let collector = || {
+            let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+            socket
+                .set_read_timeout(Some(Duration::from_millis(200)))
+                .unwrap();
+            socket
+        };
+        let (old_collector, new_collector) = (collector(), collector());
+        let set_sink = |address: String, prefix: &str, sample_rate| {
+            setup
+                .service
+                .set_metrics_sink(authorized_request(SetMetricsSinkRequest {
+                    address,
+                    prefix: prefix.to_string(),
+                    sample_rate,
+                }))
+        };
+
+        let old_address = old_collector.local_addr().unwrap().to_string();
+        set_sink(old_address.clone(), "old", None).await.unwrap();
+        statsd_client.count("test.metric", 1);
+        assert_eq!(
+            receive_metrics(&old_collector).unwrap(),
+            "old.test.metric:1|c"
+        );
+
+        let new_address = new_collector.local_addr().unwrap().to_string();
+        set_sink(new_address.clone(), "new", Some(1.0))
+            .await
+            .unwrap();
+        statsd_client.count("test.metric", 2);
+        statsd_client.gauge("test.gauge", 3);
+        assert_eq!(
+            receive_metrics(&new_collector).unwrap(),
+            "new.test.metric:2|c"
+        );
+        assert_eq!(
+            receive_metrics(&new_collector).unwrap(),
+            "new.test.gauge:3|g"
+        );
+        assert_eq!(receive_metrics(&old_collector), None);
+
+        // Bad destinations leave the sink as it was
+        for (address, prefix, sample_rate) in [
+            ("localhost".to_string(), "new", None),
+            (old_address.clone(), "", None),
+            (old_address.clone(), "new", Some(0.0)),
+            (old_address, "new", Some(1.5)),
+        ] {
+            let status = set_sink(address, prefix, sample_rate).await.unwrap_err();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        }
+        statsd_client.count("test.metric", 4);
+        assert_eq!(
+            receive_metrics(&new_collector).unwrap(),
+            "new.test.metric:4|c"
+        );
+        assert_eq!(receive_metrics(&old_collector), None);
+    }
+}
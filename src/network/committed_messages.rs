@@ -0,0 +1,132 @@
+use crate::mempool::routing::MessageRouter;
+use crate::proto::{self, gossip_message, mempool_message};
+use crate::storage::db::RocksDbTransactionBatch;
+use crate::storage::store::account::{
+    get_message_by_key, make_message_primary_key, make_ts_hash, message_bytes_decode,
+    type_to_set_postfix,
+};
+use crate::storage::store::stores::Stores;
+use prost::Message;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Looks up the user messages received over gossip in the node's stores, so messages that were
+/// already committed in a block stop being forwarded. A message is only taken as committed when
+/// the stored one is identical, a message that merely shares its key or hash with a committed one
+/// goes through like any other. Messages on shards the node doesn't have, and the ones the stores
+/// don't key by hash like link compact states, are never taken as committed. The lookups read
+/// the stores, so they're meant for blocking threads.
+#[derive(Clone)]
+pub struct CommittedMessages {
+    shard_stores: Arc<HashMap<u32, Stores>>,
+    num_shards: u32,
+    message_router: Arc<dyn MessageRouter>,
+}
+
+impl CommittedMessages {
+    pub fn new(
+        shard_stores: HashMap<u32, Stores>,
+        num_shards: u32,
+        message_router: Arc<dyn MessageRouter>,
+    ) -> Self {
+        CommittedMessages {
+            shard_stores: Arc::new(shard_stores),
+            num_shards,
+            message_router,
+        }
+    }
+
+    pub fn is_committed(&self, message: &proto::Message) -> bool {
+        let mut message = message.clone();
+        message_bytes_decode(&mut message);
+        let Some(data) = &message.data else {
+            return false;
+        };
+        let shard_id = self.message_router.route_fid(data.fid, self.num_shards);
+        let Some(stores) = self.shard_stores.get(&shard_id) else {
+            return false;
+        };
+        let Ok(set_postfix) = type_to_set_postfix(data.r#type()) else {
+            return false;
+        };
+        let Ok(ts_hash) = make_ts_hash(data.timestamp, &message.hash) else {
+            return false;
+        };
+        let primary_key = make_message_primary_key(data.fid, set_postfix as u8, Some(&ts_hash));
+        match get_message_by_key(
+            &stores.db,
+            &mut RocksDbTransactionBatch::new(),
+            &primary_key,
+        ) {
+            Ok(Some(mut stored)) => {
+                message_bytes_decode(&mut stored);
+                stored.hash == message.hash
+                    && stored.signature == message.signature
+                    && stored.signer == message.signer
+                    && stored.data == message.data
+            }
+            Err(_) | Ok(None) => false,
+        }
+    }
+
+    /// Whether the gossip message carries a user message that's already committed
+    pub fn is_committed_gossip_message(&self, data: &[u8]) -> bool {
+        let Ok(message) = proto::GossipMessage::decode(data) else {
+            return false;
+        };
+        match message.gossip_message {
+            Some(gossip_message::GossipMessage::MempoolMessage(proto::MempoolMessage {
+                mempool_message: Some(mempool_message::MempoolMessage::UserMessage(message)),
+            })) => self.is_committed(&message),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mempool::routing::{EvenOddRouterForTest, ShardRouter};
+    use crate::storage::store::test_helper::{self, FID_FOR_TEST};
+    use crate::utils::factory::messages_factory;
+
+    #[tokio::test]
+    async fn test_only_identical_messages_are_committed() {
+        let (mut engine, _dir) = test_helper::new_engine();
+        test_helper::register_user(
+            FID_FOR_TEST,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine,
+        )
+        .await;
+        let committed = messages_factory::casts::create_cast_add(FID_FOR_TEST, "one", None, None);
+        test_helper::commit_message(&mut engine, &committed).await;
+        let pending = messages_factory::casts::create_cast_add(FID_FOR_TEST, "two", None, None);
+
+        let shard_stores = HashMap::from([(engine.shard_id(), engine.get_stores())]);
+        let committed_messages =
+            CommittedMessages::new(shard_stores.clone(), 1, Arc::new(ShardRouter {}));
+        assert!(committed_messages.is_committed(&committed));
+        assert!(!committed_messages.is_committed(&pending));
+
+        // Same key and hash, different contents
+        let mut forged = committed.clone();
+        if let Some(proto::message_data::Body::CastAddBody(body)) =
+            &mut forged.data.as_mut().unwrap().body
+        {
+            body.text = "forged".to_string();
+        }
+        forged.data_bytes = None;
+        assert!(!committed_messages.is_committed(&forged));
+
+        // Only the shards the node has are looked up
+        let without_shard = CommittedMessages::new(HashMap::new(), 1, Arc::new(ShardRouter {}));
+        assert!(!without_shard.is_committed(&committed));
+
+        // Routed with the node's router, this one puts the fid on the shard the node doesn't have
+        let other_router =
+            CommittedMessages::new(shard_stores, 2, Arc::new(EvenOddRouterForTest {}));
+        assert!(!other_router.is_committed(&committed));
+    }
+}
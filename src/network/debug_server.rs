@@ -0,0 +1,329 @@
+use crate::core::error::HubError;
+use crate::mempool::mempool::{MempoolRequest, MempoolSource, MempoolSubscription};
+use crate::mempool::routing::MessageRouter;
+use crate::network::rpc_extensions::authenticate_request;
+use crate::proto::debug_service_server::DebugService;
+use crate::proto::{
+    self, DebugSubmitMessageRequest, FarcasterNetwork, MempoolEvent, RawKeyValue,
+    ScanRawKeysRequest, ScanRawKeysResponse, StreamMempoolRequest,
+};
+use crate::storage::db::{PageOptions, RocksDB};
+use crate::storage::store::account::message_bytes_decode;
+use crate::storage::store::engine::MempoolMessage;
+use crate::storage::store::stores::Stores;
+use crate::storage::store::BlockStore;
+use crate::storage::util::increment_vec_u8;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::time::timeout;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+use tracing::{info, warn};
+
+const MEMPOOL_ADD_REQUEST_TIMEOUT: Duration = Duration::from_millis(500);
+const MAX_SCAN_LIMIT: u32 = 1000;
+// Events queued for a mempool stream's client, it falls behind the mempool once they're used up
+const MEMPOOL_STREAM_BUFFER: usize = 100;
+// The dbs are opened without column families, everything is in the default one
+const DEFAULT_COLUMN_FAMILY: &str = "default";
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Config {
+    // Off unless asked for, and only ever served on devnet and testnet with rpc_auth configured
+    pub enabled: bool,
+}
+
+/// Runs the checks a submitted message has to pass, against the stores of the given shard.
+/// Implemented by the hub service, so debug submissions are held to the same rules.
+#[tonic::async_trait]
+pub trait SubmitValidator: Send + Sync {
+    async fn validate_message_on_shard(
+        &self,
+        shard_id: u32,
+        message: &proto::Message,
+    ) -> Result<(), HubError>;
+}
+
+/// Testing affordances for devnet and testnet. Nothing here is ever served on mainnet.
+pub struct MyDebugService {
+    config: Config,
+    allowed_users: HashMap<String, String>,
+    mempool_tx: mpsc::Sender<MempoolRequest>,
+    message_router: Box<dyn MessageRouter>,
+    validator: Arc<dyn SubmitValidator>,
+    num_shards: u32,
+    fc_network: FarcasterNetwork,
+    // By shard, 0 is the block db
+    dbs: HashMap<u32, Arc<RocksDB>>,
+}
+
+impl MyDebugService {
+    pub fn new(
+        config: Config,
+        rpc_auth: String,
+        mempool_tx: mpsc::Sender<MempoolRequest>,
+        message_router: Box<dyn MessageRouter>,
+        validator: Arc<dyn SubmitValidator>,
+        num_shards: u32,
+        fc_network: FarcasterNetwork,
+    ) -> Self {
+        let mut allowed_users = HashMap::new();
+        for auth in rpc_auth.split(",") {
+            let parts: Vec<&str> = auth.split(":").collect();
+            if parts.len() == 2 {
+                allowed_users.insert(parts[0].to_string(), parts[1].to_string());
+            }
+        }
+
+        Self {
+            config,
+            allowed_users,
+            mempool_tx,
+            message_router,
+            validator,
+            num_shards,
+            fc_network,
+            dbs: HashMap::new(),
+        }
+    }
+
+    pub fn with_stores(
+        mut self,
+        block_store: BlockStore,
+        shard_stores: &HashMap<u32, Stores>,
+    ) -> Self {
+        self.dbs = std::iter::once((0, block_store.db))
+            .chain(
+                shard_stores
+                    .iter()
+                    .map(|(shard_id, stores)| (*shard_id, stores.db.clone())),
+            )
+            .collect();
+        self
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.check_enabled().is_ok()
+    }
+
+    fn check_enabled(&self) -> Result<(), Status> {
+        // The service isn't mounted in any of these cases either, this guards against
+        // misconfiguration
+        if !self.config.enabled {
+            return Err(Status::permission_denied("debug service is not enabled"));
+        }
+        if self.fc_network == FarcasterNetwork::Mainnet {
+            return Err(Status::permission_denied(
+                "debug service is not available on mainnet",
+            ));
+        }
+        // Its rpcs bypass routing and expose whatever is in the dbs
+        if self.allowed_users.is_empty() {
+            return Err(Status::permission_denied(
+                "debug service requires rpc_auth to be configured",
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn hub_error_to_status(err: HubError) -> Status {
+    if err.code.starts_with("bad_request") {
+        Status::invalid_argument(err.to_string())
+    } else {
+        Status::unavailable(err.to_string())
+    }
+}
+
+#[tonic::async_trait]
+impl DebugService for MyDebugService {
+    async fn submit_message(
+        &self,
+        request: Request<DebugSubmitMessageRequest>,
+    ) -> Result<Response<proto::Message>, Status> {
+        self.check_enabled()?;
+        authenticate_request(&request, &self.allowed_users)?;
+
+        let request = request.into_inner();
+        let mut message = match request.message {
+            Some(message) => message,
+            None => return Err(Status::invalid_argument("missing message")),
+        };
+        message_bytes_decode(&mut message);
+        let fid = message.fid();
+        if fid == 0 {
+            return Err(Status::invalid_argument("fid cannot be 0"));
+        }
+
+        let routed_shard_id = self.message_router.route_fid(fid, self.num_shards);
+        if let Some(shard_id) = request.shard_hint {
+            if shard_id == 0 || shard_id > self.num_shards {
+                return Err(Status::invalid_argument(format!(
+                    "invalid shard hint {}",
+                    shard_id
+                )));
+            }
+        }
+        // Validated against the shard it's going to, so a hinted message is checked against that
+        // shard's state
+        self.validator
+            .validate_message_on_shard(request.shard_hint.unwrap_or(routed_shard_id), &message)
+            .await
+            .map_err(hub_error_to_status)?;
+
+        let (tx, rx) = oneshot::channel();
+        let mempool_request = match request.shard_hint {
+            Some(shard_id) => {
+                info!(
+                    fid,
+                    shard_id, routed_shard_id, "Submitting message with shard hint"
+                );
+                MempoolRequest::AddMessageToShard(
+                    shard_id,
+                    MempoolMessage::UserMessage(message.clone()),
+                    Some(tx),
+                )
+            }
+            None => MempoolRequest::AddMessage(
+                MempoolMessage::UserMessage(message.clone()),
+                MempoolSource::RPC(None),
+                Some(tx),
+            ),
+        };
+
+        self.mempool_tx
+            .try_send(mempool_request)
+            .map_err(|_| Status::unavailable("mempool channel is full"))?;
+
+        match timeout(MEMPOOL_ADD_REQUEST_TIMEOUT, rx).await {
+            Ok(Ok(Ok(()))) => Ok(Response::new(message)),
+            Ok(Ok(Err(err))) => Err(hub_error_to_status(err)),
+            Ok(Err(_)) | Err(_) => Err(Status::unavailable("Error adding to mempool")),
+        }
+    }
+
+    async fn scan_raw_keys(
+        &self,
+        request: Request<ScanRawKeysRequest>,
+    ) -> Result<Response<ScanRawKeysResponse>, Status> {
+        self.check_enabled()?;
+        authenticate_request(&request, &self.allowed_users)?;
+
+        let request = request.into_inner();
+        if !request.column_family.is_empty() && request.column_family != DEFAULT_COLUMN_FAMILY {
+            return Err(Status::invalid_argument(format!(
+                "unknown column family {}",
+                request.column_family
+            )));
+        }
+        let db = self.dbs.get(&request.shard_id).ok_or_else(|| {
+            Status::invalid_argument(format!("no db for shard {}", request.shard_id))
+        })?;
+        let limit = match request.limit {
+            0 => MAX_SCAN_LIMIT,
+            limit => limit.min(MAX_SCAN_LIMIT),
+        } as usize;
+        info!(
+            shard_id = request.shard_id,
+            prefix = hex::encode(&request.prefix),
+            limit,
+            "Scanning raw keys"
+        );
+
+        let (start_prefix, stop_prefix) = if request.prefix.is_empty() {
+            (None, None)
+        } else {
+            (
+                Some(request.prefix.clone()),
+                Some(increment_vec_u8(&request.prefix)),
+            )
+        };
+        let mut entries = vec![];
+        db.for_each_iterator_by_prefix(
+            start_prefix,
+            stop_prefix,
+            &PageOptions::default(),
+            |key, value| {
+                entries.push(RawKeyValue {
+                    key: hex::encode(key),
+                    value: hex::encode(value),
+                });
+                Ok(entries.len() >= limit)
+            },
+        )
+        .map_err(hub_error_to_status)?;
+
+        Ok(Response::new(ScanRawKeysResponse { entries }))
+    }
+
+    type StreamMempoolStream = ReceiverStream<Result<MempoolEvent, Status>>;
+    async fn stream_mempool(
+        &self,
+        request: Request<StreamMempoolRequest>,
+    ) -> Result<Response<Self::StreamMempoolStream>, Status> {
+        self.check_enabled()?;
+        authenticate_request(&request, &self.allowed_users)?;
+        let fid = request.into_inner().fid;
+
+        let (tx, rx) = oneshot::channel();
+        self.mempool_tx
+            .try_send(MempoolRequest::Subscribe(tx))
+            .map_err(|_| Status::unavailable("mempool channel is full"))?;
+        let MempoolSubscription {
+            pending,
+            mut events_rx,
+        } = match timeout(MEMPOOL_ADD_REQUEST_TIMEOUT, rx).await {
+            Ok(Ok(Ok(subscription))) => subscription,
+            Ok(Ok(Err(err))) => return Err(hub_error_to_status(err)),
+            Ok(Err(_)) | Err(_) => return Err(Status::unavailable("Error subscribing to mempool")),
+        };
+        info!(fid, pending = pending.len(), "Streaming the mempool");
+
+        let matches = move |event: &MempoolEvent| {
+            fid.map_or(true, |fid| {
+                event
+                    .message
+                    .as_ref()
+                    .is_some_and(|message| message.fid() == fid)
+            })
+        };
+        let (server_tx, client_rx) = mpsc::channel(MEMPOOL_STREAM_BUFFER);
+        tokio::spawn(async move {
+            for event in pending.into_iter().filter(|event| matches(event)) {
+                if server_tx.send(Ok(event)).await.is_err() {
+                    return;
+                }
+            }
+            loop {
+                let event = tokio::select! {
+                    _ = server_tx.closed() => return,
+                    event = events_rx.recv() => event,
+                };
+                match event {
+                    Ok(event) => {
+                        if matches(&event) && server_tx.send(Ok(event)).await.is_err() {
+                            return;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        // Carrying on would leave the client with a view that's silently wrong
+                        warn!(missed, "Dropping a mempool stream that fell behind");
+                        let _ = server_tx
+                            .send(Err(Status::resource_exhausted(format!(
+                                "fell behind the mempool by {} events",
+                                missed
+                            ))))
+                            .await;
+                        return;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(client_rx)))
+    }
+}
@@ -0,0 +1,360 @@
+#[cfg(test)]
+mod tests {
+    use base64::Engine;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use tokio::sync::{broadcast, mpsc};
+    use tokio_stream::StreamExt;
+    use tonic::Request;
+
+    use crate::core::error::HubError;
+    use crate::mempool::mempool::{MempoolRequest, MempoolSubscription};
+    use crate::mempool::routing::{MessageRouter, ShardRouter};
+    use crate::network::debug_server::{Config, MyDebugService, SubmitValidator};
+    use crate::proto::debug_service_server::DebugService;
+    use crate::proto::message_data::Body;
+    use crate::proto::{
+        self, DebugSubmitMessageRequest, FarcasterNetwork, MempoolEvent, MempoolEventType,
+        MempoolRemovalReason, ScanRawKeysRequest, StreamMempoolRequest,
+    };
+    use crate::storage::db::RocksDB;
+    use crate::storage::store::engine::MempoolMessage;
+    use crate::storage::store::BlockStore;
+    use crate::utils::factory::messages_factory;
+
+    const NUM_SHARDS: u32 = 2;
+
+    // Rejects messages for fids in [rejected_fids], and accepts everything else
+    struct TestValidator {
+        rejected_fids: Vec<u64>,
+    }
+
+    #[tonic::async_trait]
+    impl SubmitValidator for TestValidator {
+        async fn validate_message_on_shard(
+            &self,
+            _shard_id: u32,
+            message: &proto::Message,
+        ) -> Result<(), HubError> {
+            if self.rejected_fids.contains(&message.fid()) {
+                return Err(HubError::validation_failure("invalid message"));
+            }
+            Ok(())
+        }
+    }
+
+    fn enabled_config() -> Config {
+        Config { enabled: true }
+    }
+
+    fn new_service(
+        config: Config,
+        rpc_auth: &str,
+        fc_network: FarcasterNetwork,
+        rejected_fids: Vec<u64>,
+    ) -> (MyDebugService, mpsc::Receiver<MempoolRequest>) {
+        let (mempool_tx, mempool_rx) = mpsc::channel(10);
+        let service = MyDebugService::new(
+            config,
+            rpc_auth.to_string(),
+            mempool_tx,
+            Box::new(ShardRouter {}),
+            Arc::new(TestValidator { rejected_fids }),
+            NUM_SHARDS,
+            fc_network,
+        );
+        (service, mempool_rx)
+    }
+
+    fn make_service(
+        fc_network: FarcasterNetwork,
+    ) -> (MyDebugService, mpsc::Receiver<MempoolRequest>) {
+        new_service(enabled_config(), "user:pass", fc_network, vec![])
+    }
+
+    fn authenticated<T>(message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        let auth = format!(
+            "Basic {}",
+            base64::engine::general_purpose::STANDARD.encode("user:pass")
+        );
+        request
+            .metadata_mut()
+            .insert("authorization", auth.parse().unwrap());
+        request
+    }
+
+    fn submit_request(
+        message: proto::Message,
+        shard_hint: Option<u32>,
+    ) -> Request<DebugSubmitMessageRequest> {
+        authenticated(DebugSubmitMessageRequest {
+            message: Some(message),
+            shard_hint,
+        })
+    }
+
+    // Serves a block db holding a few keys under two prefixes
+    fn make_scan_service(rpc_auth: &str) -> (MyDebugService, tempfile::TempDir) {
+        let dir = tempfile::TempDir::new().unwrap();
+        let db = RocksDB::new(dir.path().join("block.db").to_str().unwrap());
+        db.open().unwrap();
+        for i in 0..5u8 {
+            db.put(&[1, i], &[i]).unwrap();
+            db.put(&[2, i], &[i]).unwrap();
+        }
+        let (service, _) =
+            new_service(enabled_config(), rpc_auth, FarcasterNetwork::Devnet, vec![]);
+        let service = service.with_stores(BlockStore::new(Arc::new(db)), &HashMap::new());
+        (service, dir)
+    }
+
+    fn scan_request(prefix: Vec<u8>, limit: u32) -> Request<ScanRawKeysRequest> {
+        authenticated(ScanRawKeysRequest {
+            shard_id: 0,
+            column_family: "".to_string(),
+            prefix,
+            limit,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_shard_hint_overrides_routing() {
+        let (service, mut mempool_rx) = make_service(FarcasterNetwork::Devnet);
+        let fid = 1234;
+        let routed_shard = ShardRouter {}.route_fid(fid, NUM_SHARDS);
+        let hinted_shard = if routed_shard == 1 { 2 } else { 1 };
+        let message = messages_factory::casts::create_cast_add(fid, "test", None, None);
+
+        let mempool = tokio::spawn(async move {
+            match mempool_rx.recv().await.unwrap() {
+                MempoolRequest::AddMessageToShard(shard_id, message, reply_to) => {
+                    reply_to.unwrap().send(Ok(())).unwrap();
+                    (shard_id, message)
+                }
+                _ => panic!("Expected message to be added to a specific shard"),
+            }
+        });
+
+        let response = service
+            .submit_message(submit_request(message.clone(), Some(hinted_shard)))
+            .await
+            .unwrap();
+        assert_eq!(response.into_inner().hash, message.hash);
+
+        let (shard_id, submitted) = mempool.await.unwrap();
+        assert_eq!(shard_id, hinted_shard);
+        match submitted {
+            MempoolMessage::UserMessage(submitted) => assert_eq!(submitted.hash, message.hash),
+            _ => panic!("Expected user message"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shard_hint_validation() {
+        let (service, _mempool_rx) = make_service(FarcasterNetwork::Devnet);
+        let message = messages_factory::casts::create_cast_add(1234, "test", None, None);
+
+        for shard_hint in [0, NUM_SHARDS + 1] {
+            let response = service
+                .submit_message(submit_request(message.clone(), Some(shard_hint)))
+                .await
+                .unwrap_err();
+            assert_eq!(response.code(), tonic::Code::InvalidArgument);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_invalid_messages_are_not_enqueued() {
+        let (service, mut mempool_rx) = new_service(
+            enabled_config(),
+            "user:pass",
+            FarcasterNetwork::Devnet,
+            vec![1234],
+        );
+        let message = messages_factory::casts::create_cast_add(1234, "test", None, None);
+
+        for shard_hint in [None, Some(1)] {
+            let response = service
+                .submit_message(submit_request(message.clone(), shard_hint))
+                .await
+                .unwrap_err();
+            assert_eq!(response.code(), tonic::Code::InvalidArgument);
+        }
+        assert!(mempool_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_disabled_by_default() {
+        let (service, mut mempool_rx) = new_service(
+            Config::default(),
+            "user:pass",
+            FarcasterNetwork::Devnet,
+            vec![],
+        );
+        assert!(!service.enabled());
+
+        let message = messages_factory::casts::create_cast_add(1234, "test", None, None);
+        let response = service
+            .submit_message(submit_request(message, Some(1)))
+            .await
+            .unwrap_err();
+        assert_eq!(response.code(), tonic::Code::PermissionDenied);
+        assert!(mempool_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_requires_rpc_auth() {
+        let (service, mut mempool_rx) =
+            new_service(enabled_config(), "", FarcasterNetwork::Devnet, vec![]);
+        assert!(!service.enabled());
+
+        let message = messages_factory::casts::create_cast_add(1234, "test", None, None);
+        let response = service
+            .submit_message(submit_request(message.clone(), Some(1)))
+            .await
+            .unwrap_err();
+        assert_eq!(response.code(), tonic::Code::PermissionDenied);
+        let response = service
+            .stream_mempool(authenticated(StreamMempoolRequest { fid: None }))
+            .await
+            .unwrap_err();
+        assert_eq!(response.code(), tonic::Code::PermissionDenied);
+        assert!(mempool_rx.try_recv().is_err());
+
+        // Configured, but the request doesn't authenticate
+        let (service, _mempool_rx) = make_service(FarcasterNetwork::Devnet);
+        let response = service
+            .submit_message(Request::new(DebugSubmitMessageRequest {
+                message: Some(message),
+                shard_hint: Some(1),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(response.code(), tonic::Code::Unauthenticated);
+    }
+
+    #[tokio::test]
+    async fn test_scan_raw_keys() {
+        let (service, _dir) = make_scan_service("user:pass");
+
+        let entries = service
+            .scan_raw_keys(scan_request(vec![2], 0))
+            .await
+            .unwrap()
+            .into_inner()
+            .entries;
+        assert_eq!(entries.len(), 5);
+        assert_eq!(entries[0].key, "0200");
+        assert_eq!(entries[4].key, "0204");
+        assert_eq!(entries[4].value, "04");
+
+        let entries = service
+            .scan_raw_keys(scan_request(vec![], 3))
+            .await
+            .unwrap()
+            .into_inner()
+            .entries;
+        assert_eq!(
+            entries.iter().map(|e| e.key.as_str()).collect::<Vec<_>>(),
+            vec!["0100", "0101", "0102"]
+        );
+
+        let mut request = scan_request(vec![1], 0);
+        request.get_mut().column_family = "other".to_string();
+        let response = service.scan_raw_keys(request).await.unwrap_err();
+        assert_eq!(response.code(), tonic::Code::InvalidArgument);
+
+        let mut request = scan_request(vec![1], 0);
+        request.get_mut().shard_id = 1;
+        let response = service.scan_raw_keys(request).await.unwrap_err();
+        assert_eq!(response.code(), tonic::Code::InvalidArgument);
+
+        let mut request = scan_request(vec![1], 0);
+        request.metadata_mut().remove("authorization");
+        let response = service.scan_raw_keys(request).await.unwrap_err();
+        assert_eq!(response.code(), tonic::Code::Unauthenticated);
+    }
+
+    #[tokio::test]
+    async fn test_scan_raw_keys_requires_rpc_auth() {
+        let (service, _dir) = make_scan_service("");
+        let response = service
+            .scan_raw_keys(scan_request(vec![1], 0))
+            .await
+            .unwrap_err();
+        assert_eq!(response.code(), tonic::Code::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn test_stream_mempool_filters_by_fid() {
+        let (service, mut mempool_rx) = make_service(FarcasterNetwork::Devnet);
+        let event = |event_type: MempoolEventType, fid: u64, text: &str| MempoolEvent {
+            r#type: event_type as i32,
+            shard_id: 1,
+            message: Some(messages_factory::casts::create_cast_add(
+                fid, text, None, None,
+            )),
+            removal_reason: MempoolRemovalReason::None as i32,
+        };
+        let (events_tx, events_rx) = broadcast::channel(10);
+        let pending = vec![
+            event(MempoolEventType::Pending, 1234, "first"),
+            event(MempoolEventType::Pending, 5678, "other"),
+        ];
+        let mempool = tokio::spawn(async move {
+            match mempool_rx.recv().await.unwrap() {
+                MempoolRequest::Subscribe(reply_to) => reply_to
+                    .send(Ok(MempoolSubscription { pending, events_rx }))
+                    .map_err(|_| ())
+                    .unwrap(),
+                _ => panic!("Expected a subscription"),
+            }
+        });
+
+        let mut stream = service
+            .stream_mempool(authenticated(StreamMempoolRequest { fid: Some(1234) }))
+            .await
+            .unwrap()
+            .into_inner();
+        mempool.await.unwrap();
+        events_tx
+            .send(event(MempoolEventType::Added, 5678, "other again"))
+            .unwrap();
+        events_tx
+            .send(event(MempoolEventType::Added, 1234, "second"))
+            .unwrap();
+
+        let mut texts = vec![];
+        for _ in 0..2 {
+            let event = stream.next().await.unwrap().unwrap();
+            let message = event.message.unwrap();
+            assert_eq!(message.fid(), 1234);
+            match message.data.unwrap().body {
+                Some(Body::CastAddBody(body)) => texts.push((event.r#type, body.text)),
+                _ => panic!("Expected a cast"),
+            }
+        }
+        assert_eq!(
+            texts,
+            vec![
+                (MempoolEventType::Pending as i32, "first".to_string()),
+                (MempoolEventType::Added as i32, "second".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rejected_on_mainnet() {
+        let (service, mut mempool_rx) = make_service(FarcasterNetwork::Mainnet);
+        assert!(!service.enabled());
+
+        let message = messages_factory::casts::create_cast_add(1234, "test", None, None);
+        let response = service
+            .submit_message(submit_request(message, Some(1)))
+            .await
+            .unwrap_err();
+        assert_eq!(response.code(), tonic::Code::PermissionDenied);
+        assert!(mempool_rx.try_recv().is_err());
+    }
+}
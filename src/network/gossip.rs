@@ -3,7 +3,18 @@ use crate::consensus::malachite::network_connector::MalachiteNetworkEvent;
 use crate::consensus::malachite::snapchain_codec::SnapchainCodec;
 use crate::consensus::proposer::PROTOCOL_VERSION;
 use crate::core::types::{proto, SnapchainContext, SnapchainValidatorContext};
+use crate::core::util::get_farcaster_time;
 use crate::mempool::mempool::{MempoolRequest, MempoolSource};
+use crate::network::committed_messages::CommittedMessages;
+use crate::network::gossip_compression::GossipCompression;
+use crate::network::gossip_validation::{
+    is_expired_mempool_message, peer_score_params, validate_gossip_message, GossipValidation,
+};
+use crate::network::idle_peers::IdlePeers;
+use crate::network::peer_bans::{PeerBan, PeerBans};
+use crate::network::peer_selection::PeerSelector;
+use crate::network::sync_progress::SyncProgress;
+use crate::network::vote_history::VoteHistory;
 use crate::proto::{
     gossip_message, read_node_message, ContactInfo, ContactInfoBody, FarcasterNetwork,
     GossipMessage,
@@ -19,10 +30,13 @@ use informalsystems_malachitebft_network::{Channel, PeerIdExt};
 use informalsystems_malachitebft_network::{MessageId, PeerId as MalachitePeerId};
 use informalsystems_malachitebft_sync::{self as sync};
 use libp2p::identity::ed25519::Keypair;
+use libp2p::multiaddr::Protocol;
 use libp2p::request_response::{InboundRequestId, OutboundRequestId};
 use libp2p::swarm::dial_opts::DialOpts;
+use libp2p::swarm::{ConnectionDenied, ConnectionId, DialError, ListenError};
 use libp2p::{
-    gossipsub, noise, swarm::NetworkBehaviour, swarm::SwarmEvent, tcp, yamux, PeerId, Swarm,
+    gossipsub, identify, noise, swarm::NetworkBehaviour, swarm::SwarmEvent, tcp, yamux, Multiaddr,
+    PeerId, Swarm,
 };
 use libp2p_connection_limits::ConnectionLimits;
 use prost::Message;
@@ -30,7 +44,8 @@ use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 use std::hash::{DefaultHasher, Hash, Hasher};
-use std::time::Duration;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 use tokio::io;
 use tokio::sync::mpsc::Sender;
 use tokio::sync::{mpsc, oneshot};
@@ -46,14 +61,73 @@ const DECIDED_VALUES: &str = "decided-values";
 const READ_NODE_PEER_STATUSES: &str = "read-node-peers";
 const CONTACT_INFO: &str = "contact-info";
 
+const IDLE_PEER_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+fn current_time_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub address: String,
+    // Comma separated multiaddrs to listen on besides `address`, e.g. for IPv6 or TCP
+    pub listen_addresses: String,
+    // The address peers can dial us on when it isn't one we listen on, e.g. behind NAT. It's the
+    // only address advertised through identify, and the announce address unless that's set.
+    pub external_address: String,
+    // The transports the node listens on and dials. QUIC avoids head-of-line blocking across
+    // streams and sets up connections in fewer round trips, at the cost of needing UDP through
+    // firewalls and NATs, which TCP doesn't.
+    pub enable_tcp: bool,
+    pub enable_quic: bool,
     pub announce_address: String,
     pub bootstrap_peers: String,
     pub contact_info_interval: Duration,
     pub bootstrap_reconnect_interval: Duration,
     pub enable_autodiscovery: bool,
+    // Inbound peers outside our mesh that send nothing useful for this long are disconnected, zero
+    // disables eviction
+    #[serde(with = "humantime_serde")]
+    pub idle_peer_timeout: Duration,
+    // Comma separated peer ids that are never evicted for being idle
+    pub allowlisted_peers: String,
+    // How much of a received message is checked before it's forwarded to other peers. Anything
+    // past none costs a signature check for every message received.
+    pub message_validation: GossipValidation,
+    // Received mempool messages older than this, by their timestamp, are dropped without being
+    // forwarded or handed to the mempool. Zero disables it. Consensus messages are never dropped.
+    #[serde(with = "humantime_serde")]
+    pub mempool_message_ttl: Duration,
+    // Received user messages that are already committed in the node's stores are dropped without
+    // being forwarded or handed to the mempool. Costs a store lookup for every message received.
+    pub suppress_committed_messages: bool,
+    // Connections beyond these are refused, and dials beyond them fail, rather than exhausting the
+    // node's file descriptors. Established connections are also capped at 100 in each direction.
+    pub max_connections: u32,
+    pub max_connections_per_peer: u32,
+    pub max_pending_incoming_connections: u32,
+    // Gzips the messages the node publishes. Only turn it on once every peer decompresses
+    // messages, older nodes can't decode them.
+    pub compress_messages: bool,
+    // Received messages that decompress past this are dropped, and count against the sender's
+    // peer score when message validation is on
+    pub max_decompressed_bytes: usize,
+    // Peers sending this many invalid messages within the violation window are disconnected and
+    // refused for the ban duration. Messages are only checked with message validation on. Zero
+    // disables bans.
+    pub ban_max_violations: u32,
+    #[serde(with = "humantime_serde")]
+    pub ban_violation_window: Duration,
+    #[serde(with = "humantime_serde")]
+    pub ban_duration: Duration,
+    // Bans are kept in this file across restarts, only in memory when empty
+    pub bans_file: String,
+    // Seeds the node's random choices of peers, so a test network makes the same ones on every
+    // run. Unset in production, where they're seeded from entropy.
+    pub rng_seed: Option<u64>,
 }
 
 impl Default for Config {
@@ -64,11 +138,30 @@ impl Default for Config {
         );
         Config {
             address: address.clone(),
+            listen_addresses: "".to_string(),
+            external_address: "".to_string(),
+            enable_tcp: true,
+            enable_quic: true,
             announce_address: "".to_string(),
             bootstrap_peers: "".to_string(),
             contact_info_interval: Duration::from_secs(300),
             bootstrap_reconnect_interval: Duration::from_secs(30),
             enable_autodiscovery: false,
+            idle_peer_timeout: Duration::from_secs(60 * 10),
+            allowlisted_peers: "".to_string(),
+            message_validation: GossipValidation::None,
+            mempool_message_ttl: Duration::ZERO,
+            suppress_committed_messages: false,
+            max_connections: 200,
+            max_connections_per_peer: 4,
+            max_pending_incoming_connections: 32,
+            compress_messages: false,
+            max_decompressed_bytes: MAX_GOSSIP_MESSAGE_SIZE,
+            ban_max_violations: 0,
+            ban_violation_window: Duration::from_secs(60),
+            ban_duration: Duration::from_secs(60 * 60),
+            bans_file: "".to_string(),
+            rng_seed: None,
         }
     }
 }
@@ -112,6 +205,171 @@ impl Config {
         }
     }
 
+    pub fn with_listen_addresses(self, listen_addresses: String) -> Self {
+        Config {
+            listen_addresses,
+            ..self
+        }
+    }
+
+    pub fn with_external_address(self, external_address: String) -> Self {
+        Config {
+            external_address,
+            ..self
+        }
+    }
+
+    pub fn with_transports(self, enable_tcp: bool, enable_quic: bool) -> Self {
+        Config {
+            enable_tcp,
+            enable_quic,
+            ..self
+        }
+    }
+
+    pub fn transports(&self) -> Transports {
+        Transports {
+            tcp: self.enable_tcp,
+            quic: self.enable_quic,
+        }
+    }
+
+    pub fn with_idle_peer_timeout(self, idle_peer_timeout: Duration) -> Self {
+        Config {
+            idle_peer_timeout,
+            ..self
+        }
+    }
+
+    pub fn with_message_validation(self, message_validation: GossipValidation) -> Self {
+        Config {
+            message_validation,
+            ..self
+        }
+    }
+
+    pub fn with_mempool_message_ttl(self, mempool_message_ttl: Duration) -> Self {
+        Config {
+            mempool_message_ttl,
+            ..self
+        }
+    }
+
+    pub fn with_suppress_committed_messages(self, suppress_committed_messages: bool) -> Self {
+        Config {
+            suppress_committed_messages,
+            ..self
+        }
+    }
+
+    pub fn with_connection_limits(
+        self,
+        max_connections: u32,
+        max_connections_per_peer: u32,
+        max_pending_incoming_connections: u32,
+    ) -> Self {
+        Config {
+            max_connections,
+            max_connections_per_peer,
+            max_pending_incoming_connections,
+            ..self
+        }
+    }
+
+    pub fn with_peer_bans(
+        self,
+        ban_max_violations: u32,
+        ban_violation_window: Duration,
+        ban_duration: Duration,
+    ) -> Self {
+        Config {
+            ban_max_violations,
+            ban_violation_window,
+            ban_duration,
+            ..self
+        }
+    }
+
+    pub fn with_rng_seed(self, rng_seed: Option<u64>) -> Self {
+        Config { rng_seed, ..self }
+    }
+
+    pub fn peer_bans(&self) -> PeerBans {
+        let bans_file = self.bans_file.trim();
+        PeerBans::new(
+            self.ban_max_violations,
+            self.ban_violation_window,
+            self.ban_duration,
+            (!bans_file.is_empty()).then(|| PathBuf::from(bans_file)),
+        )
+    }
+
+    pub fn connection_limits(&self) -> ConnectionLimits {
+        ConnectionLimits::default()
+            .with_max_established(Some(self.max_connections))
+            .with_max_established_per_peer(Some(self.max_connections_per_peer))
+            .with_max_pending_incoming(Some(self.max_pending_incoming_connections))
+            .with_max_established_incoming(Some(100))
+            .with_max_established_outgoing(Some(100))
+    }
+
+    pub fn allowlisted_peer_ids(&self) -> Result<HashSet<PeerId>, libp2p::identity::ParseError> {
+        self.allowlisted_peers
+            .split(',')
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .map(|s| s.parse())
+            .collect()
+    }
+
+    pub fn listen_multiaddrs(&self) -> Result<Vec<Multiaddr>, String> {
+        std::iter::once(self.address.as_str())
+            .chain(self.listen_addresses.split(','))
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .map(|s| parse_multiaddr(s, self.transports()))
+            .collect()
+    }
+
+    pub fn external_multiaddr(&self) -> Result<Option<Multiaddr>, String> {
+        let external_address = self.external_address.trim();
+        if external_address.is_empty() {
+            return Ok(None);
+        }
+        parse_multiaddr(external_address, self.transports()).map(Some)
+    }
+
+    // Checked at startup, so a typo fails fast rather than leaving the node unreachable
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.enable_tcp && !self.enable_quic {
+            return Err("at least one transport has to be enabled".to_string());
+        }
+        if self.listen_multiaddrs()?.is_empty() {
+            return Err("at least one listen address is required".to_string());
+        }
+        self.external_multiaddr()?;
+        if !self.announce_address.is_empty() {
+            parse_multiaddr(&self.announce_address, self.transports())?;
+        }
+        self.allowlisted_peer_ids()
+            .map_err(|e| format!("invalid allowlisted peer id: {}", e))?;
+        if self.max_connections == 0
+            || self.max_connections_per_peer == 0
+            || self.max_pending_incoming_connections == 0
+        {
+            return Err("connection limits have to be at least 1".to_string());
+        }
+        if self.max_decompressed_bytes == 0 {
+            return Err("max_decompressed_bytes has to be at least 1".to_string());
+        }
+        if self.ban_max_violations > 0
+            && (self.ban_violation_window.is_zero() || self.ban_duration.is_zero())
+        {
+            return Err("ban_violation_window and ban_duration can't be 0".to_string());
+        }
+        Ok(())
+    }
+
     pub fn bootstrap_addrs(&self) -> Vec<String> {
         self.bootstrap_peers
             .split(',')
@@ -120,6 +378,37 @@ impl Config {
     }
 }
 
+// Both transports are always built into the swarm, the config only decides which addresses the
+// node listens on and dials
+#[derive(Debug, Clone, Copy)]
+pub struct Transports {
+    pub tcp: bool,
+    pub quic: bool,
+}
+
+impl Transports {
+    pub fn supports(&self, address: &Multiaddr) -> bool {
+        address.iter().any(|protocol| match protocol {
+            Protocol::Tcp(_) => self.tcp,
+            Protocol::QuicV1 => self.quic,
+            _ => false,
+        })
+    }
+}
+
+fn parse_multiaddr(address: &str, transports: Transports) -> Result<Multiaddr, String> {
+    let multiaddr: Multiaddr = address
+        .parse()
+        .map_err(|e| format!("invalid multiaddr {}: {}", address, e))?;
+    if !transports.supports(&multiaddr) {
+        return Err(format!(
+            "multiaddr {} doesn't use an enabled transport ({:?})",
+            address, transports
+        ));
+    }
+    Ok(multiaddr)
+}
+
 pub enum GossipEvent<Ctx: SnapchainContext> {
     BroadcastSignedVote(SignedVote<Ctx>),
     BroadcastSignedProposal(SignedProposal<Ctx>),
@@ -134,6 +423,17 @@ pub enum GossipEvent<Ctx: SnapchainContext> {
     SyncReply(InboundRequestId, sync::Response<SnapchainValidatorContext>),
     BroadcastDecidedValue(proto::DecidedValue),
     SubscribeToDecidedValuesTopic(),
+    // Dials the address once, replying with the peer once connected
+    DialPeer(Multiaddr, oneshot::Sender<Result<PeerId, DialPeerError>>),
+    // Replies with the connected peers and the bans in effect
+    GetPeers(oneshot::Sender<(Vec<PeerId>, Vec<PeerBan>)>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DialPeerError {
+    // The address can't be dialed with the node's transports
+    InvalidAddress(String),
+    Failed(String),
 }
 
 pub enum GossipTopic {
@@ -147,9 +447,10 @@ pub enum GossipTopic {
 
 #[derive(NetworkBehaviour)]
 pub struct SnapchainBehavior {
-    pub gossipsub: gossipsub::Behaviour,
+    pub gossipsub: gossipsub::Behaviour<GossipCompression>,
     pub rpc: sync::Behaviour,
     pub connection_limits: libp2p_connection_limits::Behaviour,
+    pub identify: identify::Behaviour,
 }
 
 pub struct SnapchainGossip {
@@ -158,14 +459,27 @@ pub struct SnapchainGossip {
     rx: mpsc::Receiver<GossipEvent<SnapchainValidatorContext>>,
     system_tx: Sender<SystemMessage>,
     sync_channels: HashMap<InboundRequestId, sync::ResponseChannel>,
+    // Replies for the DialPeer events whose dial hasn't succeeded or failed yet
+    pending_dials: HashMap<ConnectionId, oneshot::Sender<Result<PeerId, DialPeerError>>>,
     read_node: bool,
     enable_autodiscovery: bool,
     bootstrap_addrs: HashSet<String>,
     connected_bootstrap_addrs: HashSet<String>,
     announce_address: String,
+    transports: Transports,
     fc_network: FarcasterNetwork,
     contact_info_interval: Duration,
     bootstrap_reconnect_interval: Duration,
+    idle_peers: IdlePeers,
+    peer_bans: PeerBans,
+    peer_selector: PeerSelector,
+    message_validation: GossipValidation,
+    mempool_message_ttl: Duration,
+    suppress_committed_messages: bool,
+    // Set once the node's stores are open, messages aren't looked up until then
+    committed_messages: Option<CommittedMessages>,
+    pub sync_progress: SyncProgress,
+    pub vote_history: VoteHistory,
     statsd_client: StatsdClientWrapper,
 }
 
@@ -178,6 +492,19 @@ impl SnapchainGossip {
         fc_network: FarcasterNetwork,
         statsd_client: StatsdClientWrapper,
     ) -> Result<Self, Box<dyn std::error::Error>> {
+        let listen_addresses = config.listen_multiaddrs()?;
+        let external_address = config.external_multiaddr()?;
+        let message_validation = config.message_validation;
+        let validate_messages = message_validation != GossipValidation::None
+            || !config.mempool_message_ttl.is_zero()
+            || config.suppress_committed_messages;
+        let connection_limits = config.connection_limits();
+        let compression = GossipCompression::new(
+            config.compress_messages,
+            config.max_decompressed_bytes as u64,
+            statsd_client.clone(),
+        );
+
         let mut swarm = libp2p::SwarmBuilder::with_existing_identity(keypair.clone().into())
             .with_tokio()
             .with_tcp(
@@ -213,46 +540,63 @@ impl SnapchainGossip {
                 };
 
                 // Set a custom gossipsub configuration
-                let gossipsub_config = gossipsub::ConfigBuilder::default()
+                let mut gossipsub_config = gossipsub::ConfigBuilder::default();
+                gossipsub_config
                     .heartbeat_interval(Duration::from_millis(500)) // This might need to be lowered to 1/3 of the block time
                     .validation_mode(gossipsub::ValidationMode::Strict) // This sets the kind of message validation. The default is Strict (enforce message signing)
                     .message_id_fn(message_id_fn) // content-address mempool messages
                     .max_transmit_size(MAX_GOSSIP_MESSAGE_SIZE) // maximum message size that can be transmitted
                     .mesh_n(10) // Try setting D to a higher value to see if it helps with slow sync (nodes will consume more bandwidth)
-                    .mesh_n_high(20) // 2x D, which is the recommended value
+                    .mesh_n_high(20); // 2x D, which is the recommended value
+                if validate_messages {
+                    // Received messages are only forwarded once they're reported valid
+                    gossipsub_config.validate_messages();
+                }
+                let gossipsub_config = gossipsub_config
                     .build()
                     .map_err(|msg| io::Error::new(io::ErrorKind::Other, msg))?; // Temporary hack because `build` does not return a proper `std::error::Error`.
 
                 // build a gossipsub network behaviour
-                let gossipsub = gossipsub::Behaviour::new(
+                let mut gossipsub = gossipsub::Behaviour::new_with_transform(
                     gossipsub::MessageAuthenticity::Signed(key.clone()),
                     gossipsub_config,
+                    compression,
                 )?;
+                if message_validation != GossipValidation::None {
+                    gossipsub
+                        .with_peer_score(
+                            peer_score_params(&[CONSENSUS_TOPIC, MEMPOOL_TOPIC]),
+                            gossipsub::PeerScoreThresholds::default(),
+                        )
+                        .map_err(|msg| io::Error::new(io::ErrorKind::Other, msg))?;
+                }
 
                 let rpc = sync::Behaviour::new(
                     sync::Config::default().with_request_timeout(Duration::from_secs(5)),
                 );
 
                 // TODO(aditi): Connection limits are set high so that we don't keep kicking off read nodes for now
-                let connection_limits = libp2p_connection_limits::Behaviour::new(
-                    ConnectionLimits::default()
-                        // .with_max_pending_incoming(Some(5))
-                        // .with_max_pending_outgoing(Some(5)),
-                        .with_max_established_incoming(Some(100))
-                        .with_max_established_outgoing(Some(100)),
+                let connection_limits = libp2p_connection_limits::Behaviour::new(connection_limits);
+
+                // With an external address, the addresses we listen on are usually private ones
+                // peers can't dial, so they aren't advertised
+                let identify = identify::Behaviour::new(
+                    identify::Config::new(format!("/snapchain/{}", PROTOCOL_VERSION), key.public())
+                        .with_hide_listen_addrs(external_address.is_some()),
                 );
 
                 Ok(SnapchainBehavior {
                     gossipsub,
                     rpc,
                     connection_limits,
+                    identify,
                 })
             })?
             .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60)))
             .build();
 
         for addr in config.bootstrap_addrs() {
-            let _ = Self::dial(&mut swarm, &addr);
+            let _ = Self::dial(&mut swarm, config.transports(), &addr);
         }
 
         if read_node {
@@ -288,7 +632,13 @@ impl SnapchainGossip {
         }
 
         // Listen on all assigned port for this id
-        swarm.listen_on(config.address.parse()?)?;
+        for address in listen_addresses {
+            swarm.listen_on(address)?;
+        }
+        if let Some(external_address) = external_address {
+            info!("Advertising {} as external address", external_address);
+            swarm.add_external_address(external_address);
+        }
 
         let announce_address = Self::get_announce_address(config).await;
 
@@ -302,22 +652,41 @@ impl SnapchainGossip {
             rx,
             system_tx,
             sync_channels: HashMap::new(),
+            pending_dials: HashMap::new(),
             read_node,
             bootstrap_addrs: config.bootstrap_addrs().into_iter().collect(),
             announce_address,
+            transports: config.transports(),
             fc_network,
             contact_info_interval: config.contact_info_interval,
             bootstrap_reconnect_interval: config.bootstrap_reconnect_interval,
+            idle_peers: IdlePeers::new(config.idle_peer_timeout, config.allowlisted_peer_ids()?),
+            peer_bans: config.peer_bans(),
+            peer_selector: PeerSelector::new(config.rng_seed),
+            message_validation: config.message_validation,
+            mempool_message_ttl: config.mempool_message_ttl,
+            suppress_committed_messages: config.suppress_committed_messages,
+            committed_messages: None,
+            sync_progress: SyncProgress::default(),
+            vote_history: VoteHistory::default(),
             statsd_client,
             connected_bootstrap_addrs: HashSet::new(),
             enable_autodiscovery: config.enable_autodiscovery,
         })
     }
 
+    pub fn with_committed_messages(mut self, committed_messages: CommittedMessages) -> Self {
+        self.committed_messages = Some(committed_messages);
+        self
+    }
+
     async fn get_announce_address(config: &Config) -> String {
         if config.announce_address.len() > 0 {
             return config.announce_address.clone();
         }
+        if config.external_address.len() > 0 {
+            return config.external_address.clone();
+        }
 
         // If no config-defined announce IP exists, detect the public IP.
         // Falling back to the address also defined in the config
@@ -346,9 +715,17 @@ impl SnapchainGossip {
 
     fn dial(
         swarm: &mut Swarm<SnapchainBehavior>,
+        transports: Transports,
         addr: &String,
     ) -> Result<(), Box<dyn std::error::Error>> {
         let parsed_addr: libp2p::Multiaddr = addr.parse()?;
+        if !transports.supports(&parsed_addr) {
+            warn!(
+                "Not dialing peer {:?}, its transport is disabled",
+                parsed_addr
+            );
+            return Err(format!("transport disabled for {}", addr).into());
+        }
         let opts = DialOpts::unknown_peer_id()
             .address(parsed_addr.clone())
             .build();
@@ -361,17 +738,56 @@ impl SnapchainGossip {
         Ok(())
     }
 
+    fn dial_peer(
+        &mut self,
+        address: Multiaddr,
+        reply_tx: oneshot::Sender<Result<PeerId, DialPeerError>>,
+    ) {
+        if !self.transports.supports(&address) {
+            let _ = reply_tx.send(Err(DialPeerError::InvalidAddress(format!(
+                "{} doesn't use an enabled transport ({:?})",
+                address, self.transports
+            ))));
+            return;
+        }
+        let opts = DialOpts::unknown_peer_id().address(address.clone()).build();
+        let connection_id = opts.connection_id();
+        info!(address = address.to_string(), "Dialing peer on request");
+        match self.swarm.dial(opts) {
+            Ok(()) => {
+                self.pending_dials.insert(connection_id, reply_tx);
+            }
+            Err(err) => {
+                warn!(
+                    address = address.to_string(),
+                    "Failed to dial peer: {:?}", err
+                );
+                let _ = reply_tx.send(Err(DialPeerError::Failed(err.to_string())));
+            }
+        }
+    }
+
     pub async fn check_and_reconnect_to_bootstrap_peers(&mut self) {
         let connected_peers_count = self.swarm.connected_peers().count();
-        // Validators should stay connected to all bootstrap peers. Read nodes should only try to connect if they're connected to too few peers
-        if !self.read_node || (self.read_node && connected_peers_count < self.bootstrap_addrs.len())
-        {
-            for addr in &self.bootstrap_addrs {
-                if !self.connected_bootstrap_addrs.contains(addr) {
-                    warn!("Attempting to reconnect to bootstrap peer: {}", addr);
-                    let _ = Self::dial(&mut self.swarm, &addr);
-                }
-            }
+        let disconnected = self
+            .bootstrap_addrs
+            .iter()
+            .filter(|addr| !self.connected_bootstrap_addrs.contains(*addr))
+            .cloned();
+        // Validators should stay connected to all bootstrap peers. Read nodes only dial as many
+        // as they're short of, when they're connected to too few peers.
+        let addrs = if self.read_node {
+            let missing = self
+                .bootstrap_addrs
+                .len()
+                .saturating_sub(connected_peers_count);
+            self.peer_selector.choose(disconnected, missing)
+        } else {
+            self.peer_selector.shuffle(disconnected)
+        };
+        for addr in addrs {
+            warn!("Attempting to reconnect to bootstrap peer: {}", addr);
+            let _ = Self::dial(&mut self.swarm, self.transports, &addr);
         }
     }
 
@@ -397,16 +813,106 @@ impl SnapchainGossip {
         self.publish(gossip_message.encode_to_vec(), CONTACT_INFO);
     }
 
+    fn report_connection_counts(&self) {
+        let network_info = self.swarm.network_info();
+        let counters = network_info.connection_counters();
+        for (key, count) in [
+            (
+                "gossip.connections.established_incoming",
+                counters.num_established_incoming(),
+            ),
+            (
+                "gossip.connections.established_outgoing",
+                counters.num_established_outgoing(),
+            ),
+            (
+                "gossip.connections.pending_incoming",
+                counters.num_pending_incoming(),
+            ),
+            (
+                "gossip.connections.pending_outgoing",
+                counters.num_pending_outgoing(),
+            ),
+        ] {
+            self.statsd_client.gauge(key, count as u64);
+        }
+        self.sync_progress
+            .record_connected_peers(self.swarm.connected_peers().count());
+    }
+
+    // Connections refused by the connection limits are expected on a busy node, so they're only
+    // counted
+    fn is_over_connection_limit(cause: &ConnectionDenied) -> bool {
+        match cause.downcast_ref::<libp2p_connection_limits::Exceeded>() {
+            Some(exceeded) => {
+                debug!("Connection refused: {}", exceeded);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn evict_idle_peers(&mut self) {
+        let mesh_peers: HashSet<PeerId> = self
+            .swarm
+            .behaviour()
+            .gossipsub
+            .all_mesh_peers()
+            .cloned()
+            .collect();
+        let idle_peers = self.idle_peers.idle_peers(&mesh_peers, Instant::now());
+        for peer_id in &idle_peers {
+            info!(
+                peer_id = peer_id.to_string(),
+                "Disconnecting idle peer to free up a connection slot"
+            );
+            // The peer stops being tracked once its connection closes
+            _ = self.swarm.disconnect_peer_id(*peer_id);
+        }
+        if !idle_peers.is_empty() {
+            self.statsd_client
+                .count("gossip.idle_peers_evicted", idle_peers.len() as i64);
+        }
+    }
+
+    // Banned peers are disconnected right away, and refused whenever they connect again until the
+    // ban runs out
+    fn record_violation(&mut self, peer_id: &PeerId) {
+        if !self
+            .peer_bans
+            .record_violation(*peer_id, current_time_millis())
+        {
+            return;
+        }
+        warn!(
+            peer_id = peer_id.to_string(),
+            "Banning peer after repeated protocol violations"
+        );
+        self.statsd_client.count("gossip.peers_banned", 1);
+        _ = self.swarm.disconnect_peer_id(*peer_id);
+    }
+
+    fn is_banned(&self, peer_id: &PeerId) -> bool {
+        self.peer_bans.is_banned(peer_id, current_time_millis())
+    }
+
     pub async fn start(self: &mut Self) {
         let mut reconnect_timer = tokio::time::interval(self.bootstrap_reconnect_interval);
 
+        let mut idle_peer_timer = tokio::time::interval(IDLE_PEER_CHECK_INTERVAL);
+
         let mut publish_contact_info_timer = tokio::time::interval(self.contact_info_interval);
 
         loop {
             tokio::select! {
                 _ = reconnect_timer.tick() => {
+                    self.peer_bans.expire(current_time_millis());
                     self.check_and_reconnect_to_bootstrap_peers().await;
                     self.statsd_client.gauge("gossip.connected_peers", self.swarm.connected_peers().count() as u64);
+                    self.report_connection_counts();
+                },
+                _ = idle_peer_timer.tick(), if self.idle_peers.enabled() => {
+                    self.evict_idle_peers();
                 },
                 _ = publish_contact_info_timer.tick() => {
                     if self.read_node {
@@ -416,8 +922,20 @@ impl SnapchainGossip {
                 }
                 gossip_event = self.swarm.select_next_some() => {
                     match gossip_event {
-                        SwarmEvent::ConnectionEstablished {peer_id, endpoint, ..} => {
+                        SwarmEvent::ConnectionEstablished {peer_id, connection_id, endpoint, ..} => {
+                            if self.is_banned(&peer_id) {
+                                info!(peer_id = peer_id.to_string(), "Refusing connection from banned peer");
+                                if let Some(reply_tx) = self.pending_dials.remove(&connection_id) {
+                                    let _ = reply_tx.send(Err(DialPeerError::Failed(format!("peer {} is banned", peer_id))));
+                                }
+                                _ = self.swarm.disconnect_peer_id(peer_id);
+                                continue;
+                            }
                             info!(total_peers = self.swarm.connected_peers().count(), "Connection established with peer: {peer_id}");
+                            if let Some(reply_tx) = self.pending_dials.remove(&connection_id) {
+                                let _ = reply_tx.send(Ok(peer_id));
+                            }
+                            self.report_connection_counts();
                             let event = MalachiteNetworkEvent::PeerConnected(MalachitePeerId::from_libp2p(&peer_id));
                             let res = self.system_tx.send(SystemMessage::MalachiteNetwork(MalachiteEventShard::None, event)).await;
                             if let Err(e) = res {
@@ -430,10 +948,16 @@ impl SnapchainGossip {
                                     }
 
                                 },
-                                libp2p::core::ConnectedPoint::Listener { .. } => {},
+                                libp2p::core::ConnectedPoint::Listener { .. } => {
+                                    self.idle_peers.connected(peer_id, Instant::now());
+                                },
                             };
                         },
-                        SwarmEvent::ConnectionClosed {peer_id, cause, endpoint, ..} => {
+                        SwarmEvent::ConnectionClosed {peer_id, cause, endpoint, num_established, ..} => {
+                            if num_established == 0 {
+                                self.idle_peers.disconnected(&peer_id);
+                            }
+                            self.report_connection_counts();
                             info!("Connection closed with peer: {:?} due to: {:?}", peer_id, cause);
                             let event = MalachiteNetworkEvent::PeerDisconnected(MalachitePeerId::from_libp2p(&peer_id));
                             let res = self.system_tx.send(SystemMessage::MalachiteNetwork(MalachiteEventShard::None, event)).await;
@@ -451,6 +975,8 @@ impl SnapchainGossip {
                             info!("Peer: {peer_id} subscribed to topic: {topic}"),
                         SwarmEvent::Behaviour(SnapchainBehaviorEvent::Gossipsub(gossipsub::Event::Unsubscribed { peer_id, topic })) =>
                             info!("Peer: {peer_id} unsubscribed to topic: {topic}"),
+                        SwarmEvent::Behaviour(SnapchainBehaviorEvent::Identify(identify::Event::Received { peer_id, info, .. })) =>
+                            debug!(listen_addrs = ?info.listen_addrs, observed_addr = %info.observed_addr, "Identified peer: {peer_id}"),
                         SwarmEvent::NewListenAddr { address, .. } => {
                             info!(address = address.to_string(), "Local node is listening");
                             let res = self.system_tx.send(SystemMessage::MalachiteNetwork(MalachiteEventShard::None, MalachiteNetworkEvent::Listening(address))).await;
@@ -458,15 +984,38 @@ impl SnapchainGossip {
                                 warn!("Failed to send Listening message: {}", e);
                             }
                         },
-                        SwarmEvent::OutgoingConnectionError {connection_id: _, peer_id, error} => {
-                            warn!("Failed to dial peer: {:?} due to: {:?}", peer_id, error);
+                        SwarmEvent::OutgoingConnectionError {connection_id, peer_id, error} => {
+                            if let Some(reply_tx) = self.pending_dials.remove(&connection_id) {
+                                let _ = reply_tx.send(Err(DialPeerError::Failed(error.to_string())));
+                            }
+                            match &error {
+                                DialError::Denied { cause } if Self::is_over_connection_limit(cause) => {
+                                    self.statsd_client.count("gossip.connection_limit_exceeded", 1);
+                                },
+                                _ => warn!("Failed to dial peer: {:?} due to: {:?}", peer_id, error),
+                            }
+                        },
+                        SwarmEvent::IncomingConnectionError {send_back_addr, error, ..} => {
+                            match &error {
+                                ListenError::Denied { cause } if Self::is_over_connection_limit(cause) => {
+                                    self.statsd_client.count("gossip.connection_limit_exceeded", 1);
+                                },
+                                _ => debug!("Incoming connection from {} failed due to: {:?}", send_back_addr, error),
+                            }
                         },
                         SwarmEvent::Behaviour(SnapchainBehaviorEvent::Gossipsub(gossipsub::Event::Message {
                             propagation_source: peer_id,
-                            message_id: _id,
+                            message_id,
                             message,
                         })) => {
-                            if let Some(system_message) = self.map_gossip_bytes_to_system_message(peer_id, message.data) {
+                            // Rejected messages are neither forwarded nor used
+                            let system_message = if self.validate_gossip_message(&peer_id, &message_id, &message.data).await {
+                                self.map_gossip_bytes_to_system_message(peer_id, message.data)
+                            } else {
+                                None
+                            };
+                            if let Some(system_message) = system_message {
+                                self.idle_peers.record_useful(&peer_id, Instant::now());
                                 let res = self.system_tx.send(system_message).await;
                                 if let Err(e) = res {
                                     warn!("Failed to send system block message: {}", e);
@@ -476,6 +1025,7 @@ impl SnapchainGossip {
                         SwarmEvent::Behaviour(SnapchainBehaviorEvent::Rpc(sync_event)) => {
                             match sync_event {
                                 sync::Event::Message {peer, message, connection_id: _} => {
+                                    self.idle_peers.record_useful(&peer, Instant::now());
                                     match message {
                                         libp2p::request_response::Message::Request {
                                             request_id,
@@ -561,6 +1111,75 @@ impl SnapchainGossip {
         }
     }
 
+    // Reports the result to gossipsub, which forwards valid messages and penalizes the peer for
+    // invalid ones. Expired and already committed messages are ignored, which drops them without a
+    // penalty, the peer may just be slow. Nothing has to be reported without validation, a ttl or
+    // committed message suppression, messages are already forwarded.
+    async fn validate_gossip_message(
+        &mut self,
+        peer_id: &PeerId,
+        message_id: &gossipsub::MessageId,
+        data: &[u8],
+    ) -> bool {
+        let has_ttl = !self.mempool_message_ttl.is_zero();
+        if self.message_validation == GossipValidation::None
+            && !has_ttl
+            && !self.suppress_committed_messages
+        {
+            return true;
+        }
+        let acceptance = if has_ttl
+            && is_expired_mempool_message(
+                data,
+                self.mempool_message_ttl,
+                get_farcaster_time().unwrap_or_default(),
+            ) {
+            debug!(
+                peer_id = peer_id.to_string(),
+                "Dropped expired gossip message"
+            );
+            self.statsd_client.count("gossip.ttl_dropped", 1);
+            gossipsub::MessageAcceptance::Ignore
+        } else if self.suppress_committed_messages && self.is_committed_gossip_message(data).await {
+            debug!(
+                peer_id = peer_id.to_string(),
+                "Dropped already committed gossip message"
+            );
+            self.statsd_client.count("gossip.already_committed", 1);
+            gossipsub::MessageAcceptance::Ignore
+        } else {
+            let acceptance =
+                validate_gossip_message(data, self.message_validation, self.fc_network);
+            if acceptance != gossipsub::MessageAcceptance::Accept {
+                warn!(
+                    peer_id = peer_id.to_string(),
+                    "Rejected invalid gossip message"
+                );
+                self.statsd_client.count("gossip.invalid_messages", 1);
+                self.record_violation(peer_id);
+            }
+            acceptance
+        };
+        let valid = acceptance == gossipsub::MessageAcceptance::Accept;
+        _ = self
+            .swarm
+            .behaviour_mut()
+            .gossipsub
+            .report_message_validation_result(message_id, peer_id, acceptance);
+        valid
+    }
+
+    // Looked up on a blocking thread, so the store reads don't block the event loop's thread
+    async fn is_committed_gossip_message(&self, data: &[u8]) -> bool {
+        let Some(committed_messages) = self.committed_messages.clone() else {
+            return false;
+        };
+        let data = data.to_vec();
+        tokio::task::spawn_blocking(move || committed_messages.is_committed_gossip_message(&data))
+            .await
+            .unwrap_or(false)
+    }
+
     fn publish(&mut self, message: Vec<u8>, topic: &str) {
         let publish_topic = gossipsub::IdentTopic::new(topic);
         if let Err(e) = self
@@ -615,8 +1234,20 @@ impl SnapchainGossip {
             return;
         }
 
+        if self.is_banned(&contact_peer_id) {
+            info!(
+                peer_id = contact_peer_id.to_string(),
+                "Not dialing banned peer"
+            );
+            return;
+        }
+
         if self.enable_autodiscovery {
-            let _ = Self::dial(&mut self.swarm, &contact_info_body.gossip_address);
+            let _ = Self::dial(
+                &mut self.swarm,
+                self.transports,
+                &contact_info_body.gossip_address,
+            );
         }
     }
 
@@ -669,6 +1300,15 @@ impl SnapchainGossip {
                     Some(SystemMessage::MalachiteNetwork(shard, event))
                 }
                 Some(proto::gossip_message::GossipMessage::Consensus(signed_consensus_msg)) => {
+                    if let Some(proto::consensus_message::ConsensusMessage::Vote(vote)) =
+                        &signed_consensus_msg.consensus_message
+                    {
+                        self.vote_history.record_vote(
+                            vote,
+                            &signed_consensus_msg.signature,
+                            current_time_millis(),
+                        );
+                    }
                     let malachite_peer_id = MalachitePeerId::from_libp2p(&peer_id);
                     let bytes = Bytes::from(signed_consensus_msg.encode_to_vec());
                     let event = MalachiteNetworkEvent::Message(
@@ -693,6 +1333,12 @@ impl SnapchainGossip {
                         );
                         return None;
                     };
+                    self.sync_progress.record_peer_height(
+                        height.shard_index,
+                        peer_id,
+                        height.block_number,
+                        Instant::now(),
+                    );
                     let shard = MalachiteEventShard::Shard(height.shard_index);
                     let malachite_peer_id = MalachitePeerId::from_libp2p(&peer_id);
                     let event = MalachiteNetworkEvent::Message(
@@ -815,6 +1461,11 @@ impl SnapchainGossip {
             }
             Some(GossipEvent::BroadcastSignedVote(vote)) => {
                 let vote_proto = vote.to_proto();
+                self.vote_history.record_vote(
+                    &vote_proto,
+                    &vote.signature.0,
+                    current_time_millis(),
+                );
                 let gossip_message = proto::GossipMessage {
                     gossip_message: Some(proto::gossip_message::GossipMessage::Consensus(
                         proto::ConsensusMessage {
@@ -886,7 +1537,23 @@ impl SnapchainGossip {
                     }
                 }
             }
+            Some(GossipEvent::DialPeer(address, reply_tx)) => {
+                self.dial_peer(address, reply_tx);
+                None
+            }
+            Some(GossipEvent::GetPeers(reply_tx)) => {
+                let peers = self.swarm.connected_peers().cloned().collect();
+                let _ = reply_tx.send((peers, self.peer_bans.bans(current_time_millis())));
+                None
+            }
             Some(GossipEvent::BroadcastStatus(status)) => {
+                self.sync_progress.record_local_height(
+                    status.height.shard_index,
+                    status.height.block_number,
+                    Instant::now(),
+                );
+                self.vote_history
+                    .record_local_height(status.height.shard_index, status.height.block_number);
                 let encoded = snapchain_codec.encode(&status);
                 match encoded {
                     Ok(encoded) => {
@@ -0,0 +1,135 @@
+use crate::utils::decompression::{gunzip_limited, gzip, is_gzip, DecompressionError};
+use crate::utils::statsd_wrapper::StatsdClientWrapper;
+use libp2p::gossipsub::{self, DataTransform, RawMessage, TopicHash};
+use std::io;
+
+/// Gzips the gossip messages the node publishes when enabled. Received messages are decompressed
+/// whether it's enabled or not, up to a maximum size. A message that decompresses past it is
+/// dropped as invalid, which gossipsub counts against the sender's score when peer scoring is on.
+/// Protobuf encoded messages never start like a gzip stream, so uncompressed ones pass through.
+pub struct GossipCompression {
+    compress_outbound: bool,
+    max_decompressed_bytes: u64,
+    statsd_client: StatsdClientWrapper,
+}
+
+impl GossipCompression {
+    pub fn new(
+        compress_outbound: bool,
+        max_decompressed_bytes: u64,
+        statsd_client: StatsdClientWrapper,
+    ) -> Self {
+        GossipCompression {
+            compress_outbound,
+            max_decompressed_bytes,
+            statsd_client,
+        }
+    }
+
+    fn decompress(&self, data: Vec<u8>) -> io::Result<Vec<u8>> {
+        if !is_gzip(&data) {
+            return Ok(data);
+        }
+        let mut decompressed = vec![];
+        match gunzip_limited(
+            data.as_slice(),
+            &mut decompressed,
+            self.max_decompressed_bytes,
+        ) {
+            Ok(_) => Ok(decompressed),
+            Err(DecompressionError::LimitExceeded(limit)) => {
+                self.statsd_client
+                    .count("gossip.decompression_limit_exceeded", 1);
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("decompressed size is over the limit of {} bytes", limit),
+                ))
+            }
+            Err(DecompressionError::Io(err)) => Err(err),
+        }
+    }
+}
+
+impl DataTransform for GossipCompression {
+    fn inbound_transform(&self, raw_message: RawMessage) -> Result<gossipsub::Message, io::Error> {
+        Ok(gossipsub::Message {
+            source: raw_message.source,
+            data: self.decompress(raw_message.data)?,
+            sequence_number: raw_message.sequence_number,
+            topic: raw_message.topic,
+        })
+    }
+
+    fn outbound_transform(&self, _topic: &TopicHash, data: Vec<u8>) -> Result<Vec<u8>, io::Error> {
+        if self.compress_outbound {
+            gzip(&data)
+        } else {
+            Ok(data)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::store::test_helper;
+    use prost::Message as _;
+
+    fn raw_message(data: Vec<u8>) -> RawMessage {
+        RawMessage {
+            source: None,
+            data,
+            sequence_number: None,
+            topic: TopicHash::from_raw("test"),
+            signature: None,
+            key: None,
+            validated: false,
+        }
+    }
+
+    #[test]
+    fn test_round_trips_messages() {
+        let message = crate::proto::GossipMessage {
+            gossip_message: Some(crate::proto::gossip_message::GossipMessage::Status(
+                crate::proto::StatusMessage::default(),
+            )),
+        }
+        .encode_to_vec();
+        let topic = TopicHash::from_raw("test");
+        let compression = GossipCompression::new(true, 1024, test_helper::statsd_client());
+        let compressed = compression
+            .outbound_transform(&topic, message.clone())
+            .unwrap();
+        assert!(is_gzip(&compressed));
+        let received = compression
+            .inbound_transform(raw_message(compressed))
+            .unwrap();
+        assert_eq!(received.data, message);
+
+        // Uncompressed messages from peers without compression pass through
+        let compression = GossipCompression::new(false, 1024, test_helper::statsd_client());
+        assert_eq!(
+            compression
+                .outbound_transform(&topic, message.clone())
+                .unwrap(),
+            message
+        );
+        assert_eq!(
+            compression
+                .inbound_transform(raw_message(message.clone()))
+                .unwrap()
+                .data,
+            message
+        );
+    }
+
+    #[test]
+    fn test_rejects_decompression_bomb() {
+        let compression = GossipCompression::new(false, 1024 * 1024, test_helper::statsd_client());
+        let bomb = gzip(&vec![0u8; 64 * 1024 * 1024]).unwrap();
+        let err = compression
+            .inbound_transform(raw_message(bomb))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -1,13 +1,20 @@
 use crate::consensus::consensus::SystemMessage;
 use crate::mempool::mempool::{MempoolRequest, MempoolSource};
+use crate::mempool::routing;
+use crate::network::committed_messages::CommittedMessages;
 use crate::network::gossip::{Config, GossipEvent, SnapchainGossip};
-use crate::proto::{FarcasterNetwork, Message, MessageData};
+use crate::network::gossip_validation::GossipValidation;
+use crate::proto::{self, FarcasterNetwork, Message, MessageData};
 use crate::storage::store::engine::MempoolMessage;
-use crate::storage::store::test_helper::statsd_client;
+use crate::storage::store::test_helper::{self, statsd_client};
 use crate::utils::factory::messages_factory;
+use futures::StreamExt;
 use libp2p::identity::ed25519::Keypair;
+use libp2p::swarm::SwarmEvent;
 use prost::Message as _;
 use serial_test::serial;
+use std::collections::HashMap;
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::mpsc;
 use tokio::{select, time};
@@ -261,3 +268,395 @@ async fn test_bootstrap_peer_reconnection() {
     let receive_counts = wait_for_message(&mut system_rx2, cast_add).await;
     assert_eq!(receive_counts, 1);
 }
+
+#[tokio::test]
+#[serial]
+async fn test_invalid_messages_are_not_rebroadcast() {
+    // Node 2 relays between nodes 1 and 3, which aren't connected to each other
+    let node1_addr = format!(
+        "/ip4/{HOST_FOR_TEST}/udp/{}/quic-v1",
+        BASE_PORT_FOR_TEST + 40
+    );
+    let node2_addr = format!(
+        "/ip4/{HOST_FOR_TEST}/udp/{}/quic-v1",
+        BASE_PORT_FOR_TEST + 41
+    );
+    let node3_addr = format!(
+        "/ip4/{HOST_FOR_TEST}/udp/{}/quic-v1",
+        BASE_PORT_FOR_TEST + 42
+    );
+    let config1 = Config::new(node1_addr.clone(), node2_addr.clone());
+    let config2 = Config::new(node2_addr.clone(), "".to_string())
+        .with_message_validation(GossipValidation::Signature);
+    let config3 = Config::new(node3_addr.clone(), node2_addr.clone());
+
+    let mut gossips = vec![];
+    let mut system_rxs = vec![];
+    for config in [config1, config2, config3] {
+        let (system_tx, system_rx) = mpsc::channel::<SystemMessage>(100);
+        let gossip = SnapchainGossip::create(
+            Keypair::generate(),
+            &config,
+            system_tx,
+            false,
+            FarcasterNetwork::Devnet,
+            statsd_client(),
+        )
+        .await
+        .unwrap();
+        gossips.push(gossip);
+        system_rxs.push(system_rx);
+    }
+    let gossip_tx1 = gossips[0].tx.clone();
+    for mut gossip in gossips {
+        tokio::spawn(async move {
+            gossip.start().await;
+        });
+    }
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let mut forged = messages_factory::casts::create_cast_add(123, "forged", None, None);
+    forged.signature[0] ^= 1;
+    let cast_add = messages_factory::casts::create_cast_add(123, "test", None, None);
+    for message in [forged, cast_add.clone()] {
+        gossip_tx1
+            .send(GossipEvent::BroadcastMempoolMessage(
+                MempoolMessage::UserMessage(message),
+            ))
+            .await
+            .unwrap();
+    }
+
+    // Only the valid message reaches either node, node 2 doesn't forward the forged one to node 3
+    let receive_counts = wait_for_message(&mut system_rxs[1], cast_add.clone()).await;
+    assert_eq!(receive_counts, 1);
+    let receive_counts = wait_for_message(&mut system_rxs[2], cast_add).await;
+    assert_eq!(receive_counts, 1);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_expired_messages_are_dropped() {
+    // Node 2 relays between nodes 1 and 3, which aren't connected to each other
+    let node1_addr = format!(
+        "/ip4/{HOST_FOR_TEST}/udp/{}/quic-v1",
+        BASE_PORT_FOR_TEST + 50
+    );
+    let node2_addr = format!(
+        "/ip4/{HOST_FOR_TEST}/udp/{}/quic-v1",
+        BASE_PORT_FOR_TEST + 51
+    );
+    let node3_addr = format!(
+        "/ip4/{HOST_FOR_TEST}/udp/{}/quic-v1",
+        BASE_PORT_FOR_TEST + 52
+    );
+    let config1 = Config::new(node1_addr.clone(), node2_addr.clone());
+    let config2 = Config::new(node2_addr.clone(), "".to_string())
+        .with_mempool_message_ttl(Duration::from_secs(60 * 60));
+    let config3 = Config::new(node3_addr.clone(), node2_addr.clone());
+
+    let mut gossips = vec![];
+    let mut system_rxs = vec![];
+    for config in [config1, config2, config3] {
+        let (system_tx, system_rx) = mpsc::channel::<SystemMessage>(100);
+        let gossip = SnapchainGossip::create(
+            Keypair::generate(),
+            &config,
+            system_tx,
+            false,
+            FarcasterNetwork::Devnet,
+            statsd_client(),
+        )
+        .await
+        .unwrap();
+        gossips.push(gossip);
+        system_rxs.push(system_rx);
+    }
+    let gossip_tx1 = gossips[0].tx.clone();
+    for mut gossip in gossips {
+        tokio::spawn(async move {
+            gossip.start().await;
+        });
+    }
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let now = crate::core::util::get_farcaster_time().unwrap() as u32;
+    let stale =
+        messages_factory::casts::create_cast_add(123, "stale", Some(now - 2 * 60 * 60), None);
+    let cast_add = messages_factory::casts::create_cast_add(123, "test", Some(now), None);
+    for message in [stale, cast_add.clone()] {
+        gossip_tx1
+            .send(GossipEvent::BroadcastMempoolMessage(
+                MempoolMessage::UserMessage(message),
+            ))
+            .await
+            .unwrap();
+    }
+
+    // Node 2 neither processes the stale message nor forwards it to node 3
+    let receive_counts = wait_for_message(&mut system_rxs[1], cast_add.clone()).await;
+    assert_eq!(receive_counts, 1);
+    let receive_counts = wait_for_message(&mut system_rxs[2], cast_add).await;
+    assert_eq!(receive_counts, 1);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_committed_messages_are_not_rebroadcast() {
+    // Node 2 relays between nodes 1 and 3, which aren't connected to each other
+    let node1_addr = format!(
+        "/ip4/{HOST_FOR_TEST}/udp/{}/quic-v1",
+        BASE_PORT_FOR_TEST + 60
+    );
+    let node2_addr = format!(
+        "/ip4/{HOST_FOR_TEST}/udp/{}/quic-v1",
+        BASE_PORT_FOR_TEST + 61
+    );
+    let node3_addr = format!(
+        "/ip4/{HOST_FOR_TEST}/udp/{}/quic-v1",
+        BASE_PORT_FOR_TEST + 62
+    );
+    let config1 = Config::new(node1_addr.clone(), node2_addr.clone());
+    let config2 =
+        Config::new(node2_addr.clone(), "".to_string()).with_suppress_committed_messages(true);
+    let config3 = Config::new(node3_addr.clone(), node2_addr.clone());
+
+    // Node 2 already committed one of the casts
+    let (mut engine, _dir) = test_helper::new_engine();
+    test_helper::register_user(
+        test_helper::FID_FOR_TEST,
+        test_helper::default_signer(),
+        test_helper::default_custody_address(),
+        &mut engine,
+    )
+    .await;
+    let committed =
+        messages_factory::casts::create_cast_add(test_helper::FID_FOR_TEST, "old", None, None);
+    test_helper::commit_message(&mut engine, &committed).await;
+    let cast_add =
+        messages_factory::casts::create_cast_add(test_helper::FID_FOR_TEST, "new", None, None);
+    let committed_messages = CommittedMessages::new(
+        HashMap::from([(engine.shard_id(), engine.get_stores())]),
+        1,
+        Arc::new(routing::ShardRouter {}),
+    );
+
+    let mut gossips = vec![];
+    let mut system_rxs = vec![];
+    for (index, config) in [config1, config2, config3].iter().enumerate() {
+        let (system_tx, system_rx) = mpsc::channel::<SystemMessage>(100);
+        let mut gossip = SnapchainGossip::create(
+            Keypair::generate(),
+            config,
+            system_tx,
+            false,
+            FarcasterNetwork::Devnet,
+            statsd_client(),
+        )
+        .await
+        .unwrap();
+        if index == 1 {
+            gossip = gossip.with_committed_messages(committed_messages.clone());
+        }
+        gossips.push(gossip);
+        system_rxs.push(system_rx);
+    }
+    let gossip_tx1 = gossips[0].tx.clone();
+    for mut gossip in gossips {
+        tokio::spawn(async move {
+            gossip.start().await;
+        });
+    }
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    for message in [committed, cast_add.clone()] {
+        gossip_tx1
+            .send(GossipEvent::BroadcastMempoolMessage(
+                MempoolMessage::UserMessage(message),
+            ))
+            .await
+            .unwrap();
+    }
+
+    // Node 2 neither processes the committed message nor forwards it to node 3
+    let receive_counts = wait_for_message(&mut system_rxs[1], cast_add.clone()).await;
+    assert_eq!(receive_counts, 1);
+    let receive_counts = wait_for_message(&mut system_rxs[2], cast_add).await;
+    assert_eq!(receive_counts, 1);
+}
+
+#[test]
+fn test_config_validation() {
+    assert!(Config::default().validate().is_ok());
+
+    let config = Config::new(
+        format!("/ip4/{HOST_FOR_TEST}/udp/{BASE_PORT_FOR_TEST}/quic-v1"),
+        "".to_string(),
+    )
+    .with_listen_addresses(format!(
+        "/ip6/::1/udp/{BASE_PORT_FOR_TEST}/quic-v1, /ip4/{HOST_FOR_TEST}/tcp/{BASE_PORT_FOR_TEST}"
+    ))
+    .with_external_address("/dns4/example.com/udp/3382/quic-v1".to_string());
+    assert!(config.validate().is_ok());
+    assert_eq!(config.listen_multiaddrs().unwrap().len(), 3);
+
+    let invalid_configs = [
+        config
+            .clone()
+            .with_listen_addresses("/ip4/not-an-ip/tcp/3382".to_string()),
+        // No transport
+        config
+            .clone()
+            .with_listen_addresses("/ip4/127.0.0.1".to_string()),
+        config
+            .clone()
+            .with_external_address("example.com:3382".to_string()),
+        config
+            .clone()
+            .with_announce_address("/ip4/1.2.3.4/udp/3382".to_string()),
+        Config::new("".to_string(), "".to_string()),
+        // The tcp listen address needs tcp enabled
+        config.clone().with_transports(false, true),
+        config.clone().with_transports(false, false),
+        config.clone().with_connection_limits(0, 4, 32),
+    ];
+    for config in invalid_configs {
+        assert!(config.validate().is_err(), "{:?} should be invalid", config);
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn test_listens_on_every_address_and_advertises_external_address() {
+    let quic_addr = format!(
+        "/ip4/{HOST_FOR_TEST}/udp/{}/quic-v1",
+        BASE_PORT_FOR_TEST + 20
+    );
+    let tcp_addr = format!("/ip4/{HOST_FOR_TEST}/tcp/{}", BASE_PORT_FOR_TEST + 20);
+    let external_addr = "/ip4/203.0.113.1/udp/3382/quic-v1".to_string();
+    let config = Config::new(quic_addr, "".to_string())
+        .with_listen_addresses(tcp_addr)
+        .with_announce_address(external_addr.clone())
+        .with_external_address(external_addr.clone());
+
+    let (system_tx, _system_rx) = mpsc::channel::<SystemMessage>(100);
+    let mut gossip = SnapchainGossip::create(
+        Keypair::generate(),
+        &config,
+        system_tx,
+        false,
+        FarcasterNetwork::Devnet,
+        statsd_client(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        gossip
+            .swarm
+            .external_addresses()
+            .cloned()
+            .collect::<Vec<_>>(),
+        vec![external_addr.parse().unwrap()]
+    );
+
+    // Listeners are reported once they're bound
+    let mut listening = vec![];
+    while listening.len() < 2 {
+        if let SwarmEvent::NewListenAddr { address, .. } =
+            time::timeout(Duration::from_secs(2), gossip.swarm.select_next_some())
+                .await
+                .unwrap()
+        {
+            listening.push(address);
+        }
+    }
+    assert!(listening
+        .iter()
+        .any(|address| address.to_string().contains("/tcp/")));
+    assert!(listening
+        .iter()
+        .any(|address| address.to_string().contains("/quic-v1")));
+}
+
+#[tokio::test]
+#[serial]
+async fn test_quic_only_nodes_exchange_decided_values() {
+    let node1_addr = format!(
+        "/ip4/{HOST_FOR_TEST}/udp/{}/quic-v1",
+        BASE_PORT_FOR_TEST + 30
+    );
+    let node2_addr = format!(
+        "/ip4/{HOST_FOR_TEST}/udp/{}/quic-v1",
+        BASE_PORT_FOR_TEST + 31
+    );
+    let config1 = Config::new(node1_addr.clone(), node2_addr.clone())
+        .with_announce_address(node1_addr.clone())
+        .with_transports(false, true);
+    let config2 = Config::new(node2_addr.clone(), node1_addr.clone())
+        .with_announce_address(node2_addr.clone())
+        .with_transports(false, true);
+    assert!(config1.validate().is_ok());
+    assert!(config2.validate().is_ok());
+
+    let (system_tx1, _system_rx1) = mpsc::channel::<SystemMessage>(100);
+    let (system_tx2, mut system_rx2) = mpsc::channel::<SystemMessage>(100);
+    let mut validator = SnapchainGossip::create(
+        Keypair::generate(),
+        &config1,
+        system_tx1,
+        false,
+        FarcasterNetwork::Devnet,
+        statsd_client(),
+    )
+    .await
+    .unwrap();
+    let mut read_node = SnapchainGossip::create(
+        Keypair::generate(),
+        &config2,
+        system_tx2,
+        true,
+        FarcasterNetwork::Devnet,
+        statsd_client(),
+    )
+    .await
+    .unwrap();
+    let validator_tx = validator.tx.clone();
+    let read_node_tx = read_node.tx.clone();
+    tokio::spawn(async move {
+        validator.start().await;
+    });
+    tokio::spawn(async move {
+        read_node.start().await;
+    });
+    read_node_tx
+        .send(GossipEvent::SubscribeToDecidedValuesTopic())
+        .await
+        .unwrap();
+
+    let decided_value = proto::DecidedValue {
+        value: Some(proto::decided_value::Value::Shard(
+            test_helper::default_shard_chunk(),
+        )),
+    };
+    // Keep publishing until the mesh has formed over QUIC and the read node gets the block
+    let deadline = time::Instant::now() + Duration::from_secs(10);
+    let mut interval = time::interval(Duration::from_millis(500));
+    loop {
+        select! {
+            _ = interval.tick() => {
+                assert!(time::Instant::now() < deadline, "decided value wasn't received");
+                validator_tx
+                    .send(GossipEvent::BroadcastDecidedValue(decided_value.clone()))
+                    .await
+                    .unwrap();
+            }
+            Some(message) = system_rx2.recv() => {
+                if let SystemMessage::DecidedValueForReadNode(received) = message {
+                    assert_eq!(received, decided_value);
+                    break;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,283 @@
+use crate::core::types::{Proposal, Vote};
+use crate::core::validations::message::{validate_message, validate_message_signature};
+use crate::proto::{self, consensus_message, gossip_message, mempool_message, FarcasterNetwork};
+use crate::storage::store::account::message_bytes_decode;
+use libp2p::gossipsub::{self, MessageAcceptance};
+use libp2p::identity::ed25519::PublicKey;
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+// A single invalid message is enough to drop a peer below the gossip threshold, the penalty decays
+// as the peer goes back to sending valid messages
+const INVALID_MESSAGE_DELIVERIES_WEIGHT: f64 = -100.0;
+
+/// How much of an inbound gossip message is checked before gossipsub forwards it to the rest of the
+/// mesh. Messages that fail are neither forwarded nor handed to the node, and count against the
+/// peer that sent them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GossipValidation {
+    // Messages are forwarded as soon as they're received, and only checked once they're used
+    #[default]
+    None,
+    // The signatures on user messages, votes and proposals are checked
+    Signature,
+    // User messages also go through the validations that don't need the node's state
+    Full,
+}
+
+/// Scores peers on the topics that carry signed messages, so gossipsub penalizes the ones sending
+/// messages that fail validation. Only invalid messages count, quiet topics and peers sharing an
+/// address aren't penalized.
+pub fn peer_score_params(topics: &[&str]) -> gossipsub::PeerScoreParams {
+    let mut params = gossipsub::PeerScoreParams {
+        ip_colocation_factor_weight: 0.0,
+        ..Default::default()
+    };
+    for topic in topics {
+        let topic_params = gossipsub::TopicScoreParams {
+            invalid_message_deliveries_weight: INVALID_MESSAGE_DELIVERIES_WEIGHT,
+            mesh_message_deliveries_weight: 0.0,
+            mesh_failure_penalty_weight: 0.0,
+            ..Default::default()
+        };
+        params
+            .topics
+            .insert(gossipsub::IdentTopic::new(*topic).hash(), topic_params);
+    }
+    params
+}
+
+fn verify_ed25519(key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    match PublicKey::try_from_bytes(key) {
+        Ok(public_key) => public_key.verify(message, signature),
+        Err(_) => false,
+    }
+}
+
+fn validate_consensus_message(message: &proto::ConsensusMessage) -> bool {
+    match &message.consensus_message {
+        Some(consensus_message::ConsensusMessage::Vote(vote)) => {
+            // Checked first, building the vote panics on them
+            let known_type = proto::VoteType::try_from(vote.r#type).is_ok();
+            if !known_type || vote.height.is_none() {
+                return false;
+            }
+            let sign_bytes = Vote::from_proto(vote.clone()).to_sign_bytes();
+            verify_ed25519(&vote.voter, &sign_bytes, &message.signature)
+        }
+        Some(consensus_message::ConsensusMessage::Proposal(proposal)) => {
+            if proposal.height.is_none() || proposal.value.is_none() {
+                return false;
+            }
+            let sign_bytes = Proposal::from_proto(proposal.clone()).to_sign_bytes();
+            verify_ed25519(&proposal.proposer, &sign_bytes, &message.signature)
+        }
+        None => false,
+    }
+}
+
+fn validate_mempool_message(
+    message: &proto::MempoolMessage,
+    validation: GossipValidation,
+    network: FarcasterNetwork,
+) -> bool {
+    match &message.mempool_message {
+        Some(mempool_message::MempoolMessage::UserMessage(message)) => match validation {
+            GossipValidation::None => true,
+            GossipValidation::Signature => validate_message_signature(message, network).is_ok(),
+            GossipValidation::Full => validate_message(message, network).is_ok(),
+        },
+        None => false,
+    }
+}
+
+/// Whether gossipsub should forward the message. Messages without a signature of their own, like
+/// statuses and contact info, are accepted without being checked.
+pub fn validate_gossip_message(
+    data: &[u8],
+    validation: GossipValidation,
+    network: FarcasterNetwork,
+) -> MessageAcceptance {
+    if validation == GossipValidation::None {
+        return MessageAcceptance::Accept;
+    }
+    let valid = match proto::GossipMessage::decode(data) {
+        Ok(message) => match &message.gossip_message {
+            Some(gossip_message::GossipMessage::Consensus(message)) => {
+                validate_consensus_message(message)
+            }
+            Some(gossip_message::GossipMessage::MempoolMessage(message)) => {
+                validate_mempool_message(message, validation, network)
+            }
+            Some(_) => true,
+            None => false,
+        },
+        Err(_) => false,
+    };
+    if valid {
+        MessageAcceptance::Accept
+    } else {
+        MessageAcceptance::Reject
+    }
+}
+
+/// Whether the message is a mempool message older than the ttl, going by the timestamp of the user
+/// message it carries. Consensus messages, blocks and everything else never expire.
+pub fn is_expired_mempool_message(data: &[u8], ttl: Duration, farcaster_time: u64) -> bool {
+    let Ok(message) = proto::GossipMessage::decode(data) else {
+        return false;
+    };
+    let Some(gossip_message::GossipMessage::MempoolMessage(proto::MempoolMessage {
+        mempool_message: Some(mempool_message::MempoolMessage::UserMessage(mut message)),
+    })) = message.gossip_message
+    else {
+        return false;
+    };
+    message_bytes_decode(&mut message);
+    match &message.data {
+        Some(data) => farcaster_time.saturating_sub(data.timestamp as u64) > ttl.as_secs(),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::factory::messages_factory;
+    use libp2p::identity::ed25519::Keypair;
+
+    fn mempool_gossip(message: proto::Message) -> Vec<u8> {
+        proto::GossipMessage {
+            gossip_message: Some(gossip_message::GossipMessage::MempoolMessage(
+                proto::MempoolMessage {
+                    mempool_message: Some(mempool_message::MempoolMessage::UserMessage(message)),
+                },
+            )),
+        }
+        .encode_to_vec()
+    }
+
+    fn vote_gossip(keypair: &Keypair, signer: &Keypair) -> Vec<u8> {
+        let vote = proto::Vote {
+            r#type: proto::VoteType::Prevote as i32,
+            height: Some(proto::Height {
+                shard_index: 1,
+                block_number: 10,
+            }),
+            round: 0,
+            value: None,
+            voter: keypair.public().to_bytes().to_vec(),
+        };
+        let signature = signer.sign(&Vote::from_proto(vote.clone()).to_sign_bytes());
+        proto::GossipMessage {
+            gossip_message: Some(gossip_message::GossipMessage::Consensus(
+                proto::ConsensusMessage {
+                    consensus_message: Some(consensus_message::ConsensusMessage::Vote(vote)),
+                    signature,
+                },
+            )),
+        }
+        .encode_to_vec()
+    }
+
+    #[test]
+    fn test_validates_signatures() {
+        let network = FarcasterNetwork::Devnet;
+        let cast = messages_factory::casts::create_cast_add(1234, "test", None, None);
+        let mut forged = cast.clone();
+        forged.signature[0] ^= 1;
+
+        for validation in [GossipValidation::Signature, GossipValidation::Full] {
+            assert_eq!(
+                validate_gossip_message(&mempool_gossip(cast.clone()), validation, network),
+                MessageAcceptance::Accept
+            );
+            assert_eq!(
+                validate_gossip_message(&mempool_gossip(forged.clone()), validation, network),
+                MessageAcceptance::Reject
+            );
+        }
+        // Nothing is checked without validation
+        assert_eq!(
+            validate_gossip_message(&mempool_gossip(forged), GossipValidation::None, network),
+            MessageAcceptance::Accept
+        );
+
+        let voter = Keypair::generate();
+        assert_eq!(
+            validate_gossip_message(
+                &vote_gossip(&voter, &voter),
+                GossipValidation::Signature,
+                network
+            ),
+            MessageAcceptance::Accept
+        );
+        assert_eq!(
+            validate_gossip_message(
+                &vote_gossip(&voter, &Keypair::generate()),
+                GossipValidation::Signature,
+                network
+            ),
+            MessageAcceptance::Reject
+        );
+        assert_eq!(
+            validate_gossip_message(&[0xff; 8], GossipValidation::Signature, network),
+            MessageAcceptance::Reject
+        );
+    }
+
+    #[test]
+    fn test_expired_mempool_messages() {
+        let ttl = Duration::from_secs(60);
+        let now: u32 = 100_000;
+        let fresh = messages_factory::casts::create_cast_add(1234, "test", Some(now - 10), None);
+        let stale = messages_factory::casts::create_cast_add(1234, "test", Some(now - 61), None);
+        // Timestamps slightly ahead of ours aren't expired either
+        let ahead = messages_factory::casts::create_cast_add(1234, "test", Some(now + 5), None);
+
+        assert!(!is_expired_mempool_message(
+            &mempool_gossip(fresh),
+            ttl,
+            now as u64
+        ));
+        assert!(is_expired_mempool_message(
+            &mempool_gossip(stale),
+            ttl,
+            now as u64
+        ));
+        assert!(!is_expired_mempool_message(
+            &mempool_gossip(ahead),
+            ttl,
+            now as u64
+        ));
+
+        // Consensus messages have no ttl, however old
+        let voter = Keypair::generate();
+        assert!(!is_expired_mempool_message(
+            &vote_gossip(&voter, &voter),
+            ttl,
+            u64::MAX
+        ));
+    }
+
+    #[test]
+    fn test_full_validation() {
+        let network = FarcasterNetwork::Devnet;
+        // Signed correctly, but too long for a cast
+        let cast = messages_factory::casts::create_cast_add(1234, &"a".repeat(1024), None, None);
+        assert_eq!(
+            validate_gossip_message(
+                &mempool_gossip(cast.clone()),
+                GossipValidation::Signature,
+                network
+            ),
+            MessageAcceptance::Accept
+        );
+        assert_eq!(
+            validate_gossip_message(&mempool_gossip(cast), GossipValidation::Full, network),
+            MessageAcceptance::Reject
+        );
+    }
+}
@@ -22,8 +22,10 @@ use crate::proto::{
     reaction_request, reactions_by_target_request, Protocol,
 };
 use crate::storage::store::account::message_decode;
+use crate::utils::latency_histograms::LatencyHistograms;
 
 use super::server::MyHubService;
+use super::submission_sequence::SUBMISSION_SEQUENCE_HEADER;
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Config {
@@ -301,6 +303,11 @@ pub struct IdRequest {
     pub hash: String,
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MessageByHashRequest {
+    pub hash: String,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct InfoRequest {} // Doesn't take dbstats not sure if issue
 
@@ -315,6 +322,42 @@ pub struct InfoResponse {
     pub version: String,
     #[serde(rename = "peer_id")]
     pub peer_id: String,
+    #[serde(
+        rename = "onchainEventsStatus",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub onchain_events_status: Option<OnchainEventsStatus>,
+    #[serde(rename = "readReplicaStatus", skip_serializing_if = "Option::is_none")]
+    pub read_replica_status: Option<ReadReplicaStatus>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ReadReplicaShardStatus {
+    #[serde(rename = "shardId")]
+    pub shard_id: u32,
+    #[serde(rename = "catchUpLagMs")]
+    pub catch_up_lag_ms: u64,
+    #[serde(rename = "catchUpError")]
+    pub catch_up_error: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ReadReplicaStatus {
+    #[serde(rename = "shardStatuses")]
+    pub shard_statuses: Vec<ReadReplicaShardStatus>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OnchainEventsStatus {
+    pub halted: bool,
+    #[serde(rename = "haltedAtBlock")]
+    pub halted_at_block: u64,
+    #[serde(rename = "reorgDepth")]
+    pub reorg_depth: u64,
+    pub failed: bool,
+    pub failure: String,
+    #[serde(rename = "pendingEvents")]
+    pub pending_events: u64,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -343,6 +386,9 @@ pub struct ShardInfo {
     pub block_delay: u64,
     #[serde(rename = "mempoolSize")]
     pub mempool_size: u64,
+    #[serde(rename = "shardRoot", with = "serdehex")]
+    pub shard_root: Vec<u8>,
+    pub role: String,
 }
 
 #[allow(non_snake_case)]
@@ -422,6 +468,49 @@ impl FidRequest {
             page_size: self.page_size.or(self.pageSize),
             page_token: self.page_token.or(self.pageToken),
             reverse: self.reverse,
+            fields: None,
+        }
+    }
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct VerificationsByFidRequest {
+    pub fid: u64,
+    // All protocols when left out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<Protocol>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page_size: Option<u32>,
+    #[serde(
+        default,
+        with = "serdebase64opt",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub page_token: Option<Vec<u8>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reverse: Option<bool>,
+
+    // For backwards compatibility
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pageSize: Option<u32>,
+    #[serde(
+        default,
+        with = "serdebase64opt",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub pageToken: Option<Vec<u8>>,
+}
+
+impl VerificationsByFidRequest {
+    pub fn to_proto(self) -> proto::VerificationsByFidRequest {
+        proto::VerificationsByFidRequest {
+            fid: self.fid,
+            protocol: self.protocol.map(|protocol| protocol as i32),
+            page_size: self.page_size.or(self.pageSize),
+            page_token: self.page_token.or(self.pageToken),
+            reverse: self.reverse,
+            fields: None,
         }
     }
 }
@@ -469,6 +558,7 @@ impl FidTimestampRequest {
             start_timestamp: self.start_timestamp.or(self.startTimestamp),
             stop_timestamp: self.stop_timestamp.or(self.stopTimestamp),
             reverse: self.reverse,
+            fields: None,
         }
     }
 }
@@ -565,6 +655,7 @@ impl ReactionsByFidRequest {
             page_size: self.page_size.or(self.pageSize),
             page_token: self.page_token.or(self.pageToken),
             reverse: self.reverse,
+            fields: None,
         }
     }
 }
@@ -704,6 +795,7 @@ impl LinksByFidRequest {
             page_size: self.page_size.or(self.pageSize),
             page_token: self.page_token.or(self.pageToken),
             reverse: self.reverse,
+            fields: None,
         }
     }
 }
@@ -823,6 +915,7 @@ pub enum OnChainEventType {
     EVENT_TYPE_SIGNER_MIGRATED = 2,
     EVENT_TYPE_ID_REGISTER = 3,
     EVENT_TYPE_STORAGE_RENT = 4,
+    EVENT_TYPE_VALIDATOR_STAKE = 5,
 }
 #[allow(non_camel_case_types)]
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -881,6 +974,13 @@ pub struct StorageRentEventBody {
     pub expiry: u32,
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ValidatorStakeEventBody {
+    #[serde(with = "serdehex", rename = "validatorPublicKey")]
+    pub validator_public_key: Vec<u8>,
+    pub stake: u64,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct OnChainEvent {
     pub r#type: OnChainEventType,
@@ -914,6 +1014,11 @@ pub struct OnChainEvent {
         skip_serializing_if = "Option::is_none"
     )]
     pub storage_rent_event_body: Option<StorageRentEventBody>,
+    #[serde(
+        rename = "validatorStakeEventBody",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub validator_stake_event_body: Option<ValidatorStakeEventBody>,
     #[serde(rename = "txIndex")]
     pub tx_index: u32,
     pub version: u32,
@@ -1128,6 +1233,10 @@ impl EventRequest {
 pub struct ValidationResult {
     pub valid: bool,
     pub message: Option<Message>,
+    #[serde(rename = "errorCode", skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<String>,
+    #[serde(rename = "errorMessage", skip_serializing_if = "Option::is_none")]
+    pub error_message: Option<String>,
 }
 
 // Common error response
@@ -1155,6 +1264,29 @@ fn map_get_info_response_to_json_info_response(
         num_shards: info_response.num_shards,
         peer_id: info_response.peer_id,
         version: info_response.version,
+        onchain_events_status: info_response.onchain_events_status.map(|status| {
+            OnchainEventsStatus {
+                halted: status.halted,
+                halted_at_block: status.halted_at_block,
+                reorg_depth: status.reorg_depth,
+                failed: status.failed,
+                failure: status.failure,
+                pending_events: status.pending_events,
+            }
+        }),
+        read_replica_status: info_response
+            .read_replica_status
+            .map(|status| ReadReplicaStatus {
+                shard_statuses: status
+                    .shard_statuses
+                    .into_iter()
+                    .map(|shard| ReadReplicaShardStatus {
+                        shard_id: shard.shard_id,
+                        catch_up_lag_ms: shard.catch_up_lag_ms,
+                        catch_up_error: shard.catch_up_error,
+                    })
+                    .collect(),
+            }),
         shard_infos: info_response
             .shard_infos
             .iter()
@@ -1166,6 +1298,8 @@ fn map_get_info_response_to_json_info_response(
                 approx_size: shard_info.approx_size,
                 block_delay: shard_info.block_delay,
                 mempool_size: shard_info.mempool_size,
+                shard_root: shard_info.shard_root.clone(),
+                role: shard_info.role().as_str_name().to_string(),
             })
             .collect(),
     })
@@ -1764,6 +1898,7 @@ fn map_proto_on_chain_event_to_json_on_chain_event(
     let mut signer_migrated_event_body: Option<SignerMigratedEventBody> = None;
     let mut id_register_event_body: Option<IdRegisterEventBody> = None;
     let mut storage_rent_event_body: Option<StorageRentEventBody> = None;
+    let mut validator_stake_event_body: Option<ValidatorStakeEventBody> = None;
     match &onchain_event.body {
         None => {}
         Some(on_chain_event::Body::SignerEventBody(body)) => {
@@ -1805,6 +1940,12 @@ fn map_proto_on_chain_event_to_json_on_chain_event(
                 expiry: body.expiry,
             });
         }
+        Some(on_chain_event::Body::ValidatorStakeEventBody(body)) => {
+            validator_stake_event_body = Some(ValidatorStakeEventBody {
+                validator_public_key: body.validator_public_key.clone(),
+                stake: body.stake,
+            });
+        }
     }
     Ok(OnChainEvent {
         r#type: match onchain_event.r#type {
@@ -1812,6 +1953,7 @@ fn map_proto_on_chain_event_to_json_on_chain_event(
             2 => OnChainEventType::EVENT_TYPE_SIGNER_MIGRATED,
             3 => OnChainEventType::EVENT_TYPE_ID_REGISTER,
             4 => OnChainEventType::EVENT_TYPE_STORAGE_RENT,
+            5 => OnChainEventType::EVENT_TYPE_VALIDATOR_STAKE,
             _ => OnChainEventType::EVENT_TYPE_NONE,
         },
         chain_id: onchain_event.chain_id,
@@ -1827,6 +1969,7 @@ fn map_proto_on_chain_event_to_json_on_chain_event(
         signer_migrated_event_body,
         id_register_event_body,
         storage_rent_event_body,
+        validator_stake_event_body,
     })
 }
 
@@ -1899,6 +2042,8 @@ fn map_proto_hub_event_to_json_hub_event(
                 reason: body.reason.clone(),
             });
         }
+        // Only sent to subscribers, it's never stored
+        Some(hub_event::Body::DrainingBody(_)) => {}
     }
 
     Ok(HubEvent {
@@ -1926,6 +2071,10 @@ fn map_proto_hub_event_to_json_hub_event(
 pub trait HubHttpService {
     async fn get_info(&self, req: InfoRequest) -> Result<InfoResponse, ErrorResponse>;
     async fn get_fids(&self, req: GetFidsRequest) -> Result<GetFidsResponse, ErrorResponse>;
+    async fn get_message_by_hash(
+        &self,
+        req: MessageByHashRequest,
+    ) -> Result<Message, ErrorResponse>;
     async fn get_cast_by_id(&self, req: IdRequest) -> Result<Message, ErrorResponse>;
     async fn get_casts_by_fid(
         &self,
@@ -1982,7 +2131,7 @@ pub trait HubHttpService {
     ) -> Result<Message, ErrorResponse>;
     async fn get_verifications_by_fid(
         &self,
-        req: FidRequest,
+        req: VerificationsByFidRequest,
     ) -> Result<PagedResponse, ErrorResponse>;
     async fn get_on_chain_signers_by_fid(
         &self,
@@ -2032,6 +2181,30 @@ impl HubHttpService for HubHttpServiceImpl {
         })
     }
 
+    async fn get_message_by_hash(
+        &self,
+        req: MessageByHashRequest,
+    ) -> Result<Message, ErrorResponse> {
+        let hash = hex::decode(&req.hash.replace("0x", "")).map_err(|e| ErrorResponse {
+            error: "Invalid hash".to_string(),
+            error_detail: Some(e.to_string()),
+        })?;
+
+        let service = &self.service;
+        let response = service
+            .get_message_by_hash(tonic::Request::<proto::MessageByHashRequest>::new(
+                proto::MessageByHashRequest { hash },
+            ))
+            .await
+            .map_err(|e| ErrorResponse {
+                error: "Failed to get message".to_string(),
+                error_detail: Some(e.to_string()),
+            })?;
+
+        let message = response.into_inner();
+        return map_proto_message_to_json_message(message);
+    }
+
     async fn get_cast_by_id(&self, req: IdRequest) -> Result<Message, ErrorResponse> {
         let fid = req.fid.parse::<u64>().map_err(|e| ErrorResponse {
             error: "Invalid fid".to_string(),
@@ -2486,6 +2659,8 @@ impl HubHttpService for HubHttpServiceImpl {
             message: Some(map_proto_message_to_json_message(
                 proto_resp.message.unwrap(),
             )?),
+            error_code: Some(proto_resp.error_code).filter(|code| !code.is_empty()),
+            error_message: Some(proto_resp.error_message).filter(|message| !message.is_empty()),
         });
     }
 
@@ -2514,6 +2689,23 @@ impl HubHttpService for HubHttpServiceImpl {
             }
         };
 
+        if let Some(sequence) = headers.get(SUBMISSION_SEQUENCE_HEADER) {
+            match sequence.to_str() {
+                Err(err) => {
+                    return Err(ErrorResponse {
+                        error: "Invalid submission sequence header".to_string(),
+                        error_detail: Some(err.to_string()),
+                    })
+                }
+                Ok(sequence) => {
+                    grpc_req.metadata_mut().append(
+                        SUBMISSION_SEQUENCE_HEADER,
+                        MetadataValue::from_str(sequence).unwrap(),
+                    );
+                }
+            }
+        };
+
         let response = service
             .submit_message(grpc_req)
             .await
@@ -2528,7 +2720,7 @@ impl HubHttpService for HubHttpServiceImpl {
     /// GET /v1/verificationsByFid
     async fn get_verifications_by_fid(
         &self,
-        req: FidRequest,
+        req: VerificationsByFidRequest,
     ) -> Result<PagedResponse, ErrorResponse> {
         let service = &self.service;
         let grpc_req = tonic::Request::new(req.to_proto());
@@ -2638,15 +2830,26 @@ impl HubHttpService for HubHttpServiceImpl {
 // Router implementation
 pub struct Router {
     service: Arc<HubHttpServiceImpl>,
+    latency_histograms: Option<Arc<LatencyHistograms>>,
 }
 
 impl Router {
     pub fn new(service: HubHttpServiceImpl) -> Self {
         Self {
             service: Arc::new(service),
+            latency_histograms: None,
         }
     }
 
+    // Served on /metrics, which isn't found without them
+    pub fn with_latency_histograms(
+        mut self,
+        latency_histograms: Option<Arc<LatencyHistograms>>,
+    ) -> Self {
+        self.latency_histograms = latency_histograms;
+        self
+    }
+
     pub async fn handle(
         &self,
         req: Request<hyper::body::Incoming>,
@@ -2665,6 +2868,12 @@ impl Router {
                 })
                 .await
             }
+            (&Method::GET, "/v1/messageByHash") => {
+                self.handle_request::<MessageByHashRequest, Message, _>(req, |service, req| {
+                    Box::pin(async move { service.get_message_by_hash(req).await })
+                })
+                .await
+            }
             (&Method::GET, "/v1/castById") => {
                 self.handle_request::<IdRequest, Message, _>(req, |service, req| {
                     Box::pin(async move { service.get_cast_by_id(req).await })
@@ -2792,9 +3001,12 @@ impl Router {
             }
             // Missing address
             (&Method::GET, "/v1/verificationsByFid") => {
-                self.handle_request::<FidRequest, PagedResponse, _>(req, |service, req| {
-                    Box::pin(async move { service.get_verifications_by_fid(req).await })
-                })
+                self.handle_request::<VerificationsByFidRequest, PagedResponse, _>(
+                    req,
+                    |service, req| {
+                        Box::pin(async move { service.get_verifications_by_fid(req).await })
+                    },
+                )
                 .await
             }
             // Missing signer
@@ -2825,6 +3037,14 @@ impl Router {
                 })
                 .await
             }
+            (&Method::GET, "/metrics") if self.latency_histograms.is_some() => {
+                let encoded = self.latency_histograms.as_ref().unwrap().encode();
+                Ok(Response::builder()
+                    .status(StatusCode::OK)
+                    .header("content-type", "text/plain; version=0.0.4")
+                    .body(Full::new(Bytes::from(encoded)).boxed())
+                    .unwrap())
+            }
             _ => Ok(Response::builder()
                 .status(StatusCode::NOT_FOUND)
                 .body(Full::new(Bytes::from("Not Found")).boxed())
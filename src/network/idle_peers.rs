@@ -0,0 +1,110 @@
+use libp2p::PeerId;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+/// Tracks when each inbound peer last sent us something useful, i.e. a gossip message we acted on
+/// or a sync request or response. Peers we dialed aren't tracked, we chose to connect to those.
+pub struct IdlePeers {
+    idle_timeout: Duration,
+    allowlist: HashSet<PeerId>,
+    last_useful: HashMap<PeerId, Instant>,
+}
+
+impl IdlePeers {
+    pub fn new(idle_timeout: Duration, allowlist: HashSet<PeerId>) -> Self {
+        IdlePeers {
+            idle_timeout,
+            allowlist,
+            last_useful: HashMap::new(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.idle_timeout.is_zero()
+    }
+
+    pub fn connected(&mut self, peer_id: PeerId, now: Instant) {
+        if !self.allowlist.contains(&peer_id) {
+            // A peer gets the full timeout to start being useful
+            self.last_useful.entry(peer_id).or_insert(now);
+        }
+    }
+
+    pub fn disconnected(&mut self, peer_id: &PeerId) {
+        self.last_useful.remove(peer_id);
+    }
+
+    pub fn record_useful(&mut self, peer_id: &PeerId, now: Instant) {
+        if let Some(last_useful) = self.last_useful.get_mut(peer_id) {
+            *last_useful = now;
+        }
+    }
+
+    /// Peers that have been idle for longer than the timeout. Mesh peers are skipped, gossipsub
+    /// relies on them to forward messages even when they have nothing new to send.
+    pub fn idle_peers(&self, mesh_peers: &HashSet<PeerId>, now: Instant) -> Vec<PeerId> {
+        if !self.enabled() {
+            return vec![];
+        }
+        self.last_useful
+            .iter()
+            .filter(|(peer_id, last_useful)| {
+                !mesh_peers.contains(peer_id)
+                    && now.duration_since(**last_useful) > self.idle_timeout
+            })
+            .map(|(peer_id, _)| *peer_id)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn test_idle_peers() {
+        let start = Instant::now();
+        let allowlisted = PeerId::random();
+        let mesh_peer = PeerId::random();
+        let useful_peer = PeerId::random();
+        let idle_peer = PeerId::random();
+        let mut idle_peers = IdlePeers::new(TIMEOUT, HashSet::from([allowlisted]));
+        for peer_id in [allowlisted, mesh_peer, useful_peer, idle_peer] {
+            idle_peers.connected(peer_id, start);
+        }
+        let mesh_peers = HashSet::from([mesh_peer]);
+
+        // Nobody is idle before the timeout
+        assert!(idle_peers
+            .idle_peers(&mesh_peers, start + TIMEOUT)
+            .is_empty());
+
+        idle_peers.record_useful(&useful_peer, start + TIMEOUT);
+        let later = start + TIMEOUT + Duration::from_secs(1);
+        assert_eq!(idle_peers.idle_peers(&mesh_peers, later), vec![idle_peer]);
+
+        // Peers that left aren't reported
+        idle_peers.disconnected(&idle_peer);
+        assert!(idle_peers.idle_peers(&mesh_peers, later).is_empty());
+
+        // Mesh peers are evicted once they leave the mesh
+        assert_eq!(
+            idle_peers.idle_peers(&HashSet::new(), later),
+            vec![mesh_peer]
+        );
+    }
+
+    #[test]
+    fn test_disabled_with_zero_timeout() {
+        let start = Instant::now();
+        let peer_id = PeerId::random();
+        let mut idle_peers = IdlePeers::new(Duration::ZERO, HashSet::new());
+        idle_peers.connected(peer_id, start);
+        assert!(!idle_peers.enabled());
+        assert!(idle_peers
+            .idle_peers(&HashSet::new(), start + TIMEOUT)
+            .is_empty());
+    }
+}
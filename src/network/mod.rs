@@ -1,9 +1,28 @@
+pub mod admin_audit;
 pub mod admin_server;
+pub mod committed_messages;
+pub mod debug_server;
 pub mod gossip;
+pub mod gossip_compression;
+pub mod gossip_validation;
 pub mod http_server;
+pub mod idle_peers;
+pub mod peer_bans;
+pub mod peer_selection;
+pub mod proposer_stats;
+pub mod reflection;
+pub mod rpc_compression;
 pub mod rpc_extensions;
+pub mod rpc_timeout;
 pub mod server;
+pub mod shard_load;
+pub mod submission_sequence;
+pub mod subscriber_limit;
+pub mod sync_progress;
+pub mod vote_history;
 
+#[cfg(test)]
+mod debug_server_tests;
 #[cfg(test)]
 mod gossip_test;
 #[cfg(test)]
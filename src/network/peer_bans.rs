@@ -0,0 +1,220 @@
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::warn;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PeerBan {
+    pub peer_id: String,
+    // Unix time in milliseconds
+    pub banned_until: u64,
+    // Within the window that got the peer banned
+    pub violations: u32,
+}
+
+/// Bans peers that keep breaking the protocol, i.e. sending gossip messages that fail
+/// validation. A peer is banned once it commits max_violations within the violation window, and
+/// stays banned for the ban duration, after which its violations start counting from zero. With
+/// a bans file, bans are written to it as they change and reloaded on restart. Times are unix
+/// milliseconds so they mean the same thing across restarts.
+pub struct PeerBans {
+    max_violations: u32,
+    violation_window: Duration,
+    ban_duration: Duration,
+    bans_file: Option<PathBuf>,
+    violations: HashMap<PeerId, VecDeque<u64>>,
+    bans: HashMap<PeerId, PeerBan>,
+}
+
+impl PeerBans {
+    pub fn new(
+        max_violations: u32,
+        violation_window: Duration,
+        ban_duration: Duration,
+        bans_file: Option<PathBuf>,
+    ) -> Self {
+        let bans = bans_file.as_ref().map(Self::load).unwrap_or_default();
+        PeerBans {
+            max_violations,
+            violation_window,
+            ban_duration,
+            bans_file,
+            violations: HashMap::new(),
+            bans,
+        }
+    }
+
+    // A missing or unreadable file starts without bans, it only makes the node more lenient
+    fn load(path: &PathBuf) -> HashMap<PeerId, PeerBan> {
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return HashMap::new(),
+            Err(err) => {
+                warn!(path = ?path, "Unable to read peer bans: {}", err);
+                return HashMap::new();
+            }
+        };
+        let bans: Vec<PeerBan> = match serde_json::from_slice(&bytes) {
+            Ok(bans) => bans,
+            Err(err) => {
+                warn!(path = ?path, "Unable to parse peer bans: {}", err);
+                return HashMap::new();
+            }
+        };
+        bans.into_iter()
+            .filter_map(|ban| Some((ban.peer_id.parse().ok()?, ban)))
+            .collect()
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.bans_file else {
+            return;
+        };
+        let result = serde_json::to_vec_pretty(&self.bans.values().collect::<Vec<_>>())
+            .map_err(|err| err.to_string())
+            .and_then(|bytes| std::fs::write(path, bytes).map_err(|err| err.to_string()));
+        if let Err(err) = result {
+            warn!(path = ?path, "Unable to save peer bans: {}", err);
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.max_violations > 0
+    }
+
+    /// Records a protocol violation by the peer. Returns true when it gets the peer banned.
+    pub fn record_violation(&mut self, peer_id: PeerId, now: u64) -> bool {
+        if !self.enabled() || self.is_banned(&peer_id, now) {
+            return false;
+        }
+        let window_start = now.saturating_sub(self.violation_window.as_millis() as u64);
+        let violations = self.violations.entry(peer_id).or_default();
+        violations.push_back(now);
+        while violations.front().is_some_and(|at| *at < window_start) {
+            violations.pop_front();
+        }
+        if violations.len() < self.max_violations as usize {
+            return false;
+        }
+        let count = violations.len() as u32;
+        self.violations.remove(&peer_id);
+        self.bans.insert(
+            peer_id,
+            PeerBan {
+                peer_id: peer_id.to_string(),
+                banned_until: now + self.ban_duration.as_millis() as u64,
+                violations: count,
+            },
+        );
+        self.save();
+        true
+    }
+
+    pub fn is_banned(&self, peer_id: &PeerId, now: u64) -> bool {
+        self.bans
+            .get(peer_id)
+            .is_some_and(|ban| ban.banned_until > now)
+    }
+
+    /// The bans in effect, by peer id
+    pub fn bans(&self, now: u64) -> Vec<PeerBan> {
+        let mut bans: Vec<PeerBan> = self
+            .bans
+            .values()
+            .filter(|ban| ban.banned_until > now)
+            .cloned()
+            .collect();
+        bans.sort_by(|a, b| a.peer_id.cmp(&b.peer_id));
+        bans
+    }
+
+    /// Lifts the bans that ran out and forgets violations that fell out of the window
+    pub fn expire(&mut self, now: u64) {
+        let window_start = now.saturating_sub(self.violation_window.as_millis() as u64);
+        self.violations
+            .retain(|_, violations| violations.back().is_some_and(|at| *at >= window_start));
+        let count = self.bans.len();
+        self.bans.retain(|_, ban| ban.banned_until > now);
+        if self.bans.len() != count {
+            self.save();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(60);
+    const BAN: Duration = Duration::from_secs(3600);
+
+    #[test]
+    fn test_repeated_violations_ban_the_peer() {
+        let mut bans = PeerBans::new(3, WINDOW, BAN, None);
+        let peer_id = PeerId::random();
+        let other = PeerId::random();
+
+        assert!(!bans.record_violation(peer_id, 1_000));
+        assert!(!bans.record_violation(other, 1_000));
+        assert!(!bans.record_violation(peer_id, 2_000));
+        assert!(!bans.is_banned(&peer_id, 2_000));
+        assert!(bans.record_violation(peer_id, 3_000));
+        assert!(bans.is_banned(&peer_id, 3_000));
+        assert!(!bans.is_banned(&other, 3_000));
+        assert_eq!(
+            bans.bans(3_000),
+            vec![PeerBan {
+                peer_id: peer_id.to_string(),
+                banned_until: 3_000 + 3_600_000,
+                violations: 3,
+            }]
+        );
+
+        // Already banned
+        assert!(!bans.record_violation(peer_id, 4_000));
+
+        // The ban runs out, and the violations start over
+        let lifted = 3_000 + 3_600_000;
+        assert!(!bans.is_banned(&peer_id, lifted));
+        bans.expire(lifted);
+        assert!(bans.bans(lifted).is_empty());
+        assert!(!bans.record_violation(peer_id, lifted));
+    }
+
+    #[test]
+    fn test_violations_outside_window_are_forgotten() {
+        let mut bans = PeerBans::new(2, WINDOW, BAN, None);
+        let peer_id = PeerId::random();
+        assert!(!bans.record_violation(peer_id, 0));
+        assert!(!bans.record_violation(peer_id, 61_000));
+        assert!(bans.record_violation(peer_id, 62_000));
+
+        // Disabled
+        let mut bans = PeerBans::new(0, WINDOW, BAN, None);
+        for now in 0..10 {
+            assert!(!bans.record_violation(peer_id, now));
+        }
+        assert!(!bans.is_banned(&peer_id, 10));
+    }
+
+    #[test]
+    fn test_bans_persist_across_restarts() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("bans.json");
+        let peer_id = PeerId::random();
+
+        let mut bans = PeerBans::new(1, WINDOW, BAN, Some(path.clone()));
+        assert!(bans.record_violation(peer_id, 1_000));
+
+        let reloaded = PeerBans::new(1, WINDOW, BAN, Some(path.clone()));
+        assert!(reloaded.is_banned(&peer_id, 2_000));
+        assert_eq!(reloaded.bans(2_000), bans.bans(2_000));
+
+        // Expired bans are dropped from the file
+        bans.expire(1_000 + 3_600_000);
+        let reloaded = PeerBans::new(1, WINDOW, BAN, Some(path));
+        assert!(reloaded.bans(0).is_empty());
+    }
+}
@@ -0,0 +1,82 @@
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+/// Makes the gossip layer's own random choices of peers, e.g. the order bootstrap peers are
+/// redialed in. With a seed, the same inputs give the same choices on every run, which keeps test
+/// networks reproducible, and without one it's seeded from entropy. Candidates are sorted before
+/// being picked from, so the order of the sets they come from doesn't matter. libp2p's gossipsub
+/// picks its mesh, fanout and IWANT peers with its own rng, which this doesn't cover.
+pub struct PeerSelector {
+    rng: StdRng,
+}
+
+impl PeerSelector {
+    pub fn new(seed: Option<u64>) -> Self {
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        PeerSelector { rng }
+    }
+
+    /// All the candidates, in random order
+    pub fn shuffle<T: Ord>(&mut self, candidates: impl IntoIterator<Item = T>) -> Vec<T> {
+        let mut candidates: Vec<T> = candidates.into_iter().collect();
+        candidates.sort();
+        candidates.shuffle(&mut self.rng);
+        candidates
+    }
+
+    /// Up to count of the candidates, picked at random
+    pub fn choose<T: Ord>(
+        &mut self,
+        candidates: impl IntoIterator<Item = T>,
+        count: usize,
+    ) -> Vec<T> {
+        let mut chosen = self.shuffle(candidates);
+        chosen.truncate(count);
+        chosen
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::PeerId;
+    use std::collections::HashSet;
+
+    fn selections(selector: &mut PeerSelector, peers: &HashSet<PeerId>) -> Vec<Vec<PeerId>> {
+        (0..10)
+            .map(|i| {
+                if i % 2 == 0 {
+                    selector.shuffle(peers.iter().cloned())
+                } else {
+                    selector.choose(peers.iter().cloned(), 3)
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_same_seed_makes_the_same_choices() {
+        let peers: Vec<PeerId> = (0..20).map(|_| PeerId::random()).collect();
+        // Built separately, so the sets iterate in different orders
+        let peers_a: HashSet<PeerId> = peers.iter().cloned().collect();
+        let peers_b: HashSet<PeerId> = peers.iter().rev().cloned().collect();
+
+        let expected = selections(&mut PeerSelector::new(Some(42)), &peers_a);
+        assert_eq!(
+            selections(&mut PeerSelector::new(Some(42)), &peers_b),
+            expected
+        );
+        assert_eq!(expected[1].len(), 3);
+        assert_ne!(expected[0], expected[2]);
+
+        assert_ne!(
+            selections(&mut PeerSelector::new(Some(7)), &peers_a),
+            expected
+        );
+        assert_ne!(selections(&mut PeerSelector::new(None), &peers_a), expected);
+    }
+}
@@ -0,0 +1,150 @@
+use crate::core::types::SnapchainValidatorSet;
+use crate::proto::{ObservedVote, ProposerStats, VoteType};
+use std::collections::BTreeMap;
+
+/// Tallies who proposed each round of a range of committed heights, for the GetProposerStats rpc.
+/// The proposer isn't stored with a block, but validators take turns at each height and round, so
+/// it's known from the round the block was committed at. Every earlier round of the height didn't
+/// commit, the votes retained for the height tell whether its proposal was seen at all.
+#[derive(Default)]
+pub struct ProposerTally {
+    // By public key
+    stats: BTreeMap<Vec<u8>, ProposerStats>,
+    pub heights: u64,
+    pub heights_with_votes: u64,
+}
+
+impl ProposerTally {
+    // votes are the validators' verified votes for the height, None when they aren't retained
+    pub fn record_height(
+        &mut self,
+        validator_set: &SnapchainValidatorSet,
+        height: u64,
+        committed_round: u64,
+        votes: Option<&[ObservedVote]>,
+    ) {
+        self.heights += 1;
+        if votes.is_some() {
+            self.heights_with_votes += 1;
+        }
+        // Validators that never got a turn in the range show up with zeros
+        for validator in &validator_set.validators {
+            let public_key = validator.public_key.to_bytes().to_vec();
+            self.stats
+                .entry(public_key.clone())
+                .or_insert_with(|| ProposerStats {
+                    public_key,
+                    ..Default::default()
+                });
+        }
+
+        for round in 0..=committed_round {
+            let public_key = validator_set
+                .proposer(height, round)
+                .public_key
+                .to_bytes()
+                .to_vec();
+            let stats = self.stats.get_mut(&public_key).unwrap();
+            stats.proposed += 1;
+            if round == committed_round {
+                stats.committed += 1;
+                continue;
+            }
+            stats.timed_out += 1;
+            if let Some(votes) = votes {
+                // A proposal that reached the validators gets prevoted for
+                let prevoted = votes.iter().any(|vote| {
+                    vote.round == round as i64
+                        && vote.r#type == VoteType::Prevote as i32
+                        && vote.value.is_some()
+                });
+                if !prevoted {
+                    stats.timed_out_without_proposal += 1;
+                }
+            }
+        }
+    }
+
+    pub fn into_stats(self) -> Vec<ProposerStats> {
+        self.stats.into_values().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::types::{SnapchainShard, SnapchainValidator};
+    use crate::proto::ShardHash;
+    use libp2p::identity::ed25519::Keypair;
+
+    fn prevote(round: i64, value: Option<ShardHash>) -> ObservedVote {
+        ObservedVote {
+            r#type: VoteType::Prevote as i32,
+            round,
+            value,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_tally() {
+        let validator_set = SnapchainValidatorSet::new(
+            (0..3)
+                .map(|_| {
+                    SnapchainValidator::new(
+                        SnapchainShard::new(1),
+                        Keypair::generate().public(),
+                        None,
+                        0,
+                    )
+                })
+                .collect(),
+        );
+        let key = |height, round| {
+            validator_set
+                .proposer(height, round)
+                .public_key
+                .to_bytes()
+                .to_vec()
+        };
+
+        let mut tally = ProposerTally::default();
+        tally.record_height(&validator_set, 1, 0, None);
+        // Round 0 was proposed but didn't commit, round 1 had no proposal
+        tally.record_height(
+            &validator_set,
+            2,
+            2,
+            Some(&[
+                prevote(0, Some(ShardHash::default())),
+                prevote(1, None),
+                prevote(2, Some(ShardHash::default())),
+            ]),
+        );
+        assert_eq!(tally.heights, 2);
+        assert_eq!(tally.heights_with_votes, 1);
+
+        let stats = tally.into_stats();
+        assert_eq!(stats.len(), 3);
+        let stats_for = |public_key: Vec<u8>| {
+            stats
+                .iter()
+                .find(|stats| stats.public_key == public_key)
+                .unwrap()
+                .clone()
+        };
+        // Height 1 round 0 and height 2 round 2 are the same validator's turn
+        assert_eq!(key(1, 0), key(2, 2));
+        let first = stats_for(key(1, 0));
+        assert_eq!(
+            (first.proposed, first.committed, first.timed_out),
+            (2, 2, 0)
+        );
+        let second = stats_for(key(2, 0));
+        assert_eq!((second.proposed, second.timed_out), (1, 1));
+        assert_eq!(second.timed_out_without_proposal, 0);
+        let third = stats_for(key(2, 1));
+        assert_eq!((third.proposed, third.timed_out), (1, 1));
+        assert_eq!(third.timed_out_without_proposal, 1);
+    }
+}
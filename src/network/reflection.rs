@@ -0,0 +1,158 @@
+use crate::proto;
+use prost::Message;
+use prost_types::FileDescriptorSet;
+use serde::{Deserialize, Serialize};
+use tonic_reflection::server::{v1, v1alpha, Builder, Error};
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Config {
+    // Lets tools like grpcurl list and describe the rpcs without the proto files
+    pub enabled: bool,
+    // The admin service is only listed when this is set, its rpcs still need admin_rpc_auth
+    pub include_admin_service: bool,
+}
+
+// As the services are named in the protos, which have no package
+const HUB_SERVICE: &str = "HubService";
+const DEBUG_SERVICE: &str = "DebugService";
+const ADMIN_SERVICE: &str = "AdminService";
+// Nothing else imports it, so it can be left out whole
+const ADMIN_PROTO: &str = "admin_rpc.proto";
+
+// The protos reflection can describe. Without the admin service, its proto is left out too, or
+// clients could still look it up by name.
+fn file_descriptor_set(include_admin_service: bool) -> Result<FileDescriptorSet, Error> {
+    let mut file_descriptor_set = FileDescriptorSet::decode(proto::FILE_DESCRIPTOR_SET)?;
+    if !include_admin_service {
+        file_descriptor_set
+            .file
+            .retain(|file| file.name() != ADMIN_PROTO);
+    }
+    Ok(file_descriptor_set)
+}
+
+fn builder(
+    config: &Config,
+    debug_service_enabled: bool,
+    admin_service_enabled: bool,
+) -> Result<Builder<'static>, Error> {
+    let include_admin_service = admin_service_enabled && config.include_admin_service;
+    // Only the node's own services are listed, not the reflection service
+    let mut builder = Builder::configure()
+        .include_reflection_service(false)
+        .register_file_descriptor_set(file_descriptor_set(include_admin_service)?)
+        .with_service_name(HUB_SERVICE);
+    if debug_service_enabled {
+        builder = builder.with_service_name(DEBUG_SERVICE);
+    }
+    if include_admin_service {
+        builder = builder.with_service_name(ADMIN_SERVICE);
+    }
+    Ok(builder)
+}
+
+/// The reflection services for the rpc server, in both versions of the protocol since older
+/// clients only speak v1alpha. They list the services the server serves, the admin service only
+/// if configured to.
+pub fn reflection_services(
+    config: &Config,
+    debug_service_enabled: bool,
+    admin_service_enabled: bool,
+) -> Result<
+    (
+        v1::ServerReflectionServer<impl v1::ServerReflection>,
+        v1alpha::ServerReflectionServer<impl v1alpha::ServerReflection>,
+    ),
+    Error,
+> {
+    Ok((
+        builder(config, debug_service_enabled, admin_service_enabled)?.build_v1()?,
+        builder(config, debug_service_enabled, admin_service_enabled)?.build_v1alpha()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic_reflection::pb::v1::server_reflection_client::ServerReflectionClient;
+    use tonic_reflection::pb::v1::server_reflection_request::MessageRequest;
+    use tonic_reflection::pb::v1::server_reflection_response::MessageResponse;
+    use tonic_reflection::pb::v1::ServerReflectionRequest;
+
+    async fn list_services(config: &Config, debug_service_enabled: bool) -> Vec<String> {
+        let (reflection_v1, reflection_v1alpha) =
+            reflection_services(config, debug_service_enabled, true).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(reflection_v1)
+                .add_service(reflection_v1alpha)
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+
+        let mut client = ServerReflectionClient::connect(format!("http://{}", addr))
+            .await
+            .unwrap();
+        let request = ServerReflectionRequest {
+            host: "".to_string(),
+            message_request: Some(MessageRequest::ListServices("".to_string())),
+        };
+        let mut responses = client
+            .server_reflection_info(tokio_stream::once(request))
+            .await
+            .unwrap()
+            .into_inner();
+        match responses.message().await.unwrap().unwrap().message_response {
+            Some(MessageResponse::ListServicesResponse(response)) => {
+                let mut services: Vec<String> =
+                    response.service.into_iter().map(|s| s.name).collect();
+                services.sort();
+                services
+            }
+            other => panic!("Unexpected response: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lists_services() {
+        let config = Config {
+            enabled: true,
+            include_admin_service: false,
+        };
+        assert_eq!(
+            list_services(&config, true).await,
+            vec![DEBUG_SERVICE, HUB_SERVICE]
+        );
+        assert_eq!(list_services(&config, false).await, vec![HUB_SERVICE]);
+
+        let config = Config {
+            enabled: true,
+            include_admin_service: true,
+        };
+        assert_eq!(
+            list_services(&config, false).await,
+            vec![ADMIN_SERVICE, HUB_SERVICE]
+        );
+    }
+
+    #[test]
+    fn test_admin_proto_is_only_described_with_the_admin_service() {
+        let has_admin_proto = |include_admin_service| {
+            file_descriptor_set(include_admin_service)
+                .unwrap()
+                .file
+                .iter()
+                .any(|file| file.name() == ADMIN_PROTO)
+        };
+        assert!(has_admin_proto(true));
+        assert!(!has_admin_proto(false));
+        assert!(file_descriptor_set(false)
+            .unwrap()
+            .file
+            .iter()
+            .any(|file| file.name() == "rpc.proto"));
+    }
+}
@@ -0,0 +1,25 @@
+use crate::proto::hub_service_server::{HubService, HubServiceServer};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tonic::codec::CompressionEncoding;
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Config {
+    // Gzips the responses of clients that accept it, as they say with grpc-accept-encoding, e.g.
+    // event subscribers on a constrained link. Other clients get them uncompressed either way.
+    pub gzip: bool,
+}
+
+/// The hub service, compressing responses as configured. tonic compresses every message of a
+/// response on its own, so events on the Subscribe and GetBlocks streams are each decodable as
+/// they arrive, with their ids and order untouched. Compressed requests are accepted alike.
+pub fn hub_service_server<T: HubService>(service: Arc<T>, config: &Config) -> HubServiceServer<T> {
+    let server = HubServiceServer::from_arc(service);
+    if config.gzip {
+        server
+            .send_compressed(CompressionEncoding::Gzip)
+            .accept_compressed(CompressionEncoding::Gzip)
+    } else {
+        server
+    }
+}
@@ -1,24 +1,52 @@
 use crate::core::error::HubError;
 use crate::proto;
 use crate::proto::{
-    CastsByParentRequest, FidRequest, FidTimestampRequest, LinksByFidRequest, ReactionsByFidRequest,
+    CastsByParentRequest, FidRequest, FidTimestampRequest, LinksByFidRequest,
+    OnChainEventsByFidRequest, ReactionsByFidRequest, VerificationsByFidRequest,
 };
 use crate::storage::db::PageOptions;
 use crate::storage::store::account::MessagesPage;
+use crate::utils::query_params::record_query_param;
 use base64::Engine;
 use std::collections::HashMap;
+use tonic::metadata::MetadataMap;
 use tonic::{Request, Response, Status};
 
+// Response header with the id of the request, for clients to reference when reporting issues
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// Identifies a single rpc in the logs, from the rpc through the mempool to the commit
+pub fn new_request_id() -> String {
+    hex::encode(rand::random::<[u8; 8]>())
+}
+
 // Extension traits to maps GRPC structs to internal structs
 pub trait AsMessagesResponse {
     fn as_response(&self) -> Result<Response<proto::MessagesResponse>, Status>;
+
+    fn as_projected_response(
+        &self,
+        fields: proto::MessageFields,
+    ) -> Result<Response<proto::MessagesResponse>, Status>;
 }
 
 impl AsMessagesResponse for Result<MessagesPage, HubError> {
     fn as_response(&self) -> Result<Response<proto::MessagesResponse>, Status> {
+        self.as_projected_response(proto::MessageFields::Full)
+    }
+
+    fn as_projected_response(
+        &self,
+        fields: proto::MessageFields,
+    ) -> Result<Response<proto::MessagesResponse>, Status> {
         match self {
             Ok(page) => Ok(Response::new(proto::MessagesResponse {
-                messages: page.messages.clone(),
+                messages: match fields {
+                    proto::MessageFields::Full => page.messages.clone(),
+                    proto::MessageFields::HashesOnly => {
+                        page.messages.iter().map(hash_only).collect()
+                    }
+                },
                 next_page_token: page.next_page_token.clone(),
             })),
             Err(err) => Err(Status::internal(err.to_string())),
@@ -26,6 +54,14 @@ impl AsMessagesResponse for Result<MessagesPage, HubError> {
     }
 }
 
+// The bodies and signatures are neither copied nor encoded into the response
+pub fn hash_only(message: &proto::Message) -> proto::Message {
+    proto::Message {
+        hash: message.hash.clone(),
+        ..Default::default()
+    }
+}
+
 pub trait AsSingleMessageResponse {
     fn as_response(&self) -> Result<Response<proto::Message>, Status>;
 }
@@ -60,6 +96,9 @@ fn page_options(
     page_token: Option<Vec<u8>>,
     reverse: Option<bool>,
 ) -> PageOptions {
+    if let Some(page_size) = page_size {
+        record_query_param("page_size", page_size as u64);
+    }
     let page_size = match page_size {
         Some(size) => Some(size as usize),
         None => None,
@@ -78,6 +117,12 @@ impl FidRequest {
     }
 }
 
+impl OnChainEventsByFidRequest {
+    pub fn page_options(&self) -> PageOptions {
+        page_options(self.page_size, self.page_token.clone(), self.reverse)
+    }
+}
+
 impl FidTimestampRequest {
     pub fn page_options(&self) -> PageOptions {
         page_options(self.page_size, self.page_token.clone(), self.reverse)
@@ -92,6 +137,15 @@ impl FidTimestampRequest {
             Some(ts) => Some(ts as u32),
             None => None,
         };
+        // Left out when unbounded
+        for (name, timestamp) in [
+            ("start_timestamp", start_timestamp),
+            ("stop_timestamp", stop_timestamp),
+        ] {
+            if let Some(timestamp) = timestamp {
+                record_query_param(name, timestamp as u64);
+            }
+        }
         (start_timestamp, stop_timestamp)
     }
 }
@@ -108,21 +162,46 @@ impl ReactionsByFidRequest {
     }
 }
 
+impl VerificationsByFidRequest {
+    pub fn page_options(&self) -> PageOptions {
+        page_options(self.page_size, self.page_token.clone(), self.reverse)
+    }
+}
+
 impl LinksByFidRequest {
     pub fn page_options(&self) -> PageOptions {
         page_options(self.page_size, self.page_token.clone(), self.reverse)
     }
 }
 
+// Users are given as user:password pairs separated by commas
+pub fn parse_rpc_auth(rpc_auth: &str) -> HashMap<String, String> {
+    let mut allowed_users = HashMap::new();
+    for auth in rpc_auth.split(",") {
+        let parts: Vec<&str> = auth.split(":").collect();
+        if parts.len() == 2 {
+            allowed_users.insert(parts[0].to_string(), parts[1].to_string());
+        }
+    }
+    allowed_users
+}
+
 pub fn authenticate_request<T>(
     request: &Request<T>,
     allowed_users: &HashMap<String, String>,
 ) -> Result<(), Status> {
+    authenticated_user(request.metadata(), allowed_users).map(|_| ())
+}
+
+/// The user the request's basic auth credentials belong to, none if no users are configured
+pub fn authenticated_user(
+    metadata_map: &MetadataMap,
+    allowed_users: &HashMap<String, String>,
+) -> Result<Option<String>, Status> {
     if allowed_users.is_empty() {
-        return Ok(());
+        return Ok(None);
     }
 
-    let metadata_map = request.metadata();
     if let Some(auth) = metadata_map.get("authorization") {
         let auth = auth
             .to_str()
@@ -148,7 +227,7 @@ pub fn authenticate_request<T>(
         }
         if let Some(password) = allowed_users.get(parts[0]) {
             if password == parts[1] {
-                Ok(())
+                Ok(Some(parts[0].to_string()))
             } else {
                 Err(Status::unauthenticated("invalid username or password"))
             }
@@ -0,0 +1,338 @@
+use crate::utils::deadline::with_deadline;
+use crate::utils::latency_histograms::Latency;
+use crate::utils::query_params::{with_query_params, QueryParams};
+use crate::utils::statsd_wrapper::StatsdClientWrapper;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tonic::body::BoxBody;
+use tonic::codegen::http;
+use tonic::Status;
+use tower::{Layer, Service};
+use tracing::warn;
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Config {
+    #[serde(with = "humantime_serde")]
+    pub read_timeout: Duration,
+    #[serde(with = "humantime_serde")]
+    pub submit_timeout: Duration,
+    #[serde(with = "humantime_serde")]
+    pub admin_timeout: Duration,
+    // Requests that take longer are logged along with their parameters, 0 turns it off
+    #[serde(with = "humantime_serde")]
+    pub read_slow_threshold: Duration,
+    #[serde(with = "humantime_serde")]
+    pub submit_slow_threshold: Duration,
+    #[serde(with = "humantime_serde")]
+    pub admin_slow_threshold: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        // Generous so that only runaway requests are cut off
+        Self {
+            read_timeout: Duration::from_secs(60),
+            submit_timeout: Duration::from_secs(30),
+            admin_timeout: Duration::from_secs(300),
+            read_slow_threshold: Duration::from_secs(1),
+            submit_slow_threshold: Duration::from_secs(1),
+            // Admin rpcs like compaction are expected to take a while
+            admin_slow_threshold: Duration::ZERO,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RpcCategory {
+    Read,
+    Submit,
+    Admin,
+}
+
+impl RpcCategory {
+    // Paths are of the form /{service}/{method}
+    pub fn from_path(path: &str) -> Self {
+        let mut parts = path.trim_start_matches('/').splitn(2, '/');
+        let service = parts.next().unwrap_or_default();
+        let method = parts.next().unwrap_or_default();
+        match (service, method) {
+            ("AdminService", _) => RpcCategory::Admin,
+            ("DebugService", _) => RpcCategory::Submit,
+            ("HubService", "SubmitMessage" | "SubmitBulkMessages") => RpcCategory::Submit,
+            _ => RpcCategory::Read,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RpcCategory::Read => "read",
+            RpcCategory::Submit => "submit",
+            RpcCategory::Admin => "admin",
+        }
+    }
+
+    // The method as service.method, unknown paths share one label so they can't grow the metrics
+    pub fn method_label(path: &str) -> String {
+        let mut parts = path.trim_start_matches('/').splitn(2, '/');
+        match (parts.next(), parts.next()) {
+            (Some(service @ ("HubService" | "AdminService" | "DebugService")), Some(method))
+                if !method.is_empty() && !method.contains('/') =>
+            {
+                format!("{}.{}", service, method)
+            }
+            _ => "unknown".to_string(),
+        }
+    }
+
+    fn timeout(&self, config: &Config) -> Duration {
+        match self {
+            RpcCategory::Read => config.read_timeout,
+            RpcCategory::Submit => config.submit_timeout,
+            RpcCategory::Admin => config.admin_timeout,
+        }
+    }
+
+    fn slow_threshold(&self, config: &Config) -> Option<Duration> {
+        let threshold = match self {
+            RpcCategory::Read => config.read_slow_threshold,
+            RpcCategory::Submit => config.submit_slow_threshold,
+            RpcCategory::Admin => config.admin_slow_threshold,
+        };
+        (!threshold.is_zero()).then_some(threshold)
+    }
+}
+
+// Logs the request if it took longer than its category's slow threshold
+fn report_slow_request(
+    config: &Config,
+    statsd_client: &StatsdClientWrapper,
+    path: &str,
+    category: RpcCategory,
+    elapsed: Duration,
+    params: &QueryParams,
+) -> bool {
+    match category.slow_threshold(config) {
+        Some(threshold) if elapsed > threshold => {
+            let params = params
+                .get()
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect::<Vec<_>>()
+                .join(" ");
+            warn!(
+                path,
+                category = category.as_str(),
+                duration = ?elapsed,
+                params,
+                "slow rpc request"
+            );
+            statsd_client.count(&format!("rpc.slow.{}", category.as_str()), 1);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Applies the configured timeout to each grpc request based on its category, and logs the ones
+/// slower than the category's slow threshold. Streaming responses are only bounded and timed
+/// until the stream is returned, not for the lifetime of the stream.
+#[derive(Clone)]
+pub struct RpcTimeoutLayer {
+    config: Config,
+    statsd_client: StatsdClientWrapper,
+}
+
+impl RpcTimeoutLayer {
+    pub fn new(config: Config, statsd_client: StatsdClientWrapper) -> Self {
+        Self {
+            config,
+            statsd_client,
+        }
+    }
+}
+
+impl<S> Layer<S> for RpcTimeoutLayer {
+    type Service = RpcTimeout<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RpcTimeout {
+            inner,
+            config: self.config.clone(),
+            statsd_client: self.statsd_client.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RpcTimeout<S> {
+    inner: S,
+    config: Config,
+    statsd_client: StatsdClientWrapper,
+}
+
+impl<S, ReqBody> Service<http::Request<ReqBody>> for RpcTimeout<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    ReqBody: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<ReqBody>) -> Self::Future {
+        // The readied service must be the one that's called, keep the clone for the next request
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let path = request.uri().path().to_string();
+        let category = RpcCategory::from_path(&path);
+        let timeout = category.timeout(&self.config);
+        let statsd_client = self.statsd_client.clone();
+        let config = self.config.clone();
+
+        Box::pin(async move {
+            let start = tokio::time::Instant::now();
+            let deadline = start + timeout;
+            let params = QueryParams::default();
+            // The deadline lets store iteration stop on its own, since dropping the handler
+            // future doesn't interrupt synchronous work
+            let result = tokio::time::timeout_at(
+                deadline,
+                with_deadline(
+                    deadline,
+                    with_query_params(params.clone(), inner.call(request)),
+                ),
+            )
+            .await;
+            statsd_client.time_latency(
+                Latency::Rpc,
+                None,
+                Some(("method", &RpcCategory::method_label(&path))),
+                start.elapsed().as_millis() as u64,
+            );
+
+            match result {
+                Ok(response) if tokio::time::Instant::now() < deadline => {
+                    report_slow_request(
+                        &config,
+                        &statsd_client,
+                        &path,
+                        category,
+                        start.elapsed(),
+                        &params,
+                    );
+                    response
+                }
+                _ => {
+                    warn!(path, timeout = ?timeout, "rpc request timed out");
+                    statsd_client.count(&format!("rpc.timeout.{}", category.as_str()), 1);
+                    Ok(Status::deadline_exceeded(format!(
+                        "request exceeded the {} rpc timeout of {:?}",
+                        category.as_str(),
+                        timeout
+                    ))
+                    .into_http())
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::query_params::record_query_param;
+
+    #[test]
+    fn test_rpc_category_from_path() {
+        assert_eq!(
+            RpcCategory::from_path("/HubService/SubmitMessage"),
+            RpcCategory::Submit
+        );
+        assert_eq!(
+            RpcCategory::from_path("/HubService/SubmitBulkMessages"),
+            RpcCategory::Submit
+        );
+        assert_eq!(
+            RpcCategory::from_path("/DebugService/SubmitMessage"),
+            RpcCategory::Submit
+        );
+        assert_eq!(
+            RpcCategory::from_path("/AdminService/FreezeShard"),
+            RpcCategory::Admin
+        );
+        assert_eq!(
+            RpcCategory::from_path("/HubService/GetCastsByFid"),
+            RpcCategory::Read
+        );
+        assert_eq!(RpcCategory::from_path("/unknown"), RpcCategory::Read);
+    }
+
+    #[test]
+    fn test_method_label() {
+        assert_eq!(
+            RpcCategory::method_label("/HubService/GetCastsByFid"),
+            "HubService.GetCastsByFid"
+        );
+        assert_eq!(
+            RpcCategory::method_label("/AdminService/FreezeShard"),
+            "AdminService.FreezeShard"
+        );
+        assert_eq!(RpcCategory::method_label("/OtherService/Method"), "unknown");
+        assert_eq!(RpcCategory::method_label("/HubService/"), "unknown");
+        assert_eq!(RpcCategory::method_label("/unknown"), "unknown");
+    }
+
+    #[tokio::test]
+    async fn test_reports_slow_requests() {
+        let config = Config::default();
+        let statsd_client = crate::storage::store::test_helper::statsd_client();
+
+        let params = QueryParams::default();
+        with_query_params(params.clone(), async {
+            record_query_param("fid", 1234);
+            record_query_param("page_size", 10);
+            // Only the first value is kept
+            record_query_param("fid", 5678);
+        })
+        .await;
+        assert_eq!(params.get(), vec![("fid", 1234), ("page_size", 10)]);
+        // Nothing is recorded outside of a request
+        record_query_param("fid", 1);
+
+        let path = "/HubService/GetCastsByFid";
+        assert!(report_slow_request(
+            &config,
+            &statsd_client,
+            path,
+            RpcCategory::Read,
+            Duration::from_secs(2),
+            &params
+        ));
+        assert!(!report_slow_request(
+            &config,
+            &statsd_client,
+            path,
+            RpcCategory::Read,
+            Duration::from_millis(100),
+            &params
+        ));
+        // Off for admin rpcs by default
+        assert!(!report_slow_request(
+            &config,
+            &statsd_client,
+            "/AdminService/FreezeShard",
+            RpcCategory::Admin,
+            Duration::from_secs(600),
+            &params
+        ));
+    }
+}
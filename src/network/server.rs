@@ -1,11 +1,35 @@
-use super::rpc_extensions::{authenticate_request, AsMessagesResponse, AsSingleMessageResponse};
-use crate::connectors::onchain_events::L1Client;
+use super::rpc_extensions::{
+    authenticate_request, hash_only, new_request_id, AsMessagesResponse, AsSingleMessageResponse,
+    REQUEST_ID_HEADER,
+};
+use crate::connectors::onchain_events::pause::PauseState;
+use crate::connectors::onchain_events::pending::PendingEvents;
+use crate::connectors::onchain_events::reorg::HaltState;
+use crate::connectors::onchain_events::retry::RetryQueue;
+use crate::connectors::onchain_events::{
+    L1Client, ID_REGISTRY, KEY_REGISTRY, OP_MAINNET_CHAIN_ID, STORAGE_REGISTRY,
+};
+use crate::consensus::validator::StoredValidatorSets;
+use crate::core::custom_network::farcaster_epoch;
 use crate::core::error::HubError;
+use crate::core::types::SnapchainValidatorSet;
 use crate::core::util::get_farcaster_time;
 use crate::core::validations;
 use crate::core::validations::verification::VerificationAddressClaim;
-use crate::mempool::mempool::{MempoolRequest, MempoolSource};
+use crate::mempool::admission::{FidAllowlist, MessageTypeAdmission, StateAdmissionRules};
+use crate::mempool::mempool::{MempoolRequest, MempoolSource, MIN_MESSAGES_PER_HOUR};
 use crate::mempool::routing;
+use crate::network::debug_server::SubmitValidator;
+use crate::network::proposer_stats::ProposerTally;
+use crate::network::shard_load::ShardLoad;
+use crate::network::submission_sequence::{
+    SubmissionSequenceError, SubmissionSequences, SUBMISSION_SEQUENCE_HEADER,
+};
+use crate::network::subscriber_limit::{SubscriberLimit, SubscriberPermit};
+use crate::network::sync_progress::SyncProgress;
+use crate::network::vote_history::{RecordedVote, VoteHistory};
+use crate::node::read_replica::CatchUpStatus;
+use crate::node::shutdown::ShutdownSignal;
 use crate::proto::hub_service_server::HubService;
 use crate::proto::link_body;
 use crate::proto::links_by_target_request;
@@ -17,13 +41,18 @@ use crate::proto::CastsByParentRequest;
 use crate::proto::FidsRequest;
 use crate::proto::FidsResponse;
 use crate::proto::GetInfoResponse;
+use crate::proto::GetProofRequest;
 use crate::proto::HubEvent;
 use crate::proto::IdRegistryEventByAddressRequest;
 use crate::proto::LinksByTargetRequest;
+use crate::proto::MessageByHashRequest;
+use crate::proto::MessageProof;
 use crate::proto::MessageType;
 use crate::proto::OnChainEvent;
 use crate::proto::OnChainEventRequest;
 use crate::proto::OnChainEventResponse;
+use crate::proto::OnChainEventsByBlockRangeRequest;
+use crate::proto::OnChainEventsByFidRequest;
 use crate::proto::ReactionType;
 use crate::proto::ReactionsByTargetRequest;
 use crate::proto::SignerRequest;
@@ -40,45 +69,146 @@ use crate::proto::{cast_add_body, Height};
 use crate::proto::{casts_by_parent_request, ShardChunk};
 use crate::proto::{Block, CastId, DbStats};
 use crate::proto::{
-    BlocksRequest, EventRequest, EventsRequest, EventsResponse, ShardChunksRequest,
-    ShardChunksResponse, SubscribeRequest,
+    BlockByHashRequest, BlocksRequest, EventRequest, EventsRequest, EventsResponse,
+    ShardChunksRequest, ShardChunksResponse, SubscribeRequest,
 };
 use crate::proto::{FidRequest, FidTimestampRequest};
-use crate::proto::{GetInfoRequest, StorageLimitsResponse};
+use crate::proto::{GetFidActivityCountsRequest, GetFidActivityCountsResponse};
+use crate::proto::{GetInfoRequest, StorageBytesResponse, StorageLimitsResponse};
+use crate::proto::{GetNetworkConfigRequest, GetNetworkConfigResponse};
+use crate::proto::{
+    GetNetworkStatsRequest, GetNetworkStatsResponse, GetShardStatsRequest, GetShardStatsResponse,
+};
+use crate::proto::{GetProposerStatsRequest, GetProposerStatsResponse};
+use crate::proto::{GetRecentBlocksSummaryRequest, GetRecentBlocksSummaryResponse};
+use crate::proto::{GetShardManifestRequest, GetShardManifestResponse};
+use crate::proto::{GetSubmissionPolicyRequest, GetSubmissionPolicyResponse};
+use crate::proto::{GetSyncStatusRequest, GetSyncStatusResponse};
+use crate::proto::{GetVotesRequest, GetVotesResponse};
 use crate::proto::{
     LinkRequest, LinksByFidRequest, Message, MessagesResponse, ReactionRequest,
-    ReactionsByFidRequest, UserDataRequest, VerificationRequest,
+    ReactionsByFidRequest, RecentCastsRequest, UserDataRequest, VerificationRequest,
+    VerificationsByFidRequest,
 };
+use crate::proto::{ShardRootRequest, ShardRootResponse};
+use crate::proto::{ValidatorSetRequest, ValidatorSetResponse};
 use crate::storage::constants::OnChainEventPostfix;
 use crate::storage::constants::RootPrefix;
+use crate::storage::constants::PAGE_SIZE_MAX;
+use crate::storage::db::disk_space::DiskSpaceGuard;
+use crate::storage::db::write_stall;
 use crate::storage::db::PageOptions;
 use crate::storage::db::RocksDbTransactionBatch;
 use crate::storage::store::account::MessagesPage;
 use crate::storage::store::account::UsernameProofStore;
-use crate::storage::store::account::{message_bytes_decode, IntoI32};
+use crate::storage::store::account::{
+    make_onchain_event_by_block_key, EventsPage, HubEventIdGenerator, OnchainEventStorageError,
+};
+use crate::storage::store::account::{message_bytes_decode, IntoI32, IntoU8, HASH_LENGTH};
 use crate::storage::store::account::{
     CastStore, LinkStore, ReactionStore, UserDataStore, VerificationStore,
 };
-use crate::storage::store::account::{EventsPage, HubEventIdGenerator};
+use crate::storage::store::activity_heights::ActivityHeights;
 use crate::storage::store::engine::{MempoolMessage, MessageValidationError, Senders, ShardEngine};
-use crate::storage::store::stores::Stores;
+use crate::storage::store::shard::get_shard_chunks_in_range;
+use crate::storage::store::stores::{Limits, StoreLimits, Stores};
 use crate::storage::store::BlockStore;
+use crate::storage::trie::merkle_trie::TrieKey;
+use crate::utils::query_params::record_query_param;
 use crate::utils::statsd_wrapper::StatsdClientWrapper;
 use hex::ToHex;
+use informalsystems_malachitebft_core_types::{Validator, ValidatorSet};
+use libp2p::identity::ed25519::Keypair;
 use moka::policy::EvictionPolicy;
 use moka::sync::{Cache, CacheBuilder};
-use std::collections::HashMap;
+use prost::Message as _;
+use std::collections::{BTreeMap, HashMap};
 use std::str::FromStr;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::sync::{mpsc, oneshot};
 use tokio::time::timeout;
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::metadata::AsciiMetadataValue;
 use tonic::{Request, Response, Status};
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn, Instrument};
 
 const MEMPOOL_ADD_REQUEST_TIMEOUT: Duration = Duration::from_millis(500);
+const PROOF_ATTEMPTS: u32 = 3;
+// Messages proven in a shard manifest
+const MANIFEST_SAMPLED_PROOFS: usize = 10;
 const MEMPOOL_SIZE_REQUEST_TIMEOUT: Duration = Duration::from_millis(100);
+// Pages a streaming rpc reads ahead of the client. Once this many are waiting to be sent, reading
+// the store pauses until the client catches up.
+const STREAM_PAGES_BUFFERED: usize = 4;
+const RECENT_CASTS_DEFAULT_LIMIT: usize = 100;
+// Number of upcoming heights GetValidatorSet returns the proposers for
+const PROPOSER_SCHEDULE_HEIGHTS: u64 = 10;
+// Most heights GetProposerStats aggregates in one request
+const MAX_PROPOSER_STATS_HEIGHTS: u64 = 10_000;
+// Most blocks GetRecentBlocksSummary returns
+const MAX_RECENT_BLOCKS_SUMMARY: u32 = 100;
+// Most hashes HasMessages checks in one request
+const MAX_HAS_MESSAGES_HASHES: usize = 1_000;
+// Messages proved by a single multiproof
+const MAX_MULTIPROOF_HASHES: usize = 100;
+// Most heights GetTrieRootsAtHeights looks up in one request
+const MAX_TRIE_ROOT_HEIGHTS: usize = 1_000;
+pub const DEFAULT_MAX_STREAMING_SUBSCRIBERS: usize = 1_000;
+
+// Why a submitted message wasn't admitted to the mempool. The reason is the validation error
+// variant when there is one, otherwise the hub error code.
+struct AdmissionRejection {
+    reason: String,
+    error: HubError,
+}
+
+impl From<HubError> for AdmissionRejection {
+    fn from(error: HubError) -> Self {
+        AdmissionRejection {
+            reason: error.code.clone(),
+            error,
+        }
+    }
+}
+
+impl From<MessageValidationError> for AdmissionRejection {
+    fn from(err: MessageValidationError) -> Self {
+        let reason: &'static str = match &err {
+            // Forward hub errors as is, otherwise we end up wrapping them
+            MessageValidationError::StoreError(hub_error) => {
+                return AdmissionRejection::from(hub_error.clone());
+            }
+            MessageValidationError::MessageValidationError(validation_error) => {
+                validation_error.into()
+            }
+            _ => (&err).into(),
+        };
+        AdmissionRejection {
+            reason: reason.to_string(),
+            error: HubError::validation_failure(&err.to_string()),
+        }
+    }
+}
+
+impl From<SubmissionSequenceError> for AdmissionRejection {
+    fn from(err: SubmissionSequenceError) -> Self {
+        let reason: &'static str = (&err).into();
+        let error = match err {
+            SubmissionSequenceError::MissingSequence => {
+                HubError::invalid_parameter(&err.to_string())
+            }
+            SubmissionSequenceError::DuplicateNonce { .. } => {
+                HubError::duplicate_nonce(&err.to_string())
+            }
+            SubmissionSequenceError::StoreError(hub_error) => hub_error,
+        };
+        AdmissionRejection {
+            reason: reason.to_string(),
+            error,
+        }
+    }
+}
 
 pub struct MyHubService {
     allowed_users: HashMap<String, String>,
@@ -89,11 +219,43 @@ pub struct MyHubService {
     message_router: Box<dyn routing::MessageRouter>,
     statsd_client: StatsdClientWrapper,
     l1_client: Option<Box<dyn L1Client>>,
+    onchain_events_halt: HaltState,
+    onchain_events_pause: PauseState,
+    onchain_events_retries: RetryQueue,
+    onchain_events_pending: PendingEvents,
+    validator_sets: HashMap<u32, StoredValidatorSets>,
+    sync_progress: SyncProgress,
+    shard_load: ShardLoad,
+    vote_history: VoteHistory,
     mempool_tx: mpsc::Sender<MempoolRequest>,
     network: proto::FarcasterNetwork,
     version: String,
     peer_id: String,
     id_registry_cache: Cache<Vec<u8>, OnChainEvent>,
+    subscriber_limit: SubscriberLimit,
+    // Set when streams are drained on shutdown
+    shutdown_signal: Option<ShutdownSignal>,
+    message_type_admission: MessageTypeAdmission,
+    fid_allowlist: FidAllowlist,
+    // The mempool's checks against the shards' state, so messages it would reject aren't admitted
+    state_admission: StateAdmissionRules,
+    write_stall_config: write_stall::Config,
+    disk_space: DiskSpaceGuard,
+    // Of the chain onchain events are read from
+    onchain_events_chain_id: u32,
+    // The mempool holds messages whose fid registration or signer hasn't been committed yet
+    pending_dependencies_enabled: bool,
+    submission_sequences: SubmissionSequences,
+    // The mempool limits how many messages each fid submits
+    rate_limits_enabled: bool,
+    // Set on read replicas, which serve another node's dbs and reject submissions
+    read_replica: Option<CatchUpStatus>,
+    // Submitted messages older than this are rejected, like the engines would when merging them
+    max_message_age: Option<Duration>,
+    // Shard manifests are signed with it
+    keypair: Option<Keypair>,
+    // Whether the node validates or only follows each shard, the ones missing are validated
+    shard_roles: HashMap<u32, proto::ShardRole>,
 }
 
 impl MyHubService {
@@ -108,6 +270,12 @@ impl MyHubService {
         message_router: Box<dyn routing::MessageRouter>,
         mempool_tx: mpsc::Sender<MempoolRequest>,
         l1_client: Option<Box<dyn L1Client>>,
+        onchain_events_halt: HaltState,
+        onchain_events_pause: PauseState,
+        onchain_events_retries: RetryQueue,
+        validator_sets: HashMap<u32, StoredValidatorSets>,
+        sync_progress: SyncProgress,
+        vote_history: VoteHistory,
         version: String,
         peer_id: String,
     ) -> Self {
@@ -130,6 +298,9 @@ impl MyHubService {
             .eviction_policy(EvictionPolicy::lru())
             .build();
 
+        let subscriber_limit =
+            SubscriberLimit::new(DEFAULT_MAX_STREAMING_SUBSCRIBERS, statsd_client.clone());
+
         let service = Self {
             allowed_users,
             network,
@@ -140,19 +311,195 @@ impl MyHubService {
             message_router,
             num_shards,
             l1_client,
+            onchain_events_halt,
+            onchain_events_pause,
+            onchain_events_retries,
+            onchain_events_pending: PendingEvents::default(),
+            validator_sets,
+            sync_progress,
+            shard_load: ShardLoad::default(),
+            vote_history,
             mempool_tx,
             version,
             peer_id,
             id_registry_cache,
+            subscriber_limit,
+            shutdown_signal: None,
+            message_type_admission: MessageTypeAdmission::default(),
+            fid_allowlist: FidAllowlist::default(),
+            state_admission: StateAdmissionRules::default(),
+            write_stall_config: write_stall::Config::default(),
+            disk_space: DiskSpaceGuard::default(),
+            onchain_events_chain_id: OP_MAINNET_CHAIN_ID,
+            pending_dependencies_enabled: false,
+            submission_sequences: SubmissionSequences::new(Default::default()),
+            rate_limits_enabled: false,
+            read_replica: None,
+            max_message_age: None,
+            keypair: None,
+            shard_roles: HashMap::new(),
         };
         service
     }
 
-    async fn submit_message_internal(
-        &self,
-        message: proto::Message,
-        bypass_validation: bool,
-    ) -> Result<proto::Message, HubError> {
+    pub fn with_message_type_admission(mut self, admission: MessageTypeAdmission) -> Self {
+        self.message_type_admission = admission;
+        self
+    }
+
+    pub fn with_fid_allowlist(mut self, allowlist: FidAllowlist) -> Self {
+        self.fid_allowlist = allowlist;
+        self
+    }
+
+    pub fn with_state_admission(mut self, state_admission: StateAdmissionRules) -> Self {
+        self.state_admission = state_admission;
+        self
+    }
+
+    pub fn with_max_streaming_subscribers(mut self, max_subscribers: usize) -> Self {
+        self.subscriber_limit = SubscriberLimit::new(max_subscribers, self.statsd_client.clone());
+        self
+    }
+
+    pub fn with_shutdown_signal(mut self, signal: ShutdownSignal) -> Self {
+        self.shutdown_signal = Some(signal);
+        self
+    }
+
+    pub fn with_write_stall_config(mut self, config: write_stall::Config) -> Self {
+        self.write_stall_config = config;
+        self
+    }
+
+    pub fn with_disk_space_guard(mut self, guard: DiskSpaceGuard) -> Self {
+        self.disk_space = guard;
+        self
+    }
+
+    pub fn with_onchain_events_chain_id(mut self, chain_id: u32) -> Self {
+        self.onchain_events_chain_id = chain_id;
+        self
+    }
+
+    pub fn with_pending_onchain_events(mut self, pending_events: PendingEvents) -> Self {
+        self.onchain_events_pending = pending_events;
+        self
+    }
+
+    pub fn with_pending_dependencies(mut self, enabled: bool) -> Self {
+        self.pending_dependencies_enabled = enabled;
+        self
+    }
+
+    pub fn with_rate_limits(mut self, enabled: bool) -> Self {
+        self.rate_limits_enabled = enabled;
+        self
+    }
+
+    pub fn with_submission_sequences(
+        mut self,
+        config: crate::network::submission_sequence::Config,
+    ) -> Self {
+        self.submission_sequences = SubmissionSequences::new(config);
+        self
+    }
+
+    pub fn with_read_replica(mut self, catch_up_status: CatchUpStatus) -> Self {
+        self.read_replica = Some(catch_up_status);
+        self
+    }
+
+    pub fn with_max_message_age(mut self, max_age: Option<Duration>) -> Self {
+        self.max_message_age = max_age;
+        self
+    }
+
+    pub fn with_keypair(mut self, keypair: Keypair) -> Self {
+        self.keypair = Some(keypair);
+        self
+    }
+
+    pub fn with_shard_roles(mut self, shard_roles: HashMap<u32, proto::ShardRole>) -> Self {
+        self.shard_roles = shard_roles;
+        self
+    }
+
+    fn shard_role(&self, shard_id: u32) -> proto::ShardRole {
+        self.shard_roles
+            .get(&shard_id)
+            .copied()
+            .unwrap_or(proto::ShardRole::Validator)
+    }
+
+    /// Counts the messages committed to each shard towards its load, until the shards stop
+    pub fn track_shard_load(&self) {
+        for (shard_id, senders) in &self.shard_senders {
+            self.shard_load
+                .track_events(*shard_id, senders.events_tx.subscribe());
+        }
+    }
+
+    // Reports the shard db's write stall, failing once it's past the configured threshold
+    fn check_write_stall(&self, stores: &Stores) -> Result<(), HubError> {
+        let stall = match stores.db.write_stall() {
+            Ok(stall) => stall,
+            Err(err) => {
+                warn!(
+                    shard_id = stores.shard_id,
+                    "Unable to read write stall: {}", err
+                );
+                return Ok(());
+            }
+        };
+        self.statsd_client
+            .gauge_with_shard(stores.shard_id, "write_stall", stall.gauge_value());
+        self.write_stall_config.check(stall)
+    }
+
+    // Resolves once the node starts shutting down, never if streams aren't drained
+    async fn wait_for_drain(signal: Option<ShutdownSignal>) {
+        match signal {
+            Some(signal) => signal.wait().await,
+            None => std::future::pending().await,
+        }
+    }
+
+    fn draining_event(shard_id: u32, resume_from_id: u64) -> HubEvent {
+        HubEvent {
+            r#type: proto::HubEventType::Draining as i32,
+            body: Some(proto::hub_event::Body::DrainingBody(proto::DrainingBody {
+                resume_from_id,
+            })),
+            shard_index: shard_id,
+            ..HubEvent::default()
+        }
+    }
+
+    fn acquire_subscriber_permit(&self) -> Result<SubscriberPermit, Status> {
+        self.subscriber_limit.try_acquire().ok_or_else(|| {
+            self.statsd_client
+                .count("rpc.streaming_subscribers.rejected", 1);
+            Status::resource_exhausted("too many streaming subscribers, try again later")
+        })
+    }
+
+    // TODO: This is a hack to get around the fact that self cannot be made mutable
+    fn readonly_engine(&self, stores: &Stores) -> ShardEngine {
+        ShardEngine::new(
+            stores.db.clone(),
+            self.network,
+            stores.trie.clone(),
+            1,
+            stores.store_limits.clone(),
+            self.statsd_client.clone(),
+            100,
+            None,
+        )
+        .with_max_message_age(self.max_message_age)
+    }
+
+    fn get_stores_for_message(&self, message: &proto::Message) -> Result<&Stores, HubError> {
         let fid = message.fid();
         if fid == 0 {
             return Err(HubError::invalid_parameter("fid cannot be 0"));
@@ -160,88 +507,156 @@ impl MyHubService {
 
         let dst_shard = self.message_router.route_fid(fid, self.num_shards);
 
-        let stores = match self.shard_stores.get(&dst_shard) {
-            Some(store) => store,
-            None => return Err(HubError::invalid_parameter("shard not found for fid")),
-        };
+        match self.shard_stores.get(&dst_shard) {
+            Some(store) => Ok(store),
+            None if self.is_shard_unavailable(dst_shard) => Err(HubError::unavailable(&format!(
+                "shard {} is unavailable on this node",
+                dst_shard
+            ))),
+            None => Err(HubError::invalid_parameter("shard not found for fid")),
+        }
+    }
 
-        if !bypass_validation {
-            // TODO: This is a hack to get around the fact that self cannot be made mutable
-            let mut readonly_engine = ShardEngine::new(
-                stores.db.clone(),
-                self.network,
-                stores.trie.clone(),
-                1,
-                stores.store_limits.clone(),
-                self.statsd_client.clone(),
-                100,
-                None,
-            );
-            let result = readonly_engine.simulate_message(&message);
+    // A shard of the network the node has no stores for, e.g. because its db couldn't be opened
+    // at startup
+    fn is_shard_unavailable(&self, shard_id: u32) -> bool {
+        shard_id >= 1 && shard_id <= self.num_shards && !self.shard_stores.contains_key(&shard_id)
+    }
 
-            if let Err(err) = result {
-                return match err {
-                    MessageValidationError::StoreError(hub_error) => {
-                        // Forward hub errors as is, otherwise we end up wrapping them
-                        Err(hub_error)
-                    }
-                    _ => Err(HubError::validation_failure(&err.to_string())),
-                };
+    // Everything a message has to pass before it's handed to the mempool. Doesn't write anything,
+    // so it's shared by submission and the dry run in ValidateMessage.
+    async fn validate_message_for_submit(
+        &self,
+        stores: &Stores,
+        message: &proto::Message,
+    ) -> Result<(), AdmissionRejection> {
+        let fid = message.fid();
+        let mut readonly_engine = self.readonly_engine(stores);
+        let result = readonly_engine.simulate_message(message);
+
+        if let Err(err) = result {
+            // Everything that doesn't need the state was checked before these, the message can
+            // wait in the mempool for the onchain events
+            let waits_for_onchain_events = matches!(
+                err,
+                MessageValidationError::MissingFid | MessageValidationError::MissingSigner
+            );
+            if !(self.pending_dependencies_enabled && waits_for_onchain_events) {
+                return Err(AdmissionRejection::from(err));
             }
+        }
 
-            // We're doing the ens and address validations here for now because we don't want L1 interactions to be on the consensus critical path. Eventually this will move to the fname server.
-            if let Some(message_data) = &message.data {
-                match &message_data.body {
-                    Some(proto::message_data::Body::UserDataBody(user_data)) => {
-                        if user_data.r#type() == proto::UserDataType::Username {
-                            if user_data.value.ends_with(".eth") {
-                                self.validate_ens_username(fid, user_data.value.to_string())
-                                    .await?;
-                            }
-                        };
-                    }
-                    Some(proto::message_data::Body::UsernameProofBody(proof)) => {
-                        if proof.r#type() == UserNameType::UsernameTypeEnsL1 {
-                            self.validate_ens_username_proof(fid, &proof).await?;
+        // We're doing the ens and address validations here for now because we don't want L1 interactions to be on the consensus critical path. Eventually this will move to the fname server.
+        if let Some(message_data) = &message.data {
+            match &message_data.body {
+                Some(proto::message_data::Body::UserDataBody(user_data)) => {
+                    if user_data.r#type() == proto::UserDataType::Username {
+                        if user_data.value.ends_with(".eth") {
+                            self.validate_ens_username(fid, user_data.value.to_string())
+                                .await?;
                         }
+                    };
+                }
+                Some(proto::message_data::Body::UsernameProofBody(proof)) => {
+                    if proof.r#type() == UserNameType::UsernameTypeEnsL1 {
+                        self.validate_ens_username_proof(fid, &proof).await?;
                     }
-                    Some(proto::message_data::Body::VerificationAddAddressBody(body)) => {
-                        if body.verification_type == 1 {
-                            // todo: thread through network
-                            let claim_result =
-                                validations::verification::make_verification_address_claim(
-                                    message_data.fid,
-                                    &body.address,
-                                    proto::FarcasterNetwork::Mainnet,
-                                    &body.block_hash,
-                                    proto::Protocol::Ethereum,
-                                );
-                            match claim_result {
-                                Ok(claim) => {
-                                    self.validate_contract_signature(claim, body).await?;
-                                }
-                                Err(err) => {
-                                    return Err(HubError::validation_failure(
-                                        format!(
-                                            "could not create verification address claim: {}",
-                                            err.to_string()
-                                        )
-                                        .as_str(),
-                                    ))
-                                }
+                }
+                Some(proto::message_data::Body::VerificationAddAddressBody(body)) => {
+                    if body.verification_type == 1 {
+                        // todo: thread through network
+                        let claim_result =
+                            validations::verification::make_verification_address_claim(
+                                message_data.fid,
+                                &body.address,
+                                proto::FarcasterNetwork::Mainnet,
+                                &body.block_hash,
+                                proto::Protocol::Ethereum,
+                            );
+                        match claim_result {
+                            Ok(claim) => {
+                                self.validate_contract_signature(claim, body).await?;
+                            }
+                            Err(err) => {
+                                return Err(HubError::validation_failure(
+                                    format!(
+                                        "could not create verification address claim: {}",
+                                        err.to_string()
+                                    )
+                                    .as_str(),
+                                )
+                                .into())
                             }
                         }
                     }
-                    _ => {}
                 }
+                _ => {}
             }
         }
+        Ok(())
+    }
+
+    async fn submit_message_internal(
+        &self,
+        message: proto::Message,
+        bypass_validation: bool,
+        sequence: Option<u64>,
+        request_id: &str,
+    ) -> Result<proto::Message, HubError> {
+        let shard_id = self
+            .message_router
+            .route_fid(message.fid(), self.num_shards);
+        let message_type = message.msg_type().as_str_name();
+        let result = self
+            .admit_message(message, bypass_validation, sequence, request_id)
+            .await;
+
+        // Every admission decision goes through here, so the counters add up to all submissions
+        match &result {
+            Ok(_) => self.statsd_client.count_with_shard_and_tags(
+                shard_id,
+                "mempool.admission.accepted",
+                &[("message_type", message_type)],
+                1,
+            ),
+            Err(rejection) => self.statsd_client.count_with_shard_and_tags(
+                shard_id,
+                "mempool.admission.rejected",
+                &[
+                    ("reason", &rejection.reason),
+                    ("message_type", message_type),
+                ],
+                1,
+            ),
+        }
+        result.map_err(|rejection| rejection.error)
+    }
+
+    async fn admit_message(
+        &self,
+        message: proto::Message,
+        bypass_validation: bool,
+        sequence: Option<u64>,
+        request_id: &str,
+    ) -> Result<proto::Message, AdmissionRejection> {
+        if self.read_replica.is_some() {
+            return Err(AdmissionRejection {
+                reason: "read_replica".to_string(),
+                error: HubError::failed_precondition("read replicas don't accept messages"),
+            });
+        }
+
+        let stores = self.check_admission(&message, bypass_validation).await?;
+
+        // Last, so a sequence isn't used up by a message that was going to be rejected anyway
+        self.submission_sequences
+            .accept(&stores.db, message.fid(), sequence)?;
 
         let (tx, rx) = oneshot::channel();
 
         match self.mempool_tx.try_send(MempoolRequest::AddMessage(
             MempoolMessage::UserMessage(message.clone()),
-            MempoolSource::RPC,
+            MempoolSource::RPC(Some(request_id.to_string())),
             Some(tx),
         )) {
             Ok(_) => {
@@ -251,14 +666,18 @@ impl MyHubService {
             Err(mpsc::error::TrySendError::Full(_)) => {
                 self.statsd_client
                     .count("rpc.submit_message.channel_full", 1);
-                return Err(HubError::unavailable("mempool channel is full"));
+                // The mempool stops reading from the channel once it's at capacity
+                return Err(AdmissionRejection {
+                    reason: "mempool_full".to_string(),
+                    error: HubError::unavailable("mempool channel is full"),
+                });
             }
             Err(e) => {
                 error!(
                     "Error sending message to mempool channel: {:?}",
                     e.to_string()
                 );
-                return Err(HubError::unavailable("mempool channel send error"));
+                return Err(HubError::unavailable("mempool channel send error").into());
             }
         }
 
@@ -269,21 +688,99 @@ impl MyHubService {
                     "Error receiving message from mempool channel: {:?}",
                     err.to_string()
                 );
-                return Err(HubError::unavailable("Error adding to mempool"));
+                return Err(HubError::unavailable("Error adding to mempool").into());
             }
             Err(_) => {
                 error!("Timeout receiving message from mempool channel",);
-                return Err(HubError::unavailable("Error adding to mempool"));
+                return Err(HubError::unavailable("Error adding to mempool").into());
             }
         };
 
         return match result {
             Ok(_) => Ok(message),
-            Err(hub_error) => Err(hub_error),
+            Err(hub_error) => Err(hub_error.into()),
         };
     }
 
+    // Everything submission checks before the message's sequence and the mempool, shared with
+    // ValidateMessage so it answers the same as submitting would
+    async fn check_admission(
+        &self,
+        message: &proto::Message,
+        bypass_validation: bool,
+    ) -> Result<&Stores, AdmissionRejection> {
+        if let Err(error) = self.message_type_admission.check(message) {
+            return Err(AdmissionRejection {
+                reason: "message_type_disabled".to_string(),
+                error,
+            });
+        }
+
+        if let Err(error) = self.fid_allowlist.check(message) {
+            return Err(AdmissionRejection {
+                reason: "fid_not_allowed".to_string(),
+                error,
+            });
+        }
+
+        let stores = self.get_stores_for_message(message)?;
+
+        if stores.shard_freeze.is_frozen() {
+            return Err(
+                HubError::failed_precondition("shard is frozen and not accepting writes").into(),
+            );
+        }
+
+        if let Err(error) = self.check_write_stall(stores) {
+            return Err(AdmissionRejection {
+                reason: "write_stall".to_string(),
+                error,
+            });
+        }
+
+        if let Err(error) = self.disk_space.check() {
+            return Err(AdmissionRejection {
+                reason: "low_disk_space".to_string(),
+                error,
+            });
+        }
+
+        let shard_id = self
+            .message_router
+            .route_fid(message.fid(), self.num_shards);
+        match self
+            .state_admission
+            .check(&self.shard_stores, shard_id, message)
+        {
+            Ok(_) => {}
+            Err(rejection) => {
+                return Err(AdmissionRejection {
+                    reason: rejection.reason.to_string(),
+                    error: rejection.error,
+                })
+            }
+        }
+
+        if !bypass_validation {
+            self.validate_message_for_submit(stores, message).await?;
+        }
+        Ok(stores)
+    }
+
+    fn check_shard_available(&self, shard_id: u32) -> Result<(), Status> {
+        if self.is_shard_unavailable(shard_id) {
+            return Err(Status::unavailable(format!(
+                "shard {} is unavailable on this node",
+                shard_id
+            )));
+        }
+        Ok(())
+    }
+
     fn get_stores_for_shard(&self, shard_id: u32) -> Result<&Stores, Status> {
+        record_query_param("shard", shard_id as u64);
+        self.shard_load.record_request(shard_id, Instant::now());
+        self.check_shard_available(shard_id)?;
         match self.shard_stores.get(&shard_id) {
             Some(store) => Ok(store),
             None => Err(Status::invalid_argument(
@@ -292,7 +789,218 @@ impl MyHubService {
         }
     }
 
+    // Read from the latest shard chunk rather than the trie, which may already hold the changes of
+    // a block that is still being committed
+    fn committed_shard_root(stores: &Stores) -> Result<(u64, Vec<u8>), Status> {
+        let header = stores
+            .shard_store
+            .get_last_shard_chunk()
+            .map_err(|err| Status::internal(err.to_string()))?
+            .and_then(|shard_chunk| shard_chunk.header);
+        match header {
+            None => Ok((0, vec![])),
+            Some(header) => {
+                let height = header
+                    .height
+                    .ok_or(Status::internal("shard chunk missing height"))?;
+                Ok((height.block_number, header.shard_root))
+            }
+        }
+    }
+
+    // The proof of the first of the trie keys found in the committed trie
+    async fn committed_proof(
+        stores: &Stores,
+        trie_keys: &[Vec<u8>],
+    ) -> Result<MessageProof, Status> {
+        // The trie and the shard chunk are written separately on commit, retry if we read in
+        // between so the proof always matches a committed shard root.
+        for _ in 0..PROOF_ATTEMPTS {
+            let (height, shard_root) = Self::last_shard_root(stores)?;
+
+            let mut found = None;
+            for trie_key in trie_keys {
+                let proof = stores
+                    .trie
+                    .get_proof(&stores.db, trie_key)
+                    .map_err(|err| Status::internal(err.to_string()))?;
+                if let Some(proof) = proof {
+                    found = Some((trie_key.clone(), proof));
+                    break;
+                }
+            }
+
+            let (trie_key, proof) = match found {
+                None => return Err(Status::not_found("message not found in committed trie")),
+                Some(found) => found,
+            };
+
+            if proof.root_hash == shard_root {
+                return Ok(MessageProof {
+                    shard_id: height.shard_index,
+                    block_number: height.block_number,
+                    root_hash: proof.root_hash,
+                    trie_key,
+                    steps: proof.steps,
+                    branching_factor: stores.trie.branching_factor(),
+                });
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        Err(Status::unavailable(
+            "trie root does not match the latest shard chunk, try again",
+        ))
+    }
+
+    // Like committed_proof, for all of the trie keys at once
+    async fn committed_multiproof(
+        stores: &Stores,
+        trie_keys: Vec<Vec<u8>>,
+    ) -> Result<proto::MultiProof, Status> {
+        for _ in 0..PROOF_ATTEMPTS {
+            let (height, shard_root) = Self::last_shard_root(stores)?;
+            let proof = stores
+                .trie
+                .get_multiproof(&stores.db, &trie_keys)
+                .map_err(|err| Status::internal(err.to_string()))?
+                .ok_or(Status::not_found("message not found in committed trie"))?;
+
+            if proof.root_hash == shard_root {
+                return Ok(proto::MultiProof {
+                    shard_id: height.shard_index,
+                    block_number: height.block_number,
+                    root_hash: proof.root_hash,
+                    trie_keys,
+                    leaf_depths: proof.leaf_depths,
+                    nodes: proof.nodes,
+                    branching_factor: stores.trie.branching_factor(),
+                });
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        Err(Status::unavailable(
+            "trie root does not match the latest shard chunk, try again",
+        ))
+    }
+
+    // The height and trie root of the shard's latest committed chunk
+    fn last_shard_root(stores: &Stores) -> Result<(proto::Height, Vec<u8>), Status> {
+        let shard_chunk = stores
+            .shard_store
+            .get_last_shard_chunk()
+            .map_err(|err| Status::internal(err.to_string()))?
+            .ok_or(Status::not_found("no committed shard chunks"))?;
+        let header = shard_chunk
+            .header
+            .ok_or(Status::internal("shard chunk missing header"))?;
+        let height = header
+            .height
+            .ok_or(Status::internal("shard chunk missing height"))?;
+        Ok((height, header.shard_root))
+    }
+
+    // The latest height committed to the shard, 0 for the block shard
+    fn confirmed_height(&self, shard_id: u32) -> Result<u64, Status> {
+        if shard_id == 0 {
+            self.block_store
+                .max_block_number()
+                .map_err(|err| Status::internal(err.to_string()))
+        } else {
+            self.get_stores_for_shard(shard_id)?
+                .shard_store
+                .max_block_number()
+                .map_err(|err| Status::internal(err.to_string()))
+        }
+    }
+
+    // The height and commit round of each block or shard chunk in the range, inclusive. Heights
+    // that were pruned are skipped.
+    fn committed_rounds(
+        &self,
+        shard_id: u32,
+        start_height: u64,
+        stop_height: u64,
+    ) -> Result<Vec<(u64, u64)>, Status> {
+        let commit_round = |commits: &Option<proto::Commits>| {
+            commits
+                .as_ref()
+                .and_then(|commits| u64::try_from(commits.round).ok())
+        };
+        let mut rounds = vec![];
+        let mut next_page_token = None;
+        loop {
+            let page_options = PageOptions {
+                page_size: Some(PAGE_SIZE_MAX),
+                page_token: next_page_token,
+                reverse: false,
+            };
+            next_page_token = if shard_id == 0 {
+                let page = self
+                    .block_store
+                    .get_blocks(start_height, Some(stop_height + 1), &page_options)
+                    .map_err(|err| Status::internal(err.to_string()))?;
+                rounds.extend(page.blocks.iter().filter_map(|block| {
+                    let height = block.header.as_ref()?.height.as_ref()?.block_number;
+                    Some((height, commit_round(&block.commits)?))
+                }));
+                page.next_page_token
+            } else {
+                let page = get_shard_chunks_in_range(
+                    &self.get_stores_for_shard(shard_id)?.shard_store.db,
+                    &page_options,
+                    start_height,
+                    Some(stop_height + 1),
+                )
+                .map_err(|err| Status::internal(err.to_string()))?;
+                rounds.extend(page.shard_chunks.iter().filter_map(|shard_chunk| {
+                    let height = shard_chunk.header.as_ref()?.height.as_ref()?.block_number;
+                    Some((height, commit_round(&shard_chunk.commits)?))
+                }));
+                page.next_page_token
+            };
+            if next_page_token.is_none() {
+                return Ok(rounds);
+            }
+        }
+    }
+
+    // Anyone can gossip a vote, so only the validators' correctly signed ones are kept
+    fn verified_votes(
+        validator_set: &SnapchainValidatorSet,
+        recorded_votes: Vec<RecordedVote>,
+    ) -> Vec<proto::ObservedVote> {
+        recorded_votes
+            .into_iter()
+            .filter_map(|recorded| {
+                let (validator_index, validator) = validator_set
+                    .validators
+                    .iter()
+                    .enumerate()
+                    .find(|(_, validator)| {
+                        validator.public_key.to_bytes().as_slice() == recorded.vote.voter
+                    })?;
+                if !validator
+                    .public_key
+                    .verify(&recorded.vote.encode_to_vec(), &recorded.signature)
+                {
+                    return None;
+                }
+                Some(proto::ObservedVote {
+                    r#type: recorded.vote.r#type,
+                    round: recorded.vote.round,
+                    voter: recorded.vote.voter,
+                    validator_index: validator_index as u32,
+                    value: recorded.vote.value,
+                    timestamp: recorded.observed_at,
+                })
+            })
+            .collect()
+    }
+
     fn get_stores_for(&self, fid: u64) -> Result<&Stores, Status> {
+        record_query_param("fid", fid);
         let shard_id = self.message_router.route_fid(fid, self.num_shards);
         self.get_stores_for_shard(shard_id)
     }
@@ -459,6 +1167,24 @@ impl MyHubService {
         hub_event
     }
 
+    // Id 0 always reads from the oldest event that's still retained
+    fn check_events_retained(stores: &Stores, event_id: u64) -> Result<(), Status> {
+        let pruned_until = stores
+            .get_events_pruned_until()
+            .map_err(|err| Status::internal(err.to_string()))?;
+        if event_id != 0 && event_id < pruned_until {
+            let mut status = Status::out_of_range(format!(
+                "event {} has been pruned from shard {}, the oldest retained event is {}",
+                event_id, stores.shard_id, pruned_until
+            ));
+            status
+                .metadata_mut()
+                .insert("x-err-code", AsciiMetadataValue::from_static("pruned"));
+            return Err(status);
+        }
+        Ok(())
+    }
+
     fn get_events_from_store(
         stores: &Stores,
         start_id: u64,
@@ -508,6 +1234,95 @@ impl MyHubService {
     }
 }
 
+fn storage_unit_limits(limits: &Limits) -> proto::StorageUnitLimits {
+    proto::StorageUnitLimits {
+        casts: limits.casts,
+        links: limits.links,
+        reactions: limits.reactions,
+        user_data: limits.user_data,
+        user_name_proofs: limits.user_name_proofs,
+        verifications: limits.verifications,
+    }
+}
+
+// Sends every page of messages get_page returns, starting from page_options, from a task that
+// stops when the client goes away
+fn stream_messages_pages<F>(
+    mut page_options: PageOptions,
+    fields: proto::MessageFields,
+    get_page: F,
+) -> ReceiverStream<Result<MessagesResponse, Status>>
+where
+    F: Fn(&PageOptions) -> Result<MessagesPage, HubError> + Send + 'static,
+{
+    page_options.page_size = Some(
+        page_options
+            .page_size
+            .unwrap_or(PAGE_SIZE_MAX)
+            .min(PAGE_SIZE_MAX),
+    );
+    let (server_tx, client_rx) =
+        mpsc::channel::<Result<MessagesResponse, Status>>(STREAM_PAGES_BUFFERED);
+
+    tokio::spawn(async move {
+        loop {
+            let page = match get_page(&page_options) {
+                Ok(page) => page,
+                Err(err) => {
+                    _ = server_tx.send(Err(Status::internal(err.to_string()))).await;
+                    break;
+                }
+            };
+            // The previous page ended exactly at the last message
+            if page.messages.is_empty() && page_options.page_token.is_some() {
+                break;
+            }
+
+            let next_page_token = page.next_page_token.clone();
+            let messages = match fields {
+                proto::MessageFields::Full => page.messages,
+                proto::MessageFields::HashesOnly => page.messages.iter().map(hash_only).collect(),
+            };
+            let response = MessagesResponse {
+                messages,
+                next_page_token: page.next_page_token,
+            };
+            if let Err(_) = server_tx.send(Ok(response)).await {
+                break;
+            }
+
+            match next_page_token {
+                Some(page_token) => page_options.page_token = Some(page_token),
+                None => break,
+            }
+        }
+    });
+
+    ReceiverStream::new(client_rx)
+}
+
+#[tonic::async_trait]
+impl SubmitValidator for MyHubService {
+    async fn validate_message_on_shard(
+        &self,
+        shard_id: u32,
+        message: &proto::Message,
+    ) -> Result<(), HubError> {
+        let stores = match self.shard_stores.get(&shard_id) {
+            Some(stores) => stores,
+            None => {
+                return Err(HubError::unavailable(&format!(
+                    "shard {} is unavailable on this node",
+                    shard_id
+                )))
+            }
+        };
+        self.validate_message_for_submit(stores, message)
+            .await
+            .map_err(|rejection| rejection.error)
+    }
+}
+
 #[tonic::async_trait]
 impl HubService for MyHubService {
     async fn submit_message(
@@ -522,14 +1337,33 @@ impl HubService for MyHubService {
             err
         })?;
 
+        let request_id = new_request_id();
+        let request_id_header = AsciiMetadataValue::from_str(&request_id).unwrap();
         let hash = request.get_ref().hash.encode_hex::<String>();
-        debug!(hash, "Received call to [submit_message] RPC");
+        debug!(hash, request_id, "Received call to [submit_message] RPC");
+
+        let sequence = match request.metadata().get(SUBMISSION_SEQUENCE_HEADER) {
+            Some(value) => match value.to_str().ok().and_then(|v| v.parse::<u64>().ok()) {
+                Some(sequence) => Some(sequence),
+                None => {
+                    self.statsd_client.count("rpc.submit_message_in_flight", -1);
+                    return Err(Status::invalid_argument(format!(
+                        "{} must be an unsigned integer",
+                        SUBMISSION_SEQUENCE_HEADER
+                    )));
+                }
+            },
+            None => None,
+        };
 
         let mut message = request.into_inner();
         message_bytes_decode(&mut message);
         let fid = message.fid();
         let msg_type = message.msg_type().into_i32();
-        let result = self.submit_message_internal(message, false).await;
+        let result = self
+            .submit_message_internal(message, false, sequence, &request_id)
+            .instrument(tracing::info_span!("submit_message", request_id))
+            .await;
 
         self.statsd_client.time(
             "rpc.submit_message.duration",
@@ -540,13 +1374,18 @@ impl HubService for MyHubService {
             Ok(message) => {
                 self.statsd_client.count("rpc.submit_message.success", 1);
                 self.statsd_client.count("rpc.submit_message_in_flight", -1);
-                Ok(Response::new(message))
+                let mut response = Response::new(message);
+                response
+                    .metadata_mut()
+                    .insert(REQUEST_ID_HEADER, request_id_header);
+                Ok(response)
             }
             Err(err) => {
                 self.statsd_client.count("rpc.submit_message.failure", 1);
                 info!(
                     hash = hash,
                     fid = fid,
+                    request_id = request_id,
                     errCode = err.code,
                     msgType = msg_type,
                     "submit_message failed: {}",
@@ -561,12 +1400,29 @@ impl HubService for MyHubService {
                     Status::internal(err.to_string())
                 } else if err_code.starts_with("unavailable") {
                     Status::unavailable(err.to_string())
+                } else if err_code == "failed_precondition" {
+                    Status::failed_precondition(err.to_string())
+                } else if err_code == "deadline_exceeded" {
+                    Status::deadline_exceeded(err.to_string())
+                } else if err_code == "resource_exhausted" {
+                    Status::resource_exhausted(err.to_string())
                 } else {
                     Status::unknown(err.to_string())
                 };
                 if let Ok(err_str) = AsciiMetadataValue::from_str(&err_code) {
                     status.metadata_mut().insert("x-err-code", err_str);
                 }
+                // In seconds, as in the http header
+                if err_code == "resource_exhausted" {
+                    let retry_after = self.write_stall_config.retry_after.as_secs().max(1);
+                    status.metadata_mut().insert(
+                        "retry-after",
+                        AsciiMetadataValue::from_str(&retry_after.to_string()).unwrap(),
+                    );
+                }
+                status
+                    .metadata_mut()
+                    .insert(REQUEST_ID_HEADER, request_id_header);
                 self.statsd_client.count("rpc.submit_message_in_flight", -1);
                 Err(status)
             }
@@ -586,9 +1442,12 @@ impl HubService for MyHubService {
 
         info!( {start_block_number, stop_block_number}, "Received call to [get_blocks] RPC");
 
+        let permit = self.acquire_subscriber_permit()?;
         let block_store = self.block_store.clone();
+        let shutdown_signal = self.shutdown_signal.clone();
 
         tokio::spawn(async move {
+            let _permit = permit;
             let mut next_page_token = None;
             loop {
                 match block_store.get_blocks(
@@ -606,6 +1465,23 @@ impl HubService for MyHubService {
                     }
                     Ok(block_page) => {
                         for block in block_page.blocks {
+                            if shutdown_signal
+                                .as_ref()
+                                .is_some_and(|signal| signal.is_shutting_down())
+                            {
+                                let block_number = block
+                                    .header
+                                    .as_ref()
+                                    .and_then(|header| header.height)
+                                    .map_or(0, |height| height.block_number);
+                                _ = server_tx
+                                    .send(Err(Status::unavailable(format!(
+                                        "node is shutting down, resume from block {}",
+                                        block_number
+                                    ))))
+                                    .await;
+                                return;
+                            }
                             if let Err(_) = server_tx.send(Ok(block)).await {
                                 break;
                             }
@@ -624,6 +1500,28 @@ impl HubService for MyHubService {
         Ok(Response::new(ReceiverStream::new(client_rx)))
     }
 
+    async fn get_block_by_hash(
+        &self,
+        request: Request<BlockByHashRequest>,
+    ) -> Result<Response<Block>, Status> {
+        let request = request.into_inner();
+        // Blocks are only stored for the block shard, the other shards have shard chunks
+        if request.shard_id != 0 {
+            return Err(Status::invalid_argument(format!(
+                "blocks are only available for shard 0, not shard {}",
+                request.shard_id
+            )));
+        }
+        match self.block_store.get_block_by_hash(&request.hash) {
+            Err(err) => Err(Status::from_error(Box::new(err))),
+            Ok(None) => Err(Status::not_found(format!(
+                "no block with hash {}",
+                hex::encode(&request.hash)
+            ))),
+            Ok(Some(block)) => Ok(Response::new(block)),
+        }
+    }
+
     async fn get_shard_chunks(
         &self,
         request: Request<ShardChunksRequest>,
@@ -636,6 +1534,7 @@ impl HubService for MyHubService {
         info!( {shard_index, start_block_number, stop_block_number},
             "Received call to [get_shard_chunks] RPC");
 
+        self.check_shard_available(shard_index)?;
         let stores = self.shard_stores.get(&shard_index);
         match stores {
             None => Err(Status::from_error(Box::new(
@@ -666,16 +1565,19 @@ impl HubService for MyHubService {
         let mut shard_infos = Vec::new();
 
         let (size_req, size_res) = oneshot::channel();
-        let _ = self
-            .mempool_tx
-            .send(MempoolRequest::GetSize(size_req))
-            .await
-            .map_err(|err| {
-                error!(
-                    { err = err.to_string() },
-                    "[get_info] error sending mempool size request"
-                );
-            });
+        // Read replicas have no mempool
+        if self.read_replica.is_none() {
+            let _ = self
+                .mempool_tx
+                .send(MempoolRequest::GetSize(size_req))
+                .await
+                .map_err(|err| {
+                    error!(
+                        { err = err.to_string() },
+                        "[get_info] error sending mempool size request"
+                    );
+                });
+        }
 
         let current_time = get_farcaster_time().unwrap_or(0);
         let block_info = proto::ShardInfo {
@@ -686,11 +1588,18 @@ impl HubService for MyHubService {
             approx_size: self.block_store.db.approximate_size(),
             block_delay: current_time - self.block_store.max_block_timestamp().unwrap_or(0),
             mempool_size: 0,
+            shard_root: vec![],
+            role: self.shard_role(0) as i32,
         };
         shard_infos.push(block_info);
 
         let mempool_size = match timeout(MEMPOOL_SIZE_REQUEST_TIMEOUT, size_res).await {
             Ok(Ok(size)) => size,
+            Ok(Err(_)) if self.read_replica.is_some() => self
+                .shard_stores
+                .keys()
+                .map(|shard_id| (*shard_id, 0))
+                .collect(),
             Ok(Err(err)) => {
                 error!(
                     { err = err.to_string() },
@@ -721,6 +1630,7 @@ impl HubService for MyHubService {
                 as u64;
 
             let max_block_time = shard_store.shard_store.max_block_timestamp().unwrap_or(0);
+            let (_, shard_root) = Self::committed_shard_root(shard_store)?;
 
             let info = proto::ShardInfo {
                 shard_id: *shard_index,
@@ -733,6 +1643,8 @@ impl HubService for MyHubService {
                 // Returning 0 would mean the clients would think the mempool is empty
                 // So, return a high value
                 mempool_size: *mempool_size.get(shard_index).unwrap_or(&(u32::MAX as u64)),
+                shard_root,
+                role: self.shard_role(*shard_index) as i32,
             };
             shard_infos.push(info);
             total_num_messages += shard_num_messages;
@@ -740,6 +1652,41 @@ impl HubService for MyHubService {
             total_approx_size += shard_approx_size;
         }
 
+        let pending_block_range_retries = self
+            .onchain_events_retries
+            .pending_block_ranges()
+            .into_iter()
+            .map(
+                |(start_block_number, stop_block_number)| proto::PendingBlockRangeRetry {
+                    start_block_number,
+                    stop_block_number,
+                },
+            )
+            .collect();
+        let failure = self.onchain_events_halt.failure();
+        let onchain_events_status = match self.onchain_events_halt.halted() {
+            Some(halt) => proto::OnchainEventsStatus {
+                halted: true,
+                halted_at_block: halt.block_number,
+                reorg_depth: halt.depth,
+                paused: self.onchain_events_pause.paused(),
+                pending_block_range_retries,
+                pending_fid_retries: self.onchain_events_retries.pending_fids(),
+                failed: failure.is_some(),
+                failure: failure.unwrap_or_default(),
+                pending_events: self.onchain_events_pending.len() as u64,
+            },
+            None => proto::OnchainEventsStatus {
+                paused: self.onchain_events_pause.paused(),
+                pending_block_range_retries,
+                pending_fid_retries: self.onchain_events_retries.pending_fids(),
+                failed: failure.is_some(),
+                failure: failure.unwrap_or_default(),
+                pending_events: self.onchain_events_pending.len() as u64,
+                ..proto::OnchainEventsStatus::default()
+            },
+        };
+
         Ok(Response::new(GetInfoResponse {
             db_stats: Some(DbStats {
                 num_fid_registrations: total_fid_registrations,
@@ -750,6 +1697,169 @@ impl HubService for MyHubService {
             num_shards: self.num_shards,
             version: self.version.clone(),
             peer_id: self.peer_id.clone(),
+            onchain_events_status: Some(onchain_events_status),
+            read_replica_status: self
+                .read_replica
+                .as_ref()
+                .map(|catch_up_status| catch_up_status.status()),
+        }))
+    }
+
+    async fn get_sync_status(
+        &self,
+        _request: Request<GetSyncStatusRequest>,
+    ) -> Result<Response<GetSyncStatusResponse>, Status> {
+        let now = Instant::now();
+        let mut shard_ids: Vec<u32> = self.shard_stores.keys().cloned().collect();
+        shard_ids.sort();
+
+        // Shard 0 is the block shard
+        let mut shard_statuses = Vec::new();
+        for shard_id in std::iter::once(0).chain(shard_ids) {
+            let height = if shard_id == 0 {
+                self.block_store
+                    .max_block_number()
+                    .map_err(|err| Status::internal(err.to_string()))?
+            } else {
+                self.get_stores_for_shard(shard_id)?
+                    .shard_store
+                    .max_block_number()
+                    .map_err(|err| Status::internal(err.to_string()))?
+            };
+            shard_statuses.push(self.sync_progress.shard_status(shard_id, height, now));
+        }
+
+        Ok(Response::new(GetSyncStatusResponse {
+            synced: shard_statuses.iter().all(|status| status.synced),
+            shard_statuses,
+            connected_peers: self.sync_progress.connected_peers() as u32,
+        }))
+    }
+
+    async fn get_shard_stats(
+        &self,
+        request: Request<GetShardStatsRequest>,
+    ) -> Result<Response<GetShardStatsResponse>, Status> {
+        let mut shard_ids: Vec<u32> = self.shard_stores.keys().cloned().collect();
+        shard_ids.sort();
+        Ok(Response::new(GetShardStatsResponse {
+            shard_stats: self.shard_load.shard_stats(
+                &shard_ids,
+                request.get_ref().include_fid_activity,
+                Instant::now(),
+            ),
+        }))
+    }
+
+    async fn get_network_stats(
+        &self,
+        _request: Request<GetNetworkStatsRequest>,
+    ) -> Result<Response<GetNetworkStatsResponse>, Status> {
+        let mut shard_ids: Vec<u32> = self.shard_stores.keys().cloned().collect();
+        shard_ids.sort();
+        // Every shard's counts are read back to back, with nothing awaited in between, so they're
+        // as close to the same moment as the shards' separate dbs allow
+        let shard_stats = self
+            .shard_load
+            .shard_stats(&shard_ids, false, Instant::now());
+        let mut shard_totals = Vec::with_capacity(shard_ids.len());
+        for (shard_id, stats) in shard_ids.iter().zip(shard_stats) {
+            let stores = self.get_stores_for_shard(*shard_id)?;
+            let height = stores.shard_store.max_block_number().unwrap_or(0);
+            let num_fid_registrations = stores
+                .db
+                .count_keys_at_prefix(vec![
+                    RootPrefix::OnChainEvent as u8,
+                    OnChainEventPostfix::IdRegisterByFid as u8,
+                ])
+                .map_err(|err| Status::from_error(Box::new(err)))?
+                as u64;
+            let num_onchain_events = stores
+                .db
+                .count_keys_at_prefix(vec![
+                    RootPrefix::OnChainEvent as u8,
+                    OnChainEventPostfix::OnChainEvents as u8,
+                ])
+                .map_err(|err| Status::from_error(Box::new(err)))?
+                as u64;
+            shard_totals.push(proto::ShardTotals {
+                shard_id: *shard_id,
+                height,
+                num_messages: stores.get_trie_leaf_count(),
+                num_fid_registrations,
+                num_onchain_events,
+                stats: Some(stats),
+            });
+        }
+
+        let mut messages_written_by_type: BTreeMap<i32, u64> = BTreeMap::new();
+        for stats in shard_totals
+            .iter()
+            .filter_map(|totals| totals.stats.as_ref())
+        {
+            for count in &stats.messages_written_by_type {
+                *messages_written_by_type
+                    .entry(count.message_type)
+                    .or_default() += count.messages;
+            }
+        }
+        Ok(Response::new(GetNetworkStatsResponse {
+            num_messages: shard_totals.iter().map(|totals| totals.num_messages).sum(),
+            num_fid_registrations: shard_totals
+                .iter()
+                .map(|totals| totals.num_fid_registrations)
+                .sum(),
+            num_onchain_events: shard_totals
+                .iter()
+                .map(|totals| totals.num_onchain_events)
+                .sum(),
+            messages_written: shard_totals
+                .iter()
+                .filter_map(|totals| totals.stats.as_ref())
+                .map(|stats| stats.messages_written)
+                .sum(),
+            messages_written_by_type: messages_written_by_type
+                .into_iter()
+                .map(|(message_type, messages)| proto::MessageTypeCount {
+                    message_type,
+                    messages,
+                })
+                .collect(),
+            max_height: shard_totals
+                .iter()
+                .map(|totals| totals.height)
+                .max()
+                .unwrap_or(0),
+            min_height: shard_totals
+                .iter()
+                .map(|totals| totals.height)
+                .min()
+                .unwrap_or(0),
+            shard_totals,
+        }))
+    }
+
+    async fn get_fid_timeline(
+        &self,
+        request: Request<proto::GetFidTimelineRequest>,
+    ) -> Result<Response<proto::GetFidTimelineResponse>, Status> {
+        let fid = request.get_ref().fid;
+        let stores = self.get_stores_for(fid)?;
+        let registration = stores
+            .onchain_event_store
+            .get_id_register_event_by_fid(fid)
+            .map_err(|err| Status::internal(format!("Store error: {:?}", err)))?;
+        let activity_heights = ActivityHeights::get(&stores.db, fid)
+            .map_err(|err| Status::from_error(Box::new(err)))?;
+        if registration.is_none() && activity_heights.is_none() {
+            return Err(Status::not_found(format!("fid {} isn't registered", fid)));
+        }
+        Ok(Response::new(proto::GetFidTimelineResponse {
+            fid,
+            shard_id: stores.shard_id,
+            registration_block: registration.map(|event| event.block_number as u64),
+            first_message_height: activity_heights.map(|heights| heights.first),
+            last_message_height: activity_heights.map(|heights| heights.last),
         }))
     }
 
@@ -761,20 +1871,31 @@ impl HubService for MyHubService {
 
         let stores = self.get_stores_for_shard(inner_request.shard_id)?;
 
+        // Cap the page size so a single request can't scan the whole registry
         let page_options = PageOptions {
-            page_size: inner_request.page_size.map(|s| s as usize),
+            page_size: Some(
+                inner_request
+                    .page_size
+                    .map(|s| s as usize)
+                    .unwrap_or(PAGE_SIZE_MAX)
+                    .min(PAGE_SIZE_MAX),
+            ),
             page_token: inner_request.page_token,
             reverse: inner_request.reverse.unwrap_or(false),
         };
 
-        let (fids, next_page_token) = stores
+        let (registrations, next_page_token) = stores
             .onchain_event_store
-            .get_fids(&page_options)
-            .unwrap_or((vec![], None));
+            .get_fid_registrations(&page_options)
+            .map_err(|e| Status::internal(format!("Store error: {:?}", e)))?;
 
         Ok(Response::new(FidsResponse {
-            fids,
+            fids: registrations
+                .iter()
+                .map(|registration| registration.fid)
+                .collect(),
             next_page_token,
+            registrations,
         }))
     }
 
@@ -789,6 +1910,11 @@ impl HubService for MyHubService {
             request.get_ref().from_id,
             request.get_ref().shard_index
         );
+        if let Some(shard_id) = request.get_ref().shard_index {
+            self.check_shard_available(shard_id)?;
+        }
+        // Shared by the tasks streaming each shard, the subscriber is counted until they all end
+        let permit = Arc::new(self.acquire_subscriber_permit()?);
         let (server_tx, client_rx) = mpsc::channel::<Result<HubEvent, Status>>(100);
         let events_txs = match request.get_ref().shard_index {
             Some(shard_id) => match self.shard_senders.get(&(shard_id)) {
@@ -797,14 +1923,25 @@ impl HubService for MyHubService {
                         HubError::invalid_internal_state("Invalid shard id"),
                     )))
                 }
-                Some(senders) => vec![(shard_id, senders.events_tx.clone())],
+                Some(senders) => vec![(
+                    shard_id,
+                    senders.events_tx.clone(),
+                    self.shard_stores.get(&shard_id).cloned(),
+                )],
             },
             None => self
                 .shard_senders
                 .iter()
-                .map(|(shard_id, senders)| (*shard_id, senders.events_tx.clone()))
+                .map(|(shard_id, senders)| {
+                    (
+                        *shard_id,
+                        senders.events_tx.clone(),
+                        self.shard_stores.get(shard_id).cloned(),
+                    )
+                })
                 .collect(),
         };
+        let shutdown_signal = self.shutdown_signal.clone();
 
         let shard_stores = match request.get_ref().shard_index {
             Some(shard_id) => {
@@ -812,6 +1949,11 @@ impl HubService for MyHubService {
             }
             None => self.shard_stores.values().cloned().collect(),
         };
+        if let Some(from_id) = request.get_ref().from_id {
+            for store in &shard_stores {
+                Self::check_events_retained(store, from_id)?;
+            }
+        }
 
         let request = request.into_inner();
         let events = request.event_types;
@@ -872,17 +2014,59 @@ impl HubService for MyHubService {
             );
 
             // TODO(aditi): It's possible that events show up between when we finish reading from the db and the subscription starts. We don't handle this case in the current hub code, but we may want to down the line.
-            for (shard_id, event_tx) in events_txs {
+            for (shard_id, event_tx, stores) in events_txs {
                 let mut inner_events: Vec<i32> = Vec::new();
                 inner_events.resize(event_types_filter.len(), 0);
                 inner_events.copy_from_slice(event_types_filter.as_slice());
                 let tx = server_tx.clone();
+                let permit = permit.clone();
+                let drain = Self::wait_for_drain(shutdown_signal.clone());
                 tokio::spawn(async move {
+                    let _permit = permit;
                     let filtered_events = inner_events.clone();
+                    // The first event of the next height until one is received, events are only
+                    // sent once their height is committed
+                    let committed_height = stores
+                        .and_then(|stores| stores.shard_store.max_block_number().ok())
+                        .unwrap_or(0);
+                    let mut resume_from_id =
+                        HubEventIdGenerator::make_event_id(committed_height + 1, 0);
                     let mut event_rx = event_tx.subscribe();
+                    tokio::pin!(drain);
                     loop {
-                        match event_rx.recv().await {
+                        let event = tokio::select! {
+                            // Without this, a client that drops the stream would only be noticed
+                            // on the next matching event
+                            _ = tx.closed() => {
+                                info!("[subscribe] Client hung up on RPC, stopping event stream");
+                                break;
+                            }
+                            _ = &mut drain => {
+                                // Passes on the events already received first, so the client
+                                // resumes past them
+                                while let Ok(hub_event) = event_rx.try_recv() {
+                                    resume_from_id = hub_event.id + 1;
+                                    if filtered_events.contains(&hub_event.r#type) {
+                                        let hub_event =
+                                            Self::rewrite_hub_event(hub_event, shard_id, None);
+                                        _ = tx.send(Ok(hub_event)).await;
+                                    }
+                                }
+                                _ = tx
+                                    .send(Ok(Self::draining_event(shard_id, resume_from_id)))
+                                    .await;
+                                info!(
+                                    shard_id,
+                                    resume_from_id,
+                                    "[subscribe] Node shutting down, closing event stream"
+                                );
+                                break;
+                            }
+                            event = event_rx.recv() => event,
+                        };
+                        match event {
                             Ok(hub_event) => {
+                                resume_from_id = hub_event.id + 1;
                                 if filtered_events.contains(&hub_event.r#type) {
                                     let hub_event =
                                         Self::rewrite_hub_event(hub_event, shard_id, None);
@@ -918,6 +2102,7 @@ impl HubService for MyHubService {
         let request = request.into_inner();
         // Not sure this is the correct way to be handling the shard
         let stores = self.get_stores_for_shard(request.shard_index)?;
+        Self::check_events_retained(stores, request.id)?;
         let hub_event_result = stores.get_event(request.id);
 
         match hub_event_result {
@@ -960,6 +2145,7 @@ impl HubService for MyHubService {
             }
             Some(index) => {
                 num_shards = 1;
+                self.check_shard_available(index)?;
                 shard_stores = match self.shard_stores.get(&index) {
                     Some(store) => {
                         vec![store]
@@ -979,6 +2165,9 @@ impl HubService for MyHubService {
                 "Page token does not match number of shards".to_string(),
             ));
         }
+        for store in &shard_stores {
+            Self::check_events_retained(store, req.start_id)?;
+        }
         let pages: Vec<EventsPage> = shard_stores
             .iter()
             .zip(per_shard_tokens.into_iter())
@@ -1012,10 +2201,147 @@ impl HubService for MyHubService {
         Ok(Response::new(response))
     }
 
+    async fn get_message_by_hash(
+        &self,
+        request: Request<MessageByHashRequest>,
+    ) -> Result<Response<proto::Message>, Status> {
+        let hash = request.into_inner().hash;
+        if hash.len() != HASH_LENGTH {
+            return Err(Status::invalid_argument(format!(
+                "hash must be {} bytes",
+                HASH_LENGTH
+            )));
+        }
+        // Without the fid there's no telling which shard has the message, but each lookup is a
+        // couple of reads of the shard's hash index
+        for stores in self.shard_stores.values() {
+            match stores.get_message_by_hash(&hash) {
+                Ok(Some(message)) => return Ok(Response::new(message)),
+                Ok(None) => {}
+                Err(err) => return Err(Status::internal(err.to_string())),
+            }
+        }
+        Err(Status::not_found(format!(
+            "no message with hash {}",
+            hex::encode(&hash)
+        )))
+    }
+
+    async fn has_messages(
+        &self,
+        request: Request<proto::HasMessagesRequest>,
+    ) -> Result<Response<proto::HasMessagesResponse>, Status> {
+        let hashes = request.into_inner().hashes;
+        if hashes.len() > MAX_HAS_MESSAGES_HASHES {
+            return Err(Status::invalid_argument(format!(
+                "at most {} hashes per request",
+                MAX_HAS_MESSAGES_HASHES
+            )));
+        }
+        if hashes.iter().any(|hash| hash.len() != HASH_LENGTH) {
+            return Err(Status::invalid_argument(format!(
+                "hashes must be {} bytes",
+                HASH_LENGTH
+            )));
+        }
+        let mut present = vec![false; hashes.len()];
+        for stores in self.shard_stores.values() {
+            let found = stores
+                .has_messages(&hashes)
+                .map_err(|err| Status::internal(err.to_string()))?;
+            for (present, found) in present.iter_mut().zip(found) {
+                *present |= found;
+            }
+        }
+        Ok(Response::new(proto::HasMessagesResponse { present }))
+    }
+
+    async fn get_message_with_proof(
+        &self,
+        request: Request<MessageByHashRequest>,
+    ) -> Result<Response<proto::MessageWithProof>, Status> {
+        let hash = request.into_inner().hash;
+        if hash.len() != HASH_LENGTH {
+            return Err(Status::invalid_argument(format!(
+                "hash must be {} bytes",
+                HASH_LENGTH
+            )));
+        }
+        for stores in self.shard_stores.values() {
+            let message = match stores.get_message_by_hash(&hash) {
+                Ok(Some(message)) => message,
+                Ok(None) => continue,
+                Err(err) => return Err(Status::internal(err.to_string())),
+            };
+            // Pruned or removed since it was read, if it's no longer in the trie
+            let proof = Self::committed_proof(stores, &[TrieKey::for_message(&message)]).await?;
+            return Ok(Response::new(proto::MessageWithProof {
+                message: Some(message),
+                proof: Some(proof),
+            }));
+        }
+        Err(Status::not_found(format!(
+            "no message with hash {}",
+            hex::encode(&hash)
+        )))
+    }
+
+    async fn get_multi_proof(
+        &self,
+        request: Request<proto::GetMultiProofRequest>,
+    ) -> Result<Response<proto::MultiProof>, Status> {
+        let hashes = request.into_inner().hashes;
+        if hashes.is_empty() || hashes.len() > MAX_MULTIPROOF_HASHES {
+            return Err(Status::invalid_argument(format!(
+                "between 1 and {} hashes per request",
+                MAX_MULTIPROOF_HASHES
+            )));
+        }
+        if hashes.iter().any(|hash| hash.len() != HASH_LENGTH) {
+            return Err(Status::invalid_argument(format!(
+                "hashes must be {} bytes",
+                HASH_LENGTH
+            )));
+        }
+
+        // The proof is against a single shard root, so the messages all have to be in one shard
+        let mut shard: Option<(u32, &Stores)> = None;
+        let mut trie_keys = vec![];
+        for hash in &hashes {
+            let mut found = None;
+            for (shard_id, stores) in &self.shard_stores {
+                if let Some(message) = stores
+                    .get_message_by_hash(hash)
+                    .map_err(|err| Status::internal(err.to_string()))?
+                {
+                    found = Some((*shard_id, stores, message));
+                    break;
+                }
+            }
+            let (shard_id, stores, message) = found.ok_or_else(|| {
+                Status::not_found(format!("no message with hash {}", hex::encode(hash)))
+            })?;
+            match shard {
+                Some((first_shard_id, _)) if first_shard_id != shard_id => {
+                    return Err(Status::invalid_argument(
+                        "the messages must all be in the same shard",
+                    ));
+                }
+                _ => shard = Some((shard_id, stores)),
+            }
+            trie_keys.push(TrieKey::for_message(&message));
+        }
+
+        let (_, stores) = shard.unwrap();
+        Self::committed_multiproof(stores, trie_keys)
+            .await
+            .map(Response::new)
+    }
+
     async fn get_cast(&self, request: Request<CastId>) -> Result<Response<proto::Message>, Status> {
         let cast_id = request.into_inner();
         let stores = self.get_stores_for(cast_id.fid)?;
-        CastStore::get_cast_add(&stores.cast_store, cast_id.fid, cast_id.hash).as_response()
+        stores.get_cast_add(cast_id.fid, cast_id.hash).as_response()
     }
 
     async fn get_casts_by_fid(
@@ -1023,9 +2349,11 @@ impl HubService for MyHubService {
         request: Request<FidRequest>,
     ) -> Result<Response<proto::MessagesResponse>, Status> {
         let request = request.into_inner();
+        let fields = request.fields();
         let stores = self.get_stores_for(request.fid)?;
         let options = request.page_options();
-        CastStore::get_cast_adds_by_fid(&stores.cast_store, request.fid, &options).as_response()
+        CastStore::get_cast_adds_by_fid(&stores.cast_store, request.fid, &options)
+            .as_projected_response(fields)
     }
 
     async fn get_all_cast_messages_by_fid(
@@ -1033,12 +2361,13 @@ impl HubService for MyHubService {
         request: Request<FidTimestampRequest>,
     ) -> Result<Response<proto::MessagesResponse>, Status> {
         let request = request.into_inner();
+        let fields = request.fields();
         let stores = self.get_stores_for(request.fid)?;
         let (start_ts, stop_ts) = request.timestamps();
         stores
             .cast_store
             .get_all_messages_by_fid(request.fid, start_ts, stop_ts, &request.page_options())
-            .as_response()
+            .as_projected_response(fields)
     }
 
     async fn get_reaction(
@@ -1070,6 +2399,7 @@ impl HubService for MyHubService {
         request: Request<ReactionsByFidRequest>,
     ) -> Result<Response<MessagesResponse>, Status> {
         let request = request.into_inner();
+        let fields = request.fields();
         let stores = self.get_stores_for(request.fid)?;
         let options = request.page_options();
         ReactionStore::get_reaction_adds_by_fid(
@@ -1078,7 +2408,7 @@ impl HubService for MyHubService {
             request.reaction_type.unwrap_or(0),
             &options,
         )
-        .as_response()
+        .as_projected_response(fields)
     }
 
     async fn get_all_reaction_messages_by_fid(
@@ -1086,12 +2416,13 @@ impl HubService for MyHubService {
         request: Request<FidTimestampRequest>,
     ) -> Result<Response<proto::MessagesResponse>, Status> {
         let request = request.into_inner();
+        let fields = request.fields();
         let stores = self.get_stores_for(request.fid)?;
         let (start_ts, stop_ts) = request.timestamps();
         stores
             .reaction_store
             .get_all_messages_by_fid(request.fid, start_ts, stop_ts, &request.page_options())
-            .as_response()
+            .as_projected_response(fields)
     }
 
     async fn get_link(&self, request: Request<LinkRequest>) -> Result<Response<Message>, Status> {
@@ -1112,6 +2443,7 @@ impl HubService for MyHubService {
         request: Request<LinksByFidRequest>,
     ) -> Result<Response<MessagesResponse>, Status> {
         let request = request.into_inner();
+        let fields = request.fields();
         let stores = self.get_stores_for(request.fid)?;
         let options = request.page_options();
         LinkStore::get_link_adds_by_fid(
@@ -1120,7 +2452,7 @@ impl HubService for MyHubService {
             request.link_type.unwrap_or("".to_string()),
             &options,
         )
-        .as_response()
+        .as_projected_response(fields)
     }
 
     async fn get_all_link_messages_by_fid(
@@ -1128,12 +2460,78 @@ impl HubService for MyHubService {
         request: Request<FidTimestampRequest>,
     ) -> Result<Response<MessagesResponse>, Status> {
         let request = request.into_inner();
+        let fields = request.fields();
         let stores = self.get_stores_for(request.fid)?;
         let (start_ts, stop_ts) = request.timestamps();
         stores
             .link_store
             .get_all_messages_by_fid(request.fid, start_ts, stop_ts, &request.page_options())
-            .as_response()
+            .as_projected_response(fields)
+    }
+
+    async fn get_cast_compact_state_by_fid(
+        &self,
+        request: Request<FidRequest>,
+    ) -> Result<Response<MessagesResponse>, Status> {
+        let request = request.into_inner();
+        let fields = request.fields();
+        let stores = self.get_stores_for(request.fid)?;
+        stores
+            .cast_store
+            .get_compact_state(request.fid, &request.page_options())
+            .as_projected_response(fields)
+    }
+
+    async fn get_reaction_compact_state_by_fid(
+        &self,
+        request: Request<FidRequest>,
+    ) -> Result<Response<MessagesResponse>, Status> {
+        let request = request.into_inner();
+        let fields = request.fields();
+        let stores = self.get_stores_for(request.fid)?;
+        stores
+            .reaction_store
+            .get_compact_state(request.fid, &request.page_options())
+            .as_projected_response(fields)
+    }
+
+    async fn get_verification_compact_state_by_fid(
+        &self,
+        request: Request<FidRequest>,
+    ) -> Result<Response<MessagesResponse>, Status> {
+        let request = request.into_inner();
+        let fields = request.fields();
+        let stores = self.get_stores_for(request.fid)?;
+        stores
+            .verification_store
+            .get_compact_state(request.fid, &request.page_options())
+            .as_projected_response(fields)
+    }
+
+    async fn get_user_data_compact_state_by_fid(
+        &self,
+        request: Request<FidRequest>,
+    ) -> Result<Response<MessagesResponse>, Status> {
+        let request = request.into_inner();
+        let fields = request.fields();
+        let stores = self.get_stores_for(request.fid)?;
+        stores
+            .user_data_store
+            .get_compact_state(request.fid, &request.page_options())
+            .as_projected_response(fields)
+    }
+
+    async fn get_link_compact_state_by_fid(
+        &self,
+        request: Request<FidRequest>,
+    ) -> Result<Response<MessagesResponse>, Status> {
+        let request = request.into_inner();
+        let fields = request.fields();
+        let stores = self.get_stores_for(request.fid)?;
+        stores
+            .link_store
+            .get_compact_state(request.fid, &request.page_options())
+            .as_projected_response(fields)
     }
 
     async fn get_user_data(
@@ -1157,6 +2555,7 @@ impl HubService for MyHubService {
         request: Request<FidRequest>,
     ) -> Result<Response<MessagesResponse>, Status> {
         let request = request.into_inner();
+        let fields = request.fields();
         let stores = self.get_stores_for(request.fid)?;
         let options = request.page_options();
         UserDataStore::get_user_data_adds_by_fid(
@@ -1166,7 +2565,7 @@ impl HubService for MyHubService {
             None,
             None,
         )
-        .as_response()
+        .as_projected_response(fields)
     }
 
     async fn get_all_user_data_messages_by_fid(
@@ -1174,25 +2573,46 @@ impl HubService for MyHubService {
         request: Request<FidTimestampRequest>,
     ) -> Result<Response<MessagesResponse>, Status> {
         let request = request.into_inner();
+        let fields = request.fields();
         let stores = self.get_stores_for(request.fid)?;
         let (start_ts, stop_ts) = request.timestamps();
         stores
             .user_data_store
             .get_all_messages_by_fid(request.fid, start_ts, stop_ts, &request.page_options())
-            .as_response()
+            .as_projected_response(fields)
     }
 
     async fn validate_message(
         &self,
         request: Request<Message>,
     ) -> Result<Response<ValidationResponse>, Status> {
-        let request = request.into_inner();
-        let result = validations::message::validate_message(&request, self.network)
-            .map_or_else(|_| false, |_| true);
+        let mut message = request.into_inner();
+        message_bytes_decode(&mut message);
+
+        let result = match self.get_stores_for_message(&message) {
+            // Frame actions can't be submitted, so only the message and its signer are checked
+            Ok(stores) if message.msg_type() == MessageType::FrameAction => self
+                .readonly_engine(stores)
+                .validate_user_message(&message, &mut RocksDbTransactionBatch::new())
+                .map_err(|err| HubError::validation_failure(&err.to_string())),
+            Ok(_) => self
+                .check_admission(&message, false)
+                .await
+                .map(|_| ())
+                .map_err(|rejection| rejection.error),
+            Err(err) => Err(err),
+        };
+
+        let (error_code, error_message) = match &result {
+            Ok(()) => ("".to_string(), "".to_string()),
+            Err(err) => (err.code.clone(), err.message.clone()),
+        };
 
         Ok(Response::new(ValidationResponse {
-            valid: result,
-            message: Some(request),
+            valid: result.is_ok(),
+            message: Some(message),
+            error_code,
+            error_message,
         }))
     }
 
@@ -1212,17 +2632,24 @@ impl HubService for MyHubService {
 
     async fn get_verifications_by_fid(
         &self,
-        request: Request<FidRequest>,
+        request: Request<VerificationsByFidRequest>,
     ) -> Result<Response<MessagesResponse>, Status> {
         let request = request.into_inner();
+        let fields = request.fields();
+        let protocol = request
+            .protocol
+            .map(proto::Protocol::try_from)
+            .transpose()
+            .map_err(|_| Status::invalid_argument("unknown protocol"))?;
         let stores = self.get_stores_for(request.fid)?;
         let options = request.page_options();
         VerificationStore::get_verification_adds_by_fid(
             &stores.verification_store,
             request.fid,
+            protocol,
             &options,
         )
-        .as_response()
+        .as_projected_response(fields)
     }
 
     async fn get_all_verification_messages_by_fid(
@@ -1230,12 +2657,13 @@ impl HubService for MyHubService {
         request: Request<FidTimestampRequest>,
     ) -> Result<Response<MessagesResponse>, Status> {
         let request = request.into_inner();
+        let fields = request.fields();
         let stores = self.get_stores_for(request.fid)?;
         let (start_ts, stop_ts) = request.timestamps();
         stores
             .verification_store
             .get_all_messages_by_fid(request.fid, start_ts, stop_ts, &request.page_options())
-            .as_response()
+            .as_projected_response(fields)
     }
 
     async fn get_link_compact_state_message_by_fid(
@@ -1243,10 +2671,11 @@ impl HubService for MyHubService {
         request: Request<FidRequest>,
     ) -> Result<Response<MessagesResponse>, Status> {
         let request = request.into_inner();
+        let fields = request.fields();
         let stores = self.get_stores_for(request.fid)?;
         let options = request.page_options();
         LinkStore::get_link_compact_state_message_by_fid(&stores.link_store, request.fid, &options)
-            .as_response()
+            .as_projected_response(fields)
     }
 
     async fn get_current_storage_limits_by_fid(
@@ -1261,6 +2690,39 @@ impl HubService for MyHubService {
         Ok(Response::new(limits))
     }
 
+    async fn get_storage_bytes_by_fid(
+        &self,
+        request: Request<FidRequest>,
+    ) -> Result<Response<StorageBytesResponse>, Status> {
+        let request = request.into_inner();
+        let stores = self.get_stores_for(request.fid)?;
+        let storage_bytes = stores
+            .get_storage_bytes(request.fid)
+            .map_err(|err| Status::internal(err.to_string()))?;
+        Ok(Response::new(storage_bytes))
+    }
+
+    async fn get_storage_usage(
+        &self,
+        request: Request<proto::GetStorageUsageRequest>,
+    ) -> Result<Response<proto::GetStorageUsageResponse>, Status> {
+        let request = request.into_inner();
+        let stores = self.get_stores_for(request.fid)?;
+        let usage = stores
+            .get_storage_usage(request.fid)
+            .map_err(|err| Status::internal(err.to_string()))?;
+        Ok(Response::new(usage))
+    }
+
+    async fn get_fid_activity_counts(
+        &self,
+        request: Request<GetFidActivityCountsRequest>,
+    ) -> Result<Response<GetFidActivityCountsResponse>, Status> {
+        let request = request.into_inner();
+        let stores = self.get_stores_for(request.fid)?;
+        Ok(Response::new(stores.get_activity_counts(request.fid)))
+    }
+
     async fn get_casts_by_parent(
         &self,
         request: Request<CastsByParentRequest>,
@@ -1363,10 +2825,15 @@ impl HubService for MyHubService {
                 })
                 .collect();
 
-        let combined_messages: Vec<Message> = pages
+        // Every shard's page is in time order, but the casts mentioning an fid can be on any shard
+        let mut combined_messages: Vec<Message> = pages
             .iter()
             .flat_map(|page| page.messages.clone())
             .collect();
+        combined_messages.sort_by_key(|message| message.data.as_ref().map(|data| data.timestamp));
+        if req.reverse.unwrap_or(false) {
+            combined_messages.reverse();
+        }
 
         let next_page_tokens: Vec<Option<Vec<u8>>> =
             pages.into_iter().map(|page| page.next_page_token).collect();
@@ -1382,6 +2849,29 @@ impl HubService for MyHubService {
         Ok(Response::new(response))
     }
 
+    async fn get_recent_casts(
+        &self,
+        request: Request<RecentCastsRequest>,
+    ) -> Result<Response<MessagesResponse>, Status> {
+        let request = request.into_inner();
+        let stores = self.get_stores_for_shard(request.shard_id)?;
+        let limit = request
+            .limit
+            .map(|limit| limit as usize)
+            .unwrap_or(RECENT_CASTS_DEFAULT_LIMIT)
+            .min(PAGE_SIZE_MAX);
+        CastStore::get_recent_casts(
+            &stores.cast_store,
+            request.before_timestamp,
+            &PageOptions {
+                page_size: Some(limit),
+                page_token: request.page_token,
+                reverse: true,
+            },
+        )
+        .as_response()
+    }
+
     async fn get_reactions_by_cast(
         &self,
         request: Request<ReactionsByTargetRequest>,
@@ -1683,6 +3173,36 @@ impl HubService for MyHubService {
         Ok(Response::new(response))
     }
 
+    async fn get_user_name_proofs_by_name(
+        &self,
+        request: Request<UsernameProofRequest>,
+    ) -> Result<Response<UsernameProofsResponse>, Status> {
+        let req = request.into_inner();
+        if req.name.is_empty() {
+            return Err(Status::invalid_argument("name is required".to_string()));
+        }
+        let name_str = std::str::from_utf8(&req.name).unwrap_or("");
+
+        // ENS proofs are messages in the username_proof_store, fnames are in the user_data_store.
+        // Each shard only has the proofs of its fids, so the histories are combined.
+        let mut proofs = Vec::new();
+        for (_shard_id, stores) in &self.shard_stores {
+            let shard_proofs = if name_str.ends_with(".eth") {
+                UsernameProofStore::get_username_proof_history(
+                    &stores.username_proof_store,
+                    &req.name,
+                )
+            } else {
+                UserDataStore::get_username_proof_history(&stores.user_data_store, &req.name)
+            }
+            .map_err(|e| Status::internal(format!("Store error: {:?}", e)))?;
+            proofs.extend(shard_proofs);
+        }
+        proofs.sort_by_key(|proof| (proof.timestamp, proof.fid));
+
+        Ok(Response::new(UsernameProofsResponse { proofs }))
+    }
+
     async fn get_on_chain_signer(
         &self,
         request: Request<SignerRequest>,
@@ -1738,6 +3258,35 @@ impl HubService for MyHubService {
         Ok(Response::new(response))
     }
 
+    async fn get_signers(
+        &self,
+        request: Request<proto::SignersRequest>,
+    ) -> Result<Response<proto::SignersResponse>, Status> {
+        let fid = request.into_inner().fid;
+        let stores = self.get_stores_for(fid)?;
+        let events = stores
+            .onchain_event_store
+            .get_active_signers(fid)
+            .map_err(|e| Status::internal(format!("Store error: {:?}", e)))?;
+
+        let signers = events
+            .into_iter()
+            .filter_map(|event| match event.body {
+                Some(Body::SignerEventBody(body)) => Some(proto::ActiveSigner {
+                    key: body.key,
+                    key_type: body.key_type,
+                    metadata_type: body.metadata_type,
+                    metadata: body.metadata,
+                    added_block_number: event.block_number,
+                    added_block_timestamp: event.block_timestamp,
+                    added_transaction_hash: event.transaction_hash,
+                }),
+                _ => None,
+            })
+            .collect();
+        Ok(Response::new(proto::SignersResponse { signers }))
+    }
+
     async fn get_on_chain_events(
         &self,
         request: Request<OnChainEventRequest>,
@@ -1764,6 +3313,99 @@ impl HubService for MyHubService {
         Ok(Response::new(response))
     }
 
+    async fn get_on_chain_events_by_fid(
+        &self,
+        request: Request<OnChainEventsByFidRequest>,
+    ) -> Result<Response<OnChainEventResponse>, Status> {
+        let req = request.into_inner();
+        let event_type = match proto::OnChainEventType::try_from(req.event_type) {
+            Ok(proto::OnChainEventType::EventTypeNone) => None,
+            Ok(event_type) => Some(event_type),
+            Err(_) => return Err(Status::invalid_argument("Invalid event type")),
+        };
+
+        let stores = self.get_stores_for(req.fid)?;
+        let page = stores
+            .onchain_event_store
+            .get_onchain_events_by_fid(
+                req.fid,
+                event_type,
+                req.start_block_number,
+                req.stop_block_number,
+                &req.page_options(),
+            )
+            .map_err(|e| Status::internal(format!("Store error: {:?}", e)))?;
+
+        Ok(Response::new(OnChainEventResponse {
+            events: page.onchain_events,
+            next_page_token: page.next_page_token,
+        }))
+    }
+
+    async fn get_on_chain_events_by_block_range(
+        &self,
+        request: Request<OnChainEventsByBlockRangeRequest>,
+    ) -> Result<Response<OnChainEventResponse>, Status> {
+        let req = request.into_inner();
+        if req.stop_block <= req.start_block {
+            return Err(Status::invalid_argument(
+                "stop_block must be greater than start_block",
+            ));
+        }
+        let page_size = req
+            .page_size
+            .map_or(PAGE_SIZE_MAX, |page_size| page_size as usize)
+            .min(PAGE_SIZE_MAX);
+        record_query_param("page_size", page_size as u64);
+
+        // Every shard has the events of its own fids. The index keys sort the same on every
+        // shard, so the first page of each one holds the first page of all of them.
+        let mut events = vec![];
+        let mut more = false;
+        for stores in self.shard_stores.values() {
+            let page = stores
+                .onchain_event_store
+                .get_onchain_events_by_block_range(
+                    req.chain_id,
+                    req.start_block,
+                    req.stop_block,
+                    &PageOptions {
+                        page_size: Some(page_size),
+                        page_token: req.page_token.clone(),
+                        reverse: false,
+                    },
+                )
+                .map_err(|err| match err {
+                    OnchainEventStorageError::HubError(err)
+                        if err.code == "bad_request.invalid_param" =>
+                    {
+                        Status::invalid_argument(err.message)
+                    }
+                    err => Status::internal(format!("Store error: {:?}", err)),
+                })?;
+            more |= page.next_page_token.is_some();
+            events.extend(
+                page.onchain_events
+                    .into_iter()
+                    .map(|event| (make_onchain_event_by_block_key(&event), event)),
+            );
+        }
+        events.sort_by(|(a, _), (b, _)| a.cmp(b));
+        // Keeps one copy of an event that's on several shards
+        events.dedup_by(|(a, _), (b, _)| a == b);
+        more |= events.len() > page_size;
+        events.truncate(page_size);
+
+        Ok(Response::new(OnChainEventResponse {
+            next_page_token: if more {
+                events.last().map(|(key, _)| key.clone())
+            } else {
+                None
+            },
+            events: events.into_iter().map(|(_, event)| event).collect(),
+        }))
+    }
+
     async fn get_id_registry_on_chain_event(
         &self,
         request: Request<FidRequest>,
@@ -1900,6 +3542,102 @@ impl HubService for MyHubService {
         Ok(Response::new(response))
     }
 
+    type StreamAllCastMessagesByFidStream = ReceiverStream<Result<MessagesResponse, Status>>;
+
+    async fn stream_all_cast_messages_by_fid(
+        &self,
+        request: Request<FidTimestampRequest>,
+    ) -> Result<Response<Self::StreamAllCastMessagesByFidStream>, Status> {
+        let request = request.into_inner();
+        let fields = request.fields();
+        let store = self.get_stores_for(request.fid)?.cast_store.clone();
+        let (start_ts, stop_ts) = request.timestamps();
+        Ok(Response::new(stream_messages_pages(
+            request.page_options(),
+            fields,
+            move |page_options| {
+                store.get_all_messages_by_fid(request.fid, start_ts, stop_ts, page_options)
+            },
+        )))
+    }
+
+    type StreamAllReactionMessagesByFidStream = ReceiverStream<Result<MessagesResponse, Status>>;
+
+    async fn stream_all_reaction_messages_by_fid(
+        &self,
+        request: Request<FidTimestampRequest>,
+    ) -> Result<Response<Self::StreamAllReactionMessagesByFidStream>, Status> {
+        let request = request.into_inner();
+        let fields = request.fields();
+        let store = self.get_stores_for(request.fid)?.reaction_store.clone();
+        let (start_ts, stop_ts) = request.timestamps();
+        Ok(Response::new(stream_messages_pages(
+            request.page_options(),
+            fields,
+            move |page_options| {
+                store.get_all_messages_by_fid(request.fid, start_ts, stop_ts, page_options)
+            },
+        )))
+    }
+
+    type StreamAllVerificationMessagesByFidStream =
+        ReceiverStream<Result<MessagesResponse, Status>>;
+
+    async fn stream_all_verification_messages_by_fid(
+        &self,
+        request: Request<FidTimestampRequest>,
+    ) -> Result<Response<Self::StreamAllVerificationMessagesByFidStream>, Status> {
+        let request = request.into_inner();
+        let fields = request.fields();
+        let store = self.get_stores_for(request.fid)?.verification_store.clone();
+        let (start_ts, stop_ts) = request.timestamps();
+        Ok(Response::new(stream_messages_pages(
+            request.page_options(),
+            fields,
+            move |page_options| {
+                store.get_all_messages_by_fid(request.fid, start_ts, stop_ts, page_options)
+            },
+        )))
+    }
+
+    type StreamAllUserDataMessagesByFidStream = ReceiverStream<Result<MessagesResponse, Status>>;
+
+    async fn stream_all_user_data_messages_by_fid(
+        &self,
+        request: Request<FidTimestampRequest>,
+    ) -> Result<Response<Self::StreamAllUserDataMessagesByFidStream>, Status> {
+        let request = request.into_inner();
+        let fields = request.fields();
+        let store = self.get_stores_for(request.fid)?.user_data_store.clone();
+        let (start_ts, stop_ts) = request.timestamps();
+        Ok(Response::new(stream_messages_pages(
+            request.page_options(),
+            fields,
+            move |page_options| {
+                store.get_all_messages_by_fid(request.fid, start_ts, stop_ts, page_options)
+            },
+        )))
+    }
+
+    type StreamAllLinkMessagesByFidStream = ReceiverStream<Result<MessagesResponse, Status>>;
+
+    async fn stream_all_link_messages_by_fid(
+        &self,
+        request: Request<FidTimestampRequest>,
+    ) -> Result<Response<Self::StreamAllLinkMessagesByFidStream>, Status> {
+        let request = request.into_inner();
+        let fields = request.fields();
+        let store = self.get_stores_for(request.fid)?.link_store.clone();
+        let (start_ts, stop_ts) = request.timestamps();
+        Ok(Response::new(stream_messages_pages(
+            request.page_options(),
+            fields,
+            move |page_options| {
+                store.get_all_messages_by_fid(request.fid, start_ts, stop_ts, page_options)
+            },
+        )))
+    }
+
     async fn get_trie_metadata_by_prefix(
         &self,
         request: Request<TrieNodeMetadataRequest>,
@@ -1914,14 +3652,16 @@ impl HubService for MyHubService {
                 &request.prefix,
             )
             .map_err(|err| Status::internal(err.to_string()))?;
-        let children = trie_node
-            .children
-            .values()
+        let mut children: Vec<_> = trie_node.children.into_values().collect();
+        children.sort_by(|a, b| a.prefix.cmp(&b.prefix));
+        let children = children
+            .into_iter()
             .map(|child_node| TrieNodeMetadataResponse {
-                prefix: child_node.prefix.clone(),
+                prefix: child_node.prefix,
                 num_messages: child_node.num_messages as u64,
-                hash: child_node.hash.clone(),
+                hash: child_node.hash,
                 children: vec![],
+                key: child_node.key.unwrap_or_default(),
             })
             .collect();
         Ok(Response::new(TrieNodeMetadataResponse {
@@ -1929,6 +3669,486 @@ impl HubService for MyHubService {
             num_messages: trie_node.num_messages as u64,
             hash: trie_node.hash,
             children,
+            key: trie_node.key.unwrap_or_default(),
+        }))
+    }
+
+    async fn get_validator_set(
+        &self,
+        request: Request<ValidatorSetRequest>,
+    ) -> Result<Response<ValidatorSetResponse>, Status> {
+        let shard_id = request.into_inner().shard_id;
+        let validator_sets = self.validator_sets.get(&shard_id).ok_or_else(|| {
+            Status::invalid_argument(format!("no validators for shard {}", shard_id))
+        })?;
+
+        let height = self.confirmed_height(shard_id)? + 1;
+
+        let stored_set = validator_sets.get_stored_validator_set(height);
+        // Weighed by stake when the shard is
+        let validator_set = validator_sets
+            .get_validator_set(height)
+            .map_err(|err| Status::internal(err.to_string()))?;
+        let validators = validator_set
+            .validators
+            .iter()
+            .map(|validator| proto::ValidatorInfo {
+                public_key: validator.public_key.to_bytes().to_vec(),
+                voting_power: validator.voting_power(),
+            })
+            .collect();
+        // The set can change at a later height, so each height uses the set in effect then. The
+        // proposer doesn't depend on the stakes.
+        let proposer_schedule = (height..height + PROPOSER_SCHEDULE_HEIGHTS)
+            .map(|height| proto::ProposerScheduleEntry {
+                height,
+                public_key: validator_sets
+                    .get_stored_validator_set(height)
+                    .validators
+                    .proposer(height, 0)
+                    .public_key
+                    .to_bytes()
+                    .to_vec(),
+            })
+            .collect();
+
+        Ok(Response::new(ValidatorSetResponse {
+            shard_id,
+            height,
+            effective_at: stored_set.effective_at,
+            validators,
+            total_voting_power: validator_set.total_voting_power(),
+            proposer_schedule,
+        }))
+    }
+
+    async fn get_epoch_info(
+        &self,
+        request: Request<proto::GetEpochInfoRequest>,
+    ) -> Result<Response<proto::GetEpochInfoResponse>, Status> {
+        let shard_id = request.into_inner().shard_id;
+        let validator_sets = self.validator_sets.get(&shard_id).ok_or_else(|| {
+            Status::invalid_argument(format!("no validators for shard {}", shard_id))
+        })?;
+        let height = self.confirmed_height(shard_id)? + 1;
+
+        let response = match validator_sets.stakes() {
+            Some(stakes) => {
+                let epoch = stakes.epoch(height);
+                proto::GetEpochInfoResponse {
+                    shard_id,
+                    height,
+                    epoch,
+                    epoch_start_height: stakes.epoch_start_height(epoch),
+                    next_epoch_height: Some(stakes.epoch_start_height(epoch + 1)),
+                    epoch_length: stakes.epoch_length(),
+                }
+            }
+            // Votes are weighed the same at every height
+            None => proto::GetEpochInfoResponse {
+                shard_id,
+                height,
+                epoch: 0,
+                epoch_start_height: 0,
+                next_epoch_height: None,
+                epoch_length: 0,
+            },
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn get_shard_root(
+        &self,
+        request: Request<ShardRootRequest>,
+    ) -> Result<Response<ShardRootResponse>, Status> {
+        let shard_id = request.into_inner().shard_id;
+        let stores = self.get_stores_for_shard(shard_id)?;
+        let (height, root_hash) = Self::committed_shard_root(stores)?;
+        Ok(Response::new(ShardRootResponse {
+            shard_id,
+            height,
+            root_hash,
+        }))
+    }
+
+    async fn get_trie_roots_at_heights(
+        &self,
+        request: Request<proto::GetTrieRootsAtHeightsRequest>,
+    ) -> Result<Response<proto::GetTrieRootsAtHeightsResponse>, Status> {
+        let request = request.into_inner();
+        let shard_id = request.shard_id;
+        if request.heights.len() > MAX_TRIE_ROOT_HEIGHTS {
+            return Err(Status::invalid_argument(format!(
+                "at most {} heights can be looked up at once",
+                MAX_TRIE_ROOT_HEIGHTS
+            )));
+        }
+        let stores = self.get_stores_for_shard(shard_id)?;
+
+        // Only the headers are read, which hold the root the chunk was committed with
+        let mut roots = Vec::with_capacity(request.heights.len());
+        for height in request.heights {
+            let header = stores
+                .shard_store
+                .get_chunk_header_by_height(height)
+                .map_err(|err| Status::internal(err.to_string()))?;
+            roots.push(match header {
+                Some(header) => proto::TrieRootAtHeight {
+                    height,
+                    root_hash: header.shard_root,
+                    found: true,
+                },
+                None => proto::TrieRootAtHeight {
+                    height,
+                    root_hash: vec![],
+                    found: false,
+                },
+            });
+        }
+        Ok(Response::new(proto::GetTrieRootsAtHeightsResponse {
+            shard_id,
+            roots,
+        }))
+    }
+
+    async fn get_shard_manifest(
+        &self,
+        request: Request<GetShardManifestRequest>,
+    ) -> Result<Response<GetShardManifestResponse>, Status> {
+        let shard_id = request.into_inner().shard_id;
+        let stores = self.get_stores_for_shard(shard_id)?;
+        let keypair = self
+            .keypair
+            .as_ref()
+            .ok_or_else(|| Status::unavailable("no node key to sign the manifest with"))?;
+
+        // The counts and proofs are read one after the other, so they only describe one height if
+        // nothing was committed in the meantime
+        for _ in 0..PROOF_ATTEMPTS {
+            let (height, root_hash) = Self::committed_shard_root(stores)?;
+            let counted = stores.clone();
+            let (message_counts, num_onchain_events, num_fnames, sampled_keys) =
+                tokio::task::spawn_blocking(move || {
+                    Ok::<_, HubError>((
+                        counted.get_message_counts_by_store()?,
+                        counted.get_onchain_event_count()?,
+                        counted.get_fname_count()?,
+                        counted.sample_message_trie_keys(MANIFEST_SAMPLED_PROOFS)?,
+                    ))
+                })
+                .await
+                .map_err(|err| Status::internal(err.to_string()))?
+                .map_err(|err| Status::internal(err.to_string()))?;
+
+            let mut sampled_proofs = vec![];
+            for trie_key in sampled_keys {
+                match Self::committed_proof(stores, &[trie_key]).await {
+                    Ok(proof) => sampled_proofs.push(proof),
+                    // Deleted since it was picked
+                    Err(status) if status.code() == tonic::Code::NotFound => {}
+                    Err(status) => return Err(status),
+                }
+            }
+            if Self::committed_shard_root(stores)? != (height, root_hash.clone())
+                || sampled_proofs
+                    .iter()
+                    .any(|proof| proof.root_hash != root_hash)
+            {
+                continue;
+            }
+
+            let manifest = proto::ShardManifest {
+                shard_id,
+                height,
+                root_hash,
+                message_counts: message_counts
+                    .into_iter()
+                    .map(|(store_type, messages)| proto::StoreMessageCount {
+                        store_type: store_type as i32,
+                        messages,
+                    })
+                    .collect(),
+                num_onchain_events,
+                num_fnames,
+                sampled_proofs,
+                timestamp: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_millis() as u64,
+            };
+            let signature = keypair.sign(&manifest.encode_to_vec());
+            return Ok(Response::new(GetShardManifestResponse {
+                manifest: Some(manifest),
+                signature,
+                public_key: keypair.public().to_bytes().to_vec(),
+            }));
+        }
+
+        Err(Status::unavailable(
+            "shard kept changing while the manifest was made, try again",
+        ))
+    }
+
+    async fn get_votes(
+        &self,
+        request: Request<GetVotesRequest>,
+    ) -> Result<Response<GetVotesResponse>, Status> {
+        let GetVotesRequest { shard_id, height } = request.into_inner();
+        let validator_sets = self.validator_sets.get(&shard_id).ok_or_else(|| {
+            Status::invalid_argument(format!("no validators for shard {}", shard_id))
+        })?;
+        let recorded_votes = self.vote_history.votes(shard_id, height).ok_or_else(|| {
+            Status::not_found(format!(
+                "no votes retained for height {} of shard {}",
+                height, shard_id
+            ))
+        })?;
+
+        let validator_set = validator_sets
+            .get_validator_set(height)
+            .map_err(|err| Status::internal(err.to_string()))?;
+        let votes = Self::verified_votes(&validator_set, recorded_votes);
+
+        Ok(Response::new(GetVotesResponse {
+            shard_id,
+            height,
+            votes,
+        }))
+    }
+
+    async fn get_proposer_stats(
+        &self,
+        request: Request<GetProposerStatsRequest>,
+    ) -> Result<Response<GetProposerStatsResponse>, Status> {
+        let request = request.into_inner();
+        let shard_id = request.shard_id;
+        let validator_sets = self.validator_sets.get(&shard_id).ok_or_else(|| {
+            Status::invalid_argument(format!("no validators for shard {}", shard_id))
+        })?;
+        let start_height = request.start_height.max(1);
+        let stop_height = request
+            .stop_height
+            .unwrap_or(self.confirmed_height(shard_id)?);
+        if stop_height < start_height {
+            return Err(Status::invalid_argument(format!(
+                "stop_height {} is before start_height {}",
+                stop_height, start_height
+            )));
+        }
+        if stop_height - start_height >= MAX_PROPOSER_STATS_HEIGHTS {
+            return Err(Status::invalid_argument(format!(
+                "at most {} heights can be requested at once",
+                MAX_PROPOSER_STATS_HEIGHTS
+            )));
+        }
+
+        let mut tally = ProposerTally::default();
+        for (height, committed_round) in
+            self.committed_rounds(shard_id, start_height, stop_height)?
+        {
+            let validator_set = validator_sets
+                .get_validator_set(height)
+                .map_err(|err| Status::internal(err.to_string()))?;
+            let votes = self
+                .vote_history
+                .votes(shard_id, height)
+                .map(|recorded_votes| Self::verified_votes(&validator_set, recorded_votes));
+            tally.record_height(&validator_set, height, committed_round, votes.as_deref());
+        }
+
+        Ok(Response::new(GetProposerStatsResponse {
+            shard_id,
+            start_height,
+            stop_height,
+            heights: tally.heights,
+            heights_with_votes: tally.heights_with_votes,
+            proposer_stats: tally.into_stats(),
+        }))
+    }
+
+    async fn get_network_config(
+        &self,
+        _request: Request<GetNetworkConfigRequest>,
+    ) -> Result<Response<GetNetworkConfigResponse>, Status> {
+        let mut shard_ids: Vec<u32> = self.shard_stores.keys().cloned().collect();
+        shard_ids.sort();
+        // As the stores enforce them, every shard is configured with the same ones
+        let store_limits = self
+            .shard_stores
+            .values()
+            .next()
+            .map(|stores| stores.store_limits.clone())
+            .unwrap_or_else(StoreLimits::default);
+
+        Ok(Response::new(GetNetworkConfigResponse {
+            network: self.network as i32,
+            farcaster_epoch: farcaster_epoch(),
+            num_shards: self.num_shards,
+            shard_ids,
+            chain_id: self.onchain_events_chain_id,
+            id_registry_address: ID_REGISTRY.to_vec(),
+            key_registry_address: KEY_REGISTRY.to_vec(),
+            storage_registry_address: STORAGE_REGISTRY.to_vec(),
+            limits: Some(storage_unit_limits(&store_limits.limits)),
+            legacy_limits: Some(storage_unit_limits(&store_limits.legacy_limits)),
+            byte_limits: store_limits.byte_limits.as_ref().map(storage_unit_limits),
+        }))
+    }
+
+    async fn get_recent_blocks_summary(
+        &self,
+        request: Request<GetRecentBlocksSummaryRequest>,
+    ) -> Result<Response<GetRecentBlocksSummaryResponse>, Status> {
+        let request = request.into_inner();
+        let shard_id = request.shard_id;
+        let count = match request.count {
+            0 => MAX_RECENT_BLOCKS_SUMMARY,
+            count => count.min(MAX_RECENT_BLOCKS_SUMMARY),
+        } as usize;
+
+        // The height, timestamp, hash, transaction count and commits of each block, latest first
+        let blocks: Vec<_> = if shard_id == 0 {
+            let page = self
+                .block_store
+                .get_blocks(
+                    0,
+                    None,
+                    &PageOptions {
+                        page_size: Some(count),
+                        page_token: None,
+                        reverse: true,
+                    },
+                )
+                .map_err(|err| Status::internal(err.to_string()))?;
+            page.blocks
+                .into_iter()
+                .filter_map(|block| {
+                    let header = block.header?;
+                    let num_transactions = block
+                        .shard_witness
+                        .map_or(0, |witness| witness.shard_chunk_witnesses.len());
+                    Some((
+                        header.height?.block_number,
+                        header.timestamp,
+                        block.hash,
+                        num_transactions,
+                        block.commits,
+                    ))
+                })
+                .collect()
+        } else {
+            // Shard chunks carry the messages, so they're read without decoding them
+            self.get_stores_for_shard(shard_id)?
+                .shard_store
+                .get_recent_shard_chunk_views(count)
+                .map_err(|err| Status::internal(err.to_string()))?
+                .into_iter()
+                .filter_map(|view| {
+                    let header = view.header?;
+                    Some((
+                        header.height?.block_number,
+                        header.timestamp,
+                        view.hash,
+                        view.transactions.len(),
+                        view.commits,
+                    ))
+                })
+                .collect()
+        };
+
+        let validator_sets = self.validator_sets.get(&shard_id);
+        let blocks = blocks
+            .into_iter()
+            .map(|(height, timestamp, hash, num_transactions, commits)| {
+                // Blocks don't record their proposer, it's the validator whose turn the round
+                // the block was committed in was
+                let round = commits.and_then(|commits| u64::try_from(commits.round).ok());
+                let proposer = validator_sets
+                    .zip(round)
+                    .filter(|_| height > 0)
+                    .map(|(validator_sets, round)| {
+                        let validator_set =
+                            &validator_sets.get_stored_validator_set(height).validators;
+                        if validator_set.validators.is_empty() {
+                            return vec![];
+                        }
+                        validator_set
+                            .proposer(height, round)
+                            .public_key
+                            .to_bytes()
+                            .to_vec()
+                    })
+                    .unwrap_or_default();
+                proto::BlockSummary {
+                    height,
+                    hash,
+                    proposer,
+                    num_transactions: num_transactions as u64,
+                    timestamp,
+                }
+            })
+            .collect();
+
+        Ok(Response::new(GetRecentBlocksSummaryResponse {
+            shard_id,
+            blocks,
+        }))
+    }
+
+    async fn get_submission_policy(
+        &self,
+        _request: Request<GetSubmissionPolicyRequest>,
+    ) -> Result<Response<GetSubmissionPolicyResponse>, Status> {
+        let mut frozen_shard_ids: Vec<u32> = self
+            .shard_stores
+            .iter()
+            .filter(|(_, stores)| stores.shard_freeze.is_frozen())
+            .map(|(shard_id, _)| *shard_id)
+            .collect();
+        frozen_shard_ids.sort();
+
+        Ok(Response::new(GetSubmissionPolicyResponse {
+            rate_limits_enabled: self.rate_limits_enabled,
+            min_messages_per_hour: MIN_MESSAGES_PER_HOUR,
+            max_data_bytes: validations::message::MAX_DATA_BYTES as u32,
+            max_link_compact_state_data_bytes: validations::message::MAX_DATA_BYTES_FOR_LINK_COMPACT
+                as u32,
+            disabled_message_types: self
+                .message_type_admission
+                .disabled_types()
+                .into_iter()
+                .map(|message_type| message_type as i32)
+                .collect(),
+            fid_allowlist_enabled: self.fid_allowlist.is_enabled(),
+            submission_sequence_required: self.submission_sequences.is_enabled(),
+            pending_dependencies_enabled: self.pending_dependencies_enabled,
+            frozen_shard_ids,
+            low_disk_space: self.disk_space.is_low(),
+            retry_after_seconds: self.write_stall_config.retry_after.as_secs().max(1) as u32,
         }))
     }
+
+    async fn get_proof(
+        &self,
+        request: Request<GetProofRequest>,
+    ) -> Result<Response<MessageProof>, Status> {
+        let request = request.into_inner();
+        let stores = self.get_stores_for(request.fid)?;
+
+        // The message type is part of the trie key but isn't known from the hash alone, so try
+        // each type until the key is found in the trie.
+        let trie_keys: Vec<Vec<u8>> = (0..32u8)
+            .filter_map(|msg_type| MessageType::try_from(msg_type as i32).ok())
+            .filter(|msg_type| *msg_type != MessageType::None)
+            .map(|msg_type| {
+                let mut key = TrieKey::for_message_type(request.fid, msg_type.into_u8());
+                key.extend_from_slice(&request.message_hash);
+                key
+            })
+            .collect();
+
+        Self::committed_proof(stores, &trie_keys)
+            .await
+            .map(Response::new)
+    }
 }
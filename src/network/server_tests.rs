@@ -3,37 +3,59 @@ mod tests {
     use async_trait::async_trait;
     use base64::Engine;
     use foundry_common::ens::EnsError;
+    use itertools::Itertools;
     use prost::Message;
     use std::collections::HashMap;
     use std::sync::Arc;
     use std::time::{Duration, Instant};
     use tokio::time::{sleep, timeout};
 
+    use crate::connectors::onchain_events::pause::PauseState;
+    use crate::connectors::onchain_events::reorg::HaltState;
+    use crate::connectors::onchain_events::retry::RetryQueue;
     use crate::connectors::onchain_events::L1Client;
+    use crate::consensus::consensus::ValidatorSetConfig;
+    use crate::consensus::validator::StoredValidatorSets;
+    use crate::core::types::SnapchainShard;
     use crate::core::validations::{self, verification::VerificationAddressClaim};
+    use crate::mempool::admission::{
+        FidAllowlist, MessageTypeAdmission, StateAdmissionRules, UnregisteredFids,
+    };
     use crate::mempool::mempool::{self, Mempool};
     use crate::mempool::routing;
     use crate::mempool::routing::MessageRouter;
+    use crate::network::rpc_compression;
     use crate::network::server::MyHubService;
+    use crate::network::sync_progress::SyncProgress;
+    use crate::network::vote_history::VoteHistory;
+    use crate::node::read_replica::CatchUpStatus;
+    use crate::node::shutdown::ShutdownSignal;
+    use crate::proto::hub_service_client::HubServiceClient;
     use crate::proto::hub_service_server::HubService;
     use crate::proto::{
         self, EventRequest, EventsRequest, HubEvent, HubEventType, OnChainEventType, ShardChunk,
         UserNameProof, UserNameType, UsernameProofRequest, VerificationAddAddressBody,
     };
     use crate::proto::{FidRequest, SubscribeRequest};
+    use crate::storage::db::disk_space::{self, DiskSpaceGuard};
     use crate::storage::db::{self, RocksDB, RocksDbTransactionBatch};
-    use crate::storage::store::account::{HubEventIdGenerator, SEQUENCE_BITS};
-    use crate::storage::store::engine::{Senders, ShardEngine};
+    use crate::storage::store::account::UserDataStore;
+    use crate::storage::store::account::{message_encode, HubEventIdGenerator, SEQUENCE_BITS};
+    use crate::storage::store::engine::{MempoolMessage, Senders, ShardEngine};
     use crate::storage::store::stores::Stores;
     use crate::storage::store::test_helper::{commit_event, generate_signer, register_user};
+    use crate::storage::store::validator_stakes::ValidatorStakes;
     use crate::storage::store::{test_helper, BlockStore};
-    use crate::storage::trie::merkle_trie;
-    use crate::utils::factory::{events_factory, messages_factory};
+    use crate::storage::trie::merkle_trie::{self, TrieKey};
+    use crate::utils::factory::{events_factory, messages_factory, username_factory};
     use crate::utils::statsd_wrapper::StatsdClientWrapper;
+    use ed25519_dalek::Signer;
     use futures::future;
     use futures::StreamExt;
+    use libp2p::identity::ed25519::{Keypair, SecretKey};
     use tempfile;
     use tokio::sync::{broadcast, mpsc};
+    use tonic::codec::CompressionEncoding;
     use tonic::Request;
 
     const SHARD1_FID: u64 = test_helper::SHARD1_FID;
@@ -49,6 +71,7 @@ mod tests {
                 page_size: None,
                 page_token: None,
                 reverse: None,
+                fields: None,
             })
         }
     }
@@ -172,6 +195,35 @@ mod tests {
         HashMap<u32, Senders>,
         [ShardEngine; 2],
         MyHubService,
+    ) {
+        make_server_with(
+            rpc_auth,
+            SyncProgress::default(),
+            VoteHistory::default(),
+            &[],
+            None,
+        )
+        .await
+    }
+
+    // The validators of every shard, known so tests can sign votes as them
+    fn validator_keypair(index: u8) -> Keypair {
+        Keypair::from(SecretKey::try_from_bytes([index + 1; 32]).unwrap())
+    }
+
+    // Unavailable shards are left out of the service's stores, as if their db failed to open. The
+    // validators are weighed by stake with a stake epoch length.
+    async fn make_server_with(
+        rpc_auth: Option<String>,
+        sync_progress: SyncProgress,
+        vote_history: VoteHistory,
+        unavailable_shards: &[u32],
+        stake_epoch_length: Option<u64>,
+    ) -> (
+        HashMap<u32, Stores>,
+        HashMap<u32, Senders>,
+        [ShardEngine; 2],
+        MyHubService,
     ) {
         let statsd_client = StatsdClientWrapper::new(
             cadence::StatsdClient::builder("", cadence::NopMetricSink {}).build(),
@@ -211,9 +263,13 @@ mod tests {
             test_helper::statsd_client(),
         );
         let shard2_senders = engine2.get_senders();
-        let stores = HashMap::from([(1, shard1_stores), (2, shard2_stores)]);
-        let senders = HashMap::from([(1, shard1_senders), (2, shard2_senders)]);
+        let mut stores = HashMap::from([(1, shard1_stores), (2, shard2_stores)]);
+        let mut senders = HashMap::from([(1, shard1_senders), (2, shard2_senders)]);
         let num_shards = senders.len() as u32;
+        for shard_id in unavailable_shards {
+            stores.remove(shard_id);
+            senders.remove(shard_id);
+        }
 
         let auth = rpc_auth.unwrap_or_else(|| format!("{}:{}", USER_NAME, PASSWORD));
         let blocks_dir = tempfile::TempDir::new().unwrap();
@@ -223,6 +279,33 @@ mod tests {
         assert_eq!(message_router.route_fid(SHARD1_FID, 2), 1);
         assert_eq!(message_router.route_fid(SHARD2_FID, 2), 2);
 
+        let validator_set_config = vec![ValidatorSetConfig {
+            effective_at: 0,
+            validator_public_keys: (0..3)
+                .map(|index| hex::encode(validator_keypair(index).public().to_bytes()))
+                .collect(),
+            shard_ids: vec![0, 1, 2],
+        }];
+        let validator_sets =
+            [0, 1, 2]
+                .into_iter()
+                .map(|shard_id| {
+                    let stakes = stores.get(&shard_id).zip(stake_epoch_length).map(
+                        |(stores, epoch_length)| {
+                            ValidatorStakes::new(stores.shard_store.db.clone(), epoch_length)
+                        },
+                    );
+                    (
+                        shard_id,
+                        StoredValidatorSets::from_config(
+                            SnapchainShard::new(shard_id),
+                            &validator_set_config,
+                        )
+                        .with_stakes(stakes),
+                    )
+                })
+                .collect();
+
         let (mempool_tx, mempool_rx) = mpsc::channel(1000);
         let (gossip_tx, _gossip_rx) = mpsc::channel(1000);
         let (_shard_decision_tx, shard_decision_rx) = broadcast::channel(1000);
@@ -253,6 +336,12 @@ mod tests {
                 message_router,
                 mempool_tx.clone(),
                 Some(Box::new(MockL1Client {})),
+                HaltState::default(),
+                PauseState::default(),
+                RetryQueue::default(),
+                validator_sets,
+                sync_progress,
+                vote_history,
                 "0.1.2".to_string(),
                 "asddef".to_string(),
             ),
@@ -314,6 +403,159 @@ mod tests {
         let _ = shard2_subscriber.await;
     }
 
+    #[tokio::test]
+    async fn test_subscribers_are_drained_on_shutdown() {
+        let (_, senders, _, service) = make_server(None).await;
+        let (signal_tx, signal) = ShutdownSignal::new();
+        let service = service.with_shutdown_signal(signal);
+
+        let mut listener = service
+            .subscribe(Request::new(SubscribeRequest {
+                event_types: vec![HubEventType::MergeMessage as i32],
+                from_id: None,
+                shard_index: Some(1),
+            }))
+            .await
+            .unwrap();
+        // Allow time for rpc handler to subscribe to event rx channels
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let events_tx = senders.get(&1u32).unwrap().events_tx.clone();
+        send_events(events_tx.clone(), 3).await;
+        // Not subscribed to, but the client still resumes past it
+        events_tx
+            .send(HubEvent {
+                r#type: HubEventType::PruneMessage as i32,
+                id: 3,
+                body: None,
+                block_number: 1,
+                shard_index: 1,
+                timestamp: 0,
+            })
+            .unwrap();
+        for id in 0..3 {
+            let event = timeout(Duration::from_secs(1), listener.get_mut().next())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            assert_eq!(event.id, id);
+        }
+
+        signal_tx.send(true).unwrap();
+        let notice = timeout(Duration::from_secs(1), listener.get_mut().next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(notice.r#type, HubEventType::Draining as i32);
+        assert_eq!(notice.shard_index, 1);
+        assert_eq!(
+            notice.body,
+            Some(proto::hub_event::Body::DrainingBody(proto::DrainingBody {
+                resume_from_id: 4
+            }))
+        );
+
+        // Then the stream ends cleanly
+        let end = timeout(Duration::from_secs(1), listener.get_mut().next())
+            .await
+            .unwrap();
+        assert!(end.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_with_compression() {
+        let (_, senders, _, service) = make_server(None).await;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = rpc_compression::Config { gzip: true };
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(rpc_compression::hub_service_server(
+                    Arc::new(service),
+                    &config,
+                ))
+                .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+        );
+
+        let mut client = HubServiceClient::connect(format!("http://{}", addr))
+            .await
+            .unwrap()
+            .accept_compressed(CompressionEncoding::Gzip);
+        let response = client
+            .subscribe(SubscribeRequest {
+                event_types: vec![HubEventType::MergeMessage as i32],
+                from_id: None,
+                shard_index: Some(1),
+            })
+            .await
+            .unwrap();
+        assert_eq!(response.metadata().get("grpc-encoding").unwrap(), "gzip");
+        let mut events = response.into_inner();
+
+        // Allow time for rpc handler to subscribe to event rx channels
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        send_events(senders.get(&1u32).unwrap().events_tx.clone(), 5).await;
+
+        let mut ids = vec![];
+        while ids.len() < 5 {
+            let event = timeout(Duration::from_secs(5), events.message())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            assert_eq!(event.r#type, HubEventType::MergeMessage as i32);
+            ids.push(event.id);
+        }
+        assert_eq!(ids, vec![0, 1, 2, 3, 4]);
+    }
+
+    #[tokio::test]
+    async fn test_streaming_subscriber_limit() {
+        let (_, _, _, service) = make_server(None).await;
+        let service = service.with_max_streaming_subscribers(1);
+        let subscribe_request = || {
+            Request::new(SubscribeRequest {
+                event_types: vec![HubEventType::MergeMessage as i32],
+                from_id: None,
+                shard_index: Some(1),
+            })
+        };
+
+        let stream = service.subscribe(subscribe_request()).await.unwrap();
+        let response = service.subscribe(subscribe_request()).await;
+        assert_eq!(
+            response.err().unwrap().code(),
+            tonic::Code::ResourceExhausted
+        );
+        // The limit is shared by all the streaming rpcs
+        let response = service
+            .get_blocks(Request::new(proto::BlocksRequest {
+                shard_id: 0,
+                start_block_number: 0,
+                stop_block_number: None,
+            }))
+            .await;
+        assert_eq!(
+            response.err().unwrap().code(),
+            tonic::Code::ResourceExhausted
+        );
+
+        // The client goes away without an event ever being sent on the stream
+        drop(stream);
+        let resubscribed = timeout(Duration::from_secs(5), async {
+            loop {
+                if service.subscribe(subscribe_request()).await.is_ok() {
+                    return;
+                }
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        assert!(resubscribed.is_ok());
+    }
+
     #[tokio::test]
     async fn test_subscribe_with_filter_rpc() {
         let (stores, senders, _, service) = make_server(None).await;
@@ -556,6 +798,23 @@ mod tests {
         .await;
         let valid_message =
             messages_factory::casts::create_cast_add(SHARD1_FID, "test", None, None);
+        // A hash that isn't the hash of the data is rejected, even when the signature is over it
+        let mut tampered_message =
+            messages_factory::casts::create_cast_add(SHARD1_FID, "tampered", None, None);
+        tampered_message.hash[0] ^= 0xff;
+        tampered_message.signature = test_helper::default_signer()
+            .sign(&tampered_message.hash)
+            .to_bytes()
+            .to_vec();
+        let mut request = Request::new(tampered_message);
+        add_auth_header(&mut request, USER_NAME, PASSWORD);
+        let response = service.submit_message(request).await.unwrap_err();
+        assert_eq!(response.code(), tonic::Code::InvalidArgument);
+        assert_eq!(
+            response.message(),
+            "bad_request.validation_failure/Invalid message hash"
+        );
+
         test_helper::commit_message(&mut engine1, &valid_message).await;
 
         // Submitting a duplicate message should return an error
@@ -579,100 +838,441 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_authentication() {
-        let (_stores, _senders, _, service) =
-            make_server(Some("user1:pass1,user2:pass2".to_string())).await;
-        let message = messages_factory::casts::create_cast_add(123, "test", None, None);
+    async fn test_submit_message_to_frozen_shard() {
+        let (_stores, _senders, [mut engine1, _], service) = make_server(None).await;
+        register_user(
+            SHARD1_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine1,
+        )
+        .await;
+        let message = messages_factory::casts::create_cast_add(SHARD1_FID, "test", None, None);
 
-        let no_auth_request = Request::new(message.clone());
-        // Providing no auth fails
-        let response = service.submit_message(no_auth_request).await.unwrap_err();
-        assert_eq!(response.code(), tonic::Code::Unauthenticated);
-        assert_eq!(response.message(), "missing authorization header");
+        let shard_freeze = engine1.get_shard_freeze();
+        assert!(shard_freeze.freeze());
+        assert!(!shard_freeze.freeze());
 
-        let mut invalid_creds_request = Request::new(message.clone());
-        add_auth_header(&mut invalid_creds_request, "user3", "pass1");
-        let response = service
-            .submit_message(invalid_creds_request)
-            .await
-            .unwrap_err();
-        assert_eq!(response.code(), tonic::Code::Unauthenticated);
-        assert_eq!(response.message(), "invalid username or password");
+        let mut request = Request::new(message.clone());
+        add_auth_header(&mut request, USER_NAME, PASSWORD);
+        let response = service.submit_message(request).await.unwrap_err();
+        assert_eq!(response.code(), tonic::Code::FailedPrecondition);
+        assert_eq!(
+            response.message(),
+            "failed_precondition/shard is frozen and not accepting writes"
+        );
+        let failed_request_id = response.metadata().get("x-request-id").unwrap().clone();
 
-        let mut valid_creds_request = Request::new(message.clone());
-        add_auth_header(&mut valid_creds_request, "user2", "pass2");
+        // Reads are still served while frozen
         let response = service
-            .submit_message(valid_creds_request)
+            .get_fids(Request::new(proto::FidsRequest {
+                shard_id: 1,
+                page_size: None,
+                page_token: None,
+                reverse: None,
+            }))
             .await
-            .unwrap_err();
-        // Authenticated but no fid registration
-        assert_eq!(response.code(), tonic::Code::InvalidArgument);
+            .unwrap();
+        assert_eq!(response.into_inner().fids, vec![SHARD1_FID]);
+
+        assert!(shard_freeze.unfreeze());
+        let mut request = Request::new(message.clone());
+        add_auth_header(&mut request, USER_NAME, PASSWORD);
+        let response = service.submit_message(request).await.unwrap();
+        // Every submission gets its own request id
+        let request_id = response.metadata().get("x-request-id").unwrap();
+        assert_eq!(request_id.len(), 16);
+        assert_ne!(request_id, &failed_request_id);
+        assert_eq!(response.into_inner().hash, message.hash);
+    }
+
+    #[tokio::test]
+    async fn test_submit_message_while_low_on_disk_space() {
+        let (_stores, _senders, [mut engine1, _], service) = make_server(None).await;
+        let guard = DiskSpaceGuard::new(disk_space::Config {
+            low_watermark_bytes: 1000,
+            high_watermark_bytes: 2000,
+            check_interval: Duration::from_secs(1),
+        });
+        let service = service.with_disk_space_guard(guard.clone());
+        register_user(
+            SHARD1_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine1,
+        )
+        .await;
+        let message = messages_factory::casts::create_cast_add(SHARD1_FID, "test", None, None);
+
+        assert!(guard.update(999));
+        let mut request = Request::new(message.clone());
+        add_auth_header(&mut request, USER_NAME, PASSWORD);
+        let response = service.submit_message(request).await.unwrap_err();
+        assert_eq!(response.code(), tonic::Code::ResourceExhausted);
         assert_eq!(
             response.message(),
-            "bad_request.validation_failure/unknown fid"
+            "resource_exhausted/node is low on disk space and not accepting writes"
         );
+        assert!(response.metadata().get("retry-after").is_some());
+
+        // Still rejected until space is back above the high watermark
+        assert!(guard.update(1500));
+        let mut request = Request::new(message.clone());
+        add_auth_header(&mut request, USER_NAME, PASSWORD);
+        assert!(service.submit_message(request).await.is_err());
+
+        assert!(!guard.update(2001));
+        let mut request = Request::new(message.clone());
+        add_auth_header(&mut request, USER_NAME, PASSWORD);
+        let response = service.submit_message(request).await.unwrap();
+        assert_eq!(response.into_inner().hash, message.hash);
     }
 
     #[tokio::test]
-    async fn test_event_timestamp() {
+    async fn test_submit_message_of_disabled_type() {
         let (_stores, _senders, [mut engine1, _], service) = make_server(None).await;
+        let admission =
+            MessageTypeAdmission::from_config(&["MESSAGE_TYPE_LINK_ADD".to_string()]).unwrap();
+        let service = service.with_message_type_admission(admission.clone());
+        register_user(
+            SHARD1_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine1,
+        )
+        .await;
+        let link =
+            messages_factory::links::create_link_add(SHARD1_FID, "follow", SHARD2_FID, None, None);
+        let cast = messages_factory::casts::create_cast_add(SHARD1_FID, "test", None, None);
 
-        test_helper::register_user(
+        let mut request = Request::new(link.clone());
+        add_auth_header(&mut request, USER_NAME, PASSWORD);
+        let response = service.submit_message(request).await.unwrap_err();
+        assert_eq!(response.code(), tonic::Code::Unavailable);
+        assert_eq!(
+            response.message(),
+            "unavailable/MESSAGE_TYPE_LINK_ADD messages are temporarily not being accepted"
+        );
+
+        // Other types are still accepted
+        let mut request = Request::new(cast.clone());
+        add_auth_header(&mut request, USER_NAME, PASSWORD);
+        assert_eq!(
+            service
+                .submit_message(request)
+                .await
+                .unwrap()
+                .into_inner()
+                .hash,
+            cast.hash
+        );
+
+        assert!(admission.enable(proto::MessageType::LinkAdd));
+        let mut request = Request::new(link.clone());
+        add_auth_header(&mut request, USER_NAME, PASSWORD);
+        assert_eq!(
+            service
+                .submit_message(request)
+                .await
+                .unwrap()
+                .into_inner()
+                .hash,
+            link.hash
+        );
+    }
+
+    #[tokio::test]
+    async fn test_submit_message_from_fid_not_allowed() {
+        let (_stores, _senders, [mut engine1, _], service) = make_server(None).await;
+        let allowlist =
+            FidAllowlist::from_config(&[SHARD1_FID], proto::FarcasterNetwork::Devnet).unwrap();
+        let service = service.with_fid_allowlist(allowlist);
+        register_user(
             SHARD1_FID,
             test_helper::default_signer(),
             test_helper::default_custody_address(),
             &mut engine1,
         )
         .await;
-        let cast_add = messages_factory::casts::create_cast_add(SHARD1_FID, "test", None, None);
-        let cast_add2 = messages_factory::casts::create_cast_add(SHARD1_FID, "test2", None, None);
-        let mut shard_chunks = vec![];
+        let allowed = messages_factory::casts::create_cast_add(SHARD1_FID, "test", None, None);
+        let other = messages_factory::casts::create_cast_add(SHARD2_FID, "test", None, None);
 
-        shard_chunks
-            .push(test_helper::commit_messages(&mut engine1, vec![cast_add, cast_add2]).await);
+        let mut request = Request::new(allowed.clone());
+        add_auth_header(&mut request, USER_NAME, PASSWORD);
+        assert_eq!(
+            service
+                .submit_message(request)
+                .await
+                .unwrap()
+                .into_inner()
+                .hash,
+            allowed.hash
+        );
 
-        sleep(Duration::from_secs(1)).await;
+        let mut request = Request::new(other);
+        add_auth_header(&mut request, USER_NAME, PASSWORD);
+        let response = service.submit_message(request).await.unwrap_err();
+        assert_eq!(response.code(), tonic::Code::InvalidArgument);
+        assert_eq!(
+            response.message(),
+            format!(
+                "bad_request.validation_failure/fid {} is not in the allowed fids of this network",
+                SHARD2_FID
+            )
+        );
+    }
 
-        let cast_add3 = messages_factory::casts::create_cast_add(SHARD1_FID, "test3", None, None);
+    #[tokio::test]
+    async fn test_read_replica_rejects_submissions() {
+        let (_stores, _senders, [mut engine1, _], service) = make_server(None).await;
+        let service = service.with_read_replica(CatchUpStatus::default());
+        register_user(
+            SHARD1_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine1,
+        )
+        .await;
+        let cast = messages_factory::casts::create_cast_add(SHARD1_FID, "test", None, None);
 
-        shard_chunks.push(test_helper::commit_message(&mut engine1, &cast_add3).await);
+        let mut request = Request::new(cast);
+        add_auth_header(&mut request, USER_NAME, PASSWORD);
+        let response = service.submit_message(request).await.unwrap_err();
+        assert_eq!(response.code(), tonic::Code::FailedPrecondition);
 
-        let request = Request::new(SubscribeRequest {
-            event_types: vec![HubEventType::MergeMessage as i32],
-            from_id: Some(0),
-            shard_index: Some(1),
-        });
-        let mut listener = service.subscribe(request).await.unwrap();
+        let info = service
+            .get_info(Request::new(proto::GetInfoRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(info.read_replica_status.is_some());
+        assert!(info.shard_infos.iter().all(|shard| shard.mempool_size == 0));
+    }
 
-        let mut events = vec![];
-        let start_time = Instant::now();
-        loop {
-            if start_time.elapsed() > Duration::from_secs(2) {
-                break;
-            }
+    #[tokio::test]
+    async fn test_validate_message() {
+        let (_stores, _senders, [mut engine1, _], service) = make_server(None).await;
 
-            let event = timeout(Duration::from_millis(100), listener.get_mut().next()).await;
-            if let Ok(Some(Ok(hub_event))) = event {
-                assert_ne!(hub_event.timestamp, 0);
-                events.push(hub_event);
-            }
-        }
+        // Fails with the same error submission would
+        let message = messages_factory::casts::create_cast_add(SHARD1_FID, "test", None, None);
+        let response = service
+            .validate_message(Request::new(message.clone()))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!response.valid);
+        assert_eq!(response.error_code, "bad_request.validation_failure");
+        assert_eq!(response.error_message, "unknown fid");
 
-        let cast_add4 = messages_factory::casts::create_cast_add(SHARD1_FID, "test4", None, None);
+        register_user(
+            SHARD1_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine1,
+        )
+        .await;
 
-        shard_chunks.push(test_helper::commit_message(&mut engine1, &cast_add4).await);
+        let response = service
+            .validate_message(Request::new(message.clone()))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(response.valid);
+        assert_eq!(response.error_code, "");
+        assert_eq!(response.message.unwrap().hash, message.hash);
+
+        // Nothing was merged, so the message can still be submitted
+        let mut request = Request::new(message.clone());
+        add_auth_header(&mut request, USER_NAME, PASSWORD);
+        let response = service.submit_message(request).await.unwrap();
+        assert_eq!(response.into_inner().hash, message.hash);
 
-        let event = timeout(Duration::from_millis(100), listener.get_mut().next()).await;
-        if let Ok(Some(Ok(hub_event))) = event {
-            assert_ne!(hub_event.timestamp, 0);
-            events.push(hub_event);
-        }
+        test_helper::commit_message(&mut engine1, &message).await;
+        let response = service
+            .validate_message(Request::new(message.clone()))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!response.valid);
+        assert_eq!(response.error_code, "bad_request.duplicate");
+    }
 
-        let assert_events = |events: &Vec<HubEvent>, shard_chunks: &Vec<ShardChunk>| {
-            assert_eq!(events.len(), 4);
-            assert_eq!(shard_chunks.len(), 3);
-            assert_eq!(
-                events[0].timestamp,
+    #[tokio::test]
+    async fn test_validate_message_applies_state_admission() {
+        let (_stores, _senders, [mut engine1, _], service) = make_server(None).await;
+        let service = service.with_state_admission(StateAdmissionRules {
+            unregistered_fids: UnregisteredFids::Reject,
+            max_fids_per_verified_address: 0,
+            hold_enabled: false,
+        });
+        register_user(
+            SHARD1_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine1,
+        )
+        .await;
+
+        let registered = messages_factory::casts::create_cast_add(SHARD1_FID, "test", None, None);
+        let response = service
+            .validate_message(Request::new(registered))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(response.valid);
+
+        // Rejected before validation, with the mempool's error rather than the missing fid
+        let unregistered_fid = SHARD1_FID + 2;
+        let unregistered =
+            messages_factory::casts::create_cast_add(unregistered_fid, "test", None, None);
+        let response = service
+            .validate_message(Request::new(unregistered))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!response.valid);
+        assert_eq!(response.error_code, "bad_request.validation_failure");
+        assert_eq!(
+            response.error_message,
+            format!("fid {} is not registered", unregistered_fid)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_validate_message_applies_submission_admission() {
+        let (_stores, _senders, [mut engine1, _], service) = make_server(None).await;
+        let allowlist =
+            FidAllowlist::from_config(&[SHARD1_FID], proto::FarcasterNetwork::Devnet).unwrap();
+        let service = service.with_fid_allowlist(allowlist);
+        register_user(
+            SHARD1_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine1,
+        )
+        .await;
+
+        let allowed = messages_factory::casts::create_cast_add(SHARD1_FID, "test", None, None);
+        let response = service
+            .validate_message(Request::new(allowed))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(response.valid);
+
+        // Rejected the same way submission rejects it
+        let other = messages_factory::casts::create_cast_add(SHARD2_FID, "test", None, None);
+        let response = service
+            .validate_message(Request::new(other))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!response.valid);
+        assert_eq!(response.error_code, "bad_request.validation_failure");
+        assert_eq!(
+            response.error_message,
+            format!(
+                "fid {} is not in the allowed fids of this network",
+                SHARD2_FID
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_authentication() {
+        let (_stores, _senders, _, service) =
+            make_server(Some("user1:pass1,user2:pass2".to_string())).await;
+        let message = messages_factory::casts::create_cast_add(123, "test", None, None);
+
+        let no_auth_request = Request::new(message.clone());
+        // Providing no auth fails
+        let response = service.submit_message(no_auth_request).await.unwrap_err();
+        assert_eq!(response.code(), tonic::Code::Unauthenticated);
+        assert_eq!(response.message(), "missing authorization header");
+
+        let mut invalid_creds_request = Request::new(message.clone());
+        add_auth_header(&mut invalid_creds_request, "user3", "pass1");
+        let response = service
+            .submit_message(invalid_creds_request)
+            .await
+            .unwrap_err();
+        assert_eq!(response.code(), tonic::Code::Unauthenticated);
+        assert_eq!(response.message(), "invalid username or password");
+
+        let mut valid_creds_request = Request::new(message.clone());
+        add_auth_header(&mut valid_creds_request, "user2", "pass2");
+        let response = service
+            .submit_message(valid_creds_request)
+            .await
+            .unwrap_err();
+        // Authenticated but no fid registration
+        assert_eq!(response.code(), tonic::Code::InvalidArgument);
+        assert_eq!(
+            response.message(),
+            "bad_request.validation_failure/unknown fid"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_event_timestamp() {
+        let (_stores, _senders, [mut engine1, _], service) = make_server(None).await;
+
+        test_helper::register_user(
+            SHARD1_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine1,
+        )
+        .await;
+        let cast_add = messages_factory::casts::create_cast_add(SHARD1_FID, "test", None, None);
+        let cast_add2 = messages_factory::casts::create_cast_add(SHARD1_FID, "test2", None, None);
+        let mut shard_chunks = vec![];
+
+        shard_chunks
+            .push(test_helper::commit_messages(&mut engine1, vec![cast_add, cast_add2]).await);
+
+        sleep(Duration::from_secs(1)).await;
+
+        let cast_add3 = messages_factory::casts::create_cast_add(SHARD1_FID, "test3", None, None);
+
+        shard_chunks.push(test_helper::commit_message(&mut engine1, &cast_add3).await);
+
+        let request = Request::new(SubscribeRequest {
+            event_types: vec![HubEventType::MergeMessage as i32],
+            from_id: Some(0),
+            shard_index: Some(1),
+        });
+        let mut listener = service.subscribe(request).await.unwrap();
+
+        let mut events = vec![];
+        let start_time = Instant::now();
+        loop {
+            if start_time.elapsed() > Duration::from_secs(2) {
+                break;
+            }
+
+            let event = timeout(Duration::from_millis(100), listener.get_mut().next()).await;
+            if let Ok(Some(Ok(hub_event))) = event {
+                assert_ne!(hub_event.timestamp, 0);
+                events.push(hub_event);
+            }
+        }
+
+        let cast_add4 = messages_factory::casts::create_cast_add(SHARD1_FID, "test4", None, None);
+
+        shard_chunks.push(test_helper::commit_message(&mut engine1, &cast_add4).await);
+
+        let event = timeout(Duration::from_millis(100), listener.get_mut().next()).await;
+        if let Ok(Some(Ok(hub_event))) = event {
+            assert_ne!(hub_event.timestamp, 0);
+            events.push(hub_event);
+        }
+
+        let assert_events = |events: &Vec<HubEvent>, shard_chunks: &Vec<ShardChunk>| {
+            assert_eq!(events.len(), 4);
+            assert_eq!(shard_chunks.len(), 3);
+            assert_eq!(
+                events[0].timestamp,
                 shard_chunks[0].header.as_ref().unwrap().timestamp
             );
             assert_eq!(
@@ -921,6 +1521,7 @@ mod tests {
             page_size: None,
             page_token: None,
             reverse: None,
+            fields: None,
         };
         let response = service
             .get_casts_by_fid(Request::new(all_casts_request))
@@ -935,6 +1536,7 @@ mod tests {
             reverse: None,
             start_timestamp: None,
             stop_timestamp: None,
+            fields: None,
         };
         let response = service
             .get_all_cast_messages_by_fid(Request::new(all_casts_request))
@@ -948,6 +1550,7 @@ mod tests {
             reverse: None,
             start_timestamp: None,
             stop_timestamp: None,
+            fields: None,
         };
         let response = service
             .get_all_cast_messages_by_fid(Request::new(second_page_request))
@@ -961,6 +1564,7 @@ mod tests {
             reverse: Some(true),
             start_timestamp: None,
             stop_timestamp: None,
+            fields: None,
         };
         let response = service
             .get_all_cast_messages_by_fid(Request::new(reverse_request))
@@ -975,6 +1579,7 @@ mod tests {
             reverse: None,
             start_timestamp: None,
             stop_timestamp: None,
+            fields: None,
         };
         let response = service
             .get_all_cast_messages_by_fid(Request::new(bulk_casts_request))
@@ -983,24 +1588,8 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_storage_limits() {
-        // Works with no storage
-        let (_, _, [mut engine1, _], service) = make_server(None).await;
-
-        let response = service
-            .get_current_storage_limits_by_fid(FidRequest::for_fid(SHARD1_FID))
-            .await
-            .unwrap();
-        assert_eq!(response.get_ref().units, 0);
-        assert_eq!(response.get_ref().limits.len(), 6);
-        for limit in response.get_ref().limits.iter() {
-            assert_eq!(limit.limit, 0);
-            assert_eq!(limit.used, 0);
-        }
-        assert_eq!(response.get_ref().unit_details.len(), 2);
-        assert_eq!(response.get_ref().unit_details[0].unit_size, 0);
-        assert_eq!(response.get_ref().unit_details[1].unit_size, 0);
-
+    async fn test_get_message_by_hash() {
+        let (_, _, [mut engine1, mut engine2], service) = make_server(None).await;
         test_helper::register_user(
             SHARD1_FID,
             test_helper::default_signer(),
@@ -1008,84 +1597,51 @@ mod tests {
             &mut engine1,
         )
         .await;
-        // register_user will give the user a single unit of storage, let add one more legacy unit and a 2024 unit for a total of 1 legacy and 2 2024 units
-        test_helper::commit_event(
-            &mut engine1,
-            &events_factory::create_rent_event(SHARD1_FID, Some(1), None, false),
-        )
-        .await;
-        test_helper::commit_event(
-            &mut engine1,
-            &events_factory::create_rent_event(SHARD1_FID, None, Some(1), false),
-        )
-        .await;
-        let cast_add = &messages_factory::casts::create_cast_add(SHARD1_FID, "test", None, None);
-        test_helper::commit_message(&mut engine1, cast_add).await;
-        test_helper::commit_message(
-            &mut engine1,
-            &messages_factory::casts::create_cast_add(SHARD1_FID, "test2", None, None),
-        )
-        .await;
-        test_helper::commit_message(
-            &mut engine1,
-            &messages_factory::casts::create_cast_remove(
-                SHARD1_FID,
-                &cast_add.hash,
-                Some(cast_add.data.as_ref().unwrap().timestamp + 10),
-                None,
-            ),
-        )
-        .await;
-        test_helper::commit_message(
-            &mut engine1,
-            &messages_factory::links::create_link_add(SHARD1_FID, "follow", SHARD2_FID, None, None),
+        test_helper::register_user(
+            SHARD2_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine2,
         )
         .await;
-
-        let response = service
-            .get_current_storage_limits_by_fid(FidRequest::for_fid(SHARD1_FID))
-            .await
-            .unwrap();
-        assert_eq!(response.get_ref().units, 3);
-        assert_eq!(response.get_ref().unit_details.len(), 2);
-        assert_eq!(response.get_ref().unit_details[0].unit_size, 1);
-        assert_eq!(
-            response.get_ref().unit_details[0].unit_type,
-            proto::StorageUnitType::UnitTypeLegacy as i32
-        );
-        assert_eq!(
-            response.get_ref().unit_details[1].unit_type,
-            proto::StorageUnitType::UnitType2024 as i32
+        let cast_add = messages_factory::casts::create_cast_add(SHARD1_FID, "test", None, None);
+        let cast_remove = messages_factory::casts::create_cast_remove(
+            SHARD1_FID,
+            &cast_add.hash,
+            Some(cast_add.data.as_ref().unwrap().timestamp + 10),
+            None,
         );
-        assert_eq!(response.get_ref().unit_details[1].unit_size, 2);
+        let link_add =
+            messages_factory::links::create_link_add(SHARD2_FID, "follow", SHARD1_FID, None, None);
+        test_helper::commit_message(&mut engine1, &cast_add).await;
+        test_helper::commit_message(&mut engine2, &link_add).await;
+
+        let get_message_by_hash = |hash: &Vec<u8>| {
+            service.get_message_by_hash(Request::new(proto::MessageByHashRequest {
+                hash: hash.clone(),
+            }))
+        };
 
-        let casts_limit = response
-            .get_ref()
-            .limits
-            .iter()
-            .filter(|limit| limit.store_type() == proto::StoreType::Casts)
-            .collect::<Vec<_>>()[0];
-        let configured_limits = engine1.get_stores().store_limits;
-        assert_eq!(
-            casts_limit.limit as u32,
-            (configured_limits.limits.casts * 2) + (configured_limits.legacy_limits.casts)
-        );
-        assert_eq!(casts_limit.used, 2); // Cast remove counts as 1
-        assert_eq!(casts_limit.name, "CASTS");
+        // Found without the type or fid, on whichever shard has it
+        let response = get_message_by_hash(&cast_add.hash).await.unwrap();
+        assert_eq!(response.into_inner(), cast_add);
+        let response = get_message_by_hash(&link_add.hash).await.unwrap();
+        assert_eq!(response.into_inner(), link_add);
 
-        let links_limit = response
-            .get_ref()
-            .limits
-            .iter()
-            .filter(|limit| limit.store_type() == proto::StoreType::Links)
-            .collect::<Vec<_>>()[0];
-        assert_eq!(links_limit.used, 1);
+        // The remove replaces the add, which is no longer found, as with pruned messages
+        test_helper::commit_message(&mut engine1, &cast_remove).await;
+        let response = get_message_by_hash(&cast_add.hash).await.unwrap_err();
+        assert_eq!(response.code(), tonic::Code::NotFound);
+        let response = get_message_by_hash(&cast_remove.hash).await.unwrap();
+        assert_eq!(response.into_inner(), cast_remove);
+
+        let response = get_message_by_hash(&vec![1; 8]).await.unwrap_err();
+        assert_eq!(response.code(), tonic::Code::InvalidArgument);
     }
 
     #[tokio::test]
-    async fn test_get_info() {
-        let (_, _, [mut engine1, _], service) = make_server(None).await;
-
+    async fn test_has_messages() {
+        let (_, _, [mut engine1, mut engine2], service) = make_server(None).await;
         test_helper::register_user(
             SHARD1_FID,
             test_helper::default_signer(),
@@ -1093,14 +1649,1891 @@ mod tests {
             &mut engine1,
         )
         .await;
-        test_helper::commit_message(
-            &mut engine1,
-            &messages_factory::casts::create_cast_add(SHARD1_FID, "test", None, None),
+        test_helper::register_user(
+            SHARD2_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine2,
         )
         .await;
+        let cast_add = messages_factory::casts::create_cast_add(SHARD1_FID, "test", None, None);
+        let link_add =
+            messages_factory::links::create_link_add(SHARD2_FID, "follow", SHARD1_FID, None, None);
+        let pending = messages_factory::casts::create_cast_add(SHARD1_FID, "pending", None, None);
+        test_helper::commit_message(&mut engine1, &cast_add).await;
+        test_helper::commit_message(&mut engine2, &link_add).await;
+
+        let has_messages = |hashes: Vec<Vec<u8>>| {
+            service.has_messages(Request::new(proto::HasMessagesRequest { hashes }))
+        };
 
-        let response = service
-            .get_info(Request::new(proto::GetInfoRequest {}))
+        // Present on either shard, in the order asked
+        let response = has_messages(vec![
+            pending.hash.clone(),
+            cast_add.hash.clone(),
+            vec![1; 20],
+            link_add.hash.clone(),
+        ])
+        .await
+        .unwrap();
+        assert_eq!(
+            response.into_inner().present,
+            vec![false, true, false, true]
+        );
+
+        let response = has_messages(vec![]).await.unwrap();
+        assert!(response.into_inner().present.is_empty());
+
+        let response = has_messages(vec![cast_add.hash.clone(), vec![1; 8]])
+            .await
+            .unwrap_err();
+        assert_eq!(response.code(), tonic::Code::InvalidArgument);
+        let response = has_messages(vec![cast_add.hash.clone(); 1_001])
+            .await
+            .unwrap_err();
+        assert_eq!(response.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_get_casts_by_mention() {
+        let (_, _, [mut engine1, mut engine2], service) = make_server(None).await;
+        test_helper::register_user(
+            SHARD1_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine1,
+        )
+        .await;
+        test_helper::register_user(
+            SHARD2_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine2,
+        )
+        .await;
+
+        let (mention1, mention2) = (1000, 1001);
+        let timestamp = messages_factory::farcaster_time() - 100;
+        let cast1 = messages_factory::casts::create_cast_with_mentions(
+            SHARD1_FID,
+            "to both",
+            vec![mention1, mention2],
+            Some(timestamp + 1),
+            None,
+        );
+        let cast2 = messages_factory::casts::create_cast_with_mentions(
+            SHARD2_FID,
+            "to one",
+            vec![mention1],
+            Some(timestamp),
+            None,
+        );
+        test_helper::commit_message(&mut engine1, &cast1).await;
+        test_helper::commit_message(&mut engine2, &cast2).await;
+
+        let request = |fid, reverse| {
+            Request::new(FidRequest {
+                fid,
+                page_size: None,
+                page_token: None,
+                reverse: Some(reverse),
+                fields: None,
+            })
+        };
+        let mentioning =
+            |response: Result<tonic::Response<proto::MessagesResponse>, tonic::Status>| {
+                response
+                    .unwrap()
+                    .into_inner()
+                    .messages
+                    .iter()
+                    .map(|message| message.hash.clone())
+                    .collect::<Vec<_>>()
+            };
+
+        // Casts from every shard, in time order
+        let response = service.get_casts_by_mention(request(mention1, false)).await;
+        assert_eq!(
+            mentioning(response),
+            vec![cast2.hash.clone(), cast1.hash.clone()]
+        );
+        let response = service.get_casts_by_mention(request(mention1, true)).await;
+        assert_eq!(
+            mentioning(response),
+            vec![cast1.hash.clone(), cast2.hash.clone()]
+        );
+        let response = service.get_casts_by_mention(request(mention2, false)).await;
+        assert_eq!(mentioning(response), vec![cast1.hash.clone()]);
+
+        // Removing the cast removes it from the index of every fid it mentions
+        let remove_cast1 = messages_factory::casts::create_cast_remove(
+            SHARD1_FID,
+            &cast1.hash,
+            Some(timestamp + 2),
+            None,
+        );
+        test_helper::commit_message(&mut engine1, &remove_cast1).await;
+        let response = service.get_casts_by_mention(request(mention1, false)).await;
+        assert_eq!(mentioning(response), vec![cast2.hash.clone()]);
+        let response = service.get_casts_by_mention(request(mention2, false)).await;
+        assert!(mentioning(response).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_get_reactions_by_fid() {
+        let (_, _, [mut engine1, _], service) = make_server(None).await;
+        test_helper::register_user(
+            SHARD1_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine1,
+        )
+        .await;
+
+        let timestamp = messages_factory::farcaster_time() - 100;
+        let reaction = |reaction_type, target: &str, offset| {
+            messages_factory::reactions::create_reaction_add(
+                SHARD1_FID,
+                reaction_type,
+                target.to_string(),
+                Some(timestamp + offset),
+                None,
+            )
+        };
+        let like1 = reaction(proto::ReactionType::Like, "https://example.com/1", 0);
+        let recast = reaction(proto::ReactionType::Recast, "https://example.com/1", 1);
+        let like2 = reaction(proto::ReactionType::Like, "https://example.com/2", 2);
+        test_helper::commit_messages(
+            &mut engine1,
+            vec![like2.clone(), recast.clone(), like1.clone()],
+        )
+        .await;
+
+        let request = |reaction_type: Option<proto::ReactionType>, page_size, page_token| {
+            Request::new(proto::ReactionsByFidRequest {
+                fid: SHARD1_FID,
+                reaction_type: reaction_type.map(|t| t as i32),
+                page_size,
+                page_token,
+                reverse: None,
+                fields: None,
+            })
+        };
+        let hashes = |response: &proto::MessagesResponse| {
+            response
+                .messages
+                .iter()
+                .map(|message| message.hash.clone())
+                .collect::<Vec<_>>()
+        };
+
+        // Every type, in time order
+        let response = service
+            .get_reactions_by_fid(request(None, None, None))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(
+            hashes(&response),
+            vec![like1.hash.clone(), recast.hash.clone(), like2.hash.clone()]
+        );
+
+        let response = service
+            .get_reactions_by_fid(request(Some(proto::ReactionType::Recast), None, None))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(hashes(&response), vec![recast.hash.clone()]);
+
+        // The filter is applied before paging, so pages only hold likes
+        let response = service
+            .get_reactions_by_fid(request(Some(proto::ReactionType::Like), Some(1), None))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(hashes(&response), vec![like1.hash.clone()]);
+        let response = service
+            .get_reactions_by_fid(request(
+                Some(proto::ReactionType::Like),
+                Some(1),
+                response.next_page_token,
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(hashes(&response), vec![like2.hash.clone()]);
+    }
+
+    #[tokio::test]
+    async fn test_get_compact_state_by_fid() {
+        let (_, _, [mut engine1, _], service) = make_server(None).await;
+        test_helper::register_user(
+            SHARD1_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine1,
+        )
+        .await;
+
+        let timestamp = messages_factory::farcaster_time() - 100;
+        let cast1 =
+            messages_factory::casts::create_cast_add(SHARD1_FID, "cast1", Some(timestamp), None);
+        let cast2 = messages_factory::casts::create_cast_add(
+            SHARD1_FID,
+            "cast2",
+            Some(timestamp + 1),
+            None,
+        );
+        let remove_cast1 = messages_factory::casts::create_cast_remove(
+            SHARD1_FID,
+            &cast1.hash,
+            Some(timestamp + 2),
+            None,
+        );
+        let follow1 = messages_factory::links::create_link_add(
+            SHARD1_FID,
+            "follow",
+            1,
+            Some(timestamp),
+            None,
+        );
+        let unfollow1 = messages_factory::links::create_link_remove(
+            SHARD1_FID,
+            "follow",
+            1,
+            Some(timestamp + 1),
+            None,
+        );
+        let follow2 = messages_factory::links::create_link_add(
+            SHARD1_FID,
+            "follow",
+            2,
+            Some(timestamp),
+            None,
+        );
+        // Deletes the older links, except the follow it lists
+        let link_compact_state = messages_factory::links::create_link_compact_state(
+            SHARD1_FID,
+            "follow",
+            vec![2],
+            Some(timestamp + 2),
+            None,
+        );
+        let follow3 = messages_factory::links::create_link_add(
+            SHARD1_FID,
+            "follow",
+            3,
+            Some(timestamp + 3),
+            None,
+        );
+        let unfollow4 = messages_factory::links::create_link_remove(
+            SHARD1_FID,
+            "follow",
+            4,
+            Some(timestamp + 3),
+            None,
+        );
+        for message in [
+            &cast1,
+            &cast2,
+            &remove_cast1,
+            &follow1,
+            &unfollow1,
+            &follow2,
+            &link_compact_state,
+            &follow3,
+            &unfollow4,
+        ] {
+            test_helper::commit_message(&mut engine1, message).await;
+        }
+
+        let request = || {
+            Request::new(FidRequest {
+                fid: SHARD1_FID,
+                page_size: None,
+                page_token: None,
+                reverse: None,
+                fields: None,
+            })
+        };
+
+        // The compact state message, and the adds and removes that are still current
+        let response = service.get_cast_compact_state_by_fid(request()).await;
+        test_helper::assert_contains_all_messages(&response, &[&cast2, &remove_cast1]);
+        let response = service.get_link_compact_state_by_fid(request()).await;
+        test_helper::assert_contains_all_messages(
+            &response,
+            &[&link_compact_state, &follow2, &follow3, &unfollow4],
+        );
+        assert_eq!(
+            response.unwrap().into_inner().messages[0].hash,
+            link_compact_state.hash
+        );
+        let response = service.get_reaction_compact_state_by_fid(request()).await;
+        test_helper::assert_contains_all_messages(&response, &[]);
+    }
+
+    #[tokio::test]
+    async fn test_get_recent_casts() {
+        let (_, _, [mut engine1, mut engine2], service) = make_server(None).await;
+        let other_fid = SHARD1_FID + 2;
+        for fid in [SHARD1_FID, other_fid] {
+            test_helper::register_user(
+                fid,
+                test_helper::default_signer(),
+                test_helper::default_custody_address(),
+                &mut engine1,
+            )
+            .await;
+        }
+        test_helper::register_user(
+            SHARD2_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine2,
+        )
+        .await;
+
+        let timestamp = messages_factory::farcaster_time() - 100;
+        let cast = |fid, offset| {
+            messages_factory::casts::create_cast_add(
+                fid,
+                &format!("cast at {}", offset),
+                Some(timestamp + offset),
+                None,
+            )
+        };
+        let a1 = cast(SHARD1_FID, 0);
+        let b1 = cast(other_fid, 1);
+        let a2 = cast(SHARD1_FID, 2);
+        let b2 = cast(other_fid, 3);
+        let a3 = cast(SHARD1_FID, 4);
+        for message in [&a1, &b1, &a2, &b2, &a3] {
+            test_helper::commit_message(&mut engine1, message).await;
+        }
+        let remove_a2 = messages_factory::casts::create_cast_remove(
+            SHARD1_FID,
+            &a2.hash,
+            Some(timestamp + 10),
+            None,
+        );
+        test_helper::commit_message(&mut engine1, &remove_a2).await;
+        // Casts on other shards aren't included
+        test_helper::commit_message(&mut engine2, &cast(SHARD2_FID, 5)).await;
+
+        let request = |limit, before_timestamp, page_token| {
+            Request::new(proto::RecentCastsRequest {
+                shard_id: 1,
+                limit,
+                before_timestamp,
+                page_token,
+            })
+        };
+
+        // Newest first, across fids, without the removed cast
+        let response = service.get_recent_casts(request(None, None, None)).await;
+        test_helper::assert_contains_all_messages(&response, &[&a3, &b2, &b1, &a1]);
+        let hashes =
+            |response: &Result<tonic::Response<proto::MessagesResponse>, tonic::Status>| {
+                response
+                    .as_ref()
+                    .unwrap()
+                    .get_ref()
+                    .messages
+                    .iter()
+                    .map(|message| message.hash.clone())
+                    .collect::<Vec<_>>()
+            };
+        assert_eq!(
+            hashes(&response),
+            vec![
+                a3.hash.clone(),
+                b2.hash.clone(),
+                b1.hash.clone(),
+                a1.hash.clone()
+            ]
+        );
+
+        // Paging with a limit
+        let response = service.get_recent_casts(request(Some(2), None, None)).await;
+        assert_eq!(hashes(&response), vec![a3.hash.clone(), b2.hash.clone()]);
+        let page_token = response.unwrap().get_ref().next_page_token.clone();
+        assert!(page_token.is_some());
+        let response = service
+            .get_recent_casts(request(Some(2), None, page_token))
+            .await;
+        assert_eq!(hashes(&response), vec![b1.hash.clone(), a1.hash.clone()]);
+
+        // Only casts before the timestamp
+        let response = service
+            .get_recent_casts(request(None, Some(timestamp + 3), None))
+            .await;
+        assert_eq!(hashes(&response), vec![b1.hash.clone(), a1.hash.clone()]);
+
+        // Pruned casts leave the index too
+        for offset in 5..8 {
+            test_helper::commit_message(&mut engine1, &cast(other_fid, offset)).await;
+        }
+        let response = service.get_recent_casts(request(None, None, None)).await;
+        assert!(!hashes(&response).contains(&b1.hash));
+        assert!(hashes(&response).contains(&b2.hash));
+
+        let response = service
+            .get_recent_casts(Request::new(proto::RecentCastsRequest {
+                shard_id: 3,
+                limit: None,
+                before_timestamp: None,
+                page_token: None,
+            }))
+            .await;
+        assert_eq!(response.unwrap_err().code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_stream_all_messages_by_fid() {
+        let (_, _, [mut engine1, _], service) = make_server(None).await;
+        test_helper::register_user(
+            SHARD1_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine1,
+        )
+        .await;
+        let timestamp = messages_factory::farcaster_time();
+        let mut casts = vec![];
+        for i in 0..4 {
+            let cast = messages_factory::casts::create_cast_add(
+                SHARD1_FID,
+                &format!("cast {}", i),
+                Some(timestamp + i),
+                None,
+            );
+            test_helper::commit_message(&mut engine1, &cast).await;
+            casts.push(cast);
+        }
+
+        let request = |page_size| proto::FidTimestampRequest {
+            fid: SHARD1_FID,
+            page_size,
+            page_token: None,
+            reverse: None,
+            start_timestamp: None,
+            stop_timestamp: None,
+            fields: None,
+        };
+        let stream_pages = |page_size| {
+            let service = &service;
+            async move {
+                service
+                    .stream_all_cast_messages_by_fid(Request::new(request(page_size)))
+                    .await
+                    .unwrap()
+                    .into_inner()
+                    .map(|page| page.unwrap())
+                    .collect::<Vec<_>>()
+                    .await
+            }
+        };
+
+        // Every page is sent, with no empty page when the last one is full
+        let pages = stream_pages(Some(2)).await;
+        assert_eq!(pages.len(), 2);
+        assert!(pages.iter().all(|page| page.messages.len() == 2));
+        assert!(pages[0].next_page_token.is_some());
+        let hashes = pages
+            .iter()
+            .flat_map(|page| page.messages.iter().map(|message| message.hash.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            hashes,
+            casts
+                .iter()
+                .map(|cast| cast.hash.clone())
+                .collect::<Vec<_>>()
+        );
+
+        // Matches the unary rpc
+        let pages = stream_pages(None).await;
+        assert_eq!(pages.len(), 1);
+        let response = service
+            .get_all_cast_messages_by_fid(Request::new(request(None)))
+            .await
+            .unwrap();
+        assert_eq!(pages[0].messages, response.get_ref().messages);
+
+        // A fid without messages gets a single empty page
+        let pages = service
+            .stream_all_link_messages_by_fid(Request::new(request(Some(2))))
+            .await
+            .unwrap()
+            .into_inner()
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(pages.len(), 1);
+        assert!(pages[0].as_ref().unwrap().messages.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_hashes_only_reads() {
+        let (_, _, [mut engine1, _], service) = make_server(None).await;
+        test_helper::register_user(
+            SHARD1_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine1,
+        )
+        .await;
+        let timestamp = messages_factory::farcaster_time();
+        let mut casts = vec![];
+        for i in 0..3 {
+            let cast = messages_factory::casts::create_cast_add(
+                SHARD1_FID,
+                &format!("cast {}", i),
+                Some(timestamp + i),
+                None,
+            );
+            test_helper::commit_message(&mut engine1, &cast).await;
+            casts.push(cast);
+        }
+        let hashes = casts.iter().map(|cast| cast.hash.clone()).collect_vec();
+
+        let request = |fields: Option<proto::MessageFields>| proto::FidTimestampRequest {
+            fid: SHARD1_FID,
+            fields: fields.map(|fields| fields as i32),
+            ..Default::default()
+        };
+        let full = service
+            .get_all_cast_messages_by_fid(Request::new(request(None)))
+            .await
+            .unwrap()
+            .into_inner();
+        let hashes_only = service
+            .get_all_cast_messages_by_fid(Request::new(request(Some(
+                proto::MessageFields::HashesOnly,
+            ))))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(
+            full.messages.iter().map(|m| m.hash.clone()).collect_vec(),
+            hashes
+        );
+        assert_eq!(
+            hashes_only
+                .messages
+                .iter()
+                .map(|m| m.hash.clone())
+                .collect_vec(),
+            hashes
+        );
+        assert_eq!(hashes_only.next_page_token, full.next_page_token);
+
+        // Nothing but the hash is serialized
+        for message in &hashes_only.messages {
+            assert_eq!(
+                message,
+                &proto::Message {
+                    hash: message.hash.clone(),
+                    ..Default::default()
+                }
+            );
+        }
+        assert!(hashes_only.encoded_len() < full.encoded_len() / 2);
+
+        // Streams and the other by-fid reads project the same way
+        let mut pages = service
+            .stream_all_cast_messages_by_fid(Request::new(request(Some(
+                proto::MessageFields::HashesOnly,
+            ))))
+            .await
+            .unwrap()
+            .into_inner();
+        let page = pages.next().await.unwrap().unwrap();
+        assert_eq!(page.messages, hashes_only.messages);
+        let response = service
+            .get_casts_by_fid(Request::new(FidRequest {
+                fid: SHARD1_FID,
+                fields: Some(proto::MessageFields::HashesOnly as i32),
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+        assert_eq!(response.get_ref().messages, hashes_only.messages);
+    }
+
+    #[tokio::test]
+    async fn test_storage_limits() {
+        // Works with no storage
+        let (_, _, [mut engine1, _], service) = make_server(None).await;
+
+        let response = service
+            .get_current_storage_limits_by_fid(FidRequest::for_fid(SHARD1_FID))
+            .await
+            .unwrap();
+        assert_eq!(response.get_ref().units, 0);
+        assert_eq!(response.get_ref().limits.len(), 6);
+        for limit in response.get_ref().limits.iter() {
+            assert_eq!(limit.limit, 0);
+            assert_eq!(limit.used, 0);
+        }
+        assert_eq!(response.get_ref().unit_details.len(), 2);
+        assert_eq!(response.get_ref().unit_details[0].unit_size, 0);
+        assert_eq!(response.get_ref().unit_details[1].unit_size, 0);
+
+        test_helper::register_user(
+            SHARD1_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine1,
+        )
+        .await;
+        // register_user will give the user a single unit of storage, let add one more legacy unit and a 2024 unit for a total of 1 legacy and 2 2024 units
+        test_helper::commit_event(
+            &mut engine1,
+            &events_factory::create_rent_event(SHARD1_FID, Some(1), None, false),
+        )
+        .await;
+        test_helper::commit_event(
+            &mut engine1,
+            &events_factory::create_rent_event(SHARD1_FID, None, Some(1), false),
+        )
+        .await;
+        let cast_add = &messages_factory::casts::create_cast_add(SHARD1_FID, "test", None, None);
+        test_helper::commit_message(&mut engine1, cast_add).await;
+        test_helper::commit_message(
+            &mut engine1,
+            &messages_factory::casts::create_cast_add(SHARD1_FID, "test2", None, None),
+        )
+        .await;
+        test_helper::commit_message(
+            &mut engine1,
+            &messages_factory::casts::create_cast_remove(
+                SHARD1_FID,
+                &cast_add.hash,
+                Some(cast_add.data.as_ref().unwrap().timestamp + 10),
+                None,
+            ),
+        )
+        .await;
+        test_helper::commit_message(
+            &mut engine1,
+            &messages_factory::links::create_link_add(SHARD1_FID, "follow", SHARD2_FID, None, None),
+        )
+        .await;
+
+        let response = service
+            .get_current_storage_limits_by_fid(FidRequest::for_fid(SHARD1_FID))
+            .await
+            .unwrap();
+        assert_eq!(response.get_ref().units, 3);
+        assert_eq!(response.get_ref().unit_details.len(), 2);
+        assert_eq!(response.get_ref().unit_details[0].unit_size, 1);
+        assert_eq!(
+            response.get_ref().unit_details[0].unit_type,
+            proto::StorageUnitType::UnitTypeLegacy as i32
+        );
+        assert_eq!(
+            response.get_ref().unit_details[1].unit_type,
+            proto::StorageUnitType::UnitType2024 as i32
+        );
+        assert_eq!(response.get_ref().unit_details[1].unit_size, 2);
+
+        let casts_limit = response
+            .get_ref()
+            .limits
+            .iter()
+            .filter(|limit| limit.store_type() == proto::StoreType::Casts)
+            .collect::<Vec<_>>()[0];
+        let configured_limits = engine1.get_stores().store_limits;
+        assert_eq!(
+            casts_limit.limit as u32,
+            (configured_limits.limits.casts * 2) + (configured_limits.legacy_limits.casts)
+        );
+        assert_eq!(casts_limit.used, 2); // Cast remove counts as 1
+        assert_eq!(casts_limit.name, "CASTS");
+
+        let links_limit = response
+            .get_ref()
+            .limits
+            .iter()
+            .filter(|limit| limit.store_type() == proto::StoreType::Links)
+            .collect::<Vec<_>>()[0];
+        assert_eq!(links_limit.used, 1);
+
+        // A fid that never bought storage, routed to the other shard, still has no limits
+        let response = service
+            .get_current_storage_limits_by_fid(FidRequest::for_fid(SHARD2_FID))
+            .await
+            .unwrap();
+        assert_eq!(response.get_ref().units, 0);
+        assert!(response
+            .get_ref()
+            .limits
+            .iter()
+            .all(|limit| limit.limit == 0 && limit.used == 0));
+    }
+
+    #[tokio::test]
+    async fn test_verifications_by_protocol() {
+        let (stores, _, _, service) = make_server(None).await;
+        let verification_store = &stores.get(&1).unwrap().verification_store;
+        let timestamp = messages_factory::farcaster_time();
+        // Merged straight into the store, the claims aren't signed
+        for (i, (protocol, address)) in [
+            (proto::Protocol::Ethereum, vec![1u8; 20]),
+            (proto::Protocol::Solana, vec![2u8; 32]),
+            (proto::Protocol::Ethereum, vec![3u8; 20]),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let mut message = messages_factory::verifications::create_verification_add(
+                SHARD1_FID,
+                0,
+                address,
+                vec![],
+                vec![],
+                Some(timestamp + i as u32),
+                None,
+            );
+            if let Some(proto::message_data::Body::VerificationAddAddressBody(body)) =
+                &mut message.data.as_mut().unwrap().body
+            {
+                body.protocol = protocol as i32;
+            }
+            let mut txn = RocksDbTransactionBatch::new();
+            verification_store.merge(&message, &mut txn).unwrap();
+            verification_store.db().commit(txn).unwrap();
+        }
+
+        let get_verifications = |protocol: Option<proto::Protocol>, page_token| {
+            service.get_verifications_by_fid(Request::new(proto::VerificationsByFidRequest {
+                fid: SHARD1_FID,
+                page_size: Some(1),
+                page_token,
+                reverse: None,
+                fields: None,
+                protocol: protocol.map(|protocol| protocol as i32),
+            }))
+        };
+        let protocols = |response: &proto::MessagesResponse| {
+            response
+                .messages
+                .iter()
+                .map(|message| match &message.data.as_ref().unwrap().body {
+                    Some(proto::message_data::Body::VerificationAddAddressBody(body)) => {
+                        body.protocol
+                    }
+                    _ => panic!("Unexpected message body"),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Pages are filled from the matching verifications only
+        let mut ethereum = vec![];
+        let mut page_token = None;
+        loop {
+            let response = get_verifications(Some(proto::Protocol::Ethereum), page_token)
+                .await
+                .unwrap()
+                .into_inner();
+            ethereum.extend(protocols(&response));
+            page_token = response.next_page_token;
+            if page_token.is_none() {
+                break;
+            }
+        }
+        assert_eq!(ethereum, vec![proto::Protocol::Ethereum as i32; 2]);
+
+        let response = get_verifications(Some(proto::Protocol::Solana), None)
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(protocols(&response), vec![proto::Protocol::Solana as i32]);
+
+        // Every protocol without a filter
+        let response = service
+            .get_verifications_by_fid(Request::new(proto::VerificationsByFidRequest {
+                fid: SHARD1_FID,
+                page_size: None,
+                page_token: None,
+                reverse: None,
+                fields: None,
+                protocol: None,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.messages.len(), 3);
+
+        let response = service
+            .get_verifications_by_fid(Request::new(proto::VerificationsByFidRequest {
+                fid: SHARD1_FID,
+                protocol: Some(7),
+                ..Default::default()
+            }))
+            .await;
+        assert_eq!(response.unwrap_err().code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_storage_bytes() {
+        let (_, _, [mut engine1, _], service) = make_server(None).await;
+        test_helper::register_user(
+            SHARD1_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine1,
+        )
+        .await;
+        let cast_add = &messages_factory::casts::create_cast_add(SHARD1_FID, "test", None, None);
+        test_helper::commit_message(&mut engine1, cast_add).await;
+        let link_add =
+            &messages_factory::links::create_link_add(SHARD1_FID, "follow", SHARD2_FID, None, None);
+        test_helper::commit_message(&mut engine1, link_add).await;
+
+        let response = service
+            .get_storage_bytes_by_fid(FidRequest::for_fid(SHARD1_FID))
+            .await
+            .unwrap();
+        let usages = &response.get_ref().usages;
+        assert_eq!(usages.len(), 6);
+        let usage_for = |store_type: proto::StoreType| {
+            usages
+                .iter()
+                .find(|usage| usage.store_type() == store_type)
+                .unwrap()
+        };
+        assert_eq!(
+            usage_for(proto::StoreType::Casts).used,
+            message_encode(cast_add).len() as u64
+        );
+        assert_eq!(usage_for(proto::StoreType::Casts).name, "CASTS");
+        assert_eq!(
+            usage_for(proto::StoreType::Links).used,
+            message_encode(link_add).len() as u64
+        );
+        assert_eq!(usage_for(proto::StoreType::Reactions).used, 0);
+        assert_eq!(
+            response.get_ref().total_used,
+            usages.iter().map(|usage| usage.used).sum::<u64>()
+        );
+        // Stores are only limited by message count by default
+        for usage in usages {
+            assert_eq!(usage.limit, None);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_storage_usage() {
+        let (_, _, [mut engine1, _], service) = make_server(None).await;
+        async fn usage(service: &MyHubService, fid: u64) -> proto::GetStorageUsageResponse {
+            service
+                .get_storage_usage(Request::new(proto::GetStorageUsageRequest { fid }))
+                .await
+                .unwrap()
+                .into_inner()
+        }
+
+        // Without storage every store is used up
+        let response = usage(&service, SHARD1_FID).await;
+        assert_eq!(response.units, 0);
+        assert_eq!(response.usages.len(), 6);
+        assert_eq!(response.status(), proto::StorageUsageStatus::AtLimit);
+
+        test_helper::register_user(
+            SHARD1_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine1,
+        )
+        .await;
+        let timestamp = messages_factory::farcaster_time() - 100;
+        let cast1 =
+            messages_factory::casts::create_cast_add(SHARD1_FID, "cast1", Some(timestamp), None);
+        let cast2 =
+            messages_factory::casts::create_cast_add(SHARD1_FID, "cast2", Some(timestamp), None);
+        let follow = messages_factory::links::create_link_add(
+            SHARD1_FID,
+            "follow",
+            SHARD2_FID,
+            Some(timestamp),
+            None,
+        );
+        let like = messages_factory::reactions::create_reaction_add(
+            SHARD1_FID,
+            proto::ReactionType::Like,
+            "https://example.com".to_string(),
+            Some(timestamp),
+            None,
+        );
+        for message in [&cast1, &cast2, &follow, &like] {
+            test_helper::commit_message(&mut engine1, message).await;
+        }
+
+        let response = usage(&service, SHARD1_FID).await;
+        assert!(response.units > 0);
+        assert_eq!(response.status(), proto::StorageUsageStatus::UnderLimit);
+        let usage_for = |store_type: proto::StoreType| {
+            response
+                .usages
+                .iter()
+                .find(|usage| usage.store_type() == store_type)
+                .unwrap()
+        };
+        let casts = usage_for(proto::StoreType::Casts);
+        assert_eq!(casts.name, "CASTS");
+        assert_eq!(casts.messages, 2);
+        assert!(casts.max_messages > 2);
+        assert_eq!(
+            casts.bytes,
+            (message_encode(&cast1).len() + message_encode(&cast2).len()) as u64
+        );
+        assert_eq!(casts.status(), proto::StorageUsageStatus::UnderLimit);
+        assert_eq!(usage_for(proto::StoreType::Links).messages, 1);
+        assert_eq!(
+            usage_for(proto::StoreType::Links).bytes,
+            message_encode(&follow).len() as u64
+        );
+        assert_eq!(usage_for(proto::StoreType::Reactions).messages, 1);
+        assert_eq!(usage_for(proto::StoreType::UserData).messages, 0);
+        assert_eq!(usage_for(proto::StoreType::UserData).bytes, 0);
+        assert_eq!(
+            response.total_bytes,
+            response.usages.iter().map(|usage| usage.bytes).sum::<u64>()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_fid_activity_counts() {
+        let (_, _, [mut engine1, _], service) = make_server(None).await;
+        test_helper::register_user(
+            SHARD1_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine1,
+        )
+        .await;
+        async fn counts(service: &MyHubService, fid: u64) -> proto::GetFidActivityCountsResponse {
+            service
+                .get_fid_activity_counts(Request::new(proto::GetFidActivityCountsRequest { fid }))
+                .await
+                .unwrap()
+                .into_inner()
+        }
+        assert_eq!(
+            counts(&service, SHARD1_FID).await,
+            proto::GetFidActivityCountsResponse {
+                fid: SHARD1_FID,
+                ..Default::default()
+            }
+        );
+
+        let timestamp = messages_factory::farcaster_time() - 100;
+        let cast1 =
+            messages_factory::casts::create_cast_add(SHARD1_FID, "cast1", Some(timestamp), None);
+        let cast2 =
+            messages_factory::casts::create_cast_add(SHARD1_FID, "cast2", Some(timestamp), None);
+        let follow = messages_factory::links::create_link_add(
+            SHARD1_FID,
+            "follow",
+            SHARD2_FID,
+            Some(timestamp),
+            None,
+        );
+        let like = messages_factory::reactions::create_reaction_add(
+            SHARD1_FID,
+            proto::ReactionType::Like,
+            "https://example.com".to_string(),
+            Some(timestamp),
+            None,
+        );
+        let bio = messages_factory::user_data::create_user_data_add(
+            SHARD1_FID,
+            proto::UserDataType::Bio,
+            &"bio".to_string(),
+            Some(timestamp),
+            None,
+        );
+        for message in [&cast1, &cast2, &follow, &like, &bio] {
+            test_helper::commit_message(&mut engine1, message).await;
+        }
+        assert_eq!(
+            counts(&service, SHARD1_FID).await,
+            proto::GetFidActivityCountsResponse {
+                fid: SHARD1_FID,
+                casts: 2,
+                reactions: 1,
+                links: 1,
+                verifications: 0,
+                user_data: 1,
+            }
+        );
+
+        // Removes take the adds out of the counts
+        let remove_cast1 = messages_factory::casts::create_cast_remove(
+            SHARD1_FID,
+            &cast1.hash,
+            Some(timestamp + 1),
+            None,
+        );
+        let unfollow = messages_factory::links::create_link_remove(
+            SHARD1_FID,
+            "follow",
+            SHARD2_FID,
+            Some(timestamp + 1),
+            None,
+        );
+        for message in [&remove_cast1, &unfollow] {
+            test_helper::commit_message(&mut engine1, message).await;
+        }
+        let response = counts(&service, SHARD1_FID).await;
+        assert_eq!(response.casts, 1);
+        assert_eq!(response.links, 0);
+        assert_eq!(response.reactions, 1);
+
+        // Fids are routed to the shard that owns them
+        assert_eq!(counts(&service, SHARD2_FID).await.casts, 0);
+    }
+
+    #[tokio::test]
+    async fn test_get_network_config() {
+        let (_, _, _, service) = make_server_with(
+            None,
+            SyncProgress::default(),
+            VoteHistory::default(),
+            &[2],
+            None,
+        )
+        .await;
+        let service = service.with_onchain_events_chain_id(11155420);
+
+        let config = service
+            .get_network_config(Request::new(proto::GetNetworkConfigRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(config.network(), proto::FarcasterNetwork::Testnet);
+        assert_eq!(
+            config.farcaster_epoch,
+            crate::core::custom_network::farcaster_epoch()
+        );
+        assert_eq!(config.num_shards, 2);
+        assert_eq!(config.shard_ids, vec![1]);
+        assert_eq!(config.chain_id, 11155420);
+        assert_eq!(
+            hex::encode(&config.id_registry_address),
+            "00000000fc6c5f01fc30151999387bb99a9f489b"
+        );
+        assert_eq!(config.key_registry_address.len(), 20);
+        assert_eq!(config.storage_registry_address.len(), 20);
+        // The limits the stores were created with
+        let limits = test_helper::limits::test_store_limits();
+        assert_eq!(config.limits.unwrap().casts, limits.limits.casts);
+        assert_eq!(
+            config.legacy_limits.unwrap().links,
+            limits.legacy_limits.links
+        );
+        assert!(config.byte_limits.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_get_validator_set() {
+        let (_, _, [mut engine1, _], service) = make_server(None).await;
+        test_helper::register_user(
+            SHARD1_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine1,
+        )
+        .await;
+        let confirmed_height = engine1.get_confirmed_height().block_number;
+
+        let response = service
+            .get_validator_set(Request::new(proto::ValidatorSetRequest { shard_id: 1 }))
+            .await
+            .unwrap();
+        let validator_set = response.get_ref();
+        assert_eq!(validator_set.shard_id, 1);
+        assert_eq!(validator_set.height, confirmed_height + 1);
+        assert_eq!(validator_set.effective_at, 0);
+        assert_eq!(validator_set.validators.len(), 3);
+        assert!(validator_set
+            .validators
+            .iter()
+            .all(|validator| validator.voting_power == 1 && validator.public_key.len() == 32));
+        assert_eq!(validator_set.total_voting_power, 3);
+
+        // Validators take turns proposing in order
+        assert_eq!(validator_set.proposer_schedule.len(), 10);
+        for (i, entry) in validator_set.proposer_schedule.iter().enumerate() {
+            let height = validator_set.height + i as u64;
+            assert_eq!(entry.height, height);
+            assert_eq!(
+                entry.public_key,
+                validator_set.validators[(height as usize - 1) % 3].public_key
+            );
+        }
+
+        // The block shard has its own height
+        let response = service
+            .get_validator_set(Request::new(proto::ValidatorSetRequest { shard_id: 0 }))
+            .await
+            .unwrap();
+        assert_eq!(response.get_ref().height, 1);
+
+        let response = service
+            .get_validator_set(Request::new(proto::ValidatorSetRequest { shard_id: 3 }))
+            .await;
+        assert_eq!(response.unwrap_err().code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_get_epoch_info() {
+        let (_, _, [mut engine1, _], service) = make_server_with(
+            None,
+            SyncProgress::default(),
+            VoteHistory::default(),
+            &[],
+            Some(3),
+        )
+        .await;
+        let epoch_info = |shard_id: u32| {
+            let service = &service;
+            async move {
+                service
+                    .get_epoch_info(Request::new(proto::GetEpochInfoRequest { shard_id }))
+                    .await
+                    .map(|response| response.into_inner())
+            }
+        };
+
+        // Heights 0 to 2 make up the first epoch
+        let info = epoch_info(1).await.unwrap();
+        assert_eq!(
+            (info.height, info.epoch, info.epoch_start_height),
+            (1, 0, 0)
+        );
+        assert_eq!(info.next_epoch_height, Some(3));
+        assert_eq!(info.epoch_length, 3);
+
+        for _ in 0..2 {
+            let state_change = engine1.propose_state_change(1, vec![]);
+            test_helper::validate_and_commit_state_change(&mut engine1, &state_change);
+        }
+        let info = epoch_info(1).await.unwrap();
+        assert_eq!(
+            (info.height, info.epoch, info.epoch_start_height),
+            (3, 1, 3)
+        );
+        assert_eq!(info.next_epoch_height, Some(6));
+
+        // The block shard has no stakes, so it's always in its first epoch
+        let info = epoch_info(0).await.unwrap();
+        assert_eq!((info.epoch, info.epoch_start_height), (0, 0));
+        assert_eq!(info.next_epoch_height, None);
+        assert_eq!(info.epoch_length, 0);
+
+        assert_eq!(
+            epoch_info(3).await.unwrap_err().code(),
+            tonic::Code::InvalidArgument
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_votes() {
+        let vote_history = VoteHistory::default();
+        let (_, _, _, service) = make_server_with(
+            None,
+            SyncProgress::default(),
+            vote_history.clone(),
+            &[],
+            None,
+        )
+        .await;
+        let precommit = |keypair: &Keypair| proto::Vote {
+            r#type: proto::VoteType::Precommit as i32,
+            height: Some(proto::Height {
+                shard_index: 1,
+                block_number: 5,
+            }),
+            round: 0,
+            value: Some(proto::ShardHash {
+                shard_index: 1,
+                hash: vec![1; 32],
+            }),
+            voter: keypair.public().to_bytes().to_vec(),
+        };
+
+        let validator = validator_keypair(1);
+        let vote = precommit(&validator);
+        vote_history.record_vote(&vote, &validator.sign(&vote.encode_to_vec()), 1_000);
+        // Neither a vote from outside the validator set nor a badly signed one are returned
+        let outsider = Keypair::generate();
+        let outsider_vote = precommit(&outsider);
+        vote_history.record_vote(
+            &outsider_vote,
+            &outsider.sign(&outsider_vote.encode_to_vec()),
+            2_000,
+        );
+        vote_history.record_vote(&precommit(&validator_keypair(0)), &[0; 64], 3_000);
+
+        let response = service
+            .get_votes(Request::new(proto::GetVotesRequest {
+                shard_id: 1,
+                height: 5,
+            }))
+            .await
+            .unwrap();
+        let votes = &response.get_ref().votes;
+        assert_eq!(votes.len(), 1);
+        assert_eq!(votes[0].r#type, proto::VoteType::Precommit as i32);
+        assert_eq!(votes[0].voter, vote.voter);
+        assert_eq!(votes[0].validator_index, 1);
+        assert_eq!(votes[0].value, vote.value);
+        assert_eq!(votes[0].timestamp, 1_000);
+
+        let response = service
+            .get_votes(Request::new(proto::GetVotesRequest {
+                shard_id: 1,
+                height: 6,
+            }))
+            .await;
+        assert_eq!(response.unwrap_err().code(), tonic::Code::NotFound);
+
+        let response = service
+            .get_votes(Request::new(proto::GetVotesRequest {
+                shard_id: 3,
+                height: 5,
+            }))
+            .await;
+        assert_eq!(response.unwrap_err().code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_get_proposer_stats() {
+        let vote_history = VoteHistory::default();
+        let (_, _, [mut engine1, _], service) = make_server_with(
+            None,
+            SyncProgress::default(),
+            vote_history.clone(),
+            &[],
+            None,
+        )
+        .await;
+        test_helper::register_user(
+            SHARD1_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine1,
+        )
+        .await;
+        let confirmed_height = engine1.get_confirmed_height().block_number;
+        assert!(confirmed_height >= 3);
+
+        let validator = validator_keypair(0);
+        let prevote = proto::Vote {
+            r#type: proto::VoteType::Prevote as i32,
+            height: Some(proto::Height {
+                shard_index: 1,
+                block_number: 1,
+            }),
+            round: 0,
+            value: None,
+            voter: validator.public().to_bytes().to_vec(),
+        };
+        vote_history.record_vote(&prevote, &validator.sign(&prevote.encode_to_vec()), 1_000);
+
+        let response = service
+            .get_proposer_stats(Request::new(proto::GetProposerStatsRequest {
+                shard_id: 1,
+                start_height: 0,
+                stop_height: None,
+            }))
+            .await
+            .unwrap();
+        let response = response.get_ref();
+        assert_eq!(response.start_height, 1);
+        assert_eq!(response.stop_height, confirmed_height);
+        assert_eq!(response.heights, confirmed_height);
+        assert_eq!(response.heights_with_votes, 1);
+        // Every chunk was committed in its first round, so the validators took turns
+        assert_eq!(response.proposer_stats.len(), 3);
+        assert_eq!(
+            response
+                .proposer_stats
+                .iter()
+                .map(|stats| stats.committed)
+                .sum::<u64>(),
+            confirmed_height
+        );
+        assert!(response
+            .proposer_stats
+            .iter()
+            .all(|stats| stats.committed == stats.proposed
+                && stats.timed_out == 0
+                && stats.committed >= confirmed_height / 3));
+
+        let response = service
+            .get_proposer_stats(Request::new(proto::GetProposerStatsRequest {
+                shard_id: 1,
+                start_height: 2,
+                stop_height: Some(2),
+            }))
+            .await
+            .unwrap();
+        assert_eq!(response.get_ref().heights, 1);
+        assert_eq!(response.get_ref().heights_with_votes, 0);
+
+        for (shard_id, start_height, stop_height) in
+            [(1, 3, Some(2)), (1, 1, Some(20_000)), (3, 1, None)]
+        {
+            let response = service
+                .get_proposer_stats(Request::new(proto::GetProposerStatsRequest {
+                    shard_id,
+                    start_height,
+                    stop_height,
+                }))
+                .await;
+            assert_eq!(response.unwrap_err().code(), tonic::Code::InvalidArgument);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_recent_blocks_summary() {
+        let (_, _, [mut engine1, _], service) = make_server(None).await;
+        test_helper::register_user(
+            SHARD1_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine1,
+        )
+        .await;
+        let confirmed_height = engine1.get_confirmed_height().block_number;
+        let last_chunk = engine1.get_last_shard_chunk().unwrap();
+
+        let response = service
+            .get_recent_blocks_summary(Request::new(proto::GetRecentBlocksSummaryRequest {
+                shard_id: 1,
+                count: 2,
+            }))
+            .await
+            .unwrap();
+        let response = response.get_ref();
+        assert_eq!(response.shard_id, 1);
+        assert_eq!(
+            response
+                .blocks
+                .iter()
+                .map(|block| block.height)
+                .collect::<Vec<_>>(),
+            vec![confirmed_height, confirmed_height - 1]
+        );
+        let latest = &response.blocks[0];
+        assert_eq!(latest.hash, last_chunk.hash);
+        assert_eq!(
+            latest.num_transactions,
+            last_chunk.transactions.len() as u64
+        );
+        assert_eq!(
+            latest.timestamp,
+            last_chunk.header.as_ref().unwrap().timestamp
+        );
+        assert_eq!(latest.proposer.len(), 32);
+        // Committed in their first round, so consecutive heights have different proposers
+        assert_ne!(latest.proposer, response.blocks[1].proposer);
+
+        // Asking for more than are stored returns all of them
+        let response = service
+            .get_recent_blocks_summary(Request::new(proto::GetRecentBlocksSummaryRequest {
+                shard_id: 1,
+                count: 1_000,
+            }))
+            .await
+            .unwrap();
+        assert_eq!(response.get_ref().blocks.len() as u64, confirmed_height);
+
+        let response = service
+            .get_recent_blocks_summary(Request::new(proto::GetRecentBlocksSummaryRequest {
+                shard_id: 3,
+                count: 1,
+            }))
+            .await;
+        assert!(response.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_get_submission_policy() {
+        let (_, _, [engine1, _], service) = make_server(None).await;
+        async fn policy(service: &MyHubService) -> proto::GetSubmissionPolicyResponse {
+            service
+                .get_submission_policy(Request::new(proto::GetSubmissionPolicyRequest {}))
+                .await
+                .unwrap()
+                .into_inner()
+        }
+
+        let response = policy(&service).await;
+        assert!(!response.rate_limits_enabled);
+        assert_eq!(response.max_data_bytes, 2048);
+        assert!(response.disabled_message_types.is_empty());
+        assert!(!response.fid_allowlist_enabled);
+        assert!(!response.submission_sequence_required);
+        assert!(response.frozen_shard_ids.is_empty());
+        assert!(!response.low_disk_space);
+
+        let admission =
+            MessageTypeAdmission::from_config(&["MESSAGE_TYPE_LINK_ADD".to_string()]).unwrap();
+        let service = service
+            .with_message_type_admission(admission.clone())
+            .with_rate_limits(true)
+            .with_submission_sequences(crate::network::submission_sequence::Config {
+                enabled: true,
+            });
+        let response = policy(&service).await;
+        assert!(response.rate_limits_enabled);
+        assert!(response.submission_sequence_required);
+        assert_eq!(
+            response.disabled_message_types,
+            vec![proto::MessageType::LinkAdd as i32]
+        );
+
+        // Runtime changes show up without restarting
+        admission.disable(proto::MessageType::CastAdd);
+        admission.enable(proto::MessageType::LinkAdd);
+        engine1.get_shard_freeze().freeze();
+        let response = policy(&service).await;
+        assert_eq!(
+            response.disabled_message_types,
+            vec![proto::MessageType::CastAdd as i32]
+        );
+        assert_eq!(response.frozen_shard_ids, vec![1]);
+    }
+
+    #[tokio::test]
+    async fn test_unavailable_shard() {
+        let (_, _, [mut engine1, _], service) = make_server_with(
+            None,
+            SyncProgress::default(),
+            VoteHistory::default(),
+            &[2],
+            None,
+        )
+        .await;
+        test_helper::register_user(
+            SHARD1_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine1,
+        )
+        .await;
+
+        // The healthy shard is still served
+        let response = service
+            .get_casts_by_fid(FidRequest::for_fid(SHARD1_FID))
+            .await
+            .unwrap();
+        assert_eq!(response.get_ref().messages.len(), 0);
+
+        let status = service
+            .get_casts_by_fid(FidRequest::for_fid(SHARD2_FID))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+
+        let mut request = Request::new(messages_factory::casts::create_cast_add(
+            SHARD2_FID, "test", None, None,
+        ));
+        add_auth_header(&mut request, USER_NAME, PASSWORD);
+        let status = service.submit_message(request).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+
+        let status = service
+            .get_shard_chunks(Request::new(proto::ShardChunksRequest {
+                shard_id: 2,
+                start_block_number: 0,
+                stop_block_number: None,
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+
+        // Shards that aren't part of the network are still invalid
+        let status = service
+            .get_events(Request::new(proto::EventsRequest {
+                shard_index: Some(3),
+                ..Default::default()
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_get_sync_status() {
+        let sync_progress = SyncProgress::default();
+        let (_, _, [mut engine1, _], service) = make_server_with(
+            None,
+            sync_progress.clone(),
+            VoteHistory::default(),
+            &[],
+            None,
+        )
+        .await;
+        test_helper::register_user(
+            SHARD1_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine1,
+        )
+        .await;
+        let height = engine1.get_confirmed_height().block_number;
+
+        // Nothing heard from peers yet
+        let response = service
+            .get_sync_status(Request::new(proto::GetSyncStatusRequest {}))
+            .await
+            .unwrap();
+        let sync_status = response.get_ref();
+        assert!(!sync_status.synced);
+        let shard_ids: Vec<u32> = sync_status
+            .shard_statuses
+            .iter()
+            .map(|status| status.shard_id)
+            .collect();
+        assert_eq!(shard_ids, vec![0, 1, 2]);
+        assert_eq!(sync_status.shard_statuses[1].height, height);
+        assert_eq!(sync_status.shard_statuses[1].best_peer_height, None);
+
+        let now = Instant::now();
+        let peer_id = libp2p::PeerId::random();
+        sync_progress.record_peer_height(0, peer_id, 0, now);
+        sync_progress.record_peer_height(1, peer_id, height + 10, now);
+        sync_progress.record_peer_height(2, peer_id, 0, now);
+        let response = service
+            .get_sync_status(Request::new(proto::GetSyncStatusRequest {}))
+            .await
+            .unwrap();
+        let sync_status = response.get_ref();
+        assert!(!sync_status.synced);
+        assert!(sync_status.shard_statuses[0].synced);
+        assert!(sync_status.shard_statuses[2].synced);
+        let shard1_status = &sync_status.shard_statuses[1];
+        assert_eq!(shard1_status.best_peer_height, Some(height + 10));
+        assert!(!shard1_status.synced);
+        // The node hasn't reported any progress
+        assert_eq!(shard1_status.eta_seconds, None);
+
+        sync_progress.record_peer_height(1, peer_id, height, now);
+        let response = service
+            .get_sync_status(Request::new(proto::GetSyncStatusRequest {}))
+            .await
+            .unwrap();
+        assert!(response.get_ref().synced);
+        assert_eq!(response.get_ref().shard_statuses[1].eta_seconds, Some(0));
+    }
+
+    #[tokio::test]
+    async fn test_get_shard_stats() {
+        let (_, _, [mut engine1, _], service) = make_server(None).await;
+        service.track_shard_load();
+        test_helper::register_user(
+            SHARD1_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine1,
+        )
+        .await;
+        let cast_add = messages_factory::casts::create_cast_add(SHARD1_FID, "test", None, None);
+        let cast_add2 = messages_factory::casts::create_cast_add(SHARD1_FID, "test2", None, None);
+        test_helper::commit_message(&mut engine1, &cast_add).await;
+        test_helper::commit_message(&mut engine1, &cast_add2).await;
+        service
+            .get_cast(Request::new(proto::CastId {
+                fid: SHARD1_FID,
+                hash: cast_add.hash.clone(),
+            }))
+            .await
+            .unwrap();
+        // The events are counted as they're received
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let response = service
+            .get_shard_stats(Request::new(proto::GetShardStatsRequest {
+                include_fid_activity: true,
+            }))
+            .await
+            .unwrap();
+        let shard_stats = &response.get_ref().shard_stats;
+        assert_eq!(shard_stats.len(), 2);
+        assert_eq!(shard_stats[0].shard_id, 1);
+        assert!(shard_stats[0].requests_per_second > 0.0);
+        assert!(shard_stats[0].writes_per_second > 0.0);
+        assert_eq!(shard_stats[0].messages_written, 2);
+        assert_eq!(
+            shard_stats[0].fid_activity,
+            vec![proto::FidActivity {
+                fid: SHARD1_FID,
+                messages: 2,
+            }]
+        );
+        assert_eq!(shard_stats[1].messages_written, 0);
+        assert_eq!(shard_stats[1].requests_per_second, 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_get_fid_timeline() {
+        let (_, _, [mut engine1, mut engine2], service) = make_server(None).await;
+        test_helper::register_user(
+            SHARD1_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine1,
+        )
+        .await;
+        test_helper::register_user(
+            SHARD2_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine2,
+        )
+        .await;
+        let first = messages_factory::casts::create_cast_add(SHARD1_FID, "first", None, None);
+        let first_chunk = test_helper::commit_message(&mut engine1, &first).await;
+        let second = messages_factory::casts::create_cast_add(SHARD1_FID, "second", None, None);
+        let last_chunk = test_helper::commit_message(&mut engine1, &second).await;
+        let height =
+            |chunk: &proto::ShardChunk| chunk.header.as_ref().unwrap().height.unwrap().block_number;
+
+        let timeline = service
+            .get_fid_timeline(Request::new(proto::GetFidTimelineRequest {
+                fid: SHARD1_FID,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let registration = engine1
+            .get_stores()
+            .onchain_event_store
+            .get_id_register_event_by_fid(SHARD1_FID)
+            .unwrap()
+            .unwrap();
+        assert_eq!(timeline.shard_id, 1);
+        assert_eq!(
+            timeline.registration_block,
+            Some(registration.block_number as u64)
+        );
+        assert_eq!(timeline.first_message_height, Some(height(&first_chunk)));
+        assert_eq!(timeline.last_message_height, Some(height(&last_chunk)));
+        assert!(height(&last_chunk) > height(&first_chunk));
+
+        // Registered without any messages
+        let timeline = service
+            .get_fid_timeline(Request::new(proto::GetFidTimelineRequest {
+                fid: SHARD2_FID,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(timeline.shard_id, 2);
+        assert!(timeline.registration_block.is_some());
+        assert_eq!(timeline.first_message_height, None);
+        assert_eq!(timeline.last_message_height, None);
+
+        let status = service
+            .get_fid_timeline(Request::new(proto::GetFidTimelineRequest {
+                fid: SHARD1_FID + 2,
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_get_shard_manifest() {
+        let (_, _, [mut engine1, _], service) = make_server(None).await;
+        let manifest_request = || Request::new(proto::GetShardManifestRequest { shard_id: 1 });
+        let status = service
+            .get_shard_manifest(manifest_request())
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+
+        let keypair = Keypair::generate();
+        let service = service.with_keypair(keypair.clone());
+        test_helper::register_user(
+            SHARD1_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine1,
+        )
+        .await;
+        for text in ["one", "two"] {
+            let cast = messages_factory::casts::create_cast_add(SHARD1_FID, text, None, None);
+            test_helper::commit_message(&mut engine1, &cast).await;
+        }
+        let link = messages_factory::links::create_link_add(SHARD1_FID, "follow", 100, None, None);
+        test_helper::commit_message(&mut engine1, &link).await;
+
+        let response = service
+            .get_shard_manifest(manifest_request())
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.public_key, keypair.public().to_bytes().to_vec());
+        let public_key =
+            libp2p::identity::ed25519::PublicKey::try_from_bytes(&response.public_key).unwrap();
+        let mut manifest = response.manifest.unwrap();
+        assert!(public_key.verify(&manifest.encode_to_vec(), &response.signature));
+
+        let root = service
+            .get_shard_root(Request::new(proto::ShardRootRequest { shard_id: 1 }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(manifest.height, root.height);
+        assert_eq!(manifest.root_hash, root.root_hash);
+        assert_eq!(
+            manifest
+                .message_counts
+                .iter()
+                .map(|count| (count.store_type(), count.messages))
+                .collect::<Vec<_>>(),
+            vec![(proto::StoreType::Casts, 2), (proto::StoreType::Links, 1)]
+        );
+        let stores = engine1.get_stores();
+        assert_eq!(
+            manifest.num_onchain_events,
+            stores.get_onchain_event_count().unwrap()
+        );
+        assert!(manifest.num_onchain_events > 0);
+        assert_eq!(manifest.num_fnames, 0);
+        assert!(!manifest.sampled_proofs.is_empty());
+        for proof in &manifest.sampled_proofs {
+            assert!(merkle_trie::verify_proof(
+                proof.branching_factor,
+                &proof.trie_key,
+                &proof.steps,
+                &manifest.root_hash
+            ));
+        }
+
+        // Any change to the manifest breaks the signature
+        manifest.message_counts[0].messages += 1;
+        assert!(!public_key.verify(&manifest.encode_to_vec(), &response.signature));
+    }
+
+    #[tokio::test]
+    async fn test_get_network_stats() {
+        let (_, _, [mut engine1, mut engine2], service) = make_server(None).await;
+        service.track_shard_load();
+        test_helper::register_user(
+            SHARD1_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine1,
+        )
+        .await;
+        test_helper::register_user(
+            SHARD2_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine2,
+        )
+        .await;
+        for text in ["one", "two"] {
+            let cast_add = messages_factory::casts::create_cast_add(SHARD1_FID, text, None, None);
+            test_helper::commit_message(&mut engine1, &cast_add).await;
+        }
+        let link_add =
+            messages_factory::links::create_link_add(SHARD2_FID, "follow", SHARD1_FID, None, None);
+        test_helper::commit_message(&mut engine2, &link_add).await;
+        // The events are counted as they're received
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let response = service
+            .get_network_stats(Request::new(proto::GetNetworkStatsRequest {}))
+            .await
+            .unwrap();
+        let stats = response.get_ref();
+        let shard_totals = &stats.shard_totals;
+        assert_eq!(
+            shard_totals
+                .iter()
+                .map(|totals| (totals.shard_id, totals.height))
+                .collect::<Vec<_>>(),
+            vec![
+                (1, engine1.get_confirmed_height().block_number),
+                (2, engine2.get_confirmed_height().block_number)
+            ]
+        );
+        assert_eq!(
+            stats.max_height,
+            shard_totals
+                .iter()
+                .map(|totals| totals.height)
+                .max()
+                .unwrap()
+        );
+        assert_eq!(
+            stats.min_height,
+            shard_totals
+                .iter()
+                .map(|totals| totals.height)
+                .min()
+                .unwrap()
+        );
+        assert_eq!(
+            shard_totals[0].num_messages,
+            engine1.get_stores().get_trie_leaf_count()
+        );
+        assert_eq!(
+            stats.num_messages,
+            shard_totals[0].num_messages + shard_totals[1].num_messages
+        );
+        assert_eq!(stats.num_fid_registrations, 2);
+        assert_eq!(shard_totals[1].num_fid_registrations, 1);
+        // Each registration is an id register, storage rent and signer event
+        assert_eq!(stats.num_onchain_events, 6);
+
+        assert_eq!(stats.messages_written, 3);
+        assert_eq!(
+            stats.messages_written_by_type,
+            vec![
+                proto::MessageTypeCount {
+                    message_type: proto::MessageType::CastAdd as i32,
+                    messages: 2,
+                },
+                proto::MessageTypeCount {
+                    message_type: proto::MessageType::LinkAdd as i32,
+                    messages: 1,
+                },
+            ]
+        );
+        let shard1_stats = shard_totals[0].stats.as_ref().unwrap();
+        assert_eq!(shard1_stats.messages_written, 2);
+        assert!(shard1_stats.fid_activity.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_get_info() {
+        let (_, _, [mut engine1, _], service) = make_server(None).await;
+
+        test_helper::register_user(
+            SHARD1_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine1,
+        )
+        .await;
+        test_helper::commit_message(
+            &mut engine1,
+            &messages_factory::casts::create_cast_add(SHARD1_FID, "test", None, None),
+        )
+        .await;
+
+        let response = service
+            .get_info(Request::new(proto::GetInfoRequest {}))
             .await
             .unwrap();
         let info = response.get_ref();
@@ -1108,6 +3541,15 @@ mod tests {
         assert_eq!(info.shard_infos.len(), 3); // +1 for the block shard
         assert_eq!(info.peer_id, "asddef");
         assert_eq!(info.version, "0.1.2");
+        assert!(!info.onchain_events_status.as_ref().unwrap().halted);
+        assert!(!info.onchain_events_status.as_ref().unwrap().paused);
+        assert!(!info.onchain_events_status.as_ref().unwrap().failed);
+        assert!(info
+            .onchain_events_status
+            .as_ref()
+            .unwrap()
+            .pending_block_range_retries
+            .is_empty());
 
         let block_info = info
             .shard_infos
@@ -1119,6 +3561,11 @@ mod tests {
         assert_eq!(block_info.num_messages, 0);
         assert_eq!(block_info.max_height, 0);
         assert_eq!(block_info.mempool_size, 0);
+        // Shards are validated unless the node is told it only follows them
+        assert!(info
+            .shard_infos
+            .iter()
+            .all(|info| info.role() == proto::ShardRole::Validator));
 
         let shard1_info = info
             .shard_infos
@@ -1170,22 +3617,120 @@ mod tests {
         // Commit the message to engine1
         test_helper::commit_message(&mut engine1, &proof_message).await;
 
-        // Test get_username_proof for ENS name
-        let request = Request::new(UsernameProofRequest {
-            name: ens_username.as_bytes().to_vec(),
-        });
+        // Test get_username_proof for ENS name
+        let request = Request::new(UsernameProofRequest {
+            name: ens_username.as_bytes().to_vec(),
+        });
+
+        let response = service.get_username_proof(request).await;
+        assert!(
+            response.is_ok(),
+            "Failed to get ENS username proof: {:?}",
+            response.err()
+        );
+
+        let proof = response.unwrap().into_inner();
+        assert_eq!(proof.fid, fid);
+        assert_eq!(proof.name, ens_username.as_bytes().to_vec());
+        assert_eq!(proof.r#type, UserNameType::UsernameTypeEnsL1 as i32);
+    }
+
+    #[tokio::test]
+    async fn test_get_user_name_proofs_by_name() {
+        let (stores, _, [mut engine1, _], service) = make_server(None).await;
+        let signer = test_helper::default_signer();
+        let owner = hex::decode("91031dcfdea024b4d51e775486111d2b2a715871").unwrap();
+        let (first_fid, second_fid) = (SHARD1_FID, SHARD1_FID + 2);
+        for fid in [first_fid, second_fid] {
+            test_helper::register_user(fid, signer.clone(), owner.clone(), &mut engine1).await;
+        }
+
+        // The fname is registered by the first fid, then transferred to the second one
+        let user_data_store = &stores.get(&1).unwrap().user_data_store;
+        let timestamp = messages_factory::farcaster_time() as u64;
+        for (fid, timestamp) in [(first_fid, timestamp), (second_fid, timestamp + 10)] {
+            let proof = username_factory::create_username_proof(
+                fid,
+                UserNameType::UsernameTypeFname,
+                &"alice".to_string(),
+                Some(timestamp),
+                owner.clone(),
+            );
+            let mut txn = RocksDbTransactionBatch::new();
+            UserDataStore::merge_username_proof(user_data_store, &proof, &mut txn).unwrap();
+            user_data_store.db().commit(txn).unwrap();
+        }
+
+        // Both fids prove the ENS name, the second one later
+        for (fid, timestamp) in [(first_fid, timestamp), (second_fid, timestamp + 10)] {
+            let proof_message = messages_factory::username_proof::create_username_proof(
+                fid,
+                UserNameType::UsernameTypeEnsL1,
+                "alice.eth".to_string(),
+                owner.clone(),
+                "signature".to_string(),
+                timestamp,
+                None,
+            );
+            test_helper::commit_message(&mut engine1, &proof_message).await;
+        }
 
-        let response = service.get_username_proof(request).await;
-        assert!(
-            response.is_ok(),
-            "Failed to get ENS username proof: {:?}",
-            response.err()
+        for (name, name_type) in [
+            ("alice", UserNameType::UsernameTypeFname),
+            ("alice.eth", UserNameType::UsernameTypeEnsL1),
+        ] {
+            let proofs = service
+                .get_user_name_proofs_by_name(Request::new(UsernameProofRequest {
+                    name: name.as_bytes().to_vec(),
+                }))
+                .await
+                .unwrap()
+                .into_inner()
+                .proofs;
+            // Oldest first, and fnames and ENS names don't share a history
+            assert_eq!(
+                proofs.iter().map(|proof| proof.fid).collect::<Vec<_>>(),
+                vec![first_fid, second_fid]
+            );
+            assert!(proofs
+                .iter()
+                .all(|proof| proof.r#type == name_type as i32
+                    && proof.name == name.as_bytes().to_vec()));
+            assert!(proofs[0].timestamp < proofs[1].timestamp);
+        }
+
+        // Only the current owner has the names
+        let names = |proofs: Vec<UserNameProof>| {
+            proofs
+                .into_iter()
+                .map(|proof| String::from_utf8(proof.name).unwrap())
+                .collect::<Vec<_>>()
+        };
+        let first_fid_proofs = service
+            .get_user_name_proofs_by_fid(FidRequest::for_fid(first_fid))
+            .await
+            .unwrap()
+            .into_inner()
+            .proofs;
+        assert_eq!(names(first_fid_proofs), Vec::<String>::new());
+        let mut second_fid_names = names(
+            service
+                .get_user_name_proofs_by_fid(FidRequest::for_fid(second_fid))
+                .await
+                .unwrap()
+                .into_inner()
+                .proofs,
         );
+        second_fid_names.sort();
+        assert_eq!(second_fid_names, vec!["alice", "alice.eth"]);
 
-        let proof = response.unwrap().into_inner();
-        assert_eq!(proof.fid, fid);
-        assert_eq!(proof.name, ens_username.as_bytes().to_vec());
-        assert_eq!(proof.r#type, UserNameType::UsernameTypeEnsL1 as i32);
+        let unknown = service
+            .get_user_name_proofs_by_name(Request::new(UsernameProofRequest {
+                name: b"bob".to_vec(),
+            }))
+            .await
+            .unwrap();
+        assert!(unknown.into_inner().proofs.is_empty());
     }
 
     #[tokio::test]
@@ -1226,6 +3771,19 @@ mod tests {
         let res = shard1_response.into_inner();
         assert_eq!(res.fids, vec![SHARD1_FID]);
         assert!(res.next_page_token.is_some());
+        let id_register_event = engine1
+            .get_stores()
+            .onchain_event_store
+            .get_id_register_event_by_fid(SHARD1_FID)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            res.registrations,
+            vec![proto::FidRegistration {
+                fid: SHARD1_FID,
+                block_number: id_register_event.block_number as u64,
+            }]
+        );
 
         let shard1_response = service
             .get_fids(Request::new(proto::FidsRequest {
@@ -1317,6 +3875,7 @@ mod tests {
             page_size: Some(1),
             page_token: None,
             reverse: None,
+            fields: None,
         });
         let response = service.get_on_chain_signers_by_fid(request).await.unwrap();
         let events = response.get_ref().events.clone();
@@ -1331,6 +3890,7 @@ mod tests {
             page_size: None,
             page_token: response.get_ref().next_page_token.clone(),
             reverse: None,
+            fields: None,
         });
         let response = service.get_on_chain_signers_by_fid(request).await.unwrap();
         let events = response.get_ref().events.clone();
@@ -1340,4 +3900,701 @@ mod tests {
             .iter()
             .all(|event| event.r#type() == OnChainEventType::EventTypeSigner));
     }
+
+    #[tokio::test]
+    async fn test_get_signers() {
+        let (_stores, _senders, [mut engine1, _], service) = make_server(None).await;
+        let fid = SHARD1_FID;
+        let registered_key = test_helper::default_signer()
+            .verifying_key()
+            .as_bytes()
+            .to_vec();
+        test_helper::register_user(
+            fid,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine1,
+        )
+        .await;
+
+        let service = &service;
+        let get_signers = |fid| async move {
+            service
+                .get_signers(Request::new(proto::SignersRequest { fid }))
+                .await
+                .unwrap()
+                .into_inner()
+                .signers
+        };
+        let keys = |signers: &Vec<proto::ActiveSigner>| {
+            signers
+                .iter()
+                .map(|signer| signer.key.clone())
+                .sorted()
+                .collect::<Vec<_>>()
+        };
+        let signers = get_signers(fid).await;
+        assert_eq!(keys(&signers), vec![registered_key.clone()]);
+        assert_eq!(signers[0].key_type, 1);
+
+        // Each event is in a later block than the one before
+        let timestamp = crate::utils::factory::time::current_timestamp();
+        let signer = generate_signer();
+        let key = signer.verifying_key().as_bytes().to_vec();
+        let signer_event = |event_type, offset| {
+            events_factory::create_signer_event(
+                fid,
+                signer.clone(),
+                event_type,
+                Some(timestamp + offset),
+                None,
+            )
+        };
+        let find = |signers: &Vec<proto::ActiveSigner>| {
+            signers
+                .iter()
+                .find(|active_signer| active_signer.key == key)
+                .cloned()
+        };
+
+        let add = signer_event(proto::SignerEventType::Add, 0);
+        commit_event(&mut engine1, &add).await;
+        let signers = get_signers(fid).await;
+        assert_eq!(
+            keys(&signers),
+            vec![registered_key.clone(), key.clone()]
+                .into_iter()
+                .sorted()
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(find(&signers).unwrap().added_block_number, add.block_number);
+
+        commit_event(
+            &mut engine1,
+            &signer_event(proto::SignerEventType::Remove, 10),
+        )
+        .await;
+        let signers = get_signers(fid).await;
+        assert_eq!(keys(&signers), vec![registered_key.clone()]);
+
+        // Added again, the key is active from the new add
+        let re_add = signer_event(proto::SignerEventType::Add, 20);
+        commit_event(&mut engine1, &re_add).await;
+        let signers = get_signers(fid).await;
+        assert_eq!(signers.len(), 2);
+        let re_added = find(&signers).unwrap();
+        assert_eq!(re_added.added_block_number, re_add.block_number);
+        assert_eq!(re_added.added_transaction_hash, re_add.transaction_hash);
+
+        assert!(get_signers(fid + 1000).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_get_on_chain_events_by_fid() {
+        let (_stores, _senders, [mut engine1, _], service) = make_server(None).await;
+        let fid = SHARD1_FID;
+
+        // Registering creates a storage rent, an id register and a signer event
+        test_helper::register_user(
+            fid,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine1,
+        )
+        .await;
+
+        let request = |event_type: OnChainEventType,
+                       start_block_number: Option<u32>,
+                       page_size: Option<u32>,
+                       page_token: Option<Vec<u8>>| {
+            Request::new(proto::OnChainEventsByFidRequest {
+                fid,
+                event_type: event_type as i32,
+                start_block_number,
+                stop_block_number: None,
+                page_size,
+                page_token,
+                reverse: None,
+            })
+        };
+
+        // All event types, in block order
+        let response = service
+            .get_on_chain_events_by_fid(request(OnChainEventType::EventTypeNone, None, None, None))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.events.len(), 3);
+        assert!(response
+            .events
+            .windows(2)
+            .all(|pair| pair[0].block_number <= pair[1].block_number));
+        assert_eq!(response.next_page_token, None);
+        let all_events = response.events;
+
+        // Pagination
+        let first_page = service
+            .get_on_chain_events_by_fid(request(
+                OnChainEventType::EventTypeNone,
+                None,
+                Some(2),
+                None,
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(first_page.events, all_events[0..2].to_vec());
+        assert!(first_page.next_page_token.is_some());
+
+        let second_page = service
+            .get_on_chain_events_by_fid(request(
+                OnChainEventType::EventTypeNone,
+                None,
+                Some(2),
+                first_page.next_page_token,
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(second_page.events, all_events[2..].to_vec());
+        assert_eq!(second_page.next_page_token, None);
+
+        // Reverse pagination
+        let reverse_request = |page_token: Option<Vec<u8>>| {
+            Request::new(proto::OnChainEventsByFidRequest {
+                reverse: Some(true),
+                ..request(OnChainEventType::EventTypeNone, None, Some(2), page_token).into_inner()
+            })
+        };
+        let first_page = service
+            .get_on_chain_events_by_fid(reverse_request(None))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(
+            first_page.events,
+            vec![all_events[2].clone(), all_events[1].clone()]
+        );
+        let second_page = service
+            .get_on_chain_events_by_fid(reverse_request(first_page.next_page_token))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(second_page.events, vec![all_events[0].clone()]);
+        assert_eq!(second_page.next_page_token, None);
+
+        // Filter by event type
+        let response = service
+            .get_on_chain_events_by_fid(request(
+                OnChainEventType::EventTypeSigner,
+                None,
+                None,
+                None,
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.events.len(), 1);
+        assert_eq!(
+            response.events[0].r#type(),
+            OnChainEventType::EventTypeSigner
+        );
+
+        // Filter by block range
+        let last_block_number = all_events[2].block_number;
+        let response = service
+            .get_on_chain_events_by_fid(request(
+                OnChainEventType::EventTypeNone,
+                Some(last_block_number),
+                None,
+                None,
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(response
+            .events
+            .iter()
+            .all(|event| event.block_number >= last_block_number));
+        assert!(response.events.contains(&all_events[2]));
+
+        // Unknown fid returns nothing
+        let response = service
+            .get_on_chain_events_by_fid(Request::new(proto::OnChainEventsByFidRequest {
+                fid: SHARD1_FID + 2,
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(response.events.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_get_on_chain_events_by_block_range() {
+        let (_stores, _senders, [mut engine1, mut engine2], service) = make_server(None).await;
+
+        let rent_event =
+            |fid: u64, chain_id: u32, block_number: u32, log_index: u32| proto::OnChainEvent {
+                chain_id,
+                block_number,
+                log_index,
+                ..events_factory::create_rent_event(fid, None, Some(1), false)
+            };
+        // Events from both shards, committed out of block order
+        let events = [
+            rent_event(SHARD1_FID, 10, 101, 0),
+            rent_event(SHARD2_FID, 10, 100, 1),
+            rent_event(SHARD1_FID, 10, 100, 0),
+            rent_event(SHARD2_FID, 10, 102, 0),
+            rent_event(SHARD1_FID, 8453, 100, 2),
+        ];
+        for event in &events[..] {
+            let engine = if event.fid == SHARD1_FID {
+                &mut engine1
+            } else {
+                &mut engine2
+            };
+            commit_event(engine, event).await;
+        }
+
+        let request = |start_block: u32,
+                       stop_block: u32,
+                       page_size: Option<u32>,
+                       page_token: Option<Vec<u8>>| {
+            Request::new(proto::OnChainEventsByBlockRangeRequest {
+                chain_id: 10,
+                start_block,
+                stop_block,
+                page_size,
+                page_token,
+            })
+        };
+
+        // Ordered by block and log index across shards, without the other chain's event
+        let response = service
+            .get_on_chain_events_by_block_range(request(100, 103, None, None))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(
+            response.events,
+            vec![
+                events[2].clone(),
+                events[1].clone(),
+                events[0].clone(),
+                events[3].clone()
+            ]
+        );
+        assert_eq!(response.next_page_token, None);
+        let all_events = response.events;
+
+        // The stop block isn't included
+        let response = service
+            .get_on_chain_events_by_block_range(request(101, 102, None, None))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.events, vec![events[0].clone()]);
+
+        // Pagination
+        let mut page_token = None;
+        let mut paged = vec![];
+        loop {
+            let page = service
+                .get_on_chain_events_by_block_range(request(100, 103, Some(3), page_token))
+                .await
+                .unwrap()
+                .into_inner();
+            assert!(page.events.len() <= 3);
+            paged.extend(page.events);
+            page_token = page.next_page_token;
+            if page_token.is_none() {
+                break;
+            }
+        }
+        assert_eq!(paged, all_events);
+
+        let response = service
+            .get_on_chain_events_by_block_range(Request::new(
+                proto::OnChainEventsByBlockRangeRequest {
+                    chain_id: 8453,
+                    start_block: 0,
+                    stop_block: u32::MAX,
+                    page_size: None,
+                    page_token: None,
+                },
+            ))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.events, vec![events[4].clone()]);
+
+        // Empty ranges and tokens from elsewhere are rejected
+        let status = service
+            .get_on_chain_events_by_block_range(request(102, 102, None, None))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        let status = service
+            .get_on_chain_events_by_block_range(request(100, 103, None, Some(vec![1, 2, 3])))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_get_shard_root() {
+        let (_, _, [mut engine1, _], service) = make_server(None).await;
+        test_helper::register_user(
+            SHARD1_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine1,
+        )
+        .await;
+        let cast = messages_factory::casts::create_cast_add(SHARD1_FID, "root", None, None);
+        let header = test_helper::commit_message(&mut engine1, &cast)
+            .await
+            .header
+            .unwrap();
+
+        let root = service
+            .get_shard_root(Request::new(proto::ShardRootRequest { shard_id: 1 }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(root.shard_id, 1);
+        assert_eq!(root.height, header.height.unwrap().block_number);
+        assert_eq!(root.root_hash, header.shard_root);
+
+        // A proposed block doesn't move the root until it's committed
+        let pending = messages_factory::casts::create_cast_add(SHARD1_FID, "pending", None, None);
+        engine1.propose_state_change(1, vec![MempoolMessage::UserMessage(pending)]);
+        let pending_root = service
+            .get_shard_root(Request::new(proto::ShardRootRequest { shard_id: 1 }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(pending_root, root);
+
+        let info = service
+            .get_info(Request::new(proto::GetInfoRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        let shard_root = |shard_id| {
+            info.shard_infos
+                .iter()
+                .find(|shard_info| shard_info.shard_id == shard_id)
+                .unwrap()
+                .shard_root
+                .clone()
+        };
+        assert!(shard_root(0).is_empty());
+        assert_eq!(shard_root(1), header.shard_root);
+
+        // Nothing committed on shard 2 yet
+        let root = service
+            .get_shard_root(Request::new(proto::ShardRootRequest { shard_id: 2 }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(root.height, 0);
+        assert!(root.root_hash.is_empty());
+
+        for shard_id in [0, 3] {
+            let response = service
+                .get_shard_root(Request::new(proto::ShardRootRequest { shard_id }))
+                .await;
+            assert_eq!(response.unwrap_err().code(), tonic::Code::InvalidArgument);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_trie_roots_at_heights() {
+        let (_, _, [mut engine1, _], service) = make_server(None).await;
+        test_helper::register_user(
+            SHARD1_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine1,
+        )
+        .await;
+        let first = messages_factory::casts::create_cast_add(SHARD1_FID, "first", None, None);
+        let first_header = test_helper::commit_message(&mut engine1, &first)
+            .await
+            .header
+            .unwrap();
+        let second = messages_factory::casts::create_cast_add(SHARD1_FID, "second", None, None);
+        let second_header = test_helper::commit_message(&mut engine1, &second)
+            .await
+            .header
+            .unwrap();
+        let first_height = first_header.height.unwrap().block_number;
+        let second_height = second_header.height.unwrap().block_number;
+        assert_ne!(first_header.shard_root, second_header.shard_root);
+
+        // Roots come back in the order the heights were asked for, uncommitted ones aren't found
+        let response = service
+            .get_trie_roots_at_heights(Request::new(proto::GetTrieRootsAtHeightsRequest {
+                shard_id: 1,
+                heights: vec![second_height, first_height, second_height + 10],
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.shard_id, 1);
+        assert_eq!(
+            response.roots,
+            vec![
+                proto::TrieRootAtHeight {
+                    height: second_height,
+                    root_hash: second_header.shard_root,
+                    found: true,
+                },
+                proto::TrieRootAtHeight {
+                    height: first_height,
+                    root_hash: first_header.shard_root,
+                    found: true,
+                },
+                proto::TrieRootAtHeight {
+                    height: second_height + 10,
+                    root_hash: vec![],
+                    found: false,
+                },
+            ]
+        );
+
+        let response = service
+            .get_trie_roots_at_heights(Request::new(proto::GetTrieRootsAtHeightsRequest {
+                shard_id: 1,
+                heights: vec![first_height; 1_001],
+            }))
+            .await;
+        assert_eq!(response.unwrap_err().code(), tonic::Code::InvalidArgument);
+
+        for shard_id in [0, 3] {
+            let response = service
+                .get_trie_roots_at_heights(Request::new(proto::GetTrieRootsAtHeightsRequest {
+                    shard_id,
+                    heights: vec![first_height],
+                }))
+                .await;
+            assert_eq!(response.unwrap_err().code(), tonic::Code::InvalidArgument);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_proof() {
+        let (_, _, [mut engine1, _], service) = make_server(None).await;
+        test_helper::register_user(
+            SHARD1_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine1,
+        )
+        .await;
+
+        let cast = messages_factory::casts::create_cast_add(SHARD1_FID, "proof", None, None);
+        let chunk = test_helper::commit_message(&mut engine1, &cast).await;
+
+        let proof = service
+            .get_proof(Request::new(proto::GetProofRequest {
+                fid: SHARD1_FID,
+                message_hash: cast.hash.clone(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let header = chunk.header.unwrap();
+        assert_eq!(proof.shard_id, 1);
+        assert_eq!(proof.block_number, header.height.unwrap().block_number);
+        assert_eq!(proof.root_hash, header.shard_root);
+        assert_eq!(proof.trie_key, TrieKey::for_message(&cast));
+        assert!(merkle_trie::verify_proof(
+            16,
+            &proof.trie_key,
+            &proof.steps,
+            &header.shard_root
+        ));
+
+        // Proposed but uncommitted messages are not found
+        let pending = messages_factory::casts::create_cast_add(SHARD1_FID, "pending", None, None);
+        engine1.propose_state_change(1, vec![MempoolMessage::UserMessage(pending.clone())]);
+        let response = service
+            .get_proof(Request::new(proto::GetProofRequest {
+                fid: SHARD1_FID,
+                message_hash: pending.hash.clone(),
+            }))
+            .await;
+        assert_eq!(response.unwrap_err().code(), tonic::Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_get_message_with_proof() {
+        let (_, _, [mut engine1, _], service) = make_server(None).await;
+        test_helper::register_user(
+            SHARD1_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine1,
+        )
+        .await;
+
+        let cast = messages_factory::casts::create_cast_add(SHARD1_FID, "light", None, None);
+        let chunk = test_helper::commit_message(&mut engine1, &cast).await;
+        let get_message_with_proof = |hash: Vec<u8>| {
+            service.get_message_with_proof(Request::new(proto::MessageByHashRequest { hash }))
+        };
+
+        let response = get_message_with_proof(cast.hash.clone())
+            .await
+            .unwrap()
+            .into_inner();
+        let (message, proof) = (response.message.unwrap(), response.proof.unwrap());
+        let header = chunk.header.unwrap();
+        assert_eq!(message, cast);
+        assert_eq!(proof.block_number, header.height.unwrap().block_number);
+        assert_eq!(proof.root_hash, header.shard_root);
+        assert_eq!(proof.branching_factor, 16);
+        assert!(merkle_trie::verify_message_proof(
+            &message,
+            &proof,
+            &header.shard_root
+        ));
+
+        // The proof doesn't hold for another message or root
+        let mut tampered = message.clone();
+        tampered.data.as_mut().unwrap().timestamp += 1;
+        tampered.data_bytes = None;
+        assert!(!merkle_trie::verify_message_proof(
+            &tampered,
+            &proof,
+            &header.shard_root
+        ));
+        let other = messages_factory::casts::create_cast_add(SHARD1_FID, "other", None, None);
+        assert!(!merkle_trie::verify_message_proof(
+            &other,
+            &proof,
+            &header.shard_root
+        ));
+        assert!(!merkle_trie::verify_message_proof(
+            &message, &proof, &[0; 20]
+        ));
+
+        // Proposed but uncommitted messages are not found
+        let pending = messages_factory::casts::create_cast_add(SHARD1_FID, "pending", None, None);
+        engine1.propose_state_change(1, vec![MempoolMessage::UserMessage(pending.clone())]);
+        let response = get_message_with_proof(pending.hash.clone()).await;
+        assert_eq!(response.unwrap_err().code(), tonic::Code::NotFound);
+
+        // Nor are removed ones
+        let cast_remove = messages_factory::casts::create_cast_remove(
+            SHARD1_FID,
+            &cast.hash,
+            Some(cast.data.as_ref().unwrap().timestamp + 10),
+            None,
+        );
+        test_helper::commit_message(&mut engine1, &cast_remove).await;
+        let response = get_message_with_proof(cast.hash.clone()).await;
+        assert_eq!(response.unwrap_err().code(), tonic::Code::NotFound);
+
+        let response = get_message_with_proof(vec![1, 2, 3]).await;
+        assert_eq!(response.unwrap_err().code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_get_multi_proof() {
+        let (_, _, [mut engine1, _], service) = make_server(None).await;
+        test_helper::register_user(
+            SHARD1_FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine1,
+        )
+        .await;
+
+        let mut casts = vec![];
+        let mut chunk = None;
+        for text in ["one", "two", "three", "four", "five"] {
+            let cast = messages_factory::casts::create_cast_add(SHARD1_FID, text, None, None);
+            chunk = Some(test_helper::commit_message(&mut engine1, &cast).await);
+            casts.push(cast);
+        }
+        let header = chunk.unwrap().header.unwrap();
+        let get_multi_proof = |hashes: Vec<Vec<u8>>| {
+            service.get_multi_proof(Request::new(proto::GetMultiProofRequest { hashes }))
+        };
+
+        let proof = get_multi_proof(casts.iter().map(|cast| cast.hash.clone()).collect())
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(proof.shard_id, 1);
+        assert_eq!(proof.block_number, header.height.unwrap().block_number);
+        assert_eq!(proof.root_hash, header.shard_root);
+        assert!(merkle_trie::verify_multiproof(
+            &casts,
+            &proof,
+            &header.shard_root
+        ));
+
+        // The siblings the paths share are only sent once
+        let mut individual_proofs_len = 0;
+        for cast in &casts {
+            let individual_proof = service
+                .get_message_with_proof(Request::new(proto::MessageByHashRequest {
+                    hash: cast.hash.clone(),
+                }))
+                .await
+                .unwrap()
+                .into_inner()
+                .proof
+                .unwrap();
+            assert_eq!(individual_proof.root_hash, proof.root_hash);
+            individual_proofs_len += individual_proof.encoded_len();
+        }
+        assert!(proof.encoded_len() < individual_proofs_len);
+
+        // The proof doesn't hold for other messages, another order, another root or altered nodes
+        let mut others = casts.clone();
+        others[0] = messages_factory::casts::create_cast_add(SHARD1_FID, "other", None, None);
+        assert!(!merkle_trie::verify_multiproof(
+            &others,
+            &proof,
+            &header.shard_root
+        ));
+        let mut reordered = casts.clone();
+        reordered.swap(0, 1);
+        assert!(!merkle_trie::verify_multiproof(
+            &reordered,
+            &proof,
+            &header.shard_root
+        ));
+        assert!(!merkle_trie::verify_multiproof(&casts, &proof, &[0; 20]));
+        let mut altered = proof.clone();
+        altered.nodes[0].hash = vec![0; 20];
+        assert!(!merkle_trie::verify_multiproof(
+            &casts,
+            &altered,
+            &header.shard_root
+        ));
+        let mut missing_node = proof.clone();
+        missing_node.nodes.pop();
+        assert!(!merkle_trie::verify_multiproof(
+            &casts,
+            &missing_node,
+            &header.shard_root
+        ));
+
+        let unknown = messages_factory::casts::create_cast_add(SHARD1_FID, "unknown", None, None);
+        let response = get_multi_proof(vec![casts[0].hash.clone(), unknown.hash.clone()]).await;
+        assert_eq!(response.unwrap_err().code(), tonic::Code::NotFound);
+        let response = get_multi_proof(vec![]).await;
+        assert_eq!(response.unwrap_err().code(), tonic::Code::InvalidArgument);
+        let response = get_multi_proof(vec![casts[0].hash.clone(); 101]).await;
+        assert_eq!(response.unwrap_err().code(), tonic::Code::InvalidArgument);
+    }
 }
@@ -0,0 +1,243 @@
+use crate::proto::{
+    self, hub_event, FidActivity, HubEvent, MessageType, MessageTypeCount, ShardStats,
+};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use tracing::warn;
+
+// Rates are averaged over this window
+const RATE_WINDOW: Duration = Duration::from_secs(5 * 60);
+// Requests are counted in buckets of this width, so the window doesn't hold every request
+const BUCKET_WIDTH: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+struct RollingCount {
+    buckets: VecDeque<(Instant, u64)>,
+}
+
+impl RollingCount {
+    fn record(&mut self, count: u64, now: Instant) {
+        match self.buckets.back_mut() {
+            Some((started_at, total)) if now.duration_since(*started_at) < BUCKET_WIDTH => {
+                *total += count;
+            }
+            _ => self.buckets.push_back((now, count)),
+        }
+        self.expire(now);
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while let Some((started_at, _)) = self.buckets.front() {
+            if now.duration_since(*started_at) <= RATE_WINDOW {
+                break;
+            }
+            self.buckets.pop_front();
+        }
+    }
+
+    // Over the time tracked so far until it covers the whole window, so rates right after a
+    // restart aren't underestimated
+    fn per_second(&self, tracked_since: Instant, now: Instant) -> f64 {
+        let elapsed = now.duration_since(tracked_since).min(RATE_WINDOW);
+        let total: u64 = self
+            .buckets
+            .iter()
+            .filter(|(started_at, _)| now.duration_since(*started_at) <= RATE_WINDOW)
+            .map(|(_, count)| *count)
+            .sum();
+        if elapsed.is_zero() {
+            0.0
+        } else {
+            total as f64 / elapsed.as_secs_f64()
+        }
+    }
+}
+
+#[derive(Default)]
+struct ShardActivity {
+    requests: RollingCount,
+    writes: RollingCount,
+    // Since the node started, for planning how to split the load across shards
+    writes_by_fid: HashMap<u64, u64>,
+    writes_by_type: BTreeMap<i32, u64>,
+}
+
+/// The load on each shard: the rate of rpc requests the node serves for it and of messages
+/// committed to it, along with the messages committed per fid. Fed by the rpc server and the
+/// shards' events, and read by the GetShardStats and GetNetworkStats rpcs.
+#[derive(Clone)]
+pub struct ShardLoad {
+    shards: Arc<Mutex<HashMap<u32, ShardActivity>>>,
+    tracked_since: Instant,
+}
+
+impl Default for ShardLoad {
+    fn default() -> Self {
+        Self {
+            shards: Default::default(),
+            tracked_since: Instant::now(),
+        }
+    }
+}
+
+impl ShardLoad {
+    pub fn record_request(&self, shard_id: u32, now: Instant) {
+        let mut shards = self.shards.lock().unwrap();
+        shards.entry(shard_id).or_default().requests.record(1, now);
+    }
+
+    pub fn record_write(&self, shard_id: u32, fid: u64, message_type: MessageType, now: Instant) {
+        let mut shards = self.shards.lock().unwrap();
+        let shard = shards.entry(shard_id).or_default();
+        shard.writes.record(1, now);
+        *shard.writes_by_fid.entry(fid).or_default() += 1;
+        *shard.writes_by_type.entry(message_type as i32).or_default() += 1;
+    }
+
+    /// Counts the messages merged into the shard, as its events are emitted
+    pub fn track_events(&self, shard_id: u32, mut events_rx: broadcast::Receiver<HubEvent>) {
+        let shard_load = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match events_rx.recv().await {
+                    Ok(event) => {
+                        if let Some(hub_event::Body::MergeMessageBody(body)) = &event.body {
+                            if let Some(message) = &body.message {
+                                shard_load.record_write(
+                                    shard_id,
+                                    message.fid(),
+                                    message.msg_type(),
+                                    Instant::now(),
+                                );
+                            }
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!(
+                            shard_id,
+                            skipped, "Shard load fell behind the shard's events"
+                        );
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    /// Stats for the given shards, in order, all taken at once. The per fid counts are only filled
+    /// in when asked for, there's one for every fid that wrote since the node started.
+    pub fn shard_stats(
+        &self,
+        shard_ids: &[u32],
+        include_fid_activity: bool,
+        now: Instant,
+    ) -> Vec<ShardStats> {
+        let mut shards = self.shards.lock().unwrap();
+        shard_ids
+            .iter()
+            .map(|shard_id| {
+                let shard = shards.entry(*shard_id).or_default();
+                shard.requests.expire(now);
+                shard.writes.expire(now);
+                let mut fid_activity: Vec<FidActivity> = if include_fid_activity {
+                    shard
+                        .writes_by_fid
+                        .iter()
+                        .map(|(fid, messages)| FidActivity {
+                            fid: *fid,
+                            messages: *messages,
+                        })
+                        .collect()
+                } else {
+                    vec![]
+                };
+                fid_activity.sort_by_key(|activity| activity.fid);
+                proto::ShardStats {
+                    shard_id: *shard_id,
+                    requests_per_second: shard.requests.per_second(self.tracked_since, now),
+                    writes_per_second: shard.writes.per_second(self.tracked_since, now),
+                    messages_written: shard.writes_by_fid.values().sum(),
+                    active_fids: shard.writes_by_fid.len() as u64,
+                    fid_activity,
+                    messages_written_by_type: shard
+                        .writes_by_type
+                        .iter()
+                        .map(|(message_type, messages)| MessageTypeCount {
+                            message_type: *message_type,
+                            messages: *messages,
+                        })
+                        .collect(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shard_load() {
+        let shard_load = ShardLoad::default();
+        let start = shard_load.tracked_since;
+        for second in 0..10 {
+            let now = start + Duration::from_secs(second);
+            shard_load.record_request(1, now);
+            shard_load.record_request(1, now);
+            let message_type = if second < 3 {
+                MessageType::LinkAdd
+            } else {
+                MessageType::CastAdd
+            };
+            shard_load.record_write(1, 100 + second % 2, message_type, now);
+        }
+        shard_load.record_write(2, 200, MessageType::CastAdd, start);
+
+        let now = start + Duration::from_secs(10);
+        let stats = shard_load.shard_stats(&[1, 2, 3], true, now);
+        assert_eq!(stats.len(), 3);
+        assert_eq!(stats[0].requests_per_second, 2.0);
+        assert_eq!(stats[0].writes_per_second, 1.0);
+        assert_eq!(stats[0].messages_written, 10);
+        assert_eq!(
+            stats[0].fid_activity,
+            vec![
+                FidActivity {
+                    fid: 100,
+                    messages: 5
+                },
+                FidActivity {
+                    fid: 101,
+                    messages: 5
+                }
+            ]
+        );
+        assert_eq!(
+            stats[0].messages_written_by_type,
+            vec![
+                MessageTypeCount {
+                    message_type: MessageType::CastAdd as i32,
+                    messages: 7
+                },
+                MessageTypeCount {
+                    message_type: MessageType::LinkAdd as i32,
+                    messages: 3
+                }
+            ]
+        );
+        assert_eq!(stats[1].active_fids, 1);
+        assert_eq!(stats[2].writes_per_second, 0.0);
+        assert!(shard_load.shard_stats(&[1], false, now)[0]
+            .fid_activity
+            .is_empty());
+
+        // Rates only count the window, the per fid counts are kept
+        let now = start + RATE_WINDOW + Duration::from_secs(20);
+        let stats = shard_load.shard_stats(&[1], true, now);
+        assert_eq!(stats[0].requests_per_second, 0.0);
+        assert_eq!(stats[0].messages_written, 10);
+    }
+}
@@ -0,0 +1,166 @@
+use crate::core::error::HubError;
+use crate::storage::constants::UserPostfix;
+use crate::storage::db::RocksDB;
+use crate::storage::store::account::make_user_key;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use thiserror::Error;
+
+// Carries the sequence of a submitted message, in decimal
+pub const SUBMISSION_SEQUENCE_HEADER: &str = "x-submission-sequence";
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Config {
+    // Every submitted message has to carry a sequence greater than the last one accepted for its
+    // fid. Off by default, messages are otherwise ordered by the CRDT rules alone.
+    pub enabled: bool,
+}
+
+#[derive(Error, Debug, Clone, strum_macros::IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
+pub enum SubmissionSequenceError {
+    #[error("missing the {SUBMISSION_SEQUENCE_HEADER} header")]
+    MissingSequence,
+
+    #[error("sequence {sequence} isn't greater than {last}, the last one accepted for the fid")]
+    DuplicateNonce { sequence: u64, last: u64 },
+
+    #[error(transparent)]
+    StoreError(#[from] HubError),
+}
+
+fn make_sequence_key(fid: u64) -> Vec<u8> {
+    let mut key = make_user_key(fid);
+    key.push(UserPostfix::SubmissionSequence.as_u8());
+    key
+}
+
+/// Enforces increasing sequences on the messages submitted for each fid, so an integration's
+/// messages can't be replayed or reordered through this node. The last accepted sequence is kept
+/// in the fid's shard db, outside the trie and written apart from the commits, so it survives
+/// restarts but isn't shared with other nodes, and messages that arrive through gossip aren't
+/// checked.
+pub struct SubmissionSequences {
+    config: Config,
+    // Held from the check to the write, so two submissions can't both take the same sequence
+    lock: Mutex<()>,
+}
+
+impl SubmissionSequences {
+    pub fn new(config: Config) -> Self {
+        SubmissionSequences {
+            config,
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    pub fn last_sequence(db: &RocksDB, fid: u64) -> Result<Option<u64>, HubError> {
+        match db.get(&make_sequence_key(fid))? {
+            Some(bytes) => {
+                let bytes: [u8; 8] = bytes.as_slice().try_into().map_err(|_| {
+                    HubError::invalid_internal_state("invalid stored submission sequence")
+                })?;
+                Ok(Some(u64::from_be_bytes(bytes)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Records the sequence as the fid's last one if it's greater than the previous one. It's used
+    /// up from then on, even if the message doesn't make it into a block.
+    pub fn accept(
+        &self,
+        db: &RocksDB,
+        fid: u64,
+        sequence: Option<u64>,
+    ) -> Result<(), SubmissionSequenceError> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        let sequence = sequence.ok_or(SubmissionSequenceError::MissingSequence)?;
+
+        let _guard = self.lock.lock().unwrap();
+        if let Some(last) = Self::last_sequence(db, fid)? {
+            if sequence <= last {
+                return Err(SubmissionSequenceError::DuplicateNonce { sequence, last });
+            }
+        }
+        // No commit writes the key, so it's written without flushing the commits being batched
+        let mut txn = db.txn();
+        txn.put(make_sequence_key(fid), sequence.to_be_bytes().to_vec());
+        db.commit_unbuffered(txn).map_err(HubError::from)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open_db(dir: &tempfile::TempDir) -> RocksDB {
+        let db = RocksDB::new(dir.path().join("shard-1").to_str().unwrap());
+        db.open().unwrap();
+        db
+    }
+
+    #[test]
+    fn test_accepts_increasing_sequences() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let db = open_db(&dir);
+        let sequences = SubmissionSequences::new(Config { enabled: true });
+
+        sequences.accept(&db, 1234, Some(1)).unwrap();
+        sequences.accept(&db, 1234, Some(2)).unwrap();
+        // Gaps are fine, only the order matters
+        sequences.accept(&db, 1234, Some(10)).unwrap();
+        // Every fid has its own sequence
+        sequences.accept(&db, 5678, Some(1)).unwrap();
+        assert_eq!(
+            SubmissionSequences::last_sequence(&db, 1234).unwrap(),
+            Some(10)
+        );
+
+        // Kept across restarts
+        db.close();
+        let db = open_db(&dir);
+        assert_eq!(
+            SubmissionSequences::last_sequence(&db, 1234).unwrap(),
+            Some(10)
+        );
+    }
+
+    #[test]
+    fn test_rejects_replayed_and_out_of_order_sequences() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let db = open_db(&dir);
+        let sequences = SubmissionSequences::new(Config { enabled: true });
+        sequences.accept(&db, 1234, Some(5)).unwrap();
+
+        for sequence in [5, 4] {
+            let err = sequences.accept(&db, 1234, Some(sequence)).unwrap_err();
+            assert!(matches!(
+                err,
+                SubmissionSequenceError::DuplicateNonce { last: 5, .. }
+            ));
+            assert_eq!(<&'static str>::from(&err), "duplicate_nonce");
+        }
+        assert!(matches!(
+            sequences.accept(&db, 1234, None),
+            Err(SubmissionSequenceError::MissingSequence)
+        ));
+        // A rejected sequence isn't recorded
+        assert_eq!(
+            SubmissionSequences::last_sequence(&db, 1234).unwrap(),
+            Some(5)
+        );
+
+        // Nothing is checked unless enabled
+        let disabled = SubmissionSequences::new(Config::default());
+        disabled.accept(&db, 1234, Some(1)).unwrap();
+        disabled.accept(&db, 1234, None).unwrap();
+    }
+}
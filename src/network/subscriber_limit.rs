@@ -0,0 +1,84 @@
+use crate::utils::statsd_wrapper::StatsdClientWrapper;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Caps the streaming subscriptions a service serves at once, so a burst of subscribers can't
+/// exhaust the node. The count is reported to statsd whenever it changes.
+#[derive(Clone)]
+pub struct SubscriberLimit {
+    max_subscribers: usize,
+    active: Arc<AtomicUsize>,
+    statsd_client: StatsdClientWrapper,
+}
+
+/// Held for the lifetime of a subscription, the subscriber is no longer counted once it's dropped
+pub struct SubscriberPermit {
+    active: Arc<AtomicUsize>,
+    statsd_client: StatsdClientWrapper,
+}
+
+impl SubscriberLimit {
+    pub fn new(max_subscribers: usize, statsd_client: StatsdClientWrapper) -> Self {
+        SubscriberLimit {
+            max_subscribers,
+            active: Arc::new(AtomicUsize::new(0)),
+            statsd_client,
+        }
+    }
+
+    /// None if the service already has the maximum number of subscribers
+    pub fn try_acquire(&self) -> Option<SubscriberPermit> {
+        let acquired = self
+            .active
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |active| {
+                (active < self.max_subscribers).then_some(active + 1)
+            });
+        match acquired {
+            Ok(previous) => {
+                self.statsd_client
+                    .gauge("rpc.streaming_subscribers", previous as u64 + 1);
+                Some(SubscriberPermit {
+                    active: self.active.clone(),
+                    statsd_client: self.statsd_client.clone(),
+                })
+            }
+            Err(_) => None,
+        }
+    }
+
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for SubscriberPermit {
+    fn drop(&mut self) {
+        let previous = self.active.fetch_sub(1, Ordering::SeqCst);
+        self.statsd_client
+            .gauge("rpc.streaming_subscribers", previous as u64 - 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::store::test_helper;
+
+    #[test]
+    fn test_limits_subscribers() {
+        let limit = SubscriberLimit::new(2, test_helper::statsd_client());
+        let first = limit.try_acquire().unwrap();
+        let second = limit.clone().try_acquire().unwrap();
+        assert!(limit.try_acquire().is_none());
+        assert_eq!(limit.active(), 2);
+
+        drop(first);
+        assert_eq!(limit.active(), 1);
+        let third = limit.try_acquire().unwrap();
+        assert!(limit.try_acquire().is_none());
+
+        drop(second);
+        drop(third);
+        assert_eq!(limit.active(), 0);
+    }
+}
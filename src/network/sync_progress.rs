@@ -0,0 +1,252 @@
+use crate::proto;
+use libp2p::PeerId;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// The sync rate is averaged over the heights the node reported in this window
+const RATE_WINDOW: Duration = Duration::from_secs(5 * 60);
+// Peers that stopped sending status messages don't count towards the best height
+const PEER_HEIGHT_TTL: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Default)]
+struct ShardProgress {
+    peer_heights: HashMap<PeerId, (u64, Instant)>,
+    local_heights: VecDeque<(Instant, u64)>,
+    snapshot_height: Option<u64>,
+}
+
+impl ShardProgress {
+    // The median of the peers' heights, the upper one for an even count. Status messages aren't
+    // verified, so a few peers reporting heights far off don't move it.
+    fn best_peer_height(&self, now: Instant) -> Option<u64> {
+        let mut heights: Vec<u64> = self
+            .peer_heights
+            .values()
+            .filter(|(_, seen_at)| now.duration_since(*seen_at) <= PEER_HEIGHT_TTL)
+            .map(|(height, _)| *height)
+            .collect();
+        heights.sort_unstable();
+        heights.get(heights.len() / 2).copied()
+    }
+
+    fn blocks_per_second(&self) -> f64 {
+        match (self.local_heights.front(), self.local_heights.back()) {
+            (Some((first_at, first_height)), Some((last_at, last_height))) => {
+                let elapsed = last_at.duration_since(*first_at).as_secs_f64();
+                if elapsed > 0.0 {
+                    last_height.saturating_sub(*first_height) as f64 / elapsed
+                } else {
+                    0.0
+                }
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+/// Tracks how far behind its peers the node is on each shard and how fast it's catching up. Fed
+/// by the status messages gossiped by peers and the ones the node broadcasts itself, and read by
+/// the GetSyncStatus rpc. Also counts the connected peers, which consensus waits on before it
+/// starts.
+#[derive(Clone, Default)]
+pub struct SyncProgress {
+    shards: Arc<Mutex<HashMap<u32, ShardProgress>>>,
+    connected_peers: Arc<AtomicUsize>,
+    // Shards that haven't joined consensus because too few peers are connected
+    shards_waiting_for_peers: Arc<Mutex<HashSet<u32>>>,
+}
+
+impl SyncProgress {
+    pub fn record_connected_peers(&self, count: usize) {
+        self.connected_peers.store(count, Ordering::Relaxed);
+    }
+
+    pub fn connected_peers(&self) -> usize {
+        self.connected_peers.load(Ordering::Relaxed)
+    }
+
+    pub fn set_waiting_for_peers(&self, shard_id: u32, waiting: bool) {
+        let mut shards = self.shards_waiting_for_peers.lock().unwrap();
+        if waiting {
+            shards.insert(shard_id);
+        } else {
+            shards.remove(&shard_id);
+        }
+    }
+
+    pub fn record_peer_height(&self, shard_id: u32, peer_id: PeerId, height: u64, now: Instant) {
+        let mut shards = self.shards.lock().unwrap();
+        let shard = shards.entry(shard_id).or_default();
+        shard
+            .peer_heights
+            .retain(|_, (_, seen_at)| now.duration_since(*seen_at) <= PEER_HEIGHT_TTL);
+        shard.peer_heights.insert(peer_id, (height, now));
+    }
+
+    pub fn record_local_height(&self, shard_id: u32, height: u64, now: Instant) {
+        let mut shards = self.shards.lock().unwrap();
+        let local_heights = &mut shards.entry(shard_id).or_default().local_heights;
+        local_heights.push_back((now, height));
+        while let Some((recorded_at, _)) = local_heights.front() {
+            if now.duration_since(*recorded_at) <= RATE_WINDOW {
+                break;
+            }
+            local_heights.pop_front();
+        }
+    }
+
+    /// Records that the shard was restored from a snapshot at the height when the node started.
+    pub fn record_snapshot_height(&self, shard_id: u32, height: u64) {
+        let mut shards = self.shards.lock().unwrap();
+        shards.entry(shard_id).or_default().snapshot_height = Some(height);
+    }
+
+    /// Status of a shard whose latest committed block is at `height`. The eta is unset while the
+    /// node isn't making progress, and so is the best peer height until a peer sent its status.
+    pub fn shard_status(&self, shard_id: u32, height: u64, now: Instant) -> proto::ShardSyncStatus {
+        let shards = self.shards.lock().unwrap();
+        let (best_peer_height, blocks_per_second, snapshot_height) = match shards.get(&shard_id) {
+            Some(shard) => (
+                shard.best_peer_height(now),
+                shard.blocks_per_second(),
+                shard.snapshot_height,
+            ),
+            None => (None, 0.0, None),
+        };
+
+        let blocks_behind = best_peer_height.map(|best| best.saturating_sub(height));
+        let eta_seconds = match blocks_behind {
+            Some(0) => Some(0),
+            Some(blocks_behind) if blocks_per_second > 0.0 => {
+                Some((blocks_behind as f64 / blocks_per_second).ceil() as u64)
+            }
+            _ => None,
+        };
+
+        proto::ShardSyncStatus {
+            shard_id,
+            height,
+            best_peer_height,
+            blocks_per_second,
+            eta_seconds,
+            synced: blocks_behind == Some(0),
+            snapshot_height,
+            waiting_for_peers: self
+                .shards_waiting_for_peers
+                .lock()
+                .unwrap()
+                .contains(&shard_id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catching_up() {
+        let start = Instant::now();
+        let progress = SyncProgress::default();
+        progress.record_peer_height(1, PeerId::random(), 1_000, start);
+        progress.record_peer_height(1, PeerId::random(), 1_200, start);
+
+        progress.record_local_height(1, 100, start);
+        progress.record_local_height(1, 300, start + Duration::from_secs(10));
+        let later = start + Duration::from_secs(10);
+        let status = progress.shard_status(1, 300, later);
+        assert_eq!(status.best_peer_height, Some(1_200));
+        assert_eq!(status.blocks_per_second, 20.0);
+        assert_eq!(status.eta_seconds, Some(45));
+        assert!(!status.synced);
+
+        // Heights older than the window no longer count towards the rate
+        let much_later = later + RATE_WINDOW;
+        progress.record_local_height(1, 1_200, much_later);
+        let status = progress.shard_status(1, 1_200, much_later);
+        assert_eq!(status.blocks_per_second, 900.0 / RATE_WINDOW.as_secs_f64());
+        // The peers' heights are stale by now
+        assert_eq!(status.best_peer_height, None);
+        assert_eq!(status.eta_seconds, None);
+        assert!(!status.synced);
+    }
+
+    #[test]
+    fn test_caught_up_and_stuck() {
+        let start = Instant::now();
+        let progress = SyncProgress::default();
+        let peer_id = PeerId::random();
+
+        let status = progress.shard_status(1, 10, start);
+        assert_eq!(status.best_peer_height, None);
+        assert!(!status.synced);
+
+        progress.record_peer_height(1, peer_id, 10, start);
+        let status = progress.shard_status(1, 10, start);
+        assert_eq!(status.eta_seconds, Some(0));
+        assert!(status.synced);
+
+        // A node that isn't committing anything has no eta
+        progress.record_peer_height(1, peer_id, 50, start);
+        progress.record_local_height(1, 10, start);
+        progress.record_local_height(1, 10, start + Duration::from_secs(30));
+        let status = progress.shard_status(1, 10, start + Duration::from_secs(30));
+        assert_eq!(status.blocks_per_second, 0.0);
+        assert_eq!(status.eta_seconds, None);
+        assert!(!status.synced);
+
+        // Shards are tracked separately
+        assert_eq!(progress.shard_status(2, 10, start).best_peer_height, None);
+    }
+
+    #[test]
+    fn test_peer_height_outliers_are_ignored() {
+        let start = Instant::now();
+        let progress = SyncProgress::default();
+        progress.record_peer_height(1, PeerId::random(), 100, start);
+        progress.record_peer_height(1, PeerId::random(), 110, start);
+        progress.record_peer_height(1, PeerId::random(), u64::MAX, start);
+        let status = progress.shard_status(1, 110, start);
+        assert_eq!(status.best_peer_height, Some(110));
+        assert!(status.synced);
+
+        progress.record_peer_height(1, PeerId::random(), 0, start);
+        progress.record_peer_height(1, PeerId::random(), 0, start);
+        assert_eq!(
+            progress.shard_status(1, 110, start).best_peer_height,
+            Some(100)
+        );
+    }
+
+    #[test]
+    fn test_bootstrapped_from_snapshot() {
+        let start = Instant::now();
+        let progress = SyncProgress::default();
+        assert_eq!(progress.shard_status(1, 0, start).snapshot_height, None);
+
+        progress.record_snapshot_height(1, 500);
+        progress.record_peer_height(1, PeerId::random(), 600, start);
+        let status = progress.shard_status(1, 550, start);
+        assert_eq!(status.snapshot_height, Some(500));
+        assert_eq!(status.best_peer_height, Some(600));
+        assert_eq!(progress.shard_status(2, 0, start).snapshot_height, None);
+    }
+
+    #[test]
+    fn test_waiting_for_peers() {
+        let start = Instant::now();
+        let progress = SyncProgress::default();
+        let shared = progress.clone();
+        assert_eq!(progress.connected_peers(), 0);
+        shared.record_connected_peers(3);
+        assert_eq!(progress.connected_peers(), 3);
+
+        shared.set_waiting_for_peers(1, true);
+        assert!(progress.shard_status(1, 0, start).waiting_for_peers);
+        assert!(!progress.shard_status(2, 0, start).waiting_for_peers);
+        shared.set_waiting_for_peers(1, false);
+        assert!(!progress.shard_status(1, 0, start).waiting_for_peers);
+    }
+}
@@ -0,0 +1,148 @@
+use crate::proto;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+// Votes are kept for this many heights below the node's latest height
+pub const VOTE_RETENTION_HEIGHTS: u64 = 1_000;
+// Votes for heights further ahead of the node than this are dropped, so peers can't push the
+// retained heights out with votes for made up heights
+const MAX_HEIGHTS_AHEAD: u64 = 100;
+// Plenty for any validator set, it only bounds what a misbehaving peer can make the node keep
+const MAX_VOTES_PER_HEIGHT: usize = 1_000;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordedVote {
+    pub vote: proto::Vote,
+    pub signature: Vec<u8>,
+    // Unix time in milliseconds the node first saw the vote
+    pub observed_at: u64,
+}
+
+#[derive(Default)]
+struct ShardVotes {
+    local_height: u64,
+    heights: BTreeMap<u64, Vec<RecordedVote>>,
+}
+
+fn is_retained(height: u64, local_height: u64) -> bool {
+    height + VOTE_RETENTION_HEIGHTS > local_height && height <= local_height + MAX_HEIGHTS_AHEAD
+}
+
+/// Prevotes and precommits the node saw for recent heights of each shard, its own included. Fed
+/// by the consensus messages gossiped by peers and the ones the node broadcasts itself, and read
+/// by the GetVotes rpc. Votes aren't verified when they're recorded, readers have to check them
+/// against the validator set at their height.
+#[derive(Clone, Default)]
+pub struct VoteHistory {
+    shards: Arc<Mutex<HashMap<u32, ShardVotes>>>,
+}
+
+impl VoteHistory {
+    pub fn record_vote(&self, vote: &proto::Vote, signature: &[u8], observed_at: u64) {
+        let Some(height) = &vote.height else {
+            return;
+        };
+        let mut shards = self.shards.lock().unwrap();
+        let shard = shards.entry(height.shard_index).or_default();
+        if !is_retained(height.block_number, shard.local_height) {
+            return;
+        }
+
+        let votes = shard.heights.entry(height.block_number).or_default();
+        // Votes are gossiped more than once, only the first sighting counts
+        let seen = votes.iter().any(|recorded| {
+            recorded.vote.voter == vote.voter
+                && recorded.vote.r#type == vote.r#type
+                && recorded.vote.round == vote.round
+                && recorded.signature == signature
+        });
+        if !seen && votes.len() < MAX_VOTES_PER_HEIGHT {
+            votes.push(RecordedVote {
+                vote: vote.clone(),
+                signature: signature.to_vec(),
+                observed_at,
+            });
+        }
+    }
+
+    pub fn record_local_height(&self, shard_id: u32, height: u64) {
+        let mut shards = self.shards.lock().unwrap();
+        let shard = shards.entry(shard_id).or_default();
+        shard.local_height = shard.local_height.max(height);
+        let local_height = shard.local_height;
+        shard
+            .heights
+            .retain(|height, _| is_retained(*height, local_height));
+    }
+
+    /// Votes seen for the height, in the order they were first seen. None if the height isn't
+    /// retained, or no vote for it was seen.
+    pub fn votes(&self, shard_id: u32, height: u64) -> Option<Vec<RecordedVote>> {
+        let shards = self.shards.lock().unwrap();
+        shards.get(&shard_id)?.heights.get(&height).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vote(shard_id: u32, height: u64, voter: u8, vote_type: proto::VoteType) -> proto::Vote {
+        proto::Vote {
+            r#type: vote_type as i32,
+            height: Some(proto::Height {
+                shard_index: shard_id,
+                block_number: height,
+            }),
+            round: 0,
+            value: None,
+            voter: vec![voter; 32],
+        }
+    }
+
+    #[test]
+    fn test_records_votes_once() {
+        let history = VoteHistory::default();
+        assert_eq!(history.votes(1, 5), None);
+
+        let prevote = vote(1, 5, 1, proto::VoteType::Prevote);
+        let precommit = vote(1, 5, 1, proto::VoteType::Precommit);
+        history.record_vote(&prevote, &[1], 1_000);
+        history.record_vote(&prevote, &[1], 2_000);
+        history.record_vote(&precommit, &[2], 3_000);
+        history.record_vote(&vote(2, 5, 1, proto::VoteType::Prevote), &[3], 4_000);
+
+        let votes = history.votes(1, 5).unwrap();
+        assert_eq!(votes.len(), 2);
+        assert_eq!(votes[0].vote, prevote);
+        assert_eq!(votes[0].observed_at, 1_000);
+        assert_eq!(votes[1].vote, precommit);
+        assert_eq!(votes[1].signature, vec![2]);
+        // Shards are tracked separately
+        assert_eq!(history.votes(2, 5).unwrap().len(), 1);
+        assert_eq!(history.votes(1, 6), None);
+    }
+
+    #[test]
+    fn test_retention() {
+        let history = VoteHistory::default();
+        history.record_vote(&vote(1, 10, 1, proto::VoteType::Prevote), &[1], 0);
+        history.record_local_height(1, 10);
+
+        // Heights too far ahead of the node aren't recorded
+        history.record_vote(
+            &vote(1, 11 + MAX_HEIGHTS_AHEAD, 1, proto::VoteType::Prevote),
+            &[1],
+            0,
+        );
+        assert_eq!(history.votes(1, 11 + MAX_HEIGHTS_AHEAD), None);
+
+        // Old heights are dropped as the node moves on
+        history.record_local_height(1, 9 + VOTE_RETENTION_HEIGHTS);
+        assert!(history.votes(1, 10).is_some());
+        history.record_local_height(1, 10 + VOTE_RETENTION_HEIGHTS);
+        assert_eq!(history.votes(1, 10), None);
+        history.record_vote(&vote(1, 10, 2, proto::VoteType::Prevote), &[1], 0);
+        assert_eq!(history.votes(1, 10), None);
+    }
+}
@@ -1,2 +1,156 @@
+pub mod read_replica;
+pub mod shutdown;
 pub mod snapchain_node;
 pub mod snapchain_read_node;
+
+use crate::storage::db::{self, RocksDB};
+use crate::storage::store::self_check;
+use crate::utils::statsd_wrapper::StatsdClientWrapper;
+use std::sync::Arc;
+use tracing::{error, info};
+
+/// Opens the db of one of the node's shards, and runs the startup self-check on it when enabled.
+/// A db that can't be opened, e.g. because its data is corrupt, or that fails the check stops the
+/// node unless shard failures are tolerated. The shard is then left out, and the node serves the
+/// other ones until the broken shard is resynced.
+pub fn open_shard_db(
+    rocksdb_dir: &str,
+    storage_config: &db::Config,
+    shard_id: u32,
+    trie_branching_factor: u32,
+    tolerate_shard_failures: bool,
+    statsd_client: &StatsdClientWrapper,
+) -> Option<Arc<RocksDB>> {
+    let shard_dir = storage_config.shard_base_dir(rocksdb_dir, shard_id);
+    let db = match RocksDB::try_open_shard_db(
+        shard_dir.as_str(),
+        shard_id,
+        &storage_config.network_namespace,
+        storage_config.shard_write_durability(shard_id),
+        storage_config.compression,
+    ) {
+        Ok(db) => db,
+        Err(err) if tolerate_shard_failures => {
+            error!(
+                shard_id,
+                shard_dir = shard_dir.as_str(),
+                error = ?err,
+                "SHARD UNAVAILABLE: failed to open the shard db, starting without it. Requests for \
+                 the shard are rejected until it's resynced"
+            );
+            statsd_client.gauge_with_shard(shard_id, "node.shard_unavailable", 1);
+            return None;
+        }
+        Err(err) => panic!("Failed to open db for shard {}: {:?}", shard_id, err),
+    };
+
+    if storage_config.self_check.enabled {
+        let deep = storage_config.self_check.deep;
+        let start = std::time::Instant::now();
+        let result = self_check::check_shard(&db, shard_id, trie_branching_factor, deep);
+        statsd_client.gauge_with_shard(
+            shard_id,
+            "node.self_check_time_ms",
+            start.elapsed().as_millis() as u64,
+        );
+        statsd_client.gauge_with_shard(shard_id, "node.self_check_failed", result.is_err() as u64);
+        match result {
+            Ok(report) => info!(
+                shard_id,
+                deep,
+                tip_height = report.tip_height,
+                heights_checked = report.heights_checked,
+                "Shard db passed the startup self-check"
+            ),
+            Err(err) if tolerate_shard_failures => {
+                error!(
+                    shard_id,
+                    deep,
+                    error = %err,
+                    "SHARD UNAVAILABLE: the shard db failed the startup self-check, starting \
+                     without it. Requests for the shard are rejected until it's resynced"
+                );
+                statsd_client.gauge_with_shard(shard_id, "node.shard_unavailable", 1);
+                return None;
+            }
+            Err(err) => panic!("Shard {} failed the startup self-check: {}", shard_id, err),
+        }
+    }
+
+    statsd_client.gauge_with_shard(shard_id, "node.shard_unavailable", 0);
+    Some(db)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto;
+    use crate::storage::store::shard::ShardStore;
+    use crate::storage::store::test_helper;
+
+    // A file where the shard's db directory should be, which can't be opened as a db
+    fn make_unopenable_shard(rocksdb_dir: &str, shard_id: u32) {
+        std::fs::write(format!("{}/shard-{}", rocksdb_dir, shard_id), "corrupt").unwrap();
+    }
+
+    #[test]
+    fn test_unopenable_shard_is_left_out() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let rocksdb_dir = dir.path().to_str().unwrap();
+        make_unopenable_shard(rocksdb_dir, 2);
+
+        let storage_config = db::Config::default();
+        let statsd_client = test_helper::statsd_client();
+        let db = open_shard_db(rocksdb_dir, &storage_config, 1, 16, true, &statsd_client);
+        assert!(db.is_some());
+        let db = open_shard_db(rocksdb_dir, &storage_config, 2, 16, true, &statsd_client);
+        assert!(db.is_none());
+    }
+
+    #[test]
+    fn test_shard_failing_self_check_is_left_out() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let rocksdb_dir = dir.path().to_str().unwrap();
+        // A chunk recording a root the empty trie doesn't have
+        let db = RocksDB::open_shard_db(rocksdb_dir, 2, "", db::WriteDurability::default());
+        let chunk = proto::ShardChunk {
+            header: Some(proto::ShardHeader {
+                height: Some(proto::Height::new(2, 1)),
+                shard_root: vec![1; 20],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        ShardStore::new(db.clone(), 2)
+            .put_shard_chunk(&chunk)
+            .unwrap();
+        db.close();
+
+        let mut storage_config = db::Config::default();
+        let statsd_client = test_helper::statsd_client();
+        let db = open_shard_db(rocksdb_dir, &storage_config, 2, 16, true, &statsd_client);
+        assert!(db.is_some());
+        db.unwrap().close();
+
+        storage_config.self_check.enabled = true;
+        let db = open_shard_db(rocksdb_dir, &storage_config, 2, 16, true, &statsd_client);
+        assert!(db.is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Failed to open db for shard 2")]
+    fn test_unopenable_shard_fails_startup_by_default() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let rocksdb_dir = dir.path().to_str().unwrap();
+        make_unopenable_shard(rocksdb_dir, 2);
+
+        open_shard_db(
+            rocksdb_dir,
+            &db::Config::default(),
+            2,
+            16,
+            false,
+            &test_helper::statsd_client(),
+        );
+    }
+}
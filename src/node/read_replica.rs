@@ -0,0 +1,294 @@
+use crate::proto;
+use crate::storage::db::{self, RocksDB};
+use crate::storage::store::stores::{StoreLimits, Stores};
+use crate::storage::store::BlockStore;
+use crate::storage::trie::merkle_trie::MerkleTrie;
+use crate::utils::statsd_wrapper::StatsdClientWrapper;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Config {
+    // The rocksdb_dir of the node whose dbs are served, on the same machine. Unset runs a regular
+    // node. Shard dir overrides apply to it, the replica's own files stay under rocksdb_dir.
+    pub primary_rocksdb_dir: String,
+    // How often the dbs catch up with the primary's writes
+    #[serde(with = "humantime_serde")]
+    pub catch_up_interval: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            primary_rocksdb_dir: "".to_string(),
+            catch_up_interval: Duration::from_millis(500),
+        }
+    }
+}
+
+impl Config {
+    pub fn enabled(&self) -> bool {
+        !self.primary_rocksdb_dir.is_empty()
+    }
+}
+
+struct ShardCatchUp {
+    caught_up_at: Instant,
+    error: Option<String>,
+}
+
+/// When each shard's db last caught up with the primary, shared with the rpc servers for GetInfo
+#[derive(Clone, Default)]
+pub struct CatchUpStatus {
+    shards: Arc<RwLock<BTreeMap<u32, ShardCatchUp>>>,
+}
+
+impl CatchUpStatus {
+    fn record(&self, shard_id: u32, error: Option<String>) {
+        let mut shards = self.shards.write().unwrap();
+        match (shards.get_mut(&shard_id), error) {
+            // A failed attempt leaves the db as of the last one that succeeded
+            (Some(shard), Some(error)) => shard.error = Some(error),
+            (_, error) => {
+                shards.insert(
+                    shard_id,
+                    ShardCatchUp {
+                        caught_up_at: Instant::now(),
+                        error,
+                    },
+                );
+            }
+        }
+    }
+
+    pub fn status(&self) -> proto::ReadReplicaStatus {
+        let shards = self.shards.read().unwrap();
+        proto::ReadReplicaStatus {
+            shard_statuses: shards
+                .iter()
+                .map(|(shard_id, shard)| proto::ReadReplicaShardStatus {
+                    shard_id: *shard_id,
+                    catch_up_lag_ms: shard.caught_up_at.elapsed().as_millis() as u64,
+                    catch_up_error: shard.error.clone().unwrap_or_default(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Serves reads from another node's dbs without a copy of the data. Every db is opened as a
+/// RocksDB secondary of the primary node's, and catches up with its writes every
+/// catch_up_interval, so reads lag the primary by about that much. Nothing is written: there's no
+/// consensus, mempool or gossip, and submitted messages are rejected.
+pub struct ReadReplica {
+    pub block_store: BlockStore,
+    pub shard_stores: HashMap<u32, Stores>,
+    catch_up_status: CatchUpStatus,
+    statsd_client: StatsdClientWrapper,
+}
+
+impl ReadReplica {
+    pub fn new(
+        block_db: Arc<RocksDB>,
+        shard_dbs: HashMap<u32, Arc<RocksDB>>,
+        trie_branching_factor: u32,
+        store_limits: StoreLimits,
+        statsd_client: StatsdClientWrapper,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut shard_stores = HashMap::new();
+        for (shard_id, db) in shard_dbs {
+            // Fails rather than writing an empty root if the primary never initialized the trie
+            let mut trie = MerkleTrie::new(trie_branching_factor)?;
+            trie.initialize(&db)?;
+            let stores = Stores::new(
+                db,
+                shard_id,
+                trie,
+                store_limits.clone(),
+                statsd_client.clone(),
+            );
+            shard_stores.insert(shard_id, stores);
+        }
+        let replica = ReadReplica {
+            block_store: BlockStore::new(block_db),
+            shard_stores,
+            catch_up_status: CatchUpStatus::default(),
+            statsd_client,
+        };
+        replica.catch_up();
+        Ok(replica)
+    }
+
+    pub fn open(
+        config: &Config,
+        rocksdb_dir: &str,
+        storage_config: &db::Config,
+        shard_ids: &[u32],
+        trie_branching_factor: u32,
+        store_limits: StoreLimits,
+        statsd_client: StatsdClientWrapper,
+    ) -> Result<Self, Box<dyn Error>> {
+        let open_db = |shard_id: u32| -> Result<Arc<RocksDB>, Box<dyn Error>> {
+            let primary_dir = storage_config.shard_base_dir(&config.primary_rocksdb_dir, shard_id);
+            let db = RocksDB::new(&format!("{}/shard-{}", primary_dir, shard_id))
+                .with_namespace(&storage_config.network_namespace)
+                .with_compression(storage_config.compression);
+            db.open_secondary(&format!("{}/shard-{}", rocksdb_dir, shard_id))
+                .map_err(|err| format!("Failed to open shard {}: {}", shard_id, err))?;
+            Ok(Arc::new(db))
+        };
+        let block_db = open_db(0)?;
+        let mut shard_dbs = HashMap::new();
+        for shard_id in shard_ids {
+            shard_dbs.insert(*shard_id, open_db(*shard_id)?);
+        }
+        info!(
+            primary_rocksdb_dir = config.primary_rocksdb_dir,
+            "Opened the primary's dbs as secondaries"
+        );
+        Self::new(
+            block_db,
+            shard_dbs,
+            trie_branching_factor,
+            store_limits,
+            statsd_client,
+        )
+    }
+
+    pub fn catch_up_status(&self) -> CatchUpStatus {
+        self.catch_up_status.clone()
+    }
+
+    fn dbs(&self) -> impl Iterator<Item = (u32, &Arc<RocksDB>)> {
+        std::iter::once((0, &self.block_store.db)).chain(
+            self.shard_stores
+                .iter()
+                .map(|(shard_id, stores)| (*shard_id, &stores.db)),
+        )
+    }
+
+    /// Brings every db up to date with the primary's writes so far
+    pub fn catch_up(&self) {
+        for (shard_id, db) in self.dbs() {
+            let start = Instant::now();
+            let error = match db.try_catch_up_with_primary() {
+                Ok(()) => None,
+                Err(err) => {
+                    warn!(shard_id, "Unable to catch up with the primary: {}", err);
+                    Some(err.to_string())
+                }
+            };
+            self.statsd_client.gauge_with_shard(
+                shard_id,
+                "read_replica.catch_up_failed",
+                error.is_some() as u64,
+            );
+            self.statsd_client.time_with_shard(
+                shard_id,
+                "read_replica.catch_up_time",
+                start.elapsed().as_millis() as u64,
+            );
+            self.catch_up_status.record(shard_id, error);
+        }
+    }
+
+    pub async fn run(&self, catch_up_interval: Duration) {
+        let mut interval = tokio::time::interval(catch_up_interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            // Replaying the primary's writes blocks on disk io
+            tokio::task::block_in_place(|| self.catch_up());
+        }
+    }
+
+    pub fn close(&self) {
+        for (_, db) in self.dbs() {
+            db.close();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::store::test_helper::{self, FID_FOR_TEST};
+    use crate::utils::factory::messages_factory;
+
+    fn open_secondary(primary: &RocksDB, dir: &tempfile::TempDir, name: &str) -> Arc<RocksDB> {
+        let db = RocksDB::new(&primary.location());
+        db.open_secondary(dir.path().join(name).to_str().unwrap())
+            .unwrap();
+        Arc::new(db)
+    }
+
+    #[tokio::test]
+    async fn test_primary_writes_are_served_after_catch_up() {
+        let (mut engine, _primary_dir) = test_helper::new_engine();
+        test_helper::register_user(
+            FID_FOR_TEST,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine,
+        )
+        .await;
+        let first = messages_factory::casts::create_cast_add(FID_FOR_TEST, "first", None, None);
+        test_helper::commit_message(&mut engine, &first).await;
+
+        let block_dir = tempfile::TempDir::new().unwrap();
+        let block_db = RocksDB::new(block_dir.path().join("shard-0").to_str().unwrap());
+        block_db.open().unwrap();
+
+        let replica_dir = tempfile::TempDir::new().unwrap();
+        let shard_id = engine.shard_id();
+        let replica = ReadReplica::new(
+            open_secondary(&block_db, &replica_dir, "shard-0"),
+            HashMap::from([(
+                shard_id,
+                open_secondary(&engine.get_stores().db, &replica_dir, "shard-1"),
+            )]),
+            16,
+            test_helper::limits::test_store_limits(),
+            test_helper::statsd_client(),
+        )
+        .unwrap();
+        let stores = replica.shard_stores.get(&shard_id).unwrap();
+        assert_eq!(
+            stores.get_message_by_hash(&first.hash).unwrap(),
+            Some(first)
+        );
+
+        let second = messages_factory::casts::create_cast_add(FID_FOR_TEST, "second", None, None);
+        test_helper::commit_message(&mut engine, &second).await;
+        assert_eq!(stores.get_message_by_hash(&second.hash).unwrap(), None);
+
+        replica.catch_up();
+        assert_eq!(
+            stores.get_message_by_hash(&second.hash).unwrap(),
+            Some(second)
+        );
+        assert_eq!(
+            stores.shard_store.max_block_number().unwrap(),
+            engine.get_confirmed_height().block_number
+        );
+
+        let status = replica.catch_up_status().status();
+        assert_eq!(
+            status
+                .shard_statuses
+                .iter()
+                .map(|shard| (shard.shard_id, shard.catch_up_error.is_empty()))
+                .collect::<Vec<_>>(),
+            vec![(0, true), (shard_id, true)]
+        );
+        assert!(status.shard_statuses[1].catch_up_lag_ms < 60_000);
+
+        // Nothing can be written through the replica
+        assert!(stores.db.put(b"key", b"value").is_err());
+        replica.close();
+    }
+}
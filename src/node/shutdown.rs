@@ -0,0 +1,162 @@
+use crate::storage::db::RocksDB;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{error, info};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Config {
+    // How long stopping consensus and flushing the dbs may take before the node exits anyway
+    #[serde(with = "humantime_serde")]
+    pub timeout: Duration,
+    // Event subscriptions are told where to resume from and closed before the rpc server stops,
+    // rather than reset when the node exits
+    pub drain_subscribers: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(30),
+            drain_subscribers: true,
+        }
+    }
+}
+
+/// Fanned out to the components that take new work, e.g. the rpc servers, so they stop before
+/// consensus and the dbs are shut down
+#[derive(Clone)]
+pub struct ShutdownSignal {
+    rx: watch::Receiver<bool>,
+}
+
+impl ShutdownSignal {
+    pub fn new() -> (watch::Sender<bool>, Self) {
+        let (tx, rx) = watch::channel(false);
+        (tx, ShutdownSignal { rx })
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        *self.rx.borrow()
+    }
+
+    pub async fn wait(mut self) {
+        // The sender being dropped means the node is going away too
+        let _ = self.rx.wait_for(|shutting_down| *shutting_down).await;
+    }
+}
+
+// Returns the number of dbs that couldn't be flushed
+fn flush_dbs(dbs: &[Arc<RocksDB>]) -> usize {
+    let mut failed = 0;
+    for db in dbs {
+        if let Err(err) = db.flush_to_disk() {
+            error!(
+                path = db.location(),
+                "Unable to flush db on shutdown: {}", err
+            );
+            failed += 1;
+        }
+    }
+    failed
+}
+
+/// Shuts the node down in order, so no write is in flight when the process exits: the components
+/// taking new work are told to stop, consensus finishes committing the height it's on, and then
+/// every db is flushed to disk. Gives up after the timeout, leaving whatever wasn't flushed to be
+/// recovered from the WALs on the next start. Returns whether everything was flushed.
+pub async fn shutdown(
+    config: &Config,
+    signal_tx: &watch::Sender<bool>,
+    stop_consensus: impl Future<Output = ()>,
+    dbs: Vec<Arc<RocksDB>>,
+) -> bool {
+    let _ = signal_tx.send(true);
+    let result = tokio::time::timeout(config.timeout, async move {
+        stop_consensus.await;
+        info!("Consensus stopped, flushing {} dbs", dbs.len());
+        // Flushing blocks on disk io
+        tokio::task::spawn_blocking(move || flush_dbs(&dbs)).await
+    })
+    .await;
+
+    match result {
+        Ok(Ok(0)) => {
+            info!("Shutdown complete");
+            true
+        }
+        Ok(Ok(failed)) => {
+            error!(failed, "Shutdown complete, some dbs weren't flushed");
+            false
+        }
+        Ok(Err(err)) => {
+            error!("Unable to flush dbs on shutdown: {}", err);
+            false
+        }
+        Err(_) => {
+            error!(
+                timeout = ?config.timeout,
+                "Shutdown timed out, exiting without flushing"
+            );
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_shutdown_flushes_pending_writes() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("shard-1");
+        let db = Arc::new(RocksDB::new(path.to_str().unwrap()));
+        db.open().unwrap();
+
+        // Buffered as with commit batching, it's lost if the process exits before a flush
+        let mut txn = db.txn();
+        txn.put(b"key".to_vec(), b"value".to_vec());
+        db.buffer_commit(txn).unwrap();
+
+        let (signal_tx, signal) = ShutdownSignal::new();
+        let (stopped_tx, stopped_rx) = tokio::sync::oneshot::channel();
+        let stop_consensus = async move {
+            // Consensus is only stopped once the components taking new work were told to stop
+            assert!(signal.is_shutting_down());
+            stopped_tx.send(()).unwrap();
+        };
+        assert!(
+            shutdown(
+                &Config::default(),
+                &signal_tx,
+                stop_consensus,
+                vec![db.clone()]
+            )
+            .await
+        );
+        stopped_rx.await.unwrap();
+
+        // Read from rocksdb itself, past the buffer
+        let raw_value = db.db().as_ref().unwrap().get(b"key").unwrap();
+        assert_eq!(raw_value, Some(b"value".to_vec()));
+
+        db.close();
+        let db = RocksDB::new(path.to_str().unwrap());
+        db.open().unwrap();
+        assert_eq!(db.get(b"key").unwrap(), Some(b"value".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_times_out() {
+        let (signal_tx, _) = ShutdownSignal::new();
+        let config = Config {
+            timeout: Duration::from_millis(10),
+            ..Config::default()
+        };
+        let stop_consensus = std::future::pending();
+        assert!(!shutdown(&config, &signal_tx, stop_consensus, vec![]).await);
+    }
+}
@@ -1,13 +1,19 @@
-use crate::consensus::consensus::{Config, MalachiteEventShard};
+use crate::consensus::consensus::{Config, MalachiteEventShard, SystemMessage};
 use crate::consensus::malachite::network_connector::MalachiteNetworkEvent;
 use crate::consensus::malachite::spawn::MalachiteConsensusActors;
+use crate::consensus::malachite::spawn_read_node::MalachiteReadNodeActors;
 use crate::consensus::proposer::{BlockProposer, ShardProposer};
+use crate::consensus::read_validator::Engine;
+use crate::consensus::sync_apply::SyncApplyLimiter;
 use crate::consensus::validator::ShardValidator;
 use crate::core::types::{Address, ShardId, SnapchainShard, SnapchainValidatorContext};
+use crate::core::validations::custom::MessageValidators;
 use crate::mempool::mempool::MempoolMessagesRequest;
 use crate::network::gossip::GossipEvent;
-use crate::proto::{Block, FarcasterNetwork, ShardChunk};
-use crate::storage::db::RocksDB;
+use crate::network::sync_progress::SyncProgress;
+use crate::node;
+use crate::proto::{self, Block, FarcasterNetwork, ShardChunk};
+use crate::storage::db;
 use crate::storage::store::engine::{BlockEngine, Senders, ShardEngine};
 use crate::storage::store::node_local_state::LocalStateStore;
 use crate::storage::store::stores::StoreLimits;
@@ -27,6 +33,8 @@ const MAX_SHARDS: u32 = 64;
 #[derive(Clone)]
 pub struct SnapchainNode {
     pub consensus_actors: BTreeMap<u32, MalachiteConsensusActors>,
+    // Of the shards the node follows rather than validates, which sync like on a read node
+    pub follower_actors: BTreeMap<u32, MalachiteReadNodeActors>,
     pub shard_stores: HashMap<u32, Stores>,
     pub shard_senders: HashMap<u32, Senders>,
     pub address: Address,
@@ -38,24 +46,34 @@ impl SnapchainNode {
         config: Config,
         local_peer_id: PeerId,
         gossip_tx: mpsc::Sender<GossipEvent<SnapchainValidatorContext>>,
+        system_tx: mpsc::Sender<SystemMessage>,
+        sync_progress: SyncProgress,
         shard_decision_tx: broadcast::Sender<ShardChunk>,
         block_tx: Option<mpsc::Sender<Block>>,
         messages_request_tx: mpsc::Sender<MempoolMessagesRequest>,
         block_store: BlockStore,
         local_state_store: LocalStateStore,
         rocksdb_dir: String,
+        storage_config: db::Config,
         statsd_client: StatsdClientWrapper,
         trie_branching_factor: u32,
         network: FarcasterNetwork,
+        store_limits: StoreLimits,
+        message_validators: MessageValidators,
+        tolerate_shard_failures: bool,
         registry: &SharedRegistry,
     ) -> Self {
         let validator_address = Address(keypair.public().to_bytes());
 
         let mut consensus_actors = BTreeMap::new();
+        let mut follower_actors = BTreeMap::new();
 
         let mut shard_senders: HashMap<u32, Senders> = HashMap::new();
         let mut shard_stores: HashMap<u32, Stores> = HashMap::new();
 
+        // Shared by the follower shards, so it caps them all together
+        let apply_limiter = SyncApplyLimiter::new(config.sync_apply_parallelism);
+
         // Create the shard validators
         for shard_id in config.shard_ids.clone() {
             if shard_id == 0 {
@@ -64,25 +82,73 @@ impl SnapchainNode {
                 panic!("Shard ID must be between 1 and {}", MAX_SHARDS);
             }
 
-            let shard = SnapchainShard::new(shard_id);
-            let ctx = SnapchainValidatorContext::new(keypair.clone());
+            let Some(db) = node::open_shard_db(
+                &rocksdb_dir,
+                &storage_config,
+                shard_id,
+                trie_branching_factor,
+                tolerate_shard_failures,
+                &statsd_client,
+            ) else {
+                continue;
+            };
 
-            let db = RocksDB::open_shard_db(rocksdb_dir.clone().as_str(), shard_id);
+            let shard = SnapchainShard::new(shard_id);
+            let ctx = SnapchainValidatorContext::new(keypair.clone())
+                .with_proposer_selector(config.proposer_selection.selector());
             let trie = merkle_trie::MerkleTrie::new(trie_branching_factor).unwrap(); //TODO: don't unwrap()
             let engine = ShardEngine::new(
                 db.clone(),
                 network,
                 trie,
                 shard_id,
-                StoreLimits::default(),
+                store_limits.clone(),
                 statsd_client.clone(),
                 config.max_messages_per_block,
                 Some(messages_request_tx.clone()),
-            );
+            )
+            .with_commit_batching(
+                storage_config.commit_batch_size,
+                storage_config.commit_batch_window,
+            )
+            .with_commit_queue(storage_config.commit_queue_depth)
+            .with_trie_batching(storage_config.trie_batching)
+            .with_message_cache(&storage_config.message_cache)
+            .with_message_ttls(config.message_ttls())
+            .with_max_message_age(config.max_message_age())
+            .with_message_validators(message_validators.clone())
+            .with_block_limits(config.block_limits())
+            .with_mempool_requeue(system_tx.clone())
+            .with_validator_stakes(config.voting_power.stake_epoch_length());
+            let validator_stakes = engine.validator_stakes();
 
             shard_senders.insert(shard_id, engine.get_senders());
             shard_stores.insert(shard_id, engine.get_stores());
 
+            if config.shard_role(shard_id) == proto::ShardRole::Follower {
+                let follower_actor = MalachiteReadNodeActors::create_and_start(
+                    ctx,
+                    Engine::ShardEngine(engine),
+                    local_peer_id,
+                    gossip_tx.clone(),
+                    system_tx.clone(),
+                    registry,
+                    shard_id,
+                    statsd_client.clone(),
+                    config.clone(),
+                    apply_limiter.clone(),
+                    Some(shard_decision_tx.clone()),
+                )
+                .await;
+
+                if follower_actor.is_err() {
+                    panic!("Failed to create follower actor for shard {}", shard_id);
+                }
+
+                follower_actors.insert(shard_id, follower_actor.unwrap());
+                continue;
+            }
+
             let shard_proposer = ShardProposer::new(
                 validator_address.clone(),
                 shard.clone(),
@@ -100,13 +166,15 @@ impl SnapchainNode {
                 Some(shard_proposer),
                 local_state_store.clone(),
                 statsd_client.clone(),
-            );
+            )
+            .with_validator_stakes(validator_stakes);
             let consensus_actor = MalachiteConsensusActors::create_and_start(
                 ctx,
                 shard_validator,
                 local_peer_id,
                 rocksdb_dir.clone(),
                 gossip_tx.clone(),
+                sync_progress.clone(),
                 registry,
                 config.clone(),
                 statsd_client.clone(),
@@ -145,13 +213,15 @@ impl SnapchainNode {
             local_state_store,
             statsd_client.clone(),
         );
-        let ctx = SnapchainValidatorContext::new(keypair.clone());
+        let ctx = SnapchainValidatorContext::new(keypair.clone())
+            .with_proposer_selector(config.proposer_selection.selector());
         let block_consensus_actor = MalachiteConsensusActors::create_and_start(
             ctx,
             block_validator,
             local_peer_id,
             rocksdb_dir.clone(),
             gossip_tx.clone(),
+            sync_progress,
             registry,
             config,
             statsd_client.clone(),
@@ -164,6 +234,7 @@ impl SnapchainNode {
 
         Self {
             consensus_actors,
+            follower_actors,
             address: validator_address,
             shard_senders,
             shard_stores,
@@ -179,6 +250,36 @@ impl SnapchainNode {
         for (_, actor) in self.consensus_actors.iter() {
             actor.stop();
         }
+        for (_, actor) in self.follower_actors.iter() {
+            actor.stop();
+        }
+    }
+
+    pub async fn stop_and_wait(&self) {
+        for (_, actor) in self.consensus_actors.iter() {
+            actor.stop_and_wait().await;
+        }
+        for (_, actor) in self.follower_actors.iter() {
+            actor.stop_and_wait().await;
+        }
+    }
+
+    // Decided values gossiped for read nodes only matter for the shards the node follows
+    pub fn dispatch_decided_value(&self, decided_value: proto::DecidedValue) {
+        let shard_id = match decided_value.value.as_ref() {
+            Some(proto::decided_value::Value::Shard(shard_chunk)) => shard_chunk
+                .header
+                .as_ref()
+                .and_then(|header| header.height)
+                .map(|height| height.shard_index),
+            _ => None,
+        };
+        let Some(actor) = shard_id.and_then(|shard_id| self.follower_actors.get(&shard_id)) else {
+            return;
+        };
+        if let Err(e) = actor.cast_decided_value(decided_value) {
+            warn!("Failed to forward decided value to follower actor: {:?}", e);
+        }
     }
 
     pub fn dispatch(&self, shard: MalachiteEventShard, event: MalachiteNetworkEvent) {
@@ -193,6 +294,15 @@ impl SnapchainNode {
                         );
                     }
                 }
+                for (shard_index, actor) in self.follower_actors.iter() {
+                    let result = actor.cast_network_event(event.clone());
+                    if let Err(e) = result {
+                        warn!(
+                            "Failed to forward message to follower actor: {:?} at shard: {:?}",
+                            e, shard_index
+                        );
+                    }
+                }
             }
             MalachiteEventShard::Shard(shard_index) => {
                 if let Some(actor) = self.consensus_actors.get(&shard_index) {
@@ -200,6 +310,11 @@ impl SnapchainNode {
                     if let Err(e) = result {
                         warn!("Failed to forward message to actor: {:?}", e);
                     }
+                } else if let Some(actor) = self.follower_actors.get(&shard_index) {
+                    let result = actor.cast_network_event(event);
+                    if let Err(e) = result {
+                        warn!("Failed to forward message to follower actor: {:?}", e);
+                    }
                 } else {
                     warn!("No actor found for shard, could not forward message");
                 }
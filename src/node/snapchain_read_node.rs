@@ -2,11 +2,14 @@ use crate::consensus::consensus::{Config, MalachiteEventShard, SystemMessage};
 use crate::consensus::malachite::network_connector::MalachiteNetworkEvent;
 use crate::consensus::malachite::spawn_read_node::MalachiteReadNodeActors;
 use crate::consensus::read_validator::Engine;
+use crate::consensus::sync_apply::SyncApplyLimiter;
 use crate::core::types::{Address, ShardId, SnapchainShard, SnapchainValidatorContext};
+use crate::core::validations::custom::MessageValidators;
 use crate::mempool::mempool::MempoolMessagesRequest;
 use crate::network::gossip::GossipEvent;
+use crate::node;
 use crate::proto;
-use crate::storage::db::RocksDB;
+use crate::storage::db;
 use crate::storage::store::engine::{BlockEngine, Senders, ShardEngine};
 use crate::storage::store::stores::StoreLimits;
 use crate::storage::store::stores::Stores;
@@ -40,9 +43,13 @@ impl SnapchainReadNode {
         messages_request_tx: mpsc::Sender<MempoolMessagesRequest>,
         block_store: BlockStore,
         rocksdb_dir: String,
+        storage_config: db::Config,
         statsd_client: StatsdClientWrapper,
         trie_branching_factor: u32,
         farcaster_network: proto::FarcasterNetwork,
+        store_limits: StoreLimits,
+        message_validators: MessageValidators,
+        tolerate_shard_failures: bool,
         registry: &SharedRegistry,
     ) -> Self {
         let validator_address = Address(keypair.public().to_bytes());
@@ -52,6 +59,9 @@ impl SnapchainReadNode {
         let mut shard_senders: HashMap<u32, Senders> = HashMap::new();
         let mut shard_stores: HashMap<u32, Stores> = HashMap::new();
 
+        // Shared by the shards, so it caps them all together
+        let apply_limiter = SyncApplyLimiter::new(config.sync_apply_parallelism);
+
         // Create the shard validators
         for shard_id in config.shard_ids.clone() {
             if shard_id == 0 {
@@ -60,20 +70,41 @@ impl SnapchainReadNode {
                 panic!("Shard ID must be between 1 and {}", MAX_SHARDS);
             }
 
-            let ctx = SnapchainValidatorContext::new(keypair.clone());
-
-            let db = RocksDB::open_shard_db(rocksdb_dir.clone().as_str(), shard_id);
+            let Some(db) = node::open_shard_db(
+                &rocksdb_dir,
+                &storage_config,
+                shard_id,
+                trie_branching_factor,
+                tolerate_shard_failures,
+                &statsd_client,
+            ) else {
+                continue;
+            };
+
+            let ctx = SnapchainValidatorContext::new(keypair.clone())
+                .with_proposer_selector(config.proposer_selection.selector());
             let trie = merkle_trie::MerkleTrie::new(trie_branching_factor).unwrap(); //TODO: don't unwrap()
             let engine = ShardEngine::new(
                 db.clone(),
                 farcaster_network,
                 trie,
                 shard_id,
-                StoreLimits::default(),
+                store_limits.clone(),
                 statsd_client.clone(),
                 config.max_messages_per_block,
                 Some(messages_request_tx.clone()),
-            );
+            )
+            .with_commit_batching(
+                storage_config.commit_batch_size,
+                storage_config.commit_batch_window,
+            )
+            .with_commit_queue(storage_config.commit_queue_depth)
+            .with_trie_batching(storage_config.trie_batching)
+            .with_message_cache(&storage_config.message_cache)
+            .with_message_ttls(config.message_ttls())
+            .with_max_message_age(config.max_message_age())
+            .with_message_validators(message_validators.clone())
+            .with_validator_stakes(config.voting_power.stake_epoch_length());
 
             shard_senders.insert(shard_id, engine.get_senders());
             shard_stores.insert(shard_id, engine.get_stores());
@@ -88,6 +119,8 @@ impl SnapchainReadNode {
                 shard_id,
                 statsd_client.clone(),
                 config.clone(),
+                apply_limiter.clone(),
+                None,
             )
             .await;
 
@@ -103,7 +136,8 @@ impl SnapchainReadNode {
 
         // We might want to use different keys for the block shard so signatures are different and cannot be accidentally used in the wrong shard
         let engine = BlockEngine::new(block_store.clone(), statsd_client.clone());
-        let ctx = SnapchainValidatorContext::new(keypair.clone());
+        let ctx = SnapchainValidatorContext::new(keypair.clone())
+            .with_proposer_selector(config.proposer_selection.selector());
         let block_actor = MalachiteReadNodeActors::create_and_start(
             ctx,
             Engine::BlockEngine(engine),
@@ -114,6 +148,8 @@ impl SnapchainReadNode {
             block_shard.shard_id(),
             statsd_client.clone(),
             config.clone(),
+            apply_limiter,
+            None,
         )
         .await;
         if block_actor.is_err() {
@@ -139,6 +175,12 @@ impl SnapchainReadNode {
             actor.stop();
         }
     }
+
+    pub async fn stop_and_wait(&self) {
+        for (_, actor) in self.consensus_actors.iter() {
+            actor.stop_and_wait().await;
+        }
+    }
     pub fn dispatch_decided_value(&self, decided_value: proto::DecidedValue) {
         let shard_id = match decided_value.value.as_ref().unwrap() {
             proto::decided_value::Value::Shard(shard_chunk) => {
@@ -23,7 +23,7 @@ fn state_change_to_shard_chunk(
                 shard_index,
                 block_number,
             }),
-            timestamp: 0,
+            timestamp: change.timestamp,
             parent_hash: vec![], // TODO
         }),
         transactions: change.transactions.clone(),
@@ -42,6 +42,7 @@ pub async fn run() -> Result<(), Box<dyn Error>> {
         limits: Some(StoreLimits {
             limits: test_helper::limits::unlimited(),
             legacy_limits: test_helper::limits::unlimited(),
+            byte_limits: None,
         }),
         db: None,
         messages_request_tx: Some(messages_request_tx),
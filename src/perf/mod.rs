@@ -3,4 +3,5 @@ mod gen_multi;
 mod gen_single;
 pub mod generate;
 pub mod perftest;
+pub mod sync_apply_perftest;
 pub mod trie_only_perftest;
@@ -0,0 +1,165 @@
+use crate::consensus::consensus::ValidatorSetConfig;
+use crate::consensus::read_validator::{Engine, ReadValidator};
+use crate::consensus::sync_apply::SyncApplyLimiter;
+use crate::consensus::validator::{StoredValidatorSet, StoredValidatorSets};
+use crate::core::types::{Address, ShardId};
+use crate::proto::{self, Height, ShardChunk};
+use crate::storage::store::engine::{MempoolMessage, ShardEngine};
+use crate::storage::store::stores::StoreLimits;
+use crate::storage::store::test_helper;
+use crate::utils::cli::compose_message;
+use clap::Parser;
+use libp2p::identity::ed25519::Keypair;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::time::Instant;
+
+#[derive(Parser, Debug, Clone)]
+struct Args {
+    #[arg(long, default_value_t = 4)]
+    shard_count: u32,
+
+    #[arg(long, default_value_t = 100)]
+    blocks_per_shard: u32,
+
+    #[arg(long, default_value_t = 100)]
+    messages_per_block: u32,
+
+    // Defaults to sequential and then one per shard
+    #[arg(long, value_delimiter = ',')]
+    parallelism: Vec<u32>,
+}
+
+fn new_engine() -> (ShardEngine, tempfile::TempDir) {
+    test_helper::new_engine_with_options(test_helper::EngineOptions {
+        limits: Some(StoreLimits {
+            limits: test_helper::limits::unlimited(),
+            legacy_limits: test_helper::limits::unlimited(),
+            byte_limits: None,
+        }),
+        db: None,
+        messages_request_tx: None,
+    })
+}
+
+// The signed chunks of a shard, as a read node would get them from sync
+async fn generate_chunks(args: &Args, keypair: &Keypair) -> Vec<ShardChunk> {
+    let (mut engine, _dir) = new_engine();
+    let fid = test_helper::FID_FOR_TEST;
+    test_helper::register_user(
+        fid,
+        test_helper::default_signer(),
+        test_helper::default_custody_address(),
+        &mut engine,
+    )
+    .await;
+    let mut i = 0;
+    while engine.get_confirmed_height().block_number < args.blocks_per_shard as u64 {
+        let messages = (0..args.messages_per_block)
+            .map(|_| {
+                i += 1;
+                let text = format!("For benchmarking {}", i);
+                MempoolMessage::UserMessage(compose_message(fid, text.as_str(), None, None))
+            })
+            .collect();
+        let state_change = engine.propose_state_change(1, messages);
+        test_helper::validate_and_commit_state_change(&mut engine, &state_change);
+    }
+    let mut chunks = vec![];
+    for block_number in 1..=engine.get_confirmed_height().block_number {
+        let chunk = engine
+            .get_shard_chunk_by_height(Height {
+                shard_index: engine.shard_id(),
+                block_number,
+            })
+            .unwrap();
+        chunks.push(test_helper::sign_chunk(keypair, chunk).await);
+    }
+    chunks
+}
+
+fn read_validator(keypair: &Keypair) -> (ReadValidator, tempfile::TempDir) {
+    let (engine, dir) = new_engine();
+    let shard_id = engine.shard_id();
+    let validator_set_config = ValidatorSetConfig {
+        effective_at: 0,
+        validator_public_keys: vec![Address(keypair.public().to_bytes()).to_hex()],
+        shard_ids: vec![shard_id],
+    };
+    let validator = ReadValidator {
+        shard_id,
+        engine: Engine::ShardEngine(engine),
+        last_height: Height {
+            shard_index: shard_id,
+            block_number: 0,
+        },
+        max_num_buffered_blocks: 100,
+        buffered_blocks: BTreeMap::new(),
+        validator_sets: StoredValidatorSets::new(
+            shard_id,
+            vec![StoredValidatorSet::new(
+                ShardId::new(shard_id),
+                &validator_set_config,
+            )],
+        ),
+        statsd_client: test_helper::statsd_client(),
+        shard_decision_tx: None,
+    };
+    (validator, dir)
+}
+
+// Applies every shard's chunks into fresh dbs and returns the blocks applied per second
+async fn sync(shards: &[(Keypair, Vec<ShardChunk>)], parallelism: u32) -> f64 {
+    let limiter = SyncApplyLimiter::new(parallelism);
+    let mut dirs = vec![];
+    let mut validators = vec![];
+    for (keypair, _) in shards {
+        let (validator, dir) = read_validator(keypair);
+        validators.push(validator);
+        dirs.push(dir);
+    }
+
+    let start = Instant::now();
+    let mut handles = vec![];
+    for (mut validator, (_, chunks)) in validators.into_iter().zip(shards) {
+        let (limiter, chunks) = (limiter.clone(), chunks.clone());
+        handles.push(tokio::spawn(async move {
+            let mut applied = 0;
+            for chunk in chunks {
+                let value = proto::DecidedValue {
+                    value: Some(proto::decided_value::Value::Shard(chunk)),
+                };
+                applied += limiter.run(|| validator.process_decided_value(value)).await;
+            }
+            applied
+        }));
+    }
+    let mut applied = 0;
+    for handle in handles {
+        applied += handle.await.unwrap();
+    }
+    applied as f64 / start.elapsed().as_secs_f64()
+}
+
+pub async fn run() -> Result<(), Box<dyn Error>> {
+    let mut args = Args::parse();
+    if args.parallelism.is_empty() {
+        args.parallelism = vec![1, args.shard_count];
+    }
+
+    let mut shards = vec![];
+    for _ in 0..args.shard_count {
+        let keypair = Keypair::generate();
+        let chunks = generate_chunks(&args, &keypair).await;
+        shards.push((keypair, chunks));
+    }
+
+    for parallelism in &args.parallelism {
+        let blocks_per_second = sync(&shards, *parallelism).await;
+        println!(
+            "parallelism {}: {:.1} blocks/s over {} shards",
+            parallelism, blocks_per_second, args.shard_count
+        );
+    }
+    Ok(())
+}
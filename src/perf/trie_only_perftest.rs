@@ -2,7 +2,7 @@ use crate::perf::generate;
 use crate::perf::generate::{new_generator, GeneratorTypes};
 use crate::storage::db;
 use crate::storage::trie::merkle_trie;
-use crate::storage::trie::merkle_trie::Context;
+use crate::storage::trie::merkle_trie::{Context, TrieUpdates};
 use crate::utils::statsd_wrapper;
 use clap::Parser;
 use std::collections::VecDeque;
@@ -28,6 +28,10 @@ struct Args {
 
     #[arg(long, default_value_t = 1_000_000)]
     users_per_shard: u32,
+
+    // Applies each block's keys to the trie together, instead of one at a time
+    #[arg(long)]
+    batch: bool,
 }
 
 pub fn run() -> Result<(), Box<dyn Error>> {
@@ -143,6 +147,7 @@ fn run_shard(
         }
 
         let mut txn_batch = db::RocksDbTransactionBatch::new();
+        let mut updates = TrieUpdates::default();
 
         loop {
             let msg = message_queue.pop_front();
@@ -151,6 +156,9 @@ fn run_shard(
             }
             let msg = msg.unwrap();
             match msg {
+                generate::NextMessage::Message(message) if args.batch => {
+                    updates.insert(message.hash);
+                }
                 generate::NextMessage::Message(message) => {
                     t.insert(&ctx, &db, &mut txn_batch, vec![&message.hash])?;
                 }
@@ -160,6 +168,7 @@ fn run_shard(
             }
         }
 
+        t.apply(&ctx, &db, &mut txn_batch, updates)?;
         db.commit(txn_batch)?;
         t.reload(&db)?;
     }
@@ -44,6 +44,24 @@ pub enum RootPrefix {
 
     /* Used to index blocks by timestamp */
     BlockIndex = 18,
+
+    /* Used to index casts by timestamp, across fids */
+    CastsByTimestamp = 19,
+
+    /* Used to index blocks by hash */
+    BlockByHash = 20,
+
+    /* Used to keep every username proof seen for a name, fnames and ENS names alike */
+    UserNameProofHistoryByName = 21,
+
+    /* Used to index messages by hash, across stores */
+    MessageByHash = 22,
+
+    /* Used to record the validator stakes each epoch weighs votes by */
+    EpochStakes = 23,
+
+    /* Used to index every fid that verified an address */
+    FidsByVerifiedAddress = 24,
 }
 
 /** Copied from the JS code */
@@ -94,6 +112,15 @@ pub enum UserPostfix {
 
     /* Link Compact State set */
     LinkCompactStateMessage = 100,
+
+    /* Last sequence accepted for the fid's submissions, see SubmissionSequences */
+    SubmissionSequence = 101,
+
+    /* First and last heights the fid's messages were merged at, see ActivityHeights */
+    ActivityHeights = 102,
+
+    /* Serialized size of the fid's messages in a store, see Store::get_bytes_used */
+    BytesUsed = 103,
 }
 
 impl UserPostfix {
@@ -113,4 +140,6 @@ pub enum OnChainEventPostfix {
 
     #[allow(dead_code)] // TODO
     IdRegisterByCustodyAddress = 53,
+
+    OnChainEventsByBlock = 54,
 }
@@ -0,0 +1,279 @@
+use crate::proto::FarcasterNetwork;
+use crate::storage::db::{RocksDB, RocksdbError};
+use crate::storage::store::shard::{ShardStorageError, ShardStore};
+use crate::storage::store::{BlockStorageError, BlockStore};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use thiserror::Error;
+use tracing::info;
+
+// Written into the checkpoint directory next to the db files, so a restore can tell what the
+// checkpoint is of
+const METADATA_FILE: &str = "snapchain_checkpoint.json";
+
+#[derive(Error, Debug)]
+pub enum CheckpointError {
+    #[error(transparent)]
+    RocksdbError(#[from] RocksdbError),
+
+    #[error(transparent)]
+    IoError(#[from] io::Error),
+
+    #[error(transparent)]
+    SerdeError(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    BlockStorageError(#[from] BlockStorageError),
+
+    #[error(transparent)]
+    ShardStorageError(#[from] ShardStorageError),
+
+    #[error("Checkpoint is for network {found}, expected {expected}")]
+    NetworkMismatch { expected: String, found: String },
+
+    #[error("Checkpoint is for shard {found}, expected {expected}")]
+    ShardMismatch { expected: u32, found: u32 },
+
+    #[error("Checkpoint is at height {found}, but was recorded at {recorded}")]
+    HeightMismatch { recorded: u64, found: u64 },
+
+    #[error("Unable to open the shard's db, make sure the node is stopped: {0}")]
+    DbInUse(RocksdbError),
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct CheckpointMetadata {
+    pub network: String,
+    pub shard_id: u32,
+    pub block_height: u64,
+    pub timestamp: i64,
+}
+
+#[derive(Debug)]
+pub struct RestoredCheckpoint {
+    pub block_height: u64,
+    // Where the store that was replaced was moved to, if there was one
+    pub fallback_path: Option<String>,
+}
+
+fn block_height(shard_id: u32, db: Arc<RocksDB>) -> Result<u64, CheckpointError> {
+    // Shard 0 holds the blocks, the others hold shard chunks
+    if shard_id == 0 {
+        Ok(BlockStore::new(db).max_block_number()?)
+    } else {
+        Ok(ShardStore::new(db, shard_id).max_block_number()?)
+    }
+}
+
+fn block_height_at(shard_id: u32, path: &str) -> Result<u64, CheckpointError> {
+    let db = Arc::new(RocksDB::new(path));
+    db.open()?;
+    let block_height = block_height(shard_id, db.clone());
+    db.close();
+    block_height
+}
+
+pub fn read_metadata(checkpoint_path: &str) -> Result<CheckpointMetadata, CheckpointError> {
+    let metadata = fs::read(Path::new(checkpoint_path).join(METADATA_FILE))?;
+    Ok(serde_json::from_slice(&metadata)?)
+}
+
+// Checkpoints a shard's db to path, which must not exist, and records what it's of alongside it.
+pub fn create_checkpoint(
+    db: &RocksDB,
+    network: FarcasterNetwork,
+    shard_id: u32,
+    path: &str,
+) -> Result<CheckpointMetadata, CheckpointError> {
+    db.create_checkpoint(path)?;
+
+    // Read from the checkpoint itself, the live db may have moved on by the time it's done
+    let metadata = CheckpointMetadata {
+        network: network.as_str_name().to_string(),
+        shard_id,
+        block_height: block_height_at(shard_id, path)?,
+        timestamp: chrono::Utc::now().timestamp_millis(),
+    };
+    fs::write(
+        Path::new(path).join(METADATA_FILE),
+        serde_json::to_vec_pretty(&metadata)?,
+    )?;
+    Ok(metadata)
+}
+
+// Replaces the shard's db at db_path with a copy of the checkpoint. The node must be stopped. The
+// checkpoint is left untouched, and the replaced db is kept next to the new one in case it's
+// needed again.
+pub fn restore_checkpoint(
+    network: FarcasterNetwork,
+    shard_id: u32,
+    checkpoint_path: &str,
+    db_path: &str,
+) -> Result<RestoredCheckpoint, CheckpointError> {
+    let metadata = read_metadata(checkpoint_path)?;
+    if metadata.network != network.as_str_name() {
+        return Err(CheckpointError::NetworkMismatch {
+            expected: network.as_str_name().to_string(),
+            found: metadata.network,
+        });
+    }
+    if metadata.shard_id != shard_id {
+        return Err(CheckpointError::ShardMismatch {
+            expected: shard_id,
+            found: metadata.shard_id,
+        });
+    }
+
+    let has_live_db = Path::new(db_path).exists();
+    if has_live_db {
+        // RocksDB only lets one process have a db open, so this fails while the node is running
+        let live_db = RocksDB::new(db_path);
+        live_db.open().map_err(CheckpointError::DbInUse)?;
+        live_db.close();
+    }
+
+    // Copy next to the live db first, so the swap is a rename within the same filesystem
+    let staging_path = format!("{}.restoring", db_path);
+    if Path::new(&staging_path).exists() {
+        fs::remove_dir_all(&staging_path)?;
+    }
+    fs::create_dir_all(&staging_path)?;
+    for entry in fs::read_dir(checkpoint_path)? {
+        let entry = entry?;
+        if entry.file_name() == METADATA_FILE || !entry.file_type()?.is_file() {
+            continue;
+        }
+        fs::copy(
+            entry.path(),
+            Path::new(&staging_path).join(entry.file_name()),
+        )?;
+    }
+
+    let block_height = block_height_at(shard_id, &staging_path)?;
+    if block_height != metadata.block_height {
+        fs::remove_dir_all(&staging_path)?;
+        return Err(CheckpointError::HeightMismatch {
+            recorded: metadata.block_height,
+            found: block_height,
+        });
+    }
+
+    let fallback_path = if has_live_db {
+        let fallback_path = format!(
+            "{}.pre-restore-{}",
+            db_path,
+            chrono::Utc::now().timestamp_millis()
+        );
+        fs::rename(db_path, &fallback_path)?;
+        Some(fallback_path)
+    } else {
+        None
+    };
+    if let Err(err) = fs::rename(&staging_path, db_path) {
+        // Put the old db back rather than leave the shard without one
+        if let Some(fallback_path) = &fallback_path {
+            fs::rename(fallback_path, db_path)?;
+        }
+        return Err(err.into());
+    }
+
+    info!(
+        shard_id,
+        block_height,
+        ?fallback_path,
+        "Restored shard from checkpoint {}",
+        checkpoint_path
+    );
+    Ok(RestoredCheckpoint {
+        block_height,
+        fallback_path,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::store::test_helper;
+    use crate::storage::trie::merkle_trie::MerkleTrie;
+    use crate::utils::factory::messages_factory;
+
+    const FID: u64 = 1234;
+
+    fn trie_root_at(path: &str) -> Vec<u8> {
+        let db = RocksDB::new(path);
+        db.open().unwrap();
+        let mut trie = MerkleTrie::new(16).unwrap();
+        trie.initialize(&db).unwrap();
+        let root_hash = trie.root_hash().unwrap();
+        db.close();
+        root_hash
+    }
+
+    #[tokio::test]
+    async fn test_create_and_restore_checkpoint() {
+        let (mut engine, _dir) = test_helper::new_engine();
+        test_helper::register_user(
+            FID,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine,
+        )
+        .await;
+        let cast = messages_factory::casts::create_cast_add(FID, "hello", None, None);
+        test_helper::commit_message(&mut engine, &cast).await;
+
+        let stores = engine.get_stores();
+        let expected_root = engine.trie_root_hash();
+        let expected_height = stores.shard_store.max_block_number().unwrap();
+
+        let checkpoint_dir = tempfile::TempDir::new().unwrap();
+        let checkpoint_path = checkpoint_dir.path().join("checkpoint");
+        let checkpoint_path = checkpoint_path.to_str().unwrap();
+        let metadata =
+            create_checkpoint(&stores.db, FarcasterNetwork::Testnet, 1, checkpoint_path).unwrap();
+        assert_eq!(metadata.block_height, expected_height);
+        assert_eq!(read_metadata(checkpoint_path).unwrap(), metadata);
+
+        // Move the live db past the checkpoint
+        let cast = messages_factory::casts::create_cast_add(FID, "world", None, None);
+        test_helper::commit_message(&mut engine, &cast).await;
+        let later_root = engine.trie_root_hash();
+        assert_ne!(later_root, expected_root);
+
+        let db_path = stores.db.location();
+        // Refuses while the db is still open
+        assert!(matches!(
+            restore_checkpoint(FarcasterNetwork::Testnet, 1, checkpoint_path, &db_path),
+            Err(CheckpointError::DbInUse(_))
+        ));
+        stores.db.close();
+
+        // Checkpoints for another network or shard are refused
+        assert!(matches!(
+            restore_checkpoint(FarcasterNetwork::Mainnet, 1, checkpoint_path, &db_path),
+            Err(CheckpointError::NetworkMismatch { .. })
+        ));
+        assert!(matches!(
+            restore_checkpoint(FarcasterNetwork::Testnet, 2, checkpoint_path, &db_path),
+            Err(CheckpointError::ShardMismatch { .. })
+        ));
+
+        let restored =
+            restore_checkpoint(FarcasterNetwork::Testnet, 1, checkpoint_path, &db_path).unwrap();
+        assert_eq!(restored.block_height, expected_height);
+        assert_eq!(trie_root_at(&db_path), expected_root);
+
+        // The replaced db is kept as is
+        let fallback_path = restored.fallback_path.unwrap();
+        assert_eq!(trie_root_at(&fallback_path), later_root);
+
+        // The checkpoint can be restored again
+        let restored =
+            restore_checkpoint(FarcasterNetwork::Testnet, 1, checkpoint_path, &db_path).unwrap();
+        assert_eq!(restored.block_height, expected_height);
+        assert_eq!(trie_root_at(&db_path), expected_root);
+    }
+}
@@ -0,0 +1,182 @@
+use crate::core::error::HubError;
+use crate::utils::statsd_wrapper::StatsdClientWrapper;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Config {
+    // Submissions are rejected once the free space on any of the db directories drops below
+    // this. 0 disables the check.
+    pub low_watermark_bytes: u64,
+    // And accepted again once it's back above this, so the node doesn't flap around the low
+    // watermark
+    pub high_watermark_bytes: u64,
+    #[serde(with = "humantime_serde")]
+    pub check_interval: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            low_watermark_bytes: 2 * 1024 * 1024 * 1024,
+            high_watermark_bytes: 4 * 1024 * 1024 * 1024,
+            check_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+impl Config {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.low_watermark_bytes == 0 {
+            return Ok(());
+        }
+        if self.high_watermark_bytes < self.low_watermark_bytes {
+            return Err(format!(
+                "disk_space high_watermark_bytes ({}) is below low_watermark_bytes ({})",
+                self.high_watermark_bytes, self.low_watermark_bytes
+            ));
+        }
+        if self.check_interval.is_zero() {
+            return Err("disk_space check_interval can't be 0".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Whether the node is low on disk space. Rocksdb fails writes partway through once the disk is
+/// full, so while space is low the node stays up serving reads and rejects submissions with
+/// resource_exhausted instead. Cloned handles share the state.
+#[derive(Clone, Default)]
+pub struct DiskSpaceGuard {
+    config: Config,
+    low: Arc<AtomicBool>,
+}
+
+impl DiskSpaceGuard {
+    pub fn new(config: Config) -> Self {
+        DiskSpaceGuard {
+            config,
+            low: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn is_low(&self) -> bool {
+        self.low.load(Ordering::Relaxed)
+    }
+
+    /// Enters the low space mode below the low watermark and leaves it above the high one.
+    /// Returns whether the node is low on space.
+    pub fn update(&self, free_bytes: u64) -> bool {
+        if self.config.low_watermark_bytes == 0 {
+            return false;
+        }
+        let was_low = self.is_low();
+        if !was_low && free_bytes < self.config.low_watermark_bytes {
+            self.low.store(true, Ordering::Relaxed);
+            error!(
+                free_bytes,
+                low_watermark_bytes = self.config.low_watermark_bytes,
+                "Disk space is low, rejecting submissions until it recovers"
+            );
+            return true;
+        }
+        if was_low && free_bytes > self.config.high_watermark_bytes {
+            self.low.store(false, Ordering::Relaxed);
+            info!(
+                free_bytes,
+                high_watermark_bytes = self.config.high_watermark_bytes,
+                "Disk space recovered, accepting submissions again"
+            );
+            return false;
+        }
+        was_low
+    }
+
+    pub fn check(&self) -> Result<(), HubError> {
+        if self.is_low() {
+            return Err(HubError::resource_exhausted(
+                "node is low on disk space and not accepting writes",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks the free space of the filesystems holding [dirs] every check interval, reporting
+    /// the lowest in the disk.free_bytes gauge
+    pub async fn run(self, dirs: Vec<String>, statsd_client: StatsdClientWrapper) {
+        if self.config.low_watermark_bytes == 0 {
+            return;
+        }
+        let mut interval = tokio::time::interval(self.config.check_interval);
+        loop {
+            interval.tick().await;
+            let mut lowest = None;
+            for dir in &dirs {
+                match fs4::available_space(dir) {
+                    Ok(free_bytes) => {
+                        lowest = Some(lowest.map_or(free_bytes, |l: u64| l.min(free_bytes)))
+                    }
+                    Err(err) => warn!(dir, "Unable to read free disk space: {}", err),
+                }
+            }
+            if let Some(free_bytes) = lowest {
+                statsd_client.gauge("disk.free_bytes", free_bytes);
+                let was_low = self.is_low();
+                if self.update(free_bytes) && was_low {
+                    error!(free_bytes, "Disk space is still low, rejecting submissions");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_toggles_low_space_mode() {
+        let guard = DiskSpaceGuard::new(Config {
+            low_watermark_bytes: 100,
+            high_watermark_bytes: 200,
+            check_interval: Duration::from_secs(1),
+        });
+        assert!(!guard.update(150));
+        assert!(guard.check().is_ok());
+
+        assert!(guard.update(99));
+        let err = guard.check().unwrap_err();
+        assert_eq!(err.code, "resource_exhausted");
+        // Clones share the mode
+        assert!(guard.clone().is_low());
+
+        // Stays low until space is back above the high watermark
+        assert!(guard.update(150));
+        assert!(guard.update(200));
+        assert!(!guard.update(201));
+        assert!(guard.check().is_ok());
+        assert!(!guard.update(150));
+    }
+
+    #[test]
+    fn test_disabled_and_validation() {
+        let guard = DiskSpaceGuard::new(Config {
+            low_watermark_bytes: 0,
+            ..Config::default()
+        });
+        assert!(!guard.update(0));
+        assert!(guard.check().is_ok());
+
+        assert!(Config::default().validate().is_ok());
+        assert!(Config {
+            low_watermark_bytes: 200,
+            high_watermark_bytes: 100,
+            ..Config::default()
+        }
+        .validate()
+        .is_err());
+    }
+}
@@ -1,5 +1,9 @@
 pub use self::rocksdb::*;
 
+pub mod checkpoint;
+pub mod disk_space;
 mod multi_chunk_writer;
 mod rocksdb;
 pub mod snapshot;
+mod upload_throttle;
+pub mod write_stall;
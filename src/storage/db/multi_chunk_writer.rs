@@ -4,17 +4,42 @@ use gzp::ZWriter as _;
 use std::fs::{self, File};
 use std::io::{self, Result, Write};
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use tracing::info;
 
+// Hands over each finished chunk with its file name
+pub(crate) type ChunkSink = Box<dyn FnMut(String, Vec<u8>) -> Result<()> + Send>;
+
+enum ChunkOutput {
+    Dir(PathBuf),
+    // The chunk being compressed is buffered in memory until it's finished
+    Sink(ChunkSink, Arc<Mutex<Vec<u8>>>),
+}
+
+// Lets the encoder, which needs to own its writer, write into a buffer we can take back
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
 /**
 * A writer that will write data to multiple files, creating a new file when the current one
 * reaches the max size. The files are compressed using Gzip and the individual parts are
 * named chunk_XXXX.bin where XXXX is the part number.
 *
-* The writer will create the `base_path` directory if it does not exist.
+* The writer will create the `base_path` directory if it does not exist. With a sink instead, the
+* chunks are handed to it as they're finished rather than written to disk.
 */
 pub(crate) struct MultiChunkWriter {
-    base_path: PathBuf,
+    output: ChunkOutput,
     current_part: usize,
     max_size: usize,
     current_size: usize,
@@ -24,7 +49,17 @@ pub(crate) struct MultiChunkWriter {
 impl MultiChunkWriter {
     pub fn new(base_path: PathBuf, max_size: usize) -> Self {
         Self {
-            base_path,
+            output: ChunkOutput::Dir(base_path),
+            current_part: 0,
+            max_size,
+            current_size: 0,
+            encoder: None,
+        }
+    }
+
+    pub fn with_sink(sink: ChunkSink, max_size: usize) -> Self {
+        Self {
+            output: ChunkOutput::Sink(sink, Arc::new(Mutex::new(vec![]))),
             current_part: 0,
             max_size,
             current_size: 0,
@@ -32,6 +67,10 @@ impl MultiChunkWriter {
         }
     }
 
+    fn chunk_name(part: usize) -> String {
+        format!("chunk_{:04}.bin", part)
+    }
+
     fn ensure_directory_exists<P: AsRef<Path>>(&self, path: P) -> Result<()> {
         if !path.as_ref().exists() {
             fs::create_dir_all(path)?;
@@ -40,23 +79,22 @@ impl MultiChunkWriter {
     }
 
     pub fn next_part(&mut self) -> Result<()> {
-        self.ensure_directory_exists(&self.base_path)?;
+        if let ChunkOutput::Dir(base_path) = &self.output {
+            self.ensure_directory_exists(base_path)?;
+        }
         // Finish the current part if it exists
         self.finish()?;
 
         self.current_part += 1;
 
-        let file_name = self
-            .base_path
-            .join(format!("chunk_{:04}.bin", self.current_part));
-        let file = File::create(&file_name)?;
-
-        self.encoder = Some(
-            ParCompressBuilder::new()
-                .num_threads(4)
-                .unwrap()
-                .from_writer(file),
-        );
+        let builder = ParCompressBuilder::new().num_threads(4).unwrap();
+        self.encoder = Some(match &self.output {
+            ChunkOutput::Dir(base_path) => {
+                let file = File::create(base_path.join(Self::chunk_name(self.current_part)))?;
+                builder.from_writer(file)
+            }
+            ChunkOutput::Sink(_, buffer) => builder.from_writer(SharedBuffer(buffer.clone())),
+        });
 
         self.current_size = 0;
         Ok(())
@@ -76,7 +114,12 @@ impl MultiChunkWriter {
                     "Error finishing chunk"
                 );
                 io::Error::new(io::ErrorKind::InvalidData, e)
-            })?
+            })?;
+
+            if let ChunkOutput::Sink(sink, buffer) = &mut self.output {
+                let chunk = std::mem::take(&mut *buffer.lock().unwrap());
+                sink(Self::chunk_name(self.current_part), chunk)?;
+            }
         }
         Ok(())
     }
@@ -184,4 +227,32 @@ mod tests {
 
         assert!(non_existent_subdir.exists()); // Directory should now exist
     }
+
+    #[test]
+    fn test_sink_gets_the_same_chunks() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut writer = MultiChunkWriter::new(temp_dir.path().to_path_buf(), 5);
+        writer.write_all(b"12345").unwrap();
+        writer.write_all(b"67890").unwrap();
+        writer.finish().unwrap();
+
+        let chunks = Arc::new(Mutex::new(vec![]));
+        let sink_chunks = chunks.clone();
+        let mut writer = MultiChunkWriter::with_sink(
+            Box::new(move |name, chunk| {
+                sink_chunks.lock().unwrap().push((name, chunk));
+                Ok(())
+            }),
+            5,
+        );
+        writer.write_all(b"12345").unwrap();
+        writer.write_all(b"67890").unwrap();
+        writer.finish().unwrap();
+
+        let chunks = chunks.lock().unwrap();
+        assert_eq!(chunks.len(), 2);
+        for (name, chunk) in chunks.iter() {
+            assert_eq!(&std::fs::read(temp_dir.path().join(name)).unwrap(), chunk);
+        }
+    }
 }
@@ -1,9 +1,19 @@
 use crate::core::error::HubError;
-use crate::storage::db::multi_chunk_writer::MultiChunkWriter;
+use crate::proto::FarcasterNetwork;
+use crate::storage::db::disk_space;
+use crate::storage::db::multi_chunk_writer::{ChunkSink, MultiChunkWriter};
+use crate::storage::db::write_stall::{self, WriteStall};
+use crate::storage::store::message_cache;
+use crate::storage::store::self_check;
 use crate::storage::util::increment_vec_u8;
+use crate::utils::deadline::deadline_exceeded;
 use rocksdb::{Options, TransactionDB, DB};
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::{self};
+use std::iter::Peekable;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock, RwLockReadGuard};
 use thiserror::Error;
@@ -11,6 +21,28 @@ use tokio::time::Duration;
 use tracing::{info, warn};
 use walkdir::WalkDir;
 
+// How many items to visit between checks of the request deadline during iteration
+const DEADLINE_CHECK_INTERVAL: usize = 1000;
+
+// 100MB, this is the max size recommended for the S3 [put_object] API
+const TAR_GZIP_CHUNK_SIZE: usize = 100 * 1024 * 1024;
+
+// Evaluates $body with $db bound to whichever db is open, the primary or the secondary. The two
+// are different types, so reads that work the same on both are written once this way.
+macro_rules! with_readable_db {
+    ($rocksdb:expr, $db:ident => $body:expr) => {{
+        let primary = $rocksdb.db();
+        match primary.as_ref() {
+            Some($db) => $body,
+            None => {
+                let secondary = $rocksdb.secondary.read().unwrap();
+                let $db = secondary.as_ref().unwrap();
+                $body
+            }
+        }
+    }};
+}
+
 #[derive(Error, Debug)]
 pub enum RocksdbError {
     #[error(transparent)]
@@ -22,8 +54,238 @@ pub enum RocksdbError {
     #[error("DB is not open")]
     DbNotOpen,
 
+    #[error("DB is open as a read only secondary")]
+    ReadOnly,
+
     #[error(transparent)]
     BackupError(#[from] std::io::Error),
+
+    #[error("Path already exists: {0}")]
+    PathExists(String),
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ShardDirOverride {
+    pub shard_id: u32,
+    pub dir: String,
+}
+
+/// How durable a write is once the db acknowledged it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WriteDurability {
+    // Written to the WAL and fsynced, so it survives the machine going down
+    Sync,
+    // Written to the WAL without waiting for the fsync, rocksdb's default. Survives the process
+    // crashing, but the last writes are lost if the machine goes down.
+    #[default]
+    Wal,
+    // UNSAFE, for benchmarks and test networks only. Skips the WAL, so a crash loses every write
+    // that's still in memory, possibly leaving the db behind the blocks the node voted for.
+    UnsafeNoWal,
+}
+
+impl WriteDurability {
+    fn write_options(&self) -> rocksdb::WriteOptions {
+        let mut write_options = rocksdb::WriteOptions::default();
+        match self {
+            WriteDurability::Sync => write_options.set_sync(true),
+            WriteDurability::Wal => {}
+            WriteDurability::UnsafeNoWal => write_options.disable_wal(true),
+        }
+        write_options
+    }
+
+    // A flush of buffered commits stands for several of them, so it's synced whatever the
+    // durability of single writes, unless the WAL is skipped altogether
+    fn flush_write_options(&self) -> rocksdb::WriteOptions {
+        match self {
+            WriteDurability::UnsafeNoWal => self.write_options(),
+            WriteDurability::Sync | WriteDurability::Wal => WriteDurability::Sync.write_options(),
+        }
+    }
+}
+
+/// The compression of the values in a db's files
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompressionType {
+    None,
+    #[default]
+    Lz4,
+    // Smaller than lz4, especially with a dictionary, for more CPU time on writes and compactions
+    Zstd,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Compression {
+    pub algorithm: CompressionType,
+    // Only used by zstd, higher levels compress more at the cost of CPU time
+    pub zstd_level: i32,
+    // Size of the zstd dictionary trained for each file from its values, which helps with small
+    // values like messages that don't compress well on their own. 0 compresses without one.
+    pub zstd_dictionary_bytes: u32,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self {
+            algorithm: CompressionType::Lz4,
+            zstd_level: 3,
+            zstd_dictionary_bytes: 0,
+        }
+    }
+}
+
+impl Compression {
+    // Only files written from then on use it, so a db can be reopened with another compression
+    fn apply(&self, opts: &mut Options) {
+        match self.algorithm {
+            CompressionType::None => opts.set_compression_type(rocksdb::DBCompressionType::None),
+            CompressionType::Lz4 => opts.set_compression_type(rocksdb::DBCompressionType::Lz4),
+            CompressionType::Zstd => {
+                opts.set_compression_type(rocksdb::DBCompressionType::Zstd);
+                // The window bits and strategy are rocksdb's defaults
+                opts.set_compression_options(
+                    -14,
+                    self.zstd_level,
+                    0,
+                    self.zstd_dictionary_bytes as i32,
+                );
+                // Samples of up to 100 times the dictionary size, as zstd recommends
+                if self.zstd_dictionary_bytes > 0 {
+                    opts.set_zstd_max_train_bytes(
+                        (self.zstd_dictionary_bytes as i32).saturating_mul(100),
+                    );
+                }
+            }
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.algorithm == CompressionType::Zstd && !(1..=22).contains(&self.zstd_level) {
+            return Err(format!(
+                "zstd_level must be between 1 and 22: {}",
+                self.zstd_level
+            ));
+        }
+        if self.zstd_dictionary_bytes > i32::MAX as u32 / 100 {
+            return Err(format!(
+                "zstd_dictionary_bytes is too large: {}",
+                self.zstd_dictionary_bytes
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ShardWriteDurability {
+    pub shard_id: u32,
+    pub durability: WriteDurability,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Config {
+    // Per-shard base directories used instead of the global rocksdb_dir. The shard db is still
+    // created as `shard-{id}` inside the configured directory, so snapshots unpack the same way.
+    pub shard_dirs: Vec<ShardDirOverride>,
+    // Prefixes every key, so several test networks can reuse the same dbs without seeing each
+    // other's data. Empty, keys are stored as is.
+    pub network_namespace: String,
+    // The store writes of up to this many consecutively committed shard chunks are flushed as a
+    // single synced write. 0 or 1, every chunk is written on its own.
+    pub commit_batch_size: u32,
+    // A batch is flushed once its oldest chunk has waited this long, even if it isn't full. 0
+    // waits for a second.
+    #[serde(with = "humantime_serde")]
+    pub commit_batch_window: Duration,
+    // Committed chunks, or batches of them, are written on a thread of their own, and consensus
+    // only waits for the writes once this many are queued. 0 writes every chunk as it's committed.
+    pub commit_queue_depth: u32,
+    // Applies the trie changes of each transaction in a chunk together, rather than one message
+    // at a time. Roots are the same either way.
+    pub trie_batching: bool,
+    // Durability of the writes to every db, unless overridden for a shard's db (0 being the block
+    // db) in shard_write_durability
+    pub write_durability: WriteDurability,
+    pub shard_write_durability: Vec<ShardWriteDurability>,
+    // When submissions are rejected because a shard's db is stalled on writes
+    pub write_stall: write_stall::Config,
+    // When submissions are rejected because the disk holding the dbs is running out of space
+    pub disk_space: disk_space::Config,
+    // Compression of the shard dbs, which hold the message stores. The block db keeps using lz4.
+    pub compression: Compression,
+    // Checks of each shard db's trie and heights when the node starts
+    pub self_check: self_check::Config,
+    // Read-through cache of the messages looked up by hash and the casts looked up by id
+    pub message_cache: message_cache::Config,
+}
+
+impl Config {
+    pub fn shard_base_dir(&self, rocksdb_dir: &str, shard_id: u32) -> String {
+        self.shard_dirs
+            .iter()
+            .find(|o| o.shard_id == shard_id)
+            .map(|o| o.dir.clone())
+            .unwrap_or_else(|| rocksdb_dir.to_string())
+    }
+
+    pub fn shard_write_durability(&self, shard_id: u32) -> WriteDurability {
+        self.shard_write_durability
+            .iter()
+            .find(|o| o.shard_id == shard_id)
+            .map(|o| o.durability)
+            .unwrap_or(self.write_durability)
+    }
+
+    // Makes sure every override directory exists and is writable, so a bad mount fails at
+    // startup rather than when the shard is first opened.
+    pub fn validate(&self, network: FarcasterNetwork) -> Result<(), String> {
+        if network == FarcasterNetwork::Mainnet
+            && std::iter::once(self.write_durability)
+                .chain(self.shard_write_durability.iter().map(|o| o.durability))
+                .any(|durability| durability == WriteDurability::UnsafeNoWal)
+        {
+            return Err("unsafe_no_wal write durability isn't allowed on mainnet".to_string());
+        }
+        self.compression.validate()?;
+        self.disk_space.validate()?;
+
+        // The namespace is terminated by a 0 byte, so one can't be a prefix of another
+        if !self
+            .network_namespace
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(format!(
+                "network_namespace can only contain letters, digits, '-' and '_': {}",
+                self.network_namespace
+            ));
+        }
+        for o in &self.shard_dirs {
+            if self
+                .shard_dirs
+                .iter()
+                .filter(|x| x.shard_id == o.shard_id)
+                .count()
+                > 1
+            {
+                return Err(format!("duplicate dir override for shard {}", o.shard_id));
+            }
+            let probe = Path::new(&o.dir).join(".write_check");
+            fs::create_dir_all(&o.dir)
+                .and_then(|_| fs::write(&probe, b""))
+                .and_then(|_| fs::remove_file(&probe))
+                .map_err(|e| {
+                    format!(
+                        "dir {} for shard {} is not writable: {}",
+                        o.dir, o.shard_id, e
+                    )
+                })?;
+        }
+        Ok(())
+    }
 }
 
 /** Hold a transaction. List of key/value pairs that will be committed together */
@@ -61,12 +323,38 @@ impl RocksDbTransactionBatch {
 pub struct IteratorOptions {
     pub opts: rocksdb::ReadOptions,
     pub reverse: bool,
+    // Same bounds as opts, namespaced
+    lower_bound: Vec<u8>,
+    upper_bound: Vec<u8>,
+}
+
+// Where the next item of an iteration comes from when merging buffered writes with the db
+#[derive(PartialEq)]
+enum NextItem {
+    Db,
+    Buffered,
+    // The buffered write replaces the db value
+    Both,
 }
 
 #[derive(Default)]
 pub struct RocksDB {
     pub db: RwLock<Option<rocksdb::TransactionDB>>,
+    // Set instead of db when the db is opened as a secondary of another process' primary, see
+    // open_secondary
+    secondary: RwLock<Option<DB>>,
     pub path: String,
+    // Prepended to every key. Keys passed to and returned by the methods below never include it.
+    namespace: Vec<u8>,
+    // Commits passed to buffer_commit that weren't flushed yet, keyed by namespaced key. Reads see
+    // them as if they were written, a deleted key is None.
+    write_buffer: RwLock<BTreeMap<Vec<u8>, Option<Vec<u8>>>>,
+    // Buffered like write_buffer, but reads don't see them until they're flushed. Always locked
+    // after write_buffer.
+    unreported_writes: RwLock<BTreeMap<Vec<u8>, Option<Vec<u8>>>>,
+    // Unset, writes use rocksdb's defaults, i.e. the WAL without fsync
+    durability: Option<WriteDurability>,
+    compression: Compression,
 }
 
 #[derive(Debug, Default)]
@@ -82,24 +370,98 @@ impl std::fmt::Debug for RocksDB {
     }
 }
 
+fn count_keys<D: rocksdb::DBAccess>(mut iter: rocksdb::DBRawIteratorWithThreadMode<'_, D>) -> u32 {
+    let mut count = 0;
+    iter.seek_to_first();
+    while iter.valid() {
+        count += 1;
+
+        iter.next();
+    }
+    count
+}
+
 impl RocksDB {
     pub fn new(path: &str) -> RocksDB {
         info!({ path }, "Opening RocksDB database");
 
         RocksDB {
             db: RwLock::new(None),
+            secondary: RwLock::new(None),
             path: path.to_string(),
+            namespace: vec![],
+            write_buffer: RwLock::new(BTreeMap::new()),
+            unreported_writes: RwLock::new(BTreeMap::new()),
+            durability: None,
+            compression: Compression::default(),
         }
     }
 
-    pub fn open_shard_db(db_dir: &str, shard_id: u32) -> Arc<RocksDB> {
-        let db = RocksDB::new(format!("{}/shard-{}", db_dir, shard_id).as_str());
-        db.open().unwrap();
-        Arc::new(db)
+    pub fn with_compression(mut self, compression: Compression) -> RocksDB {
+        self.compression = compression;
+        self
+    }
+
+    pub fn with_write_durability(mut self, durability: WriteDurability) -> RocksDB {
+        self.durability = Some(durability);
+        self
     }
 
-    pub fn open_global_db(db_dir: &str) -> Arc<RocksDB> {
-        let db = RocksDB::new(format!("{}/global", db_dir).as_str());
+    fn write_options(&self) -> rocksdb::WriteOptions {
+        match &self.durability {
+            Some(durability) => durability.write_options(),
+            None => rocksdb::WriteOptions::default(),
+        }
+    }
+
+    pub fn with_namespace(mut self, namespace: &str) -> RocksDB {
+        self.namespace = if namespace.is_empty() {
+            vec![]
+        } else {
+            [namespace.as_bytes(), &[0]].concat()
+        };
+        self
+    }
+
+    pub fn open_shard_db(
+        db_dir: &str,
+        shard_id: u32,
+        namespace: &str,
+        durability: WriteDurability,
+    ) -> Arc<RocksDB> {
+        Self::try_open_shard_db(
+            db_dir,
+            shard_id,
+            namespace,
+            durability,
+            Compression::default(),
+        )
+        .unwrap()
+    }
+
+    pub fn try_open_shard_db(
+        db_dir: &str,
+        shard_id: u32,
+        namespace: &str,
+        durability: WriteDurability,
+        compression: Compression,
+    ) -> Result<Arc<RocksDB>, RocksdbError> {
+        let db = RocksDB::new(format!("{}/shard-{}", db_dir, shard_id).as_str())
+            .with_namespace(namespace)
+            .with_write_durability(durability)
+            .with_compression(compression);
+        db.open()?;
+        Ok(Arc::new(db))
+    }
+
+    pub fn open_global_db(
+        db_dir: &str,
+        namespace: &str,
+        durability: WriteDurability,
+    ) -> Arc<RocksDB> {
+        let db = RocksDB::new(format!("{}/global", db_dir).as_str())
+            .with_namespace(namespace)
+            .with_write_durability(durability);
         db.open().unwrap();
         Arc::new(db)
     }
@@ -177,6 +539,21 @@ impl RocksDB {
         Ok(output_file)
     }
 
+    // Creates a consistent point-in-time copy of the db at path. Files are hard linked when path
+    // is on the same filesystem, so this is much cheaper than a backup. path must not exist.
+    pub fn create_checkpoint(&self, path: &str) -> Result<(), RocksdbError> {
+        if Path::new(path).exists() {
+            return Err(RocksdbError::PathExists(path.to_string()));
+        }
+
+        let db = self.db();
+        let db = db.as_ref().ok_or(RocksdbError::DbNotOpen)?;
+        let checkpoint = rocksdb::checkpoint::Checkpoint::new(db)?;
+        checkpoint.create_checkpoint(path)?;
+        info!(path, "Created checkpoint of {}", self.path);
+        Ok(())
+    }
+
     pub fn create_tar_gzip(
         input_dir: &str,
         output_dir: &str,
@@ -204,7 +581,7 @@ impl RocksDB {
 
         let mut multi_chunk_writer = MultiChunkWriter::new(
             PathBuf::from(chunked_output_dir.clone()),
-            100 * 1024 * 1024, // 100MB, this is the max size recommended for the S3 [put_object] API
+            TAR_GZIP_CHUNK_SIZE,
         );
 
         let mut tar = tar::Builder::new(&mut multi_chunk_writer);
@@ -225,13 +602,42 @@ impl RocksDB {
         Ok(chunked_output_dir)
     }
 
+    /// Archives input_dir into the same chunks as create_tar_gzip, but hands each one to sink once
+    /// it's compressed instead of writing it to disk
+    pub fn stream_tar_gzip(input_dir: &str, sink: ChunkSink) -> Result<(), RocksdbError> {
+        let base_name = Path::new(input_dir)
+            .file_name()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap();
+
+        let start = std::time::SystemTime::now();
+        info!(
+            base_name = &base_name,
+            "Streaming chunked tar.gz snapshot for directory: {}", input_dir
+        );
+
+        let mut multi_chunk_writer = MultiChunkWriter::with_sink(sink, TAR_GZIP_CHUNK_SIZE);
+        let mut tar = tar::Builder::new(&mut multi_chunk_writer);
+        tar.append_dir_all(base_name, input_dir)?;
+        tar.finish()?;
+        drop(tar);
+        multi_chunk_writer.finish()?;
+
+        info!(
+            "Streamed chunked tar.gz archive for snapshot: dir = {}, time taken = {:?}",
+            input_dir,
+            start.elapsed().expect("Time went backwards")
+        );
+        Ok(())
+    }
+
     pub fn open(&self) -> Result<(), RocksdbError> {
         let mut db_lock = self.db.write().unwrap();
 
         // Create RocksDB options
         let mut opts = Options::default();
         opts.create_if_missing(true); // Creates a database if it does not exist
-        opts.set_compression_type(rocksdb::DBCompressionType::Lz4);
+        self.compression.apply(&mut opts);
 
         let mut tx_db_opts = rocksdb::TransactionDBOptions::default();
         tx_db_opts.set_default_lock_timeout(5000); // 5 seconds
@@ -252,16 +658,58 @@ impl RocksDB {
         Ok(())
     }
 
+    /// Opens the db at path read only, as a secondary of the process that has it open as its
+    /// primary. Reads see the primary's writes as of the last try_catch_up_with_primary, and
+    /// writes fail with ReadOnly. secondary_path holds the secondary's own info logs.
+    pub fn open_secondary(&self, secondary_path: &str) -> Result<(), RocksdbError> {
+        fs::create_dir_all(secondary_path)?;
+        let mut opts = Options::default();
+        // Secondaries must keep every table file open, the primary may delete them meanwhile
+        opts.set_max_open_files(-1);
+        self.compression.apply(&mut opts);
+
+        let db = DB::open_as_secondary(&opts, &self.path, secondary_path)?;
+        *self.secondary.write().unwrap() = Some(db);
+        Ok(())
+    }
+
+    /// Brings a secondary up to date with the primary's writes so far
+    pub fn try_catch_up_with_primary(&self) -> Result<(), RocksdbError> {
+        let secondary = self.secondary.read().unwrap();
+        let Some(db) = secondary.as_ref() else {
+            return Err(RocksdbError::DbNotOpen);
+        };
+        db.try_catch_up_with_primary()?;
+        Ok(())
+    }
+
+    pub fn is_secondary(&self) -> bool {
+        self.secondary.read().unwrap().is_some()
+    }
+
+    // The error for a write when there's no primary db open
+    fn not_writable(&self) -> RocksdbError {
+        if self.is_secondary() {
+            RocksdbError::ReadOnly
+        } else {
+            RocksdbError::DbNotOpen
+        }
+    }
+
     pub fn location(&self) -> String {
         self.path.clone()
     }
 
     pub fn close(&self) {
+        if let Err(err) = self.flush() {
+            warn!("Unable to flush buffered writes of {}: {}", self.path, err);
+        }
         let mut db_lock = self.db.write().unwrap();
         if db_lock.is_some() {
             let db = db_lock.take().unwrap();
             drop(db);
         }
+        drop(self.secondary.write().unwrap().take());
 
         // See the comment in open(). We strictly don't need to use the RwLock here, but we do it
         // to make the compiler happy. We could use unsafe to replace the value directly, like this:
@@ -290,58 +738,90 @@ impl RocksDB {
         Ok(())
     }
 
+    // The raw db, keys aren't namespaced
     pub fn db(&self) -> RwLockReadGuard<'_, Option<TransactionDB>> {
         self.db.read().unwrap()
     }
 
-    pub fn keys_exist(&self, keys: &Vec<Vec<u8>>) -> Vec<bool> {
-        let db = self.db();
-        let db = db.as_ref().unwrap();
+    fn namespaced_key<'a>(&self, key: &'a [u8]) -> Cow<'a, [u8]> {
+        if self.namespace.is_empty() {
+            Cow::Borrowed(key)
+        } else {
+            Cow::Owned([self.namespace.as_slice(), key].concat())
+        }
+    }
 
-        db.multi_get(keys)
+    // The buffered write for the key, if there is one
+    fn get_buffered(&self, namespaced_key: &[u8]) -> Option<Option<Vec<u8>>> {
+        let write_buffer = self.write_buffer.read().unwrap();
+        if write_buffer.is_empty() {
+            return None;
+        }
+        write_buffer.get(namespaced_key).cloned()
+    }
+
+    pub fn keys_exist(&self, keys: &Vec<Vec<u8>>) -> Vec<bool> {
+        let namespaced_keys: Vec<Cow<[u8]>> =
+            keys.iter().map(|key| self.namespaced_key(key)).collect();
+        with_readable_db!(self, db => db.multi_get(namespaced_keys.iter()))
             .into_iter()
-            .map(|r| match r {
-                Ok(Some(_)) => true,
-                Ok(None) => false,
-                Err(_) => false,
+            .zip(namespaced_keys.iter())
+            .map(|(r, key)| {
+                if let Some(buffered) = self.get_buffered(key) {
+                    return buffered.is_some();
+                }
+                match r {
+                    Ok(Some(_)) => true,
+                    Ok(None) => false,
+                    Err(_) => false,
+                }
             })
             .collect::<Vec<_>>()
     }
 
     pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, RocksdbError> {
-        self.db()
-            .as_ref()
-            .unwrap()
-            .get(key)
-            .map_err(|e| RocksdbError::InternalError(e))
+        let key = self.namespaced_key(key);
+        if let Some(buffered) = self.get_buffered(&key) {
+            return Ok(buffered);
+        }
+        with_readable_db!(self, db => db.get(key)).map_err(|e| RocksdbError::InternalError(e))
     }
 
     pub fn get_many(&self, keys: &Vec<Vec<u8>>) -> Result<Vec<Vec<u8>>, RocksdbError> {
-        let results = self.db().as_ref().unwrap().multi_get(keys);
+        let namespaced_keys: Vec<Cow<[u8]>> =
+            keys.iter().map(|key| self.namespaced_key(key)).collect();
+        let results = with_readable_db!(self, db => db.multi_get(namespaced_keys.iter()));
 
         // If any of the results are Errors, return an error
         let results = results.into_iter().collect::<Result<Vec<_>, _>>()?;
         let results = results
             .into_iter()
-            .map(|r| r.unwrap_or(vec![]))
+            .zip(namespaced_keys.iter())
+            .map(|(r, key)| match self.get_buffered(key) {
+                Some(buffered) => buffered.unwrap_or(vec![]),
+                None => r.unwrap_or(vec![]),
+            })
             .collect::<Vec<_>>();
 
         Ok(results)
     }
 
     pub fn put(&self, key: &[u8], value: &[u8]) -> Result<(), RocksdbError> {
+        // A buffered write of the key would otherwise overwrite this one when it's flushed
+        self.flush()?;
         self.db()
             .as_ref()
-            .unwrap()
-            .put(key, value)
+            .ok_or_else(|| self.not_writable())?
+            .put_opt(self.namespaced_key(key), value, &self.write_options())
             .map_err(|e| RocksdbError::InternalError(e))
     }
 
     pub fn del(&self, key: &[u8]) -> Result<(), RocksdbError> {
+        self.flush()?;
         self.db()
             .as_ref()
-            .unwrap()
-            .delete(key)
+            .ok_or_else(|| self.not_writable())?
+            .delete_opt(self.namespaced_key(key), &self.write_options())
             .map_err(|e| RocksdbError::InternalError(e))
     }
 
@@ -349,14 +829,35 @@ impl RocksDB {
         RocksDbTransactionBatch::new()
     }
 
+    // Buffered writes are written along with the batch, so they're never applied after it
     pub fn commit(&self, batch: RocksDbTransactionBatch) -> Result<(), RocksdbError> {
+        self.commit_opt(batch, &self.write_options())
+    }
+
+    fn commit_opt(
+        &self,
+        batch: RocksDbTransactionBatch,
+        write_options: &rocksdb::WriteOptions,
+    ) -> Result<(), RocksdbError> {
         let db = self.db();
         if db.is_none() {
-            return Err(RocksdbError::DbNotOpen);
+            return Err(self.not_writable());
         }
 
-        let txn = db.as_ref().unwrap().transaction();
+        let mut write_buffer = self.write_buffer.write().unwrap();
+        let mut unreported_writes = self.unreported_writes.write().unwrap();
+        let txn = db
+            .as_ref()
+            .unwrap()
+            .transaction_opt(write_options, &rocksdb::TransactionOptions::default());
+        for (key, value) in write_buffer.iter().chain(unreported_writes.iter()) {
+            match value {
+                None => txn.delete(key)?,
+                Some(value) => txn.put(key, value)?,
+            }
+        }
         for (key, value) in batch.batch {
+            let key = self.namespaced_key(&key);
             if value.is_none() {
                 txn.delete(key)?;
             } else {
@@ -364,10 +865,117 @@ impl RocksDB {
             }
         }
 
+        txn.commit().map_err(|e| RocksdbError::InternalError(e))?;
+        write_buffer.clear();
+        unreported_writes.clear();
+        Ok(())
+    }
+
+    /// Writes the batch right away, without writing the buffered commits first, so it doesn't
+    /// cut a batch of commits short. Only for keys no commit writes, e.g. the node's own records
+    /// outside the trie, since a buffered write of the same key is written over it when flushed.
+    pub fn commit_unbuffered(&self, batch: RocksDbTransactionBatch) -> Result<(), RocksdbError> {
+        let db = self.db();
+        let Some(db) = db.as_ref() else {
+            return Err(self.not_writable());
+        };
+        let txn = db.transaction_opt(
+            &self.write_options(),
+            &rocksdb::TransactionOptions::default(),
+        );
+        for (key, value) in batch.batch {
+            let key = self.namespaced_key(&key);
+            match value {
+                None => txn.delete(key)?,
+                Some(value) => txn.put(key, value)?,
+            }
+        }
         txn.commit().map_err(|e| RocksdbError::InternalError(e))
     }
 
+    /// Holds the batch in memory until the next flush, so several commits are written with a
+    /// single write. Reads see the batch right away, but it's lost if the process stops
+    /// before it's flushed.
+    pub fn buffer_commit(&self, batch: RocksDbTransactionBatch) -> Result<(), RocksdbError> {
+        self.buffer_commit_with_unreported(batch, RocksDbTransactionBatch::new())
+    }
+
+    /// Like buffer_commit, but reads only see the writes in `unreported` once they're flushed.
+    /// Both are written in the same write, so nothing read from `unreported` can be on disk
+    /// without the rest of the commit.
+    pub fn buffer_commit_with_unreported(
+        &self,
+        batch: RocksDbTransactionBatch,
+        unreported: RocksDbTransactionBatch,
+    ) -> Result<(), RocksdbError> {
+        if self.db().is_none() {
+            return Err(self.not_writable());
+        }
+
+        let mut write_buffer = self.write_buffer.write().unwrap();
+        let mut unreported_writes = self.unreported_writes.write().unwrap();
+        // A key is only held in one of the two, so the latest write of it is the one written
+        for (key, value) in batch.batch {
+            let key = self.namespaced_key(&key).into_owned();
+            unreported_writes.remove(&key);
+            write_buffer.insert(key, value);
+        }
+        for (key, value) in unreported.batch {
+            let key = self.namespaced_key(&key).into_owned();
+            write_buffer.remove(&key);
+            unreported_writes.insert(key, value);
+        }
+        Ok(())
+    }
+
+    /// Durably writes the buffered commits, with a synced write unless the db skips the WAL
+    pub fn flush(&self) -> Result<(), RocksdbError> {
+        if self.write_buffer.read().unwrap().is_empty()
+            && self.unreported_writes.read().unwrap().is_empty()
+        {
+            return Ok(());
+        }
+        let write_options = self
+            .durability
+            .unwrap_or(WriteDurability::Wal)
+            .flush_write_options();
+        self.commit_opt(RocksDbTransactionBatch::new(), &write_options)
+    }
+
+    /// Writes the buffered commits, then syncs the WAL and flushes the memtables, so nothing has
+    /// to be recovered from the WAL the next time the db is opened
+    pub fn flush_to_disk(&self) -> Result<(), RocksdbError> {
+        self.flush()?;
+        let db = self.db();
+        let Some(db) = db.as_ref() else {
+            return Err(self.not_writable());
+        };
+        db.flush_wal(true)?;
+        db.flush()?;
+        Ok(())
+    }
+
+    // The buffered writes in the iteration's bounds, in iteration order
+    fn buffered_writes_in_range(
+        &self,
+        iter_opts: &IteratorOptions,
+    ) -> Vec<(Vec<u8>, Option<Vec<u8>>)> {
+        let write_buffer = self.write_buffer.read().unwrap();
+        if write_buffer.is_empty() || iter_opts.lower_bound >= iter_opts.upper_bound {
+            return vec![];
+        }
+        let writes = write_buffer
+            .range(iter_opts.lower_bound.clone()..iter_opts.upper_bound.clone())
+            .map(|(key, value)| (key.clone(), value.clone()));
+        if iter_opts.reverse {
+            writes.rev().collect()
+        } else {
+            writes.collect()
+        }
+    }
+
     fn get_iterator_options(
+        &self,
         start_prefix: Option<Vec<u8>>,
         stop_prefix: Option<Vec<u8>>,
         page_options: &PageOptions,
@@ -402,13 +1010,17 @@ impl RocksDB {
             }
         };
 
+        let lower_bound = self.namespaced_key(&lower_bound).into_owned();
+        let upper_bound = self.namespaced_key(&upper_bound).into_owned();
         let mut opts = rocksdb::ReadOptions::default();
-        opts.set_iterate_lower_bound(lower_bound);
-        opts.set_iterate_upper_bound(upper_bound);
+        opts.set_iterate_lower_bound(lower_bound.clone());
+        opts.set_iterate_upper_bound(upper_bound.clone());
 
         IteratorOptions {
             opts,
             reverse: page_options.reverse,
+            lower_bound,
+            upper_bound,
         }
     }
 
@@ -421,17 +1033,40 @@ impl RocksDB {
         start_prefix: Option<Vec<u8>>,
         stop_prefix: Option<Vec<u8>>,
         page_options: &PageOptions,
-        mut f: F,
+        f: F,
     ) -> Result<bool, HubError>
     where
         F: FnMut(&[u8], &[u8]) -> Result<bool, HubError>,
     {
-        let iter_opts = RocksDB::get_iterator_options(start_prefix, stop_prefix, page_options);
+        let iter_opts = self.get_iterator_options(start_prefix, stop_prefix, page_options);
+        let buffered = self
+            .buffered_writes_in_range(&iter_opts)
+            .into_iter()
+            .peekable();
+        let reverse = iter_opts.reverse;
 
-        let db = self.db();
-        let mut iter = db.as_ref().unwrap().raw_iterator_opt(iter_opts.opts);
+        with_readable_db!(self, db => self.visit_paged(
+            db.raw_iterator_opt(iter_opts.opts),
+            buffered,
+            reverse,
+            page_options,
+            f,
+        ))
+    }
 
-        if iter_opts.reverse {
+    // Visits the db's and the buffered items in order, for for_each_iterator_by_prefix_paged
+    fn visit_paged<D: rocksdb::DBAccess, F>(
+        &self,
+        mut iter: rocksdb::DBRawIteratorWithThreadMode<'_, D>,
+        mut buffered: Peekable<std::vec::IntoIter<(Vec<u8>, Option<Vec<u8>>)>>,
+        reverse: bool,
+        page_options: &PageOptions,
+        mut f: F,
+    ) -> Result<bool, HubError>
+    where
+        F: FnMut(&[u8], &[u8]) -> Result<bool, HubError>,
+    {
+        if reverse {
             iter.seek_to_last();
         } else {
             iter.seek_to_first();
@@ -439,27 +1074,68 @@ impl RocksDB {
 
         let mut all_done = true;
         let mut count = 0;
+        let mut visited: usize = 0;
 
-        while iter.valid() {
-            if let Some((key, value)) = iter.item() {
-                if f(&key, &value)? {
+        loop {
+            let next_item = match (iter.key(), buffered.peek()) {
+                (None, None) => break,
+                (Some(_), None) => NextItem::Db,
+                (None, Some(_)) => NextItem::Buffered,
+                (Some(db_key), Some((buffered_key, _))) => {
+                    let ordering = db_key.cmp(buffered_key.as_slice());
+                    match if reverse {
+                        ordering.reverse()
+                    } else {
+                        ordering
+                    } {
+                        Ordering::Less => NextItem::Db,
+                        Ordering::Greater => NextItem::Buffered,
+                        Ordering::Equal => NextItem::Both,
+                    }
+                }
+            };
+
+            visited += 1;
+            if visited % DEADLINE_CHECK_INTERVAL == 0 && deadline_exceeded() {
+                return Err(HubError::deadline_exceeded(
+                    "iteration exceeded request deadline",
+                ));
+            }
+            let stop = match next_item {
+                NextItem::Db => match iter.item() {
+                    Some((key, value)) => Some(f(&key[self.namespace.len()..], &value)?),
+                    None => None,
+                },
+                NextItem::Buffered | NextItem::Both => match buffered.next() {
+                    Some((key, Some(value))) => Some(f(&key[self.namespace.len()..], &value)?),
+                    // Deleted by a buffered commit
+                    _ => None,
+                },
+            };
+            if next_item != NextItem::Buffered {
+                if reverse {
+                    iter.prev();
+                } else {
+                    iter.next();
+                }
+            }
+
+            match stop {
+                None => {}
+                Some(true) => {
                     all_done = false;
                     break;
                 }
-                if page_options.page_size.is_some() {
-                    count += 1;
-                    if count >= page_options.page_size.unwrap() {
-                        all_done = true;
-                        break;
+                Some(false) => {
+                    if page_options.page_size.is_some() {
+                        count += 1;
+                        if count >= page_options.page_size.unwrap() {
+                            all_done = true;
+                            break;
+                        }
                     }
                 }
             }
-
-            if iter_opts.reverse {
-                iter.prev();
-            } else {
-                iter.next();
-            }
         }
 
         Ok(all_done)
@@ -492,6 +1168,20 @@ impl RocksDB {
         Ok(all_done)
     }
 
+    // Whether the raw key is under some network's namespace, a run of the characters namespaces
+    // are made of terminated by a 0 byte. Keys outside of one start with a root prefix, which
+    // isn't one of those characters.
+    fn is_namespaced(raw_key: &[u8]) -> bool {
+        raw_key.iter().position(|&b| b == 0).is_some_and(|end| {
+            end > 0
+                && raw_key[..end]
+                    .iter()
+                    .all(|&c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_')
+        })
+    }
+
+    /// Deletes the keys in the db's namespace. Without a namespace, that's the keys outside of
+    /// every namespace, so the data of the networks sharing the db is left alone.
     pub fn clear(&self) -> Result<u32, RocksdbError> {
         let mut deleted;
 
@@ -499,13 +1189,20 @@ impl RocksDB {
             // reset deleted count
             deleted = 0;
 
-            // Iterate over all keys and delete them
             let mut txn = self.txn();
             let db = self.db();
 
-            for item in db.as_ref().unwrap().iterator(rocksdb::IteratorMode::Start) {
+            let mode = rocksdb::IteratorMode::From(&self.namespace, rocksdb::Direction::Forward);
+            for item in db.as_ref().unwrap().iterator(mode) {
                 if let Ok((key, _)) = item {
-                    txn.delete(key.to_vec());
+                    if self.namespace.is_empty() {
+                        if Self::is_namespaced(&key) {
+                            continue;
+                        }
+                    } else if !key.starts_with(&self.namespace) {
+                        break;
+                    }
+                    txn.delete(key[self.namespace.len()..].to_vec());
                     deleted += 1;
                 }
             }
@@ -521,6 +1218,34 @@ impl RocksDB {
         Ok(deleted)
     }
 
+    // Compacts the keys in [start, stop), flushing them from the memtable first. Blocks until the
+    // compaction is done, writes continue meanwhile.
+    pub fn compact_range(&self, start: &[u8], stop: &[u8]) -> Result<(), RocksdbError> {
+        let db = self.db();
+        let Some(db) = db.as_ref() else {
+            return Err(self.not_writable());
+        };
+        db.compact_range(
+            Some(self.namespaced_key(start)),
+            Some(self.namespaced_key(stop)),
+        );
+        Ok(())
+    }
+
+    pub fn write_stall(&self) -> Result<WriteStall, RocksdbError> {
+        let db = self.db();
+        let Some(db) = db.as_ref() else {
+            return Err(self.not_writable());
+        };
+        let is_write_stopped = db.property_int_value("rocksdb.is-write-stopped")?;
+        let actual_delayed_write_rate =
+            db.property_int_value("rocksdb.actual-delayed-write-rate")?;
+        Ok(WriteStall::from_stats(
+            is_write_stopped.unwrap_or(0),
+            actual_delayed_write_rate.unwrap_or(0),
+        ))
+    }
+
     pub fn approximate_size(&self) -> u64 {
         WalkDir::new(self.location())
             .into_iter()
@@ -535,21 +1260,32 @@ impl RocksDB {
      * Count the number of keys with a given prefix.
      */
     pub fn count_keys_at_prefix(&self, prefix: Vec<u8>) -> Result<u32, HubError> {
-        let iter_opts = RocksDB::get_iterator_options(
+        let iter_opts = self.get_iterator_options(
             Some(prefix.clone()),
             Some(increment_vec_u8(&prefix.to_vec())),
             &PageOptions::default(),
         );
 
-        let db = self.db();
-        let mut iter = db.as_ref().unwrap().raw_iterator_opt(iter_opts.opts);
+        let buffered = self.buffered_writes_in_range(&iter_opts);
 
-        let mut count = 0;
-        iter.seek_to_first();
-        while iter.valid() {
-            count += 1;
+        let mut count =
+            with_readable_db!(self, db => count_keys(db.raw_iterator_opt(iter_opts.opts)));
 
-            iter.next();
+        // Buffered writes add the keys that aren't in the db yet and remove the deleted ones. Only a
+        // primary buffers writes.
+        let db = self.db();
+        for (key, value) in buffered {
+            let in_db = db
+                .as_ref()
+                .unwrap()
+                .get(&key)
+                .map_err(|e| HubError::from(RocksdbError::InternalError(e)))?
+                .is_some();
+            match (in_db, value.is_some()) {
+                (false, true) => count += 1,
+                (true, false) => count -= 1,
+                _ => {}
+            }
         }
 
         Ok(count)
@@ -642,8 +1378,12 @@ impl RocksDB {
 
 #[cfg(test)]
 mod tests {
+    use crate::proto::FarcasterNetwork;
     use crate::storage::{
-        db::{RocksDB, RocksDbTransactionBatch},
+        db::{
+            Config, PageOptions, RocksDB, RocksDbTransactionBatch, ShardWriteDurability,
+            WriteDurability,
+        },
         util::increment_vec_u8,
     };
 
@@ -799,6 +1539,230 @@ mod tests {
         db.destroy().unwrap();
     }
 
+    #[test]
+    fn test_buffered_commits() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = RocksDB::new(dir.path().join("buffered").to_str().unwrap());
+        db.open().unwrap();
+        db.put(b"key1", b"value1").unwrap();
+        db.put(b"key2", b"value2").unwrap();
+
+        let mut txn = db.txn();
+        txn.put(b"key0".to_vec(), b"value0".to_vec());
+        txn.put(b"key1".to_vec(), b"updated".to_vec());
+        txn.delete(b"key2".to_vec());
+        db.buffer_commit(txn).unwrap();
+        let mut txn = db.txn();
+        txn.put(b"key3".to_vec(), b"value3".to_vec());
+        db.buffer_commit(txn).unwrap();
+
+        // Reads see the buffered commits
+        let read_all = |db: &RocksDB, reverse: bool| {
+            let mut items = vec![];
+            db.for_each_iterator_by_prefix(
+                Some(b"key".to_vec()),
+                Some(b"kez".to_vec()),
+                &PageOptions {
+                    reverse,
+                    ..PageOptions::default()
+                },
+                |key, value| {
+                    items.push((key.to_vec(), value.to_vec()));
+                    Ok(false)
+                },
+            )
+            .unwrap();
+            items
+        };
+        let expected = vec![
+            (b"key0".to_vec(), b"value0".to_vec()),
+            (b"key1".to_vec(), b"updated".to_vec()),
+            (b"key3".to_vec(), b"value3".to_vec()),
+        ];
+        assert_eq!(read_all(&db, false), expected);
+        assert_eq!(
+            read_all(&db, true),
+            expected.iter().rev().cloned().collect::<Vec<_>>()
+        );
+        assert_eq!(db.get(b"key1").unwrap(), Some(b"updated".to_vec()));
+        assert_eq!(db.get(b"key2").unwrap(), None);
+        assert_eq!(
+            db.keys_exist(&vec![b"key0".to_vec(), b"key2".to_vec()]),
+            vec![true, false]
+        );
+        assert_eq!(db.count_keys_at_prefix(b"key".to_vec()).unwrap(), 3);
+
+        // But they aren't written until flushed
+        let raw_get = |key: &[u8]| db.db().as_ref().unwrap().get(key).unwrap();
+        assert_eq!(raw_get(b"key0"), None);
+        assert_eq!(raw_get(b"key2"), Some(b"value2".to_vec()));
+
+        db.flush().unwrap();
+        assert_eq!(raw_get(b"key0"), Some(b"value0".to_vec()));
+        assert_eq!(raw_get(b"key2"), None);
+        assert_eq!(read_all(&db, false), expected);
+
+        // A regular commit writes the buffered ones with it
+        let mut txn = db.txn();
+        txn.put(b"key4".to_vec(), b"value4".to_vec());
+        db.buffer_commit(txn).unwrap();
+        let mut txn = db.txn();
+        txn.put(b"key5".to_vec(), b"value5".to_vec());
+        db.commit(txn).unwrap();
+        assert_eq!(raw_get(b"key4"), Some(b"value4".to_vec()));
+        assert_eq!(raw_get(b"key5"), Some(b"value5".to_vec()));
+
+        // Unreported writes are only read once they're written, along with the rest
+        let mut txn = db.txn();
+        txn.put(b"key6".to_vec(), b"value6".to_vec());
+        let mut unreported = db.txn();
+        unreported.put(b"key7".to_vec(), b"value7".to_vec());
+        unreported.put(b"key5".to_vec(), b"updated".to_vec());
+        db.buffer_commit_with_unreported(txn, unreported).unwrap();
+        assert_eq!(db.get(b"key6").unwrap(), Some(b"value6".to_vec()));
+        assert_eq!(db.get(b"key7").unwrap(), None);
+        assert_eq!(db.get(b"key5").unwrap(), Some(b"value5".to_vec()));
+        assert_eq!(
+            db.keys_exist(&vec![b"key6".to_vec(), b"key7".to_vec()]),
+            vec![true, false]
+        );
+        assert_eq!(db.count_keys_at_prefix(b"key".to_vec()).unwrap(), 6);
+
+        // A later reported write of the same key is the one written
+        let mut txn = db.txn();
+        txn.put(b"key7".to_vec(), b"reported".to_vec());
+        db.buffer_commit(txn).unwrap();
+        assert_eq!(db.get(b"key7").unwrap(), Some(b"reported".to_vec()));
+
+        db.flush().unwrap();
+        assert_eq!(raw_get(b"key6"), Some(b"value6".to_vec()));
+        assert_eq!(raw_get(b"key7"), Some(b"reported".to_vec()));
+        assert_eq!(db.get(b"key5").unwrap(), Some(b"updated".to_vec()));
+
+        // Unbuffered writes leave the buffered commits for the next flush
+        let mut txn = db.txn();
+        txn.put(b"key8".to_vec(), b"value8".to_vec());
+        db.buffer_commit(txn).unwrap();
+        let mut txn = db.txn();
+        txn.put(b"other".to_vec(), b"value".to_vec());
+        db.commit_unbuffered(txn).unwrap();
+        assert_eq!(raw_get(b"other"), Some(b"value".to_vec()));
+        assert_eq!(raw_get(b"key8"), None);
+        assert_eq!(db.get(b"key8").unwrap(), Some(b"value8".to_vec()));
+    }
+
+    #[test]
+    fn test_write_durability() {
+        let dir = tempfile::tempdir().unwrap();
+        for durability in [
+            WriteDurability::Sync,
+            WriteDurability::Wal,
+            WriteDurability::UnsafeNoWal,
+        ] {
+            let path = dir.path().join(format!("{:?}", durability));
+            let db = RocksDB::new(path.to_str().unwrap()).with_write_durability(durability);
+            db.open().unwrap();
+            db.put(b"key1", b"value1").unwrap();
+            let mut txn = db.txn();
+            txn.put(b"key2".to_vec(), b"value2".to_vec());
+            db.commit(txn).unwrap();
+            db.del(b"key1").unwrap();
+            db.close();
+
+            // Closing the db writes out what's still in memory, WAL or not
+            let db = RocksDB::new(path.to_str().unwrap());
+            db.open().unwrap();
+            assert_eq!(db.get(b"key1").unwrap(), None);
+            assert_eq!(db.get(b"key2").unwrap(), Some(b"value2".to_vec()));
+            db.close();
+        }
+    }
+
+    #[test]
+    fn test_write_durability_config() {
+        let config = Config {
+            write_durability: WriteDurability::Wal,
+            shard_write_durability: vec![ShardWriteDurability {
+                shard_id: 2,
+                durability: WriteDurability::UnsafeNoWal,
+            }],
+            ..Config::default()
+        };
+        assert_eq!(config.shard_write_durability(0), WriteDurability::Wal);
+        assert_eq!(
+            config.shard_write_durability(2),
+            WriteDurability::UnsafeNoWal
+        );
+        assert_eq!(config.validate(FarcasterNetwork::Devnet), Ok(()));
+        assert!(config.validate(FarcasterNetwork::Mainnet).is_err());
+
+        // Like before durability was configurable
+        let config = Config::default();
+        assert_eq!(config.shard_write_durability(1), WriteDurability::Wal);
+        assert_eq!(config.validate(FarcasterNetwork::Mainnet), Ok(()));
+    }
+
+    #[test]
+    fn test_compressed_values_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("compressed.db");
+        let zstd = Compression {
+            algorithm: CompressionType::Zstd,
+            zstd_level: 3,
+            zstd_dictionary_bytes: 16 * 1024,
+        };
+        let values: Vec<(Vec<u8>, Vec<u8>)> = (0..2_000u32)
+            .map(|i| {
+                let value = format!(
+                    "cast {} with text that compresses well {}",
+                    i,
+                    "a".repeat(64)
+                );
+                (i.to_be_bytes().to_vec(), value.into_bytes())
+            })
+            .collect();
+
+        // Written without compression, then rewritten into zstd files by the compaction
+        for compression in [
+            Compression {
+                algorithm: CompressionType::None,
+                ..Compression::default()
+            },
+            zstd,
+        ] {
+            let db = RocksDB::new(path.to_str().unwrap()).with_compression(compression);
+            db.open().unwrap();
+            if compression.algorithm == CompressionType::None {
+                let mut txn = db.txn();
+                for (key, value) in &values {
+                    txn.put(key.clone(), value.clone());
+                }
+                db.commit(txn).unwrap();
+            }
+            db.compact_range(&[], &[0xff; 4]).unwrap();
+            for (key, value) in &values {
+                assert_eq!(db.get(key).unwrap().as_ref(), Some(value));
+            }
+            db.close();
+        }
+
+        // Reopened with the default, the zstd files are still read
+        let db = RocksDB::new(path.to_str().unwrap());
+        db.open().unwrap();
+        for (key, value) in &values {
+            assert_eq!(db.get(key).unwrap().as_ref(), Some(value));
+        }
+
+        let config = Config {
+            compression: Compression {
+                zstd_level: 30,
+                ..zstd
+            },
+            ..Config::default()
+        };
+        assert!(config.validate(FarcasterNetwork::Devnet).is_err());
+    }
+
     #[test]
     fn test_keys_exist_in_db() {
         let tmp_path = tempfile::tempdir()
@@ -895,4 +1859,170 @@ mod tests {
         db.destroy().unwrap();
         db.open().unwrap();
     }
+
+    #[tokio::test]
+    async fn test_iteration_stops_at_deadline() {
+        let tmp_path = tempfile::tempdir()
+            .unwrap()
+            .path()
+            .as_os_str()
+            .to_string_lossy()
+            .to_string();
+        let db = RocksDB::new(&tmp_path);
+        db.open().unwrap();
+
+        for i in 0..(super::DEADLINE_CHECK_INTERVAL * 2) {
+            db.put(format!("key{:05}", i).as_bytes(), b"value").unwrap();
+        }
+
+        let iterate = || {
+            let mut visited = 0;
+            let result = db.for_each_iterator_by_prefix(
+                Some(b"key".to_vec()),
+                Some(b"kez".to_vec()),
+                &super::PageOptions::default(),
+                |_, _| {
+                    visited += 1;
+                    Ok(false)
+                },
+            );
+            (result, visited)
+        };
+
+        // Without a deadline, everything is visited
+        let (result, visited) = iterate();
+        assert!(result.is_ok());
+        assert_eq!(visited, super::DEADLINE_CHECK_INTERVAL * 2);
+
+        let deadline = tokio::time::Instant::now();
+        let (result, visited) =
+            crate::utils::deadline::with_deadline(deadline, async { iterate() }).await;
+        assert_eq!(result.unwrap_err().code, "deadline_exceeded");
+        assert!(visited < super::DEADLINE_CHECK_INTERVAL * 2);
+
+        // Cleanup
+        db.destroy().unwrap();
+    }
+
+    #[test]
+    fn test_create_checkpoint() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let db = RocksDB::new(tmp_dir.path().join("db").to_str().unwrap());
+        db.open().unwrap();
+        db.put(b"key1", b"value1").unwrap();
+
+        let checkpoint_path = tmp_dir.path().join("checkpoint");
+        let checkpoint_path = checkpoint_path.to_str().unwrap();
+        db.create_checkpoint(checkpoint_path).unwrap();
+
+        // Later writes aren't part of the checkpoint
+        db.put(b"key2", b"value2").unwrap();
+
+        // Refuses to overwrite an existing path
+        assert!(matches!(
+            db.create_checkpoint(checkpoint_path),
+            Err(super::RocksdbError::PathExists(_))
+        ));
+
+        let checkpoint = RocksDB::new(checkpoint_path);
+        checkpoint.open().unwrap();
+        assert_eq!(checkpoint.get(b"key1").unwrap(), Some(b"value1".to_vec()));
+        assert_eq!(checkpoint.get(b"key2").unwrap(), None);
+        checkpoint.close();
+
+        db.destroy().unwrap();
+    }
+
+    #[test]
+    fn test_clear_leaves_other_namespaces() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("db");
+        let path = path.to_str().unwrap();
+        let plain = RocksDB::new(path);
+        let namespaced = RocksDB::new(path).with_namespace("test");
+
+        plain.open().unwrap();
+        plain.put(&[1, 2], b"value1").unwrap();
+        plain.close();
+        namespaced.open().unwrap();
+        namespaced.put(&[1, 2], b"value2").unwrap();
+        namespaced.put(&[1, 3], b"value3").unwrap();
+        namespaced.close();
+
+        // Clearing without a namespace keeps the namespaced keys
+        plain.open().unwrap();
+        plain.clear().unwrap();
+        assert_eq!(plain.get(&[1, 2]).unwrap(), None);
+        plain.put(&[1, 2], b"value1").unwrap();
+        plain.close();
+
+        namespaced.open().unwrap();
+        assert_eq!(namespaced.get(&[1, 2]).unwrap(), Some(b"value2".to_vec()));
+        namespaced.clear().unwrap();
+        assert_eq!(namespaced.get(&[1, 3]).unwrap(), None);
+        namespaced.close();
+
+        // And the other way around
+        plain.open().unwrap();
+        assert_eq!(plain.get(&[1, 2]).unwrap(), Some(b"value1".to_vec()));
+        plain.destroy().unwrap();
+    }
+
+    #[test]
+    fn test_secondary_catches_up_with_primary() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let primary_path = tmp_dir.path().join("primary");
+        let primary = RocksDB::new(primary_path.to_str().unwrap()).with_namespace("test");
+        primary.open().unwrap();
+        primary.put(b"key1", b"value1").unwrap();
+
+        let secondary = RocksDB::new(primary_path.to_str().unwrap()).with_namespace("test");
+        secondary
+            .open_secondary(tmp_dir.path().join("secondary").to_str().unwrap())
+            .unwrap();
+        assert!(secondary.is_secondary());
+        assert!(!primary.is_secondary());
+        assert_eq!(secondary.get(b"key1").unwrap(), Some(b"value1".to_vec()));
+
+        // Later writes show up once the secondary caught up
+        let mut txn = primary.txn();
+        txn.put(b"key2".to_vec(), b"value2".to_vec());
+        txn.delete(b"key1".to_vec());
+        primary.commit(txn).unwrap();
+        assert_eq!(secondary.get(b"key2").unwrap(), None);
+        secondary.try_catch_up_with_primary().unwrap();
+        assert_eq!(secondary.get(b"key1").unwrap(), None);
+        assert_eq!(secondary.get(b"key2").unwrap(), Some(b"value2".to_vec()));
+        assert_eq!(secondary.count_keys_at_prefix(b"key".to_vec()).unwrap(), 1);
+        let mut keys = vec![];
+        secondary
+            .for_each_iterator_by_prefix(
+                Some(b"key".to_vec()),
+                Some(b"kez".to_vec()),
+                &PageOptions::default(),
+                |key, _| {
+                    keys.push(key.to_vec());
+                    Ok(false)
+                },
+            )
+            .unwrap();
+        assert_eq!(keys, vec![b"key2".to_vec()]);
+
+        // It can't be written to
+        assert!(matches!(
+            secondary.put(b"key3", b"value3"),
+            Err(super::RocksdbError::ReadOnly)
+        ));
+        assert!(matches!(
+            secondary.commit(secondary.txn()),
+            Err(super::RocksdbError::ReadOnly)
+        ));
+        assert!(matches!(
+            primary.try_catch_up_with_primary(),
+            Err(super::RocksdbError::DbNotOpen)
+        ));
+
+        secondary.close();
+        primary.destroy().unwrap();
+    }
 }
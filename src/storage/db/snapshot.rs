@@ -1,33 +1,65 @@
+use crate::mempool::routing::ShardRouter;
 use crate::proto::FarcasterNetwork;
+use crate::storage::store::relayout::{relayout, RelayoutError, RelayoutSummary};
+use crate::storage::store::self_check;
+use crate::storage::store::stores::{StoreLimits, Stores};
+use crate::storage::store::BlockStore;
+use crate::storage::trie::errors::TrieError;
+use crate::storage::trie::merkle_trie::MerkleTrie;
+use crate::utils::decompression::{gunzip_limited, DecompressionError};
 use crate::utils::statsd_wrapper::StatsdClientWrapper;
 use aws_config::Region;
 use aws_sdk_s3::config::http::HttpResponse;
 use aws_sdk_s3::config::Credentials;
-use aws_sdk_s3::error::{BuildError, DisplayErrorContext, SdkError};
+use aws_sdk_s3::error::{BuildError, DisplayErrorContext, ProvideErrorMetadata, SdkError};
 use aws_sdk_s3::operation::abort_multipart_upload::AbortMultipartUploadError;
 use aws_sdk_s3::operation::complete_multipart_upload::CompleteMultipartUploadError;
 use aws_sdk_s3::operation::create_multipart_upload::CreateMultipartUploadError;
 use aws_sdk_s3::operation::delete_objects::DeleteObjectsError;
+use aws_sdk_s3::operation::get_object::GetObjectError;
 use aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Error;
 use aws_sdk_s3::operation::put_object::PutObjectError;
 use aws_sdk_s3::operation::upload_part::UploadPartError;
 use aws_sdk_s3::primitives::{ByteStream, ByteStreamError};
-use aws_sdk_s3::types::{Delete, ObjectIdentifier};
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart, Delete, ObjectIdentifier};
 use aws_sdk_s3::Client;
-use flate2::read::GzDecoder;
+use bytes::Bytes;
 use futures_util::StreamExt;
 use itertools::Itertools;
+use rand::Rng;
 use serde::{Deserialize, Serialize};
-use std::io::{self, BufReader, Read};
-use std::time::{SystemTime, SystemTimeError, UNIX_EPOCH};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::io::{self, BufReader};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, SystemTimeError, UNIX_EPOCH};
 use tar::Archive;
 use thiserror::Error;
 use tokio::io::AsyncReadExt;
 use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc;
 
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
-use super::RocksdbError;
+use super::upload_throttle::{ThrottledBody, UploadThrottle};
+use super::{RocksDB, RocksdbError};
+
+// Limits of the S3 api, which S3 compatible backends share. Every part but the last has to be at
+// least the minimum size.
+const MIN_MULTIPART_PART_SIZE: u64 = 5 * 1024 * 1024;
+const MAX_MULTIPART_PART_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+const MAX_PUT_OBJECT_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+
+// Snapshots are split into chunks of at most this size, see RocksDB::create_tar_gzip
+const SNAPSHOT_CHUNK_SIZE: u64 = 100 * 1024 * 1024;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ShardSchedule {
+    pub shard_id: u32,
+    // Cron schedule for uploading this shard's snapshot, or empty to never snapshot it
+    pub schedule: String,
+}
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Config {
@@ -41,6 +73,37 @@ pub struct Config {
     pub snapshot_download_dir: String,
     pub aws_access_key_id: String,
     pub aws_secret_access_key: String,
+    // Retry policy for individual S3 operations. Only transient errors (5xx, timeouts,
+    // throttling) are retried, waiting base_backoff * 2^(attempt - 1) plus up to
+    // retry_jitter between attempts.
+    pub retry_max_attempts: u32,
+    #[serde(with = "humantime_serde")]
+    pub retry_base_backoff: Duration,
+    #[serde(with = "humantime_serde")]
+    pub retry_jitter: Duration,
+    // Caps the bandwidth used for uploading snapshot chunks. Unthrottled when unset.
+    pub upload_bytes_per_sec: Option<u64>,
+    // Cron schedule for snapshot uploads. Shards (including the block shard, 0) listed in
+    // shard_schedules use their own schedule instead.
+    pub upload_schedule: String,
+    pub shard_schedules: Vec<ShardSchedule>,
+    // Chunks larger than multipart_threshold_bytes are uploaded in parts of
+    // multipart_part_size_bytes, smaller ones with a single put. Larger parts mean fewer
+    // requests, smaller parts mean less to resend when one fails. No chunk is larger than the
+    // default threshold, so every chunk is a single put unless it's lowered.
+    pub multipart_threshold_bytes: u64,
+    pub multipart_part_size_bytes: u64,
+    // Restoring fails on a downloaded chunk that decompresses past this, rather than filling the
+    // disk. Chunks are cut at 100mb before compression.
+    pub max_decompressed_chunk_bytes: u64,
+    // Uploads each chunk as soon as it's compressed, from a checkpoint of the db, instead of
+    // staging a full backup and its archive under backup_dir first. The checkpoint hard links the
+    // db's files, so backup_dir has to be on the same filesystem as the db to save any space.
+    pub stream_upload: bool,
+    // Also uploads every shard's snapshot each time the block shard's height crosses a multiple
+    // of this, on top of the cron schedules, so snapshots line up with known heights. 0 turns it
+    // off.
+    pub snapshot_every_n_blocks: u64,
 }
 
 impl Default for Config {
@@ -57,14 +120,65 @@ impl Default for Config {
                 .to_string(),
             aws_access_key_id: "".to_string(),
             aws_secret_access_key: "".to_string(),
+            retry_max_attempts: 5,
+            retry_base_backoff: Duration::from_millis(500),
+            retry_jitter: Duration::from_millis(250),
+            upload_bytes_per_sec: None,
+            upload_schedule: "0 0 5 * * *".to_string(), // 5 AM UTC every day
+            shard_schedules: vec![],
+            multipart_threshold_bytes: SNAPSHOT_CHUNK_SIZE,
+            multipart_part_size_bytes: SNAPSHOT_CHUNK_SIZE,
+            max_decompressed_chunk_bytes: 2 * SNAPSHOT_CHUNK_SIZE,
+            stream_upload: false,
+            snapshot_every_n_blocks: 0,
         }
     }
 }
 
 impl Config {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.multipart_part_size_bytes < MIN_MULTIPART_PART_SIZE
+            || self.multipart_part_size_bytes > MAX_MULTIPART_PART_SIZE
+        {
+            return Err(format!(
+                "multipart_part_size_bytes must be between {} and {}: {}",
+                MIN_MULTIPART_PART_SIZE, MAX_MULTIPART_PART_SIZE, self.multipart_part_size_bytes
+            ));
+        }
+        // Anything up to the threshold is uploaded with a single put
+        if self.multipart_threshold_bytes > MAX_PUT_OBJECT_SIZE {
+            return Err(format!(
+                "multipart_threshold_bytes must be at most {}: {}",
+                MAX_PUT_OBJECT_SIZE, self.multipart_threshold_bytes
+            ));
+        }
+        if self.max_decompressed_chunk_bytes < SNAPSHOT_CHUNK_SIZE {
+            return Err(format!(
+                "max_decompressed_chunk_bytes must be at least {}: {}",
+                SNAPSHOT_CHUNK_SIZE, self.max_decompressed_chunk_bytes
+            ));
+        }
+        Ok(())
+    }
+
     pub fn snapshot_upload_enabled(&self) -> bool {
         !self.aws_access_key_id.is_empty() && !self.aws_secret_access_key.is_empty()
     }
+
+    // None if the shard is excluded from scheduled snapshots
+    pub fn schedule_for_shard(&self, shard_id: u32) -> Option<&str> {
+        let schedule = self
+            .shard_schedules
+            .iter()
+            .find(|shard_schedule| shard_schedule.shard_id == shard_id)
+            .map(|shard_schedule| shard_schedule.schedule.as_str())
+            .unwrap_or(self.upload_schedule.as_str());
+        if schedule.is_empty() {
+            None
+        } else {
+            Some(schedule)
+        }
+    }
 }
 
 fn snapshot_directory(network: FarcasterNetwork, shard_id: u32) -> String {
@@ -103,6 +217,9 @@ pub enum SnapshotError {
     #[error(transparent)]
     ListObjectsError(#[from] SdkError<ListObjectsV2Error, HttpResponse>),
 
+    #[error(transparent)]
+    GetObjectError(#[from] SdkError<GetObjectError, HttpResponse>),
+
     #[error(transparent)]
     BuildError(#[from] BuildError),
 
@@ -124,15 +241,230 @@ pub enum SnapshotError {
     #[error("upload already in progress")]
     UploadAlreadyInProgress,
 
+    #[error("no snapshot to download for shard {0}")]
+    NoSnapshot(u32),
+
+    #[error("no snapshot found at {0}")]
+    UnknownSnapshot(String),
+
+    #[error("no upload id returned for multipart upload of {0}")]
+    MissingUploadId(String),
+
+    #[error("snapshot format version {version} is newer than this binary supports (up to {supported}), upgrade the node to restore it")]
+    SnapshotVersionTooNew { version: u32, supported: u32 },
+
+    #[error("snapshot format version {0} can no longer be restored by this binary, restore from a newer snapshot")]
+    SnapshotVersionUnsupported(u32),
+
+    #[error("snapshot chunk {chunk} decompresses past the limit of {limit} bytes")]
+    ChunkTooLarge { chunk: String, limit: u64 },
+
+    #[error("snapshot chunk {chunk} has sha256 {actual}, expected {expected}")]
+    ChecksumMismatch {
+        chunk: String,
+        expected: String,
+        actual: String,
+    },
+
     #[error(transparent)]
     RocksDbError(#[from] RocksdbError),
+
+    #[error(transparent)]
+    TrieError(#[from] TrieError),
+
+    #[error(transparent)]
+    RelayoutError(#[from] RelayoutError),
 }
 
-#[derive(Serialize, Deserialize)]
-struct SnapshotMetadata {
-    key_base: String,
-    chunks: Vec<String>,
-    timestamp: i64,
+// Auth failures, missing buckets and malformed requests won't succeed on retry, so only
+// transient failures are considered retryable.
+fn is_retryable<E: ProvideErrorMetadata>(err: &SdkError<E, HttpResponse>) -> bool {
+    match err {
+        SdkError::TimeoutError(_) => true,
+        SdkError::DispatchFailure(failure) => failure.is_timeout() || failure.is_io(),
+        SdkError::ResponseError(_) => true,
+        SdkError::ServiceError(context) => {
+            let status = context.raw().status().as_u16();
+            status >= 500
+                || status == 429
+                || matches!(
+                    context.err().code(),
+                    Some("SlowDown" | "Throttling" | "ThrottlingException" | "RequestTimeout")
+                )
+        }
+        _ => false,
+    }
+}
+
+fn retry_backoff(snapshot_config: &Config, attempt: u32) -> Duration {
+    let exponent = attempt.saturating_sub(1).min(16);
+    let backoff = snapshot_config
+        .retry_base_backoff
+        .saturating_mul(1 << exponent);
+    let jitter_ms = snapshot_config.retry_jitter.as_millis() as u64;
+    let jitter = if jitter_ms > 0 {
+        Duration::from_millis(rand::thread_rng().gen_range(0..=jitter_ms))
+    } else {
+        Duration::ZERO
+    };
+    backoff + jitter
+}
+
+async fn with_retries<T, E, F, Fut>(
+    snapshot_config: &Config,
+    operation: &str,
+    key: &str,
+    mut f: F,
+) -> Result<T, SdkError<E, HttpResponse>>
+where
+    E: ProvideErrorMetadata + std::error::Error + 'static,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, SdkError<E, HttpResponse>>>,
+{
+    let max_attempts = snapshot_config.retry_max_attempts.max(1);
+    let mut attempt = 1;
+    loop {
+        match f().await {
+            Ok(result) => return Ok(result),
+            Err(err) if attempt < max_attempts && is_retryable(&err) => {
+                let backoff = retry_backoff(snapshot_config, attempt);
+                warn!(
+                    operation,
+                    key,
+                    attempt,
+                    max_attempts,
+                    backoff_ms = backoff.as_millis() as u64,
+                    "Retrying s3 operation after error: {}",
+                    DisplayErrorContext(&err)
+                );
+                tokio::time::sleep(backoff).await;
+                attempt += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotMetadata {
+    pub key_base: String,
+    pub chunks: Vec<String>,
+    pub timestamp: i64,
+    // Height when the backup started, the snapshot may contain a few more blocks. Not recorded
+    // by snapshots uploaded by older versions.
+    #[serde(default)]
+    pub block_height: Option<u64>,
+    #[serde(default)]
+    pub size_bytes: Option<u64>,
+    // Snapshots uploaded before the format was versioned are version 0
+    #[serde(default)]
+    pub format_version: u32,
+    // Hex sha256 of each chunk, in the same order, checked when the chunks are downloaded. Not
+    // recorded by snapshots uploaded by older versions.
+    #[serde(default)]
+    pub chunk_sha256: Vec<String>,
+    // The block shard height whose crossing triggered the upload, for snapshots uploaded every
+    // snapshot_every_n_blocks
+    #[serde(default)]
+    pub milestone_height: Option<u64>,
+}
+
+/// The format of the snapshots this binary uploads. Bump it whenever a change to the db layout
+/// or the chunk encoding means older binaries can't restore the snapshot, and add the new version
+/// to FORMAT_VERSIONS.
+pub const SNAPSHOT_FORMAT_VERSION: u32 = 1;
+
+pub struct FormatVersion {
+    pub version: u32,
+    // Whether this binary can still restore snapshots of this version
+    pub restorable: bool,
+    pub description: &'static str,
+}
+
+/// Every snapshot format version up to SNAPSHOT_FORMAT_VERSION and whether this binary restores it
+pub const FORMAT_VERSIONS: &[FormatVersion] = &[
+    FormatVersion {
+        version: 0,
+        restorable: true,
+        description: "unversioned, same layout as version 1",
+    },
+    FormatVersion {
+        version: 1,
+        restorable: true,
+        description: "gzipped tar of a rocksdb backup, split into chunks",
+    },
+];
+
+fn check_format_version_against(
+    version: u32,
+    supported: u32,
+    versions: &[FormatVersion],
+) -> Result<(), SnapshotError> {
+    if version > supported {
+        return Err(SnapshotError::SnapshotVersionTooNew { version, supported });
+    }
+    match versions.iter().find(|v| v.version == version) {
+        Some(v) if v.restorable => Ok(()),
+        _ => Err(SnapshotError::SnapshotVersionUnsupported(version)),
+    }
+}
+
+/// Checked before anything is downloaded, so a node that's too old fails straight away
+pub fn check_format_version(metadata: &SnapshotMetadata) -> Result<(), SnapshotError> {
+    check_format_version_against(
+        metadata.format_version,
+        SNAPSHOT_FORMAT_VERSION,
+        FORMAT_VERSIONS,
+    )
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    // Start time of the upload run, shared by all shards uploaded in that run
+    pub timestamp: i64,
+    pub shards: BTreeMap<u32, SnapshotMetadata>,
+}
+
+/// Records which shards each snapshot contains. Shards can be uploaded on different schedules,
+/// so a node is restored from the latest snapshot of each shard, which may come from different
+/// runs. Only the latest snapshot of a shard is kept, since older ones are deleted after upload.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotManifest {
+    pub snapshots: Vec<ManifestEntry>,
+}
+
+impl SnapshotManifest {
+    pub fn record(&mut self, timestamp: i64, shard_id: u32, metadata: SnapshotMetadata) {
+        for entry in self.snapshots.iter_mut() {
+            if entry.timestamp != timestamp {
+                entry.shards.remove(&shard_id);
+            }
+        }
+
+        match self
+            .snapshots
+            .iter_mut()
+            .find(|entry| entry.timestamp == timestamp)
+        {
+            Some(entry) => {
+                entry.shards.insert(shard_id, metadata);
+            }
+            None => self.snapshots.push(ManifestEntry {
+                timestamp,
+                shards: BTreeMap::from([(shard_id, metadata)]),
+            }),
+        }
+
+        self.snapshots.retain(|entry| !entry.shards.is_empty());
+        self.snapshots.sort_by_key(|entry| entry.timestamp);
+    }
+
+    pub fn latest_for_shard(&self, shard_id: u32) -> Option<&SnapshotMetadata> {
+        self.snapshots
+            .iter()
+            .rev()
+            .find_map(|entry| entry.shards.get(&shard_id))
+    }
 }
 
 async fn create_s3_client(snapshot_config: &Config) -> aws_sdk_s3::Client {
@@ -158,12 +490,14 @@ async fn get_objects_under_key(
     snapshot_config: &Config,
     prefix: String,
 ) -> Result<Vec<ObjectIdentifier>, SnapshotError> {
-    let objects = s3_client
-        .list_objects_v2()
-        .bucket(snapshot_config.s3_bucket.clone())
-        .prefix(prefix)
-        .send()
-        .await?;
+    let objects = with_retries(snapshot_config, "list_objects", &prefix, || {
+        s3_client
+            .list_objects_v2()
+            .bucket(snapshot_config.s3_bucket.clone())
+            .prefix(prefix.clone())
+            .send()
+    })
+    .await?;
     if let Some(contents) = objects.contents {
         Ok(contents
             .into_iter()
@@ -185,7 +519,10 @@ pub async fn clear_old_snapshots(
     let s3_client = create_s3_client(&snapshot_config).await;
     let snapshot_dir = snapshot_directory(network, shard_id);
     let objects = get_objects_under_key(&s3_client, &snapshot_config, snapshot_dir.clone()).await?;
-    let metadata = download_metadata(network, shard_id, snapshot_config).await?;
+    // Without the latest snapshot's metadata, there's no telling which objects are old
+    let metadata = download_metadata(network, shard_id, snapshot_config)
+        .await?
+        .ok_or(SnapshotError::NoSnapshot(shard_id))?;
     let old_objects = objects
         .into_iter()
         .filter(|object| {
@@ -202,12 +539,14 @@ pub async fn clear_old_snapshots(
             snapshot_dir.clone()
         );
         let delete_request = Delete::builder().set_objects(Some(old_objects)).build()?;
-        let delete_result = s3_client
-            .delete_objects()
-            .bucket(snapshot_config.s3_bucket.clone())
-            .delete(delete_request)
-            .send()
-            .await;
+        let delete_result = with_retries(snapshot_config, "delete_objects", &snapshot_dir, || {
+            s3_client
+                .delete_objects()
+                .bucket(snapshot_config.s3_bucket.clone())
+                .delete(delete_request.clone())
+                .send()
+        })
+        .await;
         if let Err(err) = &delete_result {
             info!(
                 "Error clearing snapshot from s3: {}, bucket: {}",
@@ -228,22 +567,155 @@ fn metadata_path(network: FarcasterNetwork, shard_id: u32) -> String {
     )
 }
 
+fn manifest_path(network: FarcasterNetwork) -> String {
+    format!("{}/{}", network.as_str_name(), "manifest.json")
+}
+
+// Reads the manifest directly from the bucket rather than the download url, which may serve a
+// cached copy
+async fn fetch_manifest(
+    s3_client: &Client,
+    snapshot_config: &Config,
+    network: FarcasterNetwork,
+) -> Result<SnapshotManifest, SnapshotError> {
+    let key = manifest_path(network);
+    let result = with_retries(snapshot_config, "get_object", &key, || {
+        s3_client
+            .get_object()
+            .bucket(snapshot_config.s3_bucket.clone())
+            .key(key.clone())
+            .send()
+    })
+    .await;
+
+    match result {
+        Ok(output) => {
+            let bytes = output.body.collect().await?.into_bytes();
+            Ok(serde_json::from_slice(&bytes)?)
+        }
+        Err(err)
+            if err
+                .as_service_error()
+                .map_or(false, |err| err.is_no_such_key()) =>
+        {
+            Ok(SnapshotManifest::default())
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+pub async fn record_in_manifest(
+    network: FarcasterNetwork,
+    snapshot_config: &Config,
+    timestamp: i64,
+    shard_id: u32,
+    metadata: SnapshotMetadata,
+) -> Result<(), SnapshotError> {
+    let s3_client = create_s3_client(&snapshot_config).await;
+    let mut manifest = fetch_manifest(&s3_client, snapshot_config, network).await?;
+    manifest.record(timestamp, shard_id, metadata);
+
+    let manifest_json = serde_json::to_string(&manifest)?;
+    let key = manifest_path(network);
+    with_retries(snapshot_config, "put_object", &key, || {
+        s3_client
+            .put_object()
+            .bucket(snapshot_config.s3_bucket.clone())
+            .key(key.clone())
+            .body(ByteStream::from(manifest_json.as_bytes().to_vec()))
+            .content_type("application/json")
+            .send()
+    })
+    .await?;
+    Ok(())
+}
+
+// Snapshots uploaded before the manifest was introduced don't have one
+async fn download_manifest(
+    network: FarcasterNetwork,
+    snapshot_config: &Config,
+) -> Result<Option<SnapshotManifest>, SnapshotError> {
+    let manifest_url = format!(
+        "{}/{}",
+        snapshot_config.snapshot_download_url,
+        manifest_path(network)
+    );
+    info!("Retrieving manifest from {}", manifest_url);
+    let response = reqwest::get(manifest_url).await?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    Ok(Some(
+        response
+            .error_for_status()?
+            .json::<SnapshotManifest>()
+            .await?,
+    ))
+}
+
 async fn download_metadata(
     network: FarcasterNetwork,
     shard_id: u32,
     snapshot_config: &Config,
-) -> Result<SnapshotMetadata, SnapshotError> {
+) -> Result<Option<SnapshotMetadata>, SnapshotError> {
     let metadata_url = format!(
         "{}/{}",
         snapshot_config.snapshot_download_url,
         metadata_path(network, shard_id)
     );
     info!("Retrieving metadata from {}", metadata_url);
-    let metadata = reqwest::get(metadata_url)
-        .await?
-        .json::<SnapshotMetadata>()
-        .await?;
-    Ok(metadata)
+    let response = reqwest::get(metadata_url).await?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    Ok(Some(
+        response
+            .error_for_status()?
+            .json::<SnapshotMetadata>()
+            .await?,
+    ))
+}
+
+/// Lists the snapshots available for download. Without a manifest, falls back to the latest
+/// snapshot of each of the given shards.
+pub async fn list_snapshots(
+    network: FarcasterNetwork,
+    snapshot_config: &Config,
+    shard_ids: &[u32],
+) -> Result<SnapshotManifest, SnapshotError> {
+    if let Some(manifest) = download_manifest(network, snapshot_config).await? {
+        return Ok(manifest);
+    }
+
+    let mut manifest = SnapshotManifest::default();
+    for shard_id in shard_ids {
+        match download_metadata(network, *shard_id, snapshot_config).await {
+            Ok(Some(metadata)) => manifest.record(metadata.timestamp, *shard_id, metadata),
+            Ok(None) => {}
+            Err(err) => warn!(
+                shard_id,
+                "Unable to retrieve snapshot metadata: {}",
+                err.to_string()
+            ),
+        }
+    }
+    Ok(manifest)
+}
+
+/// The latest snapshot of the shard, or None if it was never snapshotted.
+pub async fn latest_snapshot(
+    network: FarcasterNetwork,
+    snapshot_config: &Config,
+    shard_id: u32,
+) -> Result<Option<SnapshotMetadata>, SnapshotError> {
+    let manifest = download_manifest(network, snapshot_config).await?;
+    match manifest
+        .as_ref()
+        .and_then(|manifest| manifest.latest_for_shard(shard_id))
+    {
+        Some(metadata) => Ok(Some(metadata.clone())),
+        None => download_metadata(network, shard_id, snapshot_config).await,
+    }
 }
 
 pub async fn download_snapshots(
@@ -251,15 +723,325 @@ pub async fn download_snapshots(
     snapshot_config: &Config,
     db_dir: String,
     shard_id: u32,
+) -> Result<(), SnapshotError> {
+    let metadata = latest_snapshot(network, snapshot_config, shard_id)
+        .await?
+        .ok_or(SnapshotError::NoSnapshot(shard_id))?;
+    check_format_version(&metadata)?;
+    restore_snapshot(snapshot_config, metadata, &db_dir).await
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BootstrapOutcome {
+    Restored(SnapshotMetadata),
+    // The shard syncs from its peers from scratch
+    NoSnapshot,
+    // A previous run already restored or synced the shard, it resumes syncing from its height
+    ExistingData,
+}
+
+/// Restores the latest snapshot of a shard that has no data yet, so a new node starts syncing
+/// from the snapshot's height. A failed restore is removed, so running it again starts over
+/// rather than syncing from a partial db.
+pub async fn bootstrap_shard(
+    network: FarcasterNetwork,
+    snapshot_config: &Config,
+    db_dir: &str,
+    shard_id: u32,
+) -> Result<BootstrapOutcome, SnapshotError> {
+    let shard_dir = format!("{}/shard-{}", db_dir, shard_id);
+    if std::path::Path::new(&shard_dir).exists() && std::fs::read_dir(&shard_dir)?.next().is_some()
+    {
+        return Ok(BootstrapOutcome::ExistingData);
+    }
+
+    let Some(metadata) = latest_snapshot(network, snapshot_config, shard_id).await? else {
+        return Ok(BootstrapOutcome::NoSnapshot);
+    };
+    check_format_version(&metadata)?;
+    if let Err(err) = restore_snapshot(snapshot_config, metadata.clone(), db_dir).await {
+        if let Err(remove_err) = std::fs::remove_dir_all(&shard_dir) {
+            warn!(shard_id, "Unable to remove partial restore: {}", remove_err);
+        }
+        return Err(err);
+    }
+    Ok(BootstrapOutcome::Restored(metadata))
+}
+
+/// Restores the latest snapshots of a network with source_num_shards shards into the node's
+/// target shards, which may be more or fewer. Each source shard is restored to a staging directory
+/// under rocksdb_dir, then its fids are moved to the target shard ShardRouter assigns them, see
+/// relayout. The target shards have to be empty, and a failed relayout leaves them partially
+/// written, so clear them before trying again. The block shard isn't included.
+pub async fn restore_relayout(
+    network: FarcasterNetwork,
+    snapshot_config: &Config,
+    storage_config: &super::Config,
+    rocksdb_dir: &str,
+    source_num_shards: u32,
+    target_shard_ids: &[u32],
+    trie_branching_factor: u32,
+) -> Result<RelayoutSummary, SnapshotError> {
+    let staging_dir = format!("{}/relayout-staging", rocksdb_dir);
+    for shard_id in 1..=source_num_shards {
+        let metadata = latest_snapshot(network, snapshot_config, shard_id)
+            .await?
+            .ok_or(SnapshotError::NoSnapshot(shard_id))?;
+        check_format_version(&metadata)?;
+        info!(
+            shard_id,
+            "Restoring snapshot {} to relayout", metadata.key_base
+        );
+        restore_snapshot(snapshot_config, metadata, &staging_dir).await?;
+    }
+
+    // Nothing is reported from the relayout
+    let statsd = nop_statsd();
+    let open_stores = |db_dir: &str, shard_id: u32| -> Result<Stores, SnapshotError> {
+        let db = RocksDB::try_open_shard_db(
+            db_dir,
+            shard_id,
+            &storage_config.network_namespace,
+            storage_config.shard_write_durability(shard_id),
+            storage_config.compression,
+        )?;
+        Ok(Stores::new(
+            db,
+            shard_id,
+            MerkleTrie::new(trie_branching_factor)?,
+            StoreLimits::default(),
+            statsd.clone(),
+        ))
+    };
+    let sources = (1..=source_num_shards)
+        .map(|shard_id| open_stores(&staging_dir, shard_id))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut targets = target_shard_ids
+        .iter()
+        .map(|shard_id| {
+            let db_dir = storage_config.shard_base_dir(rocksdb_dir, *shard_id);
+            Ok((*shard_id, open_stores(&db_dir, *shard_id)?))
+        })
+        .collect::<Result<std::collections::HashMap<_, _>, SnapshotError>>()?;
+
+    let summary = relayout(&sources, &mut targets, &ShardRouter {})?;
+
+    for stores in sources.iter().chain(targets.values()) {
+        stores.db.close();
+    }
+    std::fs::remove_dir_all(staging_dir)?;
+    Ok(summary)
+}
+
+/// How one shard of a snapshot fared in validate_snapshot. Serialized as the snapshot_tool's
+/// output, so keep the field names stable.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ShardValidation {
+    pub shard_id: u32,
+    pub key_base: String,
+    pub passed: bool,
+    // Recorded in the snapshot's metadata, the restored tip may be a few blocks past it
+    pub block_height: Option<u64>,
+    pub tip_height: Option<u64>,
+    // Not counted for the block shard, which has no trie
+    pub trie_leaf_count: Option<u64>,
+    pub store_count: Option<u64>,
+    // Why the shard failed
+    pub error: Option<String>,
+}
+
+fn nop_statsd() -> StatsdClientWrapper {
+    StatsdClientWrapper::new(
+        cadence::StatsdClient::builder("", cadence::NopMetricSink {}).build(),
+        false,
+    )
+}
+
+fn check_restored_shard(
+    db: &Arc<RocksDB>,
+    shard_id: u32,
+    trie_branching_factor: u32,
+    validation: &mut ShardValidation,
+) -> Result<(), String> {
+    if shard_id == 0 {
+        let block_store = BlockStore::new(db.clone());
+        let tip = block_store
+            .get_last_block()
+            .map_err(|err| err.to_string())?
+            .and_then(|block| block.header)
+            .and_then(|header| header.height)
+            .map(|height| height.block_number);
+        if let Some(tip) = tip {
+            let min = block_store
+                .min_block_number()
+                .map_err(|err| err.to_string())?;
+            if tip > min
+                && block_store
+                    .get_block_by_height(tip - 1)
+                    .map_err(|err| err.to_string())?
+                    .is_none()
+            {
+                return Err(format!(
+                    "height {} is missing below the tip at {}",
+                    tip - 1,
+                    tip
+                ));
+            }
+        }
+        validation.tip_height = tip;
+    } else {
+        let report = self_check::check_shard(db, shard_id, trie_branching_factor, false)
+            .map_err(|err| err.to_string())?;
+        validation.tip_height = report.tip_height;
+        let stores = Stores::new(
+            db.clone(),
+            shard_id,
+            MerkleTrie::new(trie_branching_factor).map_err(|err| err.to_string())?,
+            StoreLimits::default(),
+            nop_statsd(),
+        );
+        let trie_leaf_count = stores.get_trie_leaf_count();
+        let store_count = stores.get_store_count().map_err(|err| err.to_string())?;
+        validation.trie_leaf_count = Some(trie_leaf_count);
+        validation.store_count = Some(store_count);
+        if trie_leaf_count != store_count {
+            return Err(format!(
+                "trie has {} leaves, the stores have {} entries",
+                trie_leaf_count, store_count
+            ));
+        }
+    }
+
+    if let Some(recorded) = validation.block_height {
+        let tip = validation.tip_height.unwrap_or(0);
+        if tip < recorded {
+            return Err(format!(
+                "tip at {} is below the recorded height {}",
+                tip, recorded
+            ));
+        }
+    }
+    Ok(())
+}
+
+async fn validate_shard(
+    snapshot_config: &Config,
+    storage_config: &super::Config,
+    work_dir: &str,
+    shard_id: u32,
+    metadata: &SnapshotMetadata,
+    trie_branching_factor: u32,
+    validation: &mut ShardValidation,
+) -> Result<(), String> {
+    check_format_version(metadata).map_err(|err| err.to_string())?;
+    let download_config = Config {
+        snapshot_download_dir: format!("{}/download-{}", work_dir, shard_id),
+        ..snapshot_config.clone()
+    };
+    let db_dir = format!("{}/db", work_dir);
+    restore_snapshot(&download_config, metadata.clone(), &db_dir)
+        .await
+        .map_err(|err| err.to_string())?;
+
+    let db = RocksDB::try_open_shard_db(
+        &db_dir,
+        shard_id,
+        &storage_config.network_namespace,
+        storage_config.shard_write_durability(shard_id),
+        storage_config.compression,
+    )
+    .map_err(|err| err.to_string())?;
+    let result = check_restored_shard(&db, shard_id, trie_branching_factor, validation);
+    db.close();
+    // Only one shard is on disk at a time
+    if let Err(err) = std::fs::remove_dir_all(format!("{}/shard-{}", db_dir, shard_id)) {
+        warn!(shard_id, "Unable to remove the restored shard: {}", err);
+    }
+    result
+}
+
+/// Checks every shard of a snapshot before it's trusted for a restore, without touching a node.
+/// The snapshot is the one whose shards include the one uploaded at key_base, as printed by
+/// list_snapshots. Each shard is downloaded with its checksums verified, restored under a
+/// temporary directory in parent_dir, or the system's, and the startup self-check is run on its
+/// tip, along with the trie leaf count the CheckShardConsistency admin rpc compares. The restored
+/// dbs are only read, and removed once checked. A shard that fails doesn't stop the others from
+/// being checked.
+pub async fn validate_snapshot(
+    network: FarcasterNetwork,
+    snapshot_config: &Config,
+    storage_config: &super::Config,
+    key_base: &str,
+    shard_ids: &[u32],
+    trie_branching_factor: u32,
+    parent_dir: Option<&str>,
+) -> Result<Vec<ShardValidation>, SnapshotError> {
+    let key_base = key_base.trim_matches('/');
+    let manifest = list_snapshots(network, snapshot_config, shard_ids).await?;
+    let entry = manifest
+        .snapshots
+        .into_iter()
+        .find(|entry| {
+            entry
+                .shards
+                .values()
+                .any(|metadata| metadata.key_base.trim_matches('/') == key_base)
+        })
+        .ok_or_else(|| SnapshotError::UnknownSnapshot(key_base.to_string()))?;
+
+    let work_dir = match parent_dir {
+        Some(parent_dir) => tempfile::TempDir::new_in(parent_dir)?,
+        None => tempfile::TempDir::new()?,
+    };
+    let work_dir_path = work_dir
+        .path()
+        .to_str()
+        .ok_or(SnapshotError::UnableToParseFileName)?;
+
+    let mut validations = vec![];
+    for (shard_id, metadata) in &entry.shards {
+        info!(shard_id, "Validating snapshot {}", metadata.key_base);
+        let mut validation = ShardValidation {
+            shard_id: *shard_id,
+            key_base: metadata.key_base.clone(),
+            block_height: metadata.block_height,
+            ..ShardValidation::default()
+        };
+        let result = validate_shard(
+            snapshot_config,
+            storage_config,
+            work_dir_path,
+            *shard_id,
+            metadata,
+            trie_branching_factor,
+            &mut validation,
+        )
+        .await;
+        match result {
+            Ok(()) => validation.passed = true,
+            Err(err) => {
+                warn!(shard_id, "Snapshot shard failed validation: {}", err);
+                validation.error = Some(err);
+            }
+        }
+        validations.push(validation);
+    }
+    Ok(validations)
+}
+
+async fn restore_snapshot(
+    snapshot_config: &Config,
+    metadata: SnapshotMetadata,
+    db_dir: &str,
 ) -> Result<(), SnapshotError> {
     let snapshot_dir = snapshot_config.snapshot_download_dir.clone();
     std::fs::create_dir_all(snapshot_dir.clone())?;
 
-    let metadata_json = download_metadata(network, shard_id, snapshot_config).await?;
+    let metadata_json = metadata;
     let base_path = metadata_json.key_base;
 
     let mut local_chunks = vec![];
-    for chunk in metadata_json.chunks {
+    for (index, chunk) in metadata_json.chunks.into_iter().enumerate() {
         info!("Downloading zipped snapshot chunk {}", chunk);
         let download_path = format!(
             "{}/{}/{}",
@@ -271,24 +1053,63 @@ pub async fn download_snapshots(
         let download_response = reqwest::get(download_path).await?;
         let mut byte_stream = download_response.bytes_stream();
 
+        let mut hasher = Sha256::new();
         while let Some(piece) = byte_stream.next().await {
-            file.write_all(&piece?).await?;
+            let piece = piece?;
+            hasher.update(&piece);
+            file.write_all(&piece).await?;
         }
         file.flush().await?;
+        if let Some(expected) = metadata_json.chunk_sha256.get(index) {
+            let actual = hex::encode(hasher.finalize());
+            if &actual != expected {
+                return Err(SnapshotError::ChecksumMismatch {
+                    chunk,
+                    expected: expected.clone(),
+                    actual,
+                });
+            }
+        }
         local_chunks.push(filename);
     }
 
+    unpack_snapshot_chunks(
+        local_chunks,
+        &snapshot_dir,
+        db_dir,
+        snapshot_config.max_decompressed_chunk_bytes,
+    )
+    .await?;
+
+    std::fs::remove_dir_all(snapshot_dir)?;
+    Ok(())
+}
+
+async fn unpack_snapshot_chunks(
+    local_chunks: Vec<String>,
+    snapshot_dir: &str,
+    db_dir: &str,
+    max_decompressed_chunk_bytes: u64,
+) -> Result<(), SnapshotError> {
     let tar_filename = format!("{}/snapshot.tar", snapshot_dir);
     let mut tar_file = BufWriter::new(tokio::fs::File::create(tar_filename.clone()).await?);
 
     for filename in local_chunks {
         info!("Unzipping snapshot chunk {}", filename);
-        let file = std::fs::File::open(filename)?;
+        let file = std::fs::File::open(&filename)?;
         let reader = BufReader::new(file);
-        let mut gz_decoder = GzDecoder::new(reader);
         let mut buffer = Vec::new();
-        // These files are small, 100MB max each
-        gz_decoder.read_to_end(&mut buffer)?;
+        // These files are small, 100MB max each, unless they were crafted to expand
+        match gunzip_limited(reader, &mut buffer, max_decompressed_chunk_bytes) {
+            Ok(_) => {}
+            Err(DecompressionError::LimitExceeded(limit)) => {
+                return Err(SnapshotError::ChunkTooLarge {
+                    chunk: filename,
+                    limit,
+                })
+            }
+            Err(DecompressionError::Io(err)) => return Err(err.into()),
+        }
         tar_file.write_all(&buffer).await?;
     }
     tar_file.flush().await?;
@@ -297,84 +1118,1069 @@ pub async fn download_snapshots(
     info!("Unpacking snapshot file {}", tar_filename);
     let mut archive = Archive::new(file);
     archive.unpack(&db_dir)?;
+    Ok(())
+}
 
-    std::fs::remove_dir_all(snapshot_dir)?;
+fn upload_body(throttle: &Option<Arc<UploadThrottle>>, bytes: Bytes) -> (ByteStream, Option<i64>) {
+    match throttle {
+        Some(throttle) => {
+            let content_length = bytes.len() as i64;
+            (
+                ByteStream::from_body_1_x(ThrottledBody::new(throttle.clone(), bytes)),
+                Some(content_length),
+            )
+        }
+        None => (ByteStream::from(bytes), None),
+    }
+}
+
+async fn upload_parts(
+    s3_client: &Client,
+    snapshot_config: &Config,
+    key: &str,
+    upload_id: &str,
+    buffer: &Bytes,
+    throttle: &Option<Arc<UploadThrottle>>,
+) -> Result<Vec<CompletedPart>, SnapshotError> {
+    let part_size = snapshot_config.multipart_part_size_bytes.max(1) as usize;
+    let mut parts = vec![];
+    for (index, start) in (0..buffer.len()).step_by(part_size).enumerate() {
+        // Part numbers start at 1
+        let part_number = index as i32 + 1;
+        let part = buffer.slice(start..(start + part_size).min(buffer.len()));
+        let output = with_retries(snapshot_config, "upload_part", key, || {
+            let (body, content_length) = upload_body(throttle, part.clone());
+            s3_client
+                .upload_part()
+                .bucket(snapshot_config.s3_bucket.clone())
+                .key(key)
+                .upload_id(upload_id)
+                .part_number(part_number)
+                .body(body)
+                .set_content_length(content_length)
+                .send()
+        })
+        .await?;
+        parts.push(
+            CompletedPart::builder()
+                .part_number(part_number)
+                .set_e_tag(output.e_tag)
+                .build(),
+        );
+    }
+    Ok(parts)
+}
+
+async fn upload_multipart(
+    s3_client: &Client,
+    snapshot_config: &Config,
+    key: &str,
+    buffer: &Bytes,
+    throttle: &Option<Arc<UploadThrottle>>,
+) -> Result<(), SnapshotError> {
+    let output = with_retries(snapshot_config, "create_multipart_upload", key, || {
+        s3_client
+            .create_multipart_upload()
+            .bucket(snapshot_config.s3_bucket.clone())
+            .key(key)
+            .send()
+    })
+    .await?;
+    let upload_id = output
+        .upload_id
+        .ok_or_else(|| SnapshotError::MissingUploadId(key.to_string()))?;
+
+    let parts = match upload_parts(
+        s3_client,
+        snapshot_config,
+        key,
+        &upload_id,
+        buffer,
+        throttle,
+    )
+    .await
+    {
+        Ok(parts) => parts,
+        Err(err) => {
+            // Otherwise the bucket is billed for the parts until they expire
+            if let Err(abort_err) = s3_client
+                .abort_multipart_upload()
+                .bucket(snapshot_config.s3_bucket.clone())
+                .key(key)
+                .upload_id(&upload_id)
+                .send()
+                .await
+            {
+                warn!(
+                    key,
+                    "Unable to abort multipart upload: {}",
+                    DisplayErrorContext(&abort_err)
+                );
+            }
+            return Err(err);
+        }
+    };
+
+    with_retries(snapshot_config, "complete_multipart_upload", key, || {
+        s3_client
+            .complete_multipart_upload()
+            .bucket(snapshot_config.s3_bucket.clone())
+            .key(key)
+            .upload_id(&upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts.clone()))
+                    .build(),
+            )
+            .send()
+    })
+    .await?;
     Ok(())
 }
 
+// Uploads the chunks of one snapshot under a common key, then its metadata
+struct SnapshotUpload {
+    s3_client: Client,
+    shard_id: u32,
+    upload_dir: String,
+    start_timestamp: i64,
+    throttle: Option<Arc<UploadThrottle>>,
+    chunks: Vec<String>,
+    chunk_sha256: Vec<String>,
+    size_bytes: u64,
+}
+
+impl SnapshotUpload {
+    async fn start(
+        network: FarcasterNetwork,
+        snapshot_config: &Config,
+        shard_id: u32,
+    ) -> Result<Self, SnapshotError> {
+        let start_timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64;
+        let start_date = chrono::DateTime::from_timestamp_millis(start_timestamp)
+            .ok_or(SnapshotError::DateError)?
+            .date_naive();
+        let upload_dir = format!(
+            "{}/snapshot-{}-{}.tar.gz",
+            snapshot_directory(network, shard_id),
+            start_date,
+            start_timestamp / 1000
+        );
+        let throttle = snapshot_config.upload_bytes_per_sec.map(|bytes_per_sec| {
+            info!(shard_id, bytes_per_sec, "Throttling snapshot upload");
+            Arc::new(UploadThrottle::new(shard_id, bytes_per_sec))
+        });
+        Ok(SnapshotUpload {
+            s3_client: create_s3_client(snapshot_config).await,
+            shard_id,
+            upload_dir,
+            start_timestamp,
+            throttle,
+            chunks: vec![],
+            chunk_sha256: vec![],
+            size_bytes: 0,
+        })
+    }
+
+    async fn upload_chunk(
+        &mut self,
+        snapshot_config: &Config,
+        file_name: String,
+        buffer: Bytes,
+        statsd_client: &StatsdClientWrapper,
+    ) -> Result<(), SnapshotError> {
+        let key = format!("{}/{}", self.upload_dir, file_name);
+        if buffer.len() as u64 > snapshot_config.multipart_threshold_bytes {
+            upload_multipart(
+                &self.s3_client,
+                snapshot_config,
+                &key,
+                &buffer,
+                &self.throttle,
+            )
+            .await?;
+        } else {
+            with_retries(snapshot_config, "put_object", &key, || {
+                let (body, content_length) = upload_body(&self.throttle, buffer.clone());
+                self.s3_client
+                    .put_object()
+                    .bucket(snapshot_config.s3_bucket.clone())
+                    .key(key.clone())
+                    .body(body)
+                    .set_content_length(content_length)
+                    .send()
+            })
+            .await?;
+        }
+
+        info!(key, "Finished uploading snapshot to s3");
+        statsd_client.count_with_shard(self.shard_id, "snapshots.successful_upload", 1);
+
+        self.size_bytes += buffer.len() as u64;
+        self.chunk_sha256.push(sha256_hex(&buffer));
+        self.chunks.push(file_name);
+        Ok(())
+    }
+
+    async fn finish(
+        self,
+        network: FarcasterNetwork,
+        snapshot_config: &Config,
+        block_height: Option<u64>,
+        milestone_height: Option<u64>,
+    ) -> Result<SnapshotMetadata, SnapshotError> {
+        let metadata = SnapshotMetadata {
+            key_base: self.upload_dir,
+            chunks: self.chunks,
+            timestamp: self.start_timestamp,
+            block_height,
+            size_bytes: Some(self.size_bytes),
+            format_version: SNAPSHOT_FORMAT_VERSION,
+            chunk_sha256: self.chunk_sha256,
+            milestone_height,
+        };
+
+        let metadata_json = serde_json::to_string(&metadata)?;
+        let metadata_key = metadata_path(network, self.shard_id);
+        let upload_result = with_retries(snapshot_config, "put_object", &metadata_key, || {
+            self.s3_client
+                .put_object()
+                .bucket(snapshot_config.s3_bucket.clone())
+                .key(metadata_key.clone())
+                .body(ByteStream::from(metadata_json.as_bytes().to_vec()))
+                .content_type("application/json")
+                .send()
+        })
+        .await;
+
+        if let Err(err) = &upload_result {
+            error!(
+                "Error uploading metadata to s3: {}, key: {}, bucket: {}",
+                DisplayErrorContext(err),
+                metadata_key,
+                snapshot_config.s3_bucket
+            );
+        }
+        upload_result?;
+        Ok(metadata)
+    }
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+/// Uploads the chunks create_tar_gzip wrote to chunked_dir_path
 pub async fn upload_to_s3(
     network: FarcasterNetwork,
     chunked_dir_path: String,
     snapshot_config: &Config,
     shard_id: u32,
+    block_height: Option<u64>,
+    milestone_height: Option<u64>,
     statsd_client: &StatsdClientWrapper,
-) -> Result<(), SnapshotError> {
+) -> Result<SnapshotMetadata, SnapshotError> {
     info!(shard_id, chunked_dir_path, "Starting upload to s3");
-    let start_timetamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64;
-    let start_date = chrono::DateTime::from_timestamp_millis(start_timetamp)
-        .ok_or(SnapshotError::DateError)?
-        .date_naive();
-    let s3_client = create_s3_client(&snapshot_config).await;
-    let upload_dir = format!(
-        "{}/snapshot-{}-{}.tar.gz",
-        snapshot_directory(network, shard_id),
-        start_date,
-        start_timetamp / 1000
-    );
-    let files = std::fs::read_dir(chunked_dir_path)?;
-    let mut file_names: Vec<String> = vec![];
-    for entry in files {
+    let mut upload = SnapshotUpload::start(network, snapshot_config, shard_id).await?;
+    let mut file_names = vec![];
+    for entry in std::fs::read_dir(chunked_dir_path)? {
         let entry = entry?;
         let file_name = entry
             .file_name()
             .to_str()
             .ok_or(SnapshotError::UnableToParseFileName)?
             .to_string();
-        let key = format!("{}/{}", upload_dir, file_name);
+        file_names.push((file_name, entry.path()));
+    }
+    // Chunks are restored in the order they're listed in
+    file_names.sort();
 
-        let mut file = tokio::fs::File::open(entry.path()).await?;
+    for (file_name, path) in file_names {
+        let mut file = tokio::fs::File::open(path).await?;
         let mut buffer = Vec::new();
         file.read_to_end(&mut buffer).await?;
+        upload
+            .upload_chunk(
+                snapshot_config,
+                file_name,
+                Bytes::from(buffer),
+                statsd_client,
+            )
+            .await?;
+    }
 
-        s3_client
-            .put_object()
-            .bucket(snapshot_config.s3_bucket.clone())
-            .key(key.clone())
-            .body(ByteStream::from(buffer))
-            .send()
+    upload
+        .finish(network, snapshot_config, block_height, milestone_height)
+        .await
+}
+
+/// Uploads dir as the chunks create_tar_gzip would write, each one as soon as it's compressed,
+/// so the archive is never staged on disk. At most a couple of chunks are held in memory, the one
+/// being compressed and the one being uploaded.
+pub async fn stream_to_s3(
+    network: FarcasterNetwork,
+    dir: String,
+    snapshot_config: &Config,
+    shard_id: u32,
+    block_height: Option<u64>,
+    milestone_height: Option<u64>,
+    statsd_client: &StatsdClientWrapper,
+) -> Result<SnapshotMetadata, SnapshotError> {
+    info!(shard_id, dir, "Starting streaming upload to s3");
+    let mut upload = SnapshotUpload::start(network, snapshot_config, shard_id).await?;
+
+    let (chunk_tx, mut chunk_rx) = mpsc::channel::<(String, Vec<u8>)>(1);
+    let archive = tokio::task::spawn_blocking(move || {
+        RocksDB::stream_tar_gzip(
+            &dir,
+            Box::new(move |file_name, chunk| {
+                chunk_tx.blocking_send((file_name, chunk)).map_err(|_| {
+                    io::Error::new(io::ErrorKind::BrokenPipe, "snapshot upload stopped")
+                })
+            }),
+        )
+    });
+    // A failed upload drops the receiver, which stops the archiving at the next chunk
+    while let Some((file_name, chunk)) = chunk_rx.recv().await {
+        upload
+            .upload_chunk(
+                snapshot_config,
+                file_name,
+                Bytes::from(chunk),
+                statsd_client,
+            )
             .await?;
+    }
+    archive
+        .await
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))??;
 
-        info!(key, "Finished uploading snapshot to s3");
-        statsd_client.count_with_shard(shard_id, "snapshots.successful_upload", 1);
+    upload
+        .finish(network, snapshot_config, block_height, milestone_height)
+        .await
+}
+
+/// Streams a checkpoint of the db to s3 instead of staging a backup under backup_dir. The
+/// checkpoint hard links the db's files, so it takes next to no space when backup_dir is on the
+/// same filesystem as the db, but keeps the files rocksdb compacts away on disk until the upload
+/// finishes.
+pub async fn stream_db_to_s3(
+    network: FarcasterNetwork,
+    db: Arc<RocksDB>,
+    snapshot_config: &Config,
+    shard_id: u32,
+    block_height: Option<u64>,
+    milestone_height: Option<u64>,
+    statsd_client: &StatsdClientWrapper,
+) -> Result<SnapshotMetadata, SnapshotError> {
+    std::fs::create_dir_all(&snapshot_config.backup_dir)?;
+    // Named like the backup, so the archive unpacks to the same path
+    let checkpoint_dir = format!("{}/shard-{}", snapshot_config.backup_dir, shard_id);
+    if std::path::Path::new(&checkpoint_dir).exists() {
+        warn!(
+            checkpoint_dir,
+            "Checkpoint path already exists, removing it"
+        );
+        std::fs::remove_dir_all(&checkpoint_dir)?;
+    }
+    db.create_checkpoint(&checkpoint_dir)?;
+    let result = stream_to_s3(
+        network,
+        checkpoint_dir.clone(),
+        snapshot_config,
+        shard_id,
+        block_height,
+        milestone_height,
+        statsd_client,
+    )
+    .await;
+    if let Err(err) = std::fs::remove_dir_all(&checkpoint_dir) {
+        warn!(checkpoint_dir, "Unable to remove checkpoint: {}", err);
+    }
+    result
+}
 
-        file_names.push(file_name)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobs::snapshot_upload::shards_by_schedule;
+    use crate::storage::db::RocksDB;
+    use crate::storage::store::test_helper;
+
+    fn open_db(path: String) -> Arc<RocksDB> {
+        let db = RocksDB::new(&path);
+        db.open().unwrap();
+        Arc::new(db)
     }
 
-    let metadata = SnapshotMetadata {
-        key_base: upload_dir,
-        chunks: file_names,
-        timestamp: start_timetamp,
-    };
+    // Stands in for upload_to_s3, pointing the metadata at the local chunks
+    fn backup(
+        base_dir: &str,
+        db: &Arc<RocksDB>,
+        shard_id: u32,
+        timestamp: i64,
+    ) -> SnapshotMetadata {
+        let backup_dir = format!("{}/backup-{}", base_dir, timestamp);
+        let chunk_dir = RocksDB::backup_db(db.clone(), &backup_dir, shard_id, timestamp).unwrap();
+        let chunks = std::fs::read_dir(&chunk_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .sorted()
+            .collect();
+        SnapshotMetadata {
+            key_base: chunk_dir,
+            chunks,
+            timestamp,
+            block_height: None,
+            size_bytes: None,
+            format_version: SNAPSHOT_FORMAT_VERSION,
+            chunk_sha256: vec![],
+            milestone_height: None,
+        }
+    }
+
+    // Serves the files under root over http, standing in for the snapshot download url
+    async fn serve_dir(root: String) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let root = root.clone();
+                tokio::spawn(async move {
+                    let service = hyper::service::service_fn(
+                        move |request: hyper::Request<hyper::body::Incoming>| {
+                            let path = format!("{}{}", root, request.uri().path());
+                            async move {
+                                let response = match std::fs::read(&path) {
+                                    Ok(contents) => hyper::Response::new(
+                                        http_body_util::Full::new(Bytes::from(contents)),
+                                    ),
+                                    Err(_) => hyper::Response::builder()
+                                        .status(404)
+                                        .body(http_body_util::Full::new(Bytes::new()))
+                                        .unwrap(),
+                                };
+                                Ok::<_, std::convert::Infallible>(response)
+                            }
+                        },
+                    );
+                    _ = hyper::server::conn::http1::Builder::new()
+                        .serve_connection(hyper_util::rt::TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    // Stands in for an S3 compatible backend, recording the operation and payload size of every
+    // request it gets
+    async fn serve_fake_s3(requests: Arc<std::sync::Mutex<Vec<(String, usize)>>>) -> String {
+        use http_body_util::BodyExt;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let requests = requests.clone();
+                tokio::spawn(async move {
+                    let service = hyper::service::service_fn(
+                        move |request: hyper::Request<hyper::body::Incoming>| {
+                            let requests = requests.clone();
+                            async move {
+                                let query = request.uri().query().unwrap_or("").to_string();
+                                let operation = match *request.method() {
+                                    hyper::Method::POST if query.contains("uploads") => {
+                                        "create_multipart_upload"
+                                    }
+                                    hyper::Method::POST => "complete_multipart_upload",
+                                    hyper::Method::PUT if query.contains("partNumber") => {
+                                        "upload_part"
+                                    }
+                                    hyper::Method::PUT => "put_object",
+                                    _ => "other",
+                                };
+                                // Bodies sent with trailing checksums are larger than the payload
+                                let decoded_length = request
+                                    .headers()
+                                    .get("x-amz-decoded-content-length")
+                                    .map(|length| length.to_str().unwrap().parse().unwrap());
+                                let body = request.into_body().collect().await.unwrap();
+                                let length = decoded_length.unwrap_or(body.to_bytes().len());
+                                requests
+                                    .lock()
+                                    .unwrap()
+                                    .push((operation.to_string(), length));
+
+                                let xml = match operation {
+                                    "create_multipart_upload" => {
+                                        "<InitiateMultipartUploadResult><UploadId>upload</UploadId></InitiateMultipartUploadResult>"
+                                    }
+                                    "complete_multipart_upload" => {
+                                        "<CompleteMultipartUploadResult><ETag>\"etag\"</ETag></CompleteMultipartUploadResult>"
+                                    }
+                                    _ => "",
+                                };
+                                Ok::<_, std::convert::Infallible>(
+                                    hyper::Response::builder()
+                                        .header("ETag", "\"etag\"")
+                                        .body(http_body_util::Full::new(Bytes::from(xml)))
+                                        .unwrap(),
+                                )
+                            }
+                        },
+                    );
+                    _ = hyper::server::conn::http1::Builder::new()
+                        .serve_connection(hyper_util::rt::TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    async fn upload_chunk(config: &Config, chunk_size: usize) -> Vec<(String, usize)> {
+        let requests = Arc::new(std::sync::Mutex::new(vec![]));
+        let config = Config {
+            endpoint_url: serve_fake_s3(requests.clone()).await,
+            aws_access_key_id: "key".to_string(),
+            aws_secret_access_key: "secret".to_string(),
+            retry_max_attempts: 1,
+            ..config.clone()
+        };
+        let chunk_dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(
+            chunk_dir.path().join("chunk_0001.bin"),
+            vec![1u8; chunk_size],
+        )
+        .unwrap();
+
+        let metadata = upload_to_s3(
+            FarcasterNetwork::Devnet,
+            chunk_dir.path().to_str().unwrap().to_string(),
+            &config,
+            1,
+            None,
+            None,
+            &test_helper::statsd_client(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(metadata.size_bytes, Some(chunk_size as u64));
+
+        // The last request uploads the metadata
+        let mut requests = requests.lock().unwrap().clone();
+        assert_eq!(requests.pop().unwrap().0, "put_object");
+        requests
+    }
+
+    #[tokio::test]
+    async fn test_small_chunks_use_a_single_put() {
+        let config = Config {
+            multipart_threshold_bytes: MIN_MULTIPART_PART_SIZE,
+            multipart_part_size_bytes: MIN_MULTIPART_PART_SIZE,
+            ..Config::default()
+        };
+        let chunk_size = MIN_MULTIPART_PART_SIZE as usize - 1;
+        assert_eq!(
+            upload_chunk(&config, chunk_size).await,
+            vec![("put_object".to_string(), chunk_size)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_large_chunks_use_multipart() {
+        let part_size = MIN_MULTIPART_PART_SIZE as usize;
+        let config = Config {
+            multipart_threshold_bytes: part_size as u64,
+            multipart_part_size_bytes: part_size as u64,
+            ..Config::default()
+        };
+        let chunk_size = 2 * part_size + 10;
+        let requests = upload_chunk(&config, chunk_size).await;
+        assert_eq!(
+            requests
+                .iter()
+                .map(|(operation, _)| operation.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "create_multipart_upload",
+                "upload_part",
+                "upload_part",
+                "upload_part",
+                "complete_multipart_upload",
+            ]
+        );
+        let part_sizes: Vec<usize> = requests[1..4].iter().map(|(_, size)| *size).collect();
+        assert_eq!(part_sizes, vec![part_size, part_size, 10]);
+    }
+
+    #[tokio::test]
+    async fn test_streaming_upload_matches_staged() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let base_dir = tmp_dir.path().to_str().unwrap().to_string();
+        let db_dir = format!("{}/shard-1", base_dir);
+        let db = open_db(db_dir.clone());
+        for i in 0..1000u32 {
+            db.put(&i.to_be_bytes(), &[i as u8; 100]).unwrap();
+        }
+        db.close();
+
+        let requests = Arc::new(std::sync::Mutex::new(vec![]));
+        let config = Config {
+            endpoint_url: serve_fake_s3(requests.clone()).await,
+            aws_access_key_id: "key".to_string(),
+            aws_secret_access_key: "secret".to_string(),
+            retry_max_attempts: 1,
+            ..Config::default()
+        };
+        let statsd_client = test_helper::statsd_client();
+
+        let chunk_dir =
+            RocksDB::create_tar_gzip(&db_dir, &format!("{}/staged", base_dir), 1).unwrap();
+        let staged = upload_to_s3(
+            FarcasterNetwork::Devnet,
+            chunk_dir.clone(),
+            &config,
+            1,
+            Some(10),
+            None,
+            &statsd_client,
+        )
+        .await
+        .unwrap();
+        let staged_requests = std::mem::take(&mut *requests.lock().unwrap());
+
+        let streamed = stream_to_s3(
+            FarcasterNetwork::Devnet,
+            db_dir,
+            &config,
+            1,
+            Some(10),
+            None,
+            &statsd_client,
+        )
+        .await
+        .unwrap();
+        let streamed_requests = requests.lock().unwrap().clone();
+
+        // The same objects with the same contents, only the upload time in the key differs
+        assert!(!streamed.chunks.is_empty());
+        assert_eq!(streamed.chunks, staged.chunks);
+        assert_eq!(streamed.chunk_sha256, staged.chunk_sha256);
+        assert_eq!(streamed.size_bytes, staged.size_bytes);
+        assert_eq!(streamed_requests, staged_requests);
+        for (chunk, sha256) in staged.chunks.iter().zip(&staged.chunk_sha256) {
+            let contents = std::fs::read(format!("{}/{}", chunk_dir, chunk)).unwrap();
+            assert_eq!(&sha256_hex(&contents), sha256);
+        }
+    }
+
+    #[test]
+    fn test_validate_multipart_config() {
+        assert!(Config::default().validate().is_ok());
+        let config = Config {
+            multipart_part_size_bytes: MIN_MULTIPART_PART_SIZE - 1,
+            ..Config::default()
+        };
+        assert!(config.validate().is_err());
+        let config = Config {
+            multipart_part_size_bytes: MAX_MULTIPART_PART_SIZE + 1,
+            ..Config::default()
+        };
+        assert!(config.validate().is_err());
+        let config = Config {
+            multipart_threshold_bytes: MAX_PUT_OBJECT_SIZE + 1,
+            ..Config::default()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_metadata_from_older_versions() {
+        let metadata: SnapshotMetadata = serde_json::from_str(
+            r#"{"key_base":"FARCASTER_NETWORK_DEVNET/1/snapshot-2025-01-01-1735689600.tar.gz","chunks":["chunk_0001.bin"],"timestamp":1735689600000}"#,
+        )
+        .unwrap();
+        assert_eq!(metadata.chunks, vec!["chunk_0001.bin".to_string()]);
+        assert_eq!(metadata.block_height, None);
+        assert_eq!(metadata.size_bytes, None);
+        assert_eq!(metadata.format_version, 0);
+        assert!(check_format_version(&metadata).is_ok());
+    }
+
+    #[test]
+    fn test_format_version_compatibility() {
+        // Every version up to the current one is listed, each once
+        for (i, v) in FORMAT_VERSIONS.iter().enumerate() {
+            assert_eq!(v.version, i as u32);
+        }
+        assert_eq!(
+            FORMAT_VERSIONS.last().unwrap().version,
+            SNAPSHOT_FORMAT_VERSION
+        );
+        for version in 0..=SNAPSHOT_FORMAT_VERSION {
+            assert!(check_format_version_against(
+                version,
+                SNAPSHOT_FORMAT_VERSION,
+                FORMAT_VERSIONS
+            )
+            .is_ok());
+        }
 
-    let metadata_json = serde_json::to_string(&metadata)?;
-    let metadata_key = metadata_path(network, shard_id);
-    let upload_result = s3_client
-        .put_object()
-        .bucket(snapshot_config.s3_bucket.clone())
-        .key(metadata_key.clone())
-        .body(ByteStream::from(metadata_json.as_bytes().to_vec()))
-        .content_type("application/json")
-        .send()
+        let err = check_format_version_against(
+            SNAPSHOT_FORMAT_VERSION + 1,
+            SNAPSHOT_FORMAT_VERSION,
+            FORMAT_VERSIONS,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            SnapshotError::SnapshotVersionTooNew { version, supported }
+                if version == SNAPSHOT_FORMAT_VERSION + 1 && supported == SNAPSHOT_FORMAT_VERSION
+        ));
+        assert!(err.to_string().contains("upgrade the node"));
+
+        // A binary that has dropped support for an old format
+        let versions = [
+            FormatVersion {
+                version: 0,
+                restorable: false,
+                description: "",
+            },
+            FormatVersion {
+                version: 1,
+                restorable: false,
+                description: "",
+            },
+            FormatVersion {
+                version: 2,
+                restorable: true,
+                description: "",
+            },
+        ];
+        assert!(check_format_version_against(2, 2, &versions).is_ok());
+        assert!(matches!(
+            check_format_version_against(1, 2, &versions),
+            Err(SnapshotError::SnapshotVersionUnsupported(1))
+        ));
+        assert!(matches!(
+            check_format_version_against(0, 2, &versions),
+            Err(SnapshotError::SnapshotVersionUnsupported(0))
+        ));
+    }
+
+    #[test]
+    fn test_shard_schedules() {
+        let config = Config {
+            shard_schedules: vec![
+                ShardSchedule {
+                    shard_id: 1,
+                    schedule: "0 0 * * * *".to_string(),
+                },
+                ShardSchedule {
+                    shard_id: 3,
+                    schedule: "".to_string(),
+                },
+            ],
+            ..Config::default()
+        };
+
+        assert_eq!(config.schedule_for_shard(0), Some("0 0 5 * * *"));
+        assert_eq!(config.schedule_for_shard(1), Some("0 0 * * * *"));
+        assert_eq!(config.schedule_for_shard(3), None);
+
+        let schedules = shards_by_schedule(&config, &[0, 1, 2, 3]);
+        assert_eq!(
+            schedules,
+            BTreeMap::from([
+                ("0 0 * * * *".to_string(), vec![1]),
+                ("0 0 5 * * *".to_string(), vec![0, 2]),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_restore_from_mixed_cadence_snapshots() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let base_dir = tmp_dir.path().to_str().unwrap().to_string();
+
+        let block_db = open_db(format!("{}/node/shard-0", base_dir));
+        let hot_db = open_db(format!("{}/node/shard-1", base_dir));
+        let cold_db = open_db(format!("{}/node/shard-2", base_dir));
+        block_db.put(b"block", b"1").unwrap();
+        hot_db.put(b"hot", b"1").unwrap();
+        cold_db.put(b"cold", b"1").unwrap();
+
+        // Every shard is in the daily snapshot, the hot shard and blocks are uploaded more often
+        let mut manifest = SnapshotManifest::default();
+        for (shard_id, db) in [(0, &block_db), (1, &hot_db), (2, &cold_db)] {
+            manifest.record(1000, shard_id, backup(&base_dir, db, shard_id, 1000));
+        }
+        block_db.put(b"block", b"2").unwrap();
+        hot_db.put(b"hot", b"2").unwrap();
+        for (shard_id, db) in [(0, &block_db), (1, &hot_db)] {
+            manifest.record(2000, shard_id, backup(&base_dir, db, shard_id, 2000));
+        }
+        hot_db.put(b"hot", b"3").unwrap();
+        manifest.record(3000, 1, backup(&base_dir, &hot_db, 1, 3000));
+
+        // Only the latest snapshot of each shard is kept
+        let shards_by_timestamp = manifest
+            .snapshots
+            .iter()
+            .map(|entry| (entry.timestamp, entry.shards.keys().cloned().collect_vec()))
+            .collect_vec();
+        assert_eq!(
+            shards_by_timestamp,
+            vec![(1000, vec![2]), (2000, vec![0]), (3000, vec![1])]
+        );
+
+        let manifest_json = serde_json::to_string(&manifest).unwrap();
+        let manifest: SnapshotManifest = serde_json::from_str(&manifest_json).unwrap();
+
+        let restore_dir = format!("{}/restored", base_dir);
+        for shard_id in [0, 1, 2] {
+            let metadata = manifest.latest_for_shard(shard_id).unwrap();
+            let chunks = metadata
+                .chunks
+                .iter()
+                .map(|chunk| format!("{}/{}", metadata.key_base, chunk))
+                .collect();
+            let work_dir = format!("{}/work-{}", base_dir, shard_id);
+            std::fs::create_dir_all(&work_dir).unwrap();
+            unpack_snapshot_chunks(
+                chunks,
+                &work_dir,
+                &restore_dir,
+                Config::default().max_decompressed_chunk_bytes,
+            )
+            .await
+            .unwrap();
+        }
+
+        let restored_block_db = open_db(format!("{}/shard-0", restore_dir));
+        let restored_hot_db = open_db(format!("{}/shard-1", restore_dir));
+        let restored_cold_db = open_db(format!("{}/shard-2", restore_dir));
+        assert_eq!(
+            restored_block_db.get(b"block").unwrap(),
+            Some(b"2".to_vec())
+        );
+        assert_eq!(restored_hot_db.get(b"hot").unwrap(), Some(b"3".to_vec()));
+        assert_eq!(restored_cold_db.get(b"cold").unwrap(), Some(b"1".to_vec()));
+
+        // Each restored shard only has its own data
+        assert_eq!(restored_hot_db.get(b"cold").unwrap(), None);
+        assert_eq!(restored_cold_db.get(b"hot").unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_chunks_decompressing_past_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let base_dir = dir.path().to_str().unwrap();
+        // 64mb of zeros compress to well under 100kb
+        let chunk = format!("{}/chunk_0001.bin", base_dir);
+        std::fs::write(
+            &chunk,
+            crate::utils::decompression::gzip(&vec![0u8; 64 * 1024 * 1024]).unwrap(),
+        )
+        .unwrap();
+
+        let restore_dir = format!("{}/restored", base_dir);
+        let result =
+            unpack_snapshot_chunks(vec![chunk.clone()], base_dir, &restore_dir, 1024 * 1024).await;
+        match result {
+            Err(SnapshotError::ChunkTooLarge { chunk: name, limit }) => {
+                assert_eq!(name, chunk);
+                assert_eq!(limit, 1024 * 1024);
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        assert!(!std::path::Path::new(&restore_dir).exists());
+    }
+
+    #[tokio::test]
+    async fn test_bootstrap_shards() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let base_dir = tmp_dir.path().to_str().unwrap().to_string();
+        let network = FarcasterNetwork::Devnet;
+
+        // Only shard 1 was ever snapshotted
+        let db = open_db(format!("{}/node/shard-1", base_dir));
+        db.put(b"key", b"value").unwrap();
+        let mut metadata = backup(&base_dir, &db, 1, 1000);
+        metadata.key_base = metadata.key_base.replacen(&base_dir, "", 1);
+        let metadata_file = format!("{}/{}", base_dir, metadata_path(network, 1));
+        std::fs::create_dir_all(std::path::Path::new(&metadata_file).parent().unwrap()).unwrap();
+        std::fs::write(&metadata_file, serde_json::to_string(&metadata).unwrap()).unwrap();
+
+        let config = Config {
+            snapshot_download_url: serve_dir(base_dir.clone()).await,
+            snapshot_download_dir: format!("{}/download", base_dir),
+            ..Config::default()
+        };
+        let restore_dir = format!("{}/restored", base_dir);
+
+        let outcome = bootstrap_shard(network, &config, &restore_dir, 1)
+            .await
+            .unwrap();
+        assert_eq!(outcome, BootstrapOutcome::Restored(metadata));
+        {
+            let restored_db = open_db(format!("{}/shard-1", restore_dir));
+            assert_eq!(restored_db.get(b"key").unwrap(), Some(b"value".to_vec()));
+            restored_db.close();
+        }
+
+        // Without a snapshot the shard is left to sync from scratch
+        let outcome = bootstrap_shard(network, &config, &restore_dir, 2)
+            .await
+            .unwrap();
+        assert_eq!(outcome, BootstrapOutcome::NoSnapshot);
+        assert!(!std::path::Path::new(&format!("{}/shard-2", restore_dir)).exists());
+
+        // Bootstrapping again doesn't overwrite what was restored, or synced since
+        let outcome = bootstrap_shard(network, &config, &restore_dir, 1)
+            .await
+            .unwrap();
+        assert_eq!(outcome, BootstrapOutcome::ExistingData);
+
+        // A snapshot from a newer binary fails before anything is downloaded or restored
+        let db = open_db(format!("{}/node/shard-3", base_dir));
+        db.put(b"key", b"value").unwrap();
+        let mut metadata = backup(&base_dir, &db, 3, 2000);
+        metadata.key_base = metadata.key_base.replacen(&base_dir, "", 1);
+        metadata.format_version = SNAPSHOT_FORMAT_VERSION + 1;
+        let metadata_file = format!("{}/{}", base_dir, metadata_path(network, 3));
+        std::fs::create_dir_all(std::path::Path::new(&metadata_file).parent().unwrap()).unwrap();
+        std::fs::write(&metadata_file, serde_json::to_string(&metadata).unwrap()).unwrap();
+        let err = bootstrap_shard(network, &config, &restore_dir, 3)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SnapshotError::SnapshotVersionTooNew { .. }));
+        assert!(!std::path::Path::new(&config.snapshot_download_dir).exists());
+        assert!(!std::path::Path::new(&format!("{}/shard-3", restore_dir)).exists());
+
+        // A chunk that doesn't match its checksum isn't restored
+        let db = open_db(format!("{}/node/shard-4", base_dir));
+        db.put(b"key", b"value").unwrap();
+        let mut metadata = backup(&base_dir, &db, 4, 3000);
+        metadata.key_base = metadata.key_base.replacen(&base_dir, "", 1);
+        metadata.chunk_sha256 = vec![sha256_hex(b"something else"); metadata.chunks.len()];
+        let metadata_file = format!("{}/{}", base_dir, metadata_path(network, 4));
+        std::fs::create_dir_all(std::path::Path::new(&metadata_file).parent().unwrap()).unwrap();
+        std::fs::write(&metadata_file, serde_json::to_string(&metadata).unwrap()).unwrap();
+        let err = bootstrap_shard(network, &config, &restore_dir, 4)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SnapshotError::ChecksumMismatch { .. }));
+        assert!(!std::path::Path::new(&format!("{}/shard-4", restore_dir)).exists());
+    }
+
+    #[tokio::test]
+    async fn test_validate_snapshot() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let base_dir = tmp_dir.path().to_str().unwrap().to_string();
+        let network = FarcasterNetwork::Devnet;
+
+        let (mut engine, _engine_dir) = test_helper::new_engine();
+        test_helper::register_user(
+            test_helper::FID_FOR_TEST,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine,
+        )
         .await;
+        let cast = crate::utils::factory::messages_factory::casts::create_cast_add(
+            test_helper::FID_FOR_TEST,
+            "hello",
+            None,
+            None,
+        );
+        test_helper::commit_message(&mut engine, &cast).await;
+        let tip_height = engine.get_confirmed_height().block_number;
+
+        let block_db = open_db(format!("{}/node/shard-0", base_dir));
+        let shard_db = engine.get_stores().shard_store.db.clone();
+        let corrupt_db = open_db(format!("{}/node/shard-2", base_dir));
+        corrupt_db.put(b"key", b"value").unwrap();
+        let mut shards = BTreeMap::new();
+        for (shard_id, db) in [(0, &block_db), (1, &shard_db), (2, &corrupt_db)] {
+            let mut metadata = backup(&base_dir, db, shard_id, 1000);
+            metadata.key_base = metadata.key_base.replacen(&base_dir, "", 1);
+            shards.insert(shard_id, metadata);
+        }
+        shards.get_mut(&1).unwrap().block_height = Some(tip_height);
+        let corrupt = shards.get_mut(&2).unwrap();
+        corrupt.chunk_sha256 = vec![sha256_hex(b"something else"); corrupt.chunks.len()];
+        let mut manifest = SnapshotManifest {
+            snapshots: vec![ManifestEntry {
+                timestamp: 1000,
+                shards,
+            }],
+        };
+        let manifest_file = format!("{}/{}", base_dir, manifest_path(network));
+        std::fs::create_dir_all(std::path::Path::new(&manifest_file).parent().unwrap()).unwrap();
+        std::fs::write(&manifest_file, serde_json::to_string(&manifest).unwrap()).unwrap();
 
-    if let Err(err) = &upload_result {
-        error!(
-            "Error uploading metadata to s3: {}, key: {}, bucket: {}",
-            DisplayErrorContext(err),
-            metadata_key,
-            snapshot_config.s3_bucket
+        let config = Config {
+            snapshot_download_url: serve_dir(base_dir.clone()).await,
+            ..Config::default()
+        };
+        let work_dir = format!("{}/work", base_dir);
+        std::fs::create_dir_all(&work_dir).unwrap();
+        let key_base = manifest.snapshots[0].shards[&1].key_base.clone();
+        let validate = |config: Config, key_base: String| {
+            let work_dir = work_dir.clone();
+            async move {
+                validate_snapshot(
+                    network,
+                    &config,
+                    &crate::storage::db::Config::default(),
+                    &key_base,
+                    &[0, 1, 2],
+                    16,
+                    Some(&work_dir),
+                )
+                .await
+            }
+        };
+
+        // Any shard of the snapshot finds all of them
+        let validations = validate(config.clone(), key_base.clone()).await.unwrap();
+        assert_eq!(
+            validations.iter().map(|v| v.shard_id).collect_vec(),
+            vec![0, 1, 2]
         );
+        assert!(validations[0].passed);
+        assert_eq!(validations[0].tip_height, None);
+        let shard = &validations[1];
+        assert!(shard.passed, "{:?}", shard.error);
+        assert_eq!(shard.tip_height, Some(tip_height));
+        assert!(shard.trie_leaf_count.unwrap() > 0);
+        assert_eq!(shard.trie_leaf_count, shard.store_count);
+        assert!(!validations[2].passed);
+        assert!(validations[2].error.as_ref().unwrap().contains("sha256"));
+        // Nothing is left behind
+        assert_eq!(std::fs::read_dir(&work_dir).unwrap().count(), 0);
+
+        // A snapshot that claims more blocks than it has
+        manifest.snapshots[0]
+            .shards
+            .get_mut(&1)
+            .unwrap()
+            .block_height = Some(tip_height + 10);
+        std::fs::write(&manifest_file, serde_json::to_string(&manifest).unwrap()).unwrap();
+        let validations = validate(config.clone(), key_base).await.unwrap();
+        assert!(!validations[1].passed);
+        assert!(validations[1]
+            .error
+            .as_ref()
+            .unwrap()
+            .contains("below the recorded height"));
+
+        let err = validate(config, "unknown".to_string()).await.unwrap_err();
+        assert!(matches!(err, SnapshotError::UnknownSnapshot(_)));
     }
-    upload_result?;
-    Ok(())
 }
@@ -0,0 +1,214 @@
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tracing::info;
+
+// Largest piece of the body released at once. Smaller pieces keep the upload smooth, larger ones
+// reduce overhead.
+const MAX_FRAME_SIZE: u64 = 64 * 1024;
+const RATE_LOG_INTERVAL: Duration = Duration::from_secs(30);
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+/**
+* A token bucket refilled continuously at `rate` tokens per second, holding at most a tenth of a
+* second worth of tokens so that idle time doesn't turn into a burst.
+*/
+pub(crate) struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    bucket: Mutex<Bucket>,
+}
+
+impl TokenBucket {
+    pub fn new(rate: u64, now: Instant) -> Self {
+        let capacity = (rate as f64 / 10.0).max(1.0);
+        Self {
+            rate: rate.max(1) as f64,
+            capacity,
+            bucket: Mutex::new(Bucket {
+                tokens: capacity,
+                refilled_at: now,
+            }),
+        }
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.capacity as u64
+    }
+
+    // Takes n tokens if they're available, otherwise returns how long until they will be. n
+    // must not exceed the capacity.
+    pub fn try_take(&self, n: u64, now: Instant) -> Result<(), Duration> {
+        let mut bucket = self.bucket.lock().unwrap();
+        let elapsed = now.saturating_duration_since(bucket.refilled_at);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * self.rate).min(self.capacity);
+        bucket.refilled_at = now;
+
+        let n = n as f64;
+        if bucket.tokens >= n {
+            bucket.tokens -= n;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((n - bucket.tokens) / self.rate))
+        }
+    }
+}
+
+struct RateWindow {
+    started_at: Instant,
+    bytes: u64,
+}
+
+/// Limits the bandwidth used by snapshot uploads and periodically logs the rate achieved.
+pub(crate) struct UploadThrottle {
+    shard_id: u32,
+    bucket: TokenBucket,
+    window: Mutex<RateWindow>,
+}
+
+impl UploadThrottle {
+    pub fn new(shard_id: u32, bytes_per_sec: u64) -> Self {
+        let now = Instant::now();
+        Self {
+            shard_id,
+            bucket: TokenBucket::new(bytes_per_sec, now),
+            window: Mutex::new(RateWindow {
+                started_at: now,
+                bytes: 0,
+            }),
+        }
+    }
+
+    fn frame_size(&self) -> u64 {
+        MAX_FRAME_SIZE.min(self.bucket.capacity()).max(1)
+    }
+
+    fn record(&self, bytes: u64, now: Instant) {
+        let mut window = self.window.lock().unwrap();
+        window.bytes += bytes;
+        let elapsed = now.saturating_duration_since(window.started_at);
+        if elapsed >= RATE_LOG_INTERVAL {
+            info!(
+                shard_id = self.shard_id,
+                bytes_per_sec = (window.bytes as f64 / elapsed.as_secs_f64()) as u64,
+                "Snapshot upload rate"
+            );
+            window.started_at = now;
+            window.bytes = 0;
+        }
+    }
+}
+
+/// A request body that releases its data no faster than the throttle allows.
+pub(crate) struct ThrottledBody {
+    throttle: Arc<UploadThrottle>,
+    data: Bytes,
+    delay: Option<Pin<Box<tokio::time::Sleep>>>,
+}
+
+impl ThrottledBody {
+    pub fn new(throttle: Arc<UploadThrottle>, data: Bytes) -> Self {
+        Self {
+            throttle,
+            data,
+            delay: None,
+        }
+    }
+}
+
+impl Body for ThrottledBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+        loop {
+            if this.data.is_empty() {
+                return Poll::Ready(None);
+            }
+
+            if let Some(delay) = this.delay.as_mut() {
+                if delay.as_mut().poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+                this.delay = None;
+            }
+
+            let len = (this.data.len() as u64).min(this.throttle.frame_size());
+            let now = Instant::now();
+            match this.throttle.bucket.try_take(len, now) {
+                Ok(()) => {
+                    this.throttle.record(len, now);
+                    let frame = this.data.split_to(len as usize);
+                    return Poll::Ready(Some(Ok(Frame::data(frame))));
+                }
+                Err(wait) => {
+                    this.delay = Some(Box::pin(tokio::time::sleep(wait)));
+                }
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::with_exact(self.data.len() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let start = Instant::now();
+        let bucket = TokenBucket::new(1000, start);
+        assert_eq!(bucket.capacity(), 100);
+
+        // Starts full
+        assert!(bucket.try_take(100, start).is_ok());
+        let wait = bucket.try_take(50, start).unwrap_err();
+        assert!(wait > Duration::from_millis(49) && wait <= Duration::from_millis(50));
+
+        // Refills at the configured rate
+        let later = start + Duration::from_millis(60);
+        assert!(bucket.try_take(50, later).is_ok());
+        assert!(bucket.try_take(20, later).is_err());
+
+        // Doesn't accumulate more than its capacity while idle
+        let much_later = later + Duration::from_secs(10);
+        assert!(bucket.try_take(100, much_later).is_ok());
+        assert!(bucket.try_take(1, much_later).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_throttled_body() {
+        use http_body_util::BodyExt;
+
+        let throttle = Arc::new(UploadThrottle::new(1, 100_000));
+        let data = Bytes::from(vec![7u8; 60_000]);
+        let body = ThrottledBody::new(throttle, data.clone());
+        assert_eq!(body.size_hint().exact(), Some(60_000));
+
+        let start = Instant::now();
+        let collected = body.collect().await.unwrap().to_bytes();
+        assert_eq!(collected, data);
+
+        // The first 10KB are available right away, the rest at 100KB/s
+        assert!(start.elapsed() >= Duration::from_millis(450));
+    }
+}
@@ -0,0 +1,129 @@
+use crate::core::error::HubError;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// How far rocksdb is holding back writes to a db. It delays them once compaction falls behind,
+/// and stops them entirely at its hard limits until compaction catches up.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WriteStall {
+    #[default]
+    None,
+    Delayed,
+    Stopped,
+}
+
+impl WriteStall {
+    // From the rocksdb.is-write-stopped and rocksdb.actual-delayed-write-rate properties, the rate
+    // is 0 while writes aren't delayed
+    pub fn from_stats(is_write_stopped: u64, actual_delayed_write_rate: u64) -> WriteStall {
+        if is_write_stopped != 0 {
+            WriteStall::Stopped
+        } else if actual_delayed_write_rate != 0 {
+            WriteStall::Delayed
+        } else {
+            WriteStall::None
+        }
+    }
+
+    // Reported in the write_stall gauge
+    pub fn gauge_value(&self) -> u64 {
+        match self {
+            WriteStall::None => 0,
+            WriteStall::Delayed => 1,
+            WriteStall::Stopped => 2,
+        }
+    }
+}
+
+/// The write stall at which submitted messages are rejected
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WriteStallThreshold {
+    // Submissions queue up behind the stall
+    Off,
+    Delayed,
+    #[default]
+    Stopped,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Config {
+    pub threshold: WriteStallThreshold,
+    // Sent back with rejected submissions as the time to wait before retrying
+    #[serde(with = "humantime_serde")]
+    pub retry_after: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            threshold: WriteStallThreshold::default(),
+            retry_after: Duration::from_secs(5),
+        }
+    }
+}
+
+impl Config {
+    /// Rejects submissions with resource_exhausted while the shard's db is stalled past the
+    /// threshold, rather than letting them queue up behind the stall
+    pub fn check(&self, stall: WriteStall) -> Result<(), HubError> {
+        let exceeded = match self.threshold {
+            WriteStallThreshold::Off => false,
+            WriteStallThreshold::Delayed => stall != WriteStall::None,
+            WriteStallThreshold::Stopped => stall == WriteStall::Stopped,
+        };
+        if exceeded {
+            return Err(HubError::resource_exhausted(&format!(
+                "shard db is stalled on writes, retry after {:?}",
+                self.retry_after
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::db::RocksDB;
+
+    #[test]
+    fn test_rejects_submissions_past_the_threshold() {
+        let stopped = WriteStall::from_stats(1, 16 * 1024 * 1024);
+        let delayed = WriteStall::from_stats(0, 16 * 1024 * 1024);
+        let none = WriteStall::from_stats(0, 0);
+        assert_eq!(stopped, WriteStall::Stopped);
+        assert_eq!(delayed, WriteStall::Delayed);
+        assert_eq!(none, WriteStall::None);
+
+        let config = Config::default();
+        let err = config.check(stopped).unwrap_err();
+        assert_eq!(err.code, "resource_exhausted");
+        assert!(err.message.contains("retry after 5s"));
+        assert!(config.check(delayed).is_ok());
+        assert!(config.check(none).is_ok());
+
+        let config = Config {
+            threshold: WriteStallThreshold::Delayed,
+            ..Config::default()
+        };
+        assert!(config.check(stopped).is_err());
+        assert!(config.check(delayed).is_err());
+        assert!(config.check(none).is_ok());
+
+        let config = Config {
+            threshold: WriteStallThreshold::Off,
+            ..Config::default()
+        };
+        assert!(config.check(stopped).is_ok());
+    }
+
+    #[test]
+    fn test_reads_write_stall_from_db() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let db = RocksDB::new(dir.path().join("test.db").to_str().unwrap());
+        assert!(db.write_stall().is_err());
+        db.open().unwrap();
+        assert_eq!(db.write_stall().unwrap(), WriteStall::None);
+    }
+}
@@ -1,13 +1,13 @@
 use super::{
     get_many_messages, make_cast_id_key, make_fid_key, make_message_primary_key, make_user_key,
     read_fid_key, read_ts_hash,
-    store::{Store, StoreDef},
+    store::{ConflictRule, Store, StoreDef},
     MessagesPage, StoreEventHandler, HASH_LENGTH, PAGE_SIZE_MAX, TRUE_VALUE, TS_HASH_LENGTH,
 };
 use crate::core::error::HubError;
 use crate::storage::constants::{RootPrefix, UserPostfix};
 use crate::storage::db::PageOptions;
-use crate::storage::util::{bytes_compare, increment_vec_u8};
+use crate::storage::util::increment_vec_u8;
 use crate::{
     proto::{self as message, Message, MessageType},
     storage::db::{RocksDB, RocksDbTransactionBatch},
@@ -43,6 +43,7 @@ type Parent = message::cast_add_body::Parent;
  * 3. fid:set:targetTsHash -> fid:tsHash (Remove Set Index)
  * 4. parentFid:parentTsHash:fid:tsHash -> fid:tsHash (Child Set Index)
  * 5. mentionFid:fid:tsHash -> fid:tsHash (Mentions Set Index)
+ * 6. tsHash:fid -> fid:tsHash (Timestamp Index, across all fids in the shard)
  */
 #[derive(Clone)]
 pub struct CastStoreDef {
@@ -95,31 +96,10 @@ impl StoreDef for CastStoreDef {
         false
     }
 
-    // RemoveWins + LWW, instead of default
-    fn message_compare(
-        &self,
-        a_type: u8,
-        a_ts_hash: &Vec<u8>,
-        b_type: u8,
-        b_ts_hash: &Vec<u8>,
-    ) -> i8 {
-        // Compare message types to enforce that RemoveWins in case of LWW ties.
-        if (a_type == MessageType::CastRemove as u8) && (b_type == MessageType::CastAdd as u8) {
-            return 1;
-        } else if (a_type == MessageType::CastAdd as u8)
-            && (b_type == MessageType::CastRemove as u8)
-        {
-            return -1;
-        }
-
-        // Compare timestamps (first 4 bytes of tsHash) to enforce Last-Write-Wins
-        let ts_compare = bytes_compare(&a_ts_hash[0..4], &b_ts_hash[0..4]);
-        if ts_compare != 0 {
-            return ts_compare;
-        }
-
-        // Compare the rest of the ts_hash to break ties
-        bytes_compare(&a_ts_hash[4..24], &b_ts_hash[4..24])
+    // A removed cast can't be added back
+    #[inline]
+    fn conflict_rule(&self) -> ConflictRule {
+        ConflictRule::RemoveWins
     }
 
     fn build_secondary_indices(
@@ -136,6 +116,9 @@ impl StoreDef for CastStoreDef {
                 txn.put(by_mention_key, vec![TRUE_VALUE]);
             }
         }
+        if let Some(by_timestamp_key) = self.by_timestamp_secondary_index_key(ts_hash, message) {
+            txn.put(by_timestamp_key, vec![TRUE_VALUE]);
+        }
         Ok(())
     }
 
@@ -157,6 +140,10 @@ impl StoreDef for CastStoreDef {
             }
         }
 
+        if let Some(by_timestamp_key) = self.by_timestamp_secondary_index_key(ts_hash, message) {
+            txn.delete(by_timestamp_key);
+        }
+
         Ok(())
     }
 
@@ -311,6 +298,36 @@ impl CastStoreDef {
         return Ok(Some(result));
     }
 
+    fn by_timestamp_secondary_index_key(
+        &self,
+        ts_hash: &[u8; TS_HASH_LENGTH],
+        message: &Message,
+    ) -> Option<Vec<u8>> {
+        // Only cast adds are indexed, removes delete the add they target along with its index
+        match message.data.as_ref()?.body.as_ref()? {
+            message::message_data::Body::CastAddBody(_) => Some(Self::make_cast_by_timestamp_key(
+                Some(ts_hash),
+                message.data.as_ref().unwrap().fid,
+            )),
+            _ => None,
+        }
+    }
+
+    // Generates unique keys used to store or fetch CastAdd messages in the byTimestamp index. The
+    // ts_hash comes first so the index is in time order across fids.
+    #[inline]
+    pub fn make_cast_by_timestamp_key(ts_hash: Option<&[u8; TS_HASH_LENGTH]>, fid: u64) -> Vec<u8> {
+        let mut key = Vec::with_capacity(1 + 24 + 4);
+        key.push(RootPrefix::CastsByTimestamp as u8); // CastsByTimestamp prefix, 1 byte
+        if let Some(ts_hash) = ts_hash {
+            key.extend_from_slice(ts_hash);
+        }
+        if fid > 0 {
+            key.extend_from_slice(&make_fid_key(fid));
+        }
+        key
+    }
+
     // Generates unique keys used to store or fetch CastAdd messages in the adds set index
     #[inline]
     pub fn make_cast_adds_key(fid: u64, hash: &Vec<u8>) -> Vec<u8> {
@@ -477,6 +494,72 @@ impl CastStore {
         })
     }
 
+    // The shard's casts, newest first, across all fids. Only casts older than before_timestamp are
+    // returned if it's set, and page tokens continue from where the previous page stopped.
+    pub fn get_recent_casts(
+        store: &Store<CastStoreDef>,
+        before_timestamp: Option<u32>,
+        page_options: &PageOptions,
+    ) -> Result<MessagesPage, HubError> {
+        let prefix = CastStoreDef::make_cast_by_timestamp_key(None, 0);
+        let stop_prefix = match before_timestamp {
+            Some(before_timestamp) => {
+                let mut stop_prefix = prefix.clone();
+                stop_prefix.extend_from_slice(&before_timestamp.to_be_bytes());
+                stop_prefix
+            }
+            None => increment_vec_u8(&prefix),
+        };
+        let page_size = page_options.page_size.unwrap_or(PAGE_SIZE_MAX);
+        // Page tokens leave out the prefix, like the other indices
+        let page_options = PageOptions {
+            page_size: Some(page_size),
+            page_token: page_options
+                .page_token
+                .as_ref()
+                .map(|page_token| [prefix.as_slice(), page_token.as_slice()].concat()),
+            reverse: true,
+        };
+
+        let mut message_keys = vec![];
+        let mut last_key = vec![];
+
+        store.db().for_each_iterator_by_prefix(
+            Some(prefix.clone()),
+            Some(stop_prefix),
+            &page_options,
+            |key, _| {
+                let ts_hash_offset = prefix.len();
+                let fid_offset = ts_hash_offset + TS_HASH_LENGTH;
+
+                let fid = read_fid_key(key, fid_offset);
+                let ts_hash = read_ts_hash(key, ts_hash_offset);
+                let message_primary_key =
+                    make_message_primary_key(fid, store.postfix(), Some(&ts_hash));
+
+                message_keys.push(message_primary_key.to_vec());
+                if message_keys.len() >= page_size {
+                    last_key = key.to_vec();
+                    return Ok(true); // Stop iterating
+                }
+
+                Ok(false) // Continue iterating
+            },
+        )?;
+
+        let messages = get_many_messages(store.db().borrow(), message_keys)?;
+        let next_page_token = if last_key.len() > 0 {
+            Some(last_key[prefix.len()..].to_vec())
+        } else {
+            None
+        };
+
+        Ok(MessagesPage {
+            messages,
+            next_page_token,
+        })
+    }
+
     pub fn get_casts_by_mention(
         store: &Store<CastStoreDef>,
         mention: u64,
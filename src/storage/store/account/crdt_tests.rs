@@ -0,0 +1,425 @@
+#[cfg(test)]
+mod tests {
+    use crate::proto::{Message, ReactionType, UserDataType};
+    use crate::storage::db::{PageOptions, RocksDB, RocksDbTransactionBatch};
+    use crate::storage::store::account::{
+        make_user_key, resolve_conflict, CastStore, CastStoreDef, ConflictRule, LinkStore,
+        ReactionStore, ReactionStoreDef, Store, StoreDef, StoreEventHandler, UserDataStore,
+        UserDataStoreDef,
+    };
+    use crate::storage::util::increment_vec_u8;
+    use crate::utils::factory::messages_factory;
+    use itertools::Itertools;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    const FID: u64 = 1234;
+    const TIMESTAMP: u32 = 100_000;
+
+    fn open_db() -> (Arc<RocksDB>, TempDir) {
+        let dir = tempfile::TempDir::new().unwrap();
+        let db = RocksDB::new(dir.path().join("a.db").to_str().unwrap());
+        db.open().unwrap();
+        (Arc::new(db), dir)
+    }
+
+    // All of the fid's messages and indices, which have to be identical whatever the merge order
+    fn user_state(db: &RocksDB) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let prefix = make_user_key(FID);
+        let mut state = vec![];
+        db.for_each_iterator_by_prefix(
+            Some(prefix.clone()),
+            Some(increment_vec_u8(&prefix)),
+            &PageOptions::default(),
+            |key, value| {
+                state.push((key.to_vec(), value.to_vec()));
+                Ok(false)
+            },
+        )
+        .unwrap();
+        state
+    }
+
+    fn merge_in_order<T: StoreDef + Clone>(
+        new_store: &dyn Fn(Arc<RocksDB>) -> Store<T>,
+        messages: &[&Message],
+    ) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let (db, _dir) = open_db();
+        let store = new_store(db.clone());
+        for message in messages {
+            let mut txn = RocksDbTransactionBatch::new();
+            // Losing messages are rejected, only the state they leave behind matters
+            if store.merge_with(message, &mut txn, true).is_ok() {
+                db.commit(txn).unwrap();
+            }
+        }
+        user_state(&db)
+    }
+
+    fn assert_order_independent<T: StoreDef + Clone>(
+        new_store: impl Fn(Arc<RocksDB>) -> Store<T>,
+        messages: Vec<Message>,
+    ) {
+        assert_compact_state_rebuilds(&new_store, &messages);
+
+        let orderings = messages.iter().permutations(messages.len()).collect_vec();
+        let expected = merge_in_order(&new_store, &orderings[0]);
+        assert!(!expected.is_empty());
+        for ordering in &orderings[1..] {
+            let hashes = ordering
+                .iter()
+                .map(|message| hex::encode(&message.hash))
+                .collect_vec();
+            assert_eq!(
+                merge_in_order(&new_store, ordering),
+                expected,
+                "merge order {:?} led to a different state",
+                hashes
+            );
+        }
+    }
+
+    // A replica that only merges the compact state ends up with the same messages as a store that
+    // merged the full history
+    fn assert_compact_state_rebuilds<T: StoreDef + Clone>(
+        new_store: &dyn Fn(Arc<RocksDB>) -> Store<T>,
+        messages: &[Message],
+    ) {
+        let (db, _dir) = open_db();
+        let store = new_store(db.clone());
+        for message in messages {
+            let mut txn = RocksDbTransactionBatch::new();
+            if store.merge_with(message, &mut txn, true).is_ok() {
+                db.commit(txn).unwrap();
+            }
+        }
+        let compact_state = store
+            .get_compact_state(FID, &PageOptions::default())
+            .unwrap()
+            .messages;
+        // The compact state message, then the adds and removes that are left
+        let mut messages = vec![];
+        if store.store_def().compact_state_type_supported() {
+            messages = store
+                .get_compact_state_messages_by_fid(FID, &PageOptions::default())
+                .unwrap()
+                .messages;
+        }
+        let current = store
+            .get_all_messages_by_fid(FID, None, None, &PageOptions::default())
+            .unwrap()
+            .messages;
+        messages.extend(current.iter().cloned());
+        assert_eq!(compact_state, messages);
+
+        // Merged in reverse, so the replica doesn't rely on the order it's served in
+        let (replica_db, _replica_dir) = open_db();
+        let replica = new_store(replica_db.clone());
+        for message in compact_state.iter().rev() {
+            let mut txn = RocksDbTransactionBatch::new();
+            replica.merge_with(message, &mut txn, true).unwrap();
+            replica_db.commit(txn).unwrap();
+        }
+        assert_eq!(
+            replica
+                .get_compact_state(FID, &PageOptions::default())
+                .unwrap()
+                .messages,
+            compact_state
+        );
+        assert_eq!(
+            replica
+                .get_all_messages_by_fid(FID, None, None, &PageOptions::default())
+                .unwrap()
+                .messages,
+            current
+        );
+    }
+
+    fn reaction_store(db: Arc<RocksDB>) -> Store<ReactionStoreDef> {
+        ReactionStore::new(db, StoreEventHandler::new(), 100)
+    }
+
+    fn link_store(db: Arc<RocksDB>) -> Store<LinkStore> {
+        LinkStore::new(db, StoreEventHandler::new(), 100)
+    }
+
+    fn cast_store(db: Arc<RocksDB>) -> Store<CastStoreDef> {
+        CastStore::new(db, StoreEventHandler::new(), 100)
+    }
+
+    fn user_data_store(db: Arc<RocksDB>) -> Store<UserDataStoreDef> {
+        UserDataStore::new(db, StoreEventHandler::new(), 100)
+    }
+
+    fn ts_hash(timestamp: u32, hash_byte: u8) -> Vec<u8> {
+        let mut ts_hash = timestamp.to_be_bytes().to_vec();
+        ts_hash.extend([hash_byte; 20]);
+        ts_hash
+    }
+
+    #[test]
+    fn test_resolve_conflict_rules() {
+        let rule = ConflictRule::LastWriteWins;
+        // Later timestamp wins, whatever the type or hash
+        assert_eq!(
+            resolve_conflict(rule, false, &ts_hash(2, 0), true, &ts_hash(1, 9)),
+            1
+        );
+        assert_eq!(
+            resolve_conflict(rule, true, &ts_hash(1, 9), false, &ts_hash(2, 0)),
+            -1
+        );
+        // Removes win timestamp ties, whatever the hash
+        assert_eq!(
+            resolve_conflict(rule, true, &ts_hash(1, 0), false, &ts_hash(1, 9)),
+            1
+        );
+        assert_eq!(
+            resolve_conflict(rule, false, &ts_hash(1, 9), true, &ts_hash(1, 0)),
+            -1
+        );
+        // Then the higher hash wins
+        assert_eq!(
+            resolve_conflict(rule, false, &ts_hash(1, 9), false, &ts_hash(1, 0)),
+            1
+        );
+        assert_eq!(
+            resolve_conflict(rule, true, &ts_hash(1, 0), true, &ts_hash(1, 9)),
+            -1
+        );
+        assert_eq!(
+            resolve_conflict(rule, false, &ts_hash(1, 5), false, &ts_hash(1, 5)),
+            0
+        );
+
+        // Removes always win
+        let rule = ConflictRule::RemoveWins;
+        assert_eq!(
+            resolve_conflict(rule, true, &ts_hash(1, 0), false, &ts_hash(2, 9)),
+            1
+        );
+        assert_eq!(
+            resolve_conflict(rule, false, &ts_hash(2, 9), true, &ts_hash(1, 0)),
+            -1
+        );
+        assert_eq!(
+            resolve_conflict(rule, false, &ts_hash(2, 0), false, &ts_hash(1, 9)),
+            1
+        );
+        assert_eq!(
+            resolve_conflict(rule, true, &ts_hash(1, 9), true, &ts_hash(1, 0)),
+            1
+        );
+    }
+
+    #[test]
+    fn test_reaction_conflicts_in_any_order() {
+        let target = "https://example.com".to_string();
+        let add = |timestamp| {
+            messages_factory::reactions::create_reaction_add(
+                FID,
+                ReactionType::Like,
+                target.clone(),
+                Some(timestamp),
+                None,
+            )
+        };
+        let remove = |timestamp| {
+            messages_factory::reactions::create_reaction_remove(
+                FID,
+                ReactionType::Like,
+                target.clone(),
+                Some(timestamp),
+                None,
+            )
+        };
+
+        // Add and remove with the same timestamp
+        assert_order_independent(reaction_store, vec![add(TIMESTAMP), remove(TIMESTAMP)]);
+        // Later add and earlier remove, plus a duplicate
+        assert_order_independent(
+            reaction_store,
+            vec![add(TIMESTAMP + 1), remove(TIMESTAMP), add(TIMESTAMP + 1)],
+        );
+        // Several adds and removes across two timestamps
+        assert_order_independent(
+            reaction_store,
+            vec![
+                add(TIMESTAMP),
+                remove(TIMESTAMP),
+                add(TIMESTAMP + 1),
+                remove(TIMESTAMP - 1),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_cast_conflicts_in_any_order() {
+        let cast = messages_factory::casts::create_cast_add(FID, "hello", Some(TIMESTAMP), None);
+        let other_cast =
+            messages_factory::casts::create_cast_add(FID, "world", Some(TIMESTAMP), None);
+        // An earlier remove still wins
+        let early_remove =
+            messages_factory::casts::create_cast_remove(FID, &cast.hash, Some(TIMESTAMP - 1), None);
+        let late_remove =
+            messages_factory::casts::create_cast_remove(FID, &cast.hash, Some(TIMESTAMP + 1), None);
+
+        assert_order_independent(
+            cast_store,
+            vec![
+                cast.clone(),
+                other_cast,
+                early_remove.clone(),
+                late_remove.clone(),
+            ],
+        );
+        assert_order_independent(cast_store, vec![cast, early_remove]);
+    }
+
+    #[test]
+    fn test_link_compact_state_conflicts_in_any_order() {
+        let add = |target_fid, timestamp| {
+            messages_factory::links::create_link_add(
+                FID,
+                "follow",
+                target_fid,
+                Some(timestamp),
+                None,
+            )
+        };
+        let remove = |target_fid, timestamp| {
+            messages_factory::links::create_link_remove(
+                FID,
+                "follow",
+                target_fid,
+                Some(timestamp),
+                None,
+            )
+        };
+        let compact_state = |target_fids, timestamp| {
+            messages_factory::links::create_link_compact_state(
+                FID,
+                "follow",
+                target_fids,
+                Some(timestamp),
+                None,
+            )
+        };
+
+        // Two compact states with the same timestamp, which only differ in their hash
+        assert_order_independent(
+            link_store,
+            vec![
+                compact_state(vec![1, 2], TIMESTAMP),
+                compact_state(vec![2, 1], TIMESTAMP),
+                add(1, TIMESTAMP - 1),
+                add(3, TIMESTAMP - 1),
+            ],
+        );
+
+        // Adds and removes with the same timestamp as the compact state
+        assert_order_independent(
+            link_store,
+            vec![
+                compact_state(vec![1], TIMESTAMP),
+                add(1, TIMESTAMP),
+                add(2, TIMESTAMP),
+                remove(3, TIMESTAMP),
+                remove(1, TIMESTAMP + 1),
+            ],
+        );
+
+        // Older adds and removes around the compact state
+        assert_order_independent(
+            link_store,
+            vec![
+                compact_state(vec![1, 2], TIMESTAMP),
+                add(1, TIMESTAMP - 2),
+                add(3, TIMESTAMP - 1),
+                remove(2, TIMESTAMP - 1),
+                add(2, TIMESTAMP + 1),
+            ],
+        );
+
+        // Without the commutative rules, that chunks before their activation height were merged
+        // with, ties with the compact state's timestamp depend on the order: the first compact
+        // state wins, and adds at its timestamp are accepted after it
+        let first = compact_state(vec![1, 2], TIMESTAMP);
+        let second = compact_state(vec![2, 1], TIMESTAMP);
+        let (db, _dir) = open_db();
+        let store = link_store(db.clone());
+        let mut txn = RocksDbTransactionBatch::new();
+        store.merge(&first, &mut txn).unwrap();
+        db.commit(txn).unwrap();
+        let mut txn = RocksDbTransactionBatch::new();
+        assert_eq!(
+            store.merge(&second, &mut txn).unwrap_err().code,
+            "bad_request.conflict"
+        );
+        let mut txn = RocksDbTransactionBatch::new();
+        assert!(store.merge(&add(3, TIMESTAMP), &mut txn).is_ok());
+    }
+
+    #[test]
+    fn test_user_data_last_write_wins() {
+        let display = |name: &str, timestamp| {
+            messages_factory::user_data::create_user_data_add(
+                FID,
+                UserDataType::Display,
+                &name.to_string(),
+                Some(timestamp),
+                None,
+            )
+        };
+        let oldest = display("oldest", TIMESTAMP);
+        let older = display("older", TIMESTAMP + 1);
+        // Same timestamp, the higher hash wins
+        let (tie_loser, newest) = [
+            display("newest", TIMESTAMP + 2),
+            display("tie", TIMESTAMP + 2),
+        ]
+        .into_iter()
+        .sorted_by(|a, b| a.hash.cmp(&b.hash))
+        .collect_tuple()
+        .unwrap();
+        let messages = vec![oldest, older, tie_loser, newest.clone()];
+
+        let current = |store: &Store<UserDataStoreDef>| {
+            UserDataStore::get_user_data_by_fid_and_type(store, FID, UserDataType::Display).unwrap()
+        };
+
+        // Applied newest first, each in its own transaction. Every older value is rejected rather
+        // than overwriting the newest one.
+        let (db, _dir) = open_db();
+        let store = user_data_store(db.clone());
+        for (i, message) in messages.iter().rev().enumerate() {
+            let mut txn = RocksDbTransactionBatch::new();
+            match store.merge(message, &mut txn) {
+                Ok(_) => {
+                    assert_eq!(i, 0);
+                    db.commit(txn).unwrap();
+                }
+                Err(err) => assert_eq!(err.code, "bad_request.conflict"),
+            }
+            assert_eq!(current(&store), newest);
+        }
+
+        // Or all in the same transaction, as within a block
+        let (db, _dir) = open_db();
+        let store = user_data_store(db.clone());
+        let mut txn = RocksDbTransactionBatch::new();
+        for message in messages.iter().rev() {
+            _ = store.merge(message, &mut txn);
+        }
+        db.commit(txn).unwrap();
+        assert_eq!(current(&store), newest);
+
+        // Any order leaves the same state
+        let orderings = messages.iter().permutations(messages.len()).collect_vec();
+        let expected = merge_in_order(&user_data_store, &orderings[0]);
+        for ordering in &orderings[1..] {
+            assert_eq!(merge_in_order(&user_data_store, ordering), expected);
+        }
+    }
+}
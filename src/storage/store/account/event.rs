@@ -3,6 +3,7 @@ use crate::proto::HubEvent;
 use crate::storage::constants::{RootPrefix, PAGE_SIZE_MAX};
 use crate::storage::db::RocksDbTransactionBatch;
 use crate::storage::db::{PageOptions, RocksDB};
+use crate::storage::store::node_local_state::DataType;
 use crate::storage::util::increment_vec_u8;
 use prost::Message as _;
 use std::sync::{Arc, Mutex};
@@ -90,6 +91,11 @@ impl StoreEventHandler {
         generator.set_current_height(height);
     }
 
+    // The height of the chunk being built or replayed
+    pub fn current_height(&self) -> u64 {
+        self.generator.lock().unwrap().current_height
+    }
+
     // This is named "commit_transaction" but the commit doesn't actually happen here. This function is provided a [txn] that's committed elsewhere.
     pub fn commit_transaction(
         &self,
@@ -188,6 +194,66 @@ impl HubEvent {
         }
     }
 
+    fn make_pruned_until_key() -> Vec<u8> {
+        vec![
+            RootPrefix::NodeLocalState as u8,
+            DataType::EventsPrunedUntil as u8,
+        ]
+    }
+
+    /// The id every event before has been pruned, None if nothing was ever pruned. Ids keep
+    /// increasing with the block height, so a pruned id is never handed out again.
+    pub fn get_pruned_until(db: &RocksDB) -> Result<Option<u64>, HubError> {
+        match db.get(&Self::make_pruned_until_key())? {
+            Some(buf) => {
+                let bytes: [u8; 8] = buf.as_slice().try_into().map_err(|_| {
+                    HubError::invalid_internal_state("could not decode pruned event id")
+                })?;
+                Ok(Some(u64::from_be_bytes(bytes)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_oldest_event_id(db: &RocksDB) -> Result<Option<u64>, HubError> {
+        Self::get_boundary_event_id(db, 1, false)
+    }
+
+    // The id of the nth newest event, None if there are fewer events than that
+    pub fn get_nth_newest_event_id(db: &RocksDB, n: u64) -> Result<Option<u64>, HubError> {
+        Self::get_boundary_event_id(db, n, true)
+    }
+
+    fn get_boundary_event_id(db: &RocksDB, n: u64, reverse: bool) -> Result<Option<u64>, HubError> {
+        let mut seen = 0;
+        let mut event_id = None;
+        db.for_each_iterator_by_prefix(
+            Some(Self::make_event_key(0)),
+            Some(increment_vec_u8(&Self::make_event_key(std::u64::MAX))),
+            &PageOptions {
+                reverse,
+                ..PageOptions::default()
+            },
+            |key, _| {
+                seen += 1;
+                if seen >= n {
+                    // Skip the prefix byte
+                    let bytes: [u8; 8] = key[1..].try_into().map_err(|_| {
+                        HubError::invalid_internal_state("could not decode event key")
+                    })?;
+                    event_id = Some(u64::from_be_bytes(bytes));
+                    return Ok(true);
+                }
+                Ok(false)
+            },
+        )?;
+        Ok(event_id)
+    }
+
+    pub fn count_events(db: &RocksDB) -> Result<u32, HubError> {
+        db.count_keys_at_prefix(vec![RootPrefix::HubEvents as u8])
+    }
+
     pub async fn prune_events_util(
         db: Arc<RocksDB>,
         stop_height: u64,
@@ -195,12 +261,28 @@ impl HubEvent {
         throttle: Duration,
     ) -> Result<u32, HubError> {
         let stop_event_id = HubEventIdGenerator::make_event_id(stop_height, 0);
+        Self::prune_events_before(db, stop_event_id, page_options, throttle).await
+    }
+
+    /// Deletes the events before [stop_event_id], then compacts them away on disk
+    pub async fn prune_events_before(
+        db: Arc<RocksDB>,
+        stop_event_id: u64,
+        page_options: &PageOptions,
+        throttle: Duration,
+    ) -> Result<u32, HubError> {
+        // Recorded first, so a request for an event that's being deleted is already told it's
+        // been pruned
+        if Self::get_pruned_until(&db)?.unwrap_or(0) < stop_event_id {
+            db.put(&Self::make_pruned_until_key(), &stop_event_id.to_be_bytes())?;
+        }
+
         let start_event_key = Self::make_event_key(0);
         let stop_event_key = Self::make_event_key(stop_event_id);
         let total_pruned = db
             .delete_paginated(
-                Some(start_event_key),
-                Some(stop_event_key),
+                Some(start_event_key.clone()),
+                Some(stop_event_key.clone()),
                 &page_options,
                 throttle,
                 Some(|total_pruned: u32| {
@@ -208,6 +290,9 @@ impl HubEvent {
                 }),
             )
             .await?;
+        if total_pruned > 0 {
+            db.compact_range(&start_event_key, &stop_event_key)?;
+        }
         Ok(total_pruned)
     }
 }
@@ -159,6 +159,16 @@ pub fn make_message_primary_key(
     key
 }
 
+/// The index entry of a message points to the key it's stored under, which holds its fid and store
+#[inline]
+pub fn make_message_by_hash_key(hash: &[u8]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(1 + HASH_LENGTH);
+    key.push(RootPrefix::MessageByHash as u8);
+    key.extend_from_slice(hash);
+
+    key
+}
+
 #[inline]
 pub fn make_cast_id_key(cast_id: &CastId) -> Vec<u8> {
     let mut key = Vec::with_capacity(4 + HASH_LENGTH);
@@ -208,6 +218,41 @@ pub fn get_message_by_key(
     }
 }
 
+/// Looks a message up by its hash alone, whatever its type. None once it's been pruned or removed,
+/// or if the entry points to a key that now holds a different message.
+pub fn get_message_by_hash(db: &RocksDB, hash: &[u8]) -> Result<Option<MessageProto>, HubError> {
+    let Some(primary_key) = db.get(&make_message_by_hash_key(hash))? else {
+        return Ok(None);
+    };
+    match db.get(&primary_key)? {
+        Some(bytes) => {
+            let message = message_decode(&bytes)?;
+            if message.hash == hash {
+                Ok(Some(message))
+            } else {
+                Ok(None)
+            }
+        }
+        None => Ok(None),
+    }
+}
+
+/// Whether a message with each of the hashes is stored, without reading the messages. The
+/// TransactionDB has no key_may_exist, so it's a multi_get of the hash index, whose entries only
+/// hold the message's key. That key ends with the message's hash, which confirms the entry.
+pub fn messages_exist(db: &RocksDB, hashes: &[Vec<u8>]) -> Result<Vec<bool>, HubError> {
+    let keys = hashes
+        .iter()
+        .map(|hash| make_message_by_hash_key(hash))
+        .collect();
+    let primary_keys = db.get_many(&keys)?;
+    Ok(primary_keys
+        .iter()
+        .zip(hashes)
+        .map(|(primary_key, hash)| !hash.is_empty() && primary_key.ends_with(hash))
+        .collect())
+}
+
 /** Read many messages.
  * Note that if a message is not found, that corresponding entry in the result will be None.
  * This is different from the behaviour of get_message, which returns an error.
@@ -305,28 +350,38 @@ pub fn message_bytes_decode(msg: &mut MessageProto) {
     }
 }
 
+/// Decodes a stored message. Fields added to the schema after the message was written are left
+/// at their defaults, and the ones this binary doesn't know are skipped, but kept in data_bytes,
+/// so the message is encoded back as it was signed. Bytes that don't decode as a message, or
+/// whose data doesn't, are an error rather than a message without data.
 #[inline]
 pub fn message_decode(bytes: &[u8]) -> Result<MessageProto, RocksdbError> {
-    if let Ok(mut msg) = MessageProto::decode(bytes) {
-        message_bytes_decode(&mut msg);
-        Ok(msg)
-    } else {
-        Err(RocksdbError::DecodeError)
+    let mut msg = MessageProto::decode(bytes).map_err(|_| RocksdbError::DecodeError)?;
+    message_bytes_decode(&mut msg);
+    if msg.data.is_none() {
+        return Err(RocksdbError::DecodeError);
     }
+    Ok(msg)
 }
 
-pub fn put_message_transaction(
-    txn: &mut RocksDbTransactionBatch,
-    message: &MessageProto,
-) -> Result<(), HubError> {
+// The key the message's record is stored under
+pub fn message_primary_key(message: &MessageProto) -> Result<Vec<u8>, HubError> {
     let data = message.data.as_ref().unwrap();
     let ts_hash = make_ts_hash(data.timestamp, &message.hash)?;
 
-    let primary_key = make_message_primary_key(
+    Ok(make_message_primary_key(
         data.fid,
         type_to_set_postfix(MessageType::try_from(data.r#type).unwrap())? as u8,
         Some(&ts_hash),
-    );
+    ))
+}
+
+pub fn put_message_transaction(
+    txn: &mut RocksDbTransactionBatch,
+    message: &MessageProto,
+) -> Result<(), HubError> {
+    let primary_key = message_primary_key(message)?;
+    txn.put(make_message_by_hash_key(&message.hash), primary_key.clone());
     txn.put(primary_key, message_encode(&message));
 
     Ok(())
@@ -336,14 +391,8 @@ pub fn delete_message_transaction(
     txn: &mut RocksDbTransactionBatch,
     message: &MessageProto,
 ) -> Result<(), HubError> {
-    let data = message.data.as_ref().unwrap();
-    let ts_hash = make_ts_hash(data.timestamp, &message.hash)?;
-
-    let primary_key = make_message_primary_key(
-        data.fid,
-        type_to_set_postfix(MessageType::try_from(data.r#type).unwrap())? as u8,
-        Some(&ts_hash),
-    );
+    let primary_key = message_primary_key(message)?;
+    txn.delete(make_message_by_hash_key(&message.hash));
     txn.delete(primary_key);
 
     Ok(())
@@ -22,5 +22,9 @@ mod user_data_store;
 mod username_proof_store;
 mod verification_store;
 
+#[cfg(test)]
+mod crdt_tests;
 #[cfg(test)]
 mod on_chain_event_store_tests;
+#[cfg(test)]
+mod schema_evolution_tests;
@@ -5,7 +5,8 @@ use crate::{
     proto::UserNameProof,
     storage::{
         constants::RootPrefix,
-        db::{RocksDB, RocksDbTransactionBatch},
+        db::{PageOptions, RocksDB, RocksDbTransactionBatch},
+        util::increment_vec_u8,
     },
 };
 
@@ -28,6 +29,72 @@ pub fn make_fname_username_proof_by_fid_key(fid: u64) -> Vec<u8> {
     key
 }
 
+// Names are length prefixed, so the history of a name doesn't run into the history of the names
+// it's a prefix of
+#[inline]
+fn make_username_proof_history_prefix(name_type: i32, name: &[u8]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(1 + 1 + 1 + name.len());
+    key.push(RootPrefix::UserNameProofHistoryByName as u8);
+    key.push(name_type as u8);
+    key.push(name.len() as u8);
+    key.extend_from_slice(name);
+    key
+}
+
+// The timestamp comes before the fid, so a name's history is in time order
+#[inline]
+fn make_username_proof_history_key(name_type: i32, username_proof: &UserNameProof) -> Vec<u8> {
+    let mut key = make_username_proof_history_prefix(name_type, &username_proof.name);
+    key.extend_from_slice(&username_proof.timestamp.to_be_bytes());
+    key.extend_from_slice(&make_fid_key(username_proof.fid));
+    key
+}
+
+/// Records the proof in the history of its name. fnames and ENS names have separate histories,
+/// name_type picks which one. Entries are never removed, so the history keeps proofs that were
+/// later replaced, transferred away or revoked.
+#[inline]
+pub fn put_username_proof_history_transaction(
+    txn: &mut RocksDbTransactionBatch,
+    name_type: i32,
+    username_proof: &UserNameProof,
+) {
+    if username_proof.name.is_empty() || username_proof.name.len() > u8::MAX as usize {
+        return;
+    }
+    txn.put(
+        make_username_proof_history_key(name_type, username_proof),
+        username_proof.encode_to_vec(),
+    );
+}
+
+/// Every proof recorded for the name, oldest first
+pub fn get_username_proof_history(
+    db: &RocksDB,
+    name_type: i32,
+    name: &[u8],
+) -> Result<Vec<UserNameProof>, HubError> {
+    if name.len() > u8::MAX as usize {
+        return Ok(vec![]);
+    }
+    let prefix = make_username_proof_history_prefix(name_type, name);
+    let mut proofs = vec![];
+    db.for_each_iterator_by_prefix(
+        Some(prefix.clone()),
+        Some(increment_vec_u8(&prefix)),
+        &PageOptions::default(),
+        |_, value| {
+            let proof = UserNameProof::decode(value).map_err(|_| HubError {
+                code: "internal_error".to_string(),
+                message: "could not decode username proof".to_string(),
+            })?;
+            proofs.push(proof);
+            Ok(false) // Continue iterating
+        },
+    )?;
+    Ok(proofs)
+}
+
 pub fn get_username_proof(
     db: &RocksDB,
     txn: &mut RocksDbTransactionBatch,
@@ -61,6 +128,8 @@ pub fn get_fname_proof_by_fid(db: &RocksDB, fid: u64) -> Result<Option<UserNameP
     }
 
     match UserNameProof::decode(buf.unwrap().as_slice()) {
+        // The index entry of the previous owner outlives a transfer in older dbs
+        Ok(proof) if proof.fid != fid => Ok(None),
         Ok(proof) => Ok(Some(proof)),
         Err(_) => Err(HubError {
             code: "internal_error".to_string(),
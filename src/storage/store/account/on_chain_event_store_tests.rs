@@ -1,8 +1,11 @@
 #[cfg(test)]
 mod tests {
+    use crate::proto::OnChainEvent;
     use crate::storage::db;
-    use crate::storage::db::RocksDbTransactionBatch;
-    use crate::storage::store::account::{OnchainEventStore, StorageSlot, StoreEventHandler};
+    use crate::storage::db::{PageOptions, RocksDbTransactionBatch};
+    use crate::storage::store::account::{
+        make_onchain_event_by_block_key, OnchainEventStore, StorageSlot, StoreEventHandler,
+    };
     use crate::utils::factory;
     use std::sync::Arc;
     use tempfile::TempDir;
@@ -151,4 +154,49 @@ mod tests {
         assert_eq!(storage_slot.legacy_units, 12); // 5 + 7
         assert_eq!(storage_slot.units, 20); // 9 + 11
     }
+
+    fn block_range(store: &OnchainEventStore, start: u32, stop: u32) -> Vec<OnChainEvent> {
+        store
+            .get_onchain_events_by_block_range(10, start, stop, &PageOptions::default())
+            .unwrap()
+            .onchain_events
+    }
+
+    #[test]
+    fn test_rebuild_block_index() {
+        let (store, _dir) = store();
+        let events: Vec<OnChainEvent> = (0..5)
+            .map(|i| OnChainEvent {
+                block_number: 100 + i,
+                ..factory::events_factory::create_rent_event(10 + i as u64, None, Some(1), false)
+            })
+            .collect();
+        let mut txn = RocksDbTransactionBatch::new();
+        for event in &events {
+            store.merge_onchain_event(event.clone(), &mut txn).unwrap();
+        }
+        store.db.commit(txn).unwrap();
+        assert_eq!(block_range(&store, 101, 104), events[1..4].to_vec());
+
+        // Events merged before the index existed aren't found until it's rebuilt
+        let mut txn = store.db.txn();
+        for event in &events {
+            txn.delete(make_onchain_event_by_block_key(event));
+        }
+        store.db.commit(txn).unwrap();
+        assert!(block_range(&store, 0, u32::MAX).is_empty());
+
+        let mut page_token = None;
+        let mut indexed = 0;
+        loop {
+            let page = store.rebuild_block_index(page_token, 2).unwrap();
+            indexed += page.messages_indexed;
+            page_token = page.next_page_token;
+            if page_token.is_none() {
+                break;
+            }
+        }
+        assert_eq!(indexed, 5);
+        assert_eq!(block_range(&store, 0, u32::MAX), events);
+    }
 }
@@ -2,7 +2,7 @@ use std::sync::Arc;
 
 use prost::{DecodeError, Message};
 
-use super::{get_from_db_or_txn, make_fid_key, StoreEventHandler};
+use super::{get_from_db_or_txn, make_fid_key, IndexRebuildPage, StoreEventHandler};
 use crate::core::error::HubError;
 use crate::proto::{
     self, on_chain_event, IdRegisterEventBody, IdRegisterEventType, OnChainEvent, OnChainEventType,
@@ -55,6 +55,21 @@ fn make_log_index_key(log_index: u32) -> Vec<u8> {
     log_index.to_be_bytes().to_vec()
 }
 
+fn encode_onchain_event_position(block_number: u32, log_index: u32) -> Vec<u8> {
+    let mut position = make_block_number_key(block_number);
+    position.extend(make_log_index_key(log_index));
+    position
+}
+
+fn decode_onchain_event_position(token: &[u8]) -> Result<(u32, u32), OnchainEventStorageError> {
+    if token.len() != 8 {
+        return Err(HubError::invalid_parameter("invalid page token").into());
+    }
+    let block_number = u32::from_be_bytes(token[0..4].try_into().unwrap());
+    let log_index = u32::from_be_bytes(token[4..8].try_into().unwrap());
+    Ok((block_number, log_index))
+}
+
 fn make_onchain_event_type_prefix(onchain_event_type: OnChainEventType) -> Vec<u8> {
     vec![
         RootPrefix::OnChainEvent as u8,
@@ -63,6 +78,25 @@ fn make_onchain_event_type_prefix(onchain_event_type: OnChainEventType) -> Vec<u
     ]
 }
 
+fn make_onchain_events_by_block_prefix(chain_id: u32) -> Vec<u8> {
+    let mut prefix = vec![
+        RootPrefix::OnChainEvent as u8,
+        OnChainEventPostfix::OnChainEventsByBlock as u8,
+    ];
+    prefix.extend(chain_id.to_be_bytes());
+    prefix
+}
+
+// The same on every shard, so keys from different shards sort into chain order
+pub fn make_onchain_event_by_block_key(onchain_event: &OnChainEvent) -> Vec<u8> {
+    let mut key = make_onchain_events_by_block_prefix(onchain_event.chain_id);
+    key.extend(make_block_number_key(onchain_event.block_number));
+    key.extend(make_log_index_key(onchain_event.log_index));
+    key.push(onchain_event.r#type() as u8);
+    key.extend(make_fid_key(onchain_event.fid));
+    key
+}
+
 fn make_onchain_event_primary_key(onchain_event: &OnChainEvent) -> Vec<u8> {
     let mut primary_key = make_onchain_event_type_prefix(onchain_event.r#type());
     primary_key.extend(make_fid_key(onchain_event.fid));
@@ -228,9 +262,14 @@ fn build_secondary_indices(
                 build_secondary_indices_for_signer(db, txn, onchain_event, signer_event_body)?
             }
             on_chain_event::Body::SignerMigratedEventBody(_)
-            | on_chain_event::Body::StorageRentEventBody(_) => {}
+            | on_chain_event::Body::StorageRentEventBody(_)
+            | on_chain_event::Body::ValidatorStakeEventBody(_) => {}
         }
     };
+    txn.put(
+        make_onchain_event_by_block_key(onchain_event),
+        make_onchain_event_primary_key(onchain_event),
+    );
 
     Ok(())
 }
@@ -391,19 +430,32 @@ impl StorageSlot {
         Err(OnchainEventStorageError::InvalidStorageRentEventType)
     }
 
-    pub fn is_active(&self) -> bool {
-        let current_time = std::time::SystemTime::now()
+    fn current_unix_time() -> u64 {
+        std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap()
-            .as_secs() as u32;
-        current_time < self.invalidate_at
+            .as_secs()
+    }
+
+    // Uses the local clock, so it's only for serving apis. Anything that affects state has to
+    // use is_active_at with the block timestamp, or validators could disagree.
+    pub fn is_active(&self) -> bool {
+        self.is_active_at(Self::current_unix_time())
+    }
+
+    pub fn is_active_at(&self, unix_timestamp: u64) -> bool {
+        unix_timestamp < self.invalidate_at as u64
     }
 
     pub fn merge(&mut self, other: &StorageSlot) -> bool {
-        if !other.is_active() {
+        self.merge_at(other, Self::current_unix_time())
+    }
+
+    pub fn merge_at(&mut self, other: &StorageSlot, unix_timestamp: u64) -> bool {
+        if !other.is_active_at(unix_timestamp) {
             return false;
         }
-        if !self.is_active() {
+        if !self.is_active_at(unix_timestamp) {
             *self = other.clone();
             return true;
         }
@@ -476,6 +528,213 @@ impl OnchainEventStore {
         Ok(onchain_events)
     }
 
+    // Returns the onchain events for a fid in block order (block number, then log index). If
+    // event_type is None, events of all types are returned. The block range is
+    // [start_block_number, stop_block_number). The page token encodes the position of the last
+    // returned event.
+    pub fn get_onchain_events_by_fid(
+        &self,
+        fid: u64,
+        event_type: Option<OnChainEventType>,
+        start_block_number: Option<u32>,
+        stop_block_number: Option<u32>,
+        page_options: &PageOptions,
+    ) -> Result<OnchainEventsPage, OnchainEventStorageError> {
+        let event_types = match event_type {
+            Some(event_type) => vec![event_type],
+            None => vec![
+                OnChainEventType::EventTypeIdRegister,
+                OnChainEventType::EventTypeSigner,
+                OnChainEventType::EventTypeSignerMigrated,
+                OnChainEventType::EventTypeStorageRent,
+            ],
+        };
+
+        let cursor = match &page_options.page_token {
+            None => None,
+            Some(token) => Some(decode_onchain_event_position(token)?),
+        };
+
+        let page_size = page_options
+            .page_size
+            .unwrap_or(PAGE_SIZE_MAX)
+            .min(PAGE_SIZE_MAX);
+        let mut onchain_events = vec![];
+        for event_type in event_types {
+            // Each type's events are keyed in block order, so only the range past the cursor is
+            // read
+            let mut prefix = make_onchain_event_type_prefix(event_type);
+            prefix.extend(make_fid_key(fid));
+            let mut start_key = prefix.clone();
+            start_key.extend(make_block_number_key(start_block_number.unwrap_or(0)));
+            let mut stop_key = match stop_block_number {
+                None => increment_vec_u8(&prefix),
+                Some(stop_block_number) => {
+                    let mut stop_key = prefix.clone();
+                    stop_key.extend(make_block_number_key(stop_block_number));
+                    stop_key
+                }
+            };
+            if let Some((block_number, log_index)) = cursor {
+                let mut cursor_key = prefix.clone();
+                cursor_key.extend(make_block_number_key(block_number));
+                cursor_key.extend(make_log_index_key(log_index));
+                if page_options.reverse {
+                    stop_key = stop_key.min(cursor_key);
+                } else {
+                    start_key = start_key.max(increment_vec_u8(&cursor_key));
+                }
+            }
+            if start_key >= stop_key {
+                continue;
+            }
+
+            // A page never needs more than page_size events of one type, plus one to tell whether
+            // there are more
+            let mut count = 0;
+            self.db
+                .for_each_iterator_by_prefix(
+                    Some(start_key),
+                    Some(stop_key),
+                    &PageOptions {
+                        page_size: None,
+                        page_token: None,
+                        reverse: page_options.reverse,
+                    },
+                    |_, value| {
+                        onchain_events
+                            .push(OnChainEvent::decode(value).map_err(|e| HubError::from(e))?);
+                        count += 1;
+                        Ok(count > page_size)
+                    },
+                )
+                .map_err(|e| OnchainEventStorageError::HubError(e))?;
+        }
+
+        onchain_events.sort_by_key(|event| (event.block_number, event.log_index));
+        if page_options.reverse {
+            onchain_events.reverse();
+        }
+
+        let mut remaining = onchain_events.into_iter().peekable();
+        let mut page = vec![];
+        while page.len() < page_size {
+            match remaining.next() {
+                Some(event) => page.push(event),
+                None => break,
+            }
+        }
+
+        let next_page_token = match (page.last(), remaining.peek()) {
+            (Some(last), Some(_)) => Some(encode_onchain_event_position(
+                last.block_number,
+                last.log_index,
+            )),
+            _ => None,
+        };
+
+        Ok(OnchainEventsPage {
+            onchain_events: page,
+            next_page_token,
+        })
+    }
+
+    // Returns the chain's events in block order (block number, then log index), whichever fid
+    // they're for. The block range is [start_block_number, stop_block_number). The page token is
+    // the block index key of the last returned event.
+    pub fn get_onchain_events_by_block_range(
+        &self,
+        chain_id: u32,
+        start_block_number: u32,
+        stop_block_number: u32,
+        page_options: &PageOptions,
+    ) -> Result<OnchainEventsPage, OnchainEventStorageError> {
+        let prefix = make_onchain_events_by_block_prefix(chain_id);
+        if let Some(token) = &page_options.page_token {
+            if !token.starts_with(&prefix) {
+                return Err(HubError::invalid_parameter("invalid page token").into());
+            }
+        }
+        let mut start_key = prefix.clone();
+        start_key.extend(make_block_number_key(start_block_number));
+        let mut stop_key = prefix;
+        stop_key.extend(make_block_number_key(stop_block_number));
+
+        let page_size = page_options.page_size.unwrap_or(PAGE_SIZE_MAX);
+        let mut onchain_events = vec![];
+        let mut last_key = vec![];
+        self.db.for_each_iterator_by_prefix_paged(
+            Some(start_key),
+            Some(stop_key),
+            &PageOptions {
+                page_size: Some(page_size),
+                page_token: page_options.page_token.clone(),
+                reverse: false,
+            },
+            |key, primary_key| {
+                if let Some(bytes) = self.db.get(primary_key)? {
+                    let onchain_event =
+                        OnChainEvent::decode(bytes.as_slice()).map_err(|e| HubError::from(e))?;
+                    onchain_events.push(onchain_event);
+                }
+                if onchain_events.len() >= page_size {
+                    last_key = key.to_vec();
+                    return Ok(true); // Stop iterating
+                }
+                Ok(false) // Continue iterating
+            },
+        )?;
+
+        Ok(OnchainEventsPage {
+            onchain_events,
+            next_page_token: (!last_key.is_empty()).then_some(last_key),
+        })
+    }
+
+    /// Writes the block index entries of the events again, batch_size at a time, for events
+    /// merged before the index was added
+    pub fn rebuild_block_index(
+        &self,
+        page_token: Option<Vec<u8>>,
+        batch_size: usize,
+    ) -> Result<IndexRebuildPage, HubError> {
+        let prefix = vec![
+            RootPrefix::OnChainEvent as u8,
+            OnChainEventPostfix::OnChainEvents as u8,
+        ];
+        let mut onchain_events = vec![];
+        let mut last_key = None;
+        let all_done = self.db.for_each_iterator_by_prefix(
+            Some(prefix.clone()),
+            Some(increment_vec_u8(&prefix)),
+            &PageOptions {
+                page_size: None,
+                page_token,
+                reverse: false,
+            },
+            |key, value| {
+                last_key = Some(key.to_vec());
+                onchain_events.push(OnChainEvent::decode(value).map_err(|e| HubError::from(e))?);
+                Ok(onchain_events.len() >= batch_size)
+            },
+        )?;
+
+        let mut txn = self.db.txn();
+        for onchain_event in &onchain_events {
+            txn.put(
+                make_onchain_event_by_block_key(onchain_event),
+                make_onchain_event_primary_key(onchain_event),
+            );
+        }
+        self.db.commit(txn)?;
+
+        Ok(IndexRebuildPage {
+            messages_indexed: onchain_events.len() as u64,
+            fid: onchain_events.last().map_or(0, |event| event.fid),
+            next_page_token: if all_done { None } else { last_key },
+        })
+    }
+
     pub fn is_signer_key(signer_event_body: &SignerEventBody) -> bool {
         signer_event_body.key_type == SUPPORTED_SIGNER_KEY_TYPE
     }
@@ -506,6 +765,21 @@ impl OnchainEventStore {
         &self,
         page_options: &PageOptions,
     ) -> Result<(Vec<u64>, Option<Vec<u8>>), OnchainEventStorageError> {
+        let (registrations, next_page_token) = self.get_fid_registrations(page_options)?;
+        let fids = registrations
+            .iter()
+            .map(|registration| registration.fid)
+            .collect();
+        Ok((fids, next_page_token))
+    }
+
+    // Returns registered fids in fid order along with the block they were registered in.
+    // Transfers and recovery changes are skipped so each fid appears once, which means a page
+    // may contain fewer than page_size fids.
+    pub fn get_fid_registrations(
+        &self,
+        page_options: &PageOptions,
+    ) -> Result<(Vec<proto::FidRegistration>, Option<Vec<u8>>), OnchainEventStorageError> {
         let onchain_events_page = get_onchain_events(
             &self.db,
             page_options,
@@ -513,14 +787,23 @@ impl OnchainEventStore {
             None,
         )?;
 
-        let fids = onchain_events_page
+        let registrations = onchain_events_page
             .onchain_events
             .iter()
-            .map(|event| event.fid)
+            .filter(|event| match &event.body {
+                Some(on_chain_event::Body::IdRegisterEventBody(body)) => {
+                    body.event_type() == IdRegisterEventType::Register
+                }
+                _ => false,
+            })
+            .map(|event| proto::FidRegistration {
+                fid: event.fid,
+                block_number: event.block_number as u64,
+            })
             .collect();
         let next_page_token = onchain_events_page.next_page_token;
 
-        Ok((fids, next_page_token))
+        Ok((registrations, next_page_token))
     }
 
     #[inline]
@@ -554,15 +837,48 @@ impl OnchainEventStore {
         Ok(None)
     }
 
+    // The add event of every signer that can currently sign for the fid, in the order the keys
+    // were first added. A key that was removed and added again is active, with its latest add.
+    pub fn get_active_signers(
+        &self,
+        fid: u64,
+    ) -> Result<Vec<OnChainEvent>, OnchainEventStorageError> {
+        let mut keys: Vec<Vec<u8>> = vec![];
+        for event in self.get_onchain_events(OnChainEventType::EventTypeSigner, Some(fid))? {
+            if let Some(on_chain_event::Body::SignerEventBody(body)) = event.body {
+                if !keys.contains(&body.key) {
+                    keys.push(body.key);
+                }
+            }
+        }
+
+        let mut active_signers = vec![];
+        for key in keys {
+            if let Some(event) = self.get_active_signer(fid, key)? {
+                active_signers.push(event);
+            }
+        }
+        Ok(active_signers)
+    }
+
     pub fn get_storage_slot_for_fid(
         &self,
         fid: u64,
+    ) -> Result<StorageSlot, OnchainEventStorageError> {
+        self.get_storage_slot_for_fid_at(fid, StorageSlot::current_unix_time())
+    }
+
+    // The storage the fid had at the given time, from the rent that hadn't expired yet
+    pub fn get_storage_slot_for_fid_at(
+        &self,
+        fid: u64,
+        unix_timestamp: u64,
     ) -> Result<StorageSlot, OnchainEventStorageError> {
         let rent_events =
             self.get_onchain_events(OnChainEventType::EventTypeStorageRent, Some(fid))?;
         let mut storage_slot = StorageSlot::new(0, 0, 0);
         for rent_event in rent_events {
-            storage_slot.merge(&StorageSlot::from_event(&rent_event)?);
+            storage_slot.merge_at(&StorageSlot::from_event(&rent_event)?, unix_timestamp);
         }
         Ok(storage_slot)
     }
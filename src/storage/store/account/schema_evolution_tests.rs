@@ -0,0 +1,172 @@
+#[cfg(test)]
+mod tests {
+    use crate::proto::{self, OnChainEvent};
+    use crate::storage::db::RocksdbError;
+    use crate::storage::store::account::{message_decode, message_encode};
+    use crate::utils::factory::{events_factory, messages_factory};
+    use prost::Message;
+
+    // OnChainEvent as binaries from before tx_index and version were added wrote it
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct OldOnChainEvent {
+        #[prost(int32, tag = "1")]
+        r#type: i32,
+        #[prost(uint32, tag = "2")]
+        chain_id: u32,
+        #[prost(uint32, tag = "3")]
+        block_number: u32,
+        #[prost(bytes = "vec", tag = "6")]
+        transaction_hash: Vec<u8>,
+        #[prost(uint32, tag = "7")]
+        log_index: u32,
+        #[prost(uint64, tag = "8")]
+        fid: u64,
+    }
+
+    // OnChainEvent as a newer binary could write it, with a body and a field this one doesn't know
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct NewOnChainEvent {
+        #[prost(int32, tag = "1")]
+        r#type: i32,
+        #[prost(uint64, tag = "8")]
+        fid: u64,
+        #[prost(uint32, tag = "14")]
+        version: u32,
+        #[prost(bytes = "vec", tag = "16")]
+        future_body: Vec<u8>,
+        #[prost(string, tag = "100")]
+        future_field: String,
+    }
+
+    // MessageData with a field this binary doesn't know
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct NewMessageData {
+        #[prost(int32, tag = "1")]
+        r#type: i32,
+        #[prost(uint64, tag = "2")]
+        fid: u64,
+        #[prost(uint32, tag = "3")]
+        timestamp: u32,
+        #[prost(int32, tag = "4")]
+        network: i32,
+        #[prost(message, optional, tag = "5")]
+        cast_add_body: Option<proto::CastAddBody>,
+        #[prost(string, tag = "100")]
+        future_field: String,
+    }
+
+    #[test]
+    fn test_onchain_event_written_by_older_schema() {
+        let old = OldOnChainEvent {
+            r#type: proto::OnChainEventType::EventTypeIdRegister as i32,
+            chain_id: 10,
+            block_number: 100,
+            transaction_hash: vec![1; 32],
+            log_index: 2,
+            fid: 1234,
+        };
+        let event = OnChainEvent::decode(old.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(event.r#type(), proto::OnChainEventType::EventTypeIdRegister);
+        assert_eq!(event.fid, 1234);
+        assert_eq!(event.block_number, 100);
+        assert_eq!(event.transaction_hash, vec![1; 32]);
+        // Fields the old schema didn't have are left at their defaults
+        assert_eq!(event.tx_index, 0);
+        assert_eq!(event.version, 0);
+        assert_eq!(event.body, None);
+
+        // And an older binary reads what it knows of the current schema
+        let event = events_factory::create_onchain_event(1234);
+        let old = OldOnChainEvent::decode(event.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(old.fid, event.fid);
+        assert_eq!(old.block_number, event.block_number);
+        assert_eq!(old.transaction_hash, event.transaction_hash);
+    }
+
+    #[test]
+    fn test_onchain_event_written_by_newer_schema() {
+        let new = NewOnChainEvent {
+            r#type: proto::OnChainEventType::EventTypeStorageRent as i32,
+            fid: 1234,
+            version: 2,
+            future_body: vec![1, 2, 3],
+            future_field: "future".to_string(),
+        };
+        let event = OnChainEvent::decode(new.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(
+            event.r#type(),
+            proto::OnChainEventType::EventTypeStorageRent
+        );
+        assert_eq!(event.fid, 1234);
+        assert_eq!(event.version, 2);
+        // A body variant this binary doesn't know reads as no body, which the stores handle
+        assert_eq!(event.body, None);
+
+        // Unknown event types read as the default rather than failing
+        let new = NewOnChainEvent {
+            r#type: 1000,
+            ..new
+        };
+        let event = OnChainEvent::decode(new.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(event.r#type, 1000);
+        assert_eq!(event.r#type(), proto::OnChainEventType::EventTypeNone);
+    }
+
+    #[test]
+    fn test_message_written_by_newer_schema() {
+        let cast = messages_factory::casts::create_cast_add(1234, "hello", None, None);
+        let data = cast.data.as_ref().unwrap();
+        let new_data = NewMessageData {
+            r#type: data.r#type,
+            fid: data.fid,
+            timestamp: data.timestamp,
+            network: data.network,
+            cast_add_body: match &data.body {
+                Some(proto::message_data::Body::CastAddBody(body)) => Some(body.clone()),
+                _ => panic!("Expected a cast"),
+            },
+            future_field: "future".to_string(),
+        };
+        let message = proto::Message {
+            data: None,
+            data_bytes: Some(new_data.encode_to_vec()),
+            ..cast.clone()
+        };
+        let bytes = message.encode_to_vec();
+
+        let decoded = message_decode(&bytes).unwrap();
+        let decoded_data = decoded.data.as_ref().unwrap();
+        assert_eq!(decoded_data.fid, 1234);
+        assert_eq!(decoded_data.body, data.body);
+        // Encoded from data_bytes, so the unknown field is kept and the hash still matches
+        assert_eq!(message_encode(&decoded), bytes);
+    }
+
+    #[test]
+    fn test_incompatible_message_is_an_error() {
+        let cast = messages_factory::casts::create_cast_add(1234, "hello", None, None);
+        // The fid as a string instead of a varint
+        let mut data_bytes = vec![(2 << 3) | 2, 4];
+        data_bytes.extend_from_slice(b"1234");
+        let message = proto::Message {
+            data: None,
+            data_bytes: Some(data_bytes),
+            ..cast.clone()
+        };
+        assert!(matches!(
+            message_decode(&message.encode_to_vec()),
+            Err(RocksdbError::DecodeError)
+        ));
+        assert!(matches!(
+            message_decode(&[0xff, 0xff, 0xff]),
+            Err(RocksdbError::DecodeError)
+        ));
+
+        // Messages stored with their data rather than data_bytes still decode
+        let message = proto::Message {
+            data_bytes: None,
+            ..cast.clone()
+        };
+        assert_eq!(message_decode(&message.encode_to_vec()).unwrap(), message);
+    }
+}
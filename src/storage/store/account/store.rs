@@ -1,13 +1,15 @@
 use super::{
     super::super::util::{bytes_compare, vec_to_u8_24},
     delete_message_transaction, get_from_db_or_txn, get_message, get_messages_page_by_prefix,
-    is_message_in_time_range, make_message_primary_key, make_ts_hash, message_decode,
-    message_encode, put_message_transaction, MessagesPage, StoreEventHandler, TS_HASH_LENGTH,
+    is_message_in_time_range, make_message_by_hash_key, make_message_primary_key, make_ts_hash,
+    make_user_key, message_decode, message_encode, message_primary_key, put_message_transaction,
+    read_fid_key, MessagesPage, StoreEventHandler, FID_BYTES, TS_HASH_LENGTH,
 };
 use crate::core::error::HubError;
 use crate::proto::{
     hub_event, HubEvent, HubEventType, MergeMessageBody, PruneMessageBody, RevokeMessageBody,
 };
+use crate::storage::constants::{RootPrefix, UserPostfix};
 use crate::storage::db::PageOptions;
 use crate::storage::util::increment_vec_u8;
 use crate::{
@@ -29,6 +31,66 @@ pub const PAGE_SIZE_MAX: usize = 10_000;
 //     pub reverse: bool,
 // }
 
+// A message record's key: the user prefix, fid, store postfix and ts_hash
+const MESSAGE_KEY_LENGTH: usize = 1 + FID_BYTES + 1 + TS_HASH_LENGTH;
+
+/// One batch of a secondary index rebuild
+#[derive(Debug, Default, PartialEq)]
+pub struct IndexRebuildPage {
+    pub messages_indexed: u64,
+    // The fid of the last message in the batch, messages are rebuilt in fid order
+    pub fid: u64,
+    // Where the next batch starts, None once the whole store has been scanned
+    pub next_page_token: Option<Vec<u8>>,
+}
+
+/// How an add and a remove for the same target are ordered against each other.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConflictRule {
+    // The later message wins, with removes winning timestamp ties
+    LastWriteWins,
+    // A remove always wins over an add, whatever their timestamps
+    RemoveWins,
+}
+
+/// Orders two conflicting messages, returning 1 if `a` wins, -1 if `b` wins and 0 if they're the
+/// same message. Every store resolves conflicts through this, so that the merged state doesn't
+/// depend on the order messages arrive in. The rules, in order:
+/// 1. Under `RemoveWins`, a remove wins over an add
+/// 2. The message with the later timestamp wins
+/// 3. On equal timestamps, a remove wins over an add
+/// 4. Otherwise the message with the lexicographically higher hash wins
+///
+/// A ts_hash is the 4 byte timestamp followed by the 20 byte message hash.
+pub fn resolve_conflict(
+    rule: ConflictRule,
+    a_is_remove: bool,
+    a_ts_hash: &[u8],
+    b_is_remove: bool,
+    b_ts_hash: &[u8],
+) -> i8 {
+    let remove_compare = match (a_is_remove, b_is_remove) {
+        (true, false) => 1,
+        (false, true) => -1,
+        _ => 0,
+    };
+
+    if rule == ConflictRule::RemoveWins && remove_compare != 0 {
+        return remove_compare;
+    }
+
+    let ts_compare = bytes_compare(&a_ts_hash[0..4], &b_ts_hash[0..4]);
+    if ts_compare != 0 {
+        return ts_compare;
+    }
+
+    if remove_compare != 0 {
+        return remove_compare;
+    }
+
+    bytes_compare(&a_ts_hash[4..24], &b_ts_hash[4..24])
+}
+
 /// The `Send` trait indicates that a type can be safely transferred between threads.
 /// The `Sync` trait indicates that a type can be safely shared between threads.
 /// The `StoreDef` trait is implemented for types that are both `Send` and `Sync`,
@@ -185,6 +247,11 @@ pub trait StoreDef: Send + Sync {
         Ok(conflicts)
     }
 
+    #[inline]
+    fn conflict_rule(&self) -> ConflictRule {
+        ConflictRule::LastWriteWins
+    }
+
     fn message_compare(
         &self,
         a_type: u8,
@@ -192,21 +259,16 @@ pub trait StoreDef: Send + Sync {
         b_type: u8,
         b_ts_hash: &Vec<u8>,
     ) -> i8 {
-        // Compare timestamps (first 4 bytes of ts_hash)
-        let ts_compare = bytes_compare(&a_ts_hash[0..4], &b_ts_hash[0..4]);
-        if ts_compare != 0 {
-            return ts_compare;
-        }
-
-        if a_type == self.remove_message_type() && b_type == self.add_message_type() {
-            return 1;
-        }
-        if a_type == self.add_message_type() && b_type == self.remove_message_type() {
-            return -1;
-        }
-
-        // Compare the rest of the ts_hash to break ties
-        bytes_compare(&a_ts_hash[4..24], &b_ts_hash[4..24])
+        let is_remove = |message_type| {
+            self.remove_type_supported() && message_type == self.remove_message_type()
+        };
+        resolve_conflict(
+            self.conflict_rule(),
+            is_remove(a_type),
+            a_ts_hash,
+            is_remove(b_type),
+            b_ts_hash,
+        )
     }
 
     #[inline]
@@ -241,6 +303,13 @@ pub trait StoreDef: Send + Sync {
     }
 }
 
+#[derive(Clone, Copy)]
+enum PruneBy {
+    Count,
+    // Serialized size of the messages
+    Bytes,
+}
+
 #[derive(Clone)]
 pub struct Store<T>
 where
@@ -405,7 +474,13 @@ impl<T: StoreDef + Clone> Store<T> {
         }
 
         let compact_state_key = self.store_def.make_compact_state_add_key(message)?;
-        txn.put(compact_state_key, message_encode(&message));
+        let value = message_encode(&message);
+        self.track_bytes_used(txn, message, &compact_state_key, value.len())?;
+        txn.put(
+            make_message_by_hash_key(&message.hash),
+            compact_state_key.clone(),
+        );
+        txn.put(compact_state_key, value);
 
         Ok(())
     }
@@ -416,6 +491,7 @@ impl<T: StoreDef + Clone> Store<T> {
         ts_hash: &[u8; TS_HASH_LENGTH],
         message: &Message,
     ) -> Result<(), HubError> {
+        self.track_message_bytes_used(txn, message, true)?;
         put_message_transaction(txn, &message)?;
 
         let adds_key = self.store_def.make_add_key(message)?;
@@ -441,6 +517,8 @@ impl<T: StoreDef + Clone> Store<T> {
         }
 
         let compact_state_key = self.store_def.make_compact_state_add_key(message)?;
+        self.track_bytes_used(txn, message, &compact_state_key, 0)?;
+        txn.delete(make_message_by_hash_key(&message.hash));
         txn.delete(compact_state_key);
 
         Ok(())
@@ -458,6 +536,7 @@ impl<T: StoreDef + Clone> Store<T> {
         let add_key = self.store_def.make_add_key(message)?;
         txn.delete(add_key);
 
+        self.track_message_bytes_used(txn, message, false)?;
         delete_message_transaction(txn, message)
     }
 
@@ -474,6 +553,7 @@ impl<T: StoreDef + Clone> Store<T> {
             });
         }
 
+        self.track_message_bytes_used(txn, message, true)?;
         put_message_transaction(txn, &message)?;
 
         let removes_key = self.store_def.make_remove_key(message)?;
@@ -497,6 +577,7 @@ impl<T: StoreDef + Clone> Store<T> {
         let remove_key = self.store_def.make_remove_key(message)?;
         txn.delete(remove_key);
 
+        self.track_message_bytes_used(txn, message, false)?;
         delete_message_transaction(txn, message)
     }
 
@@ -525,6 +606,17 @@ impl<T: StoreDef + Clone> Store<T> {
         &self,
         message: &Message,
         txn: &mut RocksDbTransactionBatch,
+    ) -> Result<HubEvent, HubError> {
+        self.merge_with(message, txn, false)
+    }
+
+    /// Merges with the order independent compact state rules, see
+    /// ProtocolFeature::CommutativeCompactState, or with the ones chunks before it were merged with
+    pub fn merge_with(
+        &self,
+        message: &Message,
+        txn: &mut RocksDbTransactionBatch,
+        commutative_compact_state: bool,
     ) -> Result<HubEvent, HubError> {
         // Grab a merge lock. The typescript code does this by individual fid, but we don't have a
         // good way of doing that efficiently here. We'll just use an array of locks, with each fid
@@ -548,11 +640,11 @@ impl<T: StoreDef + Clone> Store<T> {
         let ts_hash = make_ts_hash(message.data.as_ref().unwrap().timestamp, &message.hash)?;
 
         if self.store_def().is_compact_state_type(message) {
-            self.merge_compact_state(message, txn)
+            self.merge_compact_state(message, txn, commutative_compact_state)
         } else if self.store_def.is_add_type(message) {
-            self.merge_add(&ts_hash, message, txn)
+            self.merge_add(&ts_hash, message, txn, commutative_compact_state)
         } else {
-            self.merge_remove(&ts_hash, message, txn)
+            self.merge_remove(&ts_hash, message, txn, commutative_compact_state)
         }
     }
 
@@ -617,6 +709,7 @@ impl<T: StoreDef + Clone> Store<T> {
         &self,
         message: &Message,
         txn: &mut RocksDbTransactionBatch,
+        commutative: bool,
     ) -> Result<HubEvent, HubError> {
         let mut merge_conflicts = vec![];
 
@@ -629,13 +722,34 @@ impl<T: StoreDef + Clone> Store<T> {
             if let Ok(existing_compact_state_message) =
                 message_decode(existing_compact_state.unwrap().as_ref())
             {
-                if existing_compact_state_message
-                    .data
-                    .as_ref()
-                    .unwrap()
-                    .timestamp
-                    < message.data.as_ref().unwrap().timestamp
-                {
+                let existing_ts_hash = make_ts_hash(
+                    existing_compact_state_message
+                        .data
+                        .as_ref()
+                        .unwrap()
+                        .timestamp,
+                    &existing_compact_state_message.hash,
+                )?;
+                let ts_hash =
+                    make_ts_hash(message.data.as_ref().unwrap().timestamp, &message.hash)?;
+                // Without the commutative rules, compact states are only ordered by timestamp and
+                // a tie keeps the one merged first
+                let compact_state_compare = if commutative {
+                    resolve_conflict(
+                        self.store_def.conflict_rule(),
+                        false,
+                        &existing_ts_hash,
+                        false,
+                        &ts_hash,
+                    )
+                } else {
+                    bytes_compare(&existing_ts_hash[0..4], &ts_hash[0..4])
+                };
+
+                if commutative && compact_state_compare == 0 {
+                    return Err(HubError::duplicate("message has already been merged"));
+                }
+                if compact_state_compare < 0 {
                     merge_conflicts.push(existing_compact_state_message);
                 } else {
                     // Can't merge an older compact state message
@@ -709,6 +823,7 @@ impl<T: StoreDef + Clone> Store<T> {
         ts_hash: &[u8; TS_HASH_LENGTH],
         message: &Message,
         txn: &mut RocksDbTransactionBatch,
+        commutative_compact_state: bool,
     ) -> Result<HubEvent, HubError> {
         // If the store supports compact state messages, we don't merge messages that don't exist in the compact state
         if self.store_def.compact_state_type_supported() {
@@ -724,10 +839,13 @@ impl<T: StoreDef + Clone> Store<T> {
 
                 if let Some(Body::LinkBody(link_body)) = &message.data.as_ref().unwrap().body {
                     if let Some(Target::TargetFid(target_fid)) = link_body.target {
-                        // If the message is older than the compact state message, and the target fid is not in the target_fids list
-                        if message.data.as_ref().unwrap().timestamp < compact_state_timestamp
-                            && !target_fids.contains(&target_fid)
-                        {
+                        // If the message is older than the compact state message, and the target fid is not in the target_fids list.
+                        // Messages at the same timestamp are covered too under the commutative rules, since merging the
+                        // compact state after them deletes them. Chunks before them accepted them.
+                        let timestamp = message.data.as_ref().unwrap().timestamp;
+                        let covered = timestamp < compact_state_timestamp
+                            || (commutative_compact_state && timestamp == compact_state_timestamp);
+                        if covered && !target_fids.contains(&target_fid) {
                             return Err(HubError {
                                 code: "bad_request.conflict".to_string(),
                                 message: format!(
@@ -769,6 +887,7 @@ impl<T: StoreDef + Clone> Store<T> {
         ts_hash: &[u8; TS_HASH_LENGTH],
         message: &Message,
         txn: &mut RocksDbTransactionBatch,
+        commutative_compact_state: bool,
     ) -> Result<HubEvent, HubError> {
         // If the store supports compact state messages, we don't merge remove messages before its timestamp
         // If the store supports compact state messages, we don't merge messages that don't exist in the compact state
@@ -783,8 +902,12 @@ impl<T: StoreDef + Clone> Store<T> {
                 let (_, compact_state_timestamp, _) =
                     self.read_compact_state_details(&compact_state_message)?;
 
-                // If the message is older than the compact state message, and the target fid is not in the target_fids list
-                if message.data.as_ref().unwrap().timestamp < compact_state_timestamp {
+                // Removes older than the compact state are deleted by it if they're merged first, and
+                // under the commutative rules the ones at its timestamp too
+                let timestamp = message.data.as_ref().unwrap().timestamp;
+                if timestamp < compact_state_timestamp
+                    || (commutative_compact_state && timestamp == compact_state_timestamp)
+                {
                     return Err(HubError {
                         code: "bad_request.prunable".to_string(),
                         message: format!(
@@ -825,24 +948,117 @@ impl<T: StoreDef + Clone> Store<T> {
         max_count: u32,
         txn: &mut RocksDbTransactionBatch,
     ) -> Result<Vec<HubEvent>, HubError> {
-        let mut pruned_events = vec![];
-
-        let mut count = current_count;
+        if current_count <= max_count {
+            return Ok(vec![]); // Nothing to prune
+        }
+        self.prune_oldest_messages(fid, (current_count - max_count) as u64, PruneBy::Count, txn)
+    }
 
-        if count <= max_count {
-            return Ok(pruned_events); // Nothing to prune
+    /// Prunes the oldest messages until the serialized size of the fid's messages is within
+    /// max_bytes. Compact state messages are kept, but their size counts towards the usage.
+    pub fn prune_messages_by_bytes(
+        &self,
+        fid: u64,
+        current_bytes: u64,
+        max_bytes: u64,
+        txn: &mut RocksDbTransactionBatch,
+    ) -> Result<Vec<HubEvent>, HubError> {
+        if current_bytes <= max_bytes {
+            return Ok(vec![]); // Nothing to prune
         }
+        self.prune_oldest_messages(fid, current_bytes - max_bytes, PruneBy::Bytes, txn)
+    }
+
+    /// Deletes the fid's messages of message_type with a timestamp before cutoff, oldest first and
+    /// at most limit of them. They're removed whatever the fid's storage, with the same events as
+    /// pruning.
+    pub fn expire_messages(
+        &self,
+        fid: u64,
+        message_type: MessageType,
+        cutoff: u32,
+        limit: usize,
+        txn: &mut RocksDbTransactionBatch,
+    ) -> Result<Vec<HubEvent>, HubError> {
+        let mut expired_events = vec![];
 
         let prefix = &make_message_primary_key(fid, self.store_def.postfix(), None);
         self.db.for_each_iterator_by_prefix(
             Some(prefix.to_vec()),
             Some(increment_vec_u8(prefix)),
             &PageOptions::default(),
-            |_key, value| {
-                if count <= max_count {
+            |key, value| {
+                if expired_events.len() >= limit {
+                    return Ok(true); // Stop the iteration
+                }
+
+                // Already deleted earlier in the transaction
+                if let Some(None) = txn.batch.get(key) {
+                    return Ok(false); // Continue the iteration
+                }
+
+                let message = message_decode(value)?;
+                let timestamp = message.data.as_ref().unwrap().timestamp;
+                // Messages are keyed by timestamp, so the rest are newer
+                if timestamp >= cutoff {
+                    return Ok(true); // Stop the iteration
+                }
+                if message.msg_type() != message_type {
+                    return Ok(false); // Continue the iteration
+                }
+
+                if self.store_def.is_add_type(&message) {
+                    let ts_hash = make_ts_hash(timestamp, &message.hash)?;
+                    self.delete_add_transaction(txn, &ts_hash, &message)?;
+                } else if self.store_def.remove_type_supported()
+                    && self.store_def.is_remove_type(&message)
+                {
+                    self.delete_remove_transaction(txn, &message)?;
+                } else {
+                    return Ok(false); // Continue the iteration
+                }
+
+                let mut hub_event = self.store_def.prune_event_args(&message);
+                let id = self
+                    .store_event_handler
+                    .commit_transaction(txn, &mut hub_event)?;
+                hub_event.id = id;
+                expired_events.push(hub_event);
+
+                Ok(false) // Continue the iteration
+            },
+        )?;
+
+        Ok(expired_events)
+    }
+
+    fn prune_oldest_messages(
+        &self,
+        fid: u64,
+        mut excess: u64,
+        prune_by: PruneBy,
+        txn: &mut RocksDbTransactionBatch,
+    ) -> Result<Vec<HubEvent>, HubError> {
+        let mut pruned_events = vec![];
+
+        let prefix = &make_message_primary_key(fid, self.store_def.postfix(), None);
+        self.db.for_each_iterator_by_prefix(
+            Some(prefix.to_vec()),
+            Some(increment_vec_u8(prefix)),
+            &PageOptions::default(),
+            |key, value| {
+                if excess == 0 {
                     return Ok(true); // Stop the iteration, nothing left to prune
                 }
 
+                // The bytes were already accounted for when the message was deleted earlier in
+                // the transaction
+                if let PruneBy::Bytes = prune_by {
+                    if let Some(None) = txn.batch.get(key) {
+                        return Ok(false); // Continue the iteration
+                    }
+                }
+
                 // Value is a message, so try to decode it
                 let message = message_decode(value)?;
 
@@ -867,7 +1083,10 @@ impl<T: StoreDef + Clone> Store<T> {
                     .store_event_handler
                     .commit_transaction(txn, &mut hub_event)?;
 
-                count -= 1;
+                excess = match prune_by {
+                    PruneBy::Count => excess - 1,
+                    PruneBy::Bytes => excess.saturating_sub(value.len() as u64),
+                };
 
                 hub_event.id = id;
                 pruned_events.push(hub_event);
@@ -879,42 +1098,177 @@ impl<T: StoreDef + Clone> Store<T> {
         Ok(pruned_events)
     }
 
+    fn make_bytes_used_key(&self, fid: u64) -> Vec<u8> {
+        let mut key = make_user_key(fid);
+        key.push(UserPostfix::BytesUsed.as_u8());
+        key.push(self.store_def.postfix());
+        key
+    }
+
+    fn read_bytes_used(
+        &self,
+        fid: u64,
+        txn: &RocksDbTransactionBatch,
+    ) -> Result<Option<u64>, HubError> {
+        let key = self.make_bytes_used_key(fid);
+        let value = match txn.batch.get(&key) {
+            Some(value) => value.clone(),
+            None => self.db.get(&key)?,
+        };
+        Ok(value.map(|bytes| u64::from_be_bytes(bytes.try_into().unwrap_or_default())))
+    }
+
+    // Brings the fid's running byte count up to date with a write of value_len bytes under key, 0
+    // for a delete. Nothing is kept until the count is first asked for with
+    // [Store::get_tracked_bytes_used].
+    fn track_bytes_used(
+        &self,
+        txn: &mut RocksDbTransactionBatch,
+        message: &Message,
+        key: &[u8],
+        value_len: usize,
+    ) -> Result<(), HubError> {
+        let fid = message.data.as_ref().unwrap().fid;
+        let Some(bytes_used) = self.read_bytes_used(fid, txn)? else {
+            return Ok(());
+        };
+        let existing_len = get_from_db_or_txn(&self.db, txn, key)?.map_or(0, |value| value.len());
+        let bytes_used = (bytes_used + value_len as u64).saturating_sub(existing_len as u64);
+        txn.put(
+            self.make_bytes_used_key(fid),
+            bytes_used.to_be_bytes().to_vec(),
+        );
+        Ok(())
+    }
+
+    fn track_message_bytes_used(
+        &self,
+        txn: &mut RocksDbTransactionBatch,
+        message: &Message,
+        put: bool,
+    ) -> Result<(), HubError> {
+        let value_len = if put {
+            message_encode(message).len()
+        } else {
+            0
+        };
+        self.track_bytes_used(txn, message, &message_primary_key(message)?, value_len)
+    }
+
+    /// Serialized size of the fid's messages in this store, including the changes pending in the
+    /// transaction. Read from the running count once there is one, which pruning by bytes starts,
+    /// counted from the messages otherwise.
+    pub fn get_bytes_used(&self, fid: u64, txn: &RocksDbTransactionBatch) -> Result<u64, HubError> {
+        match self.read_bytes_used(fid, txn)? {
+            Some(bytes_used) => Ok(bytes_used),
+            None => self.count_bytes_used(fid, txn),
+        }
+    }
+
+    /// Like [Store::get_bytes_used], and starts the running count in the transaction if there
+    /// isn't one yet, so the fid's messages are only counted once
+    pub fn get_tracked_bytes_used(
+        &self,
+        fid: u64,
+        txn: &mut RocksDbTransactionBatch,
+    ) -> Result<u64, HubError> {
+        if let Some(bytes_used) = self.read_bytes_used(fid, txn)? {
+            return Ok(bytes_used);
+        }
+        let bytes_used = self.count_bytes_used(fid, txn)?;
+        txn.put(
+            self.make_bytes_used_key(fid),
+            bytes_used.to_be_bytes().to_vec(),
+        );
+        Ok(bytes_used)
+    }
+
+    fn count_bytes_used(&self, fid: u64, txn: &RocksDbTransactionBatch) -> Result<u64, HubError> {
+        let mut prefixes = vec![make_message_primary_key(
+            fid,
+            self.store_def.postfix(),
+            None,
+        )];
+        if self.store_def.compact_state_type_supported() {
+            prefixes.push(self.store_def.make_compact_state_prefix(fid)?);
+        }
+
+        let mut bytes_used = 0;
+        for prefix in prefixes {
+            self.db.for_each_iterator_by_prefix(
+                Some(prefix.clone()),
+                Some(increment_vec_u8(&prefix)),
+                &PageOptions::default(),
+                |key, value| {
+                    // Pending changes are added below
+                    if !txn.batch.contains_key(key) {
+                        bytes_used += value.len() as u64;
+                    }
+                    Ok(false) // Continue the iteration
+                },
+            )?;
+
+            bytes_used += txn
+                .batch
+                .iter()
+                .filter(|(key, _)| key.starts_with(&prefix))
+                .filter_map(|(_, value)| value.as_ref())
+                .map(|value| value.len() as u64)
+                .sum::<u64>();
+        }
+        Ok(bytes_used)
+    }
+
+    // Compact state messages are only revoked with include_compact_state, see
+    // ProtocolFeature::RevokeAllSignerMessages
     pub fn revoke_messages_by_signer(
         &self,
         fid: u64,
         key: &Vec<u8>,
+        include_compact_state: bool,
         txn: &mut RocksDbTransactionBatch,
     ) -> Result<Vec<HubEvent>, HubError> {
         let mut revoke_events = vec![];
 
-        let prefix = &make_message_primary_key(fid, self.store_def.postfix(), None);
-        self.db.for_each_iterator_by_prefix(
-            Some(prefix.to_vec()),
-            Some(increment_vec_u8(prefix)),
-            &PageOptions::default(),
-            |_key, value| {
-                // Value is a message, so try to decode it
-                let message = message_decode(value)?;
+        let mut prefixes = vec![make_message_primary_key(
+            fid,
+            self.store_def.postfix(),
+            None,
+        )];
+        // Compact state messages are kept under their own postfix
+        if include_compact_state && self.store_def.compact_state_type_supported() {
+            prefixes.push(self.store_def.make_compact_state_prefix(fid)?);
+        }
 
-                if bytes_compare(&message.signer, key) == 0 {
-                    let result = self.revoke(&message, txn);
-                    match result {
-                        Ok(event) => {
-                            revoke_events.push(event);
-                        }
-                        Err(e) => {
-                            warn!(
-                                fid = fid,
-                                hash = message.hex_hash(),
-                                error = format!("{:?}", e),
-                                "Error revoking message, skipping"
-                            );
+        for prefix in prefixes {
+            self.db.for_each_iterator_by_prefix(
+                Some(prefix.to_vec()),
+                Some(increment_vec_u8(&prefix)),
+                &PageOptions::default(),
+                |_key, value| {
+                    // Value is a message, so try to decode it
+                    let message = message_decode(value)?;
+
+                    if bytes_compare(&message.signer, key) == 0 {
+                        let result = self.revoke(&message, txn);
+                        match result {
+                            Ok(event) => {
+                                revoke_events.push(event);
+                            }
+                            Err(e) => {
+                                warn!(
+                                    fid = fid,
+                                    hash = message.hex_hash(),
+                                    error = format!("{:?}", e),
+                                    "Error revoking message, skipping"
+                                );
+                            }
                         }
                     }
-                }
-                Ok(false) // Continue the iteration
-            },
-        )?;
+                    Ok(false) // Continue the iteration
+                },
+            )?;
+        }
 
         Ok(revoke_events)
     }
@@ -937,6 +1291,27 @@ impl<T: StoreDef + Clone> Store<T> {
         Ok(messages)
     }
 
+    /// The fid's current state in this store: its compact state message when the store has them,
+    /// then the adds and removes that won conflict resolution, in primary key order. The removes
+    /// keep older adds from being merged again, so merging these into an empty store gives the
+    /// same state without replaying the history that led to it. The compact state message is only
+    /// on the first page.
+    pub fn get_compact_state(
+        &self,
+        fid: u64,
+        page_options: &PageOptions,
+    ) -> Result<MessagesPage, HubError> {
+        let mut page = self.get_all_messages_by_fid(fid, None, None, page_options)?;
+        if page_options.page_token.is_none() && self.store_def.compact_state_type_supported() {
+            let mut messages = self
+                .get_compact_state_messages_by_fid(fid, &PageOptions::default())?
+                .messages;
+            messages.append(&mut page.messages);
+            page.messages = messages;
+        }
+        Ok(page)
+    }
+
     pub fn get_compact_state_messages_by_fid(
         &self,
         fid: u64,
@@ -959,6 +1334,70 @@ impl<T: StoreDef + Clone> Store<T> {
             Err(e) => Err(e),
         }
     }
+
+    /// Writes the secondary indices of up to batch_size of the store's adds again, starting after
+    /// page_token, for when an index is missing entries. Each batch is committed on its own, so
+    /// reads are served from a partial index while a rebuild is running. Index puts are
+    /// idempotent, so entries that are already there are left as they are.
+    pub fn rebuild_secondary_indices(
+        &self,
+        page_token: Option<Vec<u8>>,
+        batch_size: usize,
+    ) -> Result<IndexRebuildPage, HubError> {
+        let prefix = vec![RootPrefix::User as u8];
+        let postfix = self.store_def.postfix();
+        let mut messages = vec![];
+        let mut last_key = None;
+        let all_done = self.db.for_each_iterator_by_prefix(
+            Some(prefix.clone()),
+            Some(increment_vec_u8(&prefix)),
+            &PageOptions {
+                page_size: None,
+                page_token,
+                reverse: false,
+            },
+            |key, value| {
+                last_key = Some(key.to_vec());
+                if key.len() == MESSAGE_KEY_LENGTH && key[1 + FID_BYTES] == postfix {
+                    let message = message_decode(value)?;
+                    if self.store_def.is_add_type(&message) {
+                        messages.push((key.to_vec(), message));
+                    }
+                }
+                Ok(messages.len() >= batch_size)
+            },
+        )?;
+
+        let mut txn = self.db.txn();
+        for (key, message) in &messages {
+            let ts_hash = vec_to_u8_24(&Some(key[1 + FID_BYTES + 1..].to_vec()))?;
+            self.store_def
+                .build_secondary_indices(&mut txn, &ts_hash, message)?;
+        }
+        self.db.commit(txn)?;
+
+        // A message the engine deleted after it was read would be left in the index, the
+        // engine's delete has already run, so its entries are removed again here
+        let mut txn = self.db.txn();
+        for (key, message) in &messages {
+            if self.db.get(key)?.is_none() {
+                let ts_hash = vec_to_u8_24(&Some(key[1 + FID_BYTES + 1..].to_vec()))?;
+                self.store_def
+                    .delete_secondary_indices(&mut txn, &ts_hash, message)?;
+            }
+        }
+        self.db.commit(txn)?;
+
+        Ok(IndexRebuildPage {
+            messages_indexed: messages.len() as u64,
+            fid: last_key
+                .as_ref()
+                .filter(|key| key.len() > FID_BYTES)
+                .map(|key| read_fid_key(key, 1))
+                .unwrap_or(0),
+            next_page_token: if all_done { None } else { last_key },
+        })
+    }
 }
 
 // Note about dispatch - The methods are dispatched to the Store struct, which is a Box<dyn StoreDef>.
@@ -2,7 +2,8 @@ use super::{
     is_message_in_time_range, make_user_key,
     name_registry_events::{
         delete_username_proof_transaction, get_fname_proof_by_fid, get_username_proof,
-        put_username_proof_transaction,
+        get_username_proof_history, make_fname_username_proof_by_fid_key,
+        put_username_proof_history_transaction, put_username_proof_transaction,
     },
     store::{Store, StoreDef},
     MessagesPage, StoreEventHandler,
@@ -241,6 +242,19 @@ impl UserDataStore {
         get_fname_proof_by_fid(&store.db(), fid)
     }
 
+    /// Every fname proof merged for the name, oldest first. Proofs with fid 0 mark the name being
+    /// released.
+    pub fn get_username_proof_history(
+        store: &Store<UserDataStoreDef>,
+        name: &[u8],
+    ) -> Result<Vec<UserNameProof>, HubError> {
+        get_username_proof_history(
+            &store.db(),
+            proto::UserNameType::UsernameTypeFname as i32,
+            name,
+        )
+    }
+
     pub fn merge_username_proof(
         store: &Store<UserDataStoreDef>,
         username_proof: &UserNameProof,
@@ -278,8 +292,17 @@ impl UserDataStore {
         if username_proof.fid == 0 {
             delete_username_proof_transaction(txn, username_proof, existing_fid);
         } else {
+            // A transfer, the previous owner no longer has the name
+            if let Some(existing_fid) = existing_fid.filter(|fid| *fid != username_proof.fid) {
+                txn.delete(make_fname_username_proof_by_fid_key(existing_fid));
+            }
             put_username_proof_transaction(txn, username_proof);
         }
+        put_username_proof_history_transaction(
+            txn,
+            proto::UserNameType::UsernameTypeFname as i32,
+            username_proof,
+        );
 
         let mut hub_event = HubEvent::from(
             HubEventType::MergeUsernameProof,
@@ -1,5 +1,7 @@
 use super::{
-    get_from_db_or_txn, get_message, make_fid_key, make_user_key, read_fid_key,
+    get_from_db_or_txn, get_message, make_fid_key, make_user_key,
+    name_registry_events::{get_username_proof_history, put_username_proof_history_transaction},
+    read_fid_key,
     store::{Store, StoreDef},
     IntoU8, MessagesPage, StoreEventHandler, TS_HASH_LENGTH,
 };
@@ -144,6 +146,12 @@ impl StoreDef for UsernameProofStoreDef {
                 by_name_key,
                 make_fid_key(message.data.as_ref().unwrap().fid),
             );
+            // Unlike the by name index, the history isn't touched when the proof is deleted
+            put_username_proof_history_transaction(
+                txn,
+                UserNameType::UsernameTypeEnsL1 as i32,
+                body,
+            );
             Ok(())
         } else {
             Err(HubError {
@@ -420,6 +428,15 @@ impl UsernameProofStore {
         store.get_add(&partial_message)
     }
 
+    /// Every ENS proof merged for the name, oldest first, including the ones that were since
+    /// replaced or revoked
+    pub fn get_username_proof_history(
+        store: &Store<UsernameProofStoreDef>,
+        name: &[u8],
+    ) -> Result<Vec<proto::UserNameProof>, HubError> {
+        get_username_proof_history(&store.db(), UserNameType::UsernameTypeEnsL1 as i32, name)
+    }
+
     pub fn get_username_proofs_by_fid(
         store: &Store<UsernameProofStoreDef>,
         fid: u64,
@@ -110,10 +110,12 @@ impl StoreDef for VerificationStoreDef {
         }
 
         // Puts the fid into the byAddress index
+        let fid = message.data.as_ref().unwrap().fid;
         let by_address_key = Self::make_verification_by_address_key(address);
+        txn.put(by_address_key, make_fid_key(fid));
         txn.put(
-            by_address_key,
-            make_fid_key(message.data.as_ref().unwrap().fid),
+            Self::make_fids_by_verified_address_key(address, fid),
+            vec![],
         );
 
         Ok(())
@@ -146,6 +148,10 @@ impl StoreDef for VerificationStoreDef {
         // Delete the message key from byAddress index
         let by_address_key = Self::make_verification_by_address_key(address);
         txn.delete(by_address_key);
+        txn.delete(Self::make_fids_by_verified_address_key(
+            address,
+            message.data.as_ref().unwrap().fid,
+        ));
 
         Ok(())
     }
@@ -220,6 +226,24 @@ impl VerificationStoreDef {
         key
     }
 
+    // Addresses are prefixed with their length, so a shorter address's fids aren't counted as a
+    // longer one's that starts with it
+    #[inline]
+    pub fn make_fids_by_verified_address_prefix(address: &[u8]) -> Vec<u8> {
+        let mut key = Vec::with_capacity(2 + address.len());
+        key.push(RootPrefix::FidsByVerifiedAddress as u8);
+        key.push(address.len() as u8);
+        key.extend_from_slice(address);
+        key
+    }
+
+    #[inline]
+    pub fn make_fids_by_verified_address_key(address: &[u8], fid: u64) -> Vec<u8> {
+        let mut key = Self::make_fids_by_verified_address_prefix(address);
+        key.extend_from_slice(&make_fid_key(fid));
+        key
+    }
+
     #[inline]
     pub fn make_verification_adds_key(fid: u64, address: &[u8]) -> Vec<u8> {
         let mut key = Vec::with_capacity(33 + 1 + address.len());
@@ -299,12 +323,45 @@ impl VerificationStore {
     }
 
     #[inline]
+    // Filtered while paging, so pages are full even when most verifications are left out
     pub fn get_verification_adds_by_fid(
         store: &Store<VerificationStoreDef>,
         fid: u64,
+        protocol: Option<Protocol>,
         page_options: &PageOptions,
     ) -> Result<MessagesPage, HubError> {
-        store.get_adds_by_fid::<fn(&Message) -> bool>(fid, page_options, None)
+        let filter = protocol.map(|protocol| {
+            move |message: &Message| match &message.data.as_ref().unwrap().body {
+                Some(Body::VerificationAddAddressBody(body)) => body.protocol == protocol as i32,
+                _ => false,
+            }
+        });
+        store.get_adds_by_fid(fid, page_options, filter)
+    }
+
+    /// The number of fids that verified the address on the store's shard
+    pub fn get_verified_address_fid_count(
+        store: &Store<VerificationStoreDef>,
+        address: &[u8],
+    ) -> Result<u32, HubError> {
+        store
+            .db()
+            .count_keys_at_prefix(VerificationStoreDef::make_fids_by_verified_address_prefix(
+                address,
+            ))
+    }
+
+    pub fn is_verified_address_fid(
+        store: &Store<VerificationStoreDef>,
+        address: &[u8],
+        fid: u64,
+    ) -> Result<bool, HubError> {
+        Ok(store
+            .db()
+            .get(&VerificationStoreDef::make_fids_by_verified_address_key(
+                address, fid,
+            ))?
+            .is_some())
     }
 
     #[inline]
@@ -0,0 +1,63 @@
+use crate::core::error::HubError;
+use crate::proto::{hub_event, HubEvent};
+use crate::storage::constants::UserPostfix;
+use crate::storage::db::{RocksDB, RocksDbTransactionBatch};
+use crate::storage::store::account::make_user_key;
+use std::collections::BTreeSet;
+
+fn make_activity_heights_key(fid: u64) -> Vec<u8> {
+    let mut key = make_user_key(fid);
+    key.push(UserPostfix::ActivityHeights.as_u8());
+    key
+}
+
+/// The first and last heights a fid's messages were merged at on its shard, kept up to date as
+/// chunks are committed. They're kept in the shard db outside the trie, so they only cover the
+/// chunks committed since nodes started tracking them, including by the node a snapshot was taken
+/// from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ActivityHeights {
+    pub first: u64,
+    pub last: u64,
+}
+
+impl ActivityHeights {
+    pub fn get(db: &RocksDB, fid: u64) -> Result<Option<Self>, HubError> {
+        match db.get(&make_activity_heights_key(fid))? {
+            Some(bytes) if bytes.len() == 16 => Ok(Some(ActivityHeights {
+                first: u64::from_be_bytes(bytes[..8].try_into().unwrap()),
+                last: u64::from_be_bytes(bytes[8..].try_into().unwrap()),
+            })),
+            Some(_) => Err(HubError::invalid_internal_state(
+                "invalid stored activity heights",
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Records the chunk at height as the last activity of every fid it merged messages for,
+    /// and as the first of the ones that had none yet
+    pub fn record(
+        db: &RocksDB,
+        txn: &mut RocksDbTransactionBatch,
+        height: u64,
+        events: &[HubEvent],
+    ) -> Result<(), HubError> {
+        let fids: BTreeSet<u64> = events
+            .iter()
+            .filter_map(|event| match &event.body {
+                Some(hub_event::Body::MergeMessageBody(body)) => {
+                    body.message.as_ref().map(|message| message.fid())
+                }
+                _ => None,
+            })
+            .collect();
+        for fid in fids {
+            let first = Self::get(db, fid)?.map_or(height, |heights| heights.first);
+            let mut value = first.to_be_bytes().to_vec();
+            value.extend_from_slice(&height.to_be_bytes());
+            txn.put(make_activity_heights_key(fid), value);
+        }
+        Ok(())
+    }
+}
@@ -56,6 +56,13 @@ fn make_block_timestamp_index(shard_index: u32, timestamp: u64) -> Vec<u8> {
     key
 }
 
+#[inline]
+fn make_block_hash_index(hash: &[u8]) -> Vec<u8> {
+    let mut key = vec![RootPrefix::BlockByHash as u8];
+    key.extend_from_slice(hash);
+    key
+}
+
 fn get_block_page_by_prefix(
     db: &RocksDB,
     page_options: &PageOptions,
@@ -178,6 +185,11 @@ pub fn put_block(db: &RocksDB, block: &Block) -> Result<(), BlockStorageError> {
         txn.put(timestamp_index_key, primary_key);
     }
 
+    txn.put(
+        make_block_hash_index(&block.hash),
+        height.block_number.to_be_bytes().to_vec(),
+    );
+
     db.commit(txn)?;
     Ok(())
 }
@@ -223,6 +235,23 @@ impl BlockStore {
         }
     }
 
+    // The index isn't cleaned up when blocks are pruned, so this returns None for pruned blocks
+    // even though the hash is known.
+    pub fn get_block_by_hash(&self, hash: &[u8]) -> Result<Option<Block>, BlockStorageError> {
+        match self.db.get(&make_block_hash_index(hash))? {
+            None => Ok(None),
+            Some(height) => {
+                let height = u64::from_be_bytes(
+                    height
+                        .as_slice()
+                        .try_into()
+                        .map_err(|_| BlockStorageError::BlockMissingHeight)?,
+                );
+                self.get_block_by_height(height)
+            }
+        }
+    }
+
     #[inline]
     pub fn min_block_number(&self) -> Result<u64, BlockStorageError> {
         let first_block = self.get_first_block()?;
@@ -353,6 +382,7 @@ mod tests {
         };
         Block {
             header: Some(header),
+            hash: block_number.to_be_bytes().repeat(4),
             ..Block::default()
         }
     }
@@ -390,6 +420,17 @@ mod tests {
         assert_eq!(5, next_height);
     }
 
+    #[test]
+    fn test_get_block_by_hash() {
+        let store = setup_db(10);
+        let block = store.get_block_by_height(5).unwrap().unwrap();
+
+        let block_by_hash = store.get_block_by_hash(&block.hash).unwrap().unwrap();
+        assert_eq!(block, block_by_hash);
+
+        assert_eq!(None, store.get_block_by_hash(&[0u8; 32]).unwrap());
+    }
+
     #[tokio::test]
     async fn test_prune_until() {
         let store = setup_db(100);
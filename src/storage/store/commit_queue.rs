@@ -0,0 +1,215 @@
+use crate::utils::statsd_wrapper::StatsdClientWrapper;
+use std::sync::mpsc;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use tracing::error;
+
+#[derive(Default)]
+struct QueueState {
+    // Pushed and not written yet, including the commit being written
+    pending: usize,
+    // Pushes waiting for room in the queue
+    waiting_pushes: usize,
+    // Of the first write that failed, nothing is written after it
+    error: Option<String>,
+}
+
+/// Writes committed chunks on a thread of its own, so consensus moves on to the next height while
+/// the previous ones are written. Up to depth commits wait to be written, past that pushing blocks
+/// until the oldest one is, so a db that can't keep up, e.g. while it's stalled on compactions,
+/// slows consensus down instead of commits piling up in memory. Commits are written in the order
+/// they're pushed.
+pub struct CommitQueue<T> {
+    depth: usize,
+    commits_tx: Option<mpsc::Sender<T>>,
+    state: Arc<(Mutex<QueueState>, Condvar)>,
+    writer: Option<JoinHandle<()>>,
+    shard_id: u32,
+    statsd_client: StatsdClientWrapper,
+}
+
+impl<T: Send + 'static> CommitQueue<T> {
+    pub fn new(
+        depth: u32,
+        shard_id: u32,
+        statsd_client: StatsdClientWrapper,
+        mut write: impl FnMut(T) -> Result<(), String> + Send + 'static,
+    ) -> Self {
+        let (commits_tx, commits_rx) = mpsc::channel::<T>();
+        let state = Arc::new((Mutex::new(QueueState::default()), Condvar::new()));
+        let writer_state = state.clone();
+        let writer_statsd_client = statsd_client.clone();
+        let writer = std::thread::Builder::new()
+            .name(format!("commit-writer-{}", shard_id))
+            .spawn(move || {
+                for commit in commits_rx {
+                    let (lock, written) = &*writer_state;
+                    let failed = lock.lock().unwrap().error.is_some();
+                    let result = if failed { Ok(()) } else { write(commit) };
+                    let mut state = lock.lock().unwrap();
+                    state.pending -= 1;
+                    if let Err(err) = result {
+                        error!(shard_id, "Unable to write committed chunks: {}", err);
+                        state.error = Some(err);
+                    }
+                    writer_statsd_client.gauge_with_shard(
+                        shard_id,
+                        "engine.commit_queue_depth",
+                        state.pending as u64,
+                    );
+                    written.notify_all();
+                }
+            })
+            .unwrap();
+        CommitQueue {
+            depth: depth.max(1) as usize,
+            commits_tx: Some(commits_tx),
+            state,
+            writer: Some(writer),
+            shard_id,
+            statsd_client,
+        }
+    }
+
+    /// Queues the commit to be written, waiting for room in the queue first. Once a write failed,
+    /// nothing more is queued and its error is returned.
+    pub fn push(&self, commit: T) -> Result<(), String> {
+        let (lock, written) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        if state.pending >= self.depth {
+            self.statsd_client
+                .count_with_shard(self.shard_id, "engine.commit_queue_full", 1);
+            state.waiting_pushes += 1;
+            written.notify_all();
+            state = written
+                .wait_while(state, |state| {
+                    state.error.is_none() && state.pending >= self.depth
+                })
+                .unwrap();
+            state.waiting_pushes -= 1;
+        }
+        if let Some(err) = &state.error {
+            return Err(err.clone());
+        }
+        state.pending += 1;
+        self.statsd_client.gauge_with_shard(
+            self.shard_id,
+            "engine.commit_queue_depth",
+            state.pending as u64,
+        );
+        drop(state);
+        self.commits_tx.as_ref().unwrap().send(commit).unwrap();
+        Ok(())
+    }
+
+    /// Waits for every commit pushed so far to be written, or returns the error of the write that
+    /// failed
+    pub fn wait_until_written(&self) -> Result<(), String> {
+        let (lock, written) = &*self.state;
+        let state = written
+            .wait_while(lock.lock().unwrap(), |state| {
+                state.error.is_none() && state.pending > 0
+            })
+            .unwrap();
+        match &state.error {
+            Some(err) => Err(err.clone()),
+            None => Ok(()),
+        }
+    }
+
+    /// Commits pushed and not written yet
+    pub fn pending(&self) -> usize {
+        self.state.0.lock().unwrap().pending
+    }
+
+    #[cfg(test)]
+    fn wait_for_waiting_push(&self) {
+        let (lock, written) = &*self.state;
+        let _state = written
+            .wait_while(lock.lock().unwrap(), |state| state.waiting_pushes == 0)
+            .unwrap();
+    }
+}
+
+impl<T> Drop for CommitQueue<T> {
+    // Writes the commits still queued before returning
+    fn drop(&mut self) {
+        drop(self.commits_tx.take());
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::store::test_helper;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[test]
+    fn test_pushing_waits_for_slow_writes() {
+        // Every write waits to be let through, like a db stalled on compactions
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let written = Arc::new(Mutex::new(vec![]));
+        let writer_written = written.clone();
+        let queue = Arc::new(CommitQueue::new(
+            2,
+            1,
+            test_helper::statsd_client(),
+            move |height: u64| {
+                release_rx.recv().unwrap();
+                writer_written.lock().unwrap().push(height);
+                Ok(())
+            },
+        ));
+
+        queue.push(1).unwrap();
+        queue.push(2).unwrap();
+        assert_eq!(queue.pending(), 2);
+
+        let pushed = Arc::new(AtomicBool::new(false));
+        let pusher = {
+            let queue = queue.clone();
+            let pushed = pushed.clone();
+            std::thread::spawn(move || {
+                queue.push(3).unwrap();
+                pushed.store(true, Ordering::SeqCst);
+            })
+        };
+        // The queue is full, so the third commit waits rather than being buffered
+        queue.wait_for_waiting_push();
+        assert!(!pushed.load(Ordering::SeqCst));
+        assert_eq!(queue.pending(), 2);
+
+        release_tx.send(()).unwrap();
+        pusher.join().unwrap();
+        assert!(pushed.load(Ordering::SeqCst));
+        assert!(queue.pending() <= 2);
+
+        release_tx.send(()).unwrap();
+        release_tx.send(()).unwrap();
+        queue.wait_until_written().unwrap();
+        assert_eq!(queue.pending(), 0);
+        assert_eq!(*written.lock().unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_failed_write_is_returned() {
+        let queue = CommitQueue::new(2, 1, test_helper::statsd_client(), |height: u64| {
+            if height == 2 {
+                Err("disk full".to_string())
+            } else {
+                Ok(())
+            }
+        });
+        queue.push(1).unwrap();
+        queue.push(2).unwrap();
+        assert_eq!(queue.wait_until_written(), Err("disk full".to_string()));
+
+        // Nothing is queued after it
+        assert_eq!(queue.push(3), Err("disk full".to_string()));
+        assert_eq!(queue.wait_until_written(), Err("disk full".to_string()));
+        assert_eq!(queue.pending(), 0);
+    }
+}
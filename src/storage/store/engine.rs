@@ -1,37 +1,50 @@
 use super::account::UsernameProofStore;
 use super::account::{IntoU8, OnchainEventStorageError, UserDataStore};
+use crate::consensus::consensus::SystemMessage;
+use crate::consensus::proposer::current_time;
+use crate::core::custom_network;
 use crate::core::error::HubError;
+use crate::core::protocol_features::{ActivationHeights, ProtocolFeature};
 use crate::core::types::Height;
+use crate::core::util::farcaster_time_to_unix_seconds;
 use crate::core::validations;
+use crate::core::validations::custom::MessageValidators;
 use crate::core::validations::verification;
-use crate::mempool::mempool::MempoolMessagesRequest;
+use crate::mempool::mempool::{MempoolMessagesRequest, MempoolRequest};
 use crate::proto::UserNameProof;
 use crate::proto::UserNameType;
 use crate::proto::{self, Block, MessageType, ShardChunk, Transaction};
 use crate::proto::{FarcasterNetwork, HubEvent};
 use crate::proto::{OnChainEvent, OnChainEventType};
 use crate::storage::db::{PageOptions, RocksDB, RocksDbTransactionBatch};
-use crate::storage::store::account::{CastStore, MessagesPage, OnchainEventStore};
-use crate::storage::store::stores::{StoreLimits, Stores};
+use crate::storage::store::account::{CastStore, MessagesPage, OnchainEventStore, Store, StoreDef};
+use crate::storage::store::activity_heights::ActivityHeights;
+use crate::storage::store::commit_queue::CommitQueue;
+use crate::storage::store::message_cache;
+use crate::storage::store::stores::{ShardFreeze, StoreLimits, Stores};
+use crate::storage::store::validator_stakes::ValidatorStakes;
 use crate::storage::store::BlockStore;
 use crate::storage::trie;
 use crate::storage::trie::merkle_trie;
 use crate::utils::statsd_wrapper::StatsdClientWrapper;
 use informalsystems_malachitebft_core_types::Round;
-use itertools::Itertools;
-use merkle_trie::TrieKey;
+use merkle_trie::{TrieKey, TrieUpdates};
+use prost::Message as _;
 use std::cmp::PartialEq;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::str;
 use std::string::ToString;
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use thiserror::Error;
 use tokio::sync::oneshot;
 use tokio::sync::{broadcast, mpsc};
 use tokio::time::timeout;
 use tracing::{debug, error, info, warn};
 
+// Caps the work message ttls add to a chunk, whatever is left over expires in the next ones
+const MAX_EXPIRED_MESSAGES_PER_CHUNK: usize = 1_000;
+
 #[derive(Error, Debug)]
 pub enum EngineError {
     #[error(transparent)]
@@ -57,6 +70,9 @@ pub enum EngineError {
 
     #[error(transparent)]
     EngineMessageValidationError(#[from] MessageValidationError),
+
+    #[error("chunk of {transactions} transactions and {bytes} bytes is over the block limits")]
+    BlockLimitExceeded { transactions: u64, bytes: u64 },
 }
 
 #[derive(Clone, Debug, PartialEq, strum_macros::Display)]
@@ -67,7 +83,8 @@ pub enum ProposalSource {
     Commit,
 }
 
-#[derive(Error, Debug, Clone)]
+#[derive(Error, Debug, Clone, strum_macros::IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
 pub enum MessageValidationError {
     #[error("message has no data")]
     NoMessageData,
@@ -89,6 +106,9 @@ pub enum MessageValidationError {
 
     #[error("fname is not registered for fid")]
     MissingFname,
+
+    #[error("message is {age}s older than the block, the limit is {max_age}s")]
+    MessageTooOld { age: u64, max_age: u64 },
 }
 
 #[derive(Clone, Debug)]
@@ -105,6 +125,18 @@ impl MempoolMessage {
         }
     }
 
+    // Validator stakes aren't tied to an fid, each shard weighs its votes by the ones it merged
+    pub fn merged_on_every_shard(&self) -> bool {
+        match self {
+            MempoolMessage::ValidatorMessage(msg) => {
+                msg.on_chain_event.as_ref().is_some_and(|event| {
+                    event.r#type() == proto::OnChainEventType::EventTypeValidatorStake
+                })
+            }
+            MempoolMessage::UserMessage(_) => false,
+        }
+    }
+
     pub fn to_proto(&self) -> proto::MempoolMessage {
         let msg = match self {
             MempoolMessage::UserMessage(msg) => {
@@ -125,6 +157,8 @@ pub struct ShardStateChange {
     pub new_state_root: Vec<u8>,
     pub transactions: Vec<Transaction>,
     pub events: Vec<HubEvent>,
+    // The timestamp of the shard chunk, in farcaster time
+    pub timestamp: u64,
 }
 
 #[derive(Clone)]
@@ -143,6 +177,49 @@ struct TransactionCounts {
     transactions: u64,
     user_messages: u64,
     system_messages: u64,
+    // Encoded size of the messages, user and system
+    bytes: u64,
+}
+
+/// Caps on the chunks a proposer builds, on top of max_messages_per_block, 0 leaves either
+/// uncapped. Bytes are the encoded size of a chunk's messages, which is what the mempool can
+/// account for as it hands them out. From the network block limits activation height on,
+/// validators reject proposals over [BlockLimits::NETWORK], whatever their own caps.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BlockLimits {
+    pub max_transactions: u32,
+    pub max_bytes: u64,
+}
+
+impl BlockLimits {
+    // The same on every node, so validators agree on which proposals are over them
+    pub const NETWORK: BlockLimits = BlockLimits {
+        max_transactions: 10_000,
+        max_bytes: 16 * 1024 * 1024,
+    };
+
+    // The caps, with the network's in place of the ones that are off or over them
+    pub fn within_network(&self) -> BlockLimits {
+        let cap = |limit: u64, network: u64| {
+            if limit == 0 {
+                network
+            } else {
+                limit.min(network)
+            }
+        };
+        BlockLimits {
+            max_transactions: cap(
+                self.max_transactions as u64,
+                Self::NETWORK.max_transactions as u64,
+            ) as u32,
+            max_bytes: cap(self.max_bytes, Self::NETWORK.max_bytes),
+        }
+    }
+
+    pub fn allows(&self, transactions: usize, bytes: u64) -> bool {
+        (self.max_transactions == 0 || transactions <= self.max_transactions as usize)
+            && (self.max_bytes == 0 || bytes <= self.max_bytes)
+    }
 }
 
 #[derive(Clone)]
@@ -152,6 +229,308 @@ struct CachedTransaction {
     txn: RocksDbTransactionBatch,
 }
 
+// The trie keys a merge, prune or revoke event inserts (true) and deletes (false), in the order
+// they're applied
+fn trie_changes_for_event(event: &proto::HubEvent) -> Vec<(Vec<u8>, bool)> {
+    let mut changes = vec![];
+    match &event.body {
+        Some(proto::hub_event::Body::MergeMessageBody(merge)) => {
+            if let Some(msg) = &merge.message {
+                changes.push((TrieKey::for_message(&msg), true));
+            }
+            for deleted_message in &merge.deleted_messages {
+                changes.push((TrieKey::for_message(&deleted_message), false));
+            }
+        }
+        Some(proto::hub_event::Body::MergeOnChainEventBody(merge)) => {
+            if let Some(onchain_event) = &merge.on_chain_event {
+                changes.push((TrieKey::for_onchain_event(&onchain_event), true));
+            }
+        }
+        Some(proto::hub_event::Body::PruneMessageBody(prune)) => {
+            if let Some(msg) = &prune.message {
+                changes.push((TrieKey::for_message(&msg), false));
+            }
+        }
+        Some(proto::hub_event::Body::RevokeMessageBody(revoke)) => {
+            if let Some(msg) = &revoke.message {
+                changes.push((TrieKey::for_message(&msg), false));
+            }
+        }
+        Some(proto::hub_event::Body::MergeUsernameProofBody(merge)) => {
+            if let Some(msg) = &merge.username_proof_message {
+                changes.push((TrieKey::for_message(&msg), true));
+            }
+            if let Some(msg) = &merge.deleted_username_proof_message {
+                changes.push((TrieKey::for_message(&msg), false));
+            }
+            if let Some(proof) = &merge.username_proof {
+                if proof.r#type == proto::UserNameType::UsernameTypeFname as i32 && proof.fid != 0
+                // Deletes should not be added to the trie
+                {
+                    let name = str::from_utf8(&proof.name).unwrap().to_string();
+                    changes.push((TrieKey::for_fname(proof.fid, &name), true));
+                }
+            }
+            if let Some(proof) = &merge.deleted_username_proof {
+                if proof.r#type == proto::UserNameType::UsernameTypeFname as i32 {
+                    let name = str::from_utf8(&proof.name).unwrap().to_string();
+                    changes.push((TrieKey::for_fname(proof.fid, &name), false));
+                }
+            }
+        }
+        Some(proto::hub_event::Body::MergeFailure(_)) => {
+            // Merge failures don't affect the trie. They are only for event subscribers
+        }
+        Some(proto::hub_event::Body::DrainingBody(_)) => {
+            // Only sent to subscribers when the node shuts down
+        }
+        &None => {
+            // This should never happen
+            panic!("No body in event");
+        }
+    }
+    changes
+}
+
+/// Applies the trie changes of a merge, prune or revoke event to the trie, in txn_batch
+pub(crate) fn update_trie_for_event(
+    trie: &mut merkle_trie::MerkleTrie,
+    ctx: &merkle_trie::Context,
+    db: &RocksDB,
+    event: &proto::HubEvent,
+    txn_batch: &mut RocksDbTransactionBatch,
+) -> Result<(), trie::errors::TrieError> {
+    for (key, insert) in trie_changes_for_event(event) {
+        if insert {
+            trie.insert(ctx, db, txn_batch, vec![&key])?;
+        } else {
+            trie.delete(ctx, db, txn_batch, vec![&key])?;
+        }
+    }
+    Ok(())
+}
+
+fn emit_events(
+    events_tx: &broadcast::Sender<HubEvent>,
+    shard_chunk: &ShardChunk,
+    events: Vec<HubEvent>,
+) {
+    for mut event in events {
+        event.timestamp = shard_chunk.header.as_ref().unwrap().timestamp;
+        // An error here just means there are no active receivers, which is fine and will happen if there are no active subscribe rpcs
+        let _ = events_tx.send(event);
+    }
+}
+
+// Collects the trie changes of an event, to be applied with the rest of its transaction's
+fn collect_trie_updates_for_event(event: &proto::HubEvent, updates: &mut TrieUpdates) {
+    for (key, insert) in trie_changes_for_event(event) {
+        if insert {
+            updates.insert(key);
+        } else {
+            updates.delete(key);
+        }
+    }
+}
+
+// How long a buffered commit waits to be written when commit batching has no window configured
+const DEFAULT_COMMIT_BATCH_WINDOW: Duration = Duration::from_secs(1);
+
+type BufferedCommits = Vec<(ShardChunk, Vec<HubEvent>)>;
+
+#[derive(Default)]
+struct UnflushedCommits {
+    // Committed chunks whose writes are still buffered in the db, with their events. The events
+    // are only emitted once the writes are flushed.
+    commits: BufferedCommits,
+    since: Option<Instant>,
+}
+
+// Writes the commits the engine buffers once batch_size of them are buffered, or once the oldest
+// has waited for window. A thread of its own checks the window too, so the last commits of a shard
+// that stopped committing are written as well.
+struct CommitWriter {
+    shard_id: u32,
+    db: Arc<RocksDB>,
+    events_tx: broadcast::Sender<HubEvent>,
+    statsd_client: StatsdClientWrapper,
+    batch_size: usize,
+    window: Duration,
+    unflushed: Mutex<UnflushedCommits>,
+    // Set when the writes happen on a thread of their own, rather than as they're due
+    commit_queue: Option<CommitQueue<BufferedCommits>>,
+    // Halted once a write fails, nothing is committed after it
+    shard_freeze: ShardFreeze,
+}
+
+impl CommitWriter {
+    fn new(
+        shard_id: u32,
+        db: Arc<RocksDB>,
+        events_tx: broadcast::Sender<HubEvent>,
+        statsd_client: StatsdClientWrapper,
+        batch_size: u32,
+        window: Duration,
+        commit_queue_depth: u32,
+        shard_freeze: ShardFreeze,
+    ) -> Arc<CommitWriter> {
+        let commit_queue = (commit_queue_depth > 0).then(|| {
+            let db = db.clone();
+            let events_tx = events_tx.clone();
+            let writer_statsd_client = statsd_client.clone();
+            let writer_shard_freeze = shard_freeze.clone();
+            CommitQueue::new(
+                commit_queue_depth,
+                shard_id,
+                statsd_client.clone(),
+                move |commits: BufferedCommits| {
+                    let now = Instant::now();
+                    if let Err(err) = db.flush() {
+                        // Halted right away rather than at the next push, which an idle shard
+                        // may not get to
+                        writer_shard_freeze.halt();
+                        writer_statsd_client.count_with_shard(shard_id, "engine.commit_halted", 1);
+                        return Err(err.to_string());
+                    }
+                    writer_statsd_client.time_with_shard(
+                        shard_id,
+                        "engine.flush_time",
+                        now.elapsed().as_millis() as u64,
+                    );
+                    for (shard_chunk, events) in commits {
+                        emit_events(&events_tx, &shard_chunk, events);
+                    }
+                    Ok(())
+                },
+            )
+        });
+        let writer = Arc::new(CommitWriter {
+            shard_id,
+            db,
+            events_tx,
+            statsd_client,
+            batch_size: batch_size.max(1) as usize,
+            window: if window.is_zero() {
+                DEFAULT_COMMIT_BATCH_WINDOW
+            } else {
+                window
+            },
+            unflushed: Mutex::new(UnflushedCommits::default()),
+            commit_queue,
+            shard_freeze,
+        });
+
+        // Only holds on to the writer while it checks it, so it stops once the engine is dropped
+        let weak_writer = Arc::downgrade(&writer);
+        std::thread::Builder::new()
+            .name(format!("commit-flusher-{}", shard_id))
+            .spawn(move || loop {
+                let Some(writer) = weak_writer.upgrade() else {
+                    return;
+                };
+                let wait = writer.write_if_due();
+                drop(writer);
+                std::thread::sleep(wait);
+            })
+            .unwrap();
+        writer
+    }
+
+    // Buffers the commit, with the chunk's own records kept away from reads until they're written
+    fn buffer(
+        &self,
+        shard_chunk: &ShardChunk,
+        events: Vec<HubEvent>,
+        txn: RocksDbTransactionBatch,
+        chunk_txn: RocksDbTransactionBatch,
+    ) {
+        let mut unflushed = self.unflushed.lock().unwrap();
+        if let Err(err) = self.db.buffer_commit_with_unreported(txn, chunk_txn) {
+            self.halt(err.to_string());
+            return;
+        }
+        unflushed.commits.push((shard_chunk.clone(), events));
+        let since = *unflushed.since.get_or_insert_with(Instant::now);
+        if unflushed.commits.len() >= self.batch_size || since.elapsed() >= self.window {
+            self.write(&mut unflushed);
+        }
+    }
+
+    // Writes the buffered commits if the oldest of them has waited long enough, and returns how
+    // long until the next ones could be due
+    fn write_if_due(&self) -> Duration {
+        let mut unflushed = self.unflushed.lock().unwrap();
+        match unflushed.since {
+            Some(since) if since.elapsed() >= self.window => {
+                self.write(&mut unflushed);
+                self.window
+            }
+            Some(since) => self.window - since.elapsed(),
+            None => self.window,
+        }
+    }
+
+    // Writes the buffered commits and waits for the commit queue to be written
+    fn flush(&self) {
+        self.write(&mut self.unflushed.lock().unwrap());
+        if let Some(commit_queue) = &self.commit_queue {
+            if let Err(err) = commit_queue.wait_until_written() {
+                self.halt(err);
+            }
+        }
+    }
+
+    // Stops the shard rather than the node, so the other shards carry on. The chunks that weren't
+    // written are synced again once the node is restarted.
+    fn halt(&self, err: String) {
+        if self.shard_freeze.is_halted() {
+            return;
+        }
+        error!(
+            shard_id = self.shard_id,
+            "Unable to write committed chunks, halting the shard: {}", err
+        );
+        self.shard_freeze.halt();
+        self.statsd_client
+            .count_with_shard(self.shard_id, "engine.commit_halted", 1);
+    }
+
+    // Hands the buffered commits to the commit queue, or writes them right away without one
+    fn write(&self, unflushed: &mut UnflushedCommits) {
+        if unflushed.commits.is_empty() {
+            return;
+        }
+        let commits = std::mem::take(&mut unflushed.commits);
+        unflushed.since = None;
+        self.statsd_client.count_with_shard(
+            self.shard_id,
+            "engine.commit.flushed_chunks",
+            commits.len() as u64,
+        );
+        if let Some(commit_queue) = &self.commit_queue {
+            if let Err(err) = commit_queue.push(commits) {
+                self.halt(err);
+            }
+            return;
+        }
+
+        let now = Instant::now();
+        if let Err(err) = self.db.flush() {
+            self.halt(err.to_string());
+            return;
+        }
+        self.statsd_client.time_with_shard(
+            self.shard_id,
+            "engine.flush_time",
+            now.elapsed().as_millis() as u64,
+        );
+        for (shard_chunk, events) in commits {
+            emit_events(&self.events_tx, &shard_chunk, events);
+        }
+    }
+}
+
 pub struct ShardEngine {
     shard_id: u32,
     network: FarcasterNetwork,
@@ -160,8 +539,27 @@ pub struct ShardEngine {
     stores: Stores,
     statsd_client: StatsdClientWrapper,
     max_messages_per_block: u32,
+    block_limits: BlockLimits,
     messages_request_tx: Option<mpsc::Sender<MempoolMessagesRequest>>,
+    // Where messages pulled from the mempool that didn't fit in the proposal are sent back
+    requeue_tx: Option<mpsc::Sender<SystemMessage>>,
     pending_txn: Option<CachedTransaction>,
+    commit_batch_size: u32,
+    commit_batch_window: Duration,
+    commit_queue_depth: u32,
+    // Created with the first buffered commit
+    commit_writer: Option<Arc<CommitWriter>>,
+    // The last chunk committed with commit batching or the queue. Its records are only read from
+    // the store once they're written, the engine builds on it before that.
+    buffered_tip: Option<ShardChunk>,
+    message_ttls: Vec<(MessageType, Duration)>,
+    max_message_age: Option<Duration>,
+    validator_stakes: Option<ValidatorStakes>,
+    message_validators: MessageValidators,
+    trie_batching: bool,
+    // The trie changes of the transaction being applied, while they're batched
+    trie_updates: Option<TrieUpdates>,
+    activation_heights: ActivationHeights,
 }
 
 impl ShardEngine {
@@ -190,11 +588,118 @@ impl ShardEngine {
             db,
             statsd_client,
             max_messages_per_block,
+            block_limits: BlockLimits::NETWORK,
             messages_request_tx,
+            requeue_tx: None,
             pending_txn: None,
+            commit_batch_size: 1,
+            commit_batch_window: Duration::ZERO,
+            commit_queue_depth: 0,
+            commit_writer: None,
+            buffered_tip: None,
+            message_ttls: vec![],
+            max_message_age: None,
+            validator_stakes: None,
+            message_validators: MessageValidators::default(),
+            trie_batching: false,
+            trie_updates: None,
+            activation_heights: custom_network::activation_heights(network),
         }
     }
 
+    /// The heights the protocol features apply from, the network's unless set
+    pub fn with_activation_heights(mut self, activation_heights: ActivationHeights) -> ShardEngine {
+        self.activation_heights = activation_heights;
+        self
+    }
+
+    // Whether the feature applies to the chunk being built or replayed
+    fn is_active(&self, feature: ProtocolFeature) -> bool {
+        self.activation_heights
+            .is_active(feature, self.stores.event_handler.current_height())
+    }
+
+    /// Flushes the store writes of up to batch_size consecutively committed chunks together, or
+    /// once the oldest of them has waited for window, a second if it's zero, whether or not more
+    /// chunks are committed. A chunk's events are emitted once it's flushed, and it's only read
+    /// from the shard store from then on, so a node never reports a height it could lose. A node
+    /// that stops in between resumes from the last flushed chunk.
+    pub fn with_commit_batching(mut self, batch_size: u32, window: Duration) -> ShardEngine {
+        self.commit_batch_size = batch_size.max(1);
+        self.commit_batch_window = window;
+        self
+    }
+
+    /// Writes the committed chunks, or batches of them, on a thread of their own, so the next
+    /// height can start while they're written. Once depth of them are waiting to be written,
+    /// committing waits for the oldest one. A chunk's events are only emitted once it's written.
+    /// 0 writes every chunk as it's committed.
+    pub fn with_commit_queue(mut self, depth: u32) -> ShardEngine {
+        self.commit_queue_depth = depth;
+        self
+    }
+
+    /// Keeps up to config.max_messages recently read messages of the shard in memory, for the
+    /// lookups of single messages by hash and casts by id through the stores. Removed messages
+    /// are evicted as soon as their removal is committed. Off by default.
+    pub fn with_message_cache(self, config: &message_cache::Config) -> ShardEngine {
+        self.stores.message_cache.enable(config);
+        self
+    }
+
+    /// Applies the trie changes of each transaction together, right before its account root is
+    /// taken, instead of as each of its messages is merged. The roots are the same either way.
+    pub fn with_trie_batching(mut self, enabled: bool) -> ShardEngine {
+        self.trie_batching = enabled;
+        self
+    }
+
+    /// Devnet only. Messages of these types are removed from every fid on the shard once they're
+    /// older than the ttl, as of the timestamp of the chunk being built or replayed.
+    pub fn with_message_ttls(mut self, message_ttls: Vec<(MessageType, Duration)>) -> ShardEngine {
+        self.message_ttls = message_ttls;
+        self
+    }
+
+    /// Rejects user messages timestamped more than max_age before the chunk they'd be merged in,
+    /// as of the timestamp of the chunk being built or replayed. Without it, a message of any age
+    /// can be merged, like a hub backfilling its history.
+    pub fn with_max_message_age(mut self, max_age: Option<Duration>) -> ShardEngine {
+        self.max_message_age = max_age;
+        self
+    }
+
+    /// Records the stakes each epoch of epoch_length heights weighs the shard's validators by, as
+    /// its last height is committed. Without it the validators are weighed equally.
+    pub fn with_validator_stakes(mut self, epoch_length: Option<u64>) -> ShardEngine {
+        self.validator_stakes =
+            epoch_length.map(|epoch_length| ValidatorStakes::new(self.db.clone(), epoch_length));
+        self
+    }
+
+    /// Custom networks only, run on user messages after the built-in validation
+    pub fn with_message_validators(mut self, message_validators: MessageValidators) -> ShardEngine {
+        self.message_validators = message_validators;
+        self
+    }
+
+    pub fn validator_stakes(&self) -> Option<ValidatorStakes> {
+        self.validator_stakes.clone()
+    }
+
+    /// Proposals are built within the limits, and the network's
+    pub fn with_block_limits(mut self, block_limits: BlockLimits) -> ShardEngine {
+        self.block_limits = block_limits.within_network();
+        self
+    }
+
+    /// Messages pulled from the mempool that don't fit in a proposal are added back to it
+    /// through tx, instead of being dropped
+    pub fn with_mempool_requeue(mut self, tx: mpsc::Sender<SystemMessage>) -> ShardEngine {
+        self.requeue_tx = Some(tx);
+        self
+    }
+
     pub fn shard_id(&self) -> u32 {
         self.shard_id
     }
@@ -223,6 +728,10 @@ impl ShardEngine {
         self.stores.clone()
     }
 
+    pub fn get_shard_freeze(&self) -> ShardFreeze {
+        self.stores.shard_freeze.clone()
+    }
+
     pub fn get_senders(&self) -> Senders {
         self.senders.clone()
     }
@@ -244,6 +753,7 @@ impl ShardEngine {
                     shard_id: self.shard_id,
                     message_tx,
                     max_messages_per_block: self.max_messages_per_block,
+                    block_limits: self.block_limits,
                 })
                 .await
             {
@@ -279,10 +789,11 @@ impl ShardEngine {
         txn_batch: &mut RocksDbTransactionBatch,
         shard_id: u32,
         messages: Vec<MempoolMessage>,
+        timestamp: u64,
     ) -> Result<ShardStateChange, EngineError> {
         self.count("prepare_proposal.recv_messages", messages.len() as u64);
 
-        let mut snapchain_txns = self.create_transactions_from_mempool(messages)?;
+        let mut snapchain_txns = self.create_transactions_from_mempool(messages, timestamp)?;
         let mut events = vec![];
         let mut validation_error_count = 0;
         for snapchain_txn in &mut snapchain_txns {
@@ -290,18 +801,21 @@ impl ShardEngine {
                 trie_ctx,
                 &snapchain_txn,
                 txn_batch,
+                timestamp,
                 ProposalSource::Propose,
             )?;
             snapchain_txn.account_root = account_root;
             events.extend(txn_events);
             validation_error_count += validation_errors.len();
         }
+        events.extend(self.expire_messages(trie_ctx, txn_batch, timestamp)?);
 
         let count = Self::txn_counts(&snapchain_txns);
 
         self.count("prepare_proposal.transactions", count.transactions);
         self.count("prepare_proposal.user_messages", count.user_messages);
         self.count("prepare_proposal.system_messages", count.system_messages);
+        self.count("prepare_proposal.block_bytes", count.bytes);
         self.count(
             "prepare_proposal.validation_errors",
             validation_error_count as u64,
@@ -313,6 +827,7 @@ impl ShardEngine {
             new_state_root: new_root_hash.clone(),
             transactions: snapchain_txns,
             events,
+            timestamp,
         };
 
         Ok(result)
@@ -322,12 +837,27 @@ impl ShardEngine {
     fn create_transactions_from_mempool(
         &mut self,
         messages: Vec<MempoolMessage>,
+        timestamp: u64,
     ) -> Result<Vec<Transaction>, EngineError> {
         let mut transactions = vec![];
-
-        let grouped_messages = messages.iter().into_group_map_by(|msg| msg.fid());
-        let unique_fids = grouped_messages.keys().len();
-        for (fid, messages) in grouped_messages {
+        let mut bytes = 0;
+
+        // Transactions come in the order the mempool handed out their fids' first messages, so
+        // the same messages always make the same block
+        let mut fids = vec![];
+        let mut grouped_messages: HashMap<u64, Vec<&MempoolMessage>> = HashMap::new();
+        for msg in &messages {
+            grouped_messages
+                .entry(msg.fid())
+                .or_insert_with(|| {
+                    fids.push(msg.fid());
+                    vec![]
+                })
+                .push(msg);
+        }
+        let unique_fids = fids.len();
+        for fid in fids {
+            let messages = grouped_messages.remove(&fid).unwrap_or_default();
             let mut transaction = Transaction {
                 fid: fid as u64,
                 account_root: vec![], // Starts empty, will be updated after replay
@@ -337,7 +867,7 @@ impl ShardEngine {
             let storage_slot = self
                 .stores
                 .onchain_event_store
-                .get_storage_slot_for_fid(fid)?;
+                .get_storage_slot_for_fid_at(fid, farcaster_time_to_unix_seconds(timestamp))?;
             for msg in messages {
                 match msg {
                     MempoolMessage::ValidatorMessage(msg) => {
@@ -345,15 +875,28 @@ impl ShardEngine {
                     }
                     MempoolMessage::UserMessage(msg) => {
                         // Only include messages for users that have storage
-                        if storage_slot.is_active() {
+                        if storage_slot.is_active_at(farcaster_time_to_unix_seconds(timestamp)) {
                             transaction.user_messages.push(msg.clone());
                         }
                     }
                 }
             }
-            if !transaction.user_messages.is_empty() || !transaction.system_messages.is_empty() {
-                transactions.push(transaction);
+            if transaction.user_messages.is_empty() && transaction.system_messages.is_empty() {
+                continue;
+            }
+            // The mempool already hands out messages within the limits, this only leaves out
+            // messages proposed from elsewhere
+            let transaction_bytes = Self::message_bytes(&transaction);
+            if !self
+                .block_limits
+                .allows(transactions.len() + 1, bytes + transaction_bytes)
+            {
+                self.count("prepare_proposal.over_block_limits", 1);
+                self.requeue(transaction);
+                continue;
             }
+            bytes += transaction_bytes;
+            transactions.push(transaction);
         }
         info!(
             transactions = transactions.len(),
@@ -364,6 +907,33 @@ impl ShardEngine {
         Ok(transactions)
     }
 
+    // Sends the transaction's messages back to the mempool for a later proposal, which adds them
+    // back with the source they first came from
+    fn requeue(&self, transaction: Transaction) {
+        let Some(requeue_tx) = &self.requeue_tx else {
+            return;
+        };
+        let messages = transaction
+            .user_messages
+            .into_iter()
+            .map(MempoolMessage::UserMessage)
+            .chain(
+                transaction
+                    .system_messages
+                    .into_iter()
+                    .map(MempoolMessage::ValidatorMessage),
+            );
+        for message in messages {
+            match requeue_tx.try_send(SystemMessage::Mempool(MempoolRequest::Requeue(message))) {
+                Ok(()) => self.count("prepare_proposal.requeued", 1),
+                Err(err) => {
+                    warn!("Could not requeue message over the block limits: {}", err);
+                    self.count("prepare_proposal.requeue_failed", 1);
+                }
+            }
+        }
+    }
+
     pub fn start_round(&mut self, height: Height, _round: Round) {
         self.pending_txn = None;
         self.stores
@@ -375,6 +945,19 @@ impl ShardEngine {
         &mut self,
         shard: u32,
         messages: Vec<MempoolMessage>,
+    ) -> ShardStateChange {
+        self.propose_state_change_at(shard, messages, current_time())
+    }
+
+    // Proposes the state change for a shard chunk with the given timestamp. Everything that
+    // depends on time while building or replaying a chunk, like whether an fid's storage has
+    // expired, uses the chunk's timestamp rather than the local clock. Validators replay with the
+    // timestamp from the proposal, so they get the same result however skewed their clocks are.
+    pub fn propose_state_change_at(
+        &mut self,
+        shard: u32,
+        messages: Vec<MempoolMessage>,
+        timestamp: u64,
     ) -> ShardStateChange {
         let now = std::time::Instant::now();
         let mut txn = RocksDbTransactionBatch::new();
@@ -392,6 +975,7 @@ impl ShardEngine {
                 &mut txn,
                 shard,
                 messages,
+                timestamp,
             )
             .unwrap(); //TODO: don't unwrap()
 
@@ -469,6 +1053,7 @@ impl ShardEngine {
         txn_batch: &mut RocksDbTransactionBatch,
         transactions: &[Transaction],
         shard_root: &[u8],
+        timestamp: u64,
         source: ProposalSource,
     ) -> Result<Vec<HubEvent>, EngineError> {
         let now = std::time::Instant::now();
@@ -500,8 +1085,13 @@ impl ShardEngine {
         }
 
         for snapchain_txn in transactions {
-            let (account_root, txn_events, _) =
-                self.replay_snapchain_txn(trie_ctx, snapchain_txn, txn_batch, source.clone())?;
+            let (account_root, txn_events, _) = self.replay_snapchain_txn(
+                trie_ctx,
+                snapchain_txn,
+                txn_batch,
+                timestamp,
+                source.clone(),
+            )?;
             // Reject early if account roots fail to match (shard roots will definitely fail)
             if &account_root != &snapchain_txn.account_root {
                 warn!(
@@ -518,6 +1108,7 @@ impl ShardEngine {
             }
             events.extend(txn_events);
         }
+        events.extend(self.expire_messages(trie_ctx, txn_batch, timestamp)?);
 
         let root1 = self.stores.trie.root_hash()?;
         if &root1 != shard_root {
@@ -543,6 +1134,28 @@ impl ShardEngine {
         trie_ctx: &merkle_trie::Context,
         snapchain_txn: &Transaction,
         txn_batch: &mut RocksDbTransactionBatch,
+        timestamp: u64,
+        source: ProposalSource,
+    ) -> Result<(Vec<u8>, Vec<HubEvent>, Vec<MessageValidationError>), EngineError> {
+        self.trie_updates = self.trie_batching.then(TrieUpdates::default);
+        let result = self.replay_snapchain_txn_messages(
+            trie_ctx,
+            snapchain_txn,
+            txn_batch,
+            timestamp,
+            source,
+        );
+        // Changes left from a transaction that failed partway are dropped with the rest of it
+        self.trie_updates = None;
+        result
+    }
+
+    fn replay_snapchain_txn_messages(
+        &mut self,
+        trie_ctx: &merkle_trie::Context,
+        snapchain_txn: &Transaction,
+        txn_batch: &mut RocksDbTransactionBatch,
+        timestamp: u64,
         source: ProposalSource,
     ) -> Result<(Vec<u8>, Vec<HubEvent>, Vec<MessageValidationError>), EngineError> {
         let now = std::time::Instant::now();
@@ -674,10 +1287,11 @@ impl ShardEngine {
             }
         }
 
+        let revoke_all = self.is_active(ProtocolFeature::RevokeAllSignerMessages);
         for key in revoked_signers {
-            let result = self
-                .stores
-                .revoke_messages(snapchain_txn.fid, &key, txn_batch);
+            let result =
+                self.stores
+                    .revoke_messages(snapchain_txn.fid, &key, revoke_all, txn_batch);
             match result {
                 Ok(revoke_events) => {
                     for event in revoke_events {
@@ -701,7 +1315,10 @@ impl ShardEngine {
 
         for msg in &snapchain_txn.user_messages {
             // Errors are validated based on the shard root
-            match self.validate_user_message(msg, txn_batch) {
+            match self
+                .validate_message_age(msg, timestamp)
+                .and_then(|_| self.validate_user_message(msg, txn_batch))
+            {
                 Ok(()) => {
                     let result = self.merge_message(msg, txn_batch);
                     match result {
@@ -743,7 +1360,7 @@ impl ShardEngine {
 
         for msg_type in message_types {
             let fid = snapchain_txn.fid;
-            let result = self.prune_messages(fid, msg_type, txn_batch);
+            let result = self.prune_messages(fid, msg_type, timestamp, txn_batch);
             match result {
                 Ok(pruned_events) => {
                     for event in pruned_events {
@@ -765,6 +1382,7 @@ impl ShardEngine {
             }
         }
 
+        self.apply_trie_updates(trie_ctx, txn_batch)?;
         let account_root =
             self.stores
                 .trie
@@ -815,7 +1433,11 @@ impl ShardEngine {
             MessageType::LinkAdd | MessageType::LinkRemove | MessageType::LinkCompactState => self
                 .stores
                 .link_store
-                .merge(msg, txn_batch)
+                .merge_with(
+                    msg,
+                    txn_batch,
+                    self.is_active(ProtocolFeature::CommutativeCompactState),
+                )
                 .map_err(|e| MessageValidationError::StoreError(e)),
             MessageType::ReactionAdd | MessageType::ReactionRemove => self
                 .stores
@@ -852,43 +1474,64 @@ impl ShardEngine {
         &mut self,
         fid: u64,
         msg_type: MessageType,
+        timestamp: u64,
         txn_batch: &mut RocksDbTransactionBatch,
     ) -> Result<Vec<HubEvent>, EngineError> {
+        let unix_timestamp = farcaster_time_to_unix_seconds(timestamp);
         let (current_count, max_count) = self
             .stores
-            .get_usage(fid, msg_type, txn_batch)
+            .get_usage(fid, msg_type, unix_timestamp, txn_batch)
+            .map_err(|_| EngineError::UsageCountError)?;
+        let max_bytes = self
+            .stores
+            .get_max_bytes(fid, msg_type, unix_timestamp)
             .map_err(|_| EngineError::UsageCountError)?;
 
         let events = match msg_type {
-            MessageType::CastAdd | MessageType::CastRemove => self
-                .stores
-                .cast_store
-                .prune_messages(fid, current_count, max_count, txn_batch)
-                .map_err(|e| EngineError::StoreError(e)),
-            MessageType::LinkAdd | MessageType::LinkRemove | MessageType::LinkCompactState => self
-                .stores
-                .link_store
-                .prune_messages(fid, current_count, max_count, txn_batch)
-                .map_err(|e| EngineError::StoreError(e)),
-            MessageType::ReactionAdd | MessageType::ReactionRemove => self
-                .stores
-                .reaction_store
-                .prune_messages(fid, current_count, max_count, txn_batch)
-                .map_err(|e| EngineError::StoreError(e)),
-            MessageType::UserDataAdd => self
-                .stores
-                .user_data_store
-                .prune_messages(fid, current_count, max_count, txn_batch)
-                .map_err(|e| EngineError::StoreError(e)),
-            MessageType::VerificationAddEthAddress | MessageType::VerificationRemove => self
-                .stores
-                .verification_store
-                .prune_messages(fid, current_count, max_count, txn_batch)
-                .map_err(|e| EngineError::StoreError(e)),
+            MessageType::CastAdd | MessageType::CastRemove => Self::prune_store(
+                &self.stores.cast_store,
+                fid,
+                (current_count, max_count),
+                max_bytes,
+                txn_batch,
+            ),
+            MessageType::LinkAdd | MessageType::LinkRemove | MessageType::LinkCompactState => {
+                Self::prune_store(
+                    &self.stores.link_store,
+                    fid,
+                    (current_count, max_count),
+                    max_bytes,
+                    txn_batch,
+                )
+            }
+            MessageType::ReactionAdd | MessageType::ReactionRemove => Self::prune_store(
+                &self.stores.reaction_store,
+                fid,
+                (current_count, max_count),
+                max_bytes,
+                txn_batch,
+            ),
+            MessageType::UserDataAdd => Self::prune_store(
+                &self.stores.user_data_store,
+                fid,
+                (current_count, max_count),
+                max_bytes,
+                txn_batch,
+            ),
+            MessageType::VerificationAddEthAddress | MessageType::VerificationRemove => {
+                Self::prune_store(
+                    &self.stores.verification_store,
+                    fid,
+                    (current_count, max_count),
+                    max_bytes,
+                    txn_batch,
+                )
+            }
             unhandled_type => {
                 return Err(EngineError::UnsupportedMessageType(unhandled_type));
             }
-        }?;
+        }
+        .map_err(|e| EngineError::StoreError(e))?;
 
         if !events.is_empty() {
             info!(
@@ -901,118 +1544,165 @@ impl ShardEngine {
         Ok(events)
     }
 
-    fn update_trie(
+    // Runs after a chunk's transactions, both when proposing and replaying it, so every node
+    // expires the same messages and the shard root includes it. The fids are gone through in
+    // order, which only scales to the number of fids on a devnet.
+    fn expire_messages(
         &mut self,
-        ctx: &merkle_trie::Context,
-        event: &proto::HubEvent,
+        trie_ctx: &merkle_trie::Context,
         txn_batch: &mut RocksDbTransactionBatch,
-    ) -> Result<(), EngineError> {
-        let now = std::time::Instant::now();
-        match &event.body {
-            Some(proto::hub_event::Body::MergeMessageBody(merge)) => {
-                if let Some(msg) = &merge.message {
-                    self.stores.trie.insert(
-                        ctx,
-                        &self.db,
-                        txn_batch,
-                        vec![&TrieKey::for_message(&msg)],
-                    )?;
-                }
-                for deleted_message in &merge.deleted_messages {
-                    self.stores.trie.delete(
-                        ctx,
-                        &self.db,
-                        txn_batch,
-                        vec![&TrieKey::for_message(&deleted_message)],
-                    )?;
-                }
-            }
-            Some(proto::hub_event::Body::MergeOnChainEventBody(merge)) => {
-                if let Some(onchain_event) = &merge.on_chain_event {
-                    self.stores.trie.insert(
-                        ctx,
-                        &self.db,
-                        txn_batch,
-                        vec![&TrieKey::for_onchain_event(&onchain_event)],
-                    )?;
-                }
-            }
-            Some(proto::hub_event::Body::PruneMessageBody(prune)) => {
-                if let Some(msg) = &prune.message {
-                    self.stores.trie.delete(
-                        ctx,
-                        &self.db,
-                        txn_batch,
-                        vec![&TrieKey::for_message(&msg)],
-                    )?;
-                }
-            }
-            Some(proto::hub_event::Body::RevokeMessageBody(revoke)) => {
-                if let Some(msg) = &revoke.message {
-                    self.stores.trie.delete(
-                        ctx,
-                        &self.db,
-                        txn_batch,
-                        vec![&TrieKey::for_message(&msg)],
-                    )?;
-                }
+        timestamp: u64,
+    ) -> Result<Vec<HubEvent>, EngineError> {
+        if self.message_ttls.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut fids = vec![];
+        let mut page_token = None;
+        loop {
+            let (page, next_page_token) =
+                self.stores.onchain_event_store.get_fids(&PageOptions {
+                    page_size: None,
+                    page_token,
+                    reverse: false,
+                })?;
+            fids.extend(page);
+            match next_page_token {
+                Some(token) => page_token = Some(token),
+                None => break,
             }
-            Some(proto::hub_event::Body::MergeUsernameProofBody(merge)) => {
-                if let Some(msg) = &merge.username_proof_message {
-                    self.stores.trie.insert(
-                        ctx,
-                        &self.db,
-                        txn_batch,
-                        vec![&TrieKey::for_message(&msg)],
-                    )?;
-                }
-                if let Some(msg) = &merge.deleted_username_proof_message {
-                    self.stores.trie.delete(
-                        ctx,
-                        &self.db,
-                        txn_batch,
-                        vec![&TrieKey::for_message(&msg)],
-                    )?;
+        }
+
+        let mut events = vec![];
+        for fid in fids {
+            for (msg_type, ttl) in self.message_ttls.clone() {
+                let limit = MAX_EXPIRED_MESSAGES_PER_CHUNK - events.len();
+                if limit == 0 {
+                    return Ok(events);
                 }
-                if let Some(proof) = &merge.username_proof {
-                    if proof.r#type == proto::UserNameType::UsernameTypeFname as i32
-                        && proof.fid != 0
-                    // Deletes should not be added to the trie
-                    {
-                        let name = str::from_utf8(&proof.name).unwrap().to_string();
-                        self.stores.trie.insert(
-                            ctx,
-                            &self.db,
-                            txn_batch,
-                            vec![&TrieKey::for_fname(proof.fid, &name)],
-                        )?;
+                let cutoff = timestamp.saturating_sub(ttl.as_secs()) as u32;
+                let expired = match msg_type {
+                    MessageType::CastAdd | MessageType::CastRemove => self
+                        .stores
+                        .cast_store
+                        .expire_messages(fid, msg_type, cutoff, limit, txn_batch),
+                    MessageType::LinkAdd | MessageType::LinkRemove => self
+                        .stores
+                        .link_store
+                        .expire_messages(fid, msg_type, cutoff, limit, txn_batch),
+                    MessageType::ReactionAdd | MessageType::ReactionRemove => self
+                        .stores
+                        .reaction_store
+                        .expire_messages(fid, msg_type, cutoff, limit, txn_batch),
+                    MessageType::UserDataAdd => self
+                        .stores
+                        .user_data_store
+                        .expire_messages(fid, msg_type, cutoff, limit, txn_batch),
+                    MessageType::VerificationAddEthAddress | MessageType::VerificationRemove => {
+                        self.stores
+                            .verification_store
+                            .expire_messages(fid, msg_type, cutoff, limit, txn_batch)
                     }
-                }
-                if let Some(proof) = &merge.deleted_username_proof {
-                    if proof.r#type == proto::UserNameType::UsernameTypeFname as i32 {
-                        let name = str::from_utf8(&proof.name).unwrap().to_string();
-                        self.stores.trie.delete(
-                            ctx,
-                            &self.db,
-                            txn_batch,
-                            vec![&TrieKey::for_fname(proof.fid, &name)],
-                        )?;
+                    unhandled_type => {
+                        return Err(EngineError::UnsupportedMessageType(unhandled_type));
                     }
+                }?;
+                if expired.is_empty() {
+                    continue;
                 }
-            }
-            Some(proto::hub_event::Body::MergeFailure(_)) => {
-                // Merge failures don't affect the trie. They are only for event subscribers
-            }
-            &None => {
-                // This should never happen
-                panic!("No body in event");
+
+                for event in &expired {
+                    self.update_trie(trie_ctx, event, txn_batch)?;
+                }
+                self.statsd_client.count_with_shard_and_tags(
+                    self.shard_id,
+                    "engine.messages_expired",
+                    &[("message_type", msg_type.as_str_name())],
+                    expired.len() as u64,
+                );
+                info!(
+                    fid = fid,
+                    msg_type = msg_type.into_u8(),
+                    count = expired.len(),
+                    "Expired messages"
+                );
+                events.extend(expired);
             }
         }
+        Ok(events)
+    }
+
+    // Prunes by message count, then by bytes if the store is also limited by size
+    fn prune_store<T: StoreDef + Clone>(
+        store: &Store<T>,
+        fid: u64,
+        (current_count, max_count): (u32, u32),
+        max_bytes: Option<u64>,
+        txn_batch: &mut RocksDbTransactionBatch,
+    ) -> Result<Vec<HubEvent>, HubError> {
+        let mut events = store.prune_messages(fid, current_count, max_count, txn_batch)?;
+        if let Some(max_bytes) = max_bytes {
+            // Measured after pruning by count, which deleted messages in the transaction
+            let current_bytes = store.get_tracked_bytes_used(fid, txn_batch)?;
+            events.extend(store.prune_messages_by_bytes(
+                fid,
+                current_bytes,
+                max_bytes,
+                txn_batch,
+            )?);
+        }
+        Ok(events)
+    }
+
+    fn update_trie(
+        &mut self,
+        ctx: &merkle_trie::Context,
+        event: &proto::HubEvent,
+        txn_batch: &mut RocksDbTransactionBatch,
+    ) -> Result<(), EngineError> {
+        let now = std::time::Instant::now();
+        match &mut self.trie_updates {
+            Some(updates) => collect_trie_updates_for_event(event, updates),
+            None => update_trie_for_event(&mut self.stores.trie, ctx, &self.db, event, txn_batch)?,
+        }
         let elapsed = now.elapsed();
         self.time_with_shard("update_trie_time_us", elapsed.as_micros() as u64);
         Ok(())
     }
 
+    fn apply_trie_updates(
+        &mut self,
+        ctx: &merkle_trie::Context,
+        txn_batch: &mut RocksDbTransactionBatch,
+    ) -> Result<(), EngineError> {
+        let Some(updates) = self.trie_updates.take() else {
+            return Ok(());
+        };
+        let now = std::time::Instant::now();
+        self.stores.trie.apply(ctx, &self.db, txn_batch, updates)?;
+        let elapsed = now.elapsed();
+        self.time_with_shard("apply_trie_updates_time_us", elapsed.as_micros() as u64);
+        Ok(())
+    }
+
+    fn validate_message_age(
+        &self,
+        message: &proto::Message,
+        timestamp: u64,
+    ) -> Result<(), MessageValidationError> {
+        let (Some(max_age), Some(message_data)) = (self.max_message_age, &message.data) else {
+            return Ok(());
+        };
+        let age = timestamp.saturating_sub(message_data.timestamp as u64);
+        if age > max_age.as_secs() {
+            return Err(MessageValidationError::MessageTooOld {
+                age,
+                max_age: max_age.as_secs(),
+            });
+        }
+        Ok(())
+    }
+
     pub(crate) fn validate_user_message(
         &self,
         message: &proto::Message,
@@ -1051,6 +1741,8 @@ impl ShardEngine {
             _ => {}
         }
 
+        self.message_validators.validate(message)?;
+
         let elapsed = now.elapsed();
         self.time_with_shard("validate_user_message_time_us", elapsed.as_micros() as u64);
         Ok(())
@@ -1140,13 +1832,26 @@ impl ShardEngine {
             count_fn("trie.mem_get_count.for_validate", read_count.1);
         };
 
-        let proposal_result = self.replay_proposal(
-            &merkle_trie::Context::with_callback(count_callback),
-            &mut txn,
-            transactions,
-            shard_root,
-            ProposalSource::Validate,
-        );
+        let counts = Self::txn_counts(transactions);
+        // Chunks before the activation height were only capped by their proposers
+        let proposal_result = if self.is_active(ProtocolFeature::NetworkBlockLimits)
+            && !BlockLimits::NETWORK.allows(counts.transactions as usize, counts.bytes)
+        {
+            self.count("validate.over_block_limits", 1);
+            Err(EngineError::BlockLimitExceeded {
+                transactions: counts.transactions,
+                bytes: counts.bytes,
+            })
+        } else {
+            self.replay_proposal(
+                &merkle_trie::Context::with_callback(count_callback),
+                &mut txn,
+                transactions,
+                shard_root,
+                shard_state_change.timestamp,
+                ProposalSource::Validate,
+            )
+        };
 
         match proposal_result {
             Err(err) => {
@@ -1181,29 +1886,111 @@ impl ShardEngine {
         &mut self,
         shard_chunk: &ShardChunk,
         events: Vec<HubEvent>,
-        txn: RocksDbTransactionBatch,
+        mut txn: RocksDbTransactionBatch,
     ) {
         let now = std::time::Instant::now();
-        self.db.commit(txn).unwrap();
-        for mut event in events {
-            event.timestamp = shard_chunk.header.as_ref().unwrap().timestamp;
-            // An error here just means there are no active receivers, which is fine and will happen if there are no active subscribe rpcs
-            let _ = self.senders.events_tx.send(event);
+        let height = shard_chunk
+            .header
+            .as_ref()
+            .unwrap()
+            .height
+            .unwrap()
+            .block_number;
+        if let Err(err) = ActivityHeights::record(&self.db, &mut txn, height, &events) {
+            error!("Unable to record activity heights {}", err)
         }
-        self.stores.trie.reload(&self.db).unwrap();
+        if let Some(validator_stakes) = &self.validator_stakes {
+            // Every validator needs the same weights, so the commit can't go ahead without them.
+            // Halts the shard rather than the node, like a commit that can't be written.
+            if let Err(err) = validator_stakes.record_epoch_end(height, &mut txn) {
+                error!(
+                    shard_id = self.shard_id,
+                    height, "Unable to record the validator stakes, halting the shard: {}", err
+                );
+                self.stores.shard_freeze.halt();
+                self.count("commit_halted", 1);
+            }
+        }
+        if self.stores.shard_freeze.is_halted() {
+            // The state this chunk builds on may not have been written, so it's synced again
+            // once the node restarts
+            error!(
+                shard_id = self.shard_id,
+                height, "Shard halted, not committing the chunk"
+            );
+            return;
+        }
+        let trie_commit_lock = self.stores.trie_commit_lock.clone();
+        let _trie_commit_guard = trie_commit_lock.lock().unwrap();
+        if self.commit_batch_size > 1 || self.commit_queue_depth > 0 {
+            self.buffer_commit(shard_chunk, events, txn);
+        } else {
+            self.db.commit(txn).unwrap();
+            self.stores.message_cache.evict_removed(&events);
+            emit_events(&self.senders.events_tx, shard_chunk, events);
+            self.stores.trie.reload(&self.db).unwrap();
 
-        _ = self.emit_commit_metrics(&shard_chunk);
+            _ = self.emit_commit_metrics(&shard_chunk);
 
-        match self.stores.shard_store.put_shard_chunk(shard_chunk) {
-            Err(err) => {
-                error!("Unable to write shard chunk to store {}", err)
+            match self.stores.shard_store.put_shard_chunk(shard_chunk) {
+                Err(err) => {
+                    error!("Unable to write shard chunk to store {}", err)
+                }
+                Ok(()) => {}
             }
-            Ok(()) => {}
         }
         let elapsed = now.elapsed();
         self.time_with_shard("commit_time", elapsed.as_millis() as u64);
     }
 
+    fn buffer_commit(
+        &mut self,
+        shard_chunk: &ShardChunk,
+        events: Vec<HubEvent>,
+        txn: RocksDbTransactionBatch,
+    ) {
+        // Written with the state, so a flushed batch never has one without the other, but only
+        // readable once it's written, so the height isn't reported before it's durable
+        let mut chunk_txn = RocksDbTransactionBatch::new();
+        if let Err(err) = self
+            .stores
+            .shard_store
+            .put_shard_chunk_in_txn(shard_chunk, &mut chunk_txn)
+        {
+            error!("Unable to write shard chunk to store {}", err)
+        }
+        let commit_writer = self
+            .commit_writer
+            .get_or_insert_with(|| {
+                CommitWriter::new(
+                    self.shard_id,
+                    self.db.clone(),
+                    self.senders.events_tx.clone(),
+                    self.statsd_client.clone(),
+                    self.commit_batch_size,
+                    self.commit_batch_window,
+                    self.commit_queue_depth,
+                    self.stores.shard_freeze.clone(),
+                )
+            })
+            .clone();
+        // Reads see buffered commits, so removed messages can't be served from the cache either
+        self.stores.message_cache.evict_removed(&events);
+        commit_writer.buffer(shard_chunk, events, txn, chunk_txn);
+        self.buffered_tip = Some(shard_chunk.clone());
+        self.stores.trie.reload(&self.db).unwrap();
+        _ = self.emit_commit_metrics(&shard_chunk);
+    }
+
+    /// Durably writes the buffered commits, then emits their events, and waits for the ones in
+    /// the commit queue to be written. Nothing to do unless commit batching or the queue are
+    /// enabled.
+    pub fn flush_commits(&mut self) {
+        if let Some(commit_writer) = &self.commit_writer {
+            commit_writer.flush();
+        }
+    }
+
     fn emit_commit_metrics(&mut self, shard_chunk: &&ShardChunk) -> Result<(), EngineError> {
         self.count("commit.invoked", 1);
 
@@ -1225,6 +2012,8 @@ impl ShardEngine {
         self.count("commit.transactions", counts.transactions);
         self.count("commit.user_messages", counts.user_messages);
         self.count("commit.system_messages", counts.system_messages);
+        self.count("commit.block_bytes", counts.bytes);
+        self.gauge("block_bytes", counts.bytes);
 
         // useful to see on perf test dashboards
         self.gauge(
@@ -1247,6 +2036,7 @@ impl ShardEngine {
         let mut txn = RocksDbTransactionBatch::new();
 
         let shard_root = &shard_chunk.header.as_ref().unwrap().shard_root;
+        let timestamp = shard_chunk.header.as_ref().unwrap().timestamp;
         let transactions = &shard_chunk.transactions;
 
         let count_fn = Self::make_count_fn(self.statsd_client.clone(), self.shard_id);
@@ -1293,6 +2083,7 @@ impl ShardEngine {
                 &mut txn,
                 transactions,
                 shard_root,
+                timestamp,
                 ProposalSource::Commit,
             ) {
                 Err(err) => {
@@ -1317,10 +2108,13 @@ impl ShardEngine {
             system_messages: vec![],
             user_messages: vec![message.clone()],
         };
+        // Not part of consensus, the message will be replayed with the timestamp of the chunk
+        // it's included in
         let result = self.replay_snapchain_txn(
             &merkle_trie::Context::new(),
             &snapchain_txn,
             &mut txn,
+            current_time(),
             ProposalSource::Simulate,
         );
 
@@ -1364,16 +2158,36 @@ impl ShardEngine {
         }
     }
 
+    // The height of the chunk committed last, unless it's already written
+    fn buffered_tip_height(&self, durable_height: u64) -> Option<u64> {
+        let tip = self.buffered_tip.as_ref()?;
+        let height = tip.header.as_ref()?.height?.block_number;
+        (height > durable_height).then_some(height)
+    }
+
+    /// The height of the last committed chunk, including one that isn't written yet with commit
+    /// batching or the queue. The shard store only has the written ones.
     pub fn get_confirmed_height(&self) -> Height {
-        match self.stores.shard_store.max_block_number() {
-            Ok(block_num) => Height::new(self.shard_id, block_num),
-            Err(_) => Height::new(self.shard_id, 0),
-        }
+        let durable_height = self.stores.shard_store.max_block_number().unwrap_or(0);
+        let height = self
+            .buffered_tip_height(durable_height)
+            .unwrap_or(durable_height);
+        Height::new(self.shard_id, height)
     }
 
+    /// The last committed chunk, including one that isn't written yet, like get_confirmed_height
     pub fn get_last_shard_chunk(&self) -> Option<ShardChunk> {
         match self.stores.shard_store.get_last_shard_chunk() {
-            Ok(shard_chunk) => shard_chunk,
+            Ok(shard_chunk) => {
+                let durable_height = shard_chunk
+                    .as_ref()
+                    .and_then(|chunk| chunk.header.as_ref()?.height)
+                    .map_or(0, |height| height.block_number);
+                match self.buffered_tip_height(durable_height) {
+                    Some(_) => self.buffered_tip.clone(),
+                    None => shard_chunk,
+                }
+            }
             Err(err) => {
                 error!("Unable to obtain last shard chunk {:#?}", err);
                 None
@@ -1485,9 +2299,16 @@ impl ShardEngine {
             transactions: txns.len() as u64,
             user_messages: user_count as u64,
             system_messages: system_count as u64,
+            bytes: txns.iter().map(Self::message_bytes).sum(),
         }
     }
 
+    fn message_bytes(txn: &Transaction) -> u64 {
+        let user_bytes: usize = txn.user_messages.iter().map(|m| m.encoded_len()).sum();
+        let system_bytes: usize = txn.system_messages.iter().map(|m| m.encoded_len()).sum();
+        (user_bytes + system_bytes) as u64
+    }
+
     pub fn trie_num_items(&mut self) -> usize {
         self.stores.trie.items().unwrap()
     }
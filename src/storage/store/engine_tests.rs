@@ -1,14 +1,18 @@
 #[cfg(test)]
 mod tests {
+    use crate::consensus::proposer::current_time;
+    use crate::core::protocol_features::ActivationHeights;
     use crate::core::util::{calculate_message_hash, from_farcaster_time, get_farcaster_time};
+    use crate::core::validations::custom::{MessageValidator, MessageValidators};
     use crate::proto::{self, ReactionType};
     use crate::proto::{FnameTransfer, ShardChunk, UserNameProof};
     use crate::proto::{HubEvent, ValidatorMessage};
     use crate::proto::{OnChainEvent, OnChainEventType};
-    use crate::storage::db::{PageOptions, RocksDbTransactionBatch};
-    use crate::storage::store::account::HubEventIdGenerator;
+    use crate::storage::db::{PageOptions, RocksDB, RocksDbTransactionBatch};
+    use crate::storage::store::account::{message_encode, HubEventIdGenerator};
     use crate::storage::store::engine::{MempoolMessage, ShardEngine};
-    use crate::storage::store::stores::StoreLimits;
+    use crate::storage::store::message_cache;
+    use crate::storage::store::stores::{Limits, StoreLimits};
     use crate::storage::store::test_helper::{
         self, default_custody_address, EngineOptions, FID3_FOR_TEST,
     };
@@ -19,6 +23,8 @@ mod tests {
     use crate::utils::factory::{self, events_factory, messages_factory, time, username_factory};
     use ed25519_dalek::{Signer, SigningKey};
     use prost::Message;
+    use std::sync::Arc;
+    use std::time::Duration;
 
     fn from_hex(s: &str) -> Vec<u8> {
         hex::decode(s).unwrap()
@@ -354,6 +360,83 @@ mod tests {
         assert_eq!(message_exists_in_trie(&mut engine, &msg1), true);
     }
 
+    #[tokio::test]
+    async fn test_proposals_do_not_depend_on_local_clock() {
+        let (mut proposer, _tmpdir1) = test_helper::new_engine();
+        let (mut validator, _tmpdir2) = test_helper::new_engine();
+        // The events are generated with random fields, so both engines need the same ones
+        let events = [
+            test_helper::default_storage_event(FID_FOR_TEST),
+            events_factory::create_id_register_event(
+                FID_FOR_TEST,
+                proto::IdRegisterEventType::Register,
+                default_custody_address(),
+                None,
+            ),
+            events_factory::create_signer_event(
+                FID_FOR_TEST,
+                test_helper::default_signer(),
+                proto::SignerEventType::Add,
+                None,
+                None,
+            ),
+        ];
+        for event in &events {
+            test_helper::commit_event(&mut proposer, event).await;
+            test_helper::commit_event(&mut validator, event).await;
+        }
+
+        let timestamp = messages_factory::farcaster_time();
+        let cast1 =
+            messages_factory::casts::create_cast_add(FID_FOR_TEST, "msg1", Some(timestamp), None);
+        let cast2 = messages_factory::casts::create_cast_add(
+            FID_FOR_TEST,
+            "msg2",
+            Some(timestamp + 1),
+            None,
+        );
+
+        // Both nodes build the same chunk from the same mempool and timestamp
+        let now = current_time();
+        let state_change = proposer.propose_state_change_at(
+            1,
+            vec![MempoolMessage::UserMessage(cast1.clone())],
+            now,
+        );
+        let other_state_change = validator.propose_state_change_at(
+            1,
+            vec![MempoolMessage::UserMessage(cast1.clone())],
+            now,
+        );
+        assert_eq!(state_change.transactions, other_state_change.transactions);
+        assert_eq!(
+            state_change.new_state_root,
+            other_state_change.new_state_root
+        );
+        assert_eq!(state_change.transactions[0].user_messages.len(), 1);
+        test_helper::validate_and_commit_state_change(&mut proposer, &state_change);
+        test_helper::validate_and_commit_state_change(&mut validator, &state_change);
+
+        // A proposer whose clock is two years ahead sees the fid's storage as expired and leaves
+        // the cast out. The validator's clock isn't skewed, but it replays the chunk with the
+        // proposed timestamp and agrees.
+        let skewed = now + 2 * 365 * 24 * 60 * 60;
+        let state_change = proposer.propose_state_change_at(
+            1,
+            vec![MempoolMessage::UserMessage(cast2.clone())],
+            skewed,
+        );
+        assert!(state_change
+            .transactions
+            .iter()
+            .all(|transaction| transaction.user_messages.is_empty()));
+        test_helper::validate_and_commit_state_change(&mut validator, &state_change);
+        test_helper::validate_and_commit_state_change(&mut proposer, &state_change);
+        assert_eq!(proposer.trie_root_hash(), validator.trie_root_hash());
+        assert_eq!(message_exists_in_trie(&mut validator, &cast1), true);
+        assert_eq!(message_exists_in_trie(&mut validator, &cast2), false);
+    }
+
     #[tokio::test]
     async fn test_engine_commit_delete_message() {
         let timestamp = messages_factory::farcaster_time();
@@ -395,6 +478,119 @@ mod tests {
         assert_eq!(message_exists_in_trie(&mut engine, &delete_cast), true);
     }
 
+    #[tokio::test]
+    async fn test_removal_evicts_the_cached_message() {
+        let timestamp = messages_factory::farcaster_time();
+        let cast =
+            messages_factory::casts::create_cast_add(FID_FOR_TEST, "hot", Some(timestamp), None);
+        let (engine, _tmpdir) = test_helper::new_engine();
+        let mut engine = engine.with_message_cache(&message_cache::Config {
+            max_messages: 100,
+            ttl: Duration::from_secs(60),
+        });
+        test_helper::register_user(
+            FID_FOR_TEST,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine,
+        )
+        .await;
+        commit_message(&mut engine, &cast).await;
+
+        let stores = engine.get_stores();
+        assert_eq!(
+            stores.get_message_by_hash(&cast.hash).unwrap(),
+            Some(cast.clone())
+        );
+        assert_eq!(
+            stores
+                .get_cast_add(FID_FOR_TEST, cast.hash.clone())
+                .unwrap(),
+            Some(cast.clone())
+        );
+        // Another fid's cast id doesn't match the cached cast
+        assert_eq!(
+            stores
+                .get_cast_add(FID2_FOR_TEST, cast.hash.clone())
+                .unwrap(),
+            None
+        );
+
+        let remove_cast = messages_factory::casts::create_cast_remove(
+            FID_FOR_TEST,
+            &cast.hash,
+            Some(timestamp + 1),
+            None,
+        );
+        commit_message(&mut engine, &remove_cast).await;
+        assert_eq!(stores.get_message_by_hash(&cast.hash).unwrap(), None);
+        assert_eq!(
+            stores
+                .get_cast_add(FID_FOR_TEST, cast.hash.clone())
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_store_count_matches_trie_leaf_count() {
+        let timestamp = messages_factory::farcaster_time();
+        let (mut engine, _tmpdir) = test_helper::new_engine();
+        test_helper::register_user(
+            FID_FOR_TEST,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine,
+        )
+        .await;
+        test_helper::register_fname(
+            FID_FOR_TEST,
+            &"farcaster".to_string(),
+            None,
+            &mut engine,
+            default_custody_address(),
+        )
+        .await;
+
+        let cast =
+            messages_factory::casts::create_cast_add(FID_FOR_TEST, "msg1", Some(timestamp), None);
+        let remove_cast = messages_factory::casts::create_cast_remove(
+            FID_FOR_TEST,
+            &cast.hash,
+            Some(timestamp + 1),
+            None,
+        );
+        let link_add = messages_factory::links::create_link_add(
+            FID_FOR_TEST,
+            "follow",
+            15,
+            Some(timestamp),
+            None,
+        );
+        let compact_state = messages_factory::links::create_link_compact_state(
+            FID_FOR_TEST,
+            "follow",
+            vec![15, 16],
+            Some(timestamp + 1),
+            None,
+        );
+        for message in [&cast, &remove_cast, &link_add, &compact_state] {
+            commit_message(&mut engine, message).await;
+        }
+
+        let stores = engine.get_stores();
+        let trie_leaf_count = stores.get_trie_leaf_count();
+        // 3 onchain events, the fname, the cast remove, the link and the compact state
+        assert_eq!(trie_leaf_count, 7);
+        assert_eq!(stores.get_store_count().unwrap(), trie_leaf_count);
+
+        // A message missing from the stores shows up as a mismatch
+        let mut txn = RocksDbTransactionBatch::new();
+        crate::storage::store::account::delete_message_transaction(&mut txn, &link_add).unwrap();
+        engine.db.commit(txn).unwrap();
+        assert_eq!(stores.get_store_count().unwrap(), trie_leaf_count - 1);
+    }
+
     #[tokio::test]
     async fn test_commit_link_messages() {
         let timestamp = messages_factory::farcaster_time();
@@ -999,6 +1195,64 @@ mod tests {
         .await;
     }
 
+    struct RejectCastText(&'static str);
+
+    impl MessageValidator for RejectCastText {
+        fn name(&self) -> &str {
+            "reject_cast_text"
+        }
+
+        fn validate(&self, message: &proto::Message) -> Result<(), String> {
+            match &message.data.as_ref().unwrap().body {
+                Some(proto::message_data::Body::CastAddBody(body)) if body.text == self.0 => {
+                    Err(format!("casts can't say {}", self.0))
+                }
+                _ => Ok(()),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_custom_message_validators() {
+        let validators: Vec<Arc<dyn MessageValidator>> = vec![Arc::new(RejectCastText("spam"))];
+        // Only custom networks take extra validators
+        assert!(
+            MessageValidators::new(proto::FarcasterNetwork::Mainnet, validators.clone()).is_err()
+        );
+        assert!(MessageValidators::new(proto::FarcasterNetwork::Mainnet, vec![]).is_ok());
+        let message_validators =
+            MessageValidators::new(proto::FarcasterNetwork::Custom, validators).unwrap();
+
+        let (engine, _tmpdir) = test_helper::new_engine();
+        let mut engine = engine.with_message_validators(message_validators);
+        register_user(
+            FID_FOR_TEST,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine,
+        )
+        .await;
+
+        let spam = messages_factory::casts::create_cast_add(FID_FOR_TEST, "spam", None, None);
+        let result = engine.simulate_message(&spam);
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("Rejected by reject_cast_text: casts can't say spam"));
+        assert_commit_fails(
+            &mut engine,
+            &spam,
+            "bad_request.validation_failure",
+            "Rejected by reject_cast_text: casts can't say spam",
+        )
+        .await;
+
+        let cast = messages_factory::casts::create_cast_add(FID_FOR_TEST, "hello", None, None);
+        assert!(engine.simulate_message(&cast).is_ok());
+        commit_message(&mut engine, &cast).await;
+        assert!(message_exists_in_trie(&mut engine, &cast));
+    }
+
     #[tokio::test]
     async fn test_messages_pruned_with_exceeded_storage() {
         let (mut engine, _tmpdir) = test_helper::new_engine();
@@ -1270,6 +1524,248 @@ mod tests {
         .await;
     }
 
+    // A username proof and a link compact state signed by a signer that is then removed
+    async fn remove_signer_of_proof_and_compact_state(
+        engine: &mut ShardEngine,
+    ) -> (proto::Message, proto::Message, Vec<HubEvent>) {
+        let signer = SigningKey::generate(&mut rand::rngs::OsRng);
+        test_helper::register_user(
+            FID_FOR_TEST,
+            signer.clone(),
+            test_helper::default_custody_address(),
+            engine,
+        )
+        .await;
+        let timestamp = factory::time::farcaster_time();
+        let username_proof = messages_factory::username_proof::create_username_proof(
+            FID_FOR_TEST,
+            proto::UserNameType::UsernameTypeFname,
+            "username".to_string(),
+            "owner".to_string().encode_to_vec(),
+            "signature".to_string(),
+            timestamp as u64,
+            Some(&signer),
+        );
+        let link_compact_state = messages_factory::links::create_link_compact_state(
+            FID_FOR_TEST,
+            "follow",
+            vec![FID2_FOR_TEST],
+            Some(timestamp),
+            Some(&signer),
+        );
+        commit_message(engine, &username_proof).await;
+        commit_message(engine, &link_compact_state).await;
+
+        let mut event_rx = engine.get_senders().events_tx.subscribe();
+        let remove_event = events_factory::create_signer_event(
+            FID_FOR_TEST,
+            signer,
+            proto::SignerEventType::Remove,
+            Some(timestamp + 1),
+            None,
+        );
+        test_helper::commit_event(engine, &remove_event).await;
+        let mut events = vec![];
+        while let Ok(event) = event_rx.try_recv() {
+            events.push(event);
+        }
+        (username_proof, link_compact_state, events)
+    }
+
+    #[tokio::test]
+    async fn test_revoking_a_signer_revokes_username_proofs() {
+        let (mut engine, _tmpdir) = test_helper::new_engine();
+        let (username_proof, link_compact_state, events) =
+            remove_signer_of_proof_and_compact_state(&mut engine).await;
+
+        // Username proofs are revoked with the merge event hubs emit for a deleted proof
+        let deleted_proofs: Vec<_> = events
+            .iter()
+            .filter_map(|event| match &event.body {
+                Some(proto::hub_event::Body::MergeUsernameProofBody(body)) => {
+                    assert_eq!(body.username_proof_message, None);
+                    body.deleted_username_proof_message.clone()
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(deleted_proofs, vec![username_proof.clone()]);
+
+        assert!(!message_exists_in_trie(&mut engine, &username_proof));
+        assert!(!message_exists_in_trie(&mut engine, &link_compact_state));
+        assert_eq!(
+            engine
+                .get_username_proofs_by_fid(FID_FOR_TEST)
+                .unwrap()
+                .messages
+                .len(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_signer_removes_before_activation_keep_proofs_and_compact_states() {
+        let (engine, _tmpdir) = test_helper::new_engine();
+        // Later than any height the test reaches
+        let mut engine = engine.with_activation_heights(ActivationHeights {
+            revoke_all_signer_messages: Some(1_000),
+            ..ActivationHeights::all()
+        });
+        let (username_proof, link_compact_state, events) =
+            remove_signer_of_proof_and_compact_state(&mut engine).await;
+
+        // Only the signer remove itself
+        assert_eq!(events.len(), 1);
+        assert!(message_exists_in_trie(&mut engine, &username_proof));
+        assert!(message_exists_in_trie(&mut engine, &link_compact_state));
+        assert_eq!(
+            engine
+                .get_username_proofs_by_fid(FID_FOR_TEST)
+                .unwrap()
+                .messages,
+            vec![username_proof]
+        );
+        assert_eq!(
+            engine
+                .get_link_compact_state_messages_by_fid(FID_FOR_TEST)
+                .unwrap()
+                .messages,
+            vec![link_compact_state]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_revoking_a_signer_revokes_messages_across_stores() {
+        let (mut engine, _tmpdir) = test_helper::new_engine();
+        let signer = SigningKey::generate(&mut rand::rngs::OsRng);
+        let another_signer = SigningKey::generate(&mut rand::rngs::OsRng);
+        test_helper::register_user(
+            FID_FOR_TEST,
+            signer.clone(),
+            test_helper::default_custody_address(),
+            &mut engine,
+        )
+        .await;
+        let another_signer_event = events_factory::create_signer_event(
+            FID_FOR_TEST,
+            another_signer.clone(),
+            proto::SignerEventType::Add,
+            None,
+            None,
+        );
+        test_helper::commit_event(&mut engine, &another_signer_event).await;
+
+        let timestamp = factory::time::farcaster_time();
+        let cast = messages_factory::casts::create_cast_add(
+            FID_FOR_TEST,
+            "cast",
+            Some(timestamp),
+            Some(&signer),
+        );
+        let reaction_add = messages_factory::reactions::create_reaction_add(
+            FID_FOR_TEST,
+            ReactionType::Like,
+            "https://example.com/a".to_string(),
+            Some(timestamp),
+            Some(&signer),
+        );
+        let reaction_remove = messages_factory::reactions::create_reaction_remove(
+            FID_FOR_TEST,
+            ReactionType::Like,
+            "https://example.com/b".to_string(),
+            Some(timestamp),
+            Some(&signer),
+        );
+        let link_compact_state = messages_factory::links::create_link_compact_state(
+            FID_FOR_TEST,
+            "follow",
+            vec![FID2_FOR_TEST],
+            Some(timestamp),
+            Some(&signer),
+        );
+        // Signed by a signer that stays active
+        let kept_reaction = messages_factory::reactions::create_reaction_add(
+            FID_FOR_TEST,
+            ReactionType::Recast,
+            "https://example.com/a".to_string(),
+            Some(timestamp),
+            Some(&another_signer),
+        );
+        let revoked_messages = vec![
+            cast.clone(),
+            reaction_add.clone(),
+            reaction_remove.clone(),
+            link_compact_state.clone(),
+        ];
+        for message in revoked_messages.iter().chain([&kept_reaction]) {
+            commit_message(&mut engine, message).await;
+            assert!(message_exists_in_trie(&mut engine, message));
+        }
+
+        let mut event_rx = engine.get_senders().events_tx.subscribe();
+        let remove_event = events_factory::create_signer_event(
+            FID_FOR_TEST,
+            signer.clone(),
+            proto::SignerEventType::Remove,
+            Some(timestamp + 1),
+            None,
+        );
+        test_helper::commit_event(&mut engine, &remove_event).await;
+        assert_onchain_hub_event(&event_rx.try_recv().unwrap(), &remove_event, 0);
+
+        let mut revoked_hashes = vec![];
+        while let Ok(event) = event_rx.try_recv() {
+            match &event.body {
+                Some(proto::hub_event::Body::RevokeMessageBody(body)) => {
+                    revoked_hashes.push(to_hex(&body.message.as_ref().unwrap().hash))
+                }
+                _ => panic!("Unexpected event type: {:?}", event.body),
+            }
+        }
+        revoked_hashes.sort();
+        let mut expected_hashes = revoked_messages
+            .iter()
+            .map(|message| to_hex(&message.hash))
+            .collect::<Vec<_>>();
+        expected_hashes.sort();
+        assert_eq!(revoked_hashes, expected_hashes);
+
+        // Revoked messages are gone from both the stores and the trie
+        for message in &revoked_messages {
+            assert!(!message_exists_in_trie(&mut engine, message));
+        }
+        assert!(message_exists_in_trie(&mut engine, &kept_reaction));
+        assert_eq!(
+            engine
+                .get_casts_by_fid(FID_FOR_TEST)
+                .unwrap()
+                .messages
+                .len(),
+            0
+        );
+        assert_eq!(
+            engine
+                .get_link_compact_state_messages_by_fid(FID_FOR_TEST)
+                .unwrap()
+                .messages
+                .len(),
+            0
+        );
+        let reactions = engine.get_reactions_by_fid(FID_FOR_TEST).unwrap().messages;
+        assert_eq!(reactions.len(), 1);
+        assert_eq!(reactions[0].hash, kept_reaction.hash);
+        let reaction_removes = engine
+            .get_stores()
+            .reaction_store
+            .get_removes_by_fid::<fn(&proto::Message) -> bool>(
+                FID_FOR_TEST,
+                &PageOptions::default(),
+                None,
+            )
+            .unwrap();
+        assert_eq!(reaction_removes.messages.len(), 0);
+    }
+
     #[tokio::test]
     async fn test_merge_fname() {
         let (mut engine, _tmpdir) = test_helper::new_engine();
@@ -1530,6 +2026,7 @@ mod tests {
         let single_message_limit = StoreLimits {
             limits: test_helper::limits::one(),
             legacy_limits: test_helper::limits::zero(),
+            byte_limits: None,
         };
         let (mut engine, _tmpdir) = test_helper::new_engine_with_options(EngineOptions {
             limits: Some(single_message_limit),
@@ -1581,6 +2078,579 @@ mod tests {
         .await;
     }
 
+    #[tokio::test]
+    async fn test_count_and_byte_based_storage_accounting() {
+        let timestamp = time::farcaster_time();
+        let tiny_casts: Vec<proto::Message> = (1..=4)
+            .map(|i| {
+                messages_factory::casts::create_cast_add(
+                    FID_FOR_TEST,
+                    &format!("tiny {}", i),
+                    Some(timestamp + i),
+                    None,
+                )
+            })
+            .collect();
+        let fat_casts: Vec<proto::Message> = (1..=2)
+            .map(|i| {
+                messages_factory::casts::create_cast_add(
+                    FID_FOR_TEST,
+                    &format!("{}{}", "x".repeat(300), i),
+                    Some(timestamp + 4 + i),
+                    None,
+                )
+            })
+            .collect();
+        let size = |message: &proto::Message| message_encode(message).len() as u64;
+        // Room for the last two tiny casts and both fat ones
+        let max_bytes =
+            size(&tiny_casts[2]) + size(&tiny_casts[3]) + fat_casts.iter().map(size).sum::<u64>();
+        assert!(size(&tiny_casts[0]) + size(&tiny_casts[1]) <= size(&fat_casts[1]));
+
+        let count_limits = StoreLimits {
+            limits: Limits {
+                casts: 3,
+                ..test_helper::limits::test()
+            },
+            legacy_limits: test_helper::limits::zero(),
+            byte_limits: None,
+        };
+        let byte_limits = StoreLimits {
+            limits: test_helper::limits::unlimited(),
+            legacy_limits: test_helper::limits::zero(),
+            byte_limits: Some(Limits {
+                casts: max_bytes as u32,
+                ..test_helper::limits::unlimited()
+            }),
+        };
+
+        let mut kept_casts = vec![];
+        let mut used_bytes = vec![];
+        for store_limits in [count_limits, byte_limits] {
+            let (mut engine, _tmpdir) = test_helper::new_engine_with_options(EngineOptions {
+                limits: Some(store_limits),
+                db: None,
+                messages_request_tx: None,
+            });
+            register_user(
+                FID_FOR_TEST,
+                test_helper::default_signer(),
+                test_helper::default_custody_address(),
+                &mut engine,
+            )
+            .await;
+            for cast in tiny_casts.iter().chain(fat_casts.iter()) {
+                commit_message(&mut engine, cast).await;
+            }
+
+            let casts = engine.get_casts_by_fid(FID_FOR_TEST).unwrap().messages;
+            let bytes = engine.get_stores().get_storage_bytes(FID_FOR_TEST).unwrap();
+            let cast_bytes = bytes
+                .usages
+                .iter()
+                .find(|usage| usage.store_type() == proto::StoreType::Casts)
+                .unwrap();
+            assert_eq!(cast_bytes.used, casts.iter().map(size).sum::<u64>());
+            assert_eq!(bytes.total_used, cast_bytes.used);
+            kept_casts.push(casts);
+            used_bytes.push(cast_bytes.clone());
+        }
+
+        // Counting messages keeps the 3 most recent casts, whatever their size
+        assert_eq!(
+            kept_casts[0],
+            vec![
+                tiny_casts[3].clone(),
+                fat_casts[0].clone(),
+                fat_casts[1].clone()
+            ]
+        );
+        assert_eq!(used_bytes[0].limit, None);
+
+        // Counting bytes only prunes the tiny casts needed to make room for the fat ones
+        assert_eq!(
+            kept_casts[1],
+            vec![
+                tiny_casts[2].clone(),
+                tiny_casts[3].clone(),
+                fat_casts[0].clone(),
+                fat_casts[1].clone()
+            ]
+        );
+        assert_eq!(used_bytes[1].used, max_bytes);
+        assert_eq!(used_bytes[1].limit, Some(max_bytes));
+    }
+
+    #[tokio::test]
+    async fn test_bytes_used_follows_removes() {
+        let timestamp = factory::time::farcaster_time();
+        let cast1 =
+            messages_factory::casts::create_cast_add(FID_FOR_TEST, "first", Some(timestamp), None);
+        let cast2 = messages_factory::casts::create_cast_add(
+            FID_FOR_TEST,
+            &"x".repeat(300),
+            Some(timestamp + 1),
+            None,
+        );
+        let remove = messages_factory::casts::create_cast_remove(
+            FID_FOR_TEST,
+            &cast1.hash,
+            Some(timestamp + 2),
+            None,
+        );
+        let size = |message: &proto::Message| message_encode(message).len() as u64;
+
+        let (mut engine, _tmpdir) = test_helper::new_engine_with_options(EngineOptions {
+            limits: Some(StoreLimits {
+                limits: test_helper::limits::unlimited(),
+                legacy_limits: test_helper::limits::zero(),
+                byte_limits: Some(test_helper::limits::unlimited()),
+            }),
+            db: None,
+            messages_request_tx: None,
+        });
+        register_user(
+            FID_FOR_TEST,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine,
+        )
+        .await;
+        let cast_bytes = |engine: &ShardEngine| {
+            engine
+                .get_stores()
+                .get_storage_bytes(FID_FOR_TEST)
+                .unwrap()
+                .usages
+                .iter()
+                .find(|usage| usage.store_type() == proto::StoreType::Casts)
+                .unwrap()
+                .used
+        };
+
+        commit_message(&mut engine, &cast1).await;
+        commit_message(&mut engine, &cast2).await;
+        assert_eq!(cast_bytes(&engine), size(&cast1) + size(&cast2));
+
+        // The remove replaces the add it targets
+        commit_message(&mut engine, &remove).await;
+        assert_eq!(cast_bytes(&engine), size(&cast2) + size(&remove));
+    }
+
+    #[tokio::test]
+    async fn test_network_namespaces_are_isolated() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let db_path = dir.path().join("shared.db");
+        let open_engine = |namespace: &str| {
+            let db = RocksDB::new(db_path.to_str().unwrap()).with_namespace(namespace);
+            db.open().unwrap();
+            test_helper::new_engine_with_options(EngineOptions {
+                limits: None,
+                db: Some(Arc::new(db)),
+                messages_request_tx: None,
+            })
+        };
+        let timestamp = time::farcaster_time();
+        let cast_a =
+            messages_factory::casts::create_cast_add(FID_FOR_TEST, "a", Some(timestamp), None);
+        let cast_b =
+            messages_factory::casts::create_cast_add(FID_FOR_TEST, "b", Some(timestamp + 1), None);
+
+        // The same fid registers and casts on both networks
+        for (namespace, cast) in [("network-a", &cast_a), ("network-b", &cast_b)] {
+            let (mut engine, _tmpdir) = open_engine(namespace);
+            assert!(engine
+                .get_casts_by_fid(FID_FOR_TEST)
+                .unwrap()
+                .messages
+                .is_empty());
+            register_user(
+                FID_FOR_TEST,
+                test_helper::default_signer(),
+                test_helper::default_custody_address(),
+                &mut engine,
+            )
+            .await;
+            commit_message(&mut engine, cast).await;
+            engine.db.close();
+        }
+
+        for (namespace, cast) in [("network-a", &cast_a), ("network-b", &cast_b)] {
+            let (engine, _tmpdir) = open_engine(namespace);
+            let casts = engine.get_casts_by_fid(FID_FOR_TEST).unwrap().messages;
+            assert_eq!(casts, vec![cast.clone()]);
+            engine.db.close();
+        }
+
+        // Nothing was written outside the namespaces
+        let (engine, _tmpdir) = open_engine("");
+        assert!(engine
+            .get_casts_by_fid(FID_FOR_TEST)
+            .unwrap()
+            .messages
+            .is_empty());
+        assert_eq!(engine.get_confirmed_height().block_number, 0);
+    }
+
+    #[tokio::test]
+    async fn test_commit_batching() {
+        let (engine, _tmpdir) = test_helper::new_engine();
+        let mut batched_engine = engine.with_commit_batching(3, Duration::from_secs(3600));
+        let (mut engine, _tmpdir2) = test_helper::new_engine();
+        for engine in [&mut engine, &mut batched_engine] {
+            register_user(
+                FID_FOR_TEST,
+                test_helper::default_signer(),
+                test_helper::default_custody_address(),
+                engine,
+            )
+            .await;
+        }
+        batched_engine.flush_commits();
+        let mut event_rx = batched_engine.get_senders().events_tx.subscribe();
+
+        let timestamp = time::farcaster_time();
+        let casts: Vec<proto::Message> = (0..3)
+            .map(|i| {
+                messages_factory::casts::create_cast_add(
+                    FID_FOR_TEST,
+                    &format!("cast {}", i),
+                    Some(timestamp + i),
+                    None,
+                )
+            })
+            .collect();
+        for cast in &casts[..2] {
+            commit_message(&mut engine, cast).await;
+            commit_message(&mut batched_engine, cast).await;
+        }
+
+        // The buffered chunks are readable and build on each other, but aren't acknowledged yet
+        assert_eq!(
+            batched_engine.get_confirmed_height(),
+            engine.get_confirmed_height()
+        );
+        // Nor reported from the shard store, e.g. by GetInfo or to syncing peers
+        let durable_height =
+            |engine: &ShardEngine| engine.get_stores().shard_store.max_block_number().unwrap();
+        let confirmed_height = engine.get_confirmed_height().block_number;
+        assert_eq!(durable_height(&batched_engine), confirmed_height - 2);
+        assert!(batched_engine
+            .get_stores()
+            .shard_store
+            .get_chunk_by_height(confirmed_height)
+            .unwrap()
+            .is_none());
+        assert_eq!(batched_engine.trie_root_hash(), engine.trie_root_hash());
+        assert_eq!(
+            batched_engine
+                .get_casts_by_fid(FID_FOR_TEST)
+                .unwrap()
+                .messages
+                .len(),
+            2
+        );
+        assert!(event_rx.try_recv().is_err());
+
+        // A full batch is flushed, with the events of every chunk in it
+        commit_message(&mut engine, &casts[2]).await;
+        commit_message(&mut batched_engine, &casts[2]).await;
+        assert_eq!(batched_engine.trie_root_hash(), engine.trie_root_hash());
+        assert_eq!(
+            durable_height(&batched_engine),
+            engine.get_confirmed_height().block_number
+        );
+        for cast in &casts {
+            assert_merge_event(&event_rx.try_recv().unwrap(), cast, 0);
+        }
+        assert!(event_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_commit_batching_window() {
+        let (engine, _tmpdir) = test_helper::new_engine();
+        let mut engine = engine.with_commit_batching(100, Duration::from_millis(50));
+        register_user(
+            FID_FOR_TEST,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine,
+        )
+        .await;
+        engine.flush_commits();
+        let mut event_rx = engine.get_senders().events_tx.subscribe();
+
+        let cast = messages_factory::casts::create_cast_add(FID_FOR_TEST, "idle", None, None);
+        commit_message(&mut engine, &cast).await;
+
+        // Nothing else is committed, the batch is still written once the window is over
+        let event = tokio::time::timeout(Duration::from_secs(5), event_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_merge_event(&event, &cast, 0);
+        assert_eq!(
+            engine.get_stores().shard_store.max_block_number().unwrap(),
+            engine.get_confirmed_height().block_number
+        );
+    }
+
+    #[tokio::test]
+    async fn test_commit_queue() {
+        let (engine, _tmpdir) = test_helper::new_engine();
+        let mut queued_engine = engine.with_commit_queue(2);
+        let (mut engine, _tmpdir2) = test_helper::new_engine();
+        for engine in [&mut engine, &mut queued_engine] {
+            register_user(
+                FID_FOR_TEST,
+                test_helper::default_signer(),
+                test_helper::default_custody_address(),
+                engine,
+            )
+            .await;
+        }
+        queued_engine.flush_commits();
+        let mut event_rx = queued_engine.get_senders().events_tx.subscribe();
+
+        let timestamp = time::farcaster_time();
+        let casts: Vec<proto::Message> = (0..3)
+            .map(|i| {
+                messages_factory::casts::create_cast_add(
+                    FID_FOR_TEST,
+                    &format!("cast {}", i),
+                    Some(timestamp + i),
+                    None,
+                )
+            })
+            .collect();
+        for cast in &casts {
+            commit_message(&mut engine, cast).await;
+            commit_message(&mut queued_engine, cast).await;
+        }
+        assert_eq!(queued_engine.trie_root_hash(), engine.trie_root_hash());
+        assert_eq!(
+            queued_engine.get_confirmed_height(),
+            engine.get_confirmed_height()
+        );
+
+        // Every chunk's events are emitted once it's written, in order, and only then is it
+        // reported from the shard store
+        queued_engine.flush_commits();
+        assert_eq!(
+            queued_engine
+                .get_stores()
+                .shard_store
+                .max_block_number()
+                .unwrap(),
+            engine.get_confirmed_height().block_number
+        );
+        assert!(!queued_engine.get_shard_freeze().is_halted());
+        for cast in &casts {
+            assert_merge_event(&event_rx.try_recv().unwrap(), cast, 0);
+        }
+        assert!(event_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_trie_batching() {
+        let (engine, _tmpdir) = test_helper::new_engine();
+        let mut batched_engine = engine.with_trie_batching(true);
+        let (mut engine, _tmpdir2) = test_helper::new_engine();
+        for engine in [&mut engine, &mut batched_engine] {
+            for fid in [FID_FOR_TEST, FID2_FOR_TEST] {
+                register_user(
+                    fid,
+                    test_helper::default_signer(),
+                    test_helper::default_custody_address(),
+                    engine,
+                )
+                .await;
+            }
+        }
+        assert_eq!(batched_engine.trie_root_hash(), engine.trie_root_hash());
+
+        let timestamp = time::farcaster_time();
+        let casts: Vec<proto::Message> = (0..5)
+            .map(|i| {
+                messages_factory::casts::create_cast_add(
+                    FID_FOR_TEST,
+                    &format!("cast {}", i),
+                    Some(timestamp + i),
+                    None,
+                )
+            })
+            .collect();
+        // Inserted and deleted within the same transaction
+        let remove = messages_factory::casts::create_cast_remove(
+            FID_FOR_TEST,
+            &casts[1].hash,
+            Some(timestamp + 10),
+            None,
+        );
+        // A transaction of its own
+        let other_fid_cast =
+            messages_factory::casts::create_cast_add(FID2_FOR_TEST, "other", Some(timestamp), None);
+        let messages: Vec<MempoolMessage> = casts
+            .iter()
+            .chain([&remove, &other_fid_cast])
+            .map(|msg| MempoolMessage::UserMessage(msg.clone()))
+            .collect();
+
+        // Each engine validates the other's chunk, so the account and shard roots have to match
+        let state_change = batched_engine.propose_state_change(1, messages);
+        test_helper::validate_and_commit_state_change(&mut batched_engine, &state_change);
+        test_helper::validate_and_commit_state_change(&mut engine, &state_change);
+        assert_eq!(batched_engine.trie_root_hash(), engine.trie_root_hash());
+        assert!(!message_exists_in_trie(&mut batched_engine, &casts[1]));
+        assert!(message_exists_in_trie(&mut batched_engine, &remove));
+        assert!(message_exists_in_trie(&mut batched_engine, &other_fid_cast));
+
+        let more = messages_factory::casts::create_cast_add(
+            FID_FOR_TEST,
+            "more",
+            Some(timestamp + 20),
+            None,
+        );
+        let state_change =
+            engine.propose_state_change(1, vec![MempoolMessage::UserMessage(more.clone())]);
+        test_helper::validate_and_commit_state_change(&mut engine, &state_change);
+        test_helper::validate_and_commit_state_change(&mut batched_engine, &state_change);
+        assert_eq!(batched_engine.trie_root_hash(), engine.trie_root_hash());
+        assert!(message_exists_in_trie(&mut batched_engine, &more));
+    }
+
+    #[tokio::test]
+    async fn test_message_ttl() {
+        let (engine, _tmpdir) = test_helper::new_engine();
+        let mut engine =
+            engine.with_message_ttls(vec![(proto::MessageType::CastAdd, Duration::from_secs(60))]);
+        register_user(
+            FID_FOR_TEST,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine,
+        )
+        .await;
+
+        let timestamp = time::farcaster_time();
+        let expired = messages_factory::casts::create_cast_add(
+            FID_FOR_TEST,
+            "old",
+            Some(timestamp - 120),
+            None,
+        );
+        let fresh =
+            messages_factory::casts::create_cast_add(FID_FOR_TEST, "new", Some(timestamp), None);
+        let reaction = messages_factory::reactions::create_reaction_add(
+            FID_FOR_TEST,
+            ReactionType::Like,
+            "https://example.com".to_string(),
+            Some(timestamp - 120),
+            None,
+        );
+        // Only messages committed before a chunk expire in it
+        test_helper::commit_messages(
+            &mut engine,
+            vec![expired.clone(), fresh.clone(), reaction.clone()],
+        )
+        .await;
+        let root_before = engine.trie_root_hash();
+        let mut event_rx = engine.get_senders().events_tx.subscribe();
+
+        // Validating the chunk replays it, so the expiry also has to match
+        let state_change = engine.propose_state_change(1, vec![]);
+        test_helper::validate_and_commit_state_change(&mut engine, &state_change);
+        assert_ne!(engine.trie_root_hash(), root_before);
+        assert!(!message_exists_in_trie(&mut engine, &expired));
+        assert!(message_exists_in_trie(&mut engine, &fresh));
+        // Reactions have no ttl
+        assert!(message_exists_in_trie(&mut engine, &reaction));
+        assert_eq!(
+            engine.get_casts_by_fid(FID_FOR_TEST).unwrap().messages,
+            vec![fresh.clone()]
+        );
+        assert_prune_event(&event_rx.try_recv().unwrap(), &expired, 0);
+        assert!(event_rx.try_recv().is_err());
+
+        // Nothing is left to expire
+        let root_after = engine.trie_root_hash();
+        let state_change = engine.propose_state_change(1, vec![]);
+        test_helper::validate_and_commit_state_change(&mut engine, &state_change);
+        assert_eq!(engine.trie_root_hash(), root_after);
+    }
+
+    #[tokio::test]
+    async fn test_max_message_age() {
+        let (engine, _tmpdir) = test_helper::new_engine();
+        let mut engine = engine.with_max_message_age(Some(Duration::from_secs(10)));
+        register_user(
+            FID_FOR_TEST,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine,
+        )
+        .await;
+
+        let timestamp = time::farcaster_time();
+        let at_limit = messages_factory::casts::create_cast_add(
+            FID_FOR_TEST,
+            "at the limit",
+            Some(timestamp - 10),
+            None,
+        );
+        let too_old = messages_factory::casts::create_cast_add(
+            FID_FOR_TEST,
+            "too old",
+            Some(timestamp - 11),
+            None,
+        );
+        let mut event_rx = engine.get_senders().events_tx.subscribe();
+        // The age is taken as of the chunk's timestamp, which validators replay with
+        let state_change = engine.propose_state_change_at(
+            1,
+            vec![
+                MempoolMessage::UserMessage(at_limit.clone()),
+                MempoolMessage::UserMessage(too_old.clone()),
+            ],
+            timestamp as u64,
+        );
+        test_helper::validate_and_commit_state_change(&mut engine, &state_change);
+        assert!(message_exists_in_trie(&mut engine, &at_limit));
+        assert!(!message_exists_in_trie(&mut engine, &too_old));
+
+        let mut merge_failures = vec![];
+        while let Ok(event) = event_rx.try_recv() {
+            if let Some(proto::hub_event::Body::MergeFailure(failure)) = event.body {
+                merge_failures.push((failure.message.unwrap().hash, failure.reason));
+            }
+        }
+        assert_eq!(
+            merge_failures,
+            vec![(
+                too_old.hash.clone(),
+                "message is 11s older than the block, the limit is 10s".to_string()
+            )]
+        );
+
+        // Without a limit, old messages are merged as before
+        let (engine, _tmpdir) = test_helper::new_engine();
+        let mut engine = engine.with_max_message_age(None);
+        register_user(
+            FID_FOR_TEST,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine,
+        )
+        .await;
+        let state_change = engine.propose_state_change_at(
+            1,
+            vec![MempoolMessage::UserMessage(too_old.clone())],
+            timestamp as u64,
+        );
+        test_helper::validate_and_commit_state_change(&mut engine, &state_change);
+        assert!(message_exists_in_trie(&mut engine, &too_old));
+    }
+
     #[tokio::test]
     async fn test_fname_validation() {
         let (mut engine, _tmpdir) = test_helper::new_engine();
@@ -0,0 +1,154 @@
+use crate::core::error::HubError;
+use crate::proto::{hub_event, HubEvent, Message};
+use moka::sync::{Cache, CacheBuilder};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Config {
+    // Messages kept in memory per shard for lookups by hash, 0 turns the cache off
+    pub max_messages: u64,
+    // A cached message is read from the db again after this long
+    #[serde(with = "humantime_serde")]
+    pub ttl: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_messages: 0,
+            ttl: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Keeps recently read messages in memory, so hot ones are served without reading the shard db.
+/// Messages never change once merged, but they can be removed, so the engine evicts every message
+/// a committed chunk removes as soon as reads see the chunk's writes. Lookups that read a message from the db
+/// while a removal is being written don't cache it, as it may be the removed one. Off until it's
+/// enabled.
+#[derive(Clone, Default)]
+pub struct MessageCache {
+    // Shared with every clone of the shard's stores, whenever it's enabled
+    cache: Arc<OnceLock<Cache<Vec<u8>, Message>>>,
+    removals: Arc<AtomicU64>,
+}
+
+impl MessageCache {
+    pub fn enable(&self, config: &Config) {
+        if config.max_messages == 0 {
+            return;
+        }
+        let _ = self.cache.set(
+            CacheBuilder::new(config.max_messages)
+                .time_to_live(config.ttl)
+                .build(),
+        );
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.cache.get().is_some()
+    }
+
+    /// Looks the message up in the cache, reading it with load and caching it on a miss. Returns
+    /// whether it was a hit along with the message.
+    pub fn get_or_load(
+        &self,
+        hash: &[u8],
+        load: impl FnOnce() -> Result<Option<Message>, HubError>,
+    ) -> Result<(bool, Option<Message>), HubError> {
+        let Some(cache) = self.cache.get() else {
+            return Ok((false, load()?));
+        };
+        if let Some(message) = cache.get(hash) {
+            return Ok((true, Some(message)));
+        }
+        let removals = self.removals.load(Ordering::SeqCst);
+        let message = load()?;
+        if let Some(message) = &message {
+            // A removal written since the read may have been of this message, and its eviction
+            // can run before or after this insert
+            if self.removals.load(Ordering::SeqCst) == removals {
+                cache.insert(hash.to_vec(), message.clone());
+            }
+        }
+        Ok((false, message))
+    }
+
+    /// Evicts the messages the events removed. Called once reads see the chunk they're from.
+    pub fn evict_removed(&self, events: &[HubEvent]) {
+        let Some(cache) = self.cache.get() else {
+            return;
+        };
+        let removed: Vec<&Message> = events
+            .iter()
+            .flat_map(|event| match &event.body {
+                Some(hub_event::Body::MergeMessageBody(body)) => {
+                    body.deleted_messages.iter().collect()
+                }
+                Some(hub_event::Body::PruneMessageBody(body)) => body.message.iter().collect(),
+                Some(hub_event::Body::RevokeMessageBody(body)) => body.message.iter().collect(),
+                Some(hub_event::Body::MergeUsernameProofBody(body)) => {
+                    body.deleted_username_proof_message.iter().collect()
+                }
+                _ => vec![],
+            })
+            .collect();
+        if removed.is_empty() {
+            return;
+        }
+        self.removals.fetch_add(1, Ordering::SeqCst);
+        for message in removed {
+            cache.invalidate(&message.hash);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::MergeMessageBody;
+    use crate::utils::factory::messages_factory;
+
+    fn removal(message: &Message) -> HubEvent {
+        HubEvent {
+            body: Some(hub_event::Body::MergeMessageBody(MergeMessageBody {
+                message: None,
+                deleted_messages: vec![message.clone()],
+            })),
+            ..HubEvent::default()
+        }
+    }
+
+    #[test]
+    fn test_read_during_a_removal_isnt_cached() {
+        let cache = MessageCache::default();
+        cache.enable(&Config {
+            max_messages: 100,
+            ttl: Duration::from_secs(60),
+        });
+        let cast = messages_factory::casts::create_cast_add(1234, "hot", None, None);
+
+        // The removal is written and evicted between the read and the insert
+        let (_, message) = cache
+            .get_or_load(&cast.hash, || {
+                cache.evict_removed(&[removal(&cast)]);
+                Ok(Some(cast.clone()))
+            })
+            .unwrap();
+        assert_eq!(message, Some(cast.clone()));
+        let (hit, message) = cache.get_or_load(&cast.hash, || Ok(None)).unwrap();
+        assert_eq!((hit, message), (false, None));
+
+        // Cached once nothing is removed in between
+        cache
+            .get_or_load(&cast.hash, || Ok(Some(cast.clone())))
+            .unwrap();
+        let (hit, message) = cache
+            .get_or_load(&cast.hash, || panic!("read from the db"))
+            .unwrap();
+        assert_eq!((hit, message), (true, Some(cast)));
+    }
+}
@@ -1,12 +1,18 @@
 pub use self::block::*;
 
 pub mod account;
+pub mod activity_heights;
 pub mod block;
+pub mod commit_queue;
 pub mod engine;
+pub mod message_cache;
 pub mod node_local_state;
+pub mod relayout;
+pub mod self_check;
 pub mod shard;
 pub mod stores;
 pub mod utils;
+pub mod validator_stakes;
 
 pub mod test_helper;
 
@@ -36,6 +36,8 @@ pub struct LocalStateStore {
 pub enum DataType {
     OnchainEvent = 1,
     FnameTransfer = 2,
+    // Kept in the shard dbs, see HubEvent::get_pruned_until
+    EventsPrunedUntil = 3,
 }
 
 impl LocalStateStore {
@@ -111,23 +113,45 @@ impl LocalStateStore {
         }
     }
 
-    pub fn set_latest_block_number(&self, block_number: u64) -> Result<(), LocalStateError> {
+    fn get_onchain_event_state(&self) -> Result<Option<OnChainEventState>, LocalStateError> {
+        match self.db.get(&Self::make_onchain_event_primary_key())? {
+            Some(state) => Ok(Some(OnChainEventState::decode(state.as_slice())?)),
+            None => Ok(None),
+        }
+    }
+
+    fn update_onchain_event_state(
+        &self,
+        update: impl FnOnce(&mut OnChainEventState),
+    ) -> Result<(), LocalStateError> {
+        let mut state = self.get_onchain_event_state()?.unwrap_or_default();
+        update(&mut state);
         Ok(self.db.put(
             &Self::make_onchain_event_primary_key(),
-            &OnChainEventState {
-                last_l2_block: block_number,
-            }
-            .encode_to_vec(),
+            &state.encode_to_vec(),
         )?)
     }
 
+    pub fn set_latest_block_number(&self, block_number: u64) -> Result<(), LocalStateError> {
+        self.update_onchain_event_state(|state| state.last_l2_block = block_number)
+    }
+
     pub fn get_latest_block_number(&self) -> Result<Option<u64>, LocalStateError> {
-        match self.db.get(&Self::make_onchain_event_primary_key())? {
-            Some(state) => Ok(Some(
-                OnChainEventState::decode(state.as_slice())?.last_l2_block,
-            )),
-            None => Ok(None),
-        }
+        Ok(self
+            .get_onchain_event_state()?
+            .map(|state| state.last_l2_block))
+    }
+
+    // The chain the stored onchain events came from. None for dbs from before it was recorded.
+    pub fn set_onchain_events_chain_id(&self, chain_id: u32) -> Result<(), LocalStateError> {
+        self.update_onchain_event_state(|state| state.chain_id = chain_id)
+    }
+
+    pub fn get_onchain_events_chain_id(&self) -> Result<Option<u32>, LocalStateError> {
+        Ok(self
+            .get_onchain_event_state()?
+            .map(|state| state.chain_id)
+            .filter(|chain_id| *chain_id != 0))
     }
 
     fn make_fname_transfer_primary_key() -> Vec<u8> {
@@ -0,0 +1,468 @@
+use crate::core::error::HubError;
+use crate::mempool::routing::MessageRouter;
+use crate::proto::{HubEvent, Message, MessageType, OnChainEventType};
+use crate::storage::constants::{RootPrefix, PAGE_SIZE_MAX};
+use crate::storage::db::{PageOptions, RocksDbTransactionBatch, RocksdbError};
+use crate::storage::store::account::{
+    get_onchain_events, read_fid_key, OnchainEventStorageError, Store, StoreDef, UserDataStore,
+    FID_BYTES,
+};
+use crate::storage::store::engine::update_trie_for_event;
+use crate::storage::store::stores::Stores;
+use crate::storage::trie::errors::TrieError;
+use crate::storage::trie::merkle_trie;
+use crate::storage::util::increment_vec_u8;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use thiserror::Error;
+use tracing::info;
+
+// Merges written to a target shard in one commit. Their events are dropped before committing, but
+// still take an id each, and ids only go up to 16384 per height.
+const MERGES_PER_COMMIT: usize = 1_000;
+
+const ONCHAIN_EVENT_TYPES: [OnChainEventType; 4] = [
+    OnChainEventType::EventTypeIdRegister,
+    OnChainEventType::EventTypeSigner,
+    OnChainEventType::EventTypeSignerMigrated,
+    OnChainEventType::EventTypeStorageRent,
+];
+
+#[derive(Error, Debug)]
+pub enum RelayoutError {
+    #[error(transparent)]
+    HubError(#[from] HubError),
+
+    #[error(transparent)]
+    OnchainEventStorageError(#[from] OnchainEventStorageError),
+
+    #[error(transparent)]
+    TrieError(#[from] TrieError),
+
+    #[error(transparent)]
+    RocksdbError(#[from] RocksdbError),
+
+    #[error("Shard {0} already has data, a relayout has to start from empty shards")]
+    TargetNotEmpty(u32),
+
+    #[error("No store for shard {0}, the target shards have to be numbered from 1")]
+    MissingTarget(u32),
+
+    #[error("Fid {fid} has data on shard {found}, but routes to shard {expected}")]
+    Misrouted { fid: u64, expected: u32, found: u32 },
+
+    #[error("The target shards have {found} {what}, expected {expected}")]
+    CountMismatch {
+        what: &'static str,
+        expected: u64,
+        found: u64,
+    },
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct RelayoutSummary {
+    pub fids: u64,
+    pub onchain_events: u64,
+    pub messages: u64,
+    pub fnames: u64,
+}
+
+// The merges for a target shard that haven't been committed yet
+struct PendingCommit {
+    txn: RocksDbTransactionBatch,
+    events: Vec<HubEvent>,
+}
+
+impl PendingCommit {
+    fn new() -> Self {
+        PendingCommit {
+            txn: RocksDbTransactionBatch::new(),
+            events: vec![],
+        }
+    }
+
+    fn push(&mut self, event: HubEvent, target: &mut Stores) -> Result<(), RelayoutError> {
+        self.events.push(event);
+        if self.events.len() >= MERGES_PER_COMMIT {
+            std::mem::replace(self, PendingCommit::new()).commit(target)?;
+        }
+        Ok(())
+    }
+
+    // Applies the merges to the trie and commits them along with it. The targets hold no history,
+    // so the events the merges wrote are left out.
+    fn commit(mut self, target: &mut Stores) -> Result<(), RelayoutError> {
+        if self.events.is_empty() {
+            return Ok(());
+        }
+        let ctx = merkle_trie::Context::new();
+        for event in &self.events {
+            update_trie_for_event(&mut target.trie, &ctx, &target.db, event, &mut self.txn)?;
+        }
+        let event_prefix = RootPrefix::HubEvents as u8;
+        self.txn
+            .batch
+            .retain(|key, _| key.first() != Some(&event_prefix));
+        target.db.commit(self.txn)?;
+        target.trie.reload(&target.db)?;
+        target.event_handler.set_current_height(0);
+        Ok(())
+    }
+}
+
+fn all_messages<T: StoreDef + Clone>(store: &Store<T>, fid: u64) -> Result<Vec<Message>, HubError> {
+    let mut messages = vec![];
+    let mut page_token = None;
+    loop {
+        let page = store.get_all_messages_by_fid(
+            fid,
+            None,
+            None,
+            &PageOptions {
+                page_size: Some(PAGE_SIZE_MAX),
+                page_token,
+                reverse: false,
+            },
+        )?;
+        messages.extend(page.messages);
+        page_token = page.next_page_token;
+        if page_token.is_none() {
+            return Ok(messages);
+        }
+    }
+}
+
+// Every fid with an onchain event on the shard. Messages are only merged for registered fids, so
+// this covers the fids with messages too.
+fn fids_on_shard(source: &Stores) -> Result<BTreeSet<u64>, RelayoutError> {
+    let mut fids = BTreeSet::new();
+    for event_type in ONCHAIN_EVENT_TYPES {
+        let mut page_token = None;
+        loop {
+            let page = get_onchain_events(
+                &source.db,
+                &PageOptions {
+                    page_size: Some(PAGE_SIZE_MAX),
+                    page_token,
+                    reverse: false,
+                },
+                event_type,
+                None,
+            )?;
+            fids.extend(page.onchain_events.iter().map(|event| event.fid));
+            page_token = page.next_page_token;
+            if page_token.is_none() {
+                break;
+            }
+        }
+    }
+    Ok(fids)
+}
+
+// Merges everything stored for the fid on the source into the target. Adds and removes are merged
+// before link compact states, which only keep the adds they list that are older than them.
+fn relayout_fid(
+    source: &Stores,
+    target: &mut Stores,
+    fid: u64,
+    summary: &mut RelayoutSummary,
+) -> Result<(), RelayoutError> {
+    let mut pending = PendingCommit::new();
+
+    for event_type in ONCHAIN_EVENT_TYPES {
+        for onchain_event in source
+            .onchain_event_store
+            .get_onchain_events(event_type, Some(fid))?
+        {
+            let event = target
+                .onchain_event_store
+                .merge_onchain_event(onchain_event, &mut pending.txn)?;
+            pending.push(event, target)?;
+            summary.onchain_events += 1;
+        }
+    }
+
+    let mut messages = vec![];
+    messages.extend(all_messages(&source.cast_store, fid)?);
+    messages.extend(all_messages(&source.link_store, fid)?);
+    messages.extend(all_messages(&source.reaction_store, fid)?);
+    messages.extend(all_messages(&source.user_data_store, fid)?);
+    messages.extend(all_messages(&source.verification_store, fid)?);
+    messages.extend(all_messages(&source.username_proof_store, fid)?);
+    messages.extend(
+        source
+            .link_store
+            .get_compact_state_messages_by_fid(fid, &PageOptions::default())?
+            .messages,
+    );
+    for message in &messages {
+        let txn = &mut pending.txn;
+        let event = match message.msg_type() {
+            MessageType::CastAdd | MessageType::CastRemove => target.cast_store.merge(message, txn),
+            MessageType::LinkAdd | MessageType::LinkRemove | MessageType::LinkCompactState => {
+                target.link_store.merge(message, txn)
+            }
+            MessageType::ReactionAdd | MessageType::ReactionRemove => {
+                target.reaction_store.merge(message, txn)
+            }
+            MessageType::UserDataAdd => target.user_data_store.merge(message, txn),
+            MessageType::VerificationAddEthAddress | MessageType::VerificationRemove => {
+                target.verification_store.merge(message, txn)
+            }
+            MessageType::UsernameProof => target.username_proof_store.merge(message, txn),
+            other => Err(HubError::invalid_parameter(&format!(
+                "unexpected message type {} in a store",
+                other.as_str_name()
+            ))),
+        }?;
+        pending.push(event, target)?;
+        summary.messages += 1;
+    }
+
+    if let Some(proof) = UserDataStore::get_username_proof_by_fid(&source.user_data_store, fid)? {
+        let event =
+            UserDataStore::merge_username_proof(&target.user_data_store, &proof, &mut pending.txn)?;
+        pending.push(event, target)?;
+        summary.fnames += 1;
+    }
+
+    pending.commit(target)
+}
+
+/// Checks that everything stored on the shard belongs to an fid that routes to it
+pub fn verify_layout(
+    stores: &Stores,
+    shard_id: u32,
+    num_shards: u32,
+    router: &dyn MessageRouter,
+) -> Result<(), RelayoutError> {
+    let check = |fid: u64| {
+        let expected = router.route_fid(fid, num_shards);
+        if expected == shard_id {
+            Ok(())
+        } else {
+            Err(RelayoutError::Misrouted {
+                fid,
+                expected,
+                found: shard_id,
+            })
+        }
+    };
+
+    let prefix = vec![RootPrefix::User as u8];
+    let mut misrouted = Ok(());
+    stores.db.for_each_iterator_by_prefix(
+        Some(prefix.clone()),
+        Some(increment_vec_u8(&prefix)),
+        &PageOptions::default(),
+        |key, _| {
+            if key.len() > FID_BYTES {
+                misrouted = check(read_fid_key(key, 1));
+            }
+            Ok(misrouted.is_err())
+        },
+    )?;
+    misrouted?;
+
+    for fid in fids_on_shard(stores)? {
+        check(fid)?;
+    }
+    Ok(())
+}
+
+/// Merges the state of the source shards into the target shards, each fid onto the shard the
+/// router assigns it with the number of targets, and builds the targets' tries from what's merged.
+/// The targets are keyed by shard id and must be empty. Only the fids' messages, onchain events
+/// and fnames move, the shard chunks and events stay behind, so the target shards start over from
+/// height 0. The sources are only read from.
+pub fn relayout(
+    sources: &[Stores],
+    targets: &mut HashMap<u32, Stores>,
+    router: &dyn MessageRouter,
+) -> Result<RelayoutSummary, RelayoutError> {
+    let num_shards = targets.len() as u32;
+    for shard_id in 1..=num_shards {
+        let target = targets
+            .get(&shard_id)
+            .ok_or(RelayoutError::MissingTarget(shard_id))?;
+        if target.get_store_count()? > 0 || target.get_trie_leaf_count() > 0 {
+            return Err(RelayoutError::TargetNotEmpty(shard_id));
+        }
+    }
+
+    let mut summary = RelayoutSummary::default();
+    let mut expected_count = 0;
+    // Validator stakes aren't tied to an fid, every shard has them
+    let mut stake_events = BTreeMap::new();
+    for source in sources {
+        let source_stake_events = source
+            .onchain_event_store
+            .get_onchain_events(OnChainEventType::EventTypeValidatorStake, None)?;
+        expected_count += source.get_store_count()? - source_stake_events.len() as u64;
+        for event in source_stake_events {
+            stake_events.insert((event.block_number, event.log_index), event);
+        }
+        let fids = fids_on_shard(source)?;
+        info!(
+            shard_id = source.shard_id,
+            fids = fids.len(),
+            "Moving the shard's fids to the new layout"
+        );
+        for fid in fids {
+            let shard_id = router.route_fid(fid, num_shards);
+            let target = targets
+                .get_mut(&shard_id)
+                .ok_or(RelayoutError::MissingTarget(shard_id))?;
+            relayout_fid(source, target, fid, &mut summary)?;
+            summary.fids += 1;
+        }
+    }
+    for target in targets.values_mut() {
+        let mut pending = PendingCommit::new();
+        for onchain_event in stake_events.values() {
+            let event = target
+                .onchain_event_store
+                .merge_onchain_event(onchain_event.clone(), &mut pending.txn)?;
+            pending.push(event, target)?;
+            summary.onchain_events += 1;
+        }
+        pending.commit(target)?;
+    }
+    expected_count += stake_events.len() as u64 * num_shards as u64;
+
+    let mut store_count = 0;
+    for (shard_id, target) in targets.iter() {
+        verify_layout(target, *shard_id, num_shards, router)?;
+        let target_count = target.get_store_count()?;
+        let leaf_count = target.get_trie_leaf_count();
+        if leaf_count != target_count {
+            return Err(RelayoutError::CountMismatch {
+                what: "trie leaves",
+                expected: target_count,
+                found: leaf_count,
+            });
+        }
+        store_count += target_count;
+    }
+    if store_count != expected_count {
+        return Err(RelayoutError::CountMismatch {
+            what: "messages, onchain events and fnames",
+            expected: expected_count,
+            found: store_count,
+        });
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mempool::routing::ShardRouter;
+    use crate::storage::store::account::CastStore;
+    use crate::storage::store::test_helper;
+    use crate::storage::trie::merkle_trie::TrieKey;
+    use crate::utils::factory::messages_factory;
+
+    fn cast_adds(stores: &Stores, fid: u64) -> Vec<Message> {
+        CastStore::get_cast_adds_by_fid(&stores.cast_store, fid, &PageOptions::default())
+            .unwrap()
+            .messages
+    }
+
+    #[tokio::test]
+    async fn test_relayout() {
+        // Two old shards, moved to three new ones. The router puts the odd fids on the first of two
+        // shards, and splits them across all three.
+        let (mut source1, _dir1) = test_helper::new_engine();
+        let (mut source2, _dir2) = test_helper::new_engine();
+        let mut casts = vec![];
+        for fid in 1..=6u64 {
+            let source = if fid % 2 == 1 {
+                &mut source1
+            } else {
+                &mut source2
+            };
+            test_helper::register_user(
+                fid,
+                test_helper::default_signer(),
+                test_helper::default_custody_address(),
+                source,
+            )
+            .await;
+            let cast = messages_factory::casts::create_cast_add(fid, "hello", None, None);
+            test_helper::commit_message(source, &cast).await;
+            casts.push(cast);
+        }
+        // A removed cast moves as its remove
+        let removed = messages_factory::casts::create_cast_add(1, "bye", None, None);
+        test_helper::commit_message(&mut source1, &removed).await;
+        let remove = messages_factory::casts::create_cast_remove(1, &removed.hash, None, None);
+        test_helper::commit_message(&mut source1, &remove).await;
+
+        let sources = vec![source1.get_stores(), source2.get_stores()];
+        let target_engines: Vec<_> = (0..3).map(|_| test_helper::new_engine()).collect();
+        let mut targets: HashMap<u32, Stores> = target_engines
+            .iter()
+            .enumerate()
+            .map(|(i, (engine, _))| (i as u32 + 1, engine.get_stores()))
+            .collect();
+
+        let router = ShardRouter {};
+        let summary = relayout(&sources, &mut targets, &router).unwrap();
+        assert_eq!(summary.fids, 6);
+        assert_eq!(summary.messages, 7);
+        assert_eq!(summary.fnames, 0);
+        assert!(summary.onchain_events >= 6 * 3);
+
+        for cast in &casts {
+            let fid = cast.fid();
+            let shard_id = router.route_fid(fid, 3);
+            for (id, target) in &targets {
+                let casts = cast_adds(target, fid);
+                if *id == shard_id {
+                    assert_eq!(casts, vec![cast.clone()]);
+                    assert!(target
+                        .trie
+                        .clone()
+                        .exists(
+                            &merkle_trie::Context::new(),
+                            &target.db,
+                            &TrieKey::for_message(cast)
+                        )
+                        .unwrap());
+                } else {
+                    assert!(casts.is_empty());
+                }
+            }
+        }
+        let fid1_target = &targets[&router.route_fid(1, 3)];
+        assert!(fid1_target
+            .trie
+            .clone()
+            .exists(
+                &merkle_trie::Context::new(),
+                &fid1_target.db,
+                &TrieKey::for_message(&remove)
+            )
+            .unwrap());
+        let leaves: u64 = targets
+            .values()
+            .map(|target| target.get_trie_leaf_count())
+            .sum();
+        let source_leaves: u64 = sources
+            .iter()
+            .map(|source| source.get_trie_leaf_count())
+            .sum();
+        assert_eq!(leaves, source_leaves);
+
+        // Running it again finds the targets full
+        assert!(matches!(
+            relayout(&sources, &mut targets, &router),
+            Err(RelayoutError::TargetNotEmpty(_))
+        ));
+        // And the old layout doesn't match the new routing
+        assert!(matches!(
+            verify_layout(&sources[0], 1, 3, &router),
+            Err(RelayoutError::Misrouted { .. })
+        ));
+    }
+}
@@ -0,0 +1,273 @@
+use crate::proto::ShardChunk;
+use crate::storage::db::{PageOptions, RocksDB};
+use crate::storage::store::shard::{get_shard_chunks_in_range, ShardStorageError, ShardStore};
+use crate::storage::trie::errors::TrieError;
+use crate::storage::trie::merkle_trie::MerkleTrie;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use thiserror::Error;
+
+const PAGE_SIZE: usize = 1000;
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Config {
+    // Check every shard db when the node starts, before it joins consensus
+    pub enabled: bool,
+    // Walk every stored height and recompute the whole trie instead of only checking the tip.
+    // Also turned on by --deep.
+    pub deep: bool,
+}
+
+#[derive(Error, Debug)]
+pub enum SelfCheckError {
+    #[error(transparent)]
+    ShardStorageError(#[from] ShardStorageError),
+
+    #[error(transparent)]
+    TrieError(#[from] TrieError),
+
+    #[error("trie root {computed} doesn't match the shard root {recorded} of height {height}")]
+    TrieRootMismatch {
+        height: u64,
+        recorded: String,
+        computed: String,
+    },
+
+    #[error("height {height} is missing below the tip at {tip_height}")]
+    MissingHeight { height: u64, tip_height: u64 },
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct SelfCheckReport {
+    // None when the shard has no chunks yet
+    pub tip_height: Option<u64>,
+    pub heights_checked: u64,
+}
+
+fn chunk_height(chunk: &ShardChunk) -> Result<u64, ShardStorageError> {
+    Ok(chunk
+        .header
+        .as_ref()
+        .ok_or(ShardStorageError::ShardMissingHeader)?
+        .height
+        .as_ref()
+        .ok_or(ShardStorageError::ShardMissingHeight)?
+        .block_number)
+}
+
+// Walks the chunks from the oldest one kept, pruning only removes the oldest ones
+fn check_all_heights(db: &RocksDB, tip_height: u64) -> Result<u64, SelfCheckError> {
+    let mut previous = None;
+    let mut heights_checked = 0;
+    let mut page_token = None;
+    loop {
+        let page = get_shard_chunks_in_range(
+            db,
+            &PageOptions {
+                page_size: Some(PAGE_SIZE),
+                page_token,
+                reverse: false,
+            },
+            0,
+            None,
+        )?;
+        for chunk in &page.shard_chunks {
+            let height = chunk_height(chunk)?;
+            if let Some(previous) = previous {
+                if height != previous + 1 {
+                    return Err(SelfCheckError::MissingHeight {
+                        height: previous + 1,
+                        tip_height,
+                    });
+                }
+            }
+            previous = Some(height);
+            heights_checked += 1;
+        }
+        if page.next_page_token.is_none() {
+            break;
+        }
+        page_token = page.next_page_token;
+    }
+    Ok(heights_checked)
+}
+
+/// Checks that a shard db is sound before the node uses it: the root of its trie has to be the
+/// shard root recorded in the latest chunk, and the chunks have to have contiguous heights. By
+/// default only the tip is checked, against the chunk below it and the root hash stored in the
+/// trie, which is quick. The deep check walks every stored chunk and recomputes the root from the
+/// trie's leaves, which also catches nodes whose hashes don't match their children, but reads the
+/// whole trie.
+pub fn check_shard(
+    db: &Arc<RocksDB>,
+    shard_id: u32,
+    trie_branching_factor: u32,
+    deep: bool,
+) -> Result<SelfCheckReport, SelfCheckError> {
+    let shard_store = ShardStore::new(db.clone(), shard_id);
+    let Some(tip) = shard_store.get_last_shard_chunk()? else {
+        return Ok(SelfCheckReport::default());
+    };
+    let tip_height = chunk_height(&tip)?;
+
+    let heights_checked = if deep {
+        check_all_heights(db, tip_height)?
+    } else if tip_height > shard_store.min_block_number()? {
+        if shard_store.get_chunk_by_height(tip_height - 1)?.is_none() {
+            return Err(SelfCheckError::MissingHeight {
+                height: tip_height - 1,
+                tip_height,
+            });
+        }
+        2
+    } else {
+        1
+    };
+
+    let computed = if deep {
+        MerkleTrie::recompute_root_hash(db)?
+    } else {
+        let mut trie = MerkleTrie::new(trie_branching_factor)?;
+        trie.initialize(db)?;
+        trie.root_hash()?
+    };
+    let recorded = &tip.header.as_ref().unwrap().shard_root;
+    if &computed != recorded {
+        return Err(SelfCheckError::TrieRootMismatch {
+            height: tip_height,
+            recorded: hex::encode(recorded),
+            computed: hex::encode(computed),
+        });
+    }
+
+    Ok(SelfCheckReport {
+        tip_height: Some(tip_height),
+        heights_checked,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::DbTrieNode;
+    use crate::storage::constants::RootPrefix;
+    use crate::storage::store::engine::ShardEngine;
+    use crate::storage::store::test_helper::{self, FID_FOR_TEST};
+    use crate::utils::factory::messages_factory;
+    use prost::Message;
+
+    const SHARD_ID: u32 = 1;
+
+    async fn engine_with_casts() -> (ShardEngine, Arc<RocksDB>, tempfile::TempDir) {
+        let (mut engine, dir) = test_helper::new_engine();
+        test_helper::register_user(
+            FID_FOR_TEST,
+            test_helper::default_signer(),
+            test_helper::default_custody_address(),
+            &mut engine,
+        )
+        .await;
+        for text in ["one", "two", "three"] {
+            let cast = messages_factory::casts::create_cast_add(FID_FOR_TEST, text, None, None);
+            test_helper::commit_message(&mut engine, &cast).await;
+        }
+        let db = engine.get_stores().shard_store.db.clone();
+        (engine, db, dir)
+    }
+
+    fn delete_height(db: &RocksDB, height: u64) {
+        let mut txn = db.txn();
+        let mut key = vec![RootPrefix::Shard as u8];
+        key.extend_from_slice(&height.to_be_bytes());
+        txn.delete(key);
+        db.commit(txn).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_clean_store_passes() {
+        let (engine, db, _dir) = engine_with_casts().await;
+        let tip_height = engine.get_confirmed_height().block_number;
+
+        let report = check_shard(&db, SHARD_ID, 16, false).unwrap();
+        assert_eq!(report.tip_height, Some(tip_height));
+        assert_eq!(report.heights_checked, 2);
+
+        let report = check_shard(&db, SHARD_ID, 16, true).unwrap();
+        assert_eq!(report.tip_height, Some(tip_height));
+        assert_eq!(report.heights_checked, tip_height);
+
+        // A shard without chunks has nothing to check
+        let (empty, _dir) = test_helper::new_engine();
+        let db = empty.get_stores().shard_store.db.clone();
+        assert_eq!(
+            check_shard(&db, SHARD_ID, 16, true).unwrap(),
+            SelfCheckReport::default()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_corrupted_store_fails() {
+        let (engine, db, _dir) = engine_with_casts().await;
+        let tip_height = engine.get_confirmed_height().block_number;
+
+        // A height missing in the middle is only found by the deep check
+        delete_height(&db, 2);
+        assert!(check_shard(&db, SHARD_ID, 16, false).is_ok());
+        assert!(matches!(
+            check_shard(&db, SHARD_ID, 16, true),
+            Err(SelfCheckError::MissingHeight { height: 2, .. })
+        ));
+        delete_height(&db, tip_height - 1);
+        assert!(matches!(
+            check_shard(&db, SHARD_ID, 16, false),
+            Err(SelfCheckError::MissingHeight { .. })
+        ));
+
+        // A tip whose root isn't the trie's
+        let (engine, db, _dir) = engine_with_casts().await;
+        let mut tip = engine.get_last_shard_chunk().unwrap();
+        let header = tip.header.as_mut().unwrap();
+        header.height.as_mut().unwrap().block_number += 1;
+        header.shard_root = vec![1; 20];
+        engine
+            .get_stores()
+            .shard_store
+            .put_shard_chunk(&tip)
+            .unwrap();
+        for deep in [false, true] {
+            assert!(matches!(
+                check_shard(&db, SHARD_ID, 16, deep),
+                Err(SelfCheckError::TrieRootMismatch { .. })
+            ));
+        }
+
+        // A leaf that changed under the stored hashes is only found by the deep check
+        let (_engine, db, _dir) = engine_with_casts().await;
+        let prefix = vec![RootPrefix::SyncMerkleTrieNode as u8];
+        let mut leaf = None;
+        db.for_each_iterator_by_prefix(
+            Some(prefix.clone()),
+            Some(vec![RootPrefix::SyncMerkleTrieNode as u8 + 1]),
+            &PageOptions::default(),
+            |key, value| {
+                let node = DbTrieNode::decode(value).unwrap();
+                if node.child_chars.is_empty() && !node.key.is_empty() {
+                    leaf = Some((key.to_vec(), node));
+                    return Ok(true);
+                }
+                Ok(false)
+            },
+        )
+        .unwrap();
+        let (key, mut node) = leaf.unwrap();
+        *node.key.last_mut().unwrap() ^= 0xff;
+        let mut txn = db.txn();
+        txn.put(key, node.encode_to_vec());
+        db.commit(txn).unwrap();
+        assert!(check_shard(&db, SHARD_ID, 16, false).is_ok());
+        assert!(matches!(
+            check_shard(&db, SHARD_ID, 16, true),
+            Err(SelfCheckError::TrieRootMismatch { .. })
+        ));
+    }
+}
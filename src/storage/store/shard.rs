@@ -3,7 +3,7 @@ use crate::core::error::HubError;
 use crate::proto;
 use crate::proto::ShardChunk;
 use crate::storage::constants::RootPrefix;
-use crate::storage::db::{PageOptions, RocksDB, RocksdbError};
+use crate::storage::db::{PageOptions, RocksDB, RocksDbTransactionBatch, RocksdbError};
 use prost::Message;
 use std::sync::Arc;
 use thiserror::Error;
@@ -154,6 +154,16 @@ pub fn get_current_header(db: &RocksDB) -> Result<Option<proto::ShardHeader>, Sh
 
 pub fn put_shard_chunk(db: &RocksDB, shard_chunk: &ShardChunk) -> Result<(), ShardStorageError> {
     let mut txn = db.txn();
+    put_shard_chunk_in_txn(db, &mut txn, shard_chunk)?;
+    db.commit(txn)?;
+    Ok(())
+}
+
+pub fn put_shard_chunk_in_txn(
+    db: &RocksDB,
+    txn: &mut RocksDbTransactionBatch,
+    shard_chunk: &ShardChunk,
+) -> Result<(), ShardStorageError> {
     let header = shard_chunk
         .header
         .as_ref()
@@ -170,8 +180,6 @@ pub fn put_shard_chunk(db: &RocksDB, shard_chunk: &ShardChunk) -> Result<(), Sha
     if db.get(&timestamp_index_key)? == None {
         txn.put(timestamp_index_key, primary_key);
     }
-
-    db.commit(txn)?;
     Ok(())
 }
 
@@ -202,6 +210,14 @@ impl ShardStore {
         put_shard_chunk(&self.db, shard_chunk)
     }
 
+    pub fn put_shard_chunk_in_txn(
+        &self,
+        shard_chunk: &ShardChunk,
+        txn: &mut RocksDbTransactionBatch,
+    ) -> Result<(), ShardStorageError> {
+        put_shard_chunk_in_txn(&self.db, txn, shard_chunk)
+    }
+
     pub fn get_first_shard_chunk(&self) -> Result<Option<ShardChunk>, ShardStorageError> {
         get_first_or_last_shard_chunk(&self.db, FirstOrLast::First)
     }
@@ -228,6 +244,21 @@ impl ShardStore {
         }
     }
 
+    // Decodes the stored chunk without its transactions, for when only the header is needed
+    pub fn get_chunk_header_by_height(
+        &self,
+        height: u64,
+    ) -> Result<Option<proto::ShardHeader>, ShardStorageError> {
+        match self.db.get(&make_shard_key(height))? {
+            None => Ok(None),
+            Some(chunk) => {
+                let view = proto::ShardChunkView::decode(chunk.as_slice())
+                    .map_err(ShardStorageError::DecodeError)?;
+                Ok(view.header)
+            }
+        }
+    }
+
     pub fn min_block_number(&self) -> Result<u64, ShardStorageError> {
         let first_shard_chunk = get_first_or_last_shard_chunk(&self.db, FirstOrLast::First)?;
         match first_shard_chunk {
@@ -290,6 +321,35 @@ impl ShardStore {
         Ok(shard_chunks)
     }
 
+    // The latest count shard chunks, latest first. Their transactions aren't decoded, so it's
+    // much cheaper than reading the chunks when only the headers are needed.
+    pub fn get_recent_shard_chunk_views(
+        &self,
+        count: usize,
+    ) -> Result<Vec<proto::ShardChunkView>, ShardStorageError> {
+        let mut views = vec![];
+        if count == 0 {
+            return Ok(views);
+        }
+        self.db
+            .for_each_iterator_by_prefix_paged(
+                Some(make_shard_key(0)),
+                Some(vec![RootPrefix::Shard as u8 + 1]),
+                &PageOptions {
+                    page_size: Some(count),
+                    page_token: None,
+                    reverse: true,
+                },
+                |_key, value| {
+                    views
+                        .push(proto::ShardChunkView::decode(value).map_err(|e| HubError::from(e))?);
+                    Ok(views.len() >= count)
+                },
+            )
+            .map_err(|e| ShardStorageError::HubError(e))?;
+        Ok(views)
+    }
+
     // Returns the next block height with a timestamp greater than or equal to
     // the given timestamp for the specified shard index.
     pub fn get_next_height_by_timestamp(
@@ -3,26 +3,50 @@ use super::account::{
     VerificationStore, VerificationStoreDef,
 };
 use crate::core::error::HubError;
-use crate::proto::MessageType;
 use crate::proto::{
-    HubEvent, StorageLimit, StorageLimitsResponse, StorageUnitDetails, StorageUnitType, StoreType,
+    GetFidActivityCountsResponse, GetStorageUsageResponse, HubEvent, StorageBytesResponse,
+    StorageBytesUsage, StorageLimit, StorageLimitsResponse, StorageUnitDetails, StorageUnitType,
+    StorageUsageStatus, StoreStorageUsage, StoreType,
 };
-use crate::storage::constants::PAGE_SIZE_MAX;
+use crate::proto::{Message, MessageType, OnChainEvent};
+use crate::storage::constants::{OnChainEventPostfix, RootPrefix, UserPostfix, PAGE_SIZE_MAX};
 use crate::storage::db::{PageOptions, RocksDB, RocksDbTransactionBatch};
 use crate::storage::store::account::{
-    CastStore, CastStoreDef, IntoU8, LinkStore, OnchainEventStorageError, OnchainEventStore, Store,
-    StoreEventHandler, UsernameProofStore, UsernameProofStoreDef,
+    get_message_by_hash, make_message_by_hash_key, make_user_key, message_decode, messages_exist,
+    read_fid_key, CastStore, CastStoreDef, IndexRebuildPage, IntoU8, LinkStore,
+    OnchainEventStorageError, OnchainEventStore, Store, StoreEventHandler, UsernameProofStore,
+    UsernameProofStoreDef, FID_BYTES,
 };
+use crate::storage::store::message_cache::MessageCache;
 use crate::storage::store::shard::ShardStore;
+use crate::storage::trie::compaction::{self, TrieCompactionResult};
+use crate::storage::trie::errors::TrieError;
+use crate::storage::trie::gc::{self, TrieGcResult};
 use crate::storage::trie::merkle_trie;
 use crate::storage::trie::merkle_trie::TrieKey;
+use crate::storage::trie::scratch::ScratchTrie;
+use crate::storage::util::increment_vec_u8;
 use crate::utils::statsd_wrapper::StatsdClientWrapper;
-use std::sync::Arc;
+use prost::Message as _;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use thiserror::Error;
 use tokio::sync::RwLock;
 use tracing::{error, info};
 
+// Postfixes under the user prefix from here on are indices rather than messages
+const FIRST_INDEX_POSTFIX: u8 = 86;
+
+// Message records use the postfixes below the index records, link compact state messages are the
+// only messages stored under an index postfix
+fn is_message_record_postfix(postfix: u8) -> bool {
+    postfix < FIRST_INDEX_POSTFIX || postfix == UserPostfix::LinkCompactStateMessage.as_u8()
+}
+
 #[derive(Error, Debug)]
 pub enum StoresError {
     #[error(transparent)]
@@ -38,6 +62,88 @@ pub enum StoresError {
     },
 }
 
+/// In-memory write freeze for a shard, shared by every clone of the shard's stores. While frozen
+/// the shard rejects submitted messages and stops starting new consensus heights, but reads are
+/// unaffected. A halted shard stays frozen until the node restarts.
+#[derive(Clone, Default)]
+pub struct ShardFreeze {
+    frozen: Arc<AtomicBool>,
+    halted: Arc<AtomicBool>,
+}
+
+impl ShardFreeze {
+    // Returns true if the shard was not already frozen
+    pub fn freeze(&self) -> bool {
+        !self.frozen.swap(true, Ordering::SeqCst)
+    }
+
+    // Returns true if the shard was frozen
+    pub fn unfreeze(&self) -> bool {
+        self.frozen.swap(false, Ordering::SeqCst)
+    }
+
+    // Freezes the shard for good, e.g. once its committed chunks can't be written
+    pub fn halt(&self) {
+        self.halted.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted.load(Ordering::SeqCst)
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.frozen.load(Ordering::SeqCst) || self.is_halted()
+    }
+}
+
+/// The secondary indices that can be rebuilt from the messages or onchain events in a store
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SecondaryIndex {
+    CastsByParent,
+    CastsByMention,
+    CastsByTimestamp,
+    LinksByTarget,
+    ReactionsByTarget,
+    VerificationsByAddress,
+    UsernameProofsByName,
+    MessagesByHash,
+    OnChainEventsByBlock,
+}
+
+impl SecondaryIndex {
+    pub const ALL: [SecondaryIndex; 9] = [
+        SecondaryIndex::CastsByParent,
+        SecondaryIndex::CastsByMention,
+        SecondaryIndex::CastsByTimestamp,
+        SecondaryIndex::LinksByTarget,
+        SecondaryIndex::ReactionsByTarget,
+        SecondaryIndex::VerificationsByAddress,
+        SecondaryIndex::UsernameProofsByName,
+        SecondaryIndex::MessagesByHash,
+        SecondaryIndex::OnChainEventsByBlock,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SecondaryIndex::CastsByParent => "casts_by_parent",
+            SecondaryIndex::CastsByMention => "casts_by_mention",
+            SecondaryIndex::CastsByTimestamp => "casts_by_timestamp",
+            SecondaryIndex::LinksByTarget => "links_by_target",
+            SecondaryIndex::ReactionsByTarget => "reactions_by_target",
+            SecondaryIndex::VerificationsByAddress => "verifications_by_address",
+            SecondaryIndex::UsernameProofsByName => "username_proofs_by_name",
+            SecondaryIndex::MessagesByHash => "messages_by_hash",
+            SecondaryIndex::OnChainEventsByBlock => "onchain_events_by_block",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<SecondaryIndex> {
+        SecondaryIndex::ALL
+            .into_iter()
+            .find(|index| index.name() == name)
+    }
+}
+
 #[derive(Clone)]
 pub struct Stores {
     pub shard_store: ShardStore,
@@ -54,10 +160,17 @@ pub struct Stores {
     pub event_handler: Arc<StoreEventHandler>,
     pub shard_id: u32,
     pub statsd: StatsdClientWrapper,
+    pub shard_freeze: ShardFreeze,
+    // Held while committing to the trie, so garbage collection can't delete a node that a commit
+    // is making reachable
+    pub trie_commit_lock: Arc<Mutex<()>>,
     prune_lock: Arc<RwLock<bool>>,
+    trie_compaction_running: Arc<AtomicBool>,
+    // For the lookups of single messages by hash and casts by id
+    pub message_cache: MessageCache,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct Limits {
     pub casts: u32,
     pub links: u32,
@@ -149,6 +262,9 @@ impl Limits {
 pub struct StoreLimits {
     pub limits: Limits,
     pub legacy_limits: Limits,
+    // Serialized message bytes each storage unit allows per store, legacy or not. When set,
+    // stores are limited by both message count and bytes.
+    pub byte_limits: Option<Limits>,
 }
 
 impl StoreLimits {
@@ -156,6 +272,12 @@ impl StoreLimits {
         units * self.limits.for_store_type(store_type)
             + legacy_units * self.legacy_limits.for_store_type(store_type)
     }
+
+    pub fn max_bytes(&self, units: u32, legacy_units: u32, store_type: StoreType) -> Option<u64> {
+        self.byte_limits.as_ref().map(|byte_limits| {
+            (units + legacy_units) as u64 * byte_limits.for_store_type(store_type) as u64
+        })
+    }
 }
 
 impl StoreLimits {
@@ -163,10 +285,32 @@ impl StoreLimits {
         StoreLimits {
             limits: Limits::default(),
             legacy_limits: Limits::legacy(),
+            byte_limits: None,
         }
     }
 }
 
+fn store_type_name(store_type: StoreType) -> &'static str {
+    match store_type {
+        StoreType::None => "NONE",
+        StoreType::Casts => "CASTS",
+        StoreType::Links => "LINKS",
+        StoreType::Reactions => "REACTIONS",
+        StoreType::UserData => "USER_DATA",
+        StoreType::Verifications => "VERIFICATIONS",
+        StoreType::UsernameProofs => "USERNAME_PROOFS",
+    }
+}
+
+const STORE_TYPES: [StoreType; 6] = [
+    StoreType::Casts,
+    StoreType::Links,
+    StoreType::Reactions,
+    StoreType::UserData,
+    StoreType::Verifications,
+    StoreType::UsernameProofs,
+];
+
 impl Stores {
     pub fn new(
         db: Arc<RocksDB>,
@@ -201,21 +345,28 @@ impl Stores {
             store_limits,
             event_handler,
             statsd,
+            shard_freeze: ShardFreeze::default(),
+            trie_commit_lock: Arc::new(Mutex::new(())),
             prune_lock: Arc::new(RwLock::new(false)),
+            trie_compaction_running: Arc::new(AtomicBool::new(false)),
+            message_cache: MessageCache::default(),
         }
     }
 
+    // The limit depends on the fid's storage at the given time, which must be the block timestamp
+    // when the usage is used to prune
     pub fn get_usage(
         &self,
         fid: u64,
         message_type: MessageType,
+        unix_timestamp: u64,
         txn_batch: &mut RocksDbTransactionBatch,
     ) -> Result<(u32, u32), StoresError> {
         let store_type = Limits::message_type_to_store_type(message_type);
         let message_count = self.get_usage_by_store_type(fid, store_type, txn_batch);
         let slot = self
             .onchain_event_store
-            .get_storage_slot_for_fid(fid)
+            .get_storage_slot_for_fid_at(fid, unix_timestamp)
             .map_err(|e| StoresError::OnchainEventError(e))?;
         let max_messages =
             self.store_limits
@@ -243,6 +394,153 @@ impl Stores {
         total_count
     }
 
+    /// Serialized size the fid's storage allows for the message type's store, or None when
+    /// stores are only limited by message count.
+    pub fn get_max_bytes(
+        &self,
+        fid: u64,
+        message_type: MessageType,
+        unix_timestamp: u64,
+    ) -> Result<Option<u64>, StoresError> {
+        if self.store_limits.byte_limits.is_none() {
+            return Ok(None);
+        }
+        let slot = self
+            .onchain_event_store
+            .get_storage_slot_for_fid_at(fid, unix_timestamp)
+            .map_err(|e| StoresError::OnchainEventError(e))?;
+        Ok(self.store_limits.max_bytes(
+            slot.units,
+            slot.legacy_units,
+            Limits::message_type_to_store_type(message_type),
+        ))
+    }
+
+    fn get_bytes_used_by_store_type(
+        &self,
+        fid: u64,
+        store_type: StoreType,
+        txn_batch: &RocksDbTransactionBatch,
+    ) -> Result<u64, StoresError> {
+        let result = match store_type {
+            StoreType::Casts => self.cast_store.get_bytes_used(fid, txn_batch),
+            StoreType::Links => self.link_store.get_bytes_used(fid, txn_batch),
+            StoreType::Reactions => self.reaction_store.get_bytes_used(fid, txn_batch),
+            StoreType::UserData => self.user_data_store.get_bytes_used(fid, txn_batch),
+            StoreType::Verifications => self.verification_store.get_bytes_used(fid, txn_batch),
+            StoreType::UsernameProofs => self.username_proof_store.get_bytes_used(fid, txn_batch),
+            StoreType::None => Ok(0),
+        };
+        result.map_err(|e| StoresError::StoreError {
+            inner: e,
+            hash: vec![],
+        })
+    }
+
+    pub fn get_storage_bytes(&self, fid: u64) -> Result<StorageBytesResponse, StoresError> {
+        let slot = self
+            .onchain_event_store
+            .get_storage_slot_for_fid(fid)
+            .map_err(|e| StoresError::OnchainEventError(e))?;
+
+        let txn_batch = &RocksDbTransactionBatch::new();
+        let mut usages = vec![];
+        for store_type in STORE_TYPES {
+            usages.push(StorageBytesUsage {
+                store_type: store_type.try_into().unwrap(),
+                name: store_type_name(store_type).to_string(),
+                used: self.get_bytes_used_by_store_type(fid, store_type, txn_batch)?,
+                limit: self
+                    .store_limits
+                    .max_bytes(slot.units, slot.legacy_units, store_type),
+            });
+        }
+
+        Ok(StorageBytesResponse {
+            total_used: usages.iter().map(|usage| usage.used).sum(),
+            usages,
+        })
+    }
+
+    /// The fid's message counts and bytes in each store next to what its storage allows. A store
+    /// is at its limit once either is used up, and over it when the fid's storage no longer
+    /// covers what's stored, until the next merge prunes it.
+    pub fn get_storage_usage(&self, fid: u64) -> Result<GetStorageUsageResponse, StoresError> {
+        let slot = self
+            .onchain_event_store
+            .get_storage_slot_for_fid(fid)
+            .map_err(|e| StoresError::OnchainEventError(e))?;
+
+        let txn_batch = &mut RocksDbTransactionBatch::new();
+        let status = |used: u64, max: u64| {
+            if used > max {
+                StorageUsageStatus::OverLimit
+            } else if used == max {
+                StorageUsageStatus::AtLimit
+            } else {
+                StorageUsageStatus::UnderLimit
+            }
+        };
+        let mut usages = vec![];
+        for store_type in STORE_TYPES {
+            let messages = self.get_usage_by_store_type(fid, store_type, txn_batch) as u64;
+            let max_messages =
+                self.store_limits
+                    .max_messages(slot.units, slot.legacy_units, store_type) as u64;
+            let bytes = self.get_bytes_used_by_store_type(fid, store_type, txn_batch)?;
+            let max_bytes = self
+                .store_limits
+                .max_bytes(slot.units, slot.legacy_units, store_type);
+            let store_status = status(messages, max_messages).max(
+                max_bytes.map_or(StorageUsageStatus::UnderLimit, |max_bytes| {
+                    status(bytes, max_bytes)
+                }),
+            );
+            usages.push(StoreStorageUsage {
+                store_type: store_type.try_into().unwrap(),
+                name: store_type_name(store_type).to_string(),
+                messages,
+                max_messages,
+                bytes,
+                max_bytes,
+                status: store_status as i32,
+            });
+        }
+
+        Ok(GetStorageUsageResponse {
+            fid,
+            units: slot.units + slot.legacy_units,
+            total_bytes: usages.iter().map(|usage| usage.bytes).sum(),
+            status: usages
+                .iter()
+                .map(|usage| usage.status())
+                .max()
+                .unwrap_or(StorageUsageStatus::UnderLimit) as i32,
+            usages,
+        })
+    }
+
+    /// The fid's stored messages by kind, from the counts the trie keeps for each message type,
+    /// so nothing is scanned. Only adds are counted, a remove replaces the add it removes.
+    pub fn get_activity_counts(&self, fid: u64) -> GetFidActivityCountsResponse {
+        let txn_batch = &mut RocksDbTransactionBatch::new();
+        let mut count = |message_type: MessageType| {
+            self.trie.get_count(
+                &self.db,
+                txn_batch,
+                &TrieKey::for_message_type(fid, message_type.into_u8()),
+            )
+        };
+        GetFidActivityCountsResponse {
+            fid,
+            casts: count(MessageType::CastAdd),
+            reactions: count(MessageType::ReactionAdd),
+            links: count(MessageType::LinkAdd),
+            verifications: count(MessageType::VerificationAddEthAddress),
+            user_data: count(MessageType::UserDataAdd),
+        }
+    }
+
     pub fn get_storage_limits(&self, fid: u64) -> Result<StorageLimitsResponse, StoresError> {
         let slot = self
             .onchain_event_store
@@ -251,30 +549,14 @@ impl Stores {
 
         let txn_batch = &mut RocksDbTransactionBatch::new();
         let mut limits = vec![];
-        for store_type in vec![
-            StoreType::Casts,
-            StoreType::Links,
-            StoreType::Reactions,
-            StoreType::UserData,
-            StoreType::Verifications,
-            StoreType::UsernameProofs,
-        ] {
+        for store_type in STORE_TYPES {
             let used = self.get_usage_by_store_type(fid, store_type, txn_batch);
             let max_messages =
                 self.store_limits
                     .max_messages(slot.units, slot.legacy_units, store_type);
-            let name = match store_type {
-                StoreType::None => "NONE",
-                StoreType::Casts => "CASTS",
-                StoreType::Links => "LINKS",
-                StoreType::Reactions => "REACTIONS",
-                StoreType::UserData => "USER_DATA",
-                StoreType::Verifications => "VERIFICATIONS",
-                StoreType::UsernameProofs => "USERNAME_PROOFS",
-            };
             let limit = StorageLimit {
                 store_type: store_type.try_into().unwrap(),
-                name: name.to_string(),
+                name: store_type_name(store_type).to_string(),
                 limit: max_messages as u64,
                 used: used as u64,
                 earliest_timestamp: 0, // Deprecate?
@@ -300,17 +582,19 @@ impl Stores {
         Ok(response)
     }
 
+    // Before RevokeAllSignerMessages, link compact states and username proofs were left behind
     pub fn revoke_messages(
         &self,
         fid: u64,
         key: &Vec<u8>,
+        revoke_all: bool,
         txn_batch: &mut RocksDbTransactionBatch,
     ) -> Result<Vec<HubEvent>, StoresError> {
         let mut revoke_events = Vec::new();
         // TODO: Dedup once we have a unified interface for stores
         revoke_events.extend(
             self.cast_store
-                .revoke_messages_by_signer(fid, key, txn_batch)
+                .revoke_messages_by_signer(fid, key, revoke_all, txn_batch)
                 .map_err(|e| StoresError::StoreError {
                     inner: e,
                     hash: key.clone(),
@@ -318,7 +602,7 @@ impl Stores {
         );
         revoke_events.extend(
             self.link_store
-                .revoke_messages_by_signer(fid, key, txn_batch)
+                .revoke_messages_by_signer(fid, key, revoke_all, txn_batch)
                 .map_err(|e| StoresError::StoreError {
                     inner: e,
                     hash: key.clone(),
@@ -326,7 +610,7 @@ impl Stores {
         );
         revoke_events.extend(
             self.reaction_store
-                .revoke_messages_by_signer(fid, key, txn_batch)
+                .revoke_messages_by_signer(fid, key, revoke_all, txn_batch)
                 .map_err(|e| StoresError::StoreError {
                     inner: e,
                     hash: key.clone(),
@@ -334,7 +618,7 @@ impl Stores {
         );
         revoke_events.extend(
             self.user_data_store
-                .revoke_messages_by_signer(fid, key, txn_batch)
+                .revoke_messages_by_signer(fid, key, revoke_all, txn_batch)
                 .map_err(|e| StoresError::StoreError {
                     inner: e,
                     hash: key.clone(),
@@ -342,15 +626,376 @@ impl Stores {
         );
         revoke_events.extend(
             self.verification_store
-                .revoke_messages_by_signer(fid, key, txn_batch)
+                .revoke_messages_by_signer(fid, key, revoke_all, txn_batch)
                 .map_err(|e| StoresError::StoreError {
                     inner: e,
                     hash: key.clone(),
                 })?,
         );
+        if revoke_all {
+            revoke_events.extend(
+                self.username_proof_store
+                    .revoke_messages_by_signer(fid, key, revoke_all, txn_batch)
+                    .map_err(|e| StoresError::StoreError {
+                        inner: e,
+                        hash: key.clone(),
+                    })?,
+            );
+        }
         Ok(revoke_events)
     }
 
+    // Every leaf in the trie: the messages in the stores, onchain events and fnames. The trie keeps
+    // its count in the root node, so this is all that has to be read to compare with the stores.
+    pub fn get_trie_leaf_count(&self) -> u64 {
+        self.trie
+            .get_count(&self.db, &mut RocksDbTransactionBatch::new(), &[])
+    }
+
+    // What the trie should contain, counted from the stores. There are no counters to read, but
+    // only keys are visited and nothing is decoded, so it's far cheaper than walking the trie.
+    pub fn get_store_count(&self) -> Result<u64, HubError> {
+        let mut message_count = 0;
+        let prefix = vec![RootPrefix::User as u8];
+        self.db.for_each_iterator_by_prefix(
+            Some(prefix.clone()),
+            Some(increment_vec_u8(&prefix)),
+            &PageOptions::default(),
+            |key, _| {
+                if let Some(&postfix) = key.get(1 + FID_BYTES) {
+                    if is_message_record_postfix(postfix) {
+                        message_count += 1;
+                    }
+                }
+                Ok(false)
+            },
+        )?;
+        Ok(message_count + self.get_onchain_event_count()? + self.get_fname_count()?)
+    }
+
+    pub fn get_onchain_event_count(&self) -> Result<u64, HubError> {
+        let count = self.db.count_keys_at_prefix(vec![
+            RootPrefix::OnChainEvent as u8,
+            OnChainEventPostfix::OnChainEvents as u8,
+        ])?;
+        Ok(count as u64)
+    }
+
+    // Only fnames that are owned by an fid are in the trie, and those are indexed by fid
+    pub fn get_fname_count(&self) -> Result<u64, HubError> {
+        let count = self
+            .db
+            .count_keys_at_prefix(vec![RootPrefix::FNameUserNameProofByFid as u8])?;
+        Ok(count as u64)
+    }
+
+    /// The messages stored in each store, counted like in get_store_count
+    pub fn get_message_counts_by_store(&self) -> Result<Vec<(StoreType, u64)>, HubError> {
+        let mut counts = BTreeMap::new();
+        let prefix = vec![RootPrefix::User as u8];
+        self.db.for_each_iterator_by_prefix(
+            Some(prefix.clone()),
+            Some(increment_vec_u8(&prefix)),
+            &PageOptions::default(),
+            |key, _| {
+                let store_type = match key.get(1 + FID_BYTES) {
+                    Some(&postfix) if postfix == UserPostfix::CastMessage.as_u8() => {
+                        StoreType::Casts
+                    }
+                    Some(&postfix)
+                        if postfix == UserPostfix::LinkMessage.as_u8()
+                            || postfix == UserPostfix::LinkCompactStateMessage.as_u8() =>
+                    {
+                        StoreType::Links
+                    }
+                    Some(&postfix) if postfix == UserPostfix::ReactionMessage.as_u8() => {
+                        StoreType::Reactions
+                    }
+                    Some(&postfix) if postfix == UserPostfix::UserDataMessage.as_u8() => {
+                        StoreType::UserData
+                    }
+                    Some(&postfix) if postfix == UserPostfix::VerificationMessage.as_u8() => {
+                        StoreType::Verifications
+                    }
+                    Some(&postfix) if postfix == UserPostfix::UsernameProofMessage.as_u8() => {
+                        StoreType::UsernameProofs
+                    }
+                    _ => return Ok(false),
+                };
+                *counts.entry(store_type).or_insert(0) += 1;
+                Ok(false)
+            },
+        )?;
+        Ok(counts.into_iter().collect())
+    }
+
+    /// Trie keys of up to count messages picked at random, to spot check the trie with. Each one
+    /// is the first message stored from a random fid on, so fids with few messages are as likely
+    /// to come up as busy ones.
+    pub fn sample_message_trie_keys(&self, count: usize) -> Result<Vec<Vec<u8>>, HubError> {
+        let prefix = vec![RootPrefix::User as u8];
+        let stop_prefix = increment_vec_u8(&prefix);
+        let mut max_fid = None;
+        self.db.for_each_iterator_by_prefix(
+            Some(prefix.clone()),
+            Some(stop_prefix.clone()),
+            &PageOptions {
+                reverse: true,
+                ..PageOptions::default()
+            },
+            |key, _| {
+                if key.len() > FID_BYTES {
+                    max_fid = Some(read_fid_key(key, 1));
+                }
+                Ok(true)
+            },
+        )?;
+        let Some(max_fid) = max_fid else {
+            return Ok(vec![]);
+        };
+
+        let mut trie_keys = vec![];
+        for _ in 0..count {
+            let fid = rand::thread_rng().gen_range(0..=max_fid);
+            let mut trie_key = None;
+            self.db.for_each_iterator_by_prefix(
+                Some(make_user_key(fid)),
+                Some(stop_prefix.clone()),
+                &PageOptions::default(),
+                |key, value| match key.get(1 + FID_BYTES) {
+                    Some(&postfix) if is_message_record_postfix(postfix) => {
+                        trie_key = Some(TrieKey::for_message(&message_decode(value)?));
+                        Ok(true)
+                    }
+                    _ => Ok(false),
+                },
+            )?;
+            if let Some(trie_key) = trie_key {
+                if !trie_keys.contains(&trie_key) {
+                    trie_keys.push(trie_key);
+                }
+            }
+        }
+        Ok(trie_keys)
+    }
+
+    /// The root hash of the trie as last committed to the db
+    pub fn get_stored_trie_root(&self) -> Result<Vec<u8>, HubError> {
+        let trie = merkle_trie::MerkleTrie::new(self.trie.branching_factor())
+            .and_then(|mut trie| trie.reload(&self.db).map(|_| trie))
+            .map_err(|err| HubError::internal_db_error(&err.to_string()))?;
+        trie.root_hash()
+            .map_err(|err| HubError::internal_db_error(&err.to_string()))
+    }
+
+    /// Builds the trie again from the stores in a scratch trie, see get_store_count for what's in
+    /// it, and returns its root hash. The shard's trie is left alone. Every message, onchain event
+    /// and fname of the shard is read and decoded, so it's much slower than counting them.
+    pub fn recompute_trie_root(&self) -> Result<Vec<u8>, HubError> {
+        let to_hub_error = |err: TrieError| HubError::internal_db_error(&err.to_string());
+        let mut scratch = ScratchTrie::new(self.trie.branching_factor()).map_err(to_hub_error)?;
+
+        let prefix = vec![RootPrefix::User as u8];
+        self.db.for_each_iterator_by_prefix(
+            Some(prefix.clone()),
+            Some(increment_vec_u8(&prefix)),
+            &PageOptions::default(),
+            |key, value| {
+                if let Some(&postfix) = key.get(1 + FID_BYTES) {
+                    if is_message_record_postfix(postfix) {
+                        let message = message_decode(value)?;
+                        scratch
+                            .insert(TrieKey::for_message(&message))
+                            .map_err(to_hub_error)?;
+                    }
+                }
+                Ok(false)
+            },
+        )?;
+
+        let prefix = vec![
+            RootPrefix::OnChainEvent as u8,
+            OnChainEventPostfix::OnChainEvents as u8,
+        ];
+        self.db.for_each_iterator_by_prefix(
+            Some(prefix.clone()),
+            Some(increment_vec_u8(&prefix)),
+            &PageOptions::default(),
+            |_, value| {
+                let event = OnChainEvent::decode(value)?;
+                scratch
+                    .insert(TrieKey::for_onchain_event(&event))
+                    .map_err(to_hub_error)?;
+                Ok(false)
+            },
+        )?;
+
+        let prefix = vec![RootPrefix::FNameUserNameProofByFid as u8];
+        self.db.for_each_iterator_by_prefix(
+            Some(prefix.clone()),
+            Some(increment_vec_u8(&prefix)),
+            &PageOptions::default(),
+            |key, _| {
+                let fid = read_fid_key(key, 1);
+                if let Some(proof) =
+                    UserDataStore::get_username_proof_by_fid(&self.user_data_store, fid)?
+                {
+                    let name = String::from_utf8_lossy(&proof.name).to_string();
+                    scratch
+                        .insert(TrieKey::for_fname(fid, &name))
+                        .map_err(to_hub_error)?;
+                }
+                Ok(false)
+            },
+        )?;
+
+        scratch.root_hash().map_err(to_hub_error)
+    }
+
+    /// Rebuilds a batch of the index, see Store::rebuild_secondary_indices. A store's indices are
+    /// built together, so the other indices of the same store are rewritten along with this one.
+    pub fn rebuild_index(
+        &self,
+        index: SecondaryIndex,
+        page_token: Option<Vec<u8>>,
+        batch_size: usize,
+    ) -> Result<IndexRebuildPage, HubError> {
+        match index {
+            SecondaryIndex::CastsByParent
+            | SecondaryIndex::CastsByMention
+            | SecondaryIndex::CastsByTimestamp => self
+                .cast_store
+                .rebuild_secondary_indices(page_token, batch_size),
+            SecondaryIndex::LinksByTarget => self
+                .link_store
+                .rebuild_secondary_indices(page_token, batch_size),
+            SecondaryIndex::ReactionsByTarget => self
+                .reaction_store
+                .rebuild_secondary_indices(page_token, batch_size),
+            SecondaryIndex::VerificationsByAddress => self
+                .verification_store
+                .rebuild_secondary_indices(page_token, batch_size),
+            SecondaryIndex::UsernameProofsByName => self
+                .username_proof_store
+                .rebuild_secondary_indices(page_token, batch_size),
+            SecondaryIndex::MessagesByHash => {
+                self.rebuild_message_hash_index(page_token, batch_size)
+            }
+            SecondaryIndex::OnChainEventsByBlock => self
+                .onchain_event_store
+                .rebuild_block_index(page_token, batch_size),
+        }
+    }
+
+    // The hash index covers the messages of every store, so it's rebuilt from all of the shard's
+    // message records rather than from one store's adds
+    fn rebuild_message_hash_index(
+        &self,
+        page_token: Option<Vec<u8>>,
+        batch_size: usize,
+    ) -> Result<IndexRebuildPage, HubError> {
+        let prefix = vec![RootPrefix::User as u8];
+        let mut messages = vec![];
+        let mut last_key = None;
+        let all_done = self.db.for_each_iterator_by_prefix(
+            Some(prefix.clone()),
+            Some(increment_vec_u8(&prefix)),
+            &PageOptions {
+                page_size: None,
+                page_token,
+                reverse: false,
+            },
+            |key, value| {
+                last_key = Some(key.to_vec());
+                if let Some(&postfix) = key.get(1 + FID_BYTES) {
+                    if is_message_record_postfix(postfix) {
+                        messages.push((key.to_vec(), message_decode(value)?.hash));
+                    }
+                }
+                Ok(messages.len() >= batch_size)
+            },
+        )?;
+
+        let mut txn = self.db.txn();
+        for (key, hash) in &messages {
+            txn.put(make_message_by_hash_key(hash), key.clone());
+        }
+        self.db.commit(txn)?;
+
+        // As with the store indices, entries of messages deleted since they were read are removed
+        let mut txn = self.db.txn();
+        for (key, hash) in &messages {
+            if self.db.get(key)?.is_none() {
+                txn.delete(make_message_by_hash_key(hash));
+            }
+        }
+        self.db.commit(txn)?;
+
+        Ok(IndexRebuildPage {
+            messages_indexed: messages.len() as u64,
+            fid: last_key
+                .as_ref()
+                .filter(|key| key.len() > FID_BYTES)
+                .map(|key| read_fid_key(key, 1))
+                .unwrap_or(0),
+            next_page_token: if all_done { None } else { last_key },
+        })
+    }
+
+    /// The message with the hash, whichever store it's in
+    pub fn get_message_by_hash(&self, hash: &[u8]) -> Result<Option<Message>, HubError> {
+        let (hit, message) = self
+            .message_cache
+            .get_or_load(hash, || get_message_by_hash(&self.db, hash))?;
+        self.count_message_cache_lookup(hit);
+        Ok(message)
+    }
+
+    /// The cast add with the hash, if the fid has it
+    pub fn get_cast_add(&self, fid: u64, hash: Vec<u8>) -> Result<Option<Message>, HubError> {
+        let (hit, message) = self.message_cache.get_or_load(&hash, || {
+            CastStore::get_cast_add(&self.cast_store, fid, hash.clone())
+        })?;
+        self.count_message_cache_lookup(hit);
+        // The hash may be of another type of message, or of another fid's
+        Ok(message
+            .filter(|message| message.fid() == fid && message.msg_type() == MessageType::CastAdd))
+    }
+
+    fn count_message_cache_lookup(&self, hit: bool) {
+        if self.message_cache.is_enabled() {
+            let key = if hit {
+                "message_cache.hit"
+            } else {
+                "message_cache.miss"
+            };
+            self.statsd.count_with_shard(self.shard_id, key, 1);
+        }
+    }
+
+    /// Whether a message with each of the hashes is stored, whichever store it's in
+    pub fn has_messages(&self, hashes: &[Vec<u8>]) -> Result<Vec<bool>, HubError> {
+        messages_exist(&self.db, hashes)
+    }
+
+    /// Deletes the trie nodes unreachable from the committed root, batch_size at a time
+    pub fn collect_trie_garbage(&self, batch_size: usize) -> Result<TrieGcResult, TrieError> {
+        gc::collect_garbage(&self.db, &self.trie_commit_lock, batch_size)
+    }
+
+    /// Compacts the trie's records in the shard db. Only one compaction runs per shard, another
+    /// one fails with failed_precondition while it's running.
+    pub fn compact_trie(&self) -> Result<TrieCompactionResult, HubError> {
+        if self.trie_compaction_running.swap(true, Ordering::SeqCst) {
+            return Err(HubError::failed_precondition(
+                "trie compaction already running",
+            ));
+        }
+        let result = compaction::compact_trie(&self.db);
+        self.trie_compaction_running.store(false, Ordering::SeqCst);
+        result.map_err(|err| HubError::internal_db_error(&err.to_string()))
+    }
+
     pub fn get_events(
         &self,
         start_id: u64,
@@ -364,6 +1009,22 @@ impl Stores {
         HubEvent::get_event(self.db.clone(), event_id)
     }
 
+    /// Ids before the returned one have been pruned. 0 when nothing was ever pruned.
+    pub fn get_events_pruned_until(&self) -> Result<u64, HubError> {
+        Ok(HubEvent::get_pruned_until(&self.db)?.unwrap_or(0))
+    }
+
+    pub fn report_event_log_metrics(&self) -> Result<(), HubError> {
+        let size = HubEvent::count_events(&self.db)?;
+        self.statsd
+            .gauge_with_shard(self.shard_id, "events.log_size", size as u64);
+        if let Some(oldest_id) = HubEvent::get_oldest_event_id(&self.db)? {
+            self.statsd
+                .gauge_with_shard(self.shard_id, "events.oldest_id", oldest_id);
+        }
+        Ok(())
+    }
+
     pub fn get_next_height_by_timestamp(&self, timestamp: u64) -> Option<u64> {
         self.shard_store
             .get_next_height_by_timestamp(timestamp)
@@ -438,6 +1099,70 @@ impl Stores {
         Ok(count)
     }
 
+    // Prunes the oldest events until at most [max_events] are left
+    pub async fn prune_events_over(
+        &self,
+        max_events: u64,
+        throttle: Duration,
+        page_options: Option<PageOptions>,
+    ) -> Result<u32, HubError> {
+        if *(self.prune_lock.read().await) {
+            info!(
+                "Prune lock is already held for shard {}. Skipping prune.",
+                self.shard_id
+            );
+            return Err(HubError::internal_db_error("pruning already running"));
+        }
+        {
+            let mut prune_lock = self.prune_lock.write().await;
+            *prune_lock = true;
+        }
+
+        let page_options = page_options.unwrap_or(PageOptions {
+            page_size: Some(PAGE_SIZE_MAX),
+            ..PageOptions::default()
+        });
+
+        let start = std::time::Instant::now();
+        // The oldest event that's kept
+        let result = match HubEvent::get_nth_newest_event_id(&self.db, max_events.max(1)) {
+            Ok(Some(stop_event_id)) => {
+                info!(
+                    "Pruning events for shard {} beyond the newest {}, before id: {}",
+                    self.shard_id, max_events, stop_event_id
+                );
+                HubEvent::prune_events_before(
+                    self.db.clone(),
+                    stop_event_id,
+                    &page_options,
+                    throttle,
+                )
+                .await
+            }
+            Ok(None) => Ok(0),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = &result {
+            error!("Error pruning events for shard {}: {}", self.shard_id, e);
+        }
+        let count = result.unwrap_or(0);
+
+        {
+            let mut prune_lock = self.prune_lock.write().await;
+            *prune_lock = false;
+        }
+        let elapsed = start.elapsed();
+        info!(
+            "Pruning events complete for shard {}. Pruned {} events in {} seconds",
+            self.shard_id,
+            count,
+            elapsed.as_secs()
+        );
+        self.statsd
+            .count_with_shard(self.shard_id, "prune.events", count as u64);
+        Ok(count)
+    }
+
     pub async fn prune_shard_chunks_until(
         &self,
         timestamp: u64,
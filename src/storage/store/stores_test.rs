@@ -21,6 +21,7 @@ mod tests {
         let limits = stores::StoreLimits {
             limits: test_helper::limits::test(),
             legacy_limits: test_helper::limits::zero(),
+            byte_limits: None,
         };
 
         stores::Stores::new(Arc::new(db), 1, trie, limits, test_helper::statsd_client())
@@ -88,6 +89,65 @@ mod tests {
         assert_eq!(events.events.len(), 2 * 3); // Same as before
     }
 
+    #[tokio::test]
+    async fn test_event_pruning_by_count() {
+        let stores = create_stores();
+        create_events(&stores);
+        assert_eq!(stores.get_events_pruned_until().unwrap(), 0);
+        let last_id = stores.get_events(0, None, None).unwrap().events[29].id;
+
+        let result = stores
+            .prune_events_over(10, Duration::from_secs(0), None)
+            .await;
+        assert_eq!(result.unwrap(), 20);
+        let events = stores.get_events(0, None, None).unwrap().events;
+        assert_eq!(events.len(), 10);
+        assert_eq!(events[9].id, last_id);
+        // The ids before the oldest retained one are recorded as pruned
+        assert_eq!(stores.get_events_pruned_until().unwrap(), events[0].id);
+
+        // Under the limit, nothing is pruned
+        let result = stores
+            .prune_events_over(10, Duration::from_secs(0), None)
+            .await;
+        assert_eq!(result.unwrap(), 0);
+
+        // New events keep getting larger ids than the pruned and retained ones
+        let message = messages_factory::casts::create_cast_add(123, "test", None, None);
+        stores.event_handler.set_current_height(10);
+        let mut txn = db::RocksDbTransactionBatch::new();
+        for _ in 0..3 {
+            let mut event = hub_events_factory::create_merge_event(&message);
+            stores
+                .event_handler
+                .commit_transaction(&mut txn, &mut event)
+                .unwrap();
+        }
+        stores.db.commit(txn).unwrap();
+
+        let ids: Vec<u64> = stores
+            .get_events(0, None, None)
+            .unwrap()
+            .events
+            .iter()
+            .map(|event| event.id)
+            .collect();
+        assert_eq!(ids.len(), 13);
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(ids[10] > last_id);
+
+        // Pruning by age only moves the pruned id forward
+        let cutoff_timestamp = get_farcaster_time().unwrap() - (2 * ONE_DAY_IN_SECONDS) - 10;
+        stores
+            .prune_events_until(cutoff_timestamp, Duration::from_secs(0), None)
+            .await
+            .unwrap();
+        assert_eq!(
+            stores.get_events_pruned_until().unwrap(),
+            stores.get_events(0, None, None).unwrap().events[0].id
+        );
+    }
+
     #[tokio::test]
     pub async fn test_shard_chunk_pruning() {
         let stores = create_stores();
@@ -1,4 +1,4 @@
-use crate::consensus::proposer::current_time;
+use crate::core::protocol_features::ActivationHeights;
 use crate::core::types::{Address, Vote};
 use crate::mempool::mempool::MempoolMessagesRequest;
 use crate::storage::db::{self, RocksDB};
@@ -108,6 +108,7 @@ pub mod limits {
         crate::storage::store::stores::StoreLimits {
             limits: test(),
             legacy_limits: legacy(),
+            byte_limits: None,
         }
     }
 }
@@ -143,6 +144,7 @@ pub fn new_engine_with_options(options: EngineOptions) -> (ShardEngine, tempfile
     let test_limits = options.limits.unwrap_or(StoreLimits {
         limits: limits::test(),
         legacy_limits: limits::zero(),
+        byte_limits: None,
     });
 
     (
@@ -155,7 +157,8 @@ pub fn new_engine_with_options(options: EngineOptions) -> (ShardEngine, tempfile
             statsd_client,
             256,
             options.messages_request_tx,
-        ),
+        )
+        .with_activation_heights(ActivationHeights::all()),
         dir,
     )
 }
@@ -317,7 +320,7 @@ pub fn state_change_to_shard_chunk(
         shard_index,
         block_number,
     });
-    chunk.header.as_mut().unwrap().timestamp = current_time();
+    chunk.header.as_mut().unwrap().timestamp = change.timestamp;
     chunk.transactions = change.transactions.clone();
     chunk
 }
@@ -0,0 +1,314 @@
+use crate::core::types::SnapchainValidatorSet;
+use crate::proto::{on_chain_event, EpochStakes, OnChainEventType, ValidatorStakeEventBody};
+use crate::storage::constants::{RootPrefix, PAGE_SIZE_MAX};
+use crate::storage::db::{PageOptions, RocksDB, RocksDbTransactionBatch, RocksdbError};
+use crate::storage::store::account::{get_onchain_events, OnchainEventStorageError};
+use prost::{DecodeError, Message};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ValidatorStakesError {
+    #[error(transparent)]
+    OnchainEventStorageError(#[from] OnchainEventStorageError),
+
+    #[error(transparent)]
+    RocksdbError(#[from] RocksdbError),
+
+    #[error(transparent)]
+    DecodeError(#[from] DecodeError),
+
+    #[error("Total voting power of the validators at height {height} overflows")]
+    VotingPowerOverflow { height: u64 },
+}
+
+/// Stakes are recorded in whole tokens, and capped so the voting power of any validator set adds
+/// up within a u64
+pub const MAX_VALIDATOR_STAKE: u64 = 1 << 40;
+
+/// The stakes a shard weighs its validators' votes by. Heights are grouped into epochs of
+/// epoch_length, and every validator of the shard has to agree on the weights of a height before
+/// voting on it, so they can't follow the stake events as they're merged. Instead, committing the
+/// last height of an epoch records the latest stake of each validator merged before it, and the
+/// next epoch is weighed by that record. It's written with the height's state, so every node
+/// records the same stakes, and snapshots carry them.
+#[derive(Clone)]
+pub struct ValidatorStakes {
+    db: Arc<RocksDB>,
+    epoch_length: u64,
+}
+
+impl ValidatorStakes {
+    pub fn new(db: Arc<RocksDB>, epoch_length: u64) -> Self {
+        ValidatorStakes { db, epoch_length }
+    }
+
+    pub fn epoch(&self, height: u64) -> u64 {
+        height / self.epoch_length
+    }
+
+    pub fn epoch_length(&self) -> u64 {
+        self.epoch_length
+    }
+
+    pub fn epoch_start_height(&self, epoch: u64) -> u64 {
+        epoch * self.epoch_length
+    }
+
+    fn make_epoch_stakes_key(epoch: u64) -> Vec<u8> {
+        let mut key = vec![RootPrefix::EpochStakes as u8];
+        key.extend_from_slice(&epoch.to_be_bytes());
+        key
+    }
+
+    // The latest stake of each validator among the stake events merged so far
+    fn latest_stakes(&self) -> Result<Vec<ValidatorStakeEventBody>, ValidatorStakesError> {
+        let mut stakes = BTreeMap::new();
+        let mut page_token = None;
+        loop {
+            // Keyed by block number then log index, so later events overwrite earlier ones
+            let page = get_onchain_events(
+                &self.db,
+                &PageOptions {
+                    page_size: Some(PAGE_SIZE_MAX),
+                    page_token,
+                    reverse: false,
+                },
+                OnChainEventType::EventTypeValidatorStake,
+                None,
+            )?;
+            for event in page.onchain_events {
+                if let Some(on_chain_event::Body::ValidatorStakeEventBody(body)) = event.body {
+                    stakes.insert(body.validator_public_key.clone(), body);
+                }
+            }
+            if page.next_page_token.is_none() {
+                break;
+            }
+            page_token = page.next_page_token;
+        }
+        Ok(stakes.into_values().collect())
+    }
+
+    /// Called while committing a height, before its state is written. When it's the last height
+    /// of an epoch, records the stakes the next epoch is weighed by into the height's txn.
+    pub fn record_epoch_end(
+        &self,
+        height: u64,
+        txn: &mut RocksDbTransactionBatch,
+    ) -> Result<(), ValidatorStakesError> {
+        if (height + 1) % self.epoch_length != 0 {
+            return Ok(());
+        }
+        let epoch = (height + 1) / self.epoch_length;
+        let stakes = EpochStakes {
+            epoch,
+            stakes: self.latest_stakes()?,
+        };
+        txn.put(Self::make_epoch_stakes_key(epoch), stakes.encode_to_vec());
+        Ok(())
+    }
+
+    pub fn get_epoch_stakes(
+        &self,
+        epoch: u64,
+    ) -> Result<Option<EpochStakes>, ValidatorStakesError> {
+        match self.db.get(&Self::make_epoch_stakes_key(epoch))? {
+            None => Ok(None),
+            Some(bytes) => Ok(Some(EpochStakes::decode(bytes.as_slice())?)),
+        }
+    }
+
+    /// Sets the voting power of every validator to its stake for the epoch of the height. The
+    /// validators keep equal weights until every one of them has a stake recorded, so a validator
+    /// that hasn't staked yet isn't left without a vote.
+    pub fn apply(
+        &self,
+        height: u64,
+        validator_set: &mut SnapchainValidatorSet,
+    ) -> Result<(), ValidatorStakesError> {
+        let Some(epoch_stakes) = self.get_epoch_stakes(self.epoch(height))? else {
+            return Ok(());
+        };
+        let stake = |public_key: &[u8]| {
+            epoch_stakes
+                .stakes
+                .iter()
+                .find(|stake| stake.validator_public_key == public_key)
+                .map_or(0, |stake| stake.stake)
+        };
+        if validator_set
+            .validators
+            .iter()
+            .any(|validator| stake(&validator.public_key.to_bytes()) == 0)
+        {
+            return Ok(());
+        }
+        let total = validator_set
+            .validators
+            .iter()
+            .try_fold(0u64, |total, validator| {
+                total.checked_add(stake(&validator.public_key.to_bytes()))
+            });
+        if total.is_none() {
+            return Err(ValidatorStakesError::VotingPowerOverflow { height });
+        }
+        for validator in &mut validator_set.validators {
+            validator.voting_power = stake(&validator.public_key.to_bytes());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::types::{SnapchainShard, SnapchainValidator};
+    use crate::storage::store::engine::ShardEngine;
+    use crate::storage::store::test_helper;
+    use crate::utils::factory::events_factory;
+    use libp2p::identity::ed25519::Keypair;
+
+    fn voting_powers(
+        stakes: &ValidatorStakes,
+        validator_set: &SnapchainValidatorSet,
+        height: u64,
+    ) -> Vec<u64> {
+        let mut validator_set = validator_set.clone();
+        stakes.apply(height, &mut validator_set).unwrap();
+        validator_set
+            .validators
+            .iter()
+            .map(|validator| validator.voting_power)
+            .collect()
+    }
+
+    fn commit_empty(engine: &mut ShardEngine) {
+        let state_change = engine.propose_state_change(1, vec![]);
+        test_helper::validate_and_commit_state_change(engine, &state_change);
+    }
+
+    #[tokio::test]
+    async fn test_stake_changes_voting_power_at_next_epoch() {
+        let (engine, _dir) = test_helper::new_engine();
+        let mut engine = engine.with_validator_stakes(Some(3));
+        let stakes = engine.validator_stakes().unwrap();
+
+        let validator_set = SnapchainValidatorSet::new(
+            (0..2)
+                .map(|_| {
+                    SnapchainValidator::new(
+                        SnapchainShard::new(1),
+                        Keypair::generate().public(),
+                        None,
+                        0,
+                    )
+                })
+                .collect(),
+        );
+        // The set is sorted, so look the keys up by index afterwards
+        let key = |i: usize| validator_set.validators[i].public_key.to_bytes().to_vec();
+
+        // The first epoch, up to height 2, is weighed equally
+        let stake = events_factory::create_validator_stake_event(key(0), 10, 100);
+        test_helper::commit_event(&mut engine, &stake).await;
+        // Merged at the epoch's last height, so it's only counted from the end of the next one
+        let stake = events_factory::create_validator_stake_event(key(1), 30, 101);
+        test_helper::commit_event(&mut engine, &stake).await;
+        assert_eq!(voting_powers(&stakes, &validator_set, 2), vec![1, 1]);
+        // Only the first validator has a stake recorded, so they stay equal
+        assert_eq!(stakes.get_epoch_stakes(1).unwrap().unwrap().stakes.len(), 1);
+        assert_eq!(voting_powers(&stakes, &validator_set, 3), vec![1, 1]);
+
+        // A change during the epoch waits for the next one
+        let stake = events_factory::create_validator_stake_event(key(0), 5, 102);
+        test_helper::commit_event(&mut engine, &stake).await;
+        commit_empty(&mut engine);
+        commit_empty(&mut engine);
+        assert_eq!(voting_powers(&stakes, &validator_set, 5), vec![1, 1]);
+        assert_eq!(voting_powers(&stakes, &validator_set, 6), vec![5, 30]);
+        assert_eq!(stakes.get_epoch_stakes(2).unwrap().unwrap().stakes.len(), 2);
+
+        // Without stake records, the validators stay equal
+        let (unstaked, _dir) = test_helper::new_engine();
+        let unstaked = unstaked
+            .with_validator_stakes(Some(3))
+            .validator_stakes()
+            .unwrap();
+        assert_eq!(voting_powers(&unstaked, &validator_set, 6), vec![1, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_partially_staked_set_is_weighed_equally() {
+        let (engine, _dir) = test_helper::new_engine();
+        let mut engine = engine.with_validator_stakes(Some(3));
+        let stakes = engine.validator_stakes().unwrap();
+        let validator_set = SnapchainValidatorSet::new(
+            (0..3)
+                .map(|_| {
+                    SnapchainValidator::new(
+                        SnapchainShard::new(1),
+                        Keypair::generate().public(),
+                        None,
+                        0,
+                    )
+                })
+                .collect(),
+        );
+        let key = |i: usize| validator_set.validators[i].public_key.to_bytes().to_vec();
+
+        // Two of the three stake, and the third unstakes after staking
+        for (i, stake, block_number) in [(0, 10, 100), (1, 20, 101), (2, 30, 102)] {
+            let stake = events_factory::create_validator_stake_event(key(i), stake, block_number);
+            test_helper::commit_event(&mut engine, &stake).await;
+        }
+        let unstake = events_factory::create_validator_stake_event(key(2), 0, 103);
+        test_helper::commit_event(&mut engine, &unstake).await;
+        // Recorded at the end of the next epoch
+        commit_empty(&mut engine);
+        assert_eq!(stakes.get_epoch_stakes(2).unwrap().unwrap().stakes.len(), 3);
+        assert_eq!(voting_powers(&stakes, &validator_set, 6), vec![1, 1, 1]);
+
+        // Weighed by stake once every validator of the set has one
+        let staked = SnapchainValidatorSet::new(validator_set.validators[..2].to_vec());
+        assert_eq!(voting_powers(&stakes, &staked, 6), vec![10, 20]);
+    }
+
+    #[tokio::test]
+    async fn test_voting_power_overflow_is_an_error() {
+        let (engine, _dir) = test_helper::new_engine();
+        let mut engine = engine.with_validator_stakes(Some(3));
+        let stakes = engine.validator_stakes().unwrap();
+        let validator_set = SnapchainValidatorSet::new(
+            (0..2)
+                .map(|_| {
+                    SnapchainValidator::new(
+                        SnapchainShard::new(1),
+                        Keypair::generate().public(),
+                        None,
+                        0,
+                    )
+                })
+                .collect(),
+        );
+
+        // Recorded before stakes were capped, the total doesn't fit
+        for (i, block_number) in [(0, 100), (1, 101)] {
+            let key = validator_set.validators[i].public_key.to_bytes().to_vec();
+            let stake = events_factory::create_validator_stake_event(key, u64::MAX, block_number);
+            test_helper::commit_event(&mut engine, &stake).await;
+        }
+        // Both are recorded at the end of the next epoch
+        for _ in 0..3 {
+            commit_empty(&mut engine);
+        }
+        let mut weighed = validator_set.clone();
+        assert!(matches!(
+            stakes.apply(6, &mut weighed),
+            Err(ValidatorStakesError::VotingPowerOverflow { height: 6 })
+        ));
+        // The set is left as it was
+        assert_eq!(weighed, validator_set);
+    }
+}
@@ -0,0 +1,89 @@
+use super::errors::TrieError;
+use super::trie_node::TrieNode;
+use crate::storage::db::RocksDB;
+use crate::storage::util::increment_vec_u8;
+
+/// On disk size of the db around a trie compaction. The sizes are of the whole db, but only the
+/// trie's keys are compacted, so the difference is what the trie gave back.
+#[derive(Debug, Default, PartialEq)]
+pub struct TrieCompactionResult {
+    pub size_before: u64,
+    pub size_after: u64,
+    pub bytes_reclaimed: u64,
+}
+
+/// Compacts the trie node records, which drops the deleted and overwritten ones from the files on
+/// disk, e.g. after garbage collection or a large pruning. The rest of the db isn't compacted.
+/// Blocks until the compaction is done.
+pub fn compact_trie(db: &RocksDB) -> Result<TrieCompactionResult, TrieError> {
+    let prefix = TrieNode::make_primary_key(&[], None);
+    let size_before = db.approximate_size();
+    db.compact_range(&prefix, &increment_vec_u8(&prefix))
+        .map_err(TrieError::wrap_database)?;
+    let size_after = db.approximate_size();
+    Ok(TrieCompactionResult {
+        size_before,
+        size_after,
+        bytes_reclaimed: size_before.saturating_sub(size_after),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::trie::merkle_trie::{Context, MerkleTrie};
+
+    #[test]
+    fn test_compacts_trie() {
+        let ctx = &Context::new();
+        let dir = tempfile::TempDir::new().unwrap();
+        let db = RocksDB::new(dir.path().join("trie.db").to_str().unwrap());
+        db.open().unwrap();
+        let mut trie = MerkleTrie::new(16).unwrap();
+        trie.initialize(&db).unwrap();
+
+        let keys: Vec<Vec<u8>> = (0..200u32)
+            .map(|i| [i.to_be_bytes().to_vec(), vec![0; 16]].concat())
+            .collect();
+        let mut txn = db.txn();
+        trie.insert(
+            ctx,
+            &db,
+            &mut txn,
+            keys.iter().map(|k| k.as_slice()).collect(),
+        )
+        .unwrap();
+        db.commit(txn).unwrap();
+        trie.reload(&db).unwrap();
+
+        // Most of the trie is deleted again, the records are only gone from disk once compacted
+        let mut txn = db.txn();
+        trie.delete(
+            ctx,
+            &db,
+            &mut txn,
+            keys[10..].iter().map(|k| k.as_slice()).collect(),
+        )
+        .unwrap();
+        db.commit(txn).unwrap();
+        trie.reload(&db).unwrap();
+        let root_hash = trie.root_hash().unwrap();
+
+        let result = compact_trie(&db).unwrap();
+        assert!(result.size_before > 0);
+        assert_eq!(
+            result.bytes_reclaimed,
+            result.size_before.saturating_sub(result.size_after)
+        );
+
+        let mut trie = MerkleTrie::new(16).unwrap();
+        trie.initialize(&db).unwrap();
+        assert_eq!(trie.root_hash().unwrap(), root_hash);
+        for key in &keys[..10] {
+            assert!(trie.exists(ctx, &db, key).unwrap());
+        }
+        for key in &keys[10..] {
+            assert!(!trie.exists(ctx, &db, key).unwrap());
+        }
+    }
+}
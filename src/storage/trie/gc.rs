@@ -0,0 +1,201 @@
+use super::errors::TrieError;
+use super::trie_node::TrieNode;
+use crate::storage::db::{PageOptions, RocksDB};
+use crate::storage::util::increment_vec_u8;
+use std::sync::Mutex;
+
+/// Totals of a garbage collection pass
+#[derive(Debug, Default, PartialEq)]
+pub struct TrieGcResult {
+    pub nodes_scanned: u64,
+    pub nodes_deleted: u64,
+    // Key and value bytes of the deleted nodes
+    pub bytes_reclaimed: u64,
+}
+
+// A reachable node on the path to the node being visited, with the children its record lists
+struct Ancestor {
+    path: Vec<u8>,
+    children: Vec<u8>,
+}
+
+fn wrap_hub_error(err: crate::core::error::HubError) -> TrieError {
+    TrieError::DatabaseError {
+        source: Box::new(err),
+    }
+}
+
+// Follows the path down from the committed root, a node is only reachable if every node above it
+// lists the next step as a child
+fn is_reachable(db: &RocksDB, path: &[u8]) -> Result<bool, TrieError> {
+    for depth in 0..path.len() {
+        let key = TrieNode::make_primary_key(&path[..depth], None);
+        let Some(bytes) = db.get(&key).map_err(TrieError::wrap_database)? else {
+            return Ok(false);
+        };
+        if !TrieNode::deserialize(&bytes)?
+            .children()
+            .contains_key(&path[depth])
+        {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Deletes the trie node records that can't be reached from the committed root, which writes that
+/// didn't complete can leave behind. Records are keyed by their path, so they're walked in key
+/// order, which visits every node after its parent.
+///
+/// Orphans are deleted in batches of batch_size while holding commit_lock, which has to be held by
+/// anything committing to the trie. Each one is checked against the committed trie again under the
+/// lock, so a node that became reachable after it was found is kept. Reads only ever follow
+/// reachable nodes, so they're unaffected.
+pub fn collect_garbage(
+    db: &RocksDB,
+    commit_lock: &Mutex<()>,
+    batch_size: usize,
+) -> Result<TrieGcResult, TrieError> {
+    let prefix = TrieNode::make_primary_key(&[], None);
+    let mut result = TrieGcResult::default();
+    let mut ancestors: Vec<Ancestor> = vec![];
+    let mut page_token = None;
+
+    loop {
+        let mut orphans = vec![];
+        let mut last_key = None;
+        let mut decode_error = None;
+        let all_done = db
+            .for_each_iterator_by_prefix(
+                Some(prefix.clone()),
+                Some(increment_vec_u8(&prefix)),
+                &PageOptions {
+                    page_size: None,
+                    page_token: page_token.take(),
+                    reverse: false,
+                },
+                |key, value| {
+                    result.nodes_scanned += 1;
+                    last_key = Some(key.to_vec());
+                    let path = &key[prefix.len()..];
+                    while ancestors
+                        .last()
+                        .is_some_and(|ancestor| !path.starts_with(&ancestor.path))
+                    {
+                        ancestors.pop();
+                    }
+                    let reachable = match ancestors.last() {
+                        None => path.is_empty(),
+                        Some(parent) => {
+                            parent.path.len() + 1 == path.len()
+                                && parent.children.contains(&path[path.len() - 1])
+                        }
+                    };
+
+                    if reachable {
+                        // Treating a node as orphaned would take everything below it along, so
+                        // nothing is deleted if a reachable node can't be read
+                        match TrieNode::deserialize(value) {
+                            Ok(node) => ancestors.push(Ancestor {
+                                path: path.to_vec(),
+                                children: node.children().keys().cloned().collect(),
+                            }),
+                            Err(err) => {
+                                decode_error = Some(err);
+                                return Ok(true);
+                            }
+                        }
+                    } else {
+                        orphans.push((key.to_vec(), value.len()));
+                    }
+                    Ok(orphans.len() >= batch_size)
+                },
+            )
+            .map_err(wrap_hub_error)?;
+        if let Some(err) = decode_error {
+            return Err(err);
+        }
+
+        if !orphans.is_empty() {
+            let _commit_guard = commit_lock.lock().unwrap();
+            let mut txn = db.txn();
+            for (key, value_len) in orphans {
+                if !is_reachable(db, &key[prefix.len()..])? {
+                    result.nodes_deleted += 1;
+                    result.bytes_reclaimed += (key.len() + value_len) as u64;
+                    txn.delete(key);
+                }
+            }
+            db.commit(txn).map_err(TrieError::wrap_database)?;
+        }
+
+        if all_done {
+            return Ok(result);
+        }
+        page_token = last_key;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::trie::merkle_trie::{Context, MerkleTrie};
+
+    #[test]
+    fn test_collects_orphaned_nodes() {
+        let ctx = &Context::new();
+        let dir = tempfile::TempDir::new().unwrap();
+        let db = RocksDB::new(dir.path().join("trie.db").to_str().unwrap());
+        db.open().unwrap();
+        let mut trie = MerkleTrie::new(16).unwrap();
+        trie.initialize(&db).unwrap();
+
+        let keys: Vec<Vec<u8>> = (0..20u8).map(|i| vec![i; 20]).collect();
+        let mut txn = db.txn();
+        trie.insert(
+            ctx,
+            &db,
+            &mut txn,
+            keys.iter().map(|k| k.as_slice()).collect(),
+        )
+        .unwrap();
+        db.commit(txn).unwrap();
+        trie.reload(&db).unwrap();
+        let root_hash = trie.root_hash().unwrap();
+        let node_count = db
+            .count_keys_at_prefix(TrieNode::make_primary_key(&[], None))
+            .unwrap();
+
+        // Paths only use nibbles with a branching factor of 16, so no parent points to these. One
+        // is below another orphan, and one is below the root.
+        let mut txn = db.txn();
+        let orphaned_paths = [vec![0xfe, 0x01], vec![0xfe, 0x01, 0x02], vec![0xff]];
+        for path in &orphaned_paths {
+            txn.put(
+                TrieNode::make_primary_key(path, None),
+                TrieNode::serialize(&TrieNode::new()),
+            );
+        }
+        db.commit(txn).unwrap();
+
+        let result = collect_garbage(&db, &Mutex::new(()), 2).unwrap();
+        assert_eq!(result.nodes_deleted, orphaned_paths.len() as u64);
+        assert!(result.bytes_reclaimed > 0);
+        assert_eq!(
+            db.count_keys_at_prefix(TrieNode::make_primary_key(&[], None))
+                .unwrap(),
+            node_count
+        );
+
+        let mut trie = MerkleTrie::new(16).unwrap();
+        trie.initialize(&db).unwrap();
+        assert_eq!(trie.root_hash().unwrap(), root_hash);
+        for key in &keys {
+            assert!(trie.exists(ctx, &db, key).unwrap());
+        }
+
+        // Nothing left to collect
+        let result = collect_garbage(&db, &Mutex::new(()), 2).unwrap();
+        assert_eq!(result.nodes_deleted, 0);
+    }
+}
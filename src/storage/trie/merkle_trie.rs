@@ -1,11 +1,14 @@
 use super::super::db::{RocksDB, RocksDbTransactionBatch};
 use super::errors::TrieError;
 use super::trie_node::{TrieNode, UNCOMPACTED_LENGTH};
+use crate::core::util::calculate_message_hash;
 use crate::mempool::routing::{MessageRouter, ShardRouter};
 use crate::proto;
 use crate::storage::store::account::{make_fid_key, IntoU8};
 use crate::storage::trie::{trie_node, util};
-use std::collections::HashMap;
+use crate::storage::util::{blake3_20, bytes_compare};
+use prost::Message as _;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use tracing::info;
 pub use trie_node::Context;
 
@@ -86,6 +89,8 @@ pub struct NodeMetadata {
     pub num_messages: usize,
     pub hash: String,
     pub children: HashMap<u8, NodeMetadata>,
+    // The full key, only set on leaves
+    pub key: Option<Vec<u8>>,
 }
 
 pub struct TrieSnapshot {
@@ -94,6 +99,197 @@ pub struct TrieSnapshot {
     pub num_messages: usize,
 }
 
+/// Trie changes collected to be applied together. Only the last change of each key is kept,
+/// which leaves the trie with the same keys as applying them one at a time, and so the same root,
+/// since its shape only depends on its keys. Applied together, the nodes their paths share are
+/// updated, and hashed back up to the root, once rather than once per key.
+#[derive(Default)]
+pub struct TrieUpdates {
+    // True to insert the key, false to delete it
+    updates: BTreeMap<Vec<u8>, bool>,
+}
+
+impl TrieUpdates {
+    pub fn insert(&mut self, key: Vec<u8>) {
+        self.updates.insert(key, true);
+    }
+
+    pub fn delete(&mut self, key: Vec<u8>) {
+        self.updates.insert(key, false);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.updates.is_empty()
+    }
+}
+
+pub struct TrieProof {
+    pub root_hash: Vec<u8>,
+    // Ordered from the leaf up to the root
+    pub steps: Vec<proto::MerkleProofStep>,
+}
+
+pub struct TrieMultiProof {
+    pub root_hash: Vec<u8>,
+    // Of each key's leaf, in the order the keys were given
+    pub leaf_depths: Vec<u32>,
+    // Siblings of the nodes on the paths to the leaves, ordered by prefix
+    pub nodes: Vec<proto::MultiProofNode>,
+}
+
+// Checks that [steps] prove [key] is included in a trie with the given root hash. [key] is the
+// unexpanded trie key, e.g. from TrieKey::for_message.
+pub fn verify_proof(
+    branching_factor: u32,
+    key: &[u8],
+    steps: &[proto::MerkleProofStep],
+    root_hash: &[u8],
+) -> bool {
+    let branch_xform = match util::get_transform_functions(branching_factor) {
+        Some(branch_xform) => branch_xform,
+        None => return false,
+    };
+
+    let mut hash = blake3_20(&(branch_xform.expand)(key));
+    for step in steps {
+        let mut concat_hashes = step.left_hashes.concat();
+        concat_hashes.extend_from_slice(&hash);
+        concat_hashes.extend_from_slice(&step.right_hashes.concat());
+        hash = blake3_20(&concat_hashes);
+    }
+
+    hash == root_hash
+}
+
+// Checks that [nodes] prove all of [keys] are included in a trie with the given root hash, each
+// with its leaf at the depth in [leaf_depths]. [keys] are unexpanded trie keys.
+pub fn verify_multiproof_keys(
+    branching_factor: u32,
+    keys: &[Vec<u8>],
+    leaf_depths: &[u32],
+    nodes: &[proto::MultiProofNode],
+    root_hash: &[u8],
+) -> bool {
+    let branch_xform = match util::get_transform_functions(branching_factor) {
+        Some(branch_xform) => branch_xform,
+        None => return false,
+    };
+    if keys.is_empty() || keys.len() != leaf_depths.len() {
+        return false;
+    }
+
+    // The hashes of the leaves and the siblings by prefix, then of the nodes on the paths as
+    // they're computed
+    let mut hashes: BTreeMap<Vec<u8>, Vec<u8>> = BTreeMap::new();
+    let mut path_prefixes = BTreeSet::new();
+    for (key, leaf_depth) in keys.iter().zip(leaf_depths) {
+        let key = (branch_xform.expand)(key);
+        let leaf_depth = *leaf_depth as usize;
+        if leaf_depth > key.len() {
+            return false;
+        }
+        let leaf_hash = blake3_20(&key);
+        // The same key can be proved twice, but two keys can't share a leaf
+        match hashes.insert(key[..leaf_depth].to_vec(), leaf_hash.clone()) {
+            Some(hash) if hash != leaf_hash => return false,
+            _ => {}
+        }
+        path_prefixes.extend((0..leaf_depth).map(|depth| key[..depth].to_vec()));
+    }
+    // Leaves have no children, so they can't be on the path to another leaf
+    if hashes.keys().any(|leaf| path_prefixes.contains(leaf)) {
+        return false;
+    }
+    for node in nodes {
+        // Siblings hang off the paths, and can't stand in for a node the keys determine
+        let on_path = match node.prefix.split_last() {
+            Some((_, parent)) => path_prefixes.contains(parent),
+            None => false,
+        };
+        if !on_path
+            || path_prefixes.contains(&node.prefix)
+            || hashes
+                .insert(node.prefix.clone(), node.hash.clone())
+                .is_some()
+        {
+            return false;
+        }
+    }
+
+    // Deepest first, so a node's children are all hashed before it is
+    let mut path_prefixes: Vec<Vec<u8>> = path_prefixes.into_iter().collect();
+    path_prefixes.sort_by_key(|prefix| std::cmp::Reverse(prefix.len()));
+    for prefix in path_prefixes {
+        let concat_hashes: Vec<u8> = hashes
+            .range(prefix.clone()..)
+            .take_while(|(child, _)| child.starts_with(&prefix))
+            .filter(|(child, _)| child.len() == prefix.len() + 1)
+            .flat_map(|(_, hash)| hash.iter().copied())
+            .collect();
+        hashes.insert(prefix, blake3_20(&concat_hashes));
+    }
+
+    hashes.get(&[][..]).map(|hash| hash.as_slice()) == Some(root_hash)
+}
+
+// The trie key of [message], if it hashes to its hash
+fn message_trie_key(message: &proto::Message) -> Option<Vec<u8>> {
+    // The hash covers data_bytes when it's set, so the data has to be the one they encode
+    let mut message = message.clone();
+    let data_bytes = match (&message.data_bytes, &message.data) {
+        (Some(data_bytes), data) => match proto::MessageData::decode(data_bytes.as_slice()) {
+            Ok(decoded) if data.is_none() || data.as_ref() == Some(&decoded) => {
+                message.data = Some(decoded);
+                data_bytes.clone()
+            }
+            _ => return None,
+        },
+        (None, Some(data)) => data.encode_to_vec(),
+        (None, None) => return None,
+    };
+    if calculate_message_hash(&data_bytes) != message.hash {
+        return None;
+    }
+    Some(TrieKey::for_message(&message))
+}
+
+// Checks that [message] is the one [proof] proves is included in a trie with the given root hash,
+// as returned by GetMessageWithProof. Light clients check this against a shard root they trust.
+pub fn verify_message_proof(
+    message: &proto::Message,
+    proof: &proto::MessageProof,
+    root_hash: &[u8],
+) -> bool {
+    match message_trie_key(message) {
+        Some(trie_key) => {
+            proof.trie_key == trie_key
+                && verify_proof(proof.branching_factor, &trie_key, &proof.steps, root_hash)
+        }
+        None => false,
+    }
+}
+
+// Checks that [messages] are the ones [proof] proves are included in a trie with the given root
+// hash, in the order they were requested from GetMultiProof.
+pub fn verify_multiproof(
+    messages: &[proto::Message],
+    proof: &proto::MultiProof,
+    root_hash: &[u8],
+) -> bool {
+    messages.len() == proof.trie_keys.len()
+        && messages
+            .iter()
+            .zip(&proof.trie_keys)
+            .all(|(message, trie_key)| message_trie_key(message).as_ref() == Some(trie_key))
+        && verify_multiproof_keys(
+            proof.branching_factor,
+            &proof.trie_keys,
+            &proof.leaf_depths,
+            &proof.nodes,
+            root_hash,
+        )
+}
+
 #[derive(Clone)]
 pub struct MerkleTrie {
     branch_xform: util::BranchingFactorTransform,
@@ -230,6 +426,30 @@ impl MerkleTrie {
         }
     }
 
+    pub fn apply(
+        &mut self,
+        ctx: &Context,
+        db: &RocksDB,
+        txn_batch: &mut RocksDbTransactionBatch,
+        updates: TrieUpdates,
+    ) -> Result<(), TrieError> {
+        let (inserts, deletes): (Vec<_>, Vec<_>) =
+            updates.updates.iter().partition(|(_, insert)| **insert);
+        self.delete(
+            ctx,
+            db,
+            txn_batch,
+            deletes.iter().map(|(key, _)| key.as_slice()).collect(),
+        )?;
+        self.insert(
+            ctx,
+            db,
+            txn_batch,
+            inserts.iter().map(|(key, _)| key.as_slice()).collect(),
+        )?;
+        Ok(())
+    }
+
     pub fn exists(&mut self, ctx: &Context, db: &RocksDB, key: &[u8]) -> Result<bool, TrieError> {
         let key: Vec<u8> = (self.branch_xform.expand)(key);
 
@@ -254,8 +474,17 @@ impl MerkleTrie {
         txn_batch: &mut RocksDbTransactionBatch,
         prefix: &[u8],
     ) -> Option<TrieNode> {
-        let prefix = (self.branch_xform.expand)(prefix);
-        let node_key = TrieNode::make_primary_key(&prefix, None);
+        self.get_node_at_path(db, txn_batch, &(self.branch_xform.expand)(prefix))
+    }
+
+    // The path is the expanded prefix
+    fn get_node_at_path(
+        &self,
+        db: &RocksDB,
+        txn_batch: &mut RocksDbTransactionBatch,
+        path: &[u8],
+    ) -> Option<TrieNode> {
+        let node_key = TrieNode::make_primary_key(path, None);
 
         // First, attempt to get it from the DB cache
         if let Some(Some(node_bytes)) = txn_batch.batch.get(&node_key) {
@@ -304,6 +533,41 @@ impl MerkleTrie {
         }
     }
 
+    /// Computes the root hash again from the leaves, rehashing every node instead of trusting the
+    /// child hashes stored with it. It reads the whole trie.
+    pub fn recompute_root_hash(db: &RocksDB) -> Result<Vec<u8>, TrieError> {
+        Self::recompute_hash(db, &mut vec![])
+    }
+
+    // The path is the expanded prefix
+    fn recompute_hash(db: &RocksDB, path: &mut Vec<u8>) -> Result<Vec<u8>, TrieError> {
+        let node_key = TrieNode::make_primary_key(path, None);
+        let node_bytes = db
+            .get(&node_key)
+            .map_err(TrieError::wrap_database)?
+            .ok_or_else(|| TrieError::NodeNotFound {
+                prefix: path.clone(),
+            })?;
+        let node = TrieNode::deserialize(&node_bytes)?;
+        if node.is_leaf() {
+            return Ok(node.hash());
+        }
+
+        let mut chars: Vec<u8> = node.children().keys().copied().collect();
+        chars.sort();
+        let mut concat_hashes = vec![];
+        for char in chars {
+            path.push(char);
+            let child_hash = Self::recompute_hash(db, path)?;
+            path.pop();
+            concat_hashes.extend_from_slice(&child_hash);
+        }
+        if concat_hashes.is_empty() {
+            return Ok(vec![]);
+        }
+        Ok(blake3_20(&concat_hashes))
+    }
+
     pub fn get_all_values(
         &mut self,
         ctx: &Context,
@@ -342,43 +606,210 @@ impl MerkleTrie {
         }
     }
 
-    pub fn get_trie_node_metadata(
+    // Builds an inclusion proof for [key] using only the nodes committed to the db, so any
+    // in-memory changes (e.g. from a proposal that hasn't been committed yet) are ignored.
+    // Returns None if the key is not in the committed trie.
+    pub fn get_proof(&self, db: &RocksDB, key: &[u8]) -> Result<Option<TrieProof>, TrieError> {
+        let key = (self.branch_xform.expand)(key);
+        let root = self.load_root(db)?.ok_or(TrieError::TrieNotInitialized)?;
+        let root_hash = root.hash();
+
+        let path = match Self::load_committed_path(db, root, &key)? {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let steps = path
+            .iter()
+            .rev()
+            .map(|(node, char)| {
+                let mut chars: Vec<u8> = node.child_hashes().keys().copied().collect();
+                chars.sort();
+                let hashes_for = |chars: Vec<&u8>| -> Vec<Vec<u8>> {
+                    chars
+                        .into_iter()
+                        .map(|c| node.child_hashes()[c].clone())
+                        .collect()
+                };
+                proto::MerkleProofStep {
+                    left_hashes: hashes_for(chars.iter().filter(|c| *c < char).collect()),
+                    right_hashes: hashes_for(chars.iter().filter(|c| *c > char).collect()),
+                }
+            })
+            .collect();
+
+        Ok(Some(TrieProof { root_hash, steps }))
+    }
+
+    // Builds a proof that all of [keys] are included in the committed trie, with the siblings
+    // their paths share only once. Like get_proof, in-memory changes are ignored. Returns None if
+    // any of the keys is not in the committed trie.
+    pub fn get_multiproof(
         &self,
         db: &RocksDB,
-        txn_batch: &mut RocksDbTransactionBatch,
-        prefix: &[u8],
-    ) -> Result<NodeMetadata, TrieError> {
-        if let Some(node) = self.get_node(db, txn_batch, prefix) {
-            let mut children = HashMap::new();
+        keys: &[Vec<u8>],
+    ) -> Result<Option<TrieMultiProof>, TrieError> {
+        let root = self.load_root(db)?.ok_or(TrieError::TrieNotInitialized)?;
+        let root_hash = root.hash();
+
+        let mut leaf_depths = vec![];
+        let mut leaves = BTreeSet::new();
+        // The nodes on the paths to the leaves, by prefix
+        let mut path_nodes = BTreeMap::new();
+        for key in keys {
+            let key = (self.branch_xform.expand)(key);
+            let path = match Self::load_committed_path(db, root.clone(), &key)? {
+                Some(path) => path,
+                None => return Ok(None),
+            };
+            leaf_depths.push(path.len() as u32);
+            leaves.insert(key[..path.len()].to_vec());
+            for (depth, (node, _)) in path.into_iter().enumerate() {
+                path_nodes.insert(key[..depth].to_vec(), node);
+            }
+        }
 
-            for char in node.children().keys() {
-                let mut child_prefix = prefix.to_vec();
-                child_prefix.push(*char);
+        let mut nodes: Vec<proto::MultiProofNode> = path_nodes
+            .iter()
+            .flat_map(|(prefix, node)| {
+                node.child_hashes().iter().map(|(char, hash)| {
+                    let mut child_prefix = prefix.clone();
+                    child_prefix.push(*char);
+                    proto::MultiProofNode {
+                        prefix: child_prefix,
+                        hash: hash.clone(),
+                    }
+                })
+            })
+            .filter(|node| !path_nodes.contains_key(&node.prefix) && !leaves.contains(&node.prefix))
+            .collect();
+        nodes.sort_by(|a, b| a.prefix.cmp(&b.prefix));
+
+        Ok(Some(TrieMultiProof {
+            root_hash,
+            leaf_depths,
+            nodes,
+        }))
+    }
 
-                let child_node = self.get_node(db, txn_batch, &child_prefix).ok_or(
-                    TrieError::ChildNotFound {
-                        char: *char,
-                        prefix: prefix.to_vec(),
-                    },
-                )?;
+    // The committed nodes from [root] down to the leaf of [key], an expanded key, each with the
+    // char of the next node on the path. Returns None if the key is not in the committed trie.
+    fn load_committed_path(
+        db: &RocksDB,
+        root: TrieNode,
+        key: &[u8],
+    ) -> Result<Option<Vec<(TrieNode, u8)>>, TrieError> {
+        let mut path = vec![];
+        let mut node = root;
+        while !node.is_leaf() {
+            let depth = path.len();
+            if depth >= key.len() {
+                return Ok(None);
+            }
+            let char = key[depth];
+            if !node.children().contains_key(&char) {
+                return Ok(None);
+            }
 
-                children.insert(
-                    *char,
-                    NodeMetadata {
-                        prefix: child_prefix,
-                        num_messages: child_node.items(),
-                        hash: hex::encode(&child_node.hash()),
-                        children: HashMap::new(),
-                    },
-                );
+            let child_key = TrieNode::make_primary_key(&key[..depth], Some(char));
+            let child = match db.get(&child_key).map_err(TrieError::wrap_database)? {
+                Some(child_bytes) => TrieNode::deserialize(&child_bytes)?,
+                None => {
+                    return Err(TrieError::ChildNotFound {
+                        char,
+                        prefix: key[..depth].to_vec(),
+                    })
+                }
+            };
+            path.push((node, char));
+            node = child;
+        }
+
+        match node.key() {
+            Some(leaf_key) if bytes_compare(leaf_key, key) == 0 => Ok(Some(path)),
+            _ => Ok(None),
+        }
+    }
+
+    fn node_metadata(&self, prefix: Vec<u8>, node: &TrieNode) -> NodeMetadata {
+        NodeMetadata {
+            prefix,
+            num_messages: node.items(),
+            hash: hex::encode(&node.hash()),
+            children: HashMap::new(),
+            key: node
+                .value()
+                .map(|key| (self.branch_xform.combine)(key.as_slice())),
+        }
+    }
+
+    // Below 256, a byte of the key spans several levels of the trie. Walks them from the node at
+    // path to list its children by the next byte, a leaf compacted above that byte being listed by
+    // its key.
+    fn collect_byte_children(
+        &self,
+        db: &RocksDB,
+        txn_batch: &mut RocksDbTransactionBatch,
+        path: &[u8],
+        node: &TrieNode,
+        levels_left: usize,
+        children: &mut HashMap<u8, NodeMetadata>,
+    ) -> Result<(), TrieError> {
+        let byte_index = path.len() / (self.branch_xform.expand)(&[0]).len();
+        for char in node.children().keys() {
+            let mut child_path = path.to_vec();
+            child_path.push(*char);
+            let child = self.get_node_at_path(db, txn_batch, &child_path).ok_or(
+                TrieError::ChildNotFound {
+                    char: *char,
+                    prefix: path.to_vec(),
+                },
+            )?;
+
+            if levels_left == 1 || child.is_leaf() {
+                let prefix = match child.value() {
+                    Some(key) if levels_left > 1 => {
+                        (self.branch_xform.combine)(key.as_slice())[..byte_index + 1].to_vec()
+                    }
+                    _ => (self.branch_xform.combine)(&child_path),
+                };
+                children.insert(prefix[byte_index], self.node_metadata(prefix, &child));
+            } else {
+                self.collect_byte_children(
+                    db,
+                    txn_batch,
+                    &child_path,
+                    &child,
+                    levels_left - 1,
+                    children,
+                )?;
             }
+        }
+        Ok(())
+    }
 
-            Ok(NodeMetadata {
-                prefix: prefix.to_vec(),
-                num_messages: node.items(),
-                hash: hex::encode(&node.hash()),
-                children,
-            })
+    /// The node at the prefix, with its children keyed by the next byte of their prefix whatever
+    /// the branching factor
+    pub fn get_trie_node_metadata(
+        &self,
+        db: &RocksDB,
+        txn_batch: &mut RocksDbTransactionBatch,
+        prefix: &[u8],
+    ) -> Result<NodeMetadata, TrieError> {
+        let path = (self.branch_xform.expand)(prefix);
+        if let Some(node) = self.get_node_at_path(db, txn_batch, &path) {
+            let mut children = HashMap::new();
+            self.collect_byte_children(
+                db,
+                txn_batch,
+                &path,
+                &node,
+                (self.branch_xform.expand)(&[0]).len(),
+                &mut children,
+            )?;
+
+            let mut metadata = self.node_metadata(prefix.to_vec(), &node);
+            metadata.children = children;
+            Ok(metadata)
         } else {
             Err(TrieError::NodeNotFound {
                 prefix: prefix.to_vec(),
@@ -489,6 +920,59 @@ mod tests {
         std::fs::remove_dir_all(&tmp_path).unwrap();
     }
 
+    #[test]
+    fn test_node_metadata_children_by_byte() {
+        let ctx = &Context::new();
+        let dir = tempfile::TempDir::new().unwrap();
+        let db = &RocksDB::new(dir.path().join("trie.db").to_str().unwrap());
+        db.open().unwrap();
+        let mut trie = MerkleTrie::new(16).unwrap();
+        trie.initialize(db).unwrap();
+        let mut txn_batch = RocksDbTransactionBatch::new();
+
+        // Past the uncompacted length, each leaf sits right below where its key diverges. key3's
+        // does so at the first nibble of the byte after the prefix.
+        let prefix = vec![1u8; UNCOMPACTED_LENGTH / 2];
+        let key1 = [prefix.clone(), vec![0xaa; 20]].concat();
+        let key2 = [prefix.clone(), vec![0xab; 20]].concat();
+        let key3 = [prefix.clone(), vec![0x5c; 20]].concat();
+        let key4 = vec![2u8; UNCOMPACTED_LENGTH / 2 + 20];
+        trie.insert(ctx, db, &mut txn_batch, vec![&key1, &key2, &key3, &key4])
+            .unwrap();
+
+        let root = trie
+            .get_trie_node_metadata(db, &mut txn_batch, &[])
+            .unwrap();
+        assert_eq!(root.num_messages, 4);
+        assert_eq!(root.key, None);
+        assert_eq!(root.children[&1].prefix, vec![1]);
+        assert_eq!(root.children[&1].num_messages, 3);
+        assert_eq!(root.children[&2].num_messages, 1);
+
+        let node = trie
+            .get_trie_node_metadata(db, &mut txn_batch, &prefix)
+            .unwrap();
+        assert_eq!(node.children.len(), 3);
+        assert_eq!(
+            node.children[&0xaa].prefix,
+            key1[..prefix.len() + 1].to_vec()
+        );
+        assert_eq!(node.children[&0xaa].key, Some(key1.clone()));
+        assert_eq!(node.children[&0xab].key, Some(key2.clone()));
+        assert_eq!(
+            node.children[&0x5c].prefix,
+            key3[..prefix.len() + 1].to_vec()
+        );
+        assert_eq!(node.children[&0x5c].key, Some(key3.clone()));
+
+        // A leaf is listed with its key
+        let leaf = trie
+            .get_trie_node_metadata(db, &mut txn_batch, &key4[..UNCOMPACTED_LENGTH / 2])
+            .unwrap();
+        assert_eq!(leaf.key, Some(key4));
+        assert!(leaf.children.is_empty());
+    }
+
     #[test]
     fn test_trie_keys_matches_uncompacted_length() {
         // The trie key for a message type should equal the uncompacted length in nibbles (due to the branching factor).
@@ -2,7 +2,7 @@
 mod tests {
     use crate::storage::db::{RocksDB, RocksDbTransactionBatch};
     use crate::storage::store::account::IntoU8;
-    use crate::storage::trie::merkle_trie::{Context, MerkleTrie, TrieKey};
+    use crate::storage::trie::merkle_trie::{verify_proof, Context, MerkleTrie, TrieKey};
     use crate::utils::factory::{events_factory, messages_factory};
 
     fn random_hash() -> Vec<u8> {
@@ -45,6 +45,42 @@ mod tests {
         assert_eq!(res, false);
     }
 
+    #[test]
+    fn test_recompute_root_hash() {
+        let ctx = &Context::new();
+
+        let tmp_path = tempfile::tempdir()
+            .unwrap()
+            .path()
+            .as_os_str()
+            .to_string_lossy()
+            .to_string();
+
+        let db = &RocksDB::new(&tmp_path);
+        db.open().unwrap();
+
+        let mut trie = MerkleTrie::new(16).unwrap();
+        trie.initialize(db).unwrap();
+        assert_eq!(MerkleTrie::recompute_root_hash(db).unwrap(), vec![]);
+
+        let hashes: Vec<Vec<u8>> = (0..50).map(|_| random_hash()).collect();
+        let mut txn_batch = RocksDbTransactionBatch::new();
+        trie.insert(
+            ctx,
+            db,
+            &mut txn_batch,
+            hashes.iter().map(|hash| hash.as_slice()).collect(),
+        )
+        .unwrap();
+        db.commit(txn_batch).unwrap();
+        trie.reload(db).unwrap();
+
+        assert_eq!(
+            MerkleTrie::recompute_root_hash(db).unwrap(),
+            trie.root_hash().unwrap()
+        );
+    }
+
     #[test]
     fn test_reload_with_existing_data() {
         let ctx = &Context::new();
@@ -87,6 +123,56 @@ mod tests {
         assert_eq!(res, false);
     }
 
+    #[test]
+    fn test_get_proof() {
+        let ctx = &Context::new();
+
+        let tmp_path = tempfile::tempdir()
+            .unwrap()
+            .path()
+            .as_os_str()
+            .to_string_lossy()
+            .to_string();
+
+        let db = &RocksDB::new(&tmp_path);
+        db.open().unwrap();
+
+        let mut trie = MerkleTrie::new(16).unwrap();
+        trie.initialize(db).unwrap();
+
+        let hashes: Vec<Vec<u8>> = (0..20).map(|_| random_hash()).collect();
+        let mut txn = RocksDbTransactionBatch::new();
+        trie.insert(
+            ctx,
+            db,
+            &mut txn,
+            hashes.iter().map(|h| h.as_slice()).collect(),
+        )
+        .unwrap();
+        db.commit(txn).unwrap();
+        trie.reload(db).unwrap();
+        let root_hash = trie.root_hash().unwrap();
+
+        for hash in &hashes {
+            let proof = trie.get_proof(db, hash).unwrap().unwrap();
+            assert_eq!(proof.root_hash, root_hash);
+            assert!(verify_proof(16, hash, &proof.steps, &root_hash));
+            // The proof doesn't verify a different key or root
+            assert!(!verify_proof(16, &random_hash(), &proof.steps, &root_hash));
+            assert!(!verify_proof(16, hash, &proof.steps, &random_hash()));
+        }
+
+        // Keys that haven't been committed have no proof
+        let uncommitted = random_hash();
+        let mut txn = RocksDbTransactionBatch::new();
+        trie.insert(ctx, db, &mut txn, vec![&uncommitted]).unwrap();
+        assert!(trie.get_proof(db, &uncommitted).unwrap().is_none());
+
+        // And existing proofs are still against the committed root
+        let proof = trie.get_proof(db, &hashes[0]).unwrap().unwrap();
+        assert_eq!(proof.root_hash, root_hash);
+    }
+
     #[test]
     fn test_trie_key() {
         let fid_key = TrieKey::for_fid(1234);
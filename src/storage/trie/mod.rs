@@ -1,5 +1,8 @@
+pub mod compaction;
 pub mod errors;
+pub mod gc;
 pub mod merkle_trie;
+pub mod scratch;
 mod trie_node; // this is private on purpose
 mod util;
 
@@ -0,0 +1,66 @@
+use crate::storage::db::{RocksDB, RocksDbTransactionBatch};
+use crate::storage::trie::errors::TrieError;
+use crate::storage::trie::merkle_trie::{Context, MerkleTrie};
+
+// Keys inserted per write batch
+const INSERT_BATCH_SIZE: usize = 10_000;
+
+/// A trie in a db of its own, under a temporary dir that's deleted along with it. It's built to
+/// find out what a trie's root should be, without reading or writing the trie itself.
+pub struct ScratchTrie {
+    trie: MerkleTrie,
+    db: RocksDB,
+    pending: Vec<Vec<u8>>,
+    _dir: tempfile::TempDir,
+}
+
+impl ScratchTrie {
+    pub fn new(branching_factor: u32) -> Result<Self, TrieError> {
+        let dir = tempfile::TempDir::new().map_err(|err| TrieError::DatabaseError {
+            source: Box::new(err),
+        })?;
+        let db = RocksDB::new(dir.path().join("trie").to_str().unwrap());
+        db.open().map_err(TrieError::wrap_database)?;
+        let mut trie = MerkleTrie::new(branching_factor)?;
+        trie.initialize(&db)?;
+        Ok(ScratchTrie {
+            trie,
+            db,
+            pending: vec![],
+            _dir: dir,
+        })
+    }
+
+    pub fn insert(&mut self, key: Vec<u8>) -> Result<(), TrieError> {
+        self.pending.push(key);
+        if self.pending.len() >= INSERT_BATCH_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), TrieError> {
+        let mut txn = RocksDbTransactionBatch::new();
+        self.trie.insert(
+            &Context::new(),
+            &self.db,
+            &mut txn,
+            self.pending.iter().map(|key| key.as_slice()).collect(),
+        )?;
+        self.db.commit(txn).map_err(TrieError::wrap_database)?;
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// The root hash with every key inserted so far
+    pub fn root_hash(&mut self) -> Result<Vec<u8>, TrieError> {
+        self.flush()?;
+        self.trie.root_hash()
+    }
+}
+
+impl Drop for ScratchTrie {
+    fn drop(&mut self) {
+        self.db.close();
+    }
+}
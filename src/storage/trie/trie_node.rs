@@ -204,6 +204,14 @@ impl TrieNode {
         &self.children
     }
 
+    pub fn child_hashes(&self) -> &HashMap<u8, Vec<u8>> {
+        &self.child_hashes
+    }
+
+    pub fn key(&self) -> Option<&Vec<u8>> {
+        self.key.as_ref()
+    }
+
     pub fn get_node_from_trie(
         &mut self,
         ctx: &Context,
@@ -32,6 +32,8 @@ impl Drop for EnvRestorer {
 mod tests {
     use super::*;
     use crate::cfg::load_and_merge_config;
+    use crate::proto::FarcasterNetwork;
+    use crate::storage::store::stores::Limits;
     use serial_test::serial; // for setting env vars
     use std::fs::File;
     use std::io::Write;
@@ -109,6 +111,24 @@ mod tests {
         })
     }
 
+    #[test]
+    #[serial]
+    fn test_default_log_format() {
+        run_test(vec![], || {
+            let (_tmpdir, file_path) = write_config_file("");
+
+            let args = vec![
+                "test_binary".to_string(),
+                "--config-path".to_string(),
+                file_path.to_string(),
+            ];
+
+            let config = load_and_merge_config(args).expect("Failed to load config");
+
+            assert_eq!(config.log_format, "pretty");
+        })
+    }
+
     #[test]
     #[serial]
     fn test_load_with_env_vars() {
@@ -157,11 +177,16 @@ mod tests {
                     file_path.to_string(),
                     "--log-format".to_string(),
                     "json".to_string(),
+                    "--bootstrap".to_string(),
+                    "--deep".to_string(),
                 ];
 
                 let config = load_and_merge_config(args).expect("Failed to load config");
 
                 assert_eq!(config.log_format, "json");
+                assert!(config.bootstrap);
+                assert!(config.storage.self_check.enabled);
+                assert!(config.storage.self_check.deep);
             },
         )
     }
@@ -205,6 +230,106 @@ mod tests {
         )
     }
 
+    #[test]
+    #[serial]
+    fn test_storage_shard_dirs() {
+        run_test(vec![], || {
+            let (_tmpdir, file_path) = write_config_file(
+                r#"
+                rocksdb_dir = "/data/rocks"
+
+                [[storage.shard_dirs]]
+                shard_id = 2
+                dir = "/mnt/nvme1/rocks"
+            "#,
+            );
+
+            let args = vec![
+                "test_binary".to_string(),
+                "--config-path".to_string(),
+                file_path.to_string(),
+            ];
+
+            let config = load_and_merge_config(args).expect("Failed to load config");
+            assert_eq!(
+                config.storage.shard_base_dir(&config.rocksdb_dir, 1),
+                "/data/rocks"
+            );
+            assert_eq!(
+                config.storage.shard_base_dir(&config.rocksdb_dir, 2),
+                "/mnt/nvme1/rocks"
+            );
+        })
+    }
+
+    #[test]
+    #[serial]
+    fn test_snapshot_retry_policy() {
+        run_test(vec![], || {
+            let (_tmpdir, file_path) = write_config_file(
+                r#"
+                [snapshot]
+                retry_max_attempts = 8
+                retry_base_backoff = "2s"
+            "#,
+            );
+
+            let args = vec![
+                "test_binary".to_string(),
+                "--config-path".to_string(),
+                file_path.to_string(),
+            ];
+
+            let config = load_and_merge_config(args).expect("Failed to load config");
+            assert_eq!(config.snapshot.retry_max_attempts, 8);
+            assert_eq!(
+                config.snapshot.retry_base_backoff,
+                std::time::Duration::from_secs(2)
+            );
+            assert_eq!(
+                config.snapshot.retry_jitter,
+                std::time::Duration::from_millis(250)
+            );
+        })
+    }
+
+    #[test]
+    #[serial]
+    fn test_custom_network() {
+        run_test(vec![], || {
+            let (_tmpdir, file_path) = write_config_file(
+                r#"
+                fc_network = "Custom"
+
+                [custom_network]
+                farcaster_epoch = 1704067200000
+
+                [custom_network.limits]
+                casts = 500
+            "#,
+            );
+
+            let args = vec![
+                "test_binary".to_string(),
+                "--config-path".to_string(),
+                file_path.to_string(),
+            ];
+
+            let config = load_and_merge_config(args).expect("Failed to load config");
+            assert_eq!(config.fc_network, FarcasterNetwork::Custom);
+            assert_eq!(config.custom_network.validate(config.fc_network), Ok(()));
+            assert_eq!(
+                config.custom_network.farcaster_epoch(config.fc_network),
+                1704067200000
+            );
+            let store_limits = config.custom_network.store_limits(config.fc_network);
+            assert_eq!(store_limits.limits.casts, 500);
+            // Stores that aren't configured keep the mainnet limits
+            assert_eq!(store_limits.limits.links, Limits::default().links);
+            assert_eq!(store_limits.legacy_limits, Limits::legacy());
+        })
+    }
+
     #[test]
     #[serial]
     fn test_missing_config_file() {
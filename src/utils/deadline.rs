@@ -0,0 +1,20 @@
+use std::future::Future;
+use tokio::time::Instant;
+
+tokio::task_local! {
+    static DEADLINE: Instant;
+}
+
+// Runs the future with a deadline that long running synchronous work, like store iteration,
+// can check to stop early. Dropping a future only cancels it at the next await point, so this
+// is what actually stops the work.
+pub async fn with_deadline<F: Future>(deadline: Instant, f: F) -> F::Output {
+    DEADLINE.scope(deadline, f).await
+}
+
+// Always false outside of [with_deadline], e.g. on the consensus path
+pub fn deadline_exceeded() -> bool {
+    DEADLINE
+        .try_with(|deadline| Instant::now() >= *deadline)
+        .unwrap_or(false)
+}
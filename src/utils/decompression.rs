@@ -0,0 +1,80 @@
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::{self, Read, Write};
+use thiserror::Error;
+
+// The first bytes of every gzip stream
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+#[derive(Debug, Error)]
+pub enum DecompressionError {
+    #[error("decompressed size is over the limit of {0} bytes")]
+    LimitExceeded(u64),
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+pub fn is_gzip(data: &[u8]) -> bool {
+    data.starts_with(&GZIP_MAGIC)
+}
+
+pub fn gzip(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+/// Decompresses the gzip stream in [reader] into [writer], giving up as soon as more than [limit]
+/// bytes come out, so a small input crafted to expand enormously is never expanded past the
+/// limit. Returns the decompressed size.
+pub fn gunzip_limited<R: Read, W: Write>(
+    reader: R,
+    writer: &mut W,
+    limit: u64,
+) -> Result<u64, DecompressionError> {
+    let mut decoder = GzDecoder::new(reader).take(limit.saturating_add(1));
+    let size = io::copy(&mut decoder, writer)?;
+    if size > limit {
+        return Err(DecompressionError::LimitExceeded(limit));
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gunzip_limited() {
+        let compressed = gzip(b"hello snapchain").unwrap();
+        assert!(is_gzip(&compressed));
+        assert!(!is_gzip(b"hello snapchain"));
+
+        let mut decompressed = vec![];
+        let size = gunzip_limited(compressed.as_slice(), &mut decompressed, 15).unwrap();
+        assert_eq!(size, 15);
+        assert_eq!(decompressed, b"hello snapchain");
+
+        let mut decompressed = vec![];
+        let err = gunzip_limited(compressed.as_slice(), &mut decompressed, 14).unwrap_err();
+        assert!(matches!(err, DecompressionError::LimitExceeded(14)));
+    }
+
+    #[test]
+    fn test_rejects_decompression_bomb() {
+        // 64mb of zeros compress to well under 100kb
+        let bomb = gzip(&vec![0u8; 64 * 1024 * 1024]).unwrap();
+        assert!(bomb.len() < 100 * 1024);
+
+        let mut decompressed = vec![];
+        let err = gunzip_limited(bomb.as_slice(), &mut decompressed, 1024 * 1024).unwrap_err();
+        assert!(matches!(err, DecompressionError::LimitExceeded(_)));
+        // Expansion stopped right past the limit
+        assert_eq!(decompressed.len(), 1024 * 1024 + 1);
+
+        let mut decompressed = vec![];
+        assert!(gunzip_limited(&b"not gzip"[..], &mut decompressed, 1024).is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use crate::core::types::FARCASTER_EPOCH;
+use crate::core::custom_network::farcaster_epoch;
 use crate::proto as message;
 use crate::proto::{OnChainEvent, OnChainEventType};
 use ed25519_dalek::{SecretKey, Signer, SigningKey};
@@ -26,7 +26,7 @@ pub mod time {
     use super::*;
 
     pub fn farcaster_time() -> u32 {
-        current_timestamp() - (FARCASTER_EPOCH / 1000) as u32
+        current_timestamp() - (farcaster_epoch() / 1000) as u32
     }
 
     pub fn farcaster_time_with_offset(offset: i32) -> u32 {
@@ -130,7 +130,7 @@ pub mod events_factory {
         timestamp: Option<u32>,
         key_type: Option<u32>,
     ) -> OnChainEvent {
-        if timestamp.is_some() && !(timestamp.unwrap() > (FARCASTER_EPOCH / 1000) as u32) {
+        if timestamp.is_some() && !(timestamp.unwrap() > (farcaster_epoch() / 1000) as u32) {
             panic!("Block timestamps must be unix epoch in seconds");
         }
         let signer_event_body = proto::SignerEventBody {
@@ -167,7 +167,7 @@ pub mod events_factory {
         custody_address: Vec<u8>,
         timestamp: Option<u32>,
     ) -> OnChainEvent {
-        if timestamp.is_some() && !(timestamp.unwrap() > (FARCASTER_EPOCH / 1000) as u32) {
+        if timestamp.is_some() && !(timestamp.unwrap() > (farcaster_epoch() / 1000) as u32) {
             panic!("Block timestamps must be unix epoch in seconds");
         }
         let id_register_event_body = proto::IdRegisterEventBody {
@@ -196,6 +196,31 @@ pub mod events_factory {
             )),
         }
     }
+
+    pub fn create_validator_stake_event(
+        validator_public_key: Vec<u8>,
+        stake: u64,
+        block_number: u32,
+    ) -> OnChainEvent {
+        OnChainEvent {
+            r#type: OnChainEventType::EventTypeValidatorStake as i32,
+            chain_id: 10,
+            block_number,
+            block_hash: vec![block_number as u8; 32],
+            block_timestamp: time::current_timestamp_with_offset(-10) as u64,
+            transaction_hash: rand::random::<[u8; 32]>().to_vec(),
+            log_index: 0,
+            fid: 0,
+            tx_index: 0,
+            version: 1,
+            body: Some(proto::on_chain_event::Body::ValidatorStakeEventBody(
+                proto::ValidatorStakeEventBody {
+                    validator_public_key,
+                    stake,
+                },
+            )),
+        }
+    }
 }
 
 pub mod messages_factory {
@@ -262,13 +287,24 @@ pub mod messages_factory {
             text: &str,
             timestamp: Option<u32>,
             private_key: Option<&SigningKey>,
+        ) -> message::Message {
+            create_cast_with_mentions(fid, text, vec![], timestamp, private_key)
+        }
+
+        // Every mention is placed at the start of the text
+        pub fn create_cast_with_mentions(
+            fid: u64,
+            text: &str,
+            mentions: Vec<u64>,
+            timestamp: Option<u32>,
+            private_key: Option<&SigningKey>,
         ) -> message::Message {
             let cast_add = CastAddBody {
                 text: text.to_string(),
                 embeds: vec![],
                 embeds_deprecated: vec![],
-                mentions: vec![],
-                mentions_positions: vec![],
+                mentions_positions: vec![0; mentions.len()],
+                mentions,
                 parent: None,
                 r#type: Cast as i32,
             };
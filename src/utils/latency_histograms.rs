@@ -0,0 +1,178 @@
+use prometheus_client::encoding::text::encode;
+use prometheus_client::metrics::family::{Family, MetricConstructor};
+use prometheus_client::metrics::histogram::Histogram;
+use prometheus_client::registry::Registry;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// Upper bounds in milliseconds, from a fast rpc read to a submission waiting out a few blocks
+pub const DEFAULT_BUCKETS_MS: [f64; 15] = [
+    1.0, 2.0, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0, 5000.0, 10000.0, 30000.0,
+    60000.0,
+];
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Config {
+    // Served on the http server's /metrics endpoint when enabled
+    pub enabled: bool,
+    // Bucket upper bounds in milliseconds, shared by every histogram
+    pub buckets_ms: Vec<f64>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            buckets_ms: DEFAULT_BUCKETS_MS.to_vec(),
+        }
+    }
+}
+
+impl Config {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.buckets_ms.is_empty() {
+            return Err("latency histograms need at least one bucket".to_string());
+        }
+        if self.buckets_ms.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err("latency histogram buckets must be increasing".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// The latencies recorded as histograms, each also sent as a statsd timer under its statsd key
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Latency {
+    // From a message entering the mempool to its block being committed
+    SubmitToCommit,
+    // Grpc handlers, until the response is returned
+    Rpc,
+    OnchainEventsBatch,
+}
+
+impl Latency {
+    const ALL: [Latency; 3] = [
+        Latency::SubmitToCommit,
+        Latency::Rpc,
+        Latency::OnchainEventsBatch,
+    ];
+
+    pub fn statsd_key(&self) -> &'static str {
+        match self {
+            Latency::SubmitToCommit => "mempool.inclusion_latency",
+            Latency::Rpc => "rpc.duration",
+            Latency::OnchainEventsBatch => "onchain_events.batch_time",
+        }
+    }
+
+    fn histogram_name(&self) -> &'static str {
+        match self {
+            Latency::SubmitToCommit => "submit_to_commit_milliseconds",
+            Latency::Rpc => "rpc_duration_milliseconds",
+            Latency::OnchainEventsBatch => "onchain_events_batch_milliseconds",
+        }
+    }
+
+    fn help(&self) -> &'static str {
+        match self {
+            Latency::SubmitToCommit => "Time from a message entering the mempool to its commit",
+            Latency::Rpc => "Time taken by grpc handlers",
+            Latency::OnchainEventsBatch => "Time taken to process a batch of onchain event logs",
+        }
+    }
+}
+
+type Labels = Vec<(String, String)>;
+
+#[derive(Clone, Debug)]
+struct Buckets(Vec<f64>);
+
+impl MetricConstructor<Histogram> for Buckets {
+    fn new_metric(&self) -> Histogram {
+        Histogram::new(self.0.iter().cloned())
+    }
+}
+
+/// Prometheus histograms for the latencies in [Latency]. Recorded through the statsd client, so
+/// each call site records a latency once for both.
+#[derive(Debug)]
+pub struct LatencyHistograms {
+    registry: Registry,
+    histograms: HashMap<Latency, Family<Labels, Histogram, Buckets>>,
+}
+
+impl LatencyHistograms {
+    pub fn new(buckets_ms: &[f64]) -> Self {
+        let mut registry = Registry::with_prefix("snapchain");
+        let mut histograms = HashMap::new();
+        for latency in Latency::ALL {
+            let family = Family::new_with_constructor(Buckets(buckets_ms.to_vec()));
+            registry.register(latency.histogram_name(), latency.help(), family.clone());
+            histograms.insert(latency, family);
+        }
+        Self {
+            registry,
+            histograms,
+        }
+    }
+
+    pub fn observe(&self, latency: Latency, labels: &[(&str, &str)], value_ms: u64) {
+        let labels: Labels = labels
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        self.histograms[&latency]
+            .get_or_create(&labels)
+            .observe(value_ms as f64);
+    }
+
+    /// In the prometheus text format
+    pub fn encode(&self) -> String {
+        let mut encoded = String::new();
+        // Writing to a string can't fail
+        encode(&mut encoded, &self.registry).unwrap();
+        encoded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records_histograms() {
+        let histograms = LatencyHistograms::new(&[10.0, 100.0]);
+        histograms.observe(Latency::Rpc, &[("method", "HubService.GetInfo")], 5);
+        histograms.observe(Latency::Rpc, &[("method", "HubService.GetInfo")], 50);
+        histograms.observe(Latency::Rpc, &[("method", "HubService.GetInfo")], 500);
+
+        let encoded = histograms.encode();
+        assert!(encoded.contains("# TYPE snapchain_rpc_duration_milliseconds histogram"));
+        assert!(encoded.contains(
+            "snapchain_rpc_duration_milliseconds_bucket{method=\"HubService.GetInfo\",le=\"10.0\"} 1"
+        ));
+        assert!(encoded.contains(
+            "snapchain_rpc_duration_milliseconds_bucket{method=\"HubService.GetInfo\",le=\"100.0\"} 2"
+        ));
+        assert!(encoded.contains(
+            "snapchain_rpc_duration_milliseconds_count{method=\"HubService.GetInfo\"} 3"
+        ));
+        // Registered before anything is recorded
+        assert!(encoded.contains("# TYPE snapchain_submit_to_commit_milliseconds histogram"));
+    }
+
+    #[test]
+    fn test_validate_buckets() {
+        assert!(Config::default().validate().is_ok());
+        let config = Config {
+            enabled: true,
+            buckets_ms: vec![10.0, 5.0],
+        };
+        assert!(config.validate().is_err());
+        let config = Config {
+            enabled: true,
+            buckets_ms: vec![],
+        };
+        assert!(config.validate().is_err());
+    }
+}
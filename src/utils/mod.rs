@@ -1,3 +1,9 @@
 pub mod cli;
+pub mod deadline;
+pub mod decompression;
 pub mod factory;
+pub mod latency_histograms;
+pub mod query_params;
+pub mod reshard_advice;
 pub mod statsd_wrapper;
+pub mod trie_diff;
@@ -0,0 +1,37 @@
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+tokio::task_local! {
+    static QUERY_PARAMS: QueryParams;
+}
+
+/// The parameters that describe how much work a request asks for, e.g. its fid and page size, so
+/// slow requests can be logged with them. Only numbers are recorded, never message contents or
+/// request metadata like the authorization header.
+#[derive(Clone, Debug, Default)]
+pub struct QueryParams {
+    params: Arc<Mutex<Vec<(&'static str, u64)>>>,
+}
+
+impl QueryParams {
+    pub fn get(&self) -> Vec<(&'static str, u64)> {
+        self.params.lock().unwrap().clone()
+    }
+}
+
+// Records the future's parameters into params, which the caller keeps to read them once it's done
+pub async fn with_query_params<F: Future>(params: QueryParams, f: F) -> F::Output {
+    QUERY_PARAMS.scope(params, f).await
+}
+
+// Only the first value of each parameter is kept, e.g. a request that reads several shards is
+// recorded with the first one. Does nothing outside of [with_query_params], or in tasks the
+// request spawns.
+pub fn record_query_param(name: &'static str, value: u64) {
+    _ = QUERY_PARAMS.try_with(|query_params| {
+        let mut params = query_params.params.lock().unwrap();
+        if !params.iter().any(|(existing, _)| *existing == name) {
+            params.push((name, value));
+        }
+    });
+}
@@ -0,0 +1,191 @@
+use crate::mempool::routing::{MessageRouter, ShardRouter};
+use crate::proto::ShardStats;
+use serde::Serialize;
+
+/// Where an fid would go under a new shard count
+#[derive(Debug, PartialEq, Serialize)]
+pub struct FidAssignment {
+    pub fid: u64,
+    // Messages written, as observed by the node
+    pub messages: u64,
+    pub current_shard: u32,
+    // Where hashing the fid would route it with the target shard count
+    pub hashed_shard: u32,
+    pub shard: u32,
+}
+
+/// A recommended split of the observed load across a target number of shards. It starts from the
+/// hash routing at the target count and only overrides it for the fids that even the load out, so
+/// most fids keep the shard hashing gives them. Fids that weren't seen writing keep theirs too.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ReshardPlan {
+    pub target_shards: u32,
+    // Indexed by shard, starting from shard 1
+    pub hashed_loads: Vec<u64>,
+    pub planned_loads: Vec<u64>,
+    pub hashed_variance: f64,
+    pub planned_variance: f64,
+    // The active fids, by fid
+    pub assignments: Vec<FidAssignment>,
+}
+
+impl ReshardPlan {
+    // The fids the plan routes away from their hashed shard
+    pub fn overrides(&self) -> impl Iterator<Item = &FidAssignment> {
+        self.assignments
+            .iter()
+            .filter(|assignment| assignment.shard != assignment.hashed_shard)
+    }
+}
+
+fn variance(loads: &[u64]) -> f64 {
+    if loads.is_empty() {
+        return 0.0;
+    }
+    let mean = loads.iter().sum::<u64>() as f64 / loads.len() as f64;
+    loads
+        .iter()
+        .map(|load| (*load as f64 - mean).powi(2))
+        .sum::<f64>()
+        / loads.len() as f64
+}
+
+/// Plans the move to [target_shards] from the per fid activity in GetShardStats. Repeatedly moves
+/// an fid from the busiest shard to the quietest one, picking the fid that lowers the variance the
+/// most, until no move lowers it.
+pub fn recommend(shard_stats: &[ShardStats], target_shards: u32) -> ReshardPlan {
+    let router = ShardRouter {};
+    let mut assignments: Vec<FidAssignment> = shard_stats
+        .iter()
+        .flat_map(|stats| {
+            stats.fid_activity.iter().map(|activity| {
+                let hashed_shard = router.route_fid(activity.fid, target_shards);
+                FidAssignment {
+                    fid: activity.fid,
+                    messages: activity.messages,
+                    current_shard: stats.shard_id,
+                    hashed_shard,
+                    shard: hashed_shard,
+                }
+            })
+        })
+        .collect();
+    assignments.sort_by_key(|assignment| assignment.fid);
+
+    let mut loads = vec![0u64; target_shards as usize];
+    for assignment in &assignments {
+        loads[assignment.shard as usize - 1] += assignment.messages;
+    }
+    let hashed_loads = loads.clone();
+
+    // Every move strictly lowers the sum of squared loads, so this ends
+    loop {
+        let (busiest, quietest) = match (
+            (0..loads.len()).max_by_key(|shard| loads[*shard]),
+            (0..loads.len()).min_by_key(|shard| loads[*shard]),
+        ) {
+            (Some(busiest), Some(quietest)) => (busiest, quietest),
+            _ => break,
+        };
+        let gap = loads[busiest] - loads[quietest];
+        // Moving m messages lowers the sum of squares by 2m(gap - m), most when m is gap / 2
+        let best = assignments
+            .iter_mut()
+            .filter(|assignment| {
+                assignment.shard as usize - 1 == busiest
+                    && assignment.messages > 0
+                    && assignment.messages < gap
+            })
+            .max_by_key(|assignment| assignment.messages * (gap - assignment.messages));
+        match best {
+            Some(assignment) => {
+                assignment.shard = quietest as u32 + 1;
+                loads[busiest] -= assignment.messages;
+                loads[quietest] += assignment.messages;
+            }
+            None => break,
+        }
+    }
+
+    ReshardPlan {
+        target_shards,
+        hashed_variance: variance(&hashed_loads),
+        planned_variance: variance(&loads),
+        hashed_loads,
+        planned_loads: loads,
+        assignments,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::FidActivity;
+
+    fn stats(shard_id: u32, activity: &[(u64, u64)]) -> ShardStats {
+        ShardStats {
+            shard_id,
+            fid_activity: activity
+                .iter()
+                .map(|(fid, messages)| FidActivity {
+                    fid: *fid,
+                    messages: *messages,
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_recommend_evens_out_load() {
+        let router = ShardRouter {};
+        // Pile the load onto the fids hashing to shard 1 of 3
+        let mut activity = vec![];
+        let mut fid = 1;
+        while activity.len() < 4 {
+            if router.route_fid(fid, 3) == 1 {
+                activity.push((fid, 100));
+            }
+            fid += 1;
+        }
+        // And a light fid on shard 2
+        while router.route_fid(fid, 3) != 2 {
+            fid += 1;
+        }
+        activity.push((fid, 1));
+        let shard_stats = vec![stats(1, &activity[..2]), stats(2, &activity[2..])];
+
+        let plan = recommend(&shard_stats, 3);
+        assert_eq!(plan.assignments.len(), 5);
+        assert_eq!(plan.hashed_loads.iter().sum::<u64>(), 401);
+        assert_eq!(plan.hashed_loads[0], 400);
+        assert_eq!(plan.planned_loads.iter().sum::<u64>(), 401);
+        assert!(plan.planned_loads.iter().all(|load| *load <= 200));
+        assert!(plan.planned_variance < plan.hashed_variance);
+        // Only the heavy fids move off their hashed shard
+        assert_eq!(plan.overrides().count(), 2);
+        assert!(plan
+            .overrides()
+            .all(|assignment| assignment.messages == 100));
+        assert_eq!(plan.assignments[2].current_shard, 2);
+    }
+
+    #[test]
+    fn test_recommend_keeps_balanced_routing() {
+        let activity: Vec<(u64, u64)> = (1..=2)
+            .flat_map(|shard| {
+                (1..)
+                    .filter(move |fid| ShardRouter {}.route_fid(*fid, 2) == shard)
+                    .take(2)
+                    .map(|fid| (fid, 10))
+            })
+            .collect();
+        let plan = recommend(&[stats(1, &activity)], 2);
+        assert_eq!(plan.planned_loads, vec![20, 20]);
+        assert_eq!(plan.overrides().count(), 0);
+        assert_eq!(plan.planned_variance, 0.0);
+
+        let plan = recommend(&[], 4);
+        assert_eq!(plan.planned_loads, vec![0, 0, 0, 0]);
+    }
+}
@@ -1,73 +1,289 @@
-use cadence::{Counted, Gauged, StatsdClient, Timed};
-use std::sync::Arc;
+use super::latency_histograms::{Latency, LatencyHistograms};
+use cadence::{Counted, Gauged, Histogrammed, StatsdClient, Timed};
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::{Arc, RwLock};
+use tracing::warn;
 
-pub struct StatsdClientWrapper {
+/// A client that sends to a statsd server over udp. The address has to be an ip and a port, names
+/// aren't resolved.
+pub fn udp_statsd_client(addr: &str, prefix: &str) -> Result<StatsdClient, String> {
+    let addr = addr
+        .parse::<SocketAddr>()
+        .map_err(|e| format!("invalid statsd address: {}", e))?;
+    let socket = UdpSocket::bind("0.0.0.0:0").map_err(|e| e.to_string())?;
+    let sink = cadence::UdpMetricSink::from(addr, socket).map_err(|e| e.to_string())?;
+    Ok(StatsdClient::builder(prefix, sink).build())
+}
+
+struct MetricsSink {
     client: Arc<StatsdClient>,
+    // Share of the metrics that are sent, the others are dropped
+    sample_rate: f64,
+}
+
+pub struct StatsdClientWrapper {
+    // Shared by every clone, so changing it repoints all of them
+    sink: Arc<RwLock<MetricsSink>>,
     use_tags: bool,
+    latency_histograms: Option<Arc<LatencyHistograms>>,
 }
 
 impl Clone for StatsdClientWrapper {
     fn clone(&self) -> Self {
         Self {
-            client: self.client.clone(),
+            sink: self.sink.clone(),
             use_tags: self.use_tags,
+            latency_histograms: self.latency_histograms.clone(),
         }
     }
 }
 
+// Counts that are sent stand in for the ones that were dropped, so the totals stay the same
+fn scale_count(value: u64, sample_rate: f64) -> u64 {
+    (value as f64 / sample_rate).round() as u64
+}
+
 impl StatsdClientWrapper {
     pub fn new(client: StatsdClient, use_tags: bool) -> Self {
         Self {
-            client: Arc::new(client),
+            sink: Arc::new(RwLock::new(MetricsSink {
+                client: Arc::new(client),
+                sample_rate: 1.0,
+            })),
             use_tags,
+            latency_histograms: None,
+        }
+    }
+
+    /// Sends the metrics of this wrapper and all its clones to another client from now on. The old
+    /// client is flushed once it's swapped out, so what it had buffered still goes to the old
+    /// destination, and no metric is sent to both.
+    pub fn set_sink(&self, client: StatsdClient, sample_rate: f64) {
+        let old = std::mem::replace(
+            &mut *self.sink.write().unwrap(),
+            MetricsSink {
+                client: Arc::new(client),
+                sample_rate,
+            },
+        );
+        if let Err(err) = old.client.flush() {
+            warn!("Unable to flush the old metrics sink: {}", err);
+        }
+    }
+
+    // The client to send a metric with and the sample rate, None when the metric is dropped
+    fn sampled_client(&self) -> Option<(Arc<StatsdClient>, f64)> {
+        let sink = self.sink.read().unwrap();
+        if sink.sample_rate < 1.0 && rand::random::<f64>() >= sink.sample_rate {
+            return None;
+        }
+        Some((sink.client.clone(), sink.sample_rate))
+    }
+
+    // Shared by every clone made afterwards
+    pub fn with_latency_histograms(mut self, histograms: Arc<LatencyHistograms>) -> Self {
+        self.latency_histograms = Some(histograms);
+        self
+    }
+
+    pub fn latency_histograms(&self) -> Option<Arc<LatencyHistograms>> {
+        self.latency_histograms.clone()
+    }
+
+    /// Sends the latency as a statsd timer, keyed like the other timers, and records it in its
+    /// histogram when histograms are enabled. The shard and tag are labels of the histogram.
+    pub fn time_latency(
+        &self,
+        latency: Latency,
+        shard_id: Option<u32>,
+        tag: Option<(&str, &str)>,
+        value: u64,
+    ) {
+        let key = latency.statsd_key();
+        match (shard_id, tag) {
+            (Some(shard_id), Some(tag)) => self.time_with_shard_and_tag(shard_id, key, tag, value),
+            (Some(shard_id), None) => self.time_with_shard(shard_id, key, value),
+            (None, Some(tag)) => {
+                if let Some((client, _)) = self.sampled_client() {
+                    if self.use_tags {
+                        client
+                            .time_with_tags(key, value)
+                            .with_tag(tag.0, tag.1)
+                            .send()
+                    } else {
+                        let key = format!("{}.{}", key, tag.1);
+                        _ = client.time(key.as_str(), value)
+                    }
+                }
+            }
+            (None, None) => self.time(key, value),
+        }
+
+        if let Some(histograms) = &self.latency_histograms {
+            let shard_id = shard_id.map(|shard_id| shard_id.to_string());
+            let labels: Vec<(&str, &str)> = shard_id
+                .as_deref()
+                .map(|shard_id| ("shard", shard_id))
+                .into_iter()
+                .chain(tag)
+                .collect();
+            histograms.observe(latency, &labels, value);
         }
     }
 
     pub fn count_with_shard(&self, shard_id: u32, key: &str, value: u64) {
+        let Some((client, sample_rate)) = self.sampled_client() else {
+            return;
+        };
+        let value = scale_count(value, sample_rate);
         if self.use_tags {
-            self.client
+            client
                 .count_with_tags(key, value)
                 .with_tag("shard", format!("{}", shard_id).as_str())
                 .send()
         } else {
             let key = format!("shard{}.{}", shard_id, key);
-            _ = self.client.count(key.as_str(), value)
+            _ = client.count(key.as_str(), value)
+        }
+    }
+
+    // Without tag support, the tag values are appended to the key instead
+    pub fn count_with_shard_and_tags(
+        &self,
+        shard_id: u32,
+        key: &str,
+        tags: &[(&str, &str)],
+        value: u64,
+    ) {
+        let Some((client, sample_rate)) = self.sampled_client() else {
+            return;
+        };
+        let value = scale_count(value, sample_rate);
+        if self.use_tags {
+            let shard_id = format!("{}", shard_id);
+            let mut metric = client
+                .count_with_tags(key, value)
+                .with_tag("shard", shard_id.as_str());
+            for (name, tag_value) in tags {
+                metric = metric.with_tag(name, tag_value);
+            }
+            metric.send()
+        } else {
+            let mut key = format!("shard{}.{}", shard_id, key);
+            for (_, tag_value) in tags {
+                key = format!("{}.{}", key, tag_value);
+            }
+            _ = client.count(key.as_str(), value)
         }
     }
 
     pub fn count(&self, key: &str, value: i64) {
-        _ = self.client.count(key, value)
+        let Some((client, sample_rate)) = self.sampled_client() else {
+            return;
+        };
+        let value = (value as f64 / sample_rate).round() as i64;
+        _ = client.count(key, value)
+    }
+
+    // Without tag support, the tag values are appended to the key instead
+    pub fn count_with_tags(&self, key: &str, tags: &[(&str, &str)], value: u64) {
+        let Some((client, sample_rate)) = self.sampled_client() else {
+            return;
+        };
+        let value = scale_count(value, sample_rate);
+        if self.use_tags {
+            let mut metric = client.count_with_tags(key, value);
+            for (name, tag_value) in tags {
+                metric = metric.with_tag(name, tag_value);
+            }
+            metric.send()
+        } else {
+            let mut key = key.to_string();
+            for (_, tag_value) in tags {
+                key = format!("{}.{}", key, tag_value);
+            }
+            _ = client.count(key.as_str(), value)
+        }
     }
 
     pub fn gauge_with_shard(&self, shard_id: u32, key: &str, value: u64) {
+        let Some((client, _)) = self.sampled_client() else {
+            return;
+        };
         if self.use_tags {
-            self.client
+            client
                 .gauge_with_tags(key, value)
                 .with_tag("shard", format!("{}", shard_id).as_str())
                 .send()
         } else {
             let key = format!("shard{}.{}", shard_id, key);
-            _ = self.client.gauge(key.as_str(), value)
+            _ = client.gauge(key.as_str(), value)
         }
     }
 
     pub fn gauge(&self, key: &str, value: u64) {
-        _ = self.client.gauge(key, value)
+        if let Some((client, _)) = self.sampled_client() {
+            _ = client.gauge(key, value)
+        }
     }
 
     pub fn time_with_shard(&self, shard_id: u32, key: &str, value: u64) {
+        let Some((client, _)) = self.sampled_client() else {
+            return;
+        };
         if self.use_tags {
-            self.client
+            client
                 .time_with_tags(key, value)
                 .with_tag("shard", format!("{}", shard_id).as_str())
                 .send()
         } else {
             let key = format!("shard{}.{}", shard_id, key);
-            _ = self.client.time(key.as_str(), value)
+            _ = client.time(key.as_str(), value)
         }
     }
 
     pub fn time(&self, key: &str, value: u64) {
-        _ = self.client.time(key, value)
+        if let Some((client, _)) = self.sampled_client() {
+            _ = client.time(key, value)
+        }
+    }
+
+    // Without tag support, the tag value is appended to the key instead
+    pub fn time_with_shard_and_tag(&self, shard_id: u32, key: &str, tag: (&str, &str), value: u64) {
+        let Some((client, _)) = self.sampled_client() else {
+            return;
+        };
+        if self.use_tags {
+            client
+                .time_with_tags(key, value)
+                .with_tag("shard", format!("{}", shard_id).as_str())
+                .with_tag(tag.0, tag.1)
+                .send()
+        } else {
+            let key = format!("shard{}.{}.{}", shard_id, key, tag.1);
+            _ = client.time(key.as_str(), value)
+        }
+    }
+
+    pub fn histogram_with_shard_and_tag(
+        &self,
+        shard_id: u32,
+        key: &str,
+        tag: (&str, &str),
+        value: u64,
+    ) {
+        let Some((client, _)) = self.sampled_client() else {
+            return;
+        };
+        if self.use_tags {
+            client
+                .histogram_with_tags(key, value)
+                .with_tag("shard", format!("{}", shard_id).as_str())
+                .with_tag(tag.0, tag.1)
+                .send()
+        } else {
+            let key = format!("shard{}.{}.{}", shard_id, key, tag.1);
+            _ = client.histogram(key.as_str(), value)
+        }
     }
 }
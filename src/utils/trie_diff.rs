@@ -0,0 +1,323 @@
+use crate::proto::hub_service_client::HubServiceClient;
+use crate::proto::{self, MessageType};
+use async_trait::async_trait;
+use std::collections::{BTreeMap, BTreeSet};
+use thiserror::Error;
+use tonic::transport::Channel;
+use tonic::Status;
+
+/// A node's shard trie, as served by its read rpcs
+#[async_trait]
+pub trait TrieSource: Send {
+    async fn shard_root(&mut self, shard_id: u32) -> Result<proto::ShardRootResponse, Status>;
+
+    async fn trie_node(
+        &mut self,
+        shard_id: u32,
+        prefix: Vec<u8>,
+    ) -> Result<proto::TrieNodeMetadataResponse, Status>;
+}
+
+#[async_trait]
+impl TrieSource for HubServiceClient<Channel> {
+    async fn shard_root(&mut self, shard_id: u32) -> Result<proto::ShardRootResponse, Status> {
+        Ok(self
+            .get_shard_root(proto::ShardRootRequest { shard_id })
+            .await?
+            .into_inner())
+    }
+
+    async fn trie_node(
+        &mut self,
+        shard_id: u32,
+        prefix: Vec<u8>,
+    ) -> Result<proto::TrieNodeMetadataResponse, Status> {
+        Ok(self
+            .get_trie_metadata_by_prefix(proto::TrieNodeMetadataRequest { shard_id, prefix })
+            .await?
+            .into_inner())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum TrieDiffError {
+    // Nodes only serve their trie at the latest committed height
+    #[error("node {node} is at height {actual}, not {expected}")]
+    WrongHeight {
+        node: &'static str,
+        expected: u64,
+        actual: u64,
+    },
+
+    #[error(transparent)]
+    Rpc(#[from] Status),
+}
+
+/// The trie keys only one of the nodes has
+#[derive(Debug, Default, PartialEq)]
+pub struct TrieDiff {
+    pub only_in_a: Vec<Vec<u8>>,
+    pub only_in_b: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, Copy)]
+enum Side {
+    A,
+    B,
+}
+
+enum Step {
+    // Both nodes have the subtree, but with different hashes
+    Compare(Vec<u8>),
+    // Every key in one node's subtree is collected, to be compared once the walk is done
+    Collect(Side, Vec<u8>),
+}
+
+async fn check_height(
+    source: &mut dyn TrieSource,
+    node: &'static str,
+    shard_id: u32,
+    height: u64,
+) -> Result<(), TrieDiffError> {
+    let actual = source.shard_root(shard_id).await?.height;
+    if actual != height {
+        return Err(TrieDiffError::WrongHeight {
+            node,
+            expected: height,
+            actual,
+        });
+    }
+    Ok(())
+}
+
+/// Compares the shard's trie on both nodes at the height, which both have to be at for the whole
+/// diff, since only the latest trie is served. Only the subtrees whose hashes differ are walked.
+pub async fn diff_tries(
+    a: &mut dyn TrieSource,
+    b: &mut dyn TrieSource,
+    shard_id: u32,
+    height: u64,
+) -> Result<TrieDiff, TrieDiffError> {
+    check_height(a, "A", shard_id, height).await?;
+    check_height(b, "B", shard_id, height).await?;
+
+    let mut keys_a = BTreeSet::new();
+    let mut keys_b = BTreeSet::new();
+    let mut steps = vec![Step::Compare(vec![])];
+    while let Some(step) = steps.pop() {
+        match step {
+            Step::Compare(prefix) => {
+                let node_a = a.trie_node(shard_id, prefix.clone()).await?;
+                let node_b = b.trie_node(shard_id, prefix).await?;
+                if node_a.hash == node_b.hash {
+                    continue;
+                }
+                let mut children: BTreeMap<Vec<u8>, (Option<_>, Option<_>)> = BTreeMap::new();
+                for child in node_a.children {
+                    children.entry(child.prefix.clone()).or_default().0 = Some(child);
+                }
+                for child in node_b.children {
+                    children.entry(child.prefix.clone()).or_default().1 = Some(child);
+                }
+
+                for (prefix, (child_a, child_b)) in children {
+                    match (&child_a, &child_b) {
+                        (Some(child_a), Some(child_b)) if child_a.hash == child_b.hash => {}
+                        (Some(child_a), Some(child_b))
+                            if child_a.key.is_empty() && child_b.key.is_empty() =>
+                        {
+                            steps.push(Step::Compare(prefix));
+                        }
+                        _ => {
+                            for (side, child) in [(Side::A, child_a), (Side::B, child_b)] {
+                                let Some(child) = child else { continue };
+                                if child.key.is_empty() {
+                                    steps.push(Step::Collect(side, child.prefix));
+                                } else {
+                                    match side {
+                                        Side::A => keys_a.insert(child.key),
+                                        Side::B => keys_b.insert(child.key),
+                                    };
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            Step::Collect(side, prefix) => {
+                let (source, keys) = match side {
+                    Side::A => (&mut *a, &mut keys_a),
+                    Side::B => (&mut *b, &mut keys_b),
+                };
+                for child in source.trie_node(shard_id, prefix).await?.children {
+                    if child.key.is_empty() {
+                        steps.push(Step::Collect(side, child.prefix));
+                    } else {
+                        keys.insert(child.key);
+                    }
+                }
+            }
+        }
+    }
+
+    // The trie keeps changing as blocks are committed, so the result is only meaningful if
+    // neither node moved on
+    check_height(a, "A", shard_id, height).await?;
+    check_height(b, "B", shard_id, height).await?;
+
+    Ok(TrieDiff {
+        only_in_a: keys_a.difference(&keys_b).cloned().collect(),
+        only_in_b: keys_b.difference(&keys_a).cloned().collect(),
+    })
+}
+
+/// Names what the trie key is for, e.g. the fid, type and hash of a message, as laid out by
+/// TrieKey
+pub fn describe_trie_key(key: &[u8]) -> String {
+    if key.len() < 6 {
+        return format!("key 0x{}", hex::encode(key));
+    }
+    let fid = u32::from_be_bytes(key[1..5].try_into().unwrap());
+    let rest = hex::encode(&key[6..]);
+    match key[5] {
+        1..=6 => match proto::OnChainEventType::try_from(key[5] as i32) {
+            Ok(event_type) => format!("fid {} {} 0x{}", fid, event_type.as_str_name(), rest),
+            Err(_) => format!("fid {} onchain event 0x{}", fid, rest),
+        },
+        7 => {
+            let name = String::from_utf8_lossy(&key[6..]);
+            format!("fid {} fname {}", fid, name.trim_end_matches('\0'))
+        }
+        message_type => match MessageType::try_from((message_type >> 3) as i32) {
+            Ok(message_type) => format!("fid {} {} 0x{}", fid, message_type.as_str_name(), rest),
+            Err(_) => format!("fid {} key 0x{}", fid, hex::encode(key)),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::db::{RocksDB, RocksDbTransactionBatch};
+    use crate::storage::trie::merkle_trie::{Context, MerkleTrie, TrieKey};
+    use crate::utils::factory::messages_factory;
+
+    struct LocalTrie {
+        trie: MerkleTrie,
+        db: RocksDB,
+        height: u64,
+        _dir: tempfile::TempDir,
+    }
+
+    impl LocalTrie {
+        fn new(keys: &[Vec<u8>]) -> Self {
+            let dir = tempfile::TempDir::new().unwrap();
+            let db = RocksDB::new(dir.path().join("trie.db").to_str().unwrap());
+            db.open().unwrap();
+            let mut trie = MerkleTrie::new(16).unwrap();
+            trie.initialize(&db).unwrap();
+            let mut txn = db.txn();
+            trie.insert(
+                &Context::new(),
+                &db,
+                &mut txn,
+                keys.iter().map(|k| k.as_slice()).collect(),
+            )
+            .unwrap();
+            db.commit(txn).unwrap();
+            trie.reload(&db).unwrap();
+            LocalTrie {
+                trie,
+                db,
+                height: 10,
+                _dir: dir,
+            }
+        }
+    }
+
+    #[async_trait]
+    impl TrieSource for LocalTrie {
+        async fn shard_root(&mut self, shard_id: u32) -> Result<proto::ShardRootResponse, Status> {
+            Ok(proto::ShardRootResponse {
+                shard_id,
+                height: self.height,
+                root_hash: self.trie.root_hash().unwrap(),
+            })
+        }
+
+        async fn trie_node(
+            &mut self,
+            _shard_id: u32,
+            prefix: Vec<u8>,
+        ) -> Result<proto::TrieNodeMetadataResponse, Status> {
+            let node = self
+                .trie
+                .get_trie_node_metadata(&self.db, &mut RocksDbTransactionBatch::new(), &prefix)
+                .map_err(|err| Status::internal(err.to_string()))?;
+            Ok(proto::TrieNodeMetadataResponse {
+                prefix: node.prefix,
+                num_messages: node.num_messages as u64,
+                hash: node.hash,
+                children: node
+                    .children
+                    .into_values()
+                    .map(|child| proto::TrieNodeMetadataResponse {
+                        prefix: child.prefix,
+                        num_messages: child.num_messages as u64,
+                        hash: child.hash,
+                        children: vec![],
+                        key: child.key.unwrap_or_default(),
+                    })
+                    .collect(),
+                key: node.key.unwrap_or_default(),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_diff_tries() {
+        let keys: Vec<Vec<u8>> = (0..50)
+            .map(|i| {
+                let cast = messages_factory::casts::create_cast_add(
+                    1000 + i % 5,
+                    &format!("cast {}", i),
+                    None,
+                    None,
+                );
+                TrieKey::for_message(&cast)
+            })
+            .collect();
+        let cast = messages_factory::casts::create_cast_add(1234, "only on b", None, None);
+        let only_in_b = TrieKey::for_message(&cast);
+
+        // A has two keys B is missing, one of them the only one of its fid
+        let mut a = LocalTrie::new(&keys);
+        let mut b = LocalTrie::new(&[keys[2..49].to_vec(), vec![only_in_b.clone()]].concat());
+        let mut expected_only_in_a = vec![keys[0].clone(), keys[1].clone(), keys[49].clone()];
+        expected_only_in_a.sort();
+        assert_eq!(
+            diff_tries(&mut a, &mut b, 1, 10).await.unwrap(),
+            TrieDiff {
+                only_in_a: expected_only_in_a,
+                only_in_b: vec![only_in_b.clone()],
+            }
+        );
+        assert!(describe_trie_key(&only_in_b).starts_with(&format!(
+            "fid 1234 MESSAGE_TYPE_CAST_ADD 0x{}",
+            hex::encode(&cast.hash)
+        )));
+
+        // The same trie has no differences
+        let mut same = LocalTrie::new(&keys);
+        assert_eq!(
+            diff_tries(&mut a, &mut same, 1, 10).await.unwrap(),
+            TrieDiff::default()
+        );
+
+        b.height = 11;
+        assert!(matches!(
+            diff_tries(&mut a, &mut b, 1, 10).await,
+            Err(TrieDiffError::WrongHeight { node: "B", .. })
+        ));
+    }
+}
@@ -3,23 +3,31 @@ use hex;
 use informalsystems_malachitebft_metrics::SharedRegistry;
 use libp2p::identity::ed25519::Keypair;
 use serial_test::serial;
+use snapchain::connectors::onchain_events::pause::PauseState;
+use snapchain::connectors::onchain_events::reorg::HaltState;
+use snapchain::connectors::onchain_events::retry::RetryQueue;
 use snapchain::consensus::consensus::{SystemMessage, ValidatorSetConfig};
 use snapchain::consensus::proposer::GENESIS_MESSAGE;
+use snapchain::consensus::validator::StoredValidatorSets;
+use snapchain::core::types::SnapchainShard;
+use snapchain::core::validations::custom::MessageValidators;
 use snapchain::mempool::mempool::{
     self, Mempool, MempoolMessagesRequest, MempoolRequest, MempoolSource,
 };
 use snapchain::mempool::routing;
 use snapchain::network::gossip::SnapchainGossip;
 use snapchain::network::server::MyHubService;
+use snapchain::network::sync_progress::SyncProgress;
+use snapchain::network::vote_history::VoteHistory;
 use snapchain::node::snapchain_node::SnapchainNode;
 use snapchain::node::snapchain_read_node::SnapchainReadNode;
 use snapchain::proto::hub_service_server::HubServiceServer;
 use snapchain::proto::{self, Height};
 use snapchain::proto::{Block, FarcasterNetwork, IdRegisterEventType, SignerEventType};
-use snapchain::storage::db::{PageOptions, RocksDB};
+use snapchain::storage::db::{self, PageOptions, RocksDB};
 use snapchain::storage::store::engine::MempoolMessage;
 use snapchain::storage::store::node_local_state::LocalStateStore;
-use snapchain::storage::store::stores::Stores;
+use snapchain::storage::store::stores::{StoreLimits, Stores};
 use snapchain::storage::store::BlockStore;
 use snapchain::utils::factory::{self, messages_factory};
 use snapchain::utils::statsd_wrapper::StatsdClientWrapper;
@@ -35,6 +43,8 @@ use tracing_subscriber::EnvFilter;
 
 const HOST_FOR_TEST: &str = "127.0.0.1";
 const BASE_PORT_FOR_TEST: u32 = 9482;
+// So the nodes pick their peers the same way on every run
+const GOSSIP_RNG_SEED: u64 = 1;
 
 struct NodeForTest {
     node: SnapchainNode,
@@ -124,7 +134,8 @@ impl ReadNodeForTest {
             true,
         );
 
-        let config = snapchain::network::gossip::Config::new(gossip_address, bootstrap_address);
+        let config = snapchain::network::gossip::Config::new(gossip_address, bootstrap_address)
+            .with_rng_seed(Some(GOSSIP_RNG_SEED));
 
         let mut consensus_config = snapchain::consensus::consensus::Config::default();
         consensus_config =
@@ -162,9 +173,13 @@ impl ReadNodeForTest {
             messages_request_tx,
             block_store.clone(),
             make_tmp_path(),
+            db::Config::default(),
             statsd_client.clone(),
             16,
             fc_network,
+            StoreLimits::default(),
+            MessageValidators::default(),
+            false,
             registry,
         )
         .await;
@@ -219,6 +234,9 @@ impl NodeForTest {
         validator_sets: &Vec<ValidatorSetConfig>,
         gossip_address: String,
         bootstrap_address: String,
+        idle_block_time: time::Duration,
+        min_peers_for_consensus: u32,
+        follower_shard_ids: Vec<u32>,
     ) -> Self {
         let statsd_client = StatsdClientWrapper::new(
             cadence::StatsdClient::builder("", cadence::NopMetricSink {}).build(),
@@ -227,12 +245,16 @@ impl NodeForTest {
 
         let config =
             snapchain::network::gossip::Config::new(gossip_address.clone(), bootstrap_address)
-                .with_announce_address(gossip_address);
+                .with_announce_address(gossip_address)
+                .with_rng_seed(Some(GOSSIP_RNG_SEED));
 
         let mut consensus_config = snapchain::consensus::consensus::Config::default();
         consensus_config =
             consensus_config.with((1..=num_shards).collect(), validator_sets.clone());
         consensus_config.block_time = time::Duration::from_millis(250);
+        consensus_config.idle_block_time = idle_block_time;
+        consensus_config.min_peers_for_consensus = min_peers_for_consensus;
+        consensus_config.follower_shard_ids = follower_shard_ids;
 
         let (system_tx, mut system_rx) = mpsc::channel::<SystemMessage>(100);
         let fc_network = FarcasterNetwork::Testnet;
@@ -257,7 +279,7 @@ impl NodeForTest {
         let db = Arc::new(RocksDB::new(data_dir));
         db.open().unwrap();
         let block_store = BlockStore::new(db.clone());
-        let global_db = RocksDB::open_global_db(&data_dir);
+        let global_db = RocksDB::open_global_db(&data_dir, "", db::WriteDurability::Wal);
         let node_local_store = LocalStateStore::new(global_db);
         let (messages_request_tx, messages_request_rx) = mpsc::channel(100);
         let (shard_decision_tx, shard_decision_rx) = broadcast::channel(100);
@@ -266,15 +288,21 @@ impl NodeForTest {
             consensus_config,
             peer_id,
             gossip_tx.clone(),
+            system_tx.clone(),
+            gossip.sync_progress.clone(),
             shard_decision_tx,
             Some(block_tx),
             messages_request_tx,
             block_store.clone(),
             node_local_store,
             make_tmp_path(),
+            db::Config::default(),
             statsd_client.clone(),
             16,
             fc_network,
+            StoreLimits::default(),
+            MessageValidators::default(),
+            false,
             registry,
         )
         .await;
@@ -342,6 +370,23 @@ impl NodeForTest {
             Box::new(routing::EvenOddRouterForTest {}),
             mempool_tx.clone(),
             None,
+            HaltState::default(),
+            PauseState::default(),
+            RetryQueue::default(),
+            node.shard_stores
+                .keys()
+                .map(|shard_id| {
+                    (
+                        *shard_id,
+                        StoredValidatorSets::from_config(
+                            SnapchainShard::new(*shard_id),
+                            validator_sets,
+                        ),
+                    )
+                })
+                .collect(),
+            SyncProgress::default(),
+            VoteHistory::default(),
             "".to_string(),
             "".to_string(),
         );
@@ -425,6 +470,10 @@ pub struct TestNetwork {
     gossip_addresses: Vec<String>,
     nodes: Vec<NodeForTest>,
     read_nodes: Vec<ReadNodeForTest>,
+    idle_block_time: time::Duration,
+    min_peers_for_consensus: u32,
+    // By node index
+    follower_shard_ids: HashMap<u32, Vec<u32>>,
 }
 
 impl TestNetwork {
@@ -455,9 +504,27 @@ impl TestNetwork {
             gossip_addresses: node_addresses,
             nodes: vec![],
             read_nodes: vec![],
+            idle_block_time: time::Duration::ZERO,
+            min_peers_for_consensus: 0,
+            follower_shard_ids: HashMap::new(),
         }
     }
 
+    pub fn with_idle_block_time(mut self, idle_block_time: time::Duration) -> Self {
+        self.idle_block_time = idle_block_time;
+        self
+    }
+
+    pub fn with_min_peers_for_consensus(mut self, min_peers_for_consensus: u32) -> Self {
+        self.min_peers_for_consensus = min_peers_for_consensus;
+        self
+    }
+
+    pub fn with_follower_shard_ids(mut self, index: u32, shard_ids: Vec<u32>) -> Self {
+        self.follower_shard_ids.insert(index, shard_ids);
+        self
+    }
+
     async fn start_validator_node(&mut self, index: u32) {
         let keypair = self.keypairs[index as usize].clone();
         let gossip_address = self.gossip_addresses[index as usize].clone();
@@ -469,6 +536,12 @@ impl TestNetwork {
             &self.validator_sets,
             gossip_address,
             self.gossip_addresses[0].clone(),
+            self.idle_block_time,
+            self.min_peers_for_consensus,
+            self.follower_shard_ids
+                .get(&index)
+                .cloned()
+                .unwrap_or_default(),
         )
         .await;
         self.nodes.push(node);
@@ -778,3 +851,151 @@ async fn test_read_node() {
         wait_for_read_node_blocks(read_node, 1).await;
     }
 }
+
+#[tokio::test]
+#[serial]
+async fn test_idle_block_time() {
+    let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn"));
+    let _ = tracing_subscriber::fmt()
+        .with_env_filter(env_filter)
+        .try_init();
+
+    let num_shards = 1;
+    let mut network = TestNetwork::create(3, num_shards, 3420)
+        .await
+        .with_idle_block_time(time::Duration::from_secs(2));
+    network.start_validators().await;
+    wait_for_blocks(&network.nodes[0], 1).await;
+
+    // With an empty mempool, the 250ms block time would make around 24 blocks
+    let blocks_before = network.nodes[0].num_blocks().await;
+    tokio::time::sleep(time::Duration::from_secs(6)).await;
+    let idle_blocks = network.nodes[0].num_blocks().await - blocks_before;
+    assert!(
+        idle_blocks >= 1,
+        "The chain should still advance while idle"
+    );
+    assert!(
+        idle_blocks <= 6,
+        "Idle network made {} blocks, expected a few",
+        idle_blocks
+    );
+
+    // Messages are still included, at the usual pace once they're flowing
+    let messages_tx = network.nodes[0].mempool_tx.clone();
+    tokio::spawn(async move { send_messages(messages_tx).await });
+    let blocks_before = network.nodes[0].num_blocks().await;
+    tokio::time::sleep(time::Duration::from_secs(6)).await;
+    for i in 0..network.nodes.len() {
+        assert!(
+            network.nodes[i].total_messages().await > 0,
+            "Node {} should have messages",
+            i
+        );
+    }
+    assert!(network.nodes[0].num_blocks().await - blocks_before > idle_blocks);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_min_peers_for_consensus() {
+    let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn"));
+    let _ = tracing_subscriber::fmt()
+        .with_env_filter(env_filter)
+        .try_init();
+
+    // A lone validator has a quorum on its own, but doesn't use it without a peer
+    let num_shards = 1;
+    let mut network = TestNetwork::create(1, num_shards, 3440)
+        .await
+        .with_min_peers_for_consensus(1);
+    network.start_validators().await;
+    tokio::time::sleep(time::Duration::from_secs(5)).await;
+    assert_eq!(network.nodes[0].num_blocks().await, 0);
+    assert_eq!(network.nodes[0].num_shard_chunks().await, 0);
+
+    // A read node is a peer too
+    network.start_read_node(0).await;
+    wait_for_blocks(&network.nodes[0], 1).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_follower_shard() {
+    let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn"));
+    let _ = tracing_subscriber::fmt()
+        .with_env_filter(env_filter)
+        .try_init();
+
+    // The last node validates shard 1 and only follows shard 2. The other three are enough for a
+    // quorum on shard 2 without it.
+    let num_shards = 2;
+    let mut network = TestNetwork::create(4, num_shards, 3460)
+        .await
+        .with_follower_shard_ids(3, vec![2]);
+    network.start_validators().await;
+
+    let follower = &network.nodes[3];
+    assert!(follower.node.consensus_actors.contains_key(&1));
+    assert!(!follower.node.consensus_actors.contains_key(&2));
+    assert!(follower.node.follower_actors.contains_key(&2));
+
+    let messages_tx = network.nodes[0].mempool_tx.clone();
+    tokio::spawn(async move { send_messages(messages_tx).await });
+
+    network.produce_blocks(3).await;
+
+    // The follower syncs the shard's chunks from the validators
+    let follower_chunks = |node: &NodeForTest| {
+        node.node.shard_stores[&2]
+            .shard_store
+            .get_shard_chunks(0, None)
+            .unwrap()
+    };
+    let timeout = tokio::time::Duration::from_secs(10);
+    let start = tokio::time::Instant::now();
+    let mut timer = time::interval(tokio::time::Duration::from_millis(100));
+    while follower_chunks(&network.nodes[3]).len() < 3 && start.elapsed() < timeout {
+        let _ = timer.tick().await;
+    }
+    let synced = follower_chunks(&network.nodes[3]);
+    assert!(
+        synced.len() >= 3,
+        "Follower should have synced chunks, has {}",
+        synced.len()
+    );
+    let validated = follower_chunks(&network.nodes[0]);
+    for chunk in synced.iter() {
+        let height = chunk.header.as_ref().unwrap().height.unwrap();
+        let decided = validated
+            .iter()
+            .find(|decided| decided.header.as_ref().unwrap().height.unwrap() == height)
+            .unwrap();
+        assert_eq!(chunk.hash, decided.hash);
+    }
+
+    // Without voting on any of them
+    let follower_address = network.nodes[3].node.address.0.to_vec();
+    for node in network.nodes.iter() {
+        for chunk in follower_chunks(node) {
+            let signers: Vec<_> = chunk
+                .commits
+                .unwrap()
+                .signatures
+                .into_iter()
+                .map(|signature| signature.signer)
+                .collect();
+            assert!(!signers.contains(&follower_address));
+        }
+    }
+
+    // While it still validates the other shard
+    assert!(
+        network.nodes[3].node.shard_stores[&1]
+            .shard_store
+            .get_shard_chunks(0, None)
+            .unwrap()
+            .len()
+            >= 3
+    );
+}